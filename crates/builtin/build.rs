@@ -1,9 +1,12 @@
 use serde::Deserialize;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 use std::process::{Command, Stdio};
 use std::{env, fs};
 
+/// Functions which fail or return dummy values in pure evaluation mode.
+const IMPURE_FUNCTIONS: &[&str] = &["getEnv", "storePath"];
+
 fn main() {
     // Disable rebuild when source files changed.
     println!("cargo:rerun-if-changed=build.rs");
@@ -62,6 +65,11 @@ fn main() {
         "no duplicated builtins",
     );
 
+    // All documentations are stored in a single blob, deduplicated by their contents.
+    // Builtins only store spans into it, which keeps the generated map small.
+    let mut docs = String::new();
+    let mut doc_spans = HashMap::<String, (usize, usize)>::new();
+
     let mut phf_gen = phf_codegen::Map::<&'static str>::new();
     for (name, is_global) in builtins_attr_names.iter().zip(&global_names) {
         let name = &**name;
@@ -89,12 +97,22 @@ fn main() {
             .chain(args.iter().flat_map(|arg| [" ", arg]))
             .chain(Some("`"))
             .collect::<String>();
+        // Nix only marks impure constants. Some functions are also unavailable in pure evaluation.
+        let impure_only = impure_only || IMPURE_FUNCTIONS.contains(&name);
+        let description = first_sentence(&doc);
+        let (start, end) = *doc_spans.entry(doc).or_insert_with_key(|doc| {
+            let start = docs.len();
+            docs += doc;
+            (start, docs.len())
+        });
         let rhs = format!(
             "crate::Builtin {{
                 kind: crate::BuiltinKind::{kind},
                 is_global: {is_global},
                 summary: {summary:?},
-                doc: Some({doc:?}),
+                args: &{args:?},
+                description: {description:?},
+                doc: Some(crate::DocSpan {{ start: {start}, end: {end} }}),
                 impure_only: {impure_only},
                 experimental_feature: {experimental_feature:?},
             }}"
@@ -102,8 +120,10 @@ fn main() {
         phf_gen.entry(name, &rhs);
     }
 
-    let path = Path::new(&env::var("OUT_DIR").unwrap()).join("generated.expr");
-    fs::write(path, phf_gen.build().to_string()).unwrap();
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let out_dir = Path::new(&out_dir);
+    fs::write(out_dir.join("docs.md"), docs).unwrap();
+    fs::write(out_dir.join("generated.expr"), phf_gen.build().to_string()).unwrap();
 }
 
 fn dump_builtin_infos() -> Vec<BuiltinInfo> {
@@ -167,6 +187,22 @@ fn dump_builtin_infos() -> Vec<BuiltinInfo> {
         .collect()
 }
 
+/// The first sentence of the first paragraph, in a single line.
+fn first_sentence(doc: &str) -> String {
+    let para = doc
+        .trim_start()
+        .split("\n\n")
+        .next()
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    match para.find(". ") {
+        Some(pos) => para[..pos + 1].to_owned(),
+        None => para,
+    }
+}
+
 fn guess_name_kind(name: &str) -> &'static str {
     match name {
         "builtins" => "Attrset",
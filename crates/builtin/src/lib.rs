@@ -3,11 +3,62 @@ pub struct Builtin {
     pub kind: BuiltinKind,
     pub is_global: bool,
     pub summary: &'static str,
-    pub doc: Option<&'static str>,
+    /// Names of parameters of functions, empty for others.
+    pub args: &'static [&'static str],
+    /// The first sentence of the documentation.
+    pub description: &'static str,
+    doc: Option<DocSpan>,
     pub impure_only: bool,
     pub experimental_feature: Option<&'static str>,
 }
 
+impl Builtin {
+    pub fn arity(&self) -> usize {
+        self.args.len()
+    }
+
+    /// A single line of the arity, the description and restrictions, eg.
+    /// `1 argument: Return the names of the attributes in the set *set* ...`.
+    pub fn detail(&self) -> String {
+        let mut ret = String::new();
+        if self.kind == BuiltinKind::Function {
+            match self.arity() {
+                1 => ret += "1 argument: ",
+                n => ret += &format!("{n} arguments: "),
+            }
+        }
+        ret += self.description;
+        if self.impure_only {
+            ret += " (impure)";
+        }
+        if let Some(feature) = self.experimental_feature {
+            ret += &format!(" (experimental feature `{feature}`)");
+        }
+        ret
+    }
+
+    pub fn doc(&self) -> Option<&'static str> {
+        let DocSpan { start, end } = self.doc?;
+        Some(&DOCS[start as usize..end as usize])
+    }
+}
+
+/// The span of a documentation in `DOCS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct DocSpan {
+    start: u32,
+    end: u32,
+}
+
+/// Documentations of all builtins, concatenated and deduplicated at build time, and indexed by
+/// the spans in `ALL_BUILTINS`.
+///
+/// It lives in the read-only data of the executable, which the OS maps lazily and only pages in
+/// when accessed. This gives the same startup and RSS behavior as memory-mapping a separate
+/// file, without locating and validating that file at runtime. An fst is not used since the
+/// phf map is already used for lookups by name, and there are only hundreds of entries.
+static DOCS: &str = include_str!(concat!(env!("OUT_DIR"), "/docs.md"));
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum BuiltinKind {
     Const,
@@ -19,6 +70,27 @@ pub enum BuiltinKind {
 pub static ALL_BUILTINS: phf::Map<&'static str, Builtin> =
     include!(concat!(env!("OUT_DIR"), "/generated.expr"));
 
+/// Deprecated or discouraged builtins, and what to use instead.
+/// Global aliases like `__mapAttrs` are handled by [`deprecation`] separately.
+static DEPRECATED_BUILTINS: &[(&str, &str)] = &[
+    ("derivationStrict", "Use `derivation` instead"),
+    (
+        "toPath",
+        "Use `/. + \"/path\"` to convert a string into an absolute path instead",
+    ),
+];
+
+/// The suggestion for a deprecated or discouraged builtin `name`, if it is one.
+pub fn deprecation(name: &str) -> Option<String> {
+    if let Some((_, suggestion)) = DEPRECATED_BUILTINS.iter().find(|(n, _)| *n == name) {
+        return Some((*suggestion).into());
+    }
+    let name = name.strip_prefix("__")?;
+    ALL_BUILTINS
+        .contains_key(name)
+        .then(|| format!("Use `builtins.{name}` instead"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -31,28 +103,53 @@ mod tests {
                 kind: BuiltinKind::Const,
                 is_global: true,
                 summary: "`builtins.true`",
-                doc: _,
+                args: [],
+                description: _,
+                doc: Some(_),
                 impure_only: false,
                 experimental_feature: None,
             }
         ));
 
+        let b = &ALL_BUILTINS["attrNames"];
+        assert_eq!(b.kind, BuiltinKind::Function);
+        assert!(!b.is_global);
+        assert_eq!(b.summary, "`builtins.attrNames set`");
+        assert_eq!(b.args, ["set"]);
+        assert_eq!(b.arity(), 1);
         assert_eq!(
-            ALL_BUILTINS["attrNames"],
-            Builtin {
-                kind: BuiltinKind::Function,
-                is_global: false,
-                summary: "`builtins.attrNames set`",
-                doc: Some(
-                    "\
+            b.description,
+            "Return the names of the attributes in the set *set* in an alphabetically sorted list.",
+        );
+        assert_eq!(
+            b.doc(),
+            Some(
+                "\
 Return the names of the attributes in the set *set* in an
 alphabetically sorted list. For instance, `builtins.attrNames { y
 = 1; x = \"foo\"; }` evaluates to `[ \"x\" \"y\" ]`.\
                 "
-                ),
-                impure_only: false,
-                experimental_feature: None,
-            }
+            ),
+        );
+        assert_eq!(
+            b.detail(),
+            "1 argument: Return the names of the attributes in the set *set* in an alphabetically sorted list.",
+        );
+        assert!(!b.impure_only);
+        assert_eq!(b.experimental_feature, None);
+    }
+
+    #[test]
+    fn deprecation() {
+        assert_eq!(
+            super::deprecation("derivationStrict").as_deref(),
+            Some("Use `derivation` instead"),
+        );
+        assert_eq!(
+            super::deprecation("__mapAttrs").as_deref(),
+            Some("Use `builtins.mapAttrs` instead"),
         );
+        assert_eq!(super::deprecation("mapAttrs"), None);
+        assert_eq!(super::deprecation("__notExist"), None);
     }
 }
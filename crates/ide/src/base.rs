@@ -1,5 +1,10 @@
+use nix_interop::flake_lock::InputSource;
 use nix_interop::flake_output::FlakeOutput;
+use nix_interop::lib_docs::LibDocs;
 use nix_interop::nixos_options::NixosOptions;
+use nix_interop::package_aliases::PackageAliases;
+use nix_interop::package_index::PackageIndex;
+use nix_interop::search_path::SearchPath;
 use salsa::Durability;
 use std::collections::HashMap;
 use std::fmt;
@@ -168,6 +173,55 @@ impl SourceRoot {
     }
 }
 
+/// Names of functions auto-calling package files like `callPackage ./pkg.nix { }`, by default.
+pub const DEFAULT_CALL_PACKAGE_NAMES: &[&str] = &["callPackage", "callPackages"];
+
+/// Names which are idiomatically shadowed, like parameters of nested overlays, by default.
+pub const DEFAULT_SHADOWING_IGNORED_NAMES: &[&str] = &["self", "super", "final", "prev"];
+
+/// Experimental syntax of Nix. It is always parsed, but reported unless enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LanguageFeature {
+    /// `x |> f` and `f <| x`.
+    PipeOperators,
+}
+
+impl LanguageFeature {
+    pub const ALL: &'static [Self] = &[Self::PipeOperators];
+
+    /// The name in `experimental-features` of Nix.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::PipeOperators => "pipe-operators",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|feat| feat.name() == name)
+    }
+}
+
+/// Module systems with separate option sets, each completing and checking its own modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OptionSet {
+    Nixos,
+    HomeManager,
+    NixDarwin,
+}
+
+impl OptionSet {
+    pub const ALL: &'static [Self] = &[Self::Nixos, Self::HomeManager, Self::NixDarwin];
+
+    /// The human readable name.
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Nixos => "NixOS",
+            Self::HomeManager => "home-manager",
+            Self::NixDarwin => "nix-darwin",
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct FlakeGraph {
     pub nodes: HashMap<SourceRootId, FlakeInfo>,
@@ -179,6 +233,8 @@ pub struct FlakeInfo {
     pub flake_file: FileId,
     pub input_store_paths: HashMap<String, VfsPath>,
     pub input_flake_outputs: HashMap<String, FlakeOutput>,
+    /// Locked sources of inputs from `flake.lock`.
+    pub input_sources: HashMap<String, InputSource>,
 }
 
 impl fmt::Debug for FlakeInfo {
@@ -187,6 +243,7 @@ impl fmt::Debug for FlakeInfo {
             .field("flake_file", &self.flake_file)
             .field("input_store_paths", &self.input_store_paths)
             .field("input_flake_outputs", &self.input_flake_outputs.keys())
+            .field("input_sources", &self.input_sources)
             .finish_non_exhaustive()
     }
 }
@@ -251,23 +308,81 @@ pub trait SourceDatabase {
     #[salsa::input]
     fn file_source_root(&self, file_id: FileId) -> SourceRootId;
 
+    /// The number of source roots, whose ids are `0..source_root_count`.
+    #[salsa::input]
+    fn source_root_count(&self) -> u32;
+
+    /// The file at `path` in any source root.
+    fn file_for_path(&self, path: VfsPath) -> Option<FileId>;
+
     #[salsa::input]
     fn flake_graph(&self) -> Arc<FlakeGraph>;
 
     #[salsa::input]
-    fn nixos_options(&self) -> Arc<NixosOptions>;
+    fn option_set(&self, set: OptionSet) -> Arc<NixosOptions>;
+
+    /// Directories whose files are modules of the option set, besides the ones detected by
+    /// their shape.
+    #[salsa::input]
+    fn option_set_module_paths(&self, set: OptionSet) -> Arc<Vec<VfsPath>>;
+
+    /// Renamed and removed attributes of nixpkgs, effective for the nixpkgs in use.
+    #[salsa::input]
+    fn package_aliases(&self) -> Arc<PackageAliases>;
+
+    /// Top-level packages of the nixpkgs in use, for completion.
+    #[salsa::input]
+    fn package_index(&self) -> Arc<PackageIndex>;
+
+    /// Documentation of nixpkgs `lib` functions of the nixpkgs in use.
+    #[salsa::input]
+    fn lib_docs(&self) -> Arc<LibDocs>;
+
+    /// The search path for `<name/path>` lookups.
+    #[salsa::input]
+    fn search_path(&self) -> Arc<SearchPath>;
+
+    /// Names of functions auto-calling package files, like `callPackage`.
+    #[salsa::input]
+    fn call_package_names(&self) -> Arc<Vec<String>>;
+
+    /// Names not reported when shadowing outer definitions.
+    #[salsa::input]
+    fn shadowing_ignored_names(&self) -> Arc<Vec<String>>;
+
+    /// Kinds of expressions whose parentheses are not reported as redundant, like `list`.
+    #[salsa::input]
+    fn redundant_paren_kept_kinds(&self) -> Arc<Vec<String>>;
+
+    /// Experimental syntax accepted without errors.
+    #[salsa::input]
+    fn language_features(&self) -> Arc<Vec<LanguageFeature>>;
 }
 
 fn source_root_flake_info(db: &dyn SourceDatabase, sid: SourceRootId) -> Option<Arc<FlakeInfo>> {
     db.flake_graph().nodes.get(&sid).cloned().map(Arc::new)
 }
 
+fn file_for_path(db: &dyn SourceDatabase, path: VfsPath) -> Option<FileId> {
+    (0..db.source_root_count())
+        .find_map(|sid| db.source_root(SourceRootId(sid)).file_for_path(&path))
+}
+
 #[derive(Default, Clone, PartialEq, Eq)]
 pub struct Change {
     pub flake_graph: Option<FlakeGraph>,
     pub roots: Option<Vec<SourceRoot>>,
     pub file_changes: Vec<(FileId, Arc<str>)>,
-    pub nixos_options: Option<NixosOptions>,
+    pub option_sets: HashMap<OptionSet, NixosOptions>,
+    pub option_set_module_paths: HashMap<OptionSet, Vec<VfsPath>>,
+    pub package_aliases: Option<PackageAliases>,
+    pub package_index: Option<PackageIndex>,
+    pub lib_docs: Option<LibDocs>,
+    pub search_path: Option<SearchPath>,
+    pub call_package_names: Option<Vec<String>>,
+    pub shadowing_ignored_names: Option<Vec<String>>,
+    pub redundant_paren_kept_kinds: Option<Vec<String>>,
+    pub language_features: Option<Vec<LanguageFeature>>,
 }
 
 impl Change {
@@ -279,8 +394,44 @@ impl Change {
         self.flake_graph = Some(graph);
     }
 
-    pub fn set_nixos_options(&mut self, opts: NixosOptions) {
-        self.nixos_options = Some(opts);
+    pub fn set_option_set(&mut self, set: OptionSet, opts: NixosOptions) {
+        self.option_sets.insert(set, opts);
+    }
+
+    pub fn set_option_set_module_paths(&mut self, set: OptionSet, paths: Vec<VfsPath>) {
+        self.option_set_module_paths.insert(set, paths);
+    }
+
+    pub fn set_package_aliases(&mut self, aliases: PackageAliases) {
+        self.package_aliases = Some(aliases);
+    }
+
+    pub fn set_package_index(&mut self, index: PackageIndex) {
+        self.package_index = Some(index);
+    }
+
+    pub fn set_lib_docs(&mut self, docs: LibDocs) {
+        self.lib_docs = Some(docs);
+    }
+
+    pub fn set_search_path(&mut self, search_path: SearchPath) {
+        self.search_path = Some(search_path);
+    }
+
+    pub fn set_call_package_names(&mut self, names: Vec<String>) {
+        self.call_package_names = Some(names);
+    }
+
+    pub fn set_shadowing_ignored_names(&mut self, names: Vec<String>) {
+        self.shadowing_ignored_names = Some(names);
+    }
+
+    pub fn set_redundant_paren_kept_kinds(&mut self, kinds: Vec<String>) {
+        self.redundant_paren_kept_kinds = Some(kinds);
+    }
+
+    pub fn set_language_features(&mut self, features: Vec<LanguageFeature>) {
+        self.language_features = Some(features);
     }
 
     pub fn set_roots(&mut self, roots: Vec<SourceRoot>) {
@@ -295,11 +446,43 @@ impl Change {
         if let Some(flake_graph) = self.flake_graph {
             db.set_flake_graph_with_durability(Arc::new(flake_graph), Durability::MEDIUM);
         }
-        if let Some(opts) = self.nixos_options {
-            db.set_nixos_options_with_durability(Arc::new(opts), Durability::MEDIUM);
+        for (set, opts) in self.option_sets {
+            db.set_option_set_with_durability(set, Arc::new(opts), Durability::MEDIUM);
+        }
+        for (set, paths) in self.option_set_module_paths {
+            db.set_option_set_module_paths_with_durability(
+                set,
+                Arc::new(paths),
+                Durability::MEDIUM,
+            );
+        }
+        if let Some(aliases) = self.package_aliases {
+            db.set_package_aliases_with_durability(Arc::new(aliases), Durability::MEDIUM);
+        }
+        if let Some(index) = self.package_index {
+            db.set_package_index_with_durability(Arc::new(index), Durability::MEDIUM);
+        }
+        if let Some(docs) = self.lib_docs {
+            db.set_lib_docs_with_durability(Arc::new(docs), Durability::MEDIUM);
+        }
+        if let Some(search_path) = self.search_path {
+            db.set_search_path_with_durability(Arc::new(search_path), Durability::MEDIUM);
+        }
+        if let Some(names) = self.call_package_names {
+            db.set_call_package_names_with_durability(Arc::new(names), Durability::MEDIUM);
+        }
+        if let Some(names) = self.shadowing_ignored_names {
+            db.set_shadowing_ignored_names_with_durability(Arc::new(names), Durability::MEDIUM);
+        }
+        if let Some(kinds) = self.redundant_paren_kept_kinds {
+            db.set_redundant_paren_kept_kinds_with_durability(Arc::new(kinds), Durability::MEDIUM);
+        }
+        if let Some(features) = self.language_features {
+            db.set_language_features_with_durability(Arc::new(features), Durability::MEDIUM);
         }
         if let Some(roots) = self.roots {
-            u32::try_from(roots.len()).expect("Length overflow");
+            let cnt = u32::try_from(roots.len()).expect("Length overflow");
+            db.set_source_root_count_with_durability(cnt, Durability::HIGH);
             for (sid, root) in (0u32..).map(SourceRootId).zip(roots) {
                 for (fid, _) in root.files() {
                     db.set_file_source_root_with_durability(fid, sid, Durability::HIGH);
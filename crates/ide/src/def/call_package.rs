@@ -0,0 +1,117 @@
+//! The `callPackage` idiom. `callPackage ./pkg.nix { foo = 1; }` calls the function in
+//! `pkg.nix` with its parameters filled from the package set, overridden by the attrset.
+//! Names of such functions are configured by `SourceDatabase::call_package_names`.
+use std::sync::Arc;
+
+use crate::{FileId, Module, SourceDatabase};
+
+use super::{DefDatabase, Expr, ExprId, Literal};
+
+/// A call `callPackage ./pkg.nix args`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallPackageSite {
+    /// The whole application.
+    pub apply_expr: ExprId,
+    /// The function, like `callPackage` or `pkgs.callPackage`.
+    pub func_expr: ExprId,
+    /// The path literal of the package file.
+    pub path_expr: ExprId,
+    /// The explicit arguments.
+    pub arg_expr: ExprId,
+    /// The package file, if it is in the workspace.
+    pub callee: Option<FileId>,
+}
+
+/// Check if `expr` is one of `call_package_names`, or the same under `<anything>.`.
+pub(crate) fn is_call_package<DB: SourceDatabase + ?Sized>(
+    db: &DB,
+    module: &Module,
+    expr: ExprId,
+) -> bool {
+    let name = match &module[expr] {
+        Expr::Reference(text) => text,
+        Expr::Select(_, path, None) => match path.last().map(|&attr| &module[attr]) {
+            Some(Expr::Literal(Literal::String(text))) => text,
+            _ => return false,
+        },
+        _ => return false,
+    };
+    db.call_package_names().iter().any(|n| n == name)
+}
+
+pub(crate) fn call_package_sites_query(
+    db: &dyn DefDatabase,
+    file_id: FileId,
+) -> Arc<Vec<CallPackageSite>> {
+    let module = db.module(file_id);
+    let sites = module
+        .exprs()
+        .filter_map(|(apply_expr, kind)| {
+            let &Expr::Apply(func, arg_expr) = kind else {
+                return None;
+            };
+            let &Expr::Apply(func_expr, path_expr) = &module[func] else {
+                return None;
+            };
+            let &Expr::Literal(Literal::Path(path)) = &module[path_expr] else {
+                return None;
+            };
+            if !is_call_package(db, &module, func_expr) {
+                return None;
+            }
+            Some(CallPackageSite {
+                apply_expr,
+                func_expr,
+                path_expr,
+                arg_expr,
+                callee: path.resolve_file(db),
+            })
+        })
+        .collect();
+    Arc::new(sites)
+}
+
+pub(crate) fn is_called_package_query(db: &dyn DefDatabase, file_id: FileId) -> bool {
+    db.module_referrers(file_id).iter().any(|&referrer| {
+        db.call_package_sites(referrer)
+            .iter()
+            .any(|site| site.callee == Some(file_id))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use crate::{DefDatabase, SourceDatabase};
+    use std::sync::Arc;
+
+    #[test]
+    fn sites() {
+        let (mut db, f) = TestDB::from_fixture(
+            "
+#- /default.nix
+{ pkgs, callPackage }: [
+  (callPackage ./a.nix { })
+  (pkgs.callPackages ./b.nix { foo = 1; })
+  (pkgs.newScope ./a.nix { })
+  (callPackage ./missing.nix { })
+]
+#- /a.nix
+{ }: 1
+#- /b.nix
+{ }: 2
+            ",
+        )
+        .unwrap();
+        let callees = |db: &TestDB| {
+            db.call_package_sites(f["/default.nix"])
+                .iter()
+                .map(|site| site.callee)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(callees(&db), [Some(f["/a.nix"]), Some(f["/b.nix"]), None]);
+
+        db.set_call_package_names(Arc::new(vec!["newScope".into()]));
+        assert_eq!(callees(&db), [Some(f["/a.nix"])]);
+    }
+}
@@ -0,0 +1,545 @@
+//! A tiny evaluator of constant expressions, for showing computed values like
+//! `"prefix-${version}"` where `version` is a string literal, and folding dynamic attributes
+//! like `${name}` into static ones.
+//!
+//! Only literals, lists, `let`/`if`/`assert`, pure operators and a few builtins on them are
+//! supported. Anything else, including lambdas, attrsets and paths, is not a constant.
+//! Evaluation is strictly bounded by the number of steps, the recursion depth, and the size
+//! of values, so cyclic bindings like `let a = a + 1; in a` simply give up.
+use super::{AstPtr, DefDatabase, ExprId, ModuleSourceMap, NameId, NameResolution, ResolveResult};
+use crate::{FileId, InFile, Module};
+use std::fmt;
+use std::sync::Arc;
+use syntax::ast::{self, AstNode, BinaryOpKind, LiteralKind, UnaryOpKind};
+use syntax::semantic::{
+    escape_string, strip_indent, unescape_string, unescape_string_escape, AttrKind,
+    StrippedStringPart, UnescapedStringPart,
+};
+use syntax::{SyntaxKind, SyntaxNode};
+
+const MAX_STEPS: usize = 1024;
+const MAX_DEPTH: usize = 64;
+const MAX_STRING_LEN: usize = 1024;
+const MAX_LIST_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    List(Vec<ConstValue>),
+}
+
+impl fmt::Display for ConstValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(b) => b.fmt(f),
+            Self::Int(i) => i.fmt(f),
+            Self::Float(x) => x.fmt(f),
+            Self::String(s) => f.write_str(&escape_string(s)),
+            Self::List(xs) => {
+                f.write_str("[")?;
+                for x in xs {
+                    write!(f, " {x}")?;
+                }
+                f.write_str(" ]")
+            }
+        }
+    }
+}
+
+/// Evaluate the value bound to `name`, if it is a constant.
+pub(crate) fn const_eval_name(db: &dyn DefDatabase, name: InFile<NameId>) -> Option<ConstValue> {
+    Evaluator::new(db, name.file_id).eval_name(name.value, 0)
+}
+
+/// Evaluate `expr` to a string, if it is a constant.
+pub(crate) fn const_eval_string<DB: DefDatabase + ?Sized>(
+    db: &DB,
+    expr: InFile<ExprId>,
+) -> Option<String> {
+    let mut ev = Evaluator::new(db, expr.file_id);
+    let node = ev.source_map.node_for_expr(expr.value)?.to_node(&ev.root);
+    match ev.eval(&ast::Expr::cast(node)?, 0)? {
+        ConstValue::String(s) => Some(s),
+        _ => None,
+    }
+}
+
+/// The name of `attr`, folding `${expr}` if `expr` is a constant string, like `${name}` where
+/// `name = "foo";`.
+pub(crate) fn const_attr_name<DB: DefDatabase + ?Sized>(
+    db: &DB,
+    file: FileId,
+    attr: ast::Attr,
+) -> Option<String> {
+    match AttrKind::of(attr) {
+        AttrKind::Static(name) => name,
+        AttrKind::Dynamic(expr) => match Evaluator::new(db, file).eval(&expr?, 0)? {
+            ConstValue::String(s) => Some(s),
+            _ => None,
+        },
+    }
+}
+
+struct Evaluator {
+    root: SyntaxNode,
+    module: Arc<Module>,
+    source_map: Arc<ModuleSourceMap>,
+    nameres: Arc<NameResolution>,
+    steps: usize,
+}
+
+impl Evaluator {
+    fn new<DB: DefDatabase + ?Sized>(db: &DB, file: FileId) -> Self {
+        Self {
+            root: db.parse(file).syntax_node(),
+            module: db.module(file),
+            source_map: db.source_map(file),
+            nameres: db.name_resolution(file),
+            steps: 0,
+        }
+    }
+
+    fn tick(&mut self, depth: usize) -> Option<()> {
+        self.steps += 1;
+        (self.steps <= MAX_STEPS && depth <= MAX_DEPTH).then_some(())
+    }
+
+    fn eval_name(&mut self, name: NameId, depth: usize) -> Option<ConstValue> {
+        self.tick(depth)?;
+        // Only names defined once, by `name = value;` or `inherit name;`.
+        let source_map = self.source_map.clone();
+        let mut ptrs = source_map.nodes_for_name(name);
+        let (Some(ptr), None) = (ptrs.next(), ptrs.next()) else {
+            return None;
+        };
+        let node = ptr.to_node(&self.root);
+        let parent = node.parent()?;
+        match parent.kind() {
+            SyntaxKind::ATTR_PATH => {
+                let path = ast::Attrpath::cast(parent)?;
+                if path.attrs().nth(1).is_some() {
+                    return None;
+                }
+                let value = ast::AttrpathValue::cast(path.syntax().parent()?)?.value()?;
+                self.eval(&value, depth + 1)
+            }
+            SyntaxKind::INHERIT if ast::Inherit::cast(parent)?.from_expr().is_none() => {
+                let expr = source_map.expr_for_node(ptr)?;
+                self.eval_ref(expr, depth + 1)
+            }
+            _ => None,
+        }
+    }
+
+    fn eval_ref(&mut self, expr: ExprId, depth: usize) -> Option<ConstValue> {
+        match self.nameres.get(expr)? {
+            ResolveResult::Builtin("true") => Some(ConstValue::Bool(true)),
+            ResolveResult::Builtin("false") => Some(ConstValue::Bool(false)),
+            ResolveResult::Builtin("null") => Some(ConstValue::Null),
+            &ResolveResult::Definition(name) => self.eval_name(name, depth),
+            _ => None,
+        }
+    }
+
+    fn eval(&mut self, expr: &ast::Expr, depth: usize) -> Option<ConstValue> {
+        self.tick(depth)?;
+        let depth = depth + 1;
+        let ret = match expr {
+            ast::Expr::Literal(lit) => {
+                let tok = lit.token()?;
+                match lit.kind()? {
+                    LiteralKind::Int => ConstValue::Int(tok.text().parse().ok()?),
+                    LiteralKind::Float => ConstValue::Float(tok.text().parse().ok()?),
+                    LiteralKind::Uri | LiteralKind::Path | LiteralKind::SearchPath => return None,
+                }
+            }
+            ast::Expr::String(s) => {
+                let mut ret = String::new();
+                unescape_string(s, |part| {
+                    match part {
+                        UnescapedStringPart::Fragment(frag) => ret += frag,
+                        UnescapedStringPart::Dynamic(d) => {
+                            ret += &self.eval_dynamic(&d, depth).ok_or(())?;
+                        }
+                    }
+                    check_len(&ret).ok_or(())
+                })
+                .ok()?;
+                ConstValue::String(ret)
+            }
+            ast::Expr::IndentString(s) => {
+                let mut ret = String::new();
+                strip_indent(s, |part| {
+                    match part {
+                        StrippedStringPart::Fragment(frag) => ret += frag,
+                        StrippedStringPart::Escape(tok) => {
+                            ret += unescape_string_escape(tok.text())
+                        }
+                        StrippedStringPart::Dynamic(d) => {
+                            ret += &self.eval_dynamic(&d, depth).ok_or(())?;
+                        }
+                    }
+                    check_len(&ret).ok_or(())
+                })
+                .ok()?;
+                ConstValue::String(ret)
+            }
+            ast::Expr::Ref(_) => {
+                let expr = self.source_map.expr_for_node(AstPtr::new(expr.syntax()))?;
+                self.eval_ref(expr, depth)?
+            }
+            ast::Expr::Paren(e) => self.eval(&e.expr()?, depth)?,
+            ast::Expr::LetIn(e) => self.eval(&e.body()?, depth)?,
+            ast::Expr::Assert(e) => match self.eval(&e.condition()?, depth)? {
+                ConstValue::Bool(true) => self.eval(&e.body()?, depth)?,
+                _ => return None,
+            },
+            ast::Expr::IfThenElse(e) => match self.eval(&e.condition()?, depth)? {
+                ConstValue::Bool(true) => self.eval(&e.then_body()?, depth)?,
+                ConstValue::Bool(false) => self.eval(&e.else_body()?, depth)?,
+                _ => return None,
+            },
+            ast::Expr::List(e) => {
+                let elems = e
+                    .elements()
+                    .map(|elem| self.eval(&elem, depth))
+                    .collect::<Option<Vec<_>>>()?;
+                check_list(elems)?
+            }
+            ast::Expr::UnaryOp(e) => match (e.op_kind()?, self.eval(&e.arg()?, depth)?) {
+                (UnaryOpKind::Not, ConstValue::Bool(b)) => ConstValue::Bool(!b),
+                (UnaryOpKind::Negate, ConstValue::Int(i)) => ConstValue::Int(i.checked_neg()?),
+                (UnaryOpKind::Negate, ConstValue::Float(x)) => ConstValue::Float(-x),
+                _ => return None,
+            },
+            ast::Expr::BinaryOp(e) => self.eval_binary(e, depth)?,
+            ast::Expr::Apply(e) => self.eval_apply(e, depth)?,
+            _ => return None,
+        };
+        Some(ret)
+    }
+
+    /// Only strings can be interpolated.
+    fn eval_dynamic(&mut self, d: &ast::Dynamic, depth: usize) -> Option<String> {
+        match self.eval(&d.expr()?, depth)? {
+            ConstValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn eval_binary(&mut self, e: &ast::BinaryOp, depth: usize) -> Option<ConstValue> {
+        use ConstValue::{Bool, Float, Int, List, String};
+
+        let op = e.op_kind()?;
+        let lhs = self.eval(&e.lhs()?, depth)?;
+        // Short-circuiting.
+        match (op, &lhs) {
+            (BinaryOpKind::And, Bool(false)) => return Some(Bool(false)),
+            (BinaryOpKind::Or, Bool(true)) | (BinaryOpKind::Imply, Bool(false)) => {
+                return Some(Bool(true))
+            }
+            _ => {}
+        }
+        let rhs = self.eval(&e.rhs()?, depth)?;
+        let ret = match (op, lhs, rhs) {
+            (BinaryOpKind::And | BinaryOpKind::Or | BinaryOpKind::Imply, Bool(_), Bool(b)) => {
+                Bool(b)
+            }
+            (BinaryOpKind::Equal, a, b) => Bool(const_eq(&a, &b)),
+            (BinaryOpKind::NotEqual, a, b) => Bool(!const_eq(&a, &b)),
+            (BinaryOpKind::Concat, List(mut a), List(b)) => {
+                a.extend(b);
+                check_list(a)?
+            }
+            (BinaryOpKind::Add, String(mut a), String(b)) => {
+                a += &b;
+                check_len(&a)?;
+                String(a)
+            }
+            (BinaryOpKind::Add, Int(a), Int(b)) => Int(a.checked_add(b)?),
+            (BinaryOpKind::Sub, Int(a), Int(b)) => Int(a.checked_sub(b)?),
+            (BinaryOpKind::Mul, Int(a), Int(b)) => Int(a.checked_mul(b)?),
+            (BinaryOpKind::Div, Int(a), Int(b)) => Int(a.checked_div(b)?),
+            (
+                op @ (BinaryOpKind::Less
+                | BinaryOpKind::Greater
+                | BinaryOpKind::LessEqual
+                | BinaryOpKind::GreaterEqual),
+                a,
+                b,
+            ) => {
+                let ord = match (&a, &b) {
+                    (Int(a), Int(b)) => a.cmp(b),
+                    (String(a), String(b)) => a.cmp(b),
+                    _ => as_float(&a)?.partial_cmp(&as_float(&b)?)?,
+                };
+                Bool(match op {
+                    BinaryOpKind::Less => ord.is_lt(),
+                    BinaryOpKind::Greater => ord.is_gt(),
+                    BinaryOpKind::LessEqual => ord.is_le(),
+                    _ => ord.is_ge(),
+                })
+            }
+            (op, a, b) => {
+                let (a, b) = (as_float(&a)?, as_float(&b)?);
+                match op {
+                    BinaryOpKind::Add => Float(a + b),
+                    BinaryOpKind::Sub => Float(a - b),
+                    BinaryOpKind::Mul => Float(a * b),
+                    BinaryOpKind::Div if b != 0.0 => Float(a / b),
+                    _ => return None,
+                }
+            }
+        };
+        Some(ret)
+    }
+
+    /// Calls of a few pure builtins, like `builtins.length [ 1 2 ]`.
+    fn eval_apply(&mut self, e: &ast::Apply, depth: usize) -> Option<ConstValue> {
+        use ConstValue::{Int, List, String};
+
+        let mut args = vec![e.argument()?];
+        let mut func = e.function()?;
+        while let ast::Expr::Apply(inner) = &func {
+            args.push(inner.argument()?);
+            func = inner.function()?;
+        }
+        args.reverse();
+        let name = self.builtin_name(&func.flatten_paren()?)?;
+        let mut args = args
+            .iter()
+            .map(|arg| self.eval(arg, depth))
+            .collect::<Option<Vec<_>>>()?
+            .into_iter();
+
+        let ret = match (&*name, args.next()?, args.next()) {
+            ("length", List(xs), None) => Int(xs.len() as i64),
+            ("stringLength", String(s), None) => Int(s.len() as i64),
+            ("toString", v, None) => String(to_string(&v)?),
+            ("head", List(xs), None) => xs.into_iter().next()?,
+            ("elemAt", List(xs), Some(Int(i))) => xs.into_iter().nth(usize::try_from(i).ok()?)?,
+            ("concatStringsSep", String(sep), Some(List(xs))) => {
+                let strs = xs
+                    .into_iter()
+                    .map(|x| match x {
+                        String(s) => Some(s),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                let s = strs.join(&sep);
+                check_len(&s)?;
+                String(s)
+            }
+            _ => return None,
+        };
+        // Over-applied.
+        if args.next().is_some() {
+            return None;
+        }
+        Some(ret)
+    }
+
+    /// The name of a builtin referenced by `name` or `builtins.name`.
+    fn builtin_name(&self, func: &ast::Expr) -> Option<String> {
+        if let ast::Expr::Select(sel) = func {
+            let set = sel.set()?.flatten_paren()?;
+            let set = self.source_map.expr_for_node(AstPtr::new(set.syntax()))?;
+            let Some(ResolveResult::Builtin("builtins")) = self.nameres.get(set) else {
+                return None;
+            };
+            let mut attrs = sel.attrpath()?.attrs();
+            let (Some(attr), None) = (attrs.next(), attrs.next()) else {
+                return None;
+            };
+            return match AttrKind::of(attr) {
+                AttrKind::Static(name) => name,
+                AttrKind::Dynamic(_) => None,
+            };
+        }
+        let expr = self.source_map.expr_for_node(AstPtr::new(func.syntax()))?;
+        self.nameres
+            .check_builtin(expr, &self.module)
+            .map(Into::into)
+    }
+}
+
+fn check_len(s: &str) -> Option<()> {
+    (s.len() <= MAX_STRING_LEN).then_some(())
+}
+
+fn check_list(xs: Vec<ConstValue>) -> Option<ConstValue> {
+    (xs.len() <= MAX_LIST_LEN).then_some(ConstValue::List(xs))
+}
+
+/// Ints and floats are compared by their numeric values.
+fn const_eq(a: &ConstValue, b: &ConstValue) -> bool {
+    match (a, b) {
+        (ConstValue::List(a), ConstValue::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| const_eq(a, b))
+        }
+        _ => match (as_float(a), as_float(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => a == b,
+        },
+    }
+}
+
+fn as_float(v: &ConstValue) -> Option<f64> {
+    match *v {
+        ConstValue::Int(i) => Some(i as f64),
+        ConstValue::Float(x) => Some(x),
+        _ => None,
+    }
+}
+
+/// Coerce to string like `builtins.toString`.
+fn to_string(v: &ConstValue) -> Option<String> {
+    let s = match v {
+        ConstValue::Null | ConstValue::Bool(false) => String::new(),
+        ConstValue::Bool(true) => "1".into(),
+        ConstValue::Int(i) => i.to_string(),
+        ConstValue::Float(x) => format!("{x:.6}"),
+        ConstValue::String(s) => s.clone(),
+        ConstValue::List(xs) => xs
+            .iter()
+            .map(to_string)
+            .collect::<Option<Vec<_>>>()?
+            .join(" "),
+    };
+    check_len(&s)?;
+    Some(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::const_eval_name;
+    use crate::def::AstPtr;
+    use crate::tests::TestDB;
+    use crate::{DefDatabase, InFile};
+    use expect_test::{expect, Expect};
+    use syntax::ast::{self, AstNode};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let name_node = db.node_at::<ast::Name>(f[0]).expect("No name");
+        let name = db
+            .source_map(f[0].file_id)
+            .name_for_node(AstPtr::new(name_node.syntax()))
+            .expect("Not a name");
+        let got = const_eval_name(&db, InFile::new(f[0].file_id, name))
+            .map_or_else(|| "None".into(), |v| v.to_string());
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn literal() {
+        check("let $0a = 42; in a", expect!["42"]);
+        check("let $0a = 1.5; in a", expect!["1.5"]);
+        check(r#"let $0a = "a\"b\n"; in a"#, expect![[r#""a\"b\n""#]]);
+        check(
+            "let $0a = ''\n  foo\n    bar\n''; in a",
+            expect![[r#""foo\n  bar\n""#]],
+        );
+        check(
+            "let $0a = [ true false null ]; in a",
+            expect!["[ true false null ]"],
+        );
+        check("let $0a = ./foo; in a", expect!["None"]);
+        check("let $0a = { }; in a", expect!["None"]);
+    }
+
+    #[test]
+    fn reference() {
+        check(
+            r#"let version = "1.2.3"; $0name = "prefix-${version}"; in name"#,
+            expect![[r#""prefix-1.2.3""#]],
+        );
+        check(
+            r#"rec { pname = "foo"; version = "1"; $0name = "${pname}-${version}"; }"#,
+            expect![[r#""foo-1""#]],
+        );
+        check(
+            r#"let v = "1"; in { inherit v; $0s = ''v${v}''; }"#,
+            expect![[r#""v1""#]],
+        );
+        check(
+            r#"let v = "1"; in rec { inherit v; $0s = v; }"#,
+            expect![[r#""1""#]],
+        );
+        // Not a constant.
+        check("x: let $0a = x; in a", expect!["None"]);
+        check("let $0a.b = 1; in a", expect!["None"]);
+        // Ints cannot be interpolated.
+        check(r#"let v = 1; $0a = "${v}"; in a"#, expect!["None"]);
+    }
+
+    #[test]
+    fn operators() {
+        check("let $0a = 1 + 2 * 3 - -4; in a", expect!["11"]);
+        check("let $0a = 7 / 2 + 0.5; in a", expect!["3.5"]);
+        check("let $0a = 1 / 0; in a", expect!["None"]);
+        check("let $0a = 9223372036854775807 + 1; in a", expect!["None"]);
+        check(r#"let $0a = "a" + "b"; in a"#, expect![[r#""ab""#]]);
+        check("let $0a = [ 1 ] ++ [ 2 ]; in a", expect!["[ 1 2 ]"]);
+        check(
+            "let $0a = [ (1 == 1.0) (1 < 2) (\"b\" <= \"a\") (!true || false) ]; in a",
+            expect!["[ true true false false ]"],
+        );
+        check("let b = false; $0a = b && throw 1; in a", expect!["false"]);
+        check(
+            "let $0a = if 1 > 0 then \"pos\" else \"neg\"; in a",
+            expect![[r#""pos""#]],
+        );
+        check(
+            "let $0a = let b = 1; in assert b == 1; b; in a",
+            expect!["1"],
+        );
+    }
+
+    #[test]
+    fn builtins() {
+        check("let $0a = builtins.length [ 1 2 3 ]; in a", expect!["3"]);
+        check(
+            r#"let $0a = builtins.stringLength "foo"; in a"#,
+            expect!["3"],
+        );
+        check(
+            r#"let $0a = toString [ 1 true null "a" 1.5 ]; in a"#,
+            expect![[r#""1 1  a 1.500000""#]],
+        );
+        check(
+            r#"let $0a = builtins.concatStringsSep ", " [ "a" "b" ]; in a"#,
+            expect![[r#""a, b""#]],
+        );
+        check("let $0a = builtins.elemAt [ 1 2 ] 1; in a", expect!["2"]);
+        check(
+            "let inherit (builtins) head; $0a = head [ 1 2 ]; in a",
+            expect!["1"],
+        );
+        check("let $0a = builtins.length [ 1 ] 2; in a", expect!["None"]);
+        check("let $0a = builtins.typeOf 1; in a", expect!["None"]);
+    }
+
+    #[test]
+    fn bounded() {
+        check("let $0a = a + 1; in a", expect!["None"]);
+        check("let a = b; b = a; in { $0c = a; }", expect!["None"]);
+        check(
+            r#"
+let
+  s0 = "aaaaaaaaaaaaaaaa";
+  s1 = s0 + s0; s2 = s1 + s1; s3 = s2 + s2; s4 = s3 + s3;
+  s5 = s4 + s4; s6 = s5 + s5;
+  $0s7 = s6 + s6;
+in s7
+            "#,
+            expect!["None"],
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! Uses of deprecated nixpkgs attributes, according to the `package_aliases` database.
+//!
+//! We recognize `pkgs`, the conventional name of the package set, in
+//! - Selections `pkgs.name`, also in `inherit (pkgs) name`.
+//! - Names from `with pkgs;`.
+//! - Parameters of packages `{ name }:`, which are passed by `callPackage`.
+use super::{BindingValue, DefDatabase, Expr, ExprId, Literal, NameId, ResolveResult};
+use crate::{Diagnostic, DiagnosticKind, FileId, FileRange, ModuleKind};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// The conventional name of the package set.
+const PACKAGE_SET_NAME: &str = "pkgs";
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct DeprecatedPackages {
+    uses: Box<[DeprecatedPackage]>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeprecatedPackage {
+    /// The attribute name in `pkgs.name`.
+    Attr(ExprId),
+    /// A reference to `name` from `with pkgs;`.
+    WithRef(ExprId),
+    /// A name defined by `inherit` or a package parameter, which cannot be replaced in place.
+    Name(NameId),
+}
+
+impl DeprecatedPackages {
+    pub fn iter(&self) -> impl Iterator<Item = DeprecatedPackage> + ExactSizeIterator + '_ {
+        self.uses.iter().copied()
+    }
+
+    pub fn to_diagnostics<'a>(
+        &'a self,
+        db: &'a dyn DefDatabase,
+        file: FileId,
+    ) -> impl Iterator<Item = Diagnostic> + 'a {
+        let module = db.module(file);
+        let source_map = db.source_map(file);
+        let aliases = db.package_aliases();
+        self.uses.iter().filter_map(move |&use_| {
+            let (name, ptr) = match use_ {
+                DeprecatedPackage::Attr(expr) => {
+                    let Expr::Literal(Literal::String(name)) = &module[expr] else {
+                        return None;
+                    };
+                    (name, source_map.node_for_expr(expr)?)
+                }
+                DeprecatedPackage::WithRef(expr) => {
+                    let Expr::Reference(name) = &module[expr] else {
+                        return None;
+                    };
+                    (name, source_map.node_for_expr(expr)?)
+                }
+                DeprecatedPackage::Name(name) => {
+                    (&module[name].text, source_map.nodes_for_name(name).next()?)
+                }
+            };
+            let alias = aliases.get(name)?;
+            let mut note = match &alias.to {
+                Some(to) => format!("Renamed to `{to}`"),
+                None => "Removed".into(),
+            };
+            if let Some(since) = alias.since {
+                note += &format!(" since nixpkgs {since}");
+            }
+            if let Some(msg) = &alias.message {
+                note += ": ";
+                note += msg;
+            }
+            let range = ptr.text_range();
+            Some(
+                Diagnostic::new(range, DiagnosticKind::DeprecatedPackage)
+                    .with_note(FileRange::new(file, range), note),
+            )
+        })
+    }
+}
+
+pub(crate) fn deprecated_packages_query(
+    db: &dyn DefDatabase,
+    file_id: FileId,
+) -> Arc<DeprecatedPackages> {
+    let aliases = db.package_aliases();
+    if aliases.is_empty() {
+        return Arc::default();
+    }
+
+    let module = db.module(file_id);
+    let name_res = db.name_resolution(file_id);
+    let is_pkgs =
+        |expr: ExprId| matches!(&module[expr], Expr::Reference(name) if name == PACKAGE_SET_NAME);
+    let is_from_with_pkgs = |expr: ExprId| match name_res.get(expr) {
+        Some(ResolveResult::WithExprs(withs)) => withs
+            .iter()
+            .any(|&with| matches!(module[with], Expr::With(env, _) if is_pkgs(env))),
+        _ => false,
+    };
+
+    // References introduced by `inherit name;` are reported on names.
+    let mut inherited_refs = HashSet::new();
+    let mut uses = Vec::new();
+    for (_, kind) in module.exprs() {
+        let (Expr::LetIn(bindings, _)
+        | Expr::Attrset(bindings)
+        | Expr::RecAttrset(bindings)
+        | Expr::LetAttrset(bindings)) = kind
+        else {
+            continue;
+        };
+        for &(name, value) in bindings.statics.iter() {
+            let is_deprecated = match value {
+                BindingValue::Inherit(expr) => {
+                    inherited_refs.insert(expr);
+                    is_from_with_pkgs(expr)
+                }
+                BindingValue::InheritFrom(i) => is_pkgs(bindings.inherit_froms[i]),
+                BindingValue::Expr(_) => false,
+            };
+            if is_deprecated && aliases.get(&module[name].text).is_some() {
+                uses.push(DeprecatedPackage::Name(name));
+            }
+        }
+    }
+
+    for (expr, kind) in module.exprs() {
+        match kind {
+            Expr::Select(set, path, _) if is_pkgs(*set) => {
+                let Some(&attr) = path.first() else { continue };
+                if matches!(&module[attr], Expr::Literal(Literal::String(name)) if aliases.get(name).is_some())
+                {
+                    uses.push(DeprecatedPackage::Attr(attr));
+                }
+            }
+            Expr::Reference(name)
+                if !inherited_refs.contains(&expr)
+                    && aliases.get(name).is_some()
+                    && is_from_with_pkgs(expr) =>
+            {
+                uses.push(DeprecatedPackage::WithRef(expr));
+            }
+            _ => {}
+        }
+    }
+
+    if let ModuleKind::Package { lambda_expr } = *db.module_kind(file_id) {
+        if let Expr::Lambda(_, Some(pat), _) = &module[lambda_expr] {
+            uses.extend(
+                pat.fields
+                    .iter()
+                    .filter_map(|&(name, _)| name)
+                    .filter(|&name| aliases.get(&module[name].text).is_some())
+                    .map(DeprecatedPackage::Name),
+            );
+        }
+    }
+
+    Arc::new(DeprecatedPackages { uses: uses.into() })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::def::DefDatabase;
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, file) = TestDB::single_file(fixture).unwrap();
+        let got = db
+            .deprecated_packages(file)
+            .to_diagnostics(&db, file)
+            .map(|diag| diag.debug_display().to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn select() {
+        check(
+            "{ pkgs }: [ pkgs.gnome3.gnome-shell pkgs.hello pkgs.nerdfonts ]",
+            expect![[r#"
+                17..23: DeprecatedPackage
+                    17..23: Renamed to `gnome` since nixpkgs 21.11
+                52..61: DeprecatedPackage
+                    52..61: Removed since nixpkgs 25.05: Use individual fonts from `nerd-fonts` instead"#]],
+        );
+        check("{ a }: a.gnome3", expect![""]);
+    }
+
+    #[test]
+    fn with() {
+        check(
+            "{ pkgs }: with pkgs; [ gnome3 ]",
+            expect![[r#"
+                23..29: DeprecatedPackage
+                    23..29: Renamed to `gnome` since nixpkgs 21.11"#]],
+        );
+        check(
+            "{ pkgs }: with pkgs; let gnome3 = 1; in gnome3",
+            expect![""],
+        );
+        check("{ lib }: with lib; gnome3", expect![""]);
+    }
+
+    #[test]
+    fn inherit() {
+        check(
+            "{ pkgs }: { inherit (pkgs) gnome3 hello; }",
+            expect![[r#"
+                27..33: DeprecatedPackage
+                    27..33: Renamed to `gnome` since nixpkgs 21.11"#]],
+        );
+        check(
+            "{ pkgs }: with pkgs; { inherit gnome3; }",
+            expect![[r#"
+                31..37: DeprecatedPackage
+                    31..37: Renamed to `gnome` since nixpkgs 21.11"#]],
+        );
+    }
+
+    #[test]
+    fn package_param() {
+        check(
+            "{ stdenv, wireguard }: stdenv.mkDerivation { buildInputs = [ wireguard ]; }",
+            expect![[r#"
+                10..19: DeprecatedPackage
+                    10..19: Renamed to `wireguard-tools` since nixpkgs 20.09"#]],
+        );
+    }
+}
@@ -4,9 +4,9 @@ use std::sync::Arc;
 use if_chain::if_chain;
 use smol_str::SmolStr;
 
-use crate::{DefDatabase, FileId, Module};
+use crate::{DefDatabase, FileId, Module, OptionSet};
 
-use super::{BindingValue, Expr, ExprId, NameId};
+use super::{BindingValue, Bindings, Expr, ExprId, Literal, NameId};
 
 /// Guessed kind of a nix file.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -20,6 +20,9 @@ pub enum ModuleKind {
         /// Implicit inputs introduced in the pat-parameter of `outputs`.
         /// NB. `self` parameter is special and is excluded here.
         param_inputs: HashMap<SmolStr, NameId>,
+        /// String values of `follows` inside `inputs`, with the explicit inputs they refer to.
+        /// `inputs.foo.inputs.bar.follows = "baz/qux";` refers to `baz`.
+        follows: Vec<(ExprId, SmolStr)>,
         outputs_expr: Option<ExprId>,
     },
     /// A package definition as the first argument of `callPackage`.
@@ -37,8 +40,22 @@ pub enum ModuleKind {
         /// The lambda expression accepting specialArgs.
         lambda_expr: ExprId,
     },
+    /// A nixpkgs overlay `final: prev: { ... }`, or `self: super: { ... }`.
+    Overlay {
+        /// The outer lambda expression accepting `final`.
+        lambda_expr: ExprId,
+        /// The package set after applying all overlays.
+        final_param: NameId,
+        /// The package set before applying this overlay.
+        prev_param: NameId,
+        /// The attrset of attributes added or overridden by this overlay.
+        body_expr: ExprId,
+    },
 }
 
+/// Parameter names of overlays, as `(final, prev)`.
+const OVERLAY_PARAMS: &[(&str, &str)] = &[("final", "prev"), ("self", "super")];
+
 impl ModuleKind {
     pub(crate) fn module_kind_query(db: &dyn DefDatabase, file_id: FileId) -> Arc<ModuleKind> {
         let module = db.module(file_id);
@@ -50,13 +67,63 @@ impl ModuleKind {
             }
         }
 
-        Arc::new(guess(&module))
+        let is_nixos_module = OptionSet::ALL
+            .iter()
+            .any(|&set| is_in_module_paths(db, file_id, set));
+        let kind = guess(&module, is_nixos_module);
+
+        // Lambdas called by `callPackage` are always packages, of any shape. Finding callers
+        // lowers all files in the source root, thus it is only done for shapes which may be
+        // packages, unless recognized or configured.
+        let entry_expr = peel_expr(&module, module.entry_expr);
+        let ambiguous = match &kind {
+            ModuleKind::Unknown => matches!(module[entry_expr], Expr::Lambda(_, Some(_), _)),
+            ModuleKind::ConfigModule { .. } => !is_nixos_module && !has_config_fields(&module),
+            ModuleKind::Config { .. } => !is_nixos_module,
+            _ => false,
+        };
+        if ambiguous && db.is_called_package(file_id) {
+            return Arc::new(ModuleKind::Package {
+                lambda_expr: entry_expr,
+            });
+        }
+        Arc::new(kind)
+    }
+
+    pub(crate) fn module_option_set_query(db: &dyn DefDatabase, file_id: FileId) -> OptionSet {
+        // Configured directories take precedence. NixOS ones are checked last, since
+        // home-manager and nix-darwin modules are often kept inside NixOS configurations.
+        let configured = [OptionSet::HomeManager, OptionSet::NixDarwin]
+            .into_iter()
+            .find(|&set| is_in_module_paths(db, file_id, set));
+        if let Some(set) = configured {
+            return set;
+        }
+        if is_in_module_paths(db, file_id, OptionSet::Nixos) {
+            return OptionSet::Nixos;
+        }
+        let (ModuleKind::ConfigModule { lambda_expr } | ModuleKind::Config { lambda_expr }) =
+            *db.module_kind(file_id)
+        else {
+            return OptionSet::Nixos;
+        };
+        guess_option_set(&db.module(file_id), lambda_expr).unwrap_or(OptionSet::Nixos)
+    }
+
+    /// Check if `name` is the `final` or `prev` parameter of an overlay.
+    pub fn is_overlay_param(&self, name: NameId) -> bool {
+        matches!(
+            *self,
+            ModuleKind::Overlay { final_param, prev_param, .. }
+                if name == final_param || name == prev_param
+        )
     }
 }
 
 fn parse_flake_nix(module: &Module) -> ModuleKind {
     let mut explicit_inputs = HashMap::new();
     let mut param_inputs = HashMap::new();
+    let mut follows = Vec::new();
     let mut outputs_expr = None;
     if let Expr::Attrset(flake_set) | Expr::RecAttrset(flake_set) = &module[module.entry_expr()] {
         for &(name_id, value) in flake_set.statics.iter() {
@@ -76,6 +143,7 @@ fn parse_flake_nix(module: &Module) -> ModuleKind {
                             (module[input_name_id].text.clone(), input_name_id)
                         })
                         .collect();
+                    collect_follows(module, inputs, &mut follows);
                 }
                 "outputs" => {
                     outputs_expr = Some(value_expr);
@@ -98,13 +166,74 @@ fn parse_flake_nix(module: &Module) -> ModuleKind {
     ModuleKind::FlakeNix {
         explicit_inputs,
         param_inputs,
+        follows,
         outputs_expr,
     }
 }
 
-fn guess(module: &Module) -> ModuleKind {
+/// Collect `follows` of inputs and their nested `inputs`, recursively.
+fn collect_follows(module: &Module, bindings: &Bindings, out: &mut Vec<(ExprId, SmolStr)>) {
+    for &(_, value) in bindings.statics.iter() {
+        let BindingValue::Expr(input_expr) = value else {
+            continue;
+        };
+        let (Expr::Attrset(input) | Expr::RecAttrset(input)) = &module[input_expr] else {
+            continue;
+        };
+        for &(name_id, value) in input.statics.iter() {
+            let BindingValue::Expr(value_expr) = value else {
+                continue;
+            };
+            match (&*module[name_id].text, &module[value_expr]) {
+                ("follows", Expr::Literal(Literal::String(target))) => {
+                    let input_name = target.split('/').next().unwrap_or_default();
+                    out.push((value_expr, input_name.into()));
+                }
+                ("inputs", Expr::Attrset(nested) | Expr::RecAttrset(nested)) => {
+                    collect_follows(module, nested, out);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn is_in_module_paths(db: &dyn DefDatabase, file_id: FileId, set: OptionSet) -> bool {
+    let module_paths = db.option_set_module_paths(set);
+    !module_paths.is_empty() && {
+        let root = db.source_root(db.file_source_root(file_id));
+        let path = root.path_for_file(file_id).as_path();
+        module_paths.iter().any(
+            |dir| matches!((path, dir.as_path()), (Some(path), Some(dir)) if path.starts_with(dir)),
+        )
+    }
+}
+
+fn guess(module: &Module, is_nixos_module: bool) -> ModuleKind {
     let entry_expr = peel_expr(module, module.entry_expr);
 
+    // Lambdas in configured module directories are always NixOS modules, of any shape.
+    if let (true, Expr::Lambda(_, Some(_), body_expr)) = (is_nixos_module, &module[entry_expr]) {
+        return config_module_kind(module, entry_expr, *body_expr);
+    }
+
+    // Try to parse as an overlay with conventional parameter names.
+    if_chain! {
+        if let &Expr::Lambda(Some(final_param), None, inner_expr) = &module[entry_expr];
+        if let &Expr::Lambda(Some(prev_param), None, body_expr) = &module[inner_expr];
+        if OVERLAY_PARAMS.contains(&(&*module[final_param].text, &*module[prev_param].text));
+        let body_expr = peel_expr(module, body_expr);
+        if let Expr::Attrset(_) | Expr::RecAttrset(_) = &module[body_expr];
+        then {
+            return ModuleKind::Overlay {
+                lambda_expr: entry_expr,
+                final_param,
+                prev_param,
+                body_expr,
+            };
+        }
+    }
+
     // Try to parse as package definition.
     if_chain! {
         // Must be a lambda expression with Pat.
@@ -125,25 +254,111 @@ fn guess(module: &Module) -> ModuleKind {
         // Pat must have ellipsis.
         if pat.ellipsis;
         // The body must be an attrset.
-        let body_expr = peel_expr(module, *body_expr);
-        if let Expr::Attrset(bindings) | Expr::RecAttrset(bindings) = &module[body_expr];
+        if let Expr::Attrset(_) | Expr::RecAttrset(_) = &module[peel_expr(module, *body_expr)];
         then {
-            // If it has special fields, it is a NixOS module definition.
-            if bindings
-                .statics
-                .iter()
-                .any(|&(name, _)| matches!(&*module[name].text, "options" | "config" | "meta")) {
-                return ModuleKind::ConfigModule { lambda_expr: entry_expr };
-            }
-            return ModuleKind::Config { lambda_expr: entry_expr };
+            return config_module_kind(module, entry_expr, *body_expr);
         }
     }
 
     ModuleKind::Unknown
 }
 
+fn config_module_kind(module: &Module, lambda_expr: ExprId, body_expr: ExprId) -> ModuleKind {
+    // If it has special fields, it is a NixOS module definition.
+    if let Expr::Attrset(bindings) | Expr::RecAttrset(bindings) =
+        &module[peel_expr(module, body_expr)]
+    {
+        if bindings
+            .statics
+            .iter()
+            .any(|&(name, _)| matches!(&*module[name].text, "options" | "config" | "meta"))
+        {
+            return ModuleKind::ConfigModule { lambda_expr };
+        }
+    }
+    ModuleKind::Config { lambda_expr }
+}
+
+/// Whether the body of the module has `options` or `config`, which packages never return.
+fn has_config_fields(module: &Module) -> bool {
+    let Expr::Lambda(_, _, body_expr) = module[peel_expr(module, module.entry_expr)] else {
+        return false;
+    };
+    let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) =
+        &module[peel_expr(module, body_expr)]
+    else {
+        return false;
+    };
+    bindings
+        .statics
+        .iter()
+        .any(|&(name, _)| matches!(&*module[name].text, "options" | "config"))
+}
+
+/// Guess the option set of a module by its parameters, imports and top-level configurations.
+/// Options only existing in one module system are checked, like `home.packages` and `launchd`.
+fn guess_option_set(module: &Module, lambda_expr: ExprId) -> Option<OptionSet> {
+    let Expr::Lambda(_, pat, body_expr) = &module[lambda_expr] else {
+        return None;
+    };
+    // `osConfig` is passed to home-manager modules used inside NixOS or nix-darwin.
+    let has_os_config = pat
+        .iter()
+        .flat_map(|pat| pat.fields.iter())
+        .any(|&(name, _)| name.map_or(false, |name| module[name].text == "osConfig"));
+    if has_os_config {
+        return Some(OptionSet::HomeManager);
+    }
+
+    let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) =
+        &module[peel_expr(module, *body_expr)]
+    else {
+        return None;
+    };
+    let mut guessed = None;
+    let mut check_config = |bindings: &Bindings| {
+        for &(name, _) in bindings.statics.iter() {
+            match &*module[name].text {
+                "home" => guessed = guessed.or(Some(OptionSet::HomeManager)),
+                "launchd" | "homebrew" => guessed = guessed.or(Some(OptionSet::NixDarwin)),
+                _ => {}
+            }
+        }
+    };
+    check_config(bindings);
+    if let Some(BindingValue::Expr(config_expr)) = bindings.get("config", module) {
+        if let Expr::Attrset(config) | Expr::RecAttrset(config) = &module[config_expr] {
+            check_config(config);
+        }
+    }
+    if guessed.is_some() {
+        return guessed;
+    }
+
+    // Modules exported by flakes, like `inputs.foo.homeManagerModules.default`.
+    let Some(BindingValue::Expr(imports_expr)) = bindings.get("imports", module) else {
+        return None;
+    };
+    let Expr::List(imports) = &module[imports_expr] else {
+        return None;
+    };
+    imports.iter().find_map(|&import| {
+        let Expr::Select(_, path, _) = &module[import] else {
+            return None;
+        };
+        path.iter().find_map(|&attr| match &module[attr] {
+            Expr::Literal(Literal::String(s)) => match &**s {
+                "homeManagerModules" | "hmModules" => Some(OptionSet::HomeManager),
+                "darwinModules" => Some(OptionSet::NixDarwin),
+                _ => None,
+            },
+            _ => None,
+        })
+    })
+}
+
 /// Peel all environment-like wrapper expression like `With`, `Assert` and `LetIn`.
-fn peel_expr(module: &Module, expr: ExprId) -> ExprId {
+pub(crate) fn peel_expr(module: &Module, expr: ExprId) -> ExprId {
     std::iter::successors(Some(expr), |&e| match &module[e] {
         Expr::With(_, inner) | Expr::Assert(_, inner) | Expr::LetIn(_, inner) => Some(*inner),
         _ => None,
@@ -156,10 +371,11 @@ fn peel_expr(module: &Module, expr: ExprId) -> ExprId {
 mod tests {
     use expect_test::{expect, Expect};
     use itertools::Itertools;
+    use salsa::debug::DebugQueryTable;
 
     use super::*;
     use crate::tests::TestDB;
-    use crate::SourceDatabase;
+    use crate::{SourceDatabase, VfsPath};
 
     #[track_caller]
     fn check(src: &str, expect: Expect) {
@@ -175,12 +391,14 @@ mod tests {
             ModuleKind::FlakeNix {
                 explicit_inputs,
                 param_inputs,
+                follows,
                 outputs_expr,
             } => {
                 let explicit_inputs = explicit_inputs.keys().sorted().join(",");
                 let param_inputs = param_inputs.keys().sorted().join(",");
+                let follows = follows.iter().map(|(_, input)| input).join(",");
                 let outputs = outputs_expr.map(expr_header).unwrap_or_default();
-                format!("FlakeNix: explicit_inputs={explicit_inputs} param_inputs={param_inputs} outputs={outputs}")
+                format!("FlakeNix: explicit_inputs={explicit_inputs} param_inputs={param_inputs} follows={follows} outputs={outputs}")
             }
             ModuleKind::Package { lambda_expr } => {
                 format!("Package: {}", expr_header(*lambda_expr))
@@ -191,6 +409,20 @@ mod tests {
             ModuleKind::Config { lambda_expr } => {
                 format!("Config: {}", expr_header(*lambda_expr))
             }
+            ModuleKind::Overlay {
+                lambda_expr,
+                final_param,
+                prev_param,
+                ..
+            } => {
+                let module = db.module(file);
+                format!(
+                    "Overlay: {} final={} prev={}",
+                    expr_header(*lambda_expr),
+                    module[*final_param].text,
+                    module[*prev_param].text,
+                )
+            }
         };
         expect.assert_eq(&got);
     }
@@ -205,7 +437,27 @@ mod tests {
     outputs = { self, nixpkgs, ... }: { };
 }
             "#,
-            expect!["FlakeNix: explicit_inputs=nil param_inputs=nixpkgs outputs={ self, nixpkgs, ... }: { }"],
+            expect!["FlakeNix: explicit_inputs=nil param_inputs=nixpkgs follows= outputs={ self, nixpkgs, ... }: { }"],
+        );
+    }
+
+    #[test]
+    fn flake_nix_follows() {
+        check(
+            r#"
+#- /flake.nix input:nixpkgs=/nix/store/eeee
+{
+    inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+    inputs.home-manager = {
+        url = "github:nix-community/home-manager";
+        inputs.nixpkgs.follows = "nixpkgs";
+    };
+    inputs.foo.inputs.bar.inputs.baz.follows = "home-manager/nixpkgs";
+    inputs.qux.follows = "";
+    outputs = { self, nixpkgs, ... }: { };
+}
+            "#,
+            expect!["FlakeNix: explicit_inputs=foo,home-manager,nixpkgs,qux param_inputs=nixpkgs follows=nixpkgs,home-manager, outputs={ self, nixpkgs, ... }: { }"],
         );
     }
 
@@ -223,7 +475,7 @@ rec {
     outputs = { self, nixpkgs, ... }: rec { };
 }
             "#,
-            expect!["FlakeNix: explicit_inputs=nil param_inputs=nixpkgs outputs={ self, nixpkgs, ... }: rec { }"],
+            expect!["FlakeNix: explicit_inputs=nil param_inputs=nixpkgs follows= outputs={ self, nixpkgs, ... }: rec { }"],
         );
     }
 
@@ -269,4 +521,126 @@ with lib;
             expect!["Config: { lib, pkgs, ... }:"],
         );
     }
+
+    #[test]
+    fn overlay() {
+        check(
+            "
+final: prev:
+let inherit (final) callPackage; in
+{
+    foo = callPackage ./foo.nix { };
+}
+            ",
+            expect!["Overlay: final: prev: final=final prev=prev"],
+        );
+        check(
+            "self: super: rec { }",
+            expect!["Overlay: self: super: rec { } final=self prev=super"],
+        );
+        check("a: b: { }", expect!["Unknown"]);
+        check("final: prev: prev.foo", expect!["Unknown"]);
+    }
+
+    #[test]
+    fn module_path() {
+        let (mut db, f) = TestDB::from_fixture(
+            "
+#- /modules/foo.nix
+{ config, lib }: lib.mkIf config.foo.enable { }
+#- /pkgs/foo.nix
+{ config, lib }: lib.mkIf config.foo.enable { }
+            ",
+        )
+        .unwrap();
+        let [module, pkg] = [0, 1].map(|i| f.files()[i]);
+        assert!(matches!(
+            *db.module_kind(module),
+            ModuleKind::Package { .. }
+        ));
+
+        db.set_option_set_module_paths(OptionSet::Nixos, Arc::new(vec![VfsPath::new("/modules")]));
+        assert!(matches!(*db.module_kind(module), ModuleKind::Config { .. }));
+        assert!(matches!(*db.module_kind(pkg), ModuleKind::Package { .. }));
+    }
+
+    #[test]
+    fn called_package() {
+        let (db, f) = TestDB::from_fixture(
+            "
+#- /default.nix
+{ callPackage }: callPackage ./foo.nix { }
+#- /foo.nix
+{ lib, ... }: { meta.description = lib.foo; }
+#- /bar.nix
+{ lib, ... }: { meta.description = lib.foo; }
+            ",
+        )
+        .unwrap();
+        assert!(matches!(
+            *db.module_kind(f["/foo.nix"]),
+            ModuleKind::Package { .. }
+        ));
+        assert!(matches!(
+            *db.module_kind(f["/bar.nix"]),
+            ModuleKind::ConfigModule { .. }
+        ));
+    }
+
+    #[test]
+    fn called_package_not_checked() {
+        let (db, f) = TestDB::from_fixture(
+            "
+#- /default.nix
+{ callPackage }: callPackage ./foo.nix { }
+#- /foo.nix
+{ lib, ... }: { options.foo = lib.mkOption { }; }
+            ",
+        )
+        .unwrap();
+        assert!(matches!(
+            *db.module_kind(f["/foo.nix"]),
+            ModuleKind::ConfigModule { .. }
+        ));
+        // Recognized modules do not depend on their callers.
+        let checked = crate::def::IsCalledPackageQuery
+            .in_db(&db)
+            .entries::<Vec<_>>();
+        assert!(checked.is_empty());
+    }
+
+    #[test]
+    fn option_set() {
+        let (mut db, f) = TestDB::from_fixture(
+            "
+#- /configuration.nix
+{ ... }: { services.foo.enable = true; }
+#- /home.nix
+{ ... }: { home.packages = [ ]; }
+#- /os-config.nix
+{ osConfig, ... }: { }
+#- /darwin.nix
+{ ... }: { config.launchd.agents = { }; options = { }; }
+#- /imports.nix
+{ inputs, ... }: { imports = [ inputs.foo.darwinModules.default ]; }
+#- /home/foo.nix
+{ ... }: { services.foo.enable = true; }
+#- /package.nix
+{ stdenv }: stdenv.mkDerivation { home = 1; }
+            ",
+        )
+        .unwrap();
+        let set_of = |db: &TestDB, path: &str| db.module_option_set(f[path]);
+        assert_eq!(set_of(&db, "/configuration.nix"), OptionSet::Nixos);
+        assert_eq!(set_of(&db, "/home.nix"), OptionSet::HomeManager);
+        assert_eq!(set_of(&db, "/os-config.nix"), OptionSet::HomeManager);
+        assert_eq!(set_of(&db, "/darwin.nix"), OptionSet::NixDarwin);
+        assert_eq!(set_of(&db, "/imports.nix"), OptionSet::NixDarwin);
+        assert_eq!(set_of(&db, "/home/foo.nix"), OptionSet::Nixos);
+        assert_eq!(set_of(&db, "/package.nix"), OptionSet::Nixos);
+
+        let paths = Arc::new(vec![VfsPath::new("/home")]);
+        db.set_option_set_module_paths(OptionSet::HomeManager, paths);
+        assert_eq!(set_of(&db, "/home/foo.nix"), OptionSet::HomeManager);
+    }
 }
@@ -17,10 +17,15 @@
 //! - Unused `with` expressions.
 //! - Unnecessary `rec` attrsets.
 //! - Unused parameters of a package.
-use super::{BindingValue, DefDatabase, Expr, ExprId, NameId, ResolveResult};
+//! - Unused lambda arguments, and pattern fields of other lambdas.
+//! - Flake inputs neither passed to `outputs` nor followed by other inputs.
+use super::{
+    BindingValue, DefDatabase, Expr, ExprId, Literal, Module, NameId, NameResolution, ResolveResult,
+};
 use crate::{Diagnostic, DiagnosticKind, FileId, ModuleKind};
 use la_arena::ArenaMap;
-use std::collections::BTreeMap;
+use smol_str::SmolStr;
+use std::collections::{BTreeMap, HashSet};
 use std::sync::Arc;
 use syntax::ast::{self, AstNode};
 use syntax::TextRange;
@@ -28,11 +33,32 @@ use syntax::TextRange;
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct LivenessCheckResult {
     names: Box<[NameId]>,
+    params: Box<[NameId]>,
     withs: Box<[ExprId]>,
     rec_attrsets: Box<[ExprId]>,
 }
 
 impl LivenessCheckResult {
+    /// `rec` attrsets whose bindings never reference their siblings.
+    pub fn rec_attrsets(&self) -> &[ExprId] {
+        &self.rec_attrsets
+    }
+
+    /// Whether the name is reported as an unused binding or parameter.
+    pub fn is_unused(&self, name: NameId) -> bool {
+        self.names.contains(&name) || self.params.contains(&name)
+    }
+
+    /// Whether the name is reported as an unused binding, including parameters of packages.
+    pub fn is_unused_binding(&self, name: NameId) -> bool {
+        self.names.contains(&name)
+    }
+
+    /// All names reported as unused bindings or parameters.
+    pub fn unused_names(&self) -> impl Iterator<Item = NameId> + '_ {
+        self.names.iter().chain(self.params.iter()).copied()
+    }
+
     pub fn to_diagnostics<'a>(
         &'a self,
         db: &dyn DefDatabase,
@@ -47,23 +73,30 @@ impl LivenessCheckResult {
                 .flat_map(|&def| source_map.nodes_for_name(def))
                 .map(|ptr| Diagnostic::new(ptr.text_range(), DiagnosticKind::UnusedBinding)),
         );
-        diags.extend(self.withs.iter().map(|&expr| {
-            let ptr = source_map.node_for_expr(expr).unwrap();
-            let node = ast::With::cast(ptr.to_node(&root)).unwrap();
+        diags.extend(
+            self.params
+                .iter()
+                .flat_map(|&def| source_map.nodes_for_name(def))
+                .map(|ptr| Diagnostic::new(ptr.text_range(), DiagnosticKind::UnusedParam)),
+        );
+        diags.extend(self.withs.iter().filter_map(|&expr| {
+            let ptr = source_map.node_for_expr(expr)?;
+            let node = ast::With::cast(ptr.to_node(&root))?;
             let header_range = match (node.with_token(), node.semicolon_token()) {
                 (Some(start), Some(end)) => start.text_range().cover(end.text_range()),
                 _ => TextRange::empty(ptr.text_range().start()),
             };
-            Diagnostic::new(header_range, DiagnosticKind::UnusedWith)
+            Some(Diagnostic::new(header_range, DiagnosticKind::UnusedWith))
         }));
-        diags.extend(self.rec_attrsets.iter().map(|&expr| {
-            let ptr = source_map.node_for_expr(expr).unwrap();
-            let node = ast::AttrSet::cast(ptr.to_node(&root)).unwrap();
+        // Merged attrsets may have no source of their own.
+        diags.extend(self.rec_attrsets.iter().filter_map(|&expr| {
+            let ptr = source_map.node_for_expr(expr)?;
+            let node = ast::AttrSet::cast(ptr.to_node(&root))?;
             let range = node.rec_token().map_or_else(
                 || TextRange::empty(ptr.text_range().start()),
                 |tok| tok.text_range(),
             );
-            Diagnostic::new(range, DiagnosticKind::UnusedRec)
+            Some(Diagnostic::new(range, DiagnosticKind::UnusedRec))
         }));
         diags.into_iter()
     }
@@ -73,10 +106,12 @@ pub(crate) fn liveness_check_query(
     db: &dyn DefDatabase,
     file_id: FileId,
 ) -> Arc<LivenessCheckResult> {
+    let _span = tracing::debug_span!("liveness", file = file_id.0).entered();
     let module = db.module(file_id);
     let name_res = db.name_resolution(file_id);
 
-    let (must_use_params_expr, is_flake_outputs) = match &*db.module_kind(file_id) {
+    let module_kind = db.module_kind(file_id);
+    let (must_use_params_expr, is_flake_outputs) = match &*module_kind {
         ModuleKind::Package { lambda_expr }
         | ModuleKind::ConfigModule { lambda_expr }
         | ModuleKind::Config { lambda_expr } => (Some(*lambda_expr), false),
@@ -102,6 +137,8 @@ pub(crate) fn liveness_check_query(
     let mut discovered_let_rhs: BTreeMap<NameId, ExprId> = BTreeMap::new();
 
     while !stack.is_empty() {
+        db.unwind_if_cancelled();
+
         // N.B. This should be dropped in every loop.
         // Or it will make this whole check cost quadratic time!
         discovered_let_rhs.clear();
@@ -154,11 +191,20 @@ pub(crate) fn liveness_check_query(
     // It's intended to not reporting them if they are only referenced by some unused let-bindings.
     // Unused let-bindings may be caused by unfinished codes or typos,
     // situation may be changed when user tries to fixing them.
+    let mut unused_params = Vec::new();
     let mut unused_withs = Vec::new();
     let mut unused_recs = Vec::new();
     for (expr, kind) in module.exprs() {
         match kind {
             Expr::Lambda(param, pat, _) => {
+                // `foo: ...`
+                //  ^ Unused, but only renamable.
+                if let (Some(param), None) = (*param, pat) {
+                    if visited_defs.get(param).is_none() && !is_intended_unused(&module[param].text)
+                    {
+                        unused_params.push(param);
+                    }
+                }
                 // `{ ... }@bar: ...`
                 //          ^ Unused and removable.
                 if let Some(param) = *param {
@@ -190,6 +236,19 @@ pub(crate) fn liveness_check_query(
                             Some(name)
                         }));
                     }
+                    // `{ foo [, ...] }: ...`
+                    //    ^ Unused. Only removable with the ellipsis, which accepts it anyway.
+                    // Fields are kept as documentation if the whole argument is used via `@`.
+                    else if must_use_params_expr != Some(expr)
+                        && param.map_or(true, |param| visited_defs.get(param).is_none())
+                    {
+                        unused_params.extend(pat.fields.iter().filter_map(|&(name, _)| {
+                            let name = name?;
+                            (visited_defs.get(name).is_none()
+                                && !is_intended_unused(&module[name].text))
+                            .then_some(name)
+                        }));
+                    }
                 }
             }
             &Expr::With(..) if visited_withs.get(expr).is_none() => {
@@ -207,13 +266,82 @@ pub(crate) fn liveness_check_query(
         }
     }
 
+    // `inputs.foo.url = "...";`
+    //         ^ Unused, if `outputs` can never access it.
+    if let ModuleKind::FlakeNix {
+        explicit_inputs,
+        param_inputs,
+        follows,
+        outputs_expr: Some(outputs_expr),
+    } = &*module_kind
+    {
+        // Inputs are all accessible via `inputs@{ ... }: ...` or `inputs: ...`.
+        if let Expr::Lambda(None, Some(pat), _) = &module[*outputs_expr] {
+            let self_name = pat
+                .fields
+                .iter()
+                .find_map(|&(name, _)| name.filter(|&name| module[name].text == "self"));
+            let self_uses = match self_name {
+                Some(self_name) => self_input_uses(&module, &name_res, self_name),
+                None => Some(HashSet::new()),
+            };
+            // Otherwise, `self.inputs` is used as a whole, so all inputs are accessible.
+            if let Some(self_uses) = self_uses {
+                let mut unused_inputs = explicit_inputs
+                    .iter()
+                    .filter(|(name, _)| {
+                        !param_inputs.contains_key(*name)
+                            && !self_uses.contains(*name)
+                            && !follows.iter().any(|(_, input)| input == *name)
+                    })
+                    .map(|(_, &name)| name)
+                    .collect::<Vec<_>>();
+                unused_inputs.sort();
+                unused_defs.extend(unused_inputs);
+            }
+        }
+    }
+
     Arc::new(LivenessCheckResult {
         names: unused_defs.into(),
+        params: unused_params.into(),
         withs: unused_withs.into(),
         rec_attrsets: unused_recs.into(),
     })
 }
 
+/// Input names selected by `self.inputs.<name>` via the `self` parameter `self_name`.
+/// Returns `None` if `self.inputs` is used as a whole, eg. being passed elsewhere.
+fn self_input_uses(
+    module: &Module,
+    name_res: &NameResolution,
+    self_name: NameId,
+) -> Option<HashSet<SmolStr>> {
+    let mut uses = HashSet::new();
+    for (_, kind) in module.exprs() {
+        let Expr::Select(set, path, _) = kind else {
+            continue;
+        };
+        if name_res.get(*set) != Some(&ResolveResult::Definition(self_name)) {
+            continue;
+        }
+        let field = |i: usize| match path.get(i).map(|&attr| &module[attr]) {
+            Some(Expr::Literal(Literal::String(s))) => Some(s),
+            _ => None,
+        };
+        if field(0).map_or(true, |s| s != "inputs") {
+            continue;
+        }
+        uses.insert(field(1)?.clone());
+    }
+    Some(uses)
+}
+
+/// Names starting with `_` are conventionally unused.
+fn is_intended_unused(name: &str) -> bool {
+    name.starts_with('_')
+}
+
 #[cfg(test)]
 mod tests {
     use crate::tests::TestDB;
@@ -251,13 +379,24 @@ mod tests {
 
     #[test]
     fn lambda() {
-        check("a: { b }: $0c@{}: 0");
+        check("$0a: { $1b }: $2c@{}: 0");
+        check("_: _a: 0");
+    }
+
+    #[test]
+    fn unused_pat_field() {
+        check("{ $0a, b, ... }: b");
+        check("{ $0a, b }: b");
+        check("{ _a, ... }: 0");
+        // Fields are documentation of the used whole argument.
+        check("args@{ a, ... }: args");
+        check("$0args@{ $1a, ... }: 0");
     }
 
     #[test]
     fn with() {
         check("a: $0with 1; a");
-        check("a: with 1; with 2; b");
+        check("_: with 1; with 2; b");
     }
 
     #[test]
@@ -353,4 +492,53 @@ mod tests {
             ",
         );
     }
+
+    #[test]
+    fn flake_unused_input() {
+        check(
+            r#"
+#- /flake.nix
+{
+    inputs.$0foo.url = "github:foo/foo";
+    inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+    inputs.bar = { url = "github:bar/bar"; inputs.nixpkgs.follows = "nixpkgs"; };
+    inputs.utils.url = "github:numtide/flake-utils";
+    inputs.baz.inputs.utils.follows = "utils";
+    outputs = { self, bar, baz }: { inherit bar baz; };
+}
+            "#,
+        );
+
+        // Accessible via the universal parameter.
+        check(
+            r#"
+#- /flake.nix
+{
+    inputs.foo.url = "github:foo/foo";
+    outputs = { self, ... }@inputs: import ./foo.nix inputs;
+}
+            "#,
+        );
+
+        // Accessible via `self.inputs`.
+        check(
+            r#"
+#- /flake.nix
+{
+    inputs.foo.url = "github:foo/foo";
+    inputs.$0bar.url = "github:bar/bar";
+    outputs = { self }: { inherit (self.inputs.foo) lib; };
+}
+            "#,
+        );
+        check(
+            r#"
+#- /flake.nix
+{
+    inputs.foo.url = "github:foo/foo";
+    outputs = { self }: import ./foo.nix self.inputs;
+}
+            "#,
+        );
+    }
 }
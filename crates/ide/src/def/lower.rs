@@ -1,5 +1,5 @@
 use super::{
-    AstPtr, Attrpath, BindingValue, Bindings, DefDatabase, Expr, ExprId, Literal, Module,
+    AstPtr, Attrpath, BinaryOp, BindingValue, Bindings, DefDatabase, Expr, ExprId, Literal, Module,
     ModuleSourceMap, Name, NameId, NameKind, Pat, PathAnchor, PathData,
 };
 use crate::{Diagnostic, DiagnosticKind, FileId, FileRange};
@@ -71,6 +71,8 @@ impl LowerCtx<'_> {
     }
 
     fn lower_expr(&mut self, expr: ast::Expr) -> ExprId {
+        // Large files are lowered in a single query. Stop early if it is outdated by an edit.
+        self.db.unwind_if_cancelled();
         let ptr = AstPtr::new(expr.syntax());
         match expr {
             ast::Expr::Literal(e) => {
@@ -110,7 +112,12 @@ impl LowerCtx<'_> {
                 let lhs = self.lower_expr_opt(e.lhs());
                 let op = e.op_kind();
                 let rhs = self.lower_expr_opt(e.rhs());
-                self.alloc_expr(Expr::Binary(op, lhs, rhs), ptr)
+                // Pipes are just applications, so names and types flow through them.
+                match op {
+                    Some(BinaryOp::PipeInto) => self.alloc_expr(Expr::Apply(rhs, lhs), ptr),
+                    Some(BinaryOp::PipeFrom) => self.alloc_expr(Expr::Apply(lhs, rhs), ptr),
+                    _ => self.alloc_expr(Expr::Binary(op, lhs, rhs), ptr),
+                }
             }
             ast::Expr::UnaryOp(e) => {
                 let op = e.op_kind();
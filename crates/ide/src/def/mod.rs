@@ -1,3 +1,6 @@
+mod call_package;
+mod const_eval;
+mod deprecated_packages;
 mod kind;
 mod liveness;
 mod lower;
@@ -8,9 +11,8 @@ mod path;
 mod tests;
 
 use crate::base::SourceDatabase;
-use crate::{Diagnostic, FileId, SourceRootId, VfsPath};
+use crate::{Diagnostic, FileId, OptionSet, SourceRootId, VfsPath};
 use la_arena::{Arena, ArenaMap, Idx};
-use nix_interop::DEFAULT_IMPORT_FILE;
 use ordered_float::OrderedFloat;
 use smallvec::SmallVec;
 use smol_str::SmolStr;
@@ -19,6 +21,11 @@ use std::ops;
 use std::sync::Arc;
 use syntax::Parse;
 
+pub(crate) use self::call_package::is_call_package;
+pub use self::call_package::CallPackageSite;
+pub(crate) use self::const_eval::{const_attr_name, const_eval_name, const_eval_string};
+pub use self::deprecated_packages::{DeprecatedPackage, DeprecatedPackages};
+pub(crate) use self::kind::peel_expr;
 pub use self::kind::ModuleKind;
 pub use self::liveness::LivenessCheckResult;
 pub use self::nameres::{
@@ -43,6 +50,11 @@ pub trait DefDatabase: SourceDatabase {
     #[salsa::invoke(ModuleKind::module_kind_query)]
     fn module_kind(&self, file_id: FileId) -> Arc<ModuleKind>;
 
+    /// The option set of a module or configuration. Only meaningful for
+    /// `ModuleKind::ConfigModule` and `ModuleKind::Config`.
+    #[salsa::invoke(ModuleKind::module_option_set_query)]
+    fn module_option_set(&self, file_id: FileId) -> OptionSet;
+
     #[salsa::invoke(Module::module_references_query)]
     fn module_references(&self, file_id: FileId) -> Arc<HashSet<FileId>>;
 
@@ -57,9 +69,19 @@ pub trait DefDatabase: SourceDatabase {
     // And also this method is not call so often.
     fn module_referrers(&self, file_id: FileId) -> ModuleReferrers;
 
+    #[salsa::invoke(call_package::call_package_sites_query)]
+    fn call_package_sites(&self, file_id: FileId) -> Arc<Vec<CallPackageSite>>;
+
+    /// Whether `file_id` is called by `callPackage` from any file.
+    #[salsa::invoke(call_package::is_called_package_query)]
+    fn is_called_package(&self, file_id: FileId) -> bool;
+
     #[salsa::invoke(Path::resolve_path_query)]
     fn resolve_path(&self, path: Path) -> Option<VfsPath>;
 
+    #[salsa::invoke(Path::resolve_file_query)]
+    fn resolve_path_file(&self, path: Path) -> Option<FileId>;
+
     #[salsa::invoke(ModuleScopes::module_scopes_query)]
     fn scopes(&self, file_id: FileId) -> Arc<ModuleScopes>;
 
@@ -71,9 +93,13 @@ pub trait DefDatabase: SourceDatabase {
 
     #[salsa::invoke(liveness::liveness_check_query)]
     fn liveness_check(&self, file_id: FileId) -> Arc<LivenessCheckResult>;
+
+    #[salsa::invoke(deprecated_packages::deprecated_packages_query)]
+    fn deprecated_packages(&self, file_id: FileId) -> Arc<DeprecatedPackages>;
 }
 
 fn parse(db: &dyn DefDatabase, file_id: FileId) -> Parse {
+    let _span = tracing::debug_span!("parse", file = file_id.0).entered();
     let content = db.file_content(file_id);
     syntax::parse_file(&content)
 }
@@ -82,6 +108,7 @@ fn module_with_source_map(
     db: &dyn DefDatabase,
     file_id: FileId,
 ) -> (Arc<Module>, Arc<ModuleSourceMap>) {
+    let _span = tracing::debug_span!("lower", file = file_id.0).entered();
     let parse = db.parse(file_id);
     let (mut module, mut source_map) = lower::lower(db, file_id, parse);
     module.shrink_to_fit();
@@ -126,9 +153,15 @@ fn source_root_referrer_graph(
 }
 
 fn module_referrers(db: &dyn DefDatabase, file_id: FileId) -> ModuleReferrers {
-    let sid = db.file_source_root(file_id);
-    let graph = db.source_root_referrer_graph(sid);
-    graph.get(&file_id).cloned().unwrap_or_default()
+    // Files in other source roots may import this one.
+    let mut referrers = ModuleReferrers::new();
+    for sid in (0..db.source_root_count()).map(SourceRootId) {
+        if let Some(files) = db.source_root_referrer_graph(sid).get(&file_id) {
+            referrers.extend_from_slice(files);
+        }
+    }
+    referrers.sort();
+    referrers
 }
 
 fn source_root_closure(db: &dyn DefDatabase, id: SourceRootId) -> Arc<HashSet<FileId>> {
@@ -195,7 +228,6 @@ impl Module {
         db: &dyn DefDatabase,
         file_id: FileId,
     ) -> Arc<HashSet<FileId>> {
-        let source_root = db.source_root(db.file_source_root(file_id));
         let mut refs = db
             .module(file_id)
             .exprs()
@@ -203,11 +235,7 @@ impl Module {
                 let &Expr::Literal(Literal::Path(path)) = kind else {
                     return None;
                 };
-                let mut vpath = path.resolve(db)?;
-                source_root.file_for_path(&vpath).or_else(|| {
-                    vpath.push(DEFAULT_IMPORT_FILE)?;
-                    source_root.file_for_path(&vpath)
-                })
+                path.resolve_file(db)
             })
             .collect::<HashSet<_>>();
         refs.shrink_to_fit();
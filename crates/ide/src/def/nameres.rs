@@ -25,6 +25,7 @@ impl ops::Index<ScopeId> for ModuleScopes {
 
 impl ModuleScopes {
     pub(crate) fn module_scopes_query(db: &dyn DefDatabase, file_id: FileId) -> Arc<Self> {
+        let _span = tracing::debug_span!("scopes", file = file_id.0).entered();
         let module = db.module(file_id);
         let mut this = Self {
             scopes: Arena::new(),
@@ -53,7 +54,7 @@ impl ModuleScopes {
     }
 
     /// Resolve a name in the scope of an Expr.
-    fn resolve_name(&self, expr_id: ExprId, name: &SmolStr) -> Option<ResolveResult> {
+    pub fn resolve_name(&self, expr_id: ExprId, name: &SmolStr) -> Option<ResolveResult> {
         let scope = self.scope_for_expr(expr_id)?;
         // 1. Local defs.
         if let Some(name) = self
@@ -62,12 +63,20 @@ impl ModuleScopes {
         {
             return Some(ResolveResult::Definition(*name));
         }
-        // 2. Global builtin names.
+        // 2. Global builtin names, and deprecated aliases `__name` of the others.
         if let Some((name, b)) = ALL_BUILTINS.get_entry(name) {
             if b.is_global {
                 return Some(ResolveResult::Builtin(name));
             }
         }
+        if let Some((name, b)) = name
+            .strip_prefix("__")
+            .and_then(|name| ALL_BUILTINS.get_entry(name))
+        {
+            if !b.is_global {
+                return Some(ResolveResult::Builtin(name));
+            }
+        }
         // 3. "with" exprs.
         let withs = self
             .ancestors(scope)
@@ -79,6 +88,22 @@ impl ModuleScopes {
         None
     }
 
+    /// Pairs of definitions and the definitions of the same names in outer scopes,
+    /// which are shadowed by the former.
+    pub fn shadowings(&self) -> impl Iterator<Item = (NameId, NameId)> + '_ {
+        self.scopes.iter().flat_map(move |(_, data)| {
+            data.as_definitions()
+                .into_iter()
+                .flatten()
+                .filter_map(move |(text, &name)| {
+                    let shadowed = self
+                        .ancestors(data.parent?)
+                        .find_map(|outer| outer.as_definitions()?.get(text))?;
+                    Some((name, *shadowed))
+                })
+        })
+    }
+
     fn traverse_expr(&mut self, module: &Module, expr: ExprId, scope: ScopeId) {
         self.scope_by_expr.insert(expr, scope);
 
@@ -232,6 +257,7 @@ pub struct NameResolution {
 
 impl NameResolution {
     pub(crate) fn name_resolution_query(db: &dyn DefDatabase, file_id: FileId) -> Arc<Self> {
+        let _span = tracing::debug_span!("resolve", file = file_id.0).entered();
         let module = db.module(file_id);
         let scopes = db.scopes(file_id);
         let mut resolve_map = module
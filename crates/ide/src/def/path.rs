@@ -1,5 +1,6 @@
 use super::DefDatabase;
 use crate::{FileId, VfsPath};
+use nix_interop::DEFAULT_IMPORT_FILE;
 use smol_str::SmolStr;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -19,8 +20,9 @@ impl Path {
         let data = path.data(db);
         let file = match &data.anchor {
             &PathAnchor::Relative(file) => file,
+            PathAnchor::Search(name) => return Self::resolve_search(db, name, &data),
             // TODO
-            PathAnchor::Absolute | PathAnchor::Home | PathAnchor::Search(_) => return None,
+            PathAnchor::Absolute | PathAnchor::Home => return None,
         };
 
         let sid = db.file_source_root(file);
@@ -40,6 +42,53 @@ impl Path {
         Some(vpath)
     }
 
+    /// Resolve `<name/path>` through the first entry of the search path where it exists, like
+    /// Nix. Candidates exist if they are loaded files, or are recorded as existing in the search
+    /// path by the server. The disk is not accessed here, so changes on it are not tracked.
+    fn resolve_search(db: &dyn DefDatabase, name: &str, data: &PathData) -> Option<VfsPath> {
+        let path = if data.relative_path.is_empty() || data.supers != 0 {
+            name.to_owned()
+        } else {
+            format!("{name}/{}", data.relative_path)
+        };
+        let search_path = db.search_path();
+        let exists = |candidate: &std::path::Path| {
+            let mut vpath = VfsPath::from(candidate);
+            db.file_for_path(vpath.clone()).is_some()
+                || (vpath.push(DEFAULT_IMPORT_FILE).is_some() && db.file_for_path(vpath).is_some())
+        };
+        let mut vpath = VfsPath::from(search_path.resolve_existing(&path, exists)?);
+        // Leading `..` apply to the resolved entry.
+        if data.supers != 0 {
+            for _ in 0..data.supers {
+                vpath.pop();
+            }
+            if !data.relative_path.is_empty() {
+                vpath.push(&data.relative_path)?;
+            }
+        }
+        Some(vpath)
+    }
+
+    pub(crate) fn resolve_file_query(db: &dyn DefDatabase, path: Path) -> Option<FileId> {
+        let mut vpath = path.resolve(db)?;
+        // Files in the same source root are preferred, but imports may cross workspace folders.
+        let source_root = match path.data(db).anchor {
+            PathAnchor::Relative(file) => Some(db.source_root(db.file_source_root(file))),
+            _ => None,
+        };
+        let file_for_path = |vpath: &VfsPath| {
+            source_root
+                .as_ref()
+                .and_then(|root| root.file_for_path(vpath))
+                .or_else(|| db.file_for_path(vpath.clone()))
+        };
+        file_for_path(&vpath).or_else(|| {
+            vpath.push(DEFAULT_IMPORT_FILE)?;
+            file_for_path(&vpath)
+        })
+    }
+
     pub fn data(self, db: &dyn DefDatabase) -> PathData {
         db.lookup_intern_path(self)
     }
@@ -47,6 +96,12 @@ impl Path {
     pub fn resolve(self, db: &dyn DefDatabase) -> Option<VfsPath> {
         db.resolve_path(self)
     }
+
+    /// Resolve to a loaded file, preferring the same source root. Directories resolve to their
+    /// `default.nix`.
+    pub fn resolve_file(self, db: &dyn DefDatabase) -> Option<FileId> {
+        db.resolve_path_file(self)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -86,6 +141,19 @@ impl PathData {
             relative_path: relative_path.into_boxed_str(),
         }
     }
+
+    pub fn anchor(&self) -> &PathAnchor {
+        &self.anchor
+    }
+
+    /// The number of leading `..`.
+    pub fn supers(&self) -> u8 {
+        self.supers
+    }
+
+    pub fn relative_path(&self) -> &str {
+        &self.relative_path
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -194,6 +194,7 @@ fn source_root_flake() {
                 VfsPath::new("/nix/store/eeee"),
             )]),
             input_flake_outputs: HashMap::new(),
+            input_sources: HashMap::new(),
         },
     );
 }
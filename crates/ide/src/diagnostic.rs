@@ -1,7 +1,10 @@
-use crate::FileRange;
+use crate::{FileRange, LanguageFeature};
 use core::fmt;
 use syntax::{ErrorKind as SynErrorKind, TextRange};
 
+/// The document explaining all diagnostic kinds.
+const DIAGNOSTICS_DOC_URL: &str = "https://github.com/oxalica/nil/blob/main/docs/diagnostics.md";
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Diagnostic {
     pub range: TextRange,
@@ -13,6 +16,7 @@ pub struct Diagnostic {
 pub enum DiagnosticKind {
     // Syntax.
     SyntaxError(SynErrorKind),
+    DisabledLanguageFeature(LanguageFeature),
 
     // Lowering.
     InvalidDynamic,
@@ -24,6 +28,7 @@ pub enum DiagnosticKind {
     UriLiteral,
     MergePlainRecAttrset,
     MergeRecAttrset,
+    OverriddenKey,
 
     // Name resolution.
     UndefinedName,
@@ -32,6 +37,48 @@ pub enum DiagnosticKind {
     UnusedBinding,
     UnusedWith,
     UnusedRec,
+    UnusedParam,
+
+    // Shadowing.
+    ShadowedBinding,
+
+    // Evaluation.
+    InfiniteRecursion,
+    TypeMismatch,
+    UnreachableCode,
+
+    // Option types.
+    InvalidEnumValue,
+    ConflictingDefinition,
+
+    // Nixpkgs.
+    DeprecatedPackage,
+    MisspelledShellArg,
+    ShellNativeBuildInputs,
+    MissingPackageArg,
+
+    // Static analysis.
+    UnresolvedImport,
+    DynamicAttr,
+
+    // Builtins.
+    DeprecatedBuiltin,
+
+    // Filesystem.
+    MissingPath,
+
+    // Flakes.
+    UnknownFlakeAttr,
+    InvalidFlakeAttr,
+    UndefinedFollows,
+    NonSystemOutput,
+
+    // Style.
+    RedundantParen,
+    RedundantBoolean,
+    ManualInherit,
+    ManualInheritFrom,
+    EtaReduction,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -55,9 +102,67 @@ impl Diagnostic {
         self
     }
 
+    /// The stable identifier of the kind, prefixed by `E` for errors and `W` for warnings.
+    /// Identifiers are never reused after kinds get removed.
+    pub fn id(&self) -> &'static str {
+        match self.kind {
+            DiagnosticKind::SyntaxError(_) => "E001",
+            DiagnosticKind::InvalidDynamic => "E002",
+            DiagnosticKind::DuplicatedKey => "E003",
+            DiagnosticKind::DuplicatedParam => "E004",
+            DiagnosticKind::UndefinedName => "E005",
+            DiagnosticKind::DisabledLanguageFeature(_) => "E006",
+            DiagnosticKind::EmptyInherit => "W001",
+            DiagnosticKind::EmptyLetIn => "W002",
+            DiagnosticKind::LetAttrset => "W003",
+            DiagnosticKind::UriLiteral => "W004",
+            DiagnosticKind::MergePlainRecAttrset => "W005",
+            DiagnosticKind::MergeRecAttrset => "W006",
+            DiagnosticKind::OverriddenKey => "W007",
+            DiagnosticKind::UnusedBinding => "W010",
+            DiagnosticKind::UnusedWith => "W011",
+            DiagnosticKind::UnusedRec => "W012",
+            DiagnosticKind::UnusedParam => "W013",
+            DiagnosticKind::ShadowedBinding => "W014",
+            DiagnosticKind::InfiniteRecursion => "W015",
+            DiagnosticKind::TypeMismatch => "W016",
+            DiagnosticKind::UnreachableCode => "W017",
+            DiagnosticKind::InvalidEnumValue => "W020",
+            DiagnosticKind::ConflictingDefinition => "W021",
+            DiagnosticKind::DeprecatedPackage => "W030",
+            DiagnosticKind::MisspelledShellArg => "W031",
+            DiagnosticKind::ShellNativeBuildInputs => "W032",
+            DiagnosticKind::MissingPackageArg => "W033",
+            DiagnosticKind::UnresolvedImport => "W040",
+            DiagnosticKind::DynamicAttr => "W041",
+            DiagnosticKind::DeprecatedBuiltin => "W050",
+            DiagnosticKind::MissingPath => "W060",
+            DiagnosticKind::UnknownFlakeAttr => "W070",
+            DiagnosticKind::InvalidFlakeAttr => "W071",
+            DiagnosticKind::UndefinedFollows => "W072",
+            DiagnosticKind::NonSystemOutput => "W073",
+            DiagnosticKind::RedundantParen => "W080",
+            DiagnosticKind::RedundantBoolean => "W081",
+            DiagnosticKind::ManualInherit => "W082",
+            DiagnosticKind::ManualInheritFrom => "W083",
+            DiagnosticKind::EtaReduction => "W084",
+        }
+    }
+
+    /// The URL to the explanation of the kind.
+    pub fn doc_url(&self) -> String {
+        format!(
+            "{DIAGNOSTICS_DOC_URL}#{}-{}",
+            self.id().to_lowercase(),
+            self.code()
+        )
+    }
+
+    /// The stable name of the kind, used in configurations.
     pub fn code(&self) -> &'static str {
         match self.kind {
             DiagnosticKind::SyntaxError(_) => "syntax_error",
+            DiagnosticKind::DisabledLanguageFeature(_) => "disabled_language_feature",
             DiagnosticKind::InvalidDynamic => "invalid_dynamic",
             DiagnosticKind::DuplicatedKey => "duplicated_key",
             DiagnosticKind::DuplicatedParam => "duplicated_param",
@@ -67,16 +172,59 @@ impl Diagnostic {
             DiagnosticKind::UriLiteral => "uri_literal",
             DiagnosticKind::MergePlainRecAttrset => "merge_plain_rec_attrset",
             DiagnosticKind::MergeRecAttrset => "merge_rec_attrset",
+            DiagnosticKind::OverriddenKey => "overridden_key",
             DiagnosticKind::UndefinedName => "undefined_name",
             DiagnosticKind::UnusedBinding => "unused_binding",
             DiagnosticKind::UnusedWith => "unused_with",
             DiagnosticKind::UnusedRec => "unused_rec",
+            DiagnosticKind::UnusedParam => "unused_param",
+            DiagnosticKind::ShadowedBinding => "shadowed_binding",
+            DiagnosticKind::InfiniteRecursion => "infinite_recursion",
+            DiagnosticKind::TypeMismatch => "type_mismatch",
+            DiagnosticKind::UnreachableCode => "unreachable_code",
+            DiagnosticKind::InvalidEnumValue => "invalid_enum_value",
+            DiagnosticKind::ConflictingDefinition => "conflicting_definition",
+            DiagnosticKind::DeprecatedPackage => "deprecated_package",
+            DiagnosticKind::MisspelledShellArg => "misspelled_shell_arg",
+            DiagnosticKind::ShellNativeBuildInputs => "shell_native_build_inputs",
+            DiagnosticKind::MissingPackageArg => "missing_package_arg",
+            DiagnosticKind::UnresolvedImport => "unresolved_import",
+            DiagnosticKind::DynamicAttr => "dynamic_attr",
+            DiagnosticKind::DeprecatedBuiltin => "deprecated_builtin",
+            DiagnosticKind::MissingPath => "missing_path",
+            DiagnosticKind::UnknownFlakeAttr => "unknown_flake_attr",
+            DiagnosticKind::InvalidFlakeAttr => "invalid_flake_attr",
+            DiagnosticKind::UndefinedFollows => "undefined_follows",
+            DiagnosticKind::NonSystemOutput => "non_system_output",
+            DiagnosticKind::RedundantParen => "redundant_paren",
+            DiagnosticKind::RedundantBoolean => "redundant_boolean",
+            DiagnosticKind::ManualInherit => "manual_inherit",
+            DiagnosticKind::ManualInheritFrom => "manual_inherit_from",
+            DiagnosticKind::EtaReduction => "eta_reduction",
         }
     }
 
+    /// The name of the equivalent lint of [statix](https://github.com/oppiliappan/statix), if any.
+    /// It is accepted in place of the code in configurations and suppression comments.
+    pub fn statix_name(&self) -> Option<&'static str> {
+        Some(match self.kind {
+            DiagnosticKind::EmptyInherit => "empty_inherit",
+            DiagnosticKind::EmptyLetIn => "empty_let_in",
+            DiagnosticKind::LetAttrset => "legacy_let_syntax",
+            DiagnosticKind::UriLiteral => "unquoted_uri",
+            DiagnosticKind::RedundantParen => "useless_parens",
+            DiagnosticKind::RedundantBoolean => "bool_comparison",
+            DiagnosticKind::ManualInherit => "manual_inherit",
+            DiagnosticKind::ManualInheritFrom => "manual_inherit_from",
+            DiagnosticKind::EtaReduction => "eta_reduction",
+            _ => return None,
+        })
+    }
+
     pub fn severity(&self) -> Severity {
         match self.kind {
             DiagnosticKind::SyntaxError(_)
+            | DiagnosticKind::DisabledLanguageFeature(_)
             | DiagnosticKind::InvalidDynamic
             | DiagnosticKind::DuplicatedKey
             | DiagnosticKind::DuplicatedParam
@@ -87,15 +235,43 @@ impl Diagnostic {
             | DiagnosticKind::UriLiteral
             | DiagnosticKind::MergePlainRecAttrset
             | DiagnosticKind::MergeRecAttrset
+            | DiagnosticKind::OverriddenKey
             | DiagnosticKind::UnusedBinding
             | DiagnosticKind::UnusedWith
-            | DiagnosticKind::UnusedRec => Severity::Warning,
+            | DiagnosticKind::UnusedRec
+            | DiagnosticKind::UnusedParam
+            | DiagnosticKind::ShadowedBinding
+            | DiagnosticKind::InfiniteRecursion
+            | DiagnosticKind::TypeMismatch
+            | DiagnosticKind::UnreachableCode
+            | DiagnosticKind::InvalidEnumValue
+            | DiagnosticKind::ConflictingDefinition
+            | DiagnosticKind::DeprecatedPackage
+            | DiagnosticKind::MisspelledShellArg
+            | DiagnosticKind::ShellNativeBuildInputs
+            | DiagnosticKind::MissingPackageArg
+            | DiagnosticKind::UnresolvedImport
+            | DiagnosticKind::DynamicAttr
+            | DiagnosticKind::DeprecatedBuiltin
+            | DiagnosticKind::MissingPath
+            | DiagnosticKind::UnknownFlakeAttr
+            | DiagnosticKind::InvalidFlakeAttr
+            | DiagnosticKind::UndefinedFollows
+            | DiagnosticKind::NonSystemOutput
+            | DiagnosticKind::RedundantParen
+            | DiagnosticKind::RedundantBoolean
+            | DiagnosticKind::ManualInherit
+            | DiagnosticKind::ManualInheritFrom
+            | DiagnosticKind::EtaReduction => Severity::Warning,
         }
     }
 
     pub fn message(&self) -> String {
         match self.kind {
             DiagnosticKind::SyntaxError(kind) => return kind.to_string(),
+            DiagnosticKind::DisabledLanguageFeature(feat) => {
+                return format!("The experimental feature `{}` is not enabled", feat.name());
+            }
 
             DiagnosticKind::InvalidDynamic => "Invalid location of dynamic attribute",
             DiagnosticKind::DuplicatedKey => "Duplicated name definition",
@@ -114,12 +290,54 @@ impl Diagnostic {
             DiagnosticKind::MergeRecAttrset => {
                 "Merging rec-attrset with other attrsets or attrpath. Merged values can unexpectedly reference each other remotely as in a single `rec { ... }`"
             }
+            DiagnosticKind::OverriddenKey => {
+                "The key is entirely overridden by the right-hand side of `//`"
+            }
 
             DiagnosticKind::UndefinedName => "Undefined name",
 
             DiagnosticKind::UnusedBinding => "Unused binding",
             DiagnosticKind::UnusedWith => "Unused `with`",
             DiagnosticKind::UnusedRec => "Unused `rec`",
+            DiagnosticKind::UnusedParam => "Unused parameter",
+
+            DiagnosticKind::ShadowedBinding => "Shadowing an outer definition of the same name",
+
+            DiagnosticKind::InfiniteRecursion => {
+                "Infinite recursion, the value strictly depends on itself"
+            }
+            DiagnosticKind::TypeMismatch => "Type mismatch, which always fails when evaluated",
+            DiagnosticKind::UnreachableCode => "Unreachable code, which is never evaluated",
+
+            DiagnosticKind::InvalidEnumValue => "Value is not one of the allowed values of the option",
+            DiagnosticKind::ConflictingDefinition => {
+                "Conflicting definitions of the option with the same priority"
+            }
+
+            DiagnosticKind::DeprecatedPackage => "Deprecated nixpkgs attribute",
+            DiagnosticKind::MisspelledShellArg => "Possibly misspelled argument of `mkShell`",
+            DiagnosticKind::ShellNativeBuildInputs => {
+                "`nativeBuildInputs` of `mkShell`, prefer `packages`"
+            }
+            DiagnosticKind::MissingPackageArg => "Missing arguments of the called package",
+
+            DiagnosticKind::UnresolvedImport => "Imported file cannot be statically resolved",
+            DiagnosticKind::DynamicAttr => "Dynamic attribute blocks static analysis",
+
+            DiagnosticKind::DeprecatedBuiltin => "Deprecated builtin",
+
+            DiagnosticKind::MissingPath => "Path does not exist",
+
+            DiagnosticKind::UnknownFlakeAttr => "Unknown attribute of flake",
+            DiagnosticKind::InvalidFlakeAttr => "Invalid value of flake attribute",
+            DiagnosticKind::UndefinedFollows => "`follows` refers to an undeclared input",
+            DiagnosticKind::NonSystemOutput => "Flake output is not keyed by systems",
+
+            DiagnosticKind::RedundantParen => "Redundant parentheses",
+            DiagnosticKind::RedundantBoolean => "Redundant boolean expression",
+            DiagnosticKind::ManualInherit => "Assignment can be `inherit`",
+            DiagnosticKind::ManualInheritFrom => "Assignment can be `inherit (...)`",
+            DiagnosticKind::EtaReduction => "Lambda can be eta-reduced",
         }
         .into()
     }
@@ -131,13 +349,35 @@ impl Diagnostic {
                 | DiagnosticKind::UnusedBinding
                 | DiagnosticKind::UnusedWith
                 | DiagnosticKind::UnusedRec
+                | DiagnosticKind::UnusedParam
+                | DiagnosticKind::UnreachableCode
+                | DiagnosticKind::RedundantParen
+        )
+    }
+
+    /// Whether this kind is disabled by default and must be explicitly enabled by users.
+    pub fn is_opt_in(&self) -> bool {
+        matches!(
+            self.kind,
+            DiagnosticKind::ConflictingDefinition
+                | DiagnosticKind::ShellNativeBuildInputs
+                | DiagnosticKind::UnresolvedImport
+                | DiagnosticKind::DynamicAttr
+                | DiagnosticKind::ShadowedBinding
+                | DiagnosticKind::RedundantParen
+                | DiagnosticKind::ManualInherit
+                | DiagnosticKind::ManualInheritFrom
+                | DiagnosticKind::EtaReduction
         )
     }
 
     pub fn is_deprecated(&self) -> bool {
         matches!(
             self.kind,
-            DiagnosticKind::LetAttrset | DiagnosticKind::UriLiteral
+            DiagnosticKind::LetAttrset
+                | DiagnosticKind::UriLiteral
+                | DiagnosticKind::DeprecatedPackage
+                | DiagnosticKind::DeprecatedBuiltin
         )
     }
 
@@ -163,3 +403,67 @@ impl From<syntax::Error> for Diagnostic {
         Self::new(err.range, DiagnosticKind::SyntaxError(err.kind))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostic, DiagnosticKind};
+    use crate::LanguageFeature;
+    use syntax::{ErrorKind as SynErrorKind, TextRange};
+
+    #[test]
+    fn documented() {
+        let docs = include_str!("../../../docs/diagnostics.md");
+        let kinds = [
+            DiagnosticKind::SyntaxError(SynErrorKind::NestTooDeep),
+            DiagnosticKind::DisabledLanguageFeature(LanguageFeature::PipeOperators),
+            DiagnosticKind::InvalidDynamic,
+            DiagnosticKind::DuplicatedKey,
+            DiagnosticKind::DuplicatedParam,
+            DiagnosticKind::EmptyInherit,
+            DiagnosticKind::EmptyLetIn,
+            DiagnosticKind::LetAttrset,
+            DiagnosticKind::UriLiteral,
+            DiagnosticKind::MergePlainRecAttrset,
+            DiagnosticKind::MergeRecAttrset,
+            DiagnosticKind::OverriddenKey,
+            DiagnosticKind::UndefinedName,
+            DiagnosticKind::UnusedBinding,
+            DiagnosticKind::UnusedWith,
+            DiagnosticKind::UnusedRec,
+            DiagnosticKind::UnusedParam,
+            DiagnosticKind::ShadowedBinding,
+            DiagnosticKind::InfiniteRecursion,
+            DiagnosticKind::TypeMismatch,
+            DiagnosticKind::UnreachableCode,
+            DiagnosticKind::InvalidEnumValue,
+            DiagnosticKind::ConflictingDefinition,
+            DiagnosticKind::DeprecatedPackage,
+            DiagnosticKind::MisspelledShellArg,
+            DiagnosticKind::ShellNativeBuildInputs,
+            DiagnosticKind::MissingPackageArg,
+            DiagnosticKind::UnresolvedImport,
+            DiagnosticKind::DynamicAttr,
+            DiagnosticKind::DeprecatedBuiltin,
+            DiagnosticKind::MissingPath,
+            DiagnosticKind::UnknownFlakeAttr,
+            DiagnosticKind::InvalidFlakeAttr,
+            DiagnosticKind::UndefinedFollows,
+            DiagnosticKind::NonSystemOutput,
+            DiagnosticKind::RedundantParen,
+            DiagnosticKind::RedundantBoolean,
+            DiagnosticKind::ManualInherit,
+            DiagnosticKind::ManualInheritFrom,
+            DiagnosticKind::EtaReduction,
+        ];
+        let mut ids = Vec::new();
+        for kind in kinds {
+            let diag = Diagnostic::new(TextRange::default(), kind);
+            let heading = format!("\n### {} `{}`\n", diag.id(), diag.code());
+            assert!(docs.contains(&heading), "{kind:?} is not documented");
+            ids.push(diag.id());
+        }
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), kinds.len(), "duplicated ids");
+    }
+}
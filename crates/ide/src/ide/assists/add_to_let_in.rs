@@ -0,0 +1,199 @@
+//! Define an undefined name in the nearest enclosing `let-in`, or inherit it from `lib` by an
+//! existing `inherit (lib)` if it is a known `lib` function.
+//!
+//! ```nix
+//! let foo = 1; in foo + bar
+//! ```
+//! =>
+//! ```nix
+//! let foo = 1; bar = throw "TODO"; in foo + bar
+//! ```
+//!
+//! ```nix
+//! { lib }: let inherit (lib) mkIf; in mkIf true (mkDefault 1)
+//! ```
+//! =>
+//! ```nix
+//! { lib }: let inherit (lib) mkIf mkDefault; in mkIf true (mkDefault 1)
+//! ```
+use super::add_to_top_level_lambda_param::undefined_ref;
+use super::AssistsCtx;
+use crate::ty::known;
+use crate::{DiagnosticKind, TextEdit};
+use syntax::ast::{self, AstNode, HasBindings};
+use syntax::{match_ast, SyntaxNode, TextRange};
+
+pub(super) fn add_to_let_in(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let node = undefined_ref(ctx)?;
+    let name = node.token()?;
+    let name = name.text();
+
+    // Bindings of `let` are visible in both its bindings and body.
+    let let_in = node.syntax().ancestors().find_map(ast::LetIn::cast)?;
+    let binding = format!("{name} = throw \"TODO\";");
+    let edit = match let_in.bindings().last() {
+        Some(last) => {
+            let last = last.syntax();
+            let pos = last.text_range().end();
+            // Keep the layout of bindings on separated lines.
+            let indent = last
+                .prev_sibling_or_token()
+                .and_then(|elem| elem.into_token())
+                .filter(|tok| tok.kind().is_space())
+                .and_then(|tok| Some(tok.text().rsplit_once('\n')?.1.to_owned()));
+            TextEdit {
+                delete: TextRange::empty(pos),
+                insert: match indent {
+                    Some(indent) => format!("\n{indent}{binding}"),
+                    None => format!(" {binding}"),
+                }
+                .into(),
+            }
+        }
+        None => TextEdit {
+            delete: TextRange::empty(let_in.let_token()?.text_range().end()),
+            insert: format!(" {binding}").into(),
+        },
+    };
+
+    ctx.add_fix(
+        DiagnosticKind::UndefinedName,
+        node.syntax().text_range(),
+        "add_to_let_in",
+        format!("Define `{name}` in the enclosing `let`"),
+        vec![edit],
+    );
+    Some(())
+}
+
+pub(super) fn add_to_lib_inherit(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let node = undefined_ref(ctx)?;
+    let name = node.token()?;
+    let name = name.text();
+
+    let is_lib_function = known::LIB
+        .as_attrset()
+        .map_or(false, |set| set.get(name).is_some())
+        || ctx.db.lib_docs().get(None, name).is_some();
+    if !is_lib_function {
+        return None;
+    }
+
+    let inherit = lib_inherit_in_scope(node.syntax())?;
+    let edit = add_inherit_attr(&inherit, name)?;
+
+    ctx.add_fix(
+        DiagnosticKind::UndefinedName,
+        node.syntax().text_range(),
+        "add_to_lib_inherit",
+        format!("Inherit `{name}` from `lib`"),
+        vec![edit],
+    );
+    Some(())
+}
+
+/// The nearest `inherit (lib) ...;` whose names are visible at `node`.
+pub(super) fn lib_inherit_in_scope(node: &SyntaxNode) -> Option<ast::Inherit> {
+    // Only `inherit`s of `let-in` and `rec` attrsets bring names into scope.
+    node.ancestors()
+        .filter_map(|node| {
+            match_ast! {
+                match node {
+                    ast::LetIn(n) => Some(n.bindings()),
+                    ast::AttrSet(n) => n.rec_token().map(|_| n.bindings()),
+                    _ => None,
+                }
+            }
+        })
+        .flatten()
+        .find_map(|binding| match binding {
+            ast::Binding::Inherit(i) if is_from_lib(&i) => Some(i),
+            _ => None,
+        })
+}
+
+/// Append `name` to the names of `inherit`.
+pub(super) fn add_inherit_attr(inherit: &ast::Inherit, name: &str) -> Option<TextEdit> {
+    let pos = match inherit.attrs().last() {
+        Some(attr) => attr.syntax().text_range().end(),
+        None => inherit.from_expr()?.syntax().text_range().end(),
+    };
+    Some(TextEdit {
+        delete: TextRange::empty(pos),
+        insert: format!(" {name}").into(),
+    })
+}
+
+/// `inherit (lib) ...;`
+fn is_from_lib(inherit: &ast::Inherit) -> bool {
+    match inherit.from_expr().and_then(|paren| paren.expr()) {
+        Some(ast::Expr::Ref(r)) => r.token().map_or(false, |tok| tok.text() == "lib"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    #[test]
+    fn let_in() {
+        define_check_assist!(super::add_to_let_in);
+
+        check(
+            "let foo = 1; in foo + $0bar",
+            expect![[r#"let foo = 1; bar = throw "TODO"; in foo + bar"#]],
+        );
+        check(
+            "let in $0bar",
+            expect![[r#"let bar = throw "TODO"; in bar"#]],
+        );
+        check(
+            "
+{ }:
+let
+  foo = let in 1;
+  bar = $0baz;
+in
+foo
+",
+            expect![[r#"
+                { }:
+                let
+                  foo = let in 1;
+                  bar = baz;
+                  baz = throw "TODO";
+                in
+                foo
+            "#]],
+        );
+        // The nearest one.
+        check(
+            "let a = 1; in { b = let c = 2; in $0d; }",
+            expect![[r#"let a = 1; in { b = let c = 2; d = throw "TODO"; in d; }"#]],
+        );
+
+        check_no("let foo = 1; in $0foo");
+        check_no("{ foo = 1; }.$0bar");
+        check_no("$0bar");
+    }
+
+    #[test]
+    fn lib_inherit() {
+        define_check_assist!(super::add_to_lib_inherit);
+
+        check(
+            "{ lib }: let inherit (lib) mkIf; in mkIf true ($0mkDefault 1)",
+            expect!["{ lib }: let inherit (lib) mkIf mkDefault; in mkIf true (mkDefault 1)"],
+        );
+        check(
+            "{ lib, ... }: rec { inherit (lib) ; a = mkForce$0 1; }",
+            expect!["{ lib, ... }: rec { inherit (lib) mkForce ; a = mkForce 1; }"],
+        );
+
+        check_no("{ lib }: let inherit (lib) mkIf; in $0notALibFunction");
+        check_no("{ lib }: let inherit (pkgs) mkIf; in $0mkDefault");
+        // Not in scope.
+        check_no("{ lib }: { inherit (lib) mkIf; a = $0mkDefault 1; }");
+    }
+}
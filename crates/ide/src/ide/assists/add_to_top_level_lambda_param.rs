@@ -7,13 +7,13 @@
 //! ```nix
 //! { foo, bar }: foo + bar
 //! ```
-use super::{AssistKind, AssistsCtx};
-use crate::TextEdit;
+use super::AssistsCtx;
+use crate::{DiagnosticKind, TextEdit};
 use syntax::ast::{self, AstNode};
 use syntax::{SyntaxNodePtr, TextRange, TextSize};
 
 pub(super) fn add_to_top_level_lambda_param(ctx: &mut AssistsCtx<'_>) -> Option<()> {
-    let node = ctx.covering_node::<ast::Ref>()?;
+    let node = undefined_ref(ctx)?;
     let name = node.token()?;
     let name = name.text();
 
@@ -22,7 +22,20 @@ pub(super) fn add_to_top_level_lambda_param(ctx: &mut AssistsCtx<'_>) -> Option<
         .param()?
         .pat()?;
 
-    // Name should be undefined.
+    ctx.add_fix(
+        DiagnosticKind::UndefinedName,
+        node.syntax().text_range(),
+        "add_to_top_level_lambda_param",
+        format!("Add `{name}` to the top-level lambda parameter"),
+        vec![add_pat_field(&pat, name)],
+    );
+
+    Some(())
+}
+
+/// The reference under the cursor, if it is undefined.
+pub(super) fn undefined_ref(ctx: &AssistsCtx<'_>) -> Option<ast::Ref> {
+    let node = ctx.covering_node::<ast::Ref>()?;
     let expr = ctx
         .db
         .source_map(ctx.frange.file_id)
@@ -35,7 +48,11 @@ pub(super) fn add_to_top_level_lambda_param(ctx: &mut AssistsCtx<'_>) -> Option<
     {
         return None;
     };
+    Some(node)
+}
 
+/// Add a field `name` at the end of the pattern `pat`.
+pub(crate) fn add_pat_field(pat: &ast::Pat, name: &str) -> TextEdit {
     let (pos, insert) = if let Some(field) = pat.fields().last() {
         let field = field.syntax();
         let mut pos = field.text_range().end();
@@ -52,17 +69,10 @@ pub(super) fn add_to_top_level_lambda_param(ctx: &mut AssistsCtx<'_>) -> Option<
         (pat.syntax().text_range().start(), name.into())
     };
 
-    ctx.add(
-        "add_to_top_level_lambda_param",
-        format!("Add `{name}` to the top-level lambda parameter"),
-        AssistKind::QuickFix,
-        vec![TextEdit {
-            delete: TextRange::new(pos, pos),
-            insert: insert.into(),
-        }],
-    );
-
-    Some(())
+    TextEdit {
+        delete: TextRange::new(pos, pos),
+        insert: insert.into(),
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,394 @@
+//! Convert between `callPackage` and `import` of a package file.
+//!
+//! ```nix
+//! pkgs.callPackage ./foo.nix { bar = 1; }
+//! ```
+//! <=>
+//! ```nix
+//! import ./foo.nix { inherit (pkgs) stdenv fetchurl; bar = 1; }
+//! ```
+//!
+//! Explicit arguments are computed from fields without default values in the pattern of
+//! `./foo.nix`. `pkgs` is assumed if it is called by a bare `callPackage`, and it must be in
+//! scope then. Fields with default values must be passed explicitly, since `callPackage` would
+//! pass them from `pkgs` if they exist there.
+//!
+//! The result of `import` is no longer overridable via `.override` or `.overrideDerivation`.
+use super::{AssistKind, AssistsCtx};
+use crate::def::{is_call_package, AstPtr, Expr, ExprId, Literal};
+use crate::{FileId, TextEdit};
+use syntax::ast::{self, AstNode, HasBindings};
+use syntax::{SyntaxKind, TextRange, TextSize};
+
+/// The package set used for a bare `callPackage`.
+const DEFAULT_PACKAGE_SET: &str = "pkgs";
+
+pub(super) fn call_package_to_import(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let call = find_call(ctx, false)?;
+    let module = ctx.db.module(ctx.frange.file_id);
+    let source_map = ctx.db.source_map(ctx.frange.file_id);
+    let src = ctx.db.file_content(ctx.frange.file_id);
+
+    // The package set of `callPackage`, ie. `pkgs` in `pkgs.callPackage`.
+    let pkgs = match &module[call.func] {
+        Expr::Select(set_expr, path, None) => {
+            let set_range = source_map.node_for_expr(*set_expr)?.text_range();
+            let range = match path.len().checked_sub(2) {
+                Some(i) => set_range.cover(source_map.node_for_expr(path[i])?.text_range()),
+                None => set_range,
+            };
+            src[range].to_owned()
+        }
+        _ => DEFAULT_PACKAGE_SET.to_owned(),
+    };
+    let pkgs_in_scope = || {
+        matches!(&module[call.func], Expr::Select(..))
+            || ctx
+                .db
+                .scopes(ctx.frange.file_id)
+                .resolve_name(call.func, &DEFAULT_PACKAGE_SET.into())
+                .is_some()
+    };
+
+    let Expr::Attrset(bindings) = &module[call.arg] else {
+        return None;
+    };
+    if !bindings.dynamics.is_empty() {
+        return None;
+    }
+
+    // Fields which are not passed explicitly.
+    let target_module = ctx.db.module(call.target);
+    let Expr::Lambda(_, Some(pat), _) = &target_module[target_module.entry_expr()] else {
+        return None;
+    };
+    let missing = pat
+        .fields
+        .iter()
+        .filter_map(|(name, default)| Some((&*target_module[(*name)?].text, default)))
+        .filter(|(name, _)| bindings.get(name, &module).is_none())
+        .collect::<Vec<_>>();
+    // Fields with defaults may be passed from `pkgs` by `callPackage`, which we cannot tell.
+    if missing.iter().any(|(_, default)| default.is_some()) {
+        return None;
+    }
+    let names = missing.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+
+    let mut edits = vec![TextEdit {
+        delete: call.func_range,
+        insert: "import".into(),
+    }];
+    if !names.is_empty() {
+        if !pkgs_in_scope() {
+            return None;
+        }
+        let set = ast::AttrSet::cast(
+            source_map
+                .node_for_expr(call.arg)?
+                .to_node(ctx.ast.syntax()),
+        )?;
+        let pos = match set.l_curly_token()?.next_token() {
+            Some(tok) if tok.kind() == SyntaxKind::SPACE => tok.text_range().end(),
+            _ => set.l_curly_token()?.text_range().end(),
+        };
+        edits.push(TextEdit {
+            delete: TextRange::empty(pos),
+            insert: format!("inherit ({pkgs}) {}; ", names.join(" ")).into(),
+        });
+    }
+
+    ctx.add(
+        "call_package_to_import",
+        "Convert to explicit import with arguments (no longer overridable)",
+        AssistKind::RefactorRewrite,
+        edits,
+    );
+    Some(())
+}
+
+pub(super) fn import_to_call_package(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let call = find_call(ctx, true)?;
+    let source_map = ctx.db.source_map(ctx.frange.file_id);
+    let src = ctx.db.file_content(ctx.frange.file_id);
+
+    // The target should be a package file.
+    let target_module = ctx.db.module(call.target);
+    let Expr::Lambda(_, Some(_), _) = &target_module[target_module.entry_expr()] else {
+        return None;
+    };
+
+    let set = ast::AttrSet::cast(
+        source_map
+            .node_for_expr(call.arg)?
+            .to_node(ctx.ast.syntax()),
+    )?;
+    if set.rec_token().is_some() {
+        return None;
+    }
+
+    // The package set is from the first `inherit (pkgs) ...;`.
+    let inherits = || {
+        set.bindings().filter_map(|b| match b {
+            ast::Binding::Inherit(i) => Some(i),
+            ast::Binding::AttrpathValue(_) => None,
+        })
+    };
+    let inherit_from = |i: &ast::Inherit| -> Option<ast::Expr> { i.from_expr()?.expr() };
+    let pkgs = inherits().find_map(|i| inherit_from(&i))?;
+    let pkgs_text = &src[pkgs.syntax().text_range()];
+
+    let mut edits = Vec::new();
+    for i in inherits() {
+        if !matches!(inherit_from(&i), Some(e) if src[e.syntax().text_range()] == *pkgs_text) {
+            continue;
+        }
+        let mut range = i.syntax().text_range();
+        // Also remove the following space.
+        if let Some(tok) = i.syntax().last_token().and_then(|tok| tok.next_token()) {
+            if tok.kind() == SyntaxKind::SPACE {
+                range = range.cover(tok.text_range());
+            }
+        }
+        edits.push(TextEdit {
+            delete: range,
+            insert: "".into(),
+        });
+    }
+
+    let func = match pkgs {
+        ast::Expr::Ref(_) | ast::Expr::Select(_) => format!("{pkgs_text}.callPackage"),
+        _ => format!("({pkgs_text}).callPackage"),
+    };
+    edits.push(TextEdit {
+        delete: call.func_range,
+        insert: func.into(),
+    });
+
+    ctx.add(
+        "import_to_call_package",
+        "Convert to callPackage",
+        AssistKind::RefactorRewrite,
+        edits,
+    );
+    Some(())
+}
+
+struct PackageCall {
+    /// `import` or `callPackage`.
+    func: ExprId,
+    /// The range of `func`, excluding trailing spaces.
+    func_range: TextRange,
+    /// The argument attrset.
+    arg: ExprId,
+    /// The imported file.
+    target: FileId,
+}
+
+/// Find the call `import ./file.nix arg` if `is_import`, or `callPackage ./file.nix arg`
+/// otherwise, around the cursor.
+fn find_call(ctx: &AssistsCtx<'_>, is_import: bool) -> Option<PackageCall> {
+    let module = ctx.db.module(ctx.frange.file_id);
+    let source_map = ctx.db.source_map(ctx.frange.file_id);
+    let nameres = ctx.db.name_resolution(ctx.frange.file_id);
+    let src = ctx.db.file_content(ctx.frange.file_id);
+    let node = ctx.covering_node::<ast::Apply>()?;
+    node.syntax()
+        .ancestors()
+        .filter_map(ast::Apply::cast)
+        .find_map(|apply| {
+            let expr = source_map.expr_for_node(AstPtr::new(apply.syntax()))?;
+            let &Expr::Apply(func, arg) = &module[expr] else {
+                return None;
+            };
+            let &Expr::Apply(func, path_expr) = &module[func] else {
+                return None;
+            };
+            let matched = if is_import {
+                nameres.check_builtin(func, &module) == Some("import")
+            } else {
+                is_call_package(ctx.db, &module, func)
+            };
+            if !matched {
+                return None;
+            }
+            let &Expr::Literal(Literal::Path(path)) = &module[path_expr] else {
+                return None;
+            };
+            let target = ctx.db.resolve_path_file(path)?;
+            let range = source_map.node_for_expr(func)?.text_range();
+            let func_range = TextRange::at(range.start(), TextSize::of(src[range].trim_end()));
+            Some(PackageCall {
+                func,
+                func_range,
+                arg,
+                target,
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    mod call_package_to_import {
+        use super::*;
+
+        define_check_assist!(super::super::call_package_to_import, multi_file);
+
+        #[test]
+        fn simple() {
+            check(
+                "
+#- /default.nix
+{ pkgs }: pkgs.callPackage$0 ./foo.nix { }
+#- /foo.nix
+{ stdenv, fetchurl }: 1
+                ",
+                expect!["{ pkgs }: import ./foo.nix { inherit (pkgs) stdenv fetchurl; }"],
+            );
+            check(
+                "
+#- /default.nix
+{ pkgs }: pkgs.callPackage$0 ./foo.nix { enable = true; }
+#- /foo.nix
+{ stdenv, enable ? false }: 1
+                ",
+                expect!["{ pkgs }: import ./foo.nix { inherit (pkgs) stdenv; enable = true; }"],
+            );
+            check(
+                "
+#- /default.nix
+{ pkgs, callPackage }: callPackage ./foo.nix { fetchurl = 1; $0}
+#- /foo.nix
+{ stdenv, fetchurl }: 1
+                ",
+                expect![
+                    "{ pkgs, callPackage }: import ./foo.nix { inherit (pkgs) stdenv; fetchurl = 1; }"
+                ],
+            );
+            check(
+                "
+#- /default.nix
+{ callPackage }: callPackage ./foo.nix { stdenv = 1; $0}
+#- /foo.nix
+{ stdenv }: 1
+                ",
+                expect!["{ callPackage }: import ./foo.nix { stdenv = 1; }"],
+            );
+            check(
+                "
+#- /default.nix
+{ a }: a.b.callPackage ./foo.nix$0 { stdenv = 1; }
+#- /foo.nix
+{ stdenv }: 1
+                ",
+                expect!["{ a }: import ./foo.nix { stdenv = 1; }"],
+            );
+        }
+
+        #[test]
+        fn not_applicable() {
+            check_no(
+                "
+#- /default.nix
+{ callPackage, args }: callPackage$0 ./foo.nix args
+#- /foo.nix
+{ stdenv }: 1
+                ",
+            );
+            check_no(
+                "
+#- /default.nix
+{ callPackage }: callPackage$0 ./foo.nix { }
+#- /foo.nix
+stdenv: 1
+                ",
+            );
+            check_no("{ callPackage }: callPackage$0 ./foo.nix { }");
+            // `enable` may be passed from `pkgs`.
+            check_no(
+                "
+#- /default.nix
+{ pkgs }: pkgs.callPackage$0 ./foo.nix { }
+#- /foo.nix
+{ stdenv, enable ? false }: 1
+                ",
+            );
+            // `pkgs` is not in scope.
+            check_no(
+                "
+#- /default.nix
+{ callPackage }: callPackage ./foo.nix { fetchurl = 1; $0}
+#- /foo.nix
+{ stdenv, fetchurl }: 1
+                ",
+            );
+        }
+    }
+
+    mod import_to_call_package {
+        use super::*;
+
+        define_check_assist!(super::super::import_to_call_package, multi_file);
+
+        #[test]
+        fn simple() {
+            check(
+                "
+#- /default.nix
+{ pkgs }: import$0 ./foo.nix { inherit (pkgs) stdenv fetchurl; }
+#- /foo.nix
+{ stdenv, fetchurl }: 1
+                ",
+                expect!["{ pkgs }: pkgs.callPackage ./foo.nix { }"],
+            );
+            check(
+                "
+#- /default.nix
+{ pkgs, lib }: import ./foo.nix { a = 1; inherit (pkgs) stdenv; inherit (lib) b; $0}
+#- /foo.nix
+{ stdenv, a, b }: 1
+                ",
+                expect!["{ pkgs, lib }: pkgs.callPackage ./foo.nix { a = 1; inherit (lib) b; }"],
+            );
+            check(
+                "
+#- /default.nix
+{ pkgs }: import ./foo.nix$0 { inherit (pkgs.x86_64) stdenv; }
+#- /foo.nix
+{ stdenv }: 1
+                ",
+                expect!["{ pkgs }: pkgs.x86_64.callPackage ./foo.nix { }"],
+            );
+            check(
+                "
+#- /default.nix
+{ }: import ./foo.nix$0 { inherit (import <nixpkgs> { }) stdenv; }
+#- /foo.nix
+{ stdenv }: 1
+                ",
+                expect!["{ }: (import <nixpkgs> { }).callPackage ./foo.nix { }"],
+            );
+        }
+
+        #[test]
+        fn not_applicable() {
+            check_no(
+                "
+#- /default.nix
+{ pkgs }: import$0 ./foo.nix { stdenv = pkgs.stdenv; }
+#- /foo.nix
+{ stdenv }: 1
+                ",
+            );
+            check_no(
+                "
+#- /default.nix
+{ pkgs }: import$0 ./foo.nix { inherit (pkgs) stdenv; }
+#- /foo.nix
+stdenv: 1
+                ",
+            );
+        }
+    }
+}
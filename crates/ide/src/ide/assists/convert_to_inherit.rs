@@ -6,9 +6,12 @@
 //! - `prefix.key = from.key;` => `prefix = [rec] { inherit (from) key; };`
 //!   Since the `from` is resolved in the `prefix` scope thus
 //!   it is allowed to have recursive references (but may not be infinite recursion).
+//!
+//! It is the quick fix of `manual_inherit` and `manual_inherit_from` in
+//! [docs/diagnostics.md](./diagnostics.md), if reported.
 use super::{AssistKind, AssistsCtx};
 use crate::def::AstPtr;
-use crate::{NameKind, TextEdit};
+use crate::{DiagnosticKind, NameKind, TextEdit};
 use itertools::Itertools;
 use syntax::ast::{self, AstNode};
 use syntax::semantic::AttrKind;
@@ -74,15 +77,31 @@ pub(super) fn convert_to_inherit(ctx: &mut AssistsCtx<'_>) -> Option<()> {
     };
 
     // Since RHS is already a valid identifier. Not escaping is required.
-    ctx.add(
-        "convert_to_inherit",
-        format!("Convert to `inherit{from_frag} {rhs_name}`"),
-        AssistKind::RefactorRewrite,
-        vec![TextEdit {
-            delete: binding.syntax().text_range(),
-            insert: insert.into(),
-        }],
-    );
+    let range = binding.syntax().text_range();
+    let label = format!("Convert to `inherit{from_frag} {rhs_name}`");
+    let edits = vec![TextEdit {
+        delete: range,
+        insert: insert.into(),
+    }];
+    let kind = if from_frag.is_empty() {
+        DiagnosticKind::ManualInherit
+    } else {
+        DiagnosticKind::ManualInheritFrom
+    };
+    if ctx
+        .diagnostics
+        .iter()
+        .any(|diag| diag.kind == kind && diag.range == range)
+    {
+        ctx.add_fix(kind, range, "convert_to_inherit", label, edits);
+    } else {
+        ctx.add(
+            "convert_to_inherit",
+            label,
+            AssistKind::RefactorRewrite,
+            edits,
+        );
+    }
 
     Some(())
 }
@@ -0,0 +1,66 @@
+//! Reduce the lambda `x: f x` to `f`.
+//! See `eta_reduction` in [docs/diagnostics.md](./diagnostics.md).
+//!
+//! ```nix
+//! map (x: toString x) xs
+//! ```
+//! =>
+//! ```nix
+//! map toString xs
+//! ```
+use super::super::diagnostics::eta_reduce;
+use super::rewrite_if_to_optional::is_atom;
+use super::AssistsCtx;
+use crate::{DiagnosticKind, TextEdit};
+use syntax::ast::{self, AstNode};
+
+pub(super) fn eta_reduce_lambda(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let lambda = ctx.covering_node::<ast::Lambda>()?;
+    let func = eta_reduce(&lambda)?;
+    let range = lambda.syntax().text_range();
+    // `(x: f x)` => `f`, where the parentheses are no longer necessary.
+    let delete = lambda
+        .syntax()
+        .parent()
+        .and_then(ast::Paren::cast)
+        .filter(|_| is_atom(&func))
+        .map_or(range, |paren| paren.syntax().text_range());
+    let func = func.syntax().to_string();
+    let func = func.trim_end();
+    ctx.add_fix(
+        DiagnosticKind::EtaReduction,
+        range,
+        "eta_reduce_lambda",
+        format!("Reduce to `{func}`"),
+        vec![TextEdit {
+            delete,
+            insert: func.into(),
+        }],
+    );
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::eta_reduce_lambda);
+
+    #[test]
+    fn simple() {
+        check("map ($0x: toString x) xs", expect!["map toString xs"]);
+        check("map ($0x: g f x) xs", expect!["map (g f) xs"]);
+        check("y: $0x: f y x", expect!["y: f y"]);
+        check("x: (lib.$0id (x))", expect!["lib.id"]);
+    }
+
+    #[test]
+    fn kept() {
+        check_no("$0x: f x x");
+        check_no("$0x: x.f x");
+        check_no("$0x: { inherit x; }.f x");
+        check_no("$0{ x }: f x");
+        check_no("$0x: f y");
+        check_no("$0x: # Comment.\n  f x");
+    }
+}
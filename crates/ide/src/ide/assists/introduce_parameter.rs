@@ -0,0 +1,131 @@
+//! Introduce a variable defined outside a lambda as a parameter of the lambda,
+//! also passing it at all known call sites.
+//!
+//! ```nix
+//! let x = 1; f = { a }: a + x; in f { a = 2; }
+//! ```
+//! =>
+//! ```nix
+//! let x = 1; f = { a, x }: a + x; in f { a = 2; inherit x; }
+//! ```
+use super::add_to_top_level_lambda_param::add_pat_field;
+use super::{AssistKind, AssistsCtx};
+use crate::def::{AstPtr, Expr, ResolveResult};
+use crate::ide::rename::lambda_call_site_args;
+use crate::{InFile, TextEdit, WorkspaceEdit};
+use std::collections::HashMap;
+use syntax::ast::{self, AstNode};
+use syntax::TextRange;
+
+pub(super) fn introduce_parameter(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let node = ctx.covering_node::<ast::Ref>()?;
+    let name = node.token()?;
+    let name = name.text();
+    let file = ctx.frange.file_id;
+
+    // The innermost lambda with a pattern.
+    let (lambda, pat) = node.syntax().ancestors().find_map(|node| {
+        let lambda = ast::Lambda::cast(node)?;
+        let pat = lambda.param()?.pat()?;
+        Some((lambda, pat))
+    })?;
+
+    // The name should be defined outside the lambda.
+    let source_map = ctx.db.source_map(file);
+    let expr = source_map.expr_for_node(AstPtr::new(node.syntax()))?;
+    let &ResolveResult::Definition(def) = ctx.db.name_resolution(file).get(expr)? else {
+        return None;
+    };
+    let def_range = source_map.nodes_for_name(def).next()?.text_range();
+    if lambda.syntax().text_range().contains_range(def_range) {
+        return None;
+    }
+
+    let module = ctx.db.module(file);
+    let scopes = ctx.db.scopes(file);
+    let lambda_expr = source_map.expr_for_node(AstPtr::new(lambda.syntax()))?;
+    let mut content_edits = HashMap::new();
+    content_edits.insert(file, vec![add_pat_field(&pat, name)]);
+
+    // Pass the name at call sites, which must see the same definition.
+    for arg in lambda_call_site_args(ctx.db, InFile::new(file, lambda_expr)) {
+        if arg.file_id != file {
+            return None;
+        }
+        let visible = scopes
+            .ancestors(scopes.scope_for_expr(arg.value)?)
+            .find_map(|data| data.as_definitions()?.get(name).copied());
+        if visible != Some(def) {
+            return None;
+        }
+        let arg_node = source_map
+            .node_for_expr(arg.value)?
+            .to_node(ctx.ast.syntax());
+        let set = ast::AttrSet::cast(arg_node)?;
+        if set.rec_token().is_some() {
+            return None;
+        }
+        // Already passed.
+        if let Expr::Attrset(bindings) = &module[arg.value] {
+            if bindings.get(name, &module).is_some() {
+                continue;
+            }
+        }
+        let pos = set.r_curly_token()?.text_range().start();
+        content_edits.get_mut(&file).unwrap().push(TextEdit {
+            delete: TextRange::empty(pos),
+            insert: format!("inherit {name}; ").into(),
+        });
+    }
+
+    ctx.add_workspace_edit(
+        "introduce_parameter",
+        format!("Introduce `{name}` as a parameter of the lambda"),
+        AssistKind::RefactorRewrite,
+        WorkspaceEdit {
+            content_edits,
+            file_system_edits: Vec::new(),
+        },
+    );
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::introduce_parameter);
+
+    #[test]
+    fn simple() {
+        check(
+            "let x = 1; in { a }: a + $0x",
+            expect!["let x = 1; in { a, x }: a + x"],
+        );
+        check(
+            "let x = 1; f = { }: $0x; in f { }",
+            expect!["let x = 1; f = { x }: x; in f { inherit x; }"],
+        );
+    }
+
+    #[test]
+    fn call_sites() {
+        check(
+            "let x = 1; f = { a, ... }: a + $0x; in [ (f { a = 2; }) (f { a = 3; inherit x; }) ]",
+            expect!["let x = 1; f = { a, x, ... }: a + x; in [ (f { a = 2; inherit x; }) (f { a = 3; inherit x; }) ]"],
+        );
+    }
+
+    #[test]
+    fn not_applicable() {
+        check_no("{ a }: $0a");
+        check_no("x: $0x");
+        check_no("let x = 1; in { }: let y = x; in $0y");
+        check_no("{ }: $0undefined");
+        // Call sites which cannot be rewritten.
+        check_no("let x = 1; f = { }: $0x; args = { }; in f args");
+        check_no("let x = 1; f = { }: $0x; in let x = 2; in f { }");
+        check_no("let x = 1; f = { }: $0x; in f rec { }");
+    }
+}
@@ -1,29 +1,53 @@
 #[cfg(test)]
 macro_rules! define_check_assist {
     ($handler:path) => {
+        define_check_assist!(@define $handler, false);
+    };
+    // Fixtures may contain other files than the one being edited.
+    ($handler:path, multi_file) => {
+        define_check_assist!(@define $handler, true);
+    };
+    (@define $handler:path, $multi_file:literal) => {
         #[track_caller]
         fn check(fixture: &str, expect: ::expect_test::Expect) {
-            crate::ide::assists::tests::check_assist($handler, fixture, expect);
+            crate::ide::assists::tests::check_assist($handler, fixture, $multi_file, expect);
         }
         #[track_caller]
         #[allow(dead_code)]
         fn check_no(fixture: &str) {
-            crate::ide::assists::tests::check_assist_no($handler, fixture);
+            crate::ide::assists::tests::check_assist_no($handler, fixture, $multi_file);
         }
     };
 }
 
+mod add_to_let_in;
 mod add_to_top_level_lambda_param;
+mod convert_call_package;
 mod convert_to_inherit;
+mod eta_reduce;
 mod flatten_attrset;
+mod introduce_parameter;
 mod pack_bindings;
+mod remove_dead_code;
 mod remove_empty_inherit;
 mod remove_empty_let_in;
+mod remove_redundant_paren;
+mod remove_unused_param;
+mod remove_unused_rec;
+mod replace_deprecated_builtin;
+mod replace_deprecated_package;
+mod rewrite_if_to_optional;
 mod rewrite_string;
+mod simplify_boolean;
+mod sort_items;
+mod suppress_diagnostic;
+mod surround;
+
+pub(crate) use add_to_top_level_lambda_param::add_pat_field;
 
-use crate::{DefDatabase, FileRange, TextEdit, WorkspaceEdit};
+use crate::{DefDatabase, Diagnostic, DiagnosticKind, FileRange, TextEdit, WorkspaceEdit};
 use syntax::ast::{self, AstNode};
-use syntax::{best_token_at_offset, NixLanguage};
+use syntax::{best_token_at_offset, NixLanguage, TextRange, TextSize};
 
 #[derive(Debug, Clone)]
 pub struct Assist {
@@ -33,30 +57,62 @@ pub struct Assist {
     pub label: String,
     pub kind: AssistKind,
     pub edits: WorkspaceEdit,
+    /// The same edits in the snippet format with tab stops, for clients supporting them.
+    pub snippet_edits: Option<WorkspaceEdit>,
+    /// The diagnostic resolved by this quick fix, if any.
+    pub fixes: Option<Diagnostic>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum AssistKind {
     QuickFix,
     RefactorRewrite,
+    /// Actions applying to the whole file.
+    Source,
 }
 
-pub(crate) fn assists(db: &dyn DefDatabase, frange: FileRange) -> Vec<Assist> {
+/// `diagnostics` of the file are used by quick fixes, which are not recomputed here since
+/// they need a `TyDatabase`.
+pub(crate) fn assists(
+    db: &dyn DefDatabase,
+    frange: FileRange,
+    diagnostics: &[Diagnostic],
+) -> Vec<Assist> {
     let handlers = [
+        add_to_let_in::add_to_let_in,
+        add_to_let_in::add_to_lib_inherit,
         add_to_top_level_lambda_param::add_to_top_level_lambda_param,
+        convert_call_package::call_package_to_import,
+        convert_call_package::import_to_call_package,
         convert_to_inherit::convert_to_inherit,
+        eta_reduce::eta_reduce_lambda,
         flatten_attrset::flatten_attrset,
+        introduce_parameter::introduce_parameter,
         pack_bindings::pack_bindings,
+        remove_dead_code::remove_dead_code,
         remove_empty_inherit::remove_empty_inherit,
         remove_empty_let_in::remove_empty_let_in,
+        remove_redundant_paren::remove_redundant_paren,
+        remove_unused_param::remove_unused_param,
+        remove_unused_rec::remove_unused_rec,
+        replace_deprecated_builtin::replace_deprecated_builtin,
+        replace_deprecated_package::replace_deprecated_package,
+        rewrite_if_to_optional::rewrite_if_to_optional,
         rewrite_string::quote_attr,
         rewrite_string::rewrite_indented_to_string,
         rewrite_string::rewrite_string_to_indented,
         rewrite_string::rewrite_uri_to_string,
         rewrite_string::unquote_attr,
+        simplify_boolean::simplify_boolean,
+        sort_items::sort_attrset_bindings,
+        sort_items::sort_list_elements,
+        suppress_diagnostic::suppress_diagnostic,
+        suppress_diagnostic::suppress_diagnostic_in_file,
+        surround::surround_with_lambda,
+        surround::surround_with_let_in,
     ];
 
-    let mut ctx = AssistsCtx::new(db, frange);
+    let mut ctx = AssistsCtx::new(db, frange, diagnostics);
     for h in handlers {
         h(&mut ctx);
     }
@@ -66,15 +122,17 @@ pub(crate) fn assists(db: &dyn DefDatabase, frange: FileRange) -> Vec<Assist> {
 pub(crate) struct AssistsCtx<'a> {
     db: &'a dyn DefDatabase,
     frange: FileRange,
+    diagnostics: &'a [Diagnostic],
     ast: ast::SourceFile,
     assists: Vec<Assist>,
 }
 
 impl<'a> AssistsCtx<'a> {
-    fn new(db: &'a dyn DefDatabase, frange: FileRange) -> Self {
+    fn new(db: &'a dyn DefDatabase, frange: FileRange, diagnostics: &'a [Diagnostic]) -> Self {
         AssistsCtx {
             db,
             frange,
+            diagnostics,
             ast: db.parse(frange.file_id).root(),
             assists: Vec::new(),
         }
@@ -85,20 +143,96 @@ impl<'a> AssistsCtx<'a> {
         id: impl Into<String>,
         label: impl Into<String>,
         kind: AssistKind,
-        mut text_edits: Vec<TextEdit>,
+        text_edits: Vec<TextEdit>,
     ) {
-        text_edits.sort_unstable_by_key(|edit| edit.delete.start());
         let edits = WorkspaceEdit {
             content_edits: [(self.frange.file_id, text_edits)].into_iter().collect(),
+            file_system_edits: Vec::new(),
         };
+        self.add_workspace_edit(id, label, kind, edits);
+    }
+
+    /// Add an assist which may also edit other files.
+    fn add_workspace_edit(
+        &mut self,
+        id: impl Into<String>,
+        label: impl Into<String>,
+        kind: AssistKind,
+        mut edits: WorkspaceEdit,
+    ) {
+        for text_edits in edits.content_edits.values_mut() {
+            text_edits.sort_unstable_by_key(|edit| edit.delete.start());
+        }
         self.assists.push(Assist {
             id: id.into(),
             label: label.into(),
             kind,
             edits,
+            snippet_edits: None,
+            fixes: None,
+        });
+    }
+
+    /// Add an assist with `snippets` of `text_edits`, where the cursor is placed at tab stops.
+    /// Literal `$`, `}` and `\` in snippets must be escaped by [`escape_snippet`].
+    fn add_snippet(
+        &mut self,
+        id: impl Into<String>,
+        label: impl Into<String>,
+        kind: AssistKind,
+        text_edits: Vec<TextEdit>,
+        snippets: Vec<TextEdit>,
+    ) {
+        self.add(id, label, kind, text_edits);
+        let mut snippets = snippets;
+        snippets.sort_unstable_by_key(|edit| edit.delete.start());
+        self.assists.last_mut().unwrap().snippet_edits = Some(WorkspaceEdit {
+            content_edits: [(self.frange.file_id, snippets)].into_iter().collect(),
+            file_system_edits: Vec::new(),
         });
     }
 
+    /// Add a quick fix resolving the diagnostic of `kind` exactly at `range`, if it is reported.
+    fn add_fix(
+        &mut self,
+        kind: DiagnosticKind,
+        range: TextRange,
+        id: impl Into<String>,
+        label: impl Into<String>,
+        text_edits: Vec<TextEdit>,
+    ) {
+        let fixes = self
+            .diagnostics
+            .iter()
+            .find(|diag| diag.kind == kind && diag.range == range)
+            .cloned();
+        self.add(id, label, AssistKind::QuickFix, text_edits);
+        self.assists.last_mut().unwrap().fixes = fixes;
+    }
+
+    /// The expression exactly covered by the non-empty selection, ignoring surrounding spaces.
+    fn selected_expr(&self) -> Option<ast::Expr> {
+        let range = self.frange.range;
+        if range.is_empty() {
+            return None;
+        }
+        let src = self.db.file_content(self.frange.file_id);
+        let text = &src[range];
+        let start = range.start() + TextSize::of(&text[..text.len() - text.trim_start().len()]);
+        let end = range.end() - TextSize::of(&text[text.trim_end().len()..]);
+        let expr = self
+            .ast
+            .syntax()
+            .covering_element(TextRange::new(start, end.max(start)))
+            .ancestors()
+            .find_map(ast::Expr::cast)?;
+        let expr_range = expr.syntax().text_range();
+        // Trailing spaces may be inside the node.
+        let expr_text = &src[expr_range];
+        let expr_end = expr_range.end() - TextSize::of(&expr_text[expr_text.trim_end().len()..]);
+        (expr_range.start() == start && expr_end == end).then_some(expr)
+    }
+
     fn covering_node<N: AstNode<Language = NixLanguage>>(&self) -> Option<N> {
         let range = self.frange.range;
         if range.is_empty() {
@@ -111,6 +245,18 @@ impl<'a> AssistsCtx<'a> {
     }
 }
 
+/// Escape `text` to be inserted literally in a snippet.
+fn escape_snippet(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if matches!(ch, '$' | '}' | '\\') {
+            ret.push('\\');
+        }
+        ret.push(ch);
+    }
+    ret
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -122,17 +268,22 @@ mod tests {
     fn try_apply_assist(
         handler: fn(&mut AssistsCtx) -> Option<()>,
         fixture: &str,
+        multi_file: bool,
     ) -> Option<String> {
         let (db, f) = TestDB::from_fixture(fixture).unwrap();
-        assert_eq!(f.files().len(), 1);
+        if !multi_file {
+            assert_eq!(f.files().len(), 1);
+        }
         let frange = f.unwrap_single_range_marker();
-        let mut ctx = AssistsCtx::new(&db, frange);
+        let diagnostics = crate::ide::diagnostics::diagnostics(&db, frange.file_id);
+        let mut ctx = AssistsCtx::new(&db, frange, &diagnostics);
         handler(&mut ctx);
 
+        // Only edits of the file with the marker are checked.
         let assist = ctx.assists.pop()?;
-        let mut src = db.file_content(f[0].file_id).to_string();
+        let mut src = db.file_content(frange.file_id).to_string();
         // Reverse apply.
-        for edit in assist.edits.content_edits[&f[0].file_id].iter().rev() {
+        for edit in assist.edits.content_edits[&frange.file_id].iter().rev() {
             edit.apply(&mut src);
         }
         // Don't count spaces at the end of lines.
@@ -150,16 +301,43 @@ mod tests {
     pub(crate) fn check_assist(
         handler: fn(&mut AssistsCtx) -> Option<()>,
         fixture: &str,
+        multi_file: bool,
         expect: Expect,
     ) {
-        let got = try_apply_assist(handler, fixture).expect("Not applicable");
+        let got = try_apply_assist(handler, fixture, multi_file).expect("Not applicable");
         expect.assert_eq(&got);
     }
 
     #[track_caller]
-    pub(crate) fn check_assist_no(handler: fn(&mut AssistsCtx) -> Option<()>, fixture: &str) {
-        if let Some(got) = try_apply_assist(handler, fixture) {
+    pub(crate) fn check_assist_no(
+        handler: fn(&mut AssistsCtx) -> Option<()>,
+        fixture: &str,
+        multi_file: bool,
+    ) {
+        if let Some(got) = try_apply_assist(handler, fixture, multi_file) {
             panic!("Unexpected applicable:\n{got}");
         }
     }
+
+    #[test]
+    fn fixes() {
+        let (db, f) = TestDB::from_fixture("$0https://nixos.org").unwrap();
+        let frange = f.unwrap_single_range_marker();
+        let diagnostics = crate::ide::diagnostics::diagnostics(&db, frange.file_id);
+        let got = assists(&db, frange, &diagnostics)
+            .into_iter()
+            .map(|assist| (assist.id, assist.fixes.map(|diag| diag.kind)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            got,
+            [
+                (
+                    "rewrite_uri_to_string".into(),
+                    Some(DiagnosticKind::UriLiteral)
+                ),
+                ("suppress_diagnostic".into(), None),
+                ("suppress_diagnostic_in_file".into(), None),
+            ],
+        );
+    }
 }
@@ -0,0 +1,324 @@
+//! Remove all dead code in the file at once, like `deadnix --edit`:
+//! unused `let` bindings, including mutually unused ones, removable pattern fields and
+//! `@`-bindings of lambdas. Unused plain lambda arguments are renamed to `_`.
+//! Names starting with `_` are kept where they are conventionally unused.
+//!
+//! ```nix
+//! { lib, pkgs, ... }:
+//! let
+//!   a = b;
+//!   b = a;
+//! in
+//! pkgs.hello
+//! ```
+//! =>
+//! ```nix
+//! { pkgs, ... }:
+//! pkgs.hello
+//! ```
+use super::remove_unused_param::{non_trivia_sibling, trimmed_end};
+use super::{AssistKind, AssistsCtx};
+use crate::def::NameKind;
+use crate::TextEdit;
+use std::collections::HashSet;
+use syntax::ast::{self, AstNode, HasBindings};
+use syntax::rowan::Direction;
+use syntax::{SyntaxElement, SyntaxNode, TextRange, TextSize, T};
+
+pub(super) fn remove_dead_code(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let file = ctx.frange.file_id;
+    let module = ctx.db.module(file);
+    let source_map = ctx.db.source_map(file);
+    // Flake inputs and attributes are not code to be removed.
+    let dead = ctx
+        .db
+        .liveness_check(file)
+        .unused_names()
+        .filter(|&name| {
+            matches!(
+                module[name].kind,
+                NameKind::LetIn | NameKind::Param | NameKind::PatField
+            )
+        })
+        .flat_map(|name| source_map.nodes_for_name(name))
+        .map(|ptr| ptr.text_range())
+        .collect::<HashSet<_>>();
+    if dead.is_empty() {
+        return None;
+    }
+    let is_dead = |node: &SyntaxNode| dead.contains(&node.text_range());
+
+    let mut edits = Vec::new();
+    for node in ctx.ast.syntax().descendants() {
+        if let Some(let_in) = ast::LetIn::cast(node.clone()) {
+            remove_let_bindings(&let_in, &is_dead, &mut edits);
+        } else if let Some(pat) = ast::Pat::cast(node.clone()) {
+            remove_pat_fields(&pat, &is_dead, &mut edits);
+        } else if let Some(param) = ast::Param::cast(node) {
+            let Some(name) = param.name().filter(|name| is_dead(name.syntax())) else {
+                continue;
+            };
+            let name_range = name.syntax().text_range();
+            let edit = match (param.pat(), param.at_token()) {
+                // `foo @ { }` => `{ }`
+                (Some(pat), Some(at)) if name_range.start() < at.text_range().start() => {
+                    delete(name_range.start(), pat.syntax().text_range().start())
+                }
+                // `{ } @ foo` => `{ }`
+                (Some(pat), Some(_)) => delete(trimmed_end(pat.syntax()), name_range.end()),
+                // `foo: 0` => `_: 0`
+                _ => TextEdit {
+                    delete: name_range,
+                    insert: "_".into(),
+                },
+            };
+            edits.push(edit);
+        }
+    }
+    if edits.is_empty() {
+        return None;
+    }
+
+    // Edits inside removed code are dropped, and overlapping deletions are merged.
+    edits.sort_by_key(|edit| (edit.delete.start(), std::cmp::Reverse(edit.delete.end())));
+    let mut merged = Vec::<TextEdit>::with_capacity(edits.len());
+    for edit in edits {
+        match merged.last_mut() {
+            Some(last) if edit.delete.start() < last.delete.end() => {
+                last.delete = last.delete.cover(edit.delete);
+            }
+            _ => merged.push(edit),
+        }
+    }
+
+    ctx.add(
+        "remove_dead_code",
+        "Remove all dead code in file",
+        AssistKind::Source,
+        merged,
+    );
+    Some(())
+}
+
+fn delete(start: TextSize, end: TextSize) -> TextEdit {
+    TextEdit {
+        delete: TextRange::new(start, end),
+        insert: Default::default(),
+    }
+}
+
+/// Remove a child element of a binding list, with spaces before it, so that comments and
+/// indentation of other lines are kept.
+fn delete_with_leading_spaces(elem: SyntaxElement) -> TextEdit {
+    let start = std::iter::successors(elem.prev_sibling_or_token(), |e| e.prev_sibling_or_token())
+        .find(|e| !e.kind().is_space())
+        .map_or(elem.text_range().start(), |e| e.text_range().end());
+    delete(start, elem.text_range().end())
+}
+
+fn remove_let_bindings(
+    let_in: &ast::LetIn,
+    is_dead: &impl Fn(&SyntaxNode) -> bool,
+    edits: &mut Vec<TextEdit>,
+) {
+    let mut all_dead = true;
+    let mut binding_edits = Vec::new();
+    for binding in let_in.bindings() {
+        match &binding {
+            ast::Binding::AttrpathValue(binding) => {
+                let first_attr = binding.attrpath().and_then(|path| path.attrs().next());
+                if first_attr.map_or(false, |attr| is_dead(attr.syntax())) {
+                    binding_edits.push(delete_with_leading_spaces(binding.syntax().clone().into()));
+                } else {
+                    all_dead = false;
+                }
+            }
+            ast::Binding::Inherit(inherit) => {
+                let (dead, alive): (Vec<_>, Vec<_>) =
+                    inherit.attrs().partition(|attr| is_dead(attr.syntax()));
+                if alive.is_empty() && !dead.is_empty() {
+                    binding_edits.push(delete_with_leading_spaces(inherit.syntax().clone().into()));
+                } else {
+                    all_dead = false;
+                    binding_edits.extend(
+                        dead.into_iter()
+                            .map(|attr| delete_with_leading_spaces(attr.syntax().clone().into())),
+                    );
+                }
+            }
+        }
+    }
+
+    // `let a = 1; in b` => `b`
+    if all_dead && !binding_edits.is_empty() {
+        if let (Some(let_token), Some(body)) = (let_in.let_token(), let_in.body()) {
+            edits.push(delete(
+                let_token.text_range().start(),
+                body.syntax().text_range().start(),
+            ));
+            return;
+        }
+    }
+    edits.extend(binding_edits);
+}
+
+fn remove_pat_fields(
+    pat: &ast::Pat,
+    is_dead: &impl Fn(&SyntaxNode) -> bool,
+    edits: &mut Vec<TextEdit>,
+) {
+    // Fields and the ellipsis, with whether they are removed.
+    let items = pat
+        .syntax()
+        .children_with_tokens()
+        .filter_map(|elem| match &elem {
+            SyntaxElement::Node(node) => {
+                let field = ast::PatField::cast(node.clone())?;
+                let dead = field.name().map_or(false, |name| is_dead(name.syntax()));
+                Some((elem, dead))
+            }
+            SyntaxElement::Token(tok) => (tok.kind() == T![...]).then_some((elem, false)),
+        })
+        .collect::<Vec<_>>();
+    if !items.iter().any(|&(_, dead)| dead) {
+        return;
+    }
+    let end_of = |elem: &SyntaxElement| match elem {
+        SyntaxElement::Node(node) => trimmed_end(node),
+        SyntaxElement::Token(tok) => tok.text_range().end(),
+    };
+
+    // `{ foo, bar }` => `{ }`
+    if items.iter().all(|&(_, dead)| dead) {
+        if let (Some(l_curly), Some(r_curly)) = (pat.l_curly_token(), pat.r_curly_token()) {
+            edits.push(TextEdit {
+                delete: TextRange::new(l_curly.text_range().end(), r_curly.text_range().start()),
+                insert: " ".into(),
+            });
+        }
+        return;
+    }
+
+    let mut i = 0;
+    while i < items.len() {
+        if !items[i].1 {
+            i += 1;
+            continue;
+        }
+        let run_start = i;
+        while i < items.len() && items[i].1 {
+            i += 1;
+        }
+        let edit = match items.get(i) {
+            // `{ foo, bar }` => `{ bar }`
+            Some((next, _)) => delete(
+                items[run_start].0.text_range().start(),
+                next.text_range().start(),
+            ),
+            // `{ foo, bar }` => `{ foo }`
+            None => {
+                let prev = non_trivia_sibling(items[run_start].0.clone(), Direction::Prev)
+                    .filter(|e| e.kind() == T![,])
+                    .and_then(|comma| non_trivia_sibling(comma, Direction::Prev));
+                let start = prev.map_or(items[run_start].0.text_range().start(), |prev| {
+                    end_of(&prev)
+                });
+                delete(start, end_of(&items[i - 1].0))
+            }
+        };
+        edits.push(edit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::remove_dead_code);
+
+    #[test]
+    fn let_in() {
+        check("let a = 1; b = 2; in $0b", expect!["let b = 2; in b"]);
+        check("let a = b; b = a; in $01", expect!["1"]);
+        check(
+            r#"
+let
+  # Used.
+  a = 1;
+  # Unused.
+  b = c;
+  c = 2;
+  d.e = 3;
+  d.f = 4;
+in
+$0a
+            "#,
+            expect![[r#"
+                let
+                  # Used.
+                  a = 1;
+                  # Unused.
+                in
+                a
+            "#]],
+        );
+        check(
+            "let a = let b = 1; in 2; c = 3; in $0c",
+            expect!["let c = 3; in c"],
+        );
+        check("let a = 1; in let $0b = a; in 2", expect!["2"]);
+    }
+
+    #[test]
+    fn inherit() {
+        check(
+            "{ a, b }: let inherit a b; c = 1; in $0b + c",
+            expect!["{ a, b }: let inherit b; c = 1; in b + c"],
+        );
+        check(
+            "x: let inherit (x) a b; c = 1; in $0c",
+            expect!["x: let c = 1; in c"],
+        );
+    }
+
+    #[test]
+    fn pat_field() {
+        check("{ a, b, c, ... }: $0b", expect!["{ b, ... }: b"]);
+        check("{ a, b ? 1, c }: $0f { }", expect!["{ }: f { }"]);
+        check(
+            "{ stdenv, hello, lib }: $0stdenv.mkDerivation { }",
+            expect!["{ stdenv }: stdenv.mkDerivation { }"],
+        );
+        check(
+            "_: { a, b, c, d, ... }: $0b + d",
+            expect!["_: { b, d, ... }: b + d"],
+        );
+        check(
+            "{ b\n, a\n, c\n}: $0b { }",
+            expect![[r#"
+                { b
+                }: b { }
+            "#]],
+        );
+    }
+
+    #[test]
+    fn param() {
+        check("args @ { ... }: $00", expect!["{ ... }: 0"]);
+        check("{ a, ... } @ args: $0a", expect!["{ a, ... }: a"]);
+        check("a: b: $0b", expect!["_: b: b"]);
+        check("let f = x: 1; in $00", expect!["0"]);
+    }
+
+    #[test]
+    fn underscore() {
+        check("_a: { _b, c, ... }: $00", expect!["_a: { _b, ... }: 0"]);
+    }
+
+    #[test]
+    fn not_applicable() {
+        check_no("let a = 1; in $0a");
+        check_no("{ a, ... }@args: $0args.b");
+        check_no("{ a = 1; b = 2; }$0");
+    }
+}
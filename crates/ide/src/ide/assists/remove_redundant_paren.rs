@@ -0,0 +1,75 @@
+//! Remove parentheses around atoms.
+//! See `redundant_paren` in [docs/diagnostics.md](./diagnostics.md).
+//!
+//! ```nix
+//! f (x) + (1)
+//! ```
+//! =>
+//! ```nix
+//! f x + 1
+//! ```
+use super::super::diagnostics::is_redundant_paren;
+use super::AssistsCtx;
+use crate::{DiagnosticKind, TextEdit};
+use syntax::ast::{self, AstNode};
+
+pub(super) fn remove_redundant_paren(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let kept_kinds = ctx.db.redundant_paren_kept_kinds();
+    let paren = ctx.covering_node::<ast::Paren>()?;
+    // Nested ones are removed from the outermost, like `((x))`.
+    let paren = std::iter::successors(Some(paren), |paren| {
+        paren
+            .syntax()
+            .parent()
+            .and_then(ast::Paren::cast)
+            .filter(|outer| is_redundant_paren(outer, &kept_kinds))
+    })
+    .last()?;
+    if !is_redundant_paren(&paren, &kept_kinds) {
+        return None;
+    }
+    let inner = paren.expr()?.syntax().to_string();
+    let range = paren.syntax().text_range();
+    ctx.add_fix(
+        DiagnosticKind::RedundantParen,
+        range,
+        "remove_redundant_paren",
+        "Remove redundant parentheses",
+        vec![TextEdit {
+            delete: range,
+            insert: inner.trim_end().into(),
+        }],
+    );
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::remove_redundant_paren);
+
+    #[test]
+    fn simple() {
+        check("$0(x) + 1", expect!["x + 1"]);
+        check("f ($0x)", expect!["f x"]);
+        check("f ( a.b $0)", expect!["f a.b"]);
+        check(r#"[ ($0"a") ]"#, expect![[r#"[ "a" ]"#]]);
+        check("[ (($0x)) ]", expect!["[ (x) ]"]);
+        check("{ a = ({ }$0); }", expect!["{ a = { }; }"]);
+        check("- ($01)", expect!["- 1"]);
+    }
+
+    #[test]
+    fn kept() {
+        check_no("($0a * b) + c");
+        check_no("f ($0g x)");
+        check_no("f (a.b or $0c)");
+        check_no("(./a$0).b");
+        check_no("{ inherit ($0lib) mkIf; }");
+        check_no("f($0x)");
+        check_no("x:($0y)");
+        check_no("(x$0)y");
+        check_no("f ($0x # Comment.\n)");
+    }
+}
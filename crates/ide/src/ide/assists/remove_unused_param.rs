@@ -0,0 +1,161 @@
+//! Remove an unused pattern field or `@`-binding of a lambda,
+//! or rename an unused plain lambda argument to `_`.
+//!
+//! ```nix
+//! { lib, pkgs, ... }: { environment.systemPackages = [ pkgs.hello ]; }
+//! ```
+//! =>
+//! ```nix
+//! { pkgs, ... }: { environment.systemPackages = [ pkgs.hello ]; }
+//! ```
+use super::{AssistKind, AssistsCtx};
+use crate::def::AstPtr;
+use crate::TextEdit;
+use syntax::ast::{self, AstNode};
+use syntax::rowan::Direction;
+use syntax::{SyntaxElement, SyntaxNode, TextRange, TextSize, T};
+
+pub(super) fn remove_unused_param(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let name_node = ctx.covering_node::<ast::Name>()?;
+    let file = ctx.frange.file_id;
+    let name = ctx
+        .db
+        .source_map(file)
+        .name_for_node(AstPtr::new(name_node.syntax()))?;
+    if !ctx.db.liveness_check(file).is_unused(name) {
+        return None;
+    }
+    let text = &ctx.db.module(file)[name].text;
+
+    let parent = name_node.syntax().parent()?;
+    let (label, edit) = if let Some(field) = ast::PatField::cast(parent.clone()) {
+        let field = field.syntax();
+        // Without the ellipsis, callers passing it would be rejected. Parameters of packages
+        // are still removable, since only requested arguments are passed.
+        let has_ellipsis = field
+            .parent()
+            .and_then(ast::Pat::cast)
+            .map_or(false, |pat| pat.ellipsis_token().is_some());
+        if !has_ellipsis && !ctx.db.liveness_check(file).is_unused_binding(name) {
+            return None;
+        }
+        let delete = if let Some(comma) =
+            non_trivia_sibling(field.clone().into(), Direction::Next).filter(|e| e.kind() == T![,])
+        {
+            // `{ foo, bar }` => `{ bar }`
+            let end = non_trivia_sibling(comma.clone(), Direction::Next)
+                .map_or(comma.text_range().end(), |e| e.text_range().start());
+            TextRange::new(field.text_range().start(), end)
+        } else if let Some(comma) =
+            non_trivia_sibling(field.clone().into(), Direction::Prev).filter(|e| e.kind() == T![,])
+        {
+            // `{ foo, bar }` => `{ foo }`
+            TextRange::new(comma.text_range().start(), trimmed_end(field))
+        } else {
+            // `{ foo }` => `{ }`
+            TextRange::new(field.text_range().start(), trimmed_end(field))
+        };
+        (
+            format!("Remove the unused parameter `{text}`"),
+            TextEdit {
+                delete,
+                insert: Default::default(),
+            },
+        )
+    } else {
+        let param = ast::Param::cast(parent)?;
+        match (param.pat(), param.at_token()) {
+            (Some(pat), Some(at)) => {
+                let name_range = name_node.syntax().text_range();
+                let delete = if name_range.start() < at.text_range().start() {
+                    // `foo @ { }` => `{ }`
+                    TextRange::new(name_range.start(), pat.syntax().text_range().start())
+                } else {
+                    // `{ } @ foo` => `{ }`
+                    TextRange::new(trimmed_end(pat.syntax()), name_range.end())
+                };
+                (
+                    format!("Remove the unused parameter `{text}`"),
+                    TextEdit {
+                        delete,
+                        insert: Default::default(),
+                    },
+                )
+            }
+            // `foo: 0` => `_: 0`
+            _ => (
+                format!("Rename the unused parameter `{text}` to `_`"),
+                TextEdit {
+                    delete: name_node.syntax().text_range(),
+                    insert: "_".into(),
+                },
+            ),
+        }
+    };
+
+    ctx.add(
+        "remove_unused_param",
+        label,
+        AssistKind::QuickFix,
+        vec![edit],
+    );
+    Some(())
+}
+
+pub(super) fn non_trivia_sibling(elem: SyntaxElement, dir: Direction) -> Option<SyntaxElement> {
+    std::iter::successors(Some(elem), |e| match dir {
+        Direction::Next => e.next_sibling_or_token(),
+        Direction::Prev => e.prev_sibling_or_token(),
+    })
+    .skip(1)
+    .find(|e| !e.kind().is_trivia())
+}
+
+/// The end of the node, excluding trailing spaces inside it.
+pub(super) fn trimmed_end(node: &SyntaxNode) -> TextSize {
+    node.text_range().start() + TextSize::of(node.text().to_string().trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::remove_unused_param);
+
+    #[test]
+    fn pat_field() {
+        check("{ $0a, b, ... }: b", expect!["{ b, ... }: b"]);
+        check("{ b, $0a ? 1, ... }: b", expect!["{ b, ... }: b"]);
+        check(
+            "{ stdenv, $0hello }: stdenv.mkDerivation { }",
+            expect!["{ stdenv }: stdenv.mkDerivation { }"],
+        );
+        check("{ $0a, ... }: { }", expect!["{ ... }: { }"]);
+        check(
+            "{ b\n, $0a\n, ...\n}: b",
+            expect![[r#"
+                { b
+                , ...
+                }: b
+            "#]],
+        );
+    }
+
+    #[test]
+    fn at_binding() {
+        check("$0args @ { ... }: 0", expect!["{ ... }: 0"]);
+        check("{ ... } @ $0args: 0", expect!["{ ... }: 0"]);
+    }
+
+    #[test]
+    fn plain() {
+        check("$0a: 0", expect!["_: 0"]);
+    }
+
+    #[test]
+    fn not_applicable() {
+        check_no("$0a: a");
+        check_no("{ $0a, b }: b");
+        check_no("let $0a = 1; in 1");
+    }
+}
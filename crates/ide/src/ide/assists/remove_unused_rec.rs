@@ -0,0 +1,67 @@
+//! Remove `rec` from an attrset whose bindings never reference their siblings.
+//!
+//! ```nix
+//! rec { foo = 1; bar = 2; }
+//! ```
+//! =>
+//! ```nix
+//! { foo = 1; bar = 2; }
+//! ```
+use super::{AssistKind, AssistsCtx};
+use crate::def::AstPtr;
+use crate::TextEdit;
+use syntax::ast::{self, AstNode};
+
+pub(super) fn remove_unused_rec(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let node = ctx.covering_node::<ast::AttrSet>()?;
+    let rec_token = node.rec_token()?;
+
+    let file = ctx.frange.file_id;
+    let expr = ctx
+        .db
+        .source_map(file)
+        .expr_for_node(AstPtr::new(node.syntax()))?;
+    if !ctx.db.liveness_check(file).rec_attrsets().contains(&expr) {
+        return None;
+    }
+
+    // Remove trailing whitespace.
+    let last_token = rec_token
+        .next_token()
+        .filter(|tok| tok.kind().is_space())
+        .unwrap_or_else(|| rec_token.clone());
+
+    ctx.add(
+        "remove_unused_rec",
+        "Remove the unused `rec`",
+        AssistKind::QuickFix,
+        vec![TextEdit {
+            delete: rec_token.text_range().cover(last_token.text_range()),
+            insert: Default::default(),
+        }],
+    );
+
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::remove_unused_rec);
+
+    #[test]
+    fn simple() {
+        check("$0rec { a = 1; }", expect!["{ a = 1; }"]);
+        check("rec { a = 1;$0 }", expect!["{ a = 1; }"]);
+        check("{ a = rec$0{ b = 1; }; }", expect!["{ a = { b = 1; }; }"]);
+        check("rec$0/*hello*/{ }", expect!["/*hello*/{ }"]);
+    }
+
+    #[test]
+    fn not_applicable() {
+        check_no("$0rec { a = 1; b = a; }");
+        check_no("$0{ a = 1; }");
+        check_no("let a = 1; in $0rec { a = a/*self*/; }");
+    }
+}
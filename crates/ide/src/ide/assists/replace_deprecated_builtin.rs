@@ -0,0 +1,70 @@
+//! Replace a global alias of a builtin with the builtin selected from `builtins`.
+//! See `deprecated_builtin` in [docs/diagnostics.md](./diagnostics.md).
+//!
+//! ```nix
+//! __mapAttrs f set
+//! ```
+//! =>
+//! ```nix
+//! builtins.mapAttrs f set
+//! ```
+use super::AssistsCtx;
+use crate::def::{AstPtr, Expr, ResolveResult};
+use crate::{DiagnosticKind, TextEdit};
+use syntax::ast::{self, AstNode};
+
+pub(super) fn replace_deprecated_builtin(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let file = ctx.frange.file_id;
+    let node = ctx.covering_node::<ast::Ref>()?;
+    let source_map = ctx.db.source_map(file);
+    let expr = source_map.expr_for_node(AstPtr::new(node.syntax()))?;
+    let module = ctx.db.module(file);
+    let Expr::Reference(text) = &module[expr] else {
+        return None;
+    };
+    let scopes = ctx.db.scopes(file);
+    let Some(ResolveResult::Builtin(name)) = scopes.resolve_name(expr, text) else {
+        return None;
+    };
+    if !text.starts_with("__")
+        || !matches!(
+            scopes.resolve_name(expr, &"builtins".into()),
+            Some(ResolveResult::Builtin("builtins"))
+        )
+    {
+        return None;
+    }
+    let range = node.syntax().text_range();
+    ctx.add_fix(
+        DiagnosticKind::DeprecatedBuiltin,
+        range,
+        "replace_deprecated_builtin",
+        format!("Replace `{text}` with `builtins.{name}`"),
+        vec![TextEdit {
+            delete: range,
+            insert: format!("builtins.{name}").into(),
+        }],
+    );
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::replace_deprecated_builtin);
+
+    #[test]
+    fn simple() {
+        check("$0__mapAttrs f { }", expect!["builtins.mapAttrs f { }"]);
+        check("[ __isAttrs$0 ]", expect!["[ builtins.isAttrs ]"]);
+    }
+
+    #[test]
+    fn not_applicable() {
+        check_no("$0mapAttrs");
+        check_no("let __mapAttrs = 1; in $0__mapAttrs");
+        check_no("let builtins = { }; in $0__mapAttrs");
+        check_no("$0builtins.mapAttrs");
+    }
+}
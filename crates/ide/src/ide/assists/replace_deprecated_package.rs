@@ -0,0 +1,107 @@
+//! Replace a renamed nixpkgs attribute with its new name.
+//!
+//! ```nix
+//! with pkgs; [ gnome3.gnome-shell pkgs.nixFlakes ]
+//! ```
+//! =>
+//! ```nix
+//! with pkgs; [ gnome.gnome-shell pkgs.nixVersions.stable ]
+//! ```
+use super::{AssistKind, AssistsCtx};
+use crate::def::{DeprecatedPackage, Expr, Literal};
+use crate::TextEdit;
+use syntax::semantic::{escape_literal_attr, is_valid_ident};
+
+pub(super) fn replace_deprecated_package(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let file = ctx.frange.file_id;
+    let module = ctx.db.module(file);
+    let source_map = ctx.db.source_map(file);
+    let aliases = ctx.db.package_aliases();
+
+    let (expr, is_ref, range) = ctx.db.deprecated_packages(file).iter().find_map(|use_| {
+        let (expr, is_ref) = match use_ {
+            DeprecatedPackage::Attr(expr) => (expr, false),
+            DeprecatedPackage::WithRef(expr) => (expr, true),
+            DeprecatedPackage::Name(_) => return None,
+        };
+        let range = source_map.node_for_expr(expr)?.text_range();
+        range
+            .contains_range(ctx.frange.range)
+            .then_some((expr, is_ref, range))
+    })?;
+    let (Expr::Literal(Literal::String(name)) | Expr::Reference(name)) = &module[expr] else {
+        return None;
+    };
+    let to = aliases.get(name)?.to.as_deref()?;
+    let mut attrs = to.split('.');
+
+    // A reference must stay a reference to the `with` environment.
+    let first = attrs.next()?;
+    let mut new_text = if is_ref {
+        let scopes = ctx.db.scopes(file);
+        let shadowed = scopes.ancestors(scopes.scope_for_expr(expr)?).any(|data| {
+            data.as_definitions()
+                .map_or(false, |defs| defs.contains_key(first))
+        });
+        if !is_valid_ident(first) || shadowed {
+            return None;
+        }
+        first.to_owned()
+    } else {
+        escape_literal_attr(first).into_owned()
+    };
+    for attr in attrs {
+        new_text += ".";
+        new_text += &escape_literal_attr(attr);
+    }
+
+    ctx.add(
+        "replace_deprecated_package",
+        format!("Replace `{name}` with `{to}`"),
+        AssistKind::QuickFix,
+        vec![TextEdit {
+            delete: range,
+            insert: new_text.into(),
+        }],
+    );
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::replace_deprecated_package);
+
+    #[test]
+    fn select() {
+        check(
+            "{ pkgs }: pkgs.gnome3$0.gnome-shell",
+            expect!["{ pkgs }: pkgs.gnome.gnome-shell"],
+        );
+        check(
+            "{ pkgs }: [ pkgs.$0nixFlakes ]",
+            expect!["{ pkgs }: [ pkgs.nixVersions.stable ]"],
+        );
+        check(
+            r#"{ pkgs }: pkgs."gnome3$0""#,
+            expect!["{ pkgs }: pkgs.gnome"],
+        );
+    }
+
+    #[test]
+    fn with() {
+        check(
+            "{ pkgs }: with pkgs; [ $0gnome3 ]",
+            expect!["{ pkgs }: with pkgs; [ gnome ]"],
+        );
+        check_no("{ pkgs }: with pkgs; let gnome = 1; in [ $0gnome3 ]");
+    }
+
+    #[test]
+    fn not_applicable() {
+        check_no("{ pkgs }: pkgs.nerdfonts$0");
+        check_no("{ pkgs }: { inherit (pkgs) gnome3$0; }");
+        check_no("{ a }: a.gnome3$0");
+    }
+}
@@ -0,0 +1,275 @@
+//! Rewrite conditionals with an empty branch to `lib.optional`, `lib.optionals` or
+//! `lib.optionalAttrs`. Names from an existing `inherit (lib)` or `with lib;` are used directly.
+//!
+//! ```nix
+//! { lib, cond }: if cond then [ 1 ] else [ ]
+//! ```
+//! =>
+//! ```nix
+//! { lib, cond }: lib.optional cond 1
+//! ```
+//!
+//! ```nix
+//! { lib }: let inherit (lib) mkIf; in if !cond then { } else { a = 1; }
+//! ```
+//! =>
+//! ```nix
+//! { lib }: let inherit (lib) mkIf optionalAttrs; in optionalAttrs cond { a = 1; }
+//! ```
+use super::add_to_let_in::{add_inherit_attr, lib_inherit_in_scope};
+use super::add_to_top_level_lambda_param::add_pat_field;
+use super::{AssistKind, AssistsCtx};
+use crate::def::{Expr, ResolveResult};
+use crate::TextEdit;
+use syntax::ast::{self, AstNode, HasBindings, UnaryOpKind};
+use syntax::SyntaxNodePtr;
+
+pub(super) fn rewrite_if_to_optional(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let if_expr = ctx.covering_node::<ast::IfThenElse>()?;
+    let cond = if_expr.condition()?;
+    let then_body = flatten_paren(if_expr.then_body()?)?;
+    let else_body = flatten_paren(if_expr.else_body()?)?;
+
+    // `if cond then <value> else <empty>`, or the swapped one with the condition negated.
+    let (cond, value, empty) = if is_empty(&else_body) && !is_empty(&then_body) {
+        (arg_text(&cond), then_body, else_body)
+    } else if is_empty(&then_body) && !is_empty(&else_body) {
+        (negate(&cond)?, else_body, then_body)
+    } else {
+        return None;
+    };
+
+    let (func, value) = match (&empty, &value) {
+        (ast::Expr::List(_), ast::Expr::List(list)) => {
+            let mut elems = list.elements();
+            match (elems.next(), elems.next()) {
+                (Some(elem), None) => ("optional", arg_text(&elem)),
+                _ => ("optionals", arg_text(&value)),
+            }
+        }
+        (ast::Expr::List(_), ast::Expr::AttrSet(_))
+        | (ast::Expr::AttrSet(_), ast::Expr::List(_)) => return None,
+        (ast::Expr::List(_), _) => ("optionals", arg_text(&value)),
+        (ast::Expr::AttrSet(_), _) => ("optionalAttrs", arg_text(&value)),
+        _ => return None,
+    };
+
+    let (func, mut edits) = lib_function(ctx, &if_expr, func)?;
+    edits.push(TextEdit {
+        delete: if_expr.syntax().text_range(),
+        insert: format!("{func} {cond} {value}").into(),
+    });
+    ctx.add(
+        "rewrite_if_to_optional",
+        format!("Rewrite to `{func}`"),
+        AssistKind::RefactorRewrite,
+        edits,
+    );
+    Some(())
+}
+
+/// How to refer to `lib.<name>` at `if_expr`, with edits needed to make it available.
+fn lib_function(
+    ctx: &AssistsCtx<'_>,
+    if_expr: &ast::IfThenElse,
+    name: &str,
+) -> Option<(String, Vec<TextEdit>)> {
+    let file = ctx.frange.file_id;
+    let module = ctx.db.module(file);
+    let source_map = ctx.db.source_map(file);
+    let scopes = ctx.db.scopes(file);
+    let expr = source_map.expr_for_node(SyntaxNodePtr::new(if_expr.syntax()))?;
+
+    let is_lib = |e| matches!(&module[e], Expr::Reference(name) if name == "lib");
+    let lib_inherit = lib_inherit_in_scope(if_expr.syntax());
+    match scopes.resolve_name(expr, &name.into()) {
+        // Shadowed, unless it comes from `inherit (lib)`.
+        Some(ResolveResult::Definition(def)) => {
+            let from_lib_inherit = lib_inherit.map_or(false, |i| {
+                source_map
+                    .nodes_for_name(def)
+                    .any(|ptr| i.syntax().text_range().contains_range(ptr.text_range()))
+            });
+            if from_lib_inherit {
+                return Some((name.into(), Vec::new()));
+            }
+        }
+        Some(ResolveResult::WithExprs(withs)) => match module[withs[0]] {
+            Expr::With(env, _) if is_lib(env) => return Some((name.into(), Vec::new())),
+            _ => {}
+        },
+        Some(ResolveResult::Builtin(_)) => return None,
+        None => {
+            if let Some(inherit) = lib_inherit {
+                return Some((name.into(), vec![add_inherit_attr(&inherit, name)?]));
+            }
+        }
+    }
+
+    let qualified = format!("lib.{name}");
+    match scopes.resolve_name(expr, &"lib".into()) {
+        Some(ResolveResult::Definition(_)) => Some((qualified, Vec::new())),
+        Some(_) => None,
+        None => {
+            let pat = ast::Lambda::cast(ctx.ast.syntax().first_child()?)?
+                .param()?
+                .pat()?;
+            Some((qualified, vec![add_pat_field(&pat, "lib")]))
+        }
+    }
+}
+
+fn flatten_paren(mut expr: ast::Expr) -> Option<ast::Expr> {
+    while let ast::Expr::Paren(paren) = expr {
+        expr = paren.expr()?;
+    }
+    Some(expr)
+}
+
+/// `[ ]` or non-recursive `{ }`.
+fn is_empty(expr: &ast::Expr) -> bool {
+    match expr {
+        ast::Expr::List(list) => list.elements().next().is_none(),
+        ast::Expr::AttrSet(set) => {
+            set.rec_token().is_none()
+                && set.let_token().is_none()
+                && set.bindings().next().is_none()
+        }
+        _ => false,
+    }
+}
+
+/// The text of `!cond`, removing an existing `!` if any.
+fn negate(cond: &ast::Expr) -> Option<String> {
+    match cond {
+        ast::Expr::UnaryOp(op) if op.op_kind() == Some(UnaryOpKind::Not) => {
+            Some(arg_text(&flatten_paren(op.arg()?)?))
+        }
+        _ if is_atom(cond) => Some(format!("(!{})", text(cond))),
+        _ => Some(format!("(!({}))", text(cond))),
+    }
+}
+
+/// The text of `expr` as a function argument.
+fn arg_text(expr: &ast::Expr) -> String {
+    if is_atom(expr) {
+        text(expr)
+    } else {
+        format!("({})", text(expr))
+    }
+}
+
+/// Whether `expr` needs no parentheses as a function argument.
+pub(super) fn is_atom(expr: &ast::Expr) -> bool {
+    matches!(
+        expr,
+        ast::Expr::List(_)
+            | ast::Expr::AttrSet(_)
+            | ast::Expr::Select(_)
+            | ast::Expr::String(_)
+            | ast::Expr::IndentString(_)
+            | ast::Expr::Literal(_)
+            | ast::Expr::PathInterpolation(_)
+            | ast::Expr::Ref(_)
+            | ast::Expr::Paren(_)
+    )
+}
+
+fn text(expr: &ast::Expr) -> String {
+    let text = expr.syntax().to_string();
+    text.trim_end().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::rewrite_if_to_optional);
+
+    #[test]
+    fn optional() {
+        check(
+            "{ lib, cond }: $0if cond then [ 1 ] else [ ]",
+            expect!["{ lib, cond }: lib.optional cond 1"],
+        );
+        check(
+            "{ lib, a }: if a == 1 $0then [ (f a) ] else ([ ])",
+            expect!["{ lib, a }: lib.optional (a == 1) (f a)"],
+        );
+        check(
+            "{ lib, a }: $0if !a then [ ] else [ x.y ]",
+            expect!["{ lib, a }: lib.optional a x.y"],
+        );
+        check(
+            "{ lib, a }: $0if a.b then [ ] else [ 1 ]",
+            expect!["{ lib, a }: lib.optional (!a.b) 1"],
+        );
+        check(
+            "{ lib, a }: $0if a && b then [ ] else [ 1 ]",
+            expect!["{ lib, a }: lib.optional (!(a && b)) 1"],
+        );
+    }
+
+    #[test]
+    fn optionals() {
+        check(
+            "{ lib }: $0if a then [ 1 2 ] else [ ]",
+            expect!["{ lib }: lib.optionals a [ 1 2 ]"],
+        );
+        check(
+            "{ lib }: $0if a then xs ++ ys else [ ]",
+            expect!["{ lib }: lib.optionals a (xs ++ ys)"],
+        );
+    }
+
+    #[test]
+    fn optional_attrs() {
+        check(
+            "{ lib }: $0if a then { b = 1; } else { }",
+            expect!["{ lib }: lib.optionalAttrs a { b = 1; }"],
+        );
+        check(
+            "{ lib }: $0if a then f x else { }",
+            expect!["{ lib }: lib.optionalAttrs a (f x)"],
+        );
+    }
+
+    #[test]
+    fn reference() {
+        // Add `lib` to the top-level lambda.
+        check(
+            "{ a }: $0if a then [ 1 ] else [ ]",
+            expect!["{ a, lib }: lib.optional a 1"],
+        );
+        check(
+            "{ lib }: with lib; $0if a then [ 1 ] else [ ]",
+            expect!["{ lib }: with lib; optional a 1"],
+        );
+        check(
+            "{ lib }: let inherit (lib) optional; in $0if a then [ 1 ] else [ ]",
+            expect!["{ lib }: let inherit (lib) optional; in optional a 1"],
+        );
+        check(
+            "{ lib }: let inherit (lib) mkIf; in $0if a then { } else { b = 1; }",
+            expect![
+                "{ lib }: let inherit (lib) mkIf optionalAttrs; in optionalAttrs (!a) { b = 1; }"
+            ],
+        );
+        // Shadowed.
+        check(
+            "{ lib, optional }: $0if a then [ 1 ] else [ ]",
+            expect!["{ lib, optional }: lib.optional a 1"],
+        );
+
+        check_no("let optional = 1; in $0if a then [ 1 ] else [ ]");
+        check_no("$0if a then [ 1 ] else [ ]");
+    }
+
+    #[test]
+    fn not_applicable() {
+        check_no("{ lib }: $0if a then [ 1 ] else [ 2 ]");
+        check_no("{ lib }: $0if a then [ ] else [ ]");
+        check_no("{ lib }: $0if a then { b = 1; } else rec { }");
+        check_no("{ lib }: $0if a then [ 1 ] else { }");
+    }
+}
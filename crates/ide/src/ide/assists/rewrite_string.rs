@@ -6,7 +6,7 @@ use std::convert::Infallible;
 use std::fmt::Write;
 
 use super::{AssistKind, AssistsCtx};
-use crate::TextEdit;
+use crate::{DiagnosticKind, TextEdit};
 use syntax::ast::{self, AstNode};
 use syntax::semantic::{
     is_valid_ident, strip_indent, unescape_string, unescape_string_escape, unescape_string_literal,
@@ -28,10 +28,11 @@ pub(super) fn rewrite_uri_to_string(ctx: &mut AssistsCtx<'_>) -> Option<()> {
         .filter(|lit| lit.kind() == Some(ast::LiteralKind::Uri))?
         .token()?;
 
-    ctx.add(
+    ctx.add_fix(
+        DiagnosticKind::UriLiteral,
+        token.text_range(),
         "rewrite_uri_to_string",
         "Rewrite the URI literal to a double quoted string",
-        AssistKind::QuickFix,
         vec![TextEdit {
             delete: token.text_range(),
             insert: format!(r#""{}""#, token.text()).into(),
@@ -139,59 +140,37 @@ pub(super) fn rewrite_string_to_indented(ctx: &mut AssistsCtx<'_>) -> Option<()>
 
     let mut text = format!("''\n{indent}");
     let mut line_start = true;
+    // Concatenate all contiguous fragments and escape them in a single run, since escapes
+    // depend on the following characters. Eg. `'` + `'` and `$` + `{`.
+    let mut run = String::new();
+    let mut has_dynamic = false;
     let _ = unescape_string::<Infallible>(&node, |part| {
         match part {
-            UnescapedStringPart::Fragment(frag) => {
-                if frag.is_empty() {
-                    return Ok(());
-                }
-
-                let mut chars = frag.chars();
-                while let Some(x) = chars.next() {
-                    match x {
-                        '$' => match chars.next() {
-                            Some('{') => text.push_str("''${"),
-                            Some(y) => {
-                                text.push('$');
-                                text.push(y);
-                            }
-                            // It's impossible to know from this context whether the next
-                            // character is '{' or not, so we assume it is just to be safe
-                            None => text.push_str("''$"),
-                        },
-                        '\'' => match chars.next() {
-                            Some('\'') => text.push_str("'''"),
-                            Some(y) => {
-                                text.push('\'');
-                                text.push(y);
-                            }
-                            None => text.push('\''),
-                        },
-                        '\n' => {
-                            if line_start {
-                                text.insert(text.len() - indent.len(), '\n');
-                            } else {
-                                text.push('\n');
-                                text.push_str(&indent);
-                                line_start = true;
-                                continue;
-                            }
-                        }
-                        _ => text.push(x),
-                    }
-                    line_start = false;
-                }
-            }
-
+            UnescapedStringPart::Fragment(frag) => run += frag,
             UnescapedStringPart::Dynamic(dyna) => {
+                has_dynamic = true;
+                push_indented_fragment(&mut text, &indent, &mut line_start, &run, true);
+                run.clear();
                 line_start = false;
                 text.push_str(&dyna.syntax().to_string());
             }
         };
-
         Ok(())
     });
-    if line_start {
+    // Spaces of the last line are stripped, unless the last one is escaped.
+    let last_line = match run.rsplit_once('\n') {
+        Some((_, last_line)) => last_line,
+        None if !has_dynamic => &run,
+        None => "",
+    };
+    let keep_spaces = !last_line.is_empty() && last_line.bytes().all(|b| b == b' ');
+    if keep_spaces {
+        run.pop();
+    }
+    push_indented_fragment(&mut text, &indent, &mut line_start, &run, false);
+    if keep_spaces {
+        text.push_str("''\\ ");
+    } else if line_start {
         text.truncate(text.len() - 2);
     }
     text.push_str("''");
@@ -209,6 +188,51 @@ pub(super) fn rewrite_string_to_indented(ctx: &mut AssistsCtx<'_>) -> Option<()>
     Some(())
 }
 
+/// Escape an unescaped `frag` of a double quoted string into an indented string.
+fn push_indented_fragment(
+    text: &mut String,
+    indent: &str,
+    line_start: &mut bool,
+    frag: &str,
+    next_is_dynamic: bool,
+) {
+    let mut chars = frag.chars().peekable();
+    while let Some(x) = chars.next() {
+        match (x, chars.peek()) {
+            ('$', Some('{')) => {
+                chars.next();
+                text.push_str("''${");
+            }
+            // The second `$` of `$$` loses the special meaning.
+            ('$', Some('$')) => {
+                chars.next();
+                text.push_str("$$");
+            }
+            // `$${` is literal.
+            ('$', None) if next_is_dynamic => text.push_str("''$"),
+            ('\'', Some('\'')) => {
+                chars.next();
+                text.push_str("'''");
+            }
+            // It would become `'''` with the closing quotes.
+            ('\'', None) if !next_is_dynamic => text.push_str("''\\'"),
+            ('\r', _) => text.push_str("''\\r"),
+            ('\n', _) => {
+                if *line_start {
+                    text.insert(text.len() - indent.len(), '\n');
+                } else {
+                    text.push('\n');
+                    text.push_str(indent);
+                    *line_start = true;
+                }
+                continue;
+            }
+            _ => text.push(x),
+        }
+        *line_start = false;
+    }
+}
+
 /// Indented strings -> Double quoted strings
 /// ```nix
 /// ''
@@ -237,7 +261,15 @@ pub(super) fn rewrite_indented_to_string(ctx: &mut AssistsCtx<'_>) -> Option<()>
                 last_frag += unescape_string_escape(esc.text());
             }
             StrippedStringPart::Dynamic(dyna) => {
+                // `$${` is literal.
+                let escape_dollar = last_frag.ends_with('$');
+                if escape_dollar {
+                    last_frag.pop();
+                }
                 write!(ret, "{}", EscapeStringFragment(&last_frag)).unwrap();
+                if escape_dollar {
+                    ret += "\\$";
+                }
                 last_frag.clear();
                 ret += &dyna.syntax().to_string();
             }
@@ -335,6 +367,36 @@ mod tests {
             ],
         );
 
+        // Escapes across fragments and interpolations.
+        check(
+            r#"$0"'\''""#,
+            expect![[r#"
+            ''
+              '''''\'''
+        "#]],
+        );
+        check(r#"$0"\$${foo}""#, expect!["''\n  ''$${foo}''\n"]);
+        check(r#"$0"$$${foo}""#, expect!["''\n  $$${foo}''\n"]);
+        check(r#"$0"foo'""#, expect!["''\n  foo''\\'''\n"]);
+        check(r#"$0"'${foo}""#, expect!["''\n  '${foo}''\n"]);
+        check(r#"$0"a\r\tb""#, expect!["''\n  a''\\r\tb''\n"]);
+        // Spaces of the last line.
+        check(
+            r#"$0"foo\n  ""#,
+            expect![[r#"
+            ''
+              foo
+               ''\ ''
+        "#]],
+        );
+        check(
+            r#"$0"${foo}  ""#,
+            expect![[r#"
+            ''
+              ${foo}  ''
+        "#]],
+        );
+
         check_no(r#"{ $0"foo" = bar; }"#);
     }
 
@@ -351,5 +413,7 @@ mod tests {
         // See comments in `rewrite_indented_to_string`.
         check(r"$0'' ''${ ''", expect![[r#""\${ ""#]]);
         check(r"$0'' ''$ ''", expect![[r#""$ ""#]]);
+        check(r"$0'' ''$${foo} ''", expect![[r#""\$${foo} ""#]]);
+        check("$0''\n  a\tb\n''", expect![[r#""a\tb\n""#]]);
     }
 }
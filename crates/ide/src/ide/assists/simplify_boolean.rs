@@ -0,0 +1,87 @@
+//! Simplify comparisons with boolean literals, double negations and `if c then true else false`.
+//! See `redundant_boolean` in [docs/diagnostics.md](./diagnostics.md).
+//!
+//! ```nix
+//! [ (x == false) (if y then true else false) ]
+//! ```
+//! =>
+//! ```nix
+//! [ (!x) y ]
+//! ```
+use super::super::diagnostics;
+use super::AssistsCtx;
+use crate::{DiagnosticKind, TextEdit};
+use syntax::ast::{self, AstNode};
+
+pub(super) fn simplify_boolean(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let file = ctx.frange.file_id;
+    let (expr, simplified) = ctx
+        .covering_node::<ast::Expr>()?
+        .syntax()
+        .ancestors()
+        .filter_map(ast::Expr::cast)
+        .find_map(|expr| {
+            Some((
+                expr.clone(),
+                diagnostics::simplify_boolean(ctx.db, file, &expr)?,
+            ))
+        })?;
+    let range = expr.syntax().text_range();
+    ctx.add_fix(
+        DiagnosticKind::RedundantBoolean,
+        range,
+        "simplify_boolean",
+        format!("Simplify to `{simplified}`"),
+        vec![TextEdit {
+            delete: range,
+            insert: simplified.into(),
+        }],
+    );
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    define_check_assist!(super::simplify_boolean);
+
+    #[test]
+    fn comparison() {
+        check("{ x }: x =$0= true", expect!["{ x }: x"]);
+        check("{ x }: $0false != x", expect!["{ x }: x"]);
+        check("{ x }: f x $0== false", expect!["{ x }: !(f x)"]);
+        check("{ x }: a + b $0!= true", expect!["{ x }: !(a + b)"]);
+        check("{ x }: y && x == $0false", expect!["{ x }: y && !x"]);
+    }
+
+    #[test]
+    fn double_negation() {
+        check("{ x }: $0!(!x)", expect!["{ x }: x"]);
+        check("{ x }: !$0!(a || b)", expect!["{ x }: (a || b)"]);
+    }
+
+    #[test]
+    fn if_then_else() {
+        check(
+            "{ x }: if x.y $0then true else false",
+            expect!["{ x }: x.y"],
+        );
+        check(
+            "{ x }: if a && b then false else $0true",
+            expect!["{ x }: !(a && b)"],
+        );
+        check(
+            "{ x }: if x == false then true else $0false",
+            expect!["{ x }: x == false"],
+        );
+    }
+
+    #[test]
+    fn not_applicable() {
+        check_no("{ x }: x =$0= 1");
+        check_no("{ x }: $0!x");
+        check_no("{ x }: if x then true else $0true");
+        check_no("let true = 1; in { x }: x $0== true");
+    }
+}
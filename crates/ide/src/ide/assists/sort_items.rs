@@ -0,0 +1,302 @@
+//! Sort bindings of an attrset alphabetically, or elements of a list of simple items.
+//! Comments before an item, and the one following it on the same line, move with the item.
+//!
+//! ```nix
+//! {
+//!   # The editor.
+//!   vim = 1;
+//!   git = 2; # VCS.
+//! }
+//! ```
+//! =>
+//! ```nix
+//! {
+//!   git = 2; # VCS.
+//!   # The editor.
+//!   vim = 1;
+//! }
+//! ```
+use std::cmp::Ordering;
+
+use super::{AssistKind, AssistsCtx};
+use crate::TextEdit;
+use syntax::ast::{self, AstNode, HasBindings, HasStringParts};
+use syntax::{SyntaxKind, SyntaxNode, SyntaxToken, TextRange, TextSize};
+
+pub(super) fn sort_attrset_bindings(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let ast::Expr::AttrSet(set) = ctx.covering_node::<ast::Expr>()? else {
+        return None;
+    };
+    // Legacy `let { }`.
+    if set.let_token().is_some() {
+        return None;
+    }
+    let items = set
+        .bindings()
+        .map(|binding| {
+            // `inherit`s go first, in their original order.
+            let key = match &binding {
+                ast::Binding::Inherit(_) => None,
+                ast::Binding::AttrpathValue(path_value) => {
+                    Some(path_value.attrpath()?.syntax().to_string())
+                }
+            };
+            Some((binding.syntax().clone(), key))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let edit = sort_items(&set.r_curly_token()?, items)?;
+    ctx.add(
+        "sort_attrset_bindings",
+        "Sort attrset bindings alphabetically",
+        AssistKind::RefactorRewrite,
+        vec![edit],
+    );
+    Some(())
+}
+
+pub(super) fn sort_list_elements(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let expr = ctx.covering_node::<ast::Expr>()?;
+    let list = match expr {
+        ast::Expr::List(list) => list,
+        expr => ast::List::cast(expr.syntax().parent()?)?,
+    };
+    let items = list
+        .elements()
+        .map(|elem| {
+            is_simple(&elem).then(|| (elem.syntax().clone(), Some(elem.syntax().to_string())))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let edit = sort_items(&list.r_brack_token()?, items)?;
+    ctx.add(
+        "sort_list_elements",
+        "Sort list elements",
+        AssistKind::RefactorRewrite,
+        vec![edit],
+    );
+    Some(())
+}
+
+/// Literals, identifiers, strings without interpolations, and selections like `pkgs.git`.
+fn is_simple(expr: &ast::Expr) -> bool {
+    match expr {
+        ast::Expr::Literal(_) | ast::Expr::Ref(_) => true,
+        ast::Expr::String(s) => s
+            .string_parts()
+            .all(|part| !matches!(part, ast::StringPart::Dynamic(_))),
+        ast::Expr::Select(sel) => {
+            sel.or_token().is_none()
+                && sel.set().map_or(false, |set| is_simple(&set))
+                && sel.attrpath().map_or(false, |path| {
+                    path.attrs().all(|attr| matches!(attr, ast::Attr::Name(_)))
+                })
+        }
+        _ => false,
+    }
+}
+
+/// Reorder `items` with their attached comments by keys, keeping the whitespaces between them.
+/// Items without keys go first. Returns `None` if there is nothing to sort.
+fn sort_items(close: &SyntaxToken, items: Vec<(SyntaxNode, Option<String>)>) -> Option<TextEdit> {
+    if items.len() < 2 {
+        return None;
+    }
+    let close_start = close.text_range().start();
+
+    // Ranges of items covering their attached comments.
+    let mut ranges = Vec::with_capacity(items.len());
+    let mut prev_end = None;
+    for (node, _) in &items {
+        let start = leading_comments_start(node, prev_end);
+        let end = trailing_comment_end(node);
+        ranges.push(TextRange::new(start, end));
+        prev_end = Some(end);
+    }
+
+    let mut order = (0..items.len()).collect::<Vec<_>>();
+    order.sort_by(|&i, &j| match (&items[i].1, &items[j].1) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Less,
+        (Some(_), None) => Ordering::Greater,
+        (Some(a), Some(b)) => a.to_lowercase().cmp(&b.to_lowercase()).then(a.cmp(b)),
+    });
+    if order.iter().enumerate().all(|(pos, &i)| pos == i) {
+        return None;
+    }
+
+    let root = items[0].0.ancestors().last()?;
+    let src = root.to_string();
+    let text = |range: TextRange| &src[usize::from(range.start())..usize::from(range.end())];
+    let full_range = TextRange::new(ranges[0].start(), ranges[ranges.len() - 1].end());
+    let mut insert = String::new();
+    for (pos, &i) in order.iter().enumerate() {
+        let chunk = text(ranges[i]);
+        insert += chunk;
+        let gap_end = ranges.get(pos + 1).map_or(close_start, |r| r.start());
+        let gap = text(TextRange::new(ranges[pos].end(), gap_end));
+        // A line comment at the end would comment out what follows on the same line.
+        if ends_with_line_comment(&root, ranges[i]) && !gap.contains('\n') {
+            return None;
+        }
+        if pos + 1 < order.len() {
+            insert += gap;
+        }
+    }
+    Some(TextEdit {
+        delete: full_range,
+        insert: insert.into(),
+    })
+}
+
+/// The start of comments on lines right before `node`, after `prev_end` of the previous item.
+/// Comments separated by an empty line are not attached.
+fn leading_comments_start(node: &SyntaxNode, prev_end: Option<TextSize>) -> TextSize {
+    let first = significant_tokens(node).next();
+    let mut start = first
+        .as_ref()
+        .map_or(node.text_range().start(), |tok| tok.text_range().start());
+    let mut tok = first.and_then(|tok| tok.prev_token());
+    while let Some(t) = tok {
+        if prev_end.map_or(false, |end| t.text_range().start() < end) {
+            break;
+        }
+        match t.kind() {
+            SyntaxKind::SPACE if t.text().matches('\n').count() >= 2 => break,
+            SyntaxKind::SPACE => {}
+            // Only comments starting their own lines.
+            SyntaxKind::COMMENT
+                if t.prev_token().map_or(true, |prev| {
+                    prev.kind() == SyntaxKind::SPACE && prev.text().contains('\n')
+                }) =>
+            {
+                start = t.text_range().start();
+            }
+            _ => break,
+        }
+        tok = t.prev_token();
+    }
+    start
+}
+
+/// The end of a comment following `node` on the same line, or the end of `node`.
+fn trailing_comment_end(node: &SyntaxNode) -> TextSize {
+    let last = significant_tokens(node).last();
+    let end = last
+        .as_ref()
+        .map_or(node.text_range().end(), |tok| tok.text_range().end());
+    let mut tok = last.and_then(|tok| tok.next_token());
+    while let Some(t) = tok {
+        match t.kind() {
+            SyntaxKind::SPACE if !t.text().contains('\n') => {}
+            SyntaxKind::COMMENT => return t.text_range().end(),
+            _ => break,
+        }
+        tok = t.next_token();
+    }
+    end
+}
+
+/// Non-trivia tokens of `node`. Trailing spaces are sometimes inside nodes, like `ATTR_PATH`.
+fn significant_tokens(node: &SyntaxNode) -> impl Iterator<Item = SyntaxToken> {
+    node.descendants_with_tokens()
+        .filter_map(|elem| elem.into_token())
+        .filter(|tok| !tok.kind().is_trivia())
+}
+
+fn ends_with_line_comment(root: &SyntaxNode, range: TextRange) -> bool {
+    root.token_at_offset(range.end())
+        .left_biased()
+        .map_or(false, |tok| {
+            tok.kind() == SyntaxKind::COMMENT && tok.text().starts_with('#')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    #[test]
+    fn attrset() {
+        define_check_assist!(super::sort_attrset_bindings);
+
+        check("$0{ b = 1; a = 2; }", expect!["{ a = 2; b = 1; }"]);
+        check(
+            "{ c.d = 1; $0inherit (x) y; A = 2; a.b = 3; inherit z; }",
+            expect!["{ inherit (x) y; inherit z; A = 2; a.b = 3; c.d = 1; }"],
+        );
+        check(
+            "
+{$0
+  # The editor.
+  # Really.
+  vim = 1;
+  git = 2; # VCS.
+
+  # Detached.
+
+  htop = 3;
+}",
+            expect![[r#"
+                {
+                  git = 2; # VCS.
+                  htop = 3;
+
+                  # Detached.
+
+                  # The editor.
+                  # Really.
+                  vim = 1;
+                }
+            "#]],
+        );
+        check(
+            "rec { b = 1; a = { d = 1; c = 2; }; }$0",
+            expect!["rec { a = { d = 1; c = 2; }; b = 1; }"],
+        );
+        // The innermost attrset.
+        check(
+            "{ b = 1; a = { d = 1; $0c = 2; }; }",
+            expect!["{ b = 1; a = { c = 2; d = 1; }; }"],
+        );
+
+        check_no("{ a = 1; b = 2;$0 }");
+        check_no("{ a = 1; }$0");
+        check_no("{ b = 1; a = $0x; }");
+        check_no("let { b = 1; a = 2; body = a; $0}");
+        // Trailing line comment before `}` on the same line.
+        check_no("{$0 b = 1; # B.\n a = 2; }");
+    }
+
+    #[test]
+    fn list() {
+        define_check_assist!(super::sort_list_elements);
+
+        check(
+            "with pkgs; [ vim $0git htop ]",
+            expect!["with pkgs; [ git htop vim ]"],
+        );
+        check(
+            r#"[ "b" pkgs.gcc 1 ./a.nix ]$0"#,
+            expect![r#"[ "b" ./a.nix 1 pkgs.gcc ]"#],
+        );
+        check(
+            "
+$0[
+  vim # Editor.
+  # VCS.
+  git
+]",
+            expect![[r#"
+[
+  # VCS.
+  git
+  vim # Editor.
+]
+"#]],
+        );
+
+        check_no("[ a b c$0 ]");
+        check_no("[ b (a) $0]");
+        check_no(r#"[ "${b}" a $0]"#);
+        check_no("[ b a # A.\n$0]");
+    }
+}
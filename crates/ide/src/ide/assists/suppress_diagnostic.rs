@@ -0,0 +1,184 @@
+//! Suppress a diagnostic by inserting a comment on the previous line, or at the
+//! beginning of the file.
+//!
+//! ```nix
+//! let
+//!   foo = 1;
+//! in 0
+//! ```
+//! =>
+//! ```nix
+//! let
+//!   # nil:ignore unused_binding
+//!   foo = 1;
+//! in 0
+//! ```
+use super::{AssistKind, AssistsCtx};
+use crate::ide::suppression::{comment_text, line_range, parse_comment, SuppressionScope};
+use crate::TextEdit;
+use syntax::ast::AstNode;
+use syntax::{SyntaxKind, TextRange, TextSize};
+
+pub(super) fn suppress_diagnostic(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let src = ctx.db.file_content(ctx.frange.file_id);
+    for (code, pos) in diagnostic_codes(ctx) {
+        let line = line_range(&src, pos);
+
+        // The next line of a multiline string or comment cannot hold comments.
+        let in_literal = ctx.ast.syntax().token_at_offset(line.start()).any(|tok| {
+            matches!(
+                tok.parent().map(|node| node.kind()),
+                Some(SyntaxKind::STRING | SyntaxKind::INDENT_STRING)
+            ) || (tok.kind() == SyntaxKind::COMMENT && tok.text_range().start() < line.start())
+        });
+        if in_literal {
+            continue;
+        }
+
+        // Extend the suppression comment on the previous line, if any.
+        let prev_line = (line.start() > TextSize::from(0))
+            .then(|| line_range(&src, line.start() - TextSize::from(1)));
+        let prev_comment = prev_line.and_then(|prev| {
+            let text = src[prev].trim_end();
+            let (SuppressionScope::Line, _) = parse_comment(text.trim_start())? else {
+                return None;
+            };
+            Some(prev.start() + TextSize::of(text))
+        });
+        let edit = match prev_comment {
+            Some(end) => TextEdit {
+                delete: TextRange::empty(end),
+                insert: format!(" {code}").into(),
+            },
+            None => {
+                let indent_len = src[line].find(|c: char| c != ' ' && c != '\t').unwrap_or(0);
+                let indent = &src[usize::from(line.start())..][..indent_len];
+                let comment = comment_text(SuppressionScope::Line, code);
+                TextEdit {
+                    delete: TextRange::empty(line.start()),
+                    insert: format!("{indent}{comment}\n").into(),
+                }
+            }
+        };
+        ctx.add(
+            "suppress_diagnostic",
+            format!("Suppress `{code}` on this line"),
+            AssistKind::QuickFix,
+            vec![edit],
+        );
+    }
+    Some(())
+}
+
+pub(super) fn suppress_diagnostic_in_file(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let src = ctx.db.file_content(ctx.frange.file_id);
+    for (code, _) in diagnostic_codes(ctx) {
+        // Extend the file suppression comment on the first line, if any.
+        let first_line = line_range(&src, TextSize::from(0));
+        let text = src[first_line].trim_end();
+        let edit = match parse_comment(text) {
+            Some((SuppressionScope::File, _)) => TextEdit {
+                delete: TextRange::empty(TextSize::of(text)),
+                insert: format!(" {code}").into(),
+            },
+            _ => TextEdit {
+                delete: TextRange::empty(TextSize::from(0)),
+                insert: format!("{}\n", comment_text(SuppressionScope::File, code)).into(),
+            },
+        };
+        ctx.add(
+            "suppress_diagnostic_in_file",
+            format!("Suppress `{code}` in this file"),
+            AssistKind::QuickFix,
+            vec![edit],
+        );
+    }
+    Some(())
+}
+
+/// Codes and starts of diagnostics touching the selected range, one for each code.
+fn diagnostic_codes(ctx: &AssistsCtx<'_>) -> Vec<(&'static str, TextSize)> {
+    let mut codes = Vec::<(&str, TextSize)>::new();
+    for diag in ctx.diagnostics {
+        if diag.range.intersect(ctx.frange.range).is_some()
+            && codes.iter().all(|&(code, _)| code != diag.code())
+        {
+            codes.push((diag.code(), diag.range.start()));
+        }
+    }
+    codes
+}
+
+#[cfg(test)]
+mod tests {
+    use expect_test::expect;
+
+    mod line {
+        use super::*;
+        define_check_assist!(super::super::suppress_diagnostic);
+
+        #[test]
+        fn simple() {
+            check(
+                "
+let
+  $0a = 1;
+in 0
+",
+                expect![[r#"
+                    let
+                      # nil:ignore unused_binding
+                      a = 1;
+                    in 0
+                "#]],
+            );
+        }
+
+        #[test]
+        fn extend() {
+            check(
+                "
+let
+  # nil:ignore unused_with
+  $0a = with 1; 1;
+in 0
+",
+                expect![[r#"
+                    let
+                      # nil:ignore unused_with unused_binding
+                      a = with 1; 1;
+                    in 0
+                "#]],
+            );
+        }
+
+        #[test]
+        fn not_applicable() {
+            check_no("let $0a = 1; in a");
+            check_no("let a = ''\n${$0b}''; in a");
+        }
+    }
+
+    mod file {
+        use super::*;
+        define_check_assist!(super::super::suppress_diagnostic_in_file);
+
+        #[test]
+        fn simple() {
+            check(
+                "let $0a = 1; in 0",
+                expect![[r#"
+                    # nil:ignore-file unused_binding
+                    let a = 1; in 0
+                "#]],
+            );
+            check(
+                "# nil:ignore-file unused_with\nlet $0a = with 1; 1; in 0",
+                expect![[r#"
+                    # nil:ignore-file unused_with unused_binding
+                    let a = with 1; 1; in 0
+                "#]],
+            );
+        }
+    }
+}
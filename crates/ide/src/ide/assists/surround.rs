@@ -0,0 +1,225 @@
+//! Surround the selected expression with `let-in`, or a lambda taking names it uses from the
+//! enclosing scope.
+//!
+//! ```nix
+//! { a = 1; b = a + 1; }
+//! #            ^^^^^ selected
+//! ```
+//! =>
+//! ```nix
+//! { a = 1; b = let name = throw "TODO"; in a + 1; }
+//! ```
+//! or
+//! ```nix
+//! { a = 1; b = { a }: a + 1; }
+//! ```
+use super::{escape_snippet, AssistKind, AssistsCtx};
+use crate::def::ResolveResult;
+use crate::TextEdit;
+use syntax::ast::{self, AstNode};
+use syntax::{SyntaxKind, TextRange, TextSize};
+
+const PLACEHOLDER_NAME: &str = "name";
+const PLACEHOLDER_VALUE: &str = "throw \"TODO\"";
+
+pub(super) fn surround_with_let_in(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let expr = ctx.selected_expr()?;
+    let (range, text) = trimmed(&expr);
+    let text = &*text;
+    let (open, close) = parens_for(&expr);
+
+    let insert = format!("{open}let {PLACEHOLDER_NAME} = {PLACEHOLDER_VALUE}; in {text}{close}");
+    let snippet = format!(
+        "{open}let ${{1:{PLACEHOLDER_NAME}}} = ${{2:{}}}; in {}{close}",
+        escape_snippet(PLACEHOLDER_VALUE),
+        escape_snippet(text),
+    );
+    ctx.add_snippet(
+        "surround_with_let_in",
+        "Surround with `let ... in`",
+        AssistKind::RefactorRewrite,
+        vec![TextEdit {
+            delete: range,
+            insert: insert.into(),
+        }],
+        vec![TextEdit {
+            delete: range,
+            insert: snippet.into(),
+        }],
+    );
+    Some(())
+}
+
+pub(super) fn surround_with_lambda(ctx: &mut AssistsCtx<'_>) -> Option<()> {
+    let expr = ctx.selected_expr()?;
+    let (range, text) = trimmed(&expr);
+    let text = &*text;
+    let (open, close) = parens_for(&expr);
+
+    // Names used inside but defined outside the selection, in the order of occurrence.
+    let file = ctx.frange.file_id;
+    let module = ctx.db.module(file);
+    let source_map = ctx.db.source_map(file);
+    let nameres = ctx.db.name_resolution(file);
+    // Including names in `inherit`s, which are not `Ref`s in the syntax tree.
+    let mut refs = nameres
+        .iter()
+        .filter_map(|(e, res)| {
+            let &ResolveResult::Definition(name) = res else {
+                return None;
+            };
+            let ref_range = source_map.node_for_expr(e)?.text_range();
+            let is_outside = source_map
+                .nodes_for_name(name)
+                .all(|ptr| !range.contains_range(ptr.text_range()));
+            (range.contains_range(ref_range) && is_outside)
+                .then(|| (ref_range.start(), &*module[name].text))
+        })
+        .collect::<Vec<_>>();
+    refs.sort();
+    let mut params = Vec::new();
+    for (_, text) in refs {
+        if !params.contains(&text) {
+            params.push(text);
+        }
+    }
+
+    let params = params.join(", ");
+    let (insert, snippet) = if params.is_empty() {
+        (
+            format!("{open}{{ }}: {text}{close}"),
+            format!("{open}{{ $0 \\}}: {}{close}", escape_snippet(text)),
+        )
+    } else {
+        (
+            format!("{open}{{ {params} }}: {text}{close}"),
+            format!("{open}{{ {params}$0 \\}}: {}{close}", escape_snippet(text)),
+        )
+    };
+    ctx.add_snippet(
+        "surround_with_lambda",
+        "Wrap in a function taking `{ ... }:`",
+        AssistKind::RefactorRewrite,
+        vec![TextEdit {
+            delete: range,
+            insert: insert.into(),
+        }],
+        vec![TextEdit {
+            delete: range,
+            insert: snippet.into(),
+        }],
+    );
+    Some(())
+}
+
+/// The range and text of `expr` without trailing spaces.
+fn trimmed(expr: &ast::Expr) -> (TextRange, String) {
+    let mut text = expr.syntax().to_string();
+    text.truncate(text.trim_end().len());
+    (
+        TextRange::at(expr.syntax().text_range().start(), TextSize::of(&*text)),
+        text,
+    )
+}
+
+/// `let-in` and lambdas extend as far as possible, so they need parentheses except in
+/// positions which also extend to the end, like the body of another `let-in`.
+fn parens_for(expr: &ast::Expr) -> (&'static str, &'static str) {
+    let needs_parens = expr.syntax().parent().map_or(false, |parent| {
+        !matches!(
+            parent.kind(),
+            SyntaxKind::SOURCE_FILE
+                | SyntaxKind::PAREN
+                | SyntaxKind::DYNAMIC
+                | SyntaxKind::ATTR_PATH_VALUE
+                | SyntaxKind::PAT_FIELD
+                | SyntaxKind::LET_IN
+                | SyntaxKind::LAMBDA
+                | SyntaxKind::WITH
+                | SyntaxKind::ASSERT
+                | SyntaxKind::IF_THEN_ELSE
+        )
+    });
+    if needs_parens {
+        ("(", ")")
+    } else {
+        ("", "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::AssistsCtx;
+    use crate::tests::TestDB;
+    use crate::SourceDatabase;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check_snippet(handler: fn(&mut AssistsCtx) -> Option<()>, fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let frange = f.unwrap_single_range_marker();
+        let mut ctx = AssistsCtx::new(&db, frange, &[]);
+        handler(&mut ctx);
+        let assist = ctx.assists.pop().expect("Not applicable");
+        let mut src = db.file_content(frange.file_id).to_string();
+        for edit in assist.snippet_edits.unwrap().content_edits[&frange.file_id]
+            .iter()
+            .rev()
+        {
+            edit.apply(&mut src);
+        }
+        expect.assert_eq(&src);
+    }
+
+    #[test]
+    fn let_in() {
+        define_check_assist!(super::surround_with_let_in);
+
+        check(
+            "{ a = 1; b = $0a + 1$1; }",
+            expect![[r#"{ a = 1; b = let name = throw "TODO"; in a + 1; }"#]],
+        );
+        check(
+            "f $0x $1y",
+            expect![[r#"f (let name = throw "TODO"; in x) y"#]],
+        );
+        check(
+            "[ $0 { a = 1; } $1]",
+            expect![[r#"[  (let name = throw "TODO"; in { a = 1; }) ]"#]],
+        );
+        check_snippet(
+            super::surround_with_let_in,
+            r#"$0"${a}"$1"#,
+            expect![[r#"let ${1:name} = ${2:throw "TODO"}; in "\${a\}""#]],
+        );
+
+        check_no("$0a + 1");
+        check_no("$0a +$1 1");
+        check_no("{ a = $01; b$1 = 2; }");
+    }
+
+    #[test]
+    fn lambda() {
+        define_check_assist!(super::surround_with_lambda);
+
+        check(
+            "let a = 1; b = 2; in $0a + b + a + builtins.c + (x: x)$1",
+            expect!["let a = 1; b = 2; in { a, b }: a + b + a + builtins.c + (x: x)"],
+        );
+        check(
+            "map ($0let a = 1; in a$1) xs",
+            expect!["map ({ }: let a = 1; in a) xs"],
+        );
+        check(
+            "{ x }: $0[ x ]$1 ++ [ ]",
+            expect!["{ x }: ({ x }: [ x ]) ++ [ ]"],
+        );
+        check_snippet(
+            super::surround_with_lambda,
+            "{ x }: $0{ inherit x; }$1",
+            expect![[r#"{ x }: { x$0 \}: { inherit x; \}"#]],
+        );
+
+        check_no("$0{ x }: x");
+    }
+}
@@ -0,0 +1,203 @@
+use crate::def::{BindingValue, Bindings, Expr, ExprId, NameKind};
+use crate::{DefDatabase, FileId, Module, ModuleKind, ModuleSourceMap};
+use smol_str::SmolStr;
+use syntax::TextRange;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CodeLens {
+    /// Reference count of the name defined at `range`.
+    /// The references are resolved lazily via `references`.
+    References { range: TextRange },
+    /// A buildable flake output defined by the name at `range`.
+    /// `attrpath` is relative to the flake outputs, and points to a derivation.
+    FlakeOutput {
+        range: TextRange,
+        attrpath: Vec<SmolStr>,
+    },
+}
+
+impl CodeLens {
+    pub fn range(&self) -> TextRange {
+        match self {
+            Self::References { range } | Self::FlakeOutput { range, .. } => *range,
+        }
+    }
+}
+
+pub(crate) fn code_lenses(db: &dyn DefDatabase, file: FileId) -> Vec<CodeLens> {
+    let mut lenses = reference_lenses(db, file);
+    if let ModuleKind::FlakeNix {
+        outputs_expr: Some(outputs_expr),
+        ..
+    } = *db.module_kind(file)
+    {
+        let module = db.module(file);
+        let source_map = db.source_map(file);
+        let mut collector = FlakeOutputCollector {
+            module: &module,
+            source_map: &source_map,
+            path: Vec::new(),
+            lenses: &mut lenses,
+        };
+        if let Expr::Lambda(_, _, body) = module[outputs_expr] {
+            collector.collect(body);
+        }
+    }
+    lenses.sort_by_key(|lens| lens.range().start());
+    lenses
+}
+
+/// Collect reference lenses for `let` bindings and keys of the top-level attrset.
+fn reference_lenses(db: &dyn DefDatabase, file: FileId) -> Vec<CodeLens> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+
+    // Skip lambdas and wrappers of the top-level attrset.
+    // `{ lib, ... }: let a = 1; in with lib; { b = a; }`
+    let top_expr = std::iter::successors(Some(module.entry_expr()), |&e| match &module[e] {
+        Expr::Lambda(_, _, body)
+        | Expr::LetIn(_, body)
+        | Expr::With(_, body)
+        | Expr::Assert(_, body) => Some(*body),
+        _ => None,
+    })
+    .last()
+    .unwrap();
+    let top_names = match &module[top_expr] {
+        Expr::Attrset(bindings) | Expr::RecAttrset(bindings) => &bindings.statics[..],
+        _ => &[],
+    };
+
+    let mut ranges = module
+        .names()
+        .filter(|(_, name)| name.kind == NameKind::LetIn)
+        .map(|(name, _)| name)
+        .chain(top_names.iter().map(|&(name, _)| name))
+        .filter_map(|name| Some(source_map.nodes_for_name(name).next()?.text_range()))
+        .collect::<Vec<_>>();
+    ranges.sort_by_key(|range| range.start());
+    ranges.dedup();
+    ranges
+        .into_iter()
+        .map(|range| CodeLens::References { range })
+        .collect()
+}
+
+struct FlakeOutputCollector<'a> {
+    module: &'a Module,
+    source_map: &'a ModuleSourceMap,
+    path: Vec<SmolStr>,
+    lenses: &'a mut Vec<CodeLens>,
+}
+
+impl FlakeOutputCollector<'_> {
+    /// The maximum depth of recognizable outputs, ie. `packages.<system>.<name>`.
+    const MAX_DEPTH: usize = 3;
+
+    fn collect(&mut self, expr: ExprId) {
+        let expr = std::iter::successors(Some(expr), |&e| match &self.module[e] {
+            Expr::LetIn(_, body) | Expr::With(_, body) | Expr::Assert(_, body) => Some(*body),
+            _ => None,
+        })
+        .last()
+        .unwrap();
+        if let Expr::Attrset(bindings) | Expr::RecAttrset(bindings) = &self.module[expr] {
+            self.collect_bindings(bindings);
+        }
+    }
+
+    fn collect_bindings(&mut self, bindings: &Bindings) {
+        for &(name, value) in bindings.statics.iter() {
+            let BindingValue::Expr(value) = value else {
+                continue;
+            };
+            self.path.push(self.module[name].text.clone());
+            if let Some(attrpath) = self.output_derivation_path() {
+                if let Some(ptr) = self.source_map.nodes_for_name(name).next() {
+                    self.lenses.push(CodeLens::FlakeOutput {
+                        range: ptr.text_range(),
+                        attrpath,
+                    });
+                }
+            } else if self.path.len() < Self::MAX_DEPTH {
+                self.collect(value);
+            }
+            self.path.pop();
+        }
+    }
+
+    fn output_derivation_path(&self) -> Option<Vec<SmolStr>> {
+        match &self.path[..] {
+            [category, _system, _name] if matches!(&**category, "packages" | "checks") => {
+                Some(self.path.clone())
+            }
+            [category, _name] if category == "nixosConfigurations" => Some(
+                self.path
+                    .iter()
+                    .cloned()
+                    .chain(["config", "system", "build", "toplevel"].map(SmolStr::from))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CodeLens;
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let file = f.files()[0];
+        let src = db.file_content(file);
+        let got = super::code_lenses(&db, file)
+            .into_iter()
+            .map(|lens| match lens {
+                CodeLens::References { range } => src[range].to_owned(),
+                CodeLens::FlakeOutput { range, attrpath } => {
+                    format!("{}({})", &src[range], attrpath.join("."))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn let_bindings() {
+        check("let a = 1; b.c = let d = 2; in d; in a", expect!["a b d"]);
+    }
+
+    #[test]
+    fn top_level_attrset() {
+        check(
+            r#"{ lib }: let a = 1; in with lib; rec { b = a; "c" = { d = 1; }; inherit e; }"#,
+            expect![[r#"a b "c" e"#]],
+        );
+        check("[ { a = 1; } ]", expect![""]);
+    }
+
+    #[test]
+    fn flake_outputs() {
+        check(
+            r#"
+#- /flake.nix input:nixpkgs=/nix/store/eeee
+{
+    outputs = { self, nixpkgs }: let system = "x86_64-linux"; in {
+        packages.x86_64-linux.foo = 1;
+        packages.${system}.bar = 1;
+        checks = { x86_64-linux = { test = 1; }; };
+        nixosConfigurations.host = 1;
+        lib.foo = 1;
+    };
+}
+            "#,
+            expect!["outputs system foo(packages.x86_64-linux.foo) test(checks.x86_64-linux.test) host(nixosConfigurations.host.config.system.build.toplevel)"],
+        );
+    }
+}
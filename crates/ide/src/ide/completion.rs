@@ -1,12 +1,20 @@
-use crate::def::{AstPtr, BindingValue, Expr, NameKind};
-use crate::ty::{self, AttrSource, DisplayConfig, Ty};
-use crate::{FileId, FilePos, TyDatabase};
+use super::assists::add_pat_field;
+use crate::def::{
+    AstPtr, BindingValue, Expr, ExprId, Literal, NameKind, PathAnchor, ResolveResult, ScopeId,
+};
+use crate::ty::{self, known, AttrSource, DisplayConfig, Ty};
+use crate::{FileId, FilePos, ModuleKind, TextEdit, TyDatabase, VfsPath};
 use builtin::{BuiltinKind, ALL_BUILTINS};
 use either::Either::{Left, Right};
+use nix_interop::lib_docs::LibDoc;
+use nix_interop::nixos_options;
+use nix_interop::search_path::SearchPath;
+use nix_interop::DEFAULT_IMPORT_FILE;
 use smol_str::SmolStr;
+use std::collections::HashSet;
 use syntax::ast::{self, AstNode, Attr};
-use syntax::semantic::{escape_literal_attr, is_valid_ident, AttrKind};
-use syntax::{best_token_at_offset, match_ast, SyntaxKind, SyntaxNode, TextRange, T};
+use syntax::semantic::{escape_literal_attr, escape_string, is_valid_ident, AttrKind};
+use syntax::{best_token_at_offset, match_ast, SyntaxKind, SyntaxNode, TextRange, TextSize, T};
 
 use super::hover::TY_DETAILED_DISPLAY;
 
@@ -18,20 +26,35 @@ pub const TY_SIGNATURE_DISPLAY: DisplayConfig = DisplayConfig {
     lambda_need_parentheses: false,
 };
 
-#[rustfmt::skip]
-const EXPR_POS_KEYWORDS: &[&str] = &[
-    "assert",
-    // "else",
-    "if",
-    // "in",
-    // "inherit",
-    "let",
-    "or",
-    "rec",
-    // "then",
-    "with",
+/// Keywords starting an expression, which are invalid as operands and in application arguments.
+const EXPR_START_KEYWORDS: &[&str] = &["assert", "if", "let", "with"];
+
+/// Snippets `(label, body, only at the top-level)` in expression positions,
+/// in the LSP snippet syntax with tab stops.
+const EXPR_POS_SNIPPETS: &[(&str, &str, bool)] = &[
+    ("let-in", "let\n  ${1:name} = ${2:value};\nin\n$0", false),
+    ("module", "{ config, lib, pkgs, ... }:\n{\n  $0\n}", true),
+    (
+        "mkDerivation",
+        "stdenv.mkDerivation {\n  pname = \"${1:name}\";\n  version = \"${2:0.1.0}\";\n\n  src = ${3:./.};\n\n  $0\n}",
+        false,
+    ),
+    (
+        "mkOption",
+        "lib.mkOption {\n  type = lib.types.${1:str};\n  default = ${2:null};\n  description = \"$3\";\n}",
+        false,
+    ),
 ];
 
+/// The conventional name of nixpkgs library.
+pub(crate) const LIB_NAME: &str = "lib";
+
+/// The conventional name of the package set, whose attributes are completed from the index.
+const PACKAGE_SET_NAME: &str = "pkgs";
+
+/// The conventional name of nixpkgs, in search paths and flake inputs.
+const NIXPKGS_NAME: &str = "nixpkgs";
+
 /// A single completion variant in the editor pop-up.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CompletionItem {
@@ -49,6 +72,39 @@ pub struct CompletionItem {
     pub description: Option<String>,
     /// The detailed documentation.
     pub documentation: Option<String>,
+    /// Edits elsewhere in the file to apply together, eg. to bring names into scope.
+    pub additional_edits: Vec<TextEdit>,
+    /// What to do after the item is accepted.
+    pub command: Option<CompletionCommand>,
+}
+
+impl CompletionItem {
+    /// The text to match against the input, which is `replace` except for snippets.
+    pub fn filter_text(&self) -> &str {
+        match self.kind {
+            CompletionItemKind::Snippet => &self.label,
+            _ => &self.replace,
+        }
+    }
+}
+
+/// An editor action to run after a completion item is accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionCommand {
+    /// Show parameter hints, since a function is completed.
+    TriggerParameterHints,
+}
+
+/// How to bring `lib` into scope when completing its functions without `lib` defined.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LibImportStrategy {
+    /// Do not complete `lib` functions.
+    Off,
+    /// Add `lib` to the pattern of the top-level lambda, or fallback to `Let`.
+    #[default]
+    Pattern,
+    /// Insert `let lib = ...; in` inside top-level lambdas.
+    Let,
 }
 
 /// The type of the completion item.
@@ -61,6 +117,22 @@ pub enum CompletionItemKind {
     BuiltinConst,
     BuiltinFunction,
     BuiltinAttrset,
+    EnumMember,
+    Input,
+    Package,
+    /// `replace` is in the LSP snippet syntax.
+    Snippet,
+    File,
+    Folder,
+    /// An attribute only known by evaluation.
+    Evaluated,
+}
+
+/// An entry of a directory on the disk, for completing path literals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub is_dir: bool,
 }
 
 impl From<BuiltinKind> for CompletionItemKind {
@@ -85,19 +157,54 @@ impl From<NameKind> for CompletionItemKind {
 }
 
 pub(crate) fn completions(
+    db: &dyn TyDatabase,
+    fpos: FilePos,
+    trigger_char: Option<char>,
+    lib_import: LibImportStrategy,
+) -> Option<Vec<CompletionItem>> {
+    let mut items = complete(db, fpos, trigger_char, lib_import)?;
+    rank(&db.file_content(fpos.file_id), fpos.pos, &mut items);
+    Some(items)
+}
+
+fn complete(
     db: &dyn TyDatabase,
     fpos @ FilePos { file_id, pos }: FilePos,
     trigger_char: Option<char>,
+    lib_import: LibImportStrategy,
 ) -> Option<Vec<CompletionItem>> {
     let parse = db.parse(file_id);
 
+    // Manually invoked right after `.`, which behaves the same as triggered by it.
+    // The token at the cursor is not `.` in cases like `"${a.|}"`, where `}` is preferred.
+    let trigger_char = trigger_char.or_else(|| {
+        let tok = parse.syntax_node().token_at_offset(pos).left_biased()?;
+        (tok.kind() == T![.]).then_some('.')
+    });
     if let Some(items) =
-        trigger_char.and_then(|ch| complete_trigger(db, fpos, parse.syntax_node(), ch))
+        trigger_char.and_then(|ch| complete_trigger(db, fpos, parse.syntax_node(), ch, lib_import))
     {
         return Some(items);
     }
 
+    // Typing the closing quote, not an opening one.
+    if trigger_char == Some('"') {
+        let tok = parse.syntax_node().token_at_offset(pos).left_biased()?;
+        if tok.parent()?.text_range().end() == pos {
+            return None;
+        }
+    }
+
+    if let Some(items) = complete_empty_interpolation(db, fpos, &parse.syntax_node()) {
+        return Some(items);
+    }
+
     let tok = best_token_at_offset(&parse.syntax_node(), pos)?;
+    if matches!(tok.kind(), SyntaxKind::STRING_FRAGMENT | T!['"']) {
+        let string_node = tok.parent_ancestors().find_map(ast::String::cast)?;
+        return complete_string(db, fpos, string_node);
+    }
+
     let source_range = match tok.kind() {
         T![.] => TextRange::empty(pos),
         SyntaxKind::IDENT => tok.text_range(),
@@ -120,7 +227,10 @@ pub(crate) fn completions(
             match_ast! {
                 match (name_node.syntax().parent()?) {
                     ast::Attrpath(path_node) => {
-                        complete_attrpath(db, file_id, source_range, name_node, path_node)
+                        complete_attrpath(db, file_id, source_range, name_node, path_node, lib_import)
+                    },
+                    ast::Inherit(inherit_node) => {
+                        complete_inherit(db, file_id, source_range, name_node, inherit_node)
                     },
                     ast::PatField(pat_field_node) => {
                         let lambda_node = pat_field_node
@@ -141,6 +251,7 @@ fn complete_trigger(
     FilePos { file_id, pos }: FilePos,
     root_node: SyntaxNode,
     trigger_char: char,
+    lib_import: LibImportStrategy,
 ) -> Option<Vec<CompletionItem>> {
     if !matches!(trigger_char, '.' | '?') {
         return None;
@@ -173,7 +284,7 @@ fn complete_trigger(
     };
 
     let path_node = ast::Attrpath::cast(name_node.syntax().parent()?)?;
-    complete_attrpath(db, file_id, source_range, name_node, path_node)
+    complete_attrpath(db, file_id, source_range, name_node, path_node, lib_import)
 }
 
 fn complete_expr(
@@ -182,7 +293,6 @@ fn complete_expr(
     source_range: TextRange,
     ref_node: ast::Ref,
 ) -> Option<Vec<CompletionItem>> {
-    let module = db.module(file_id);
     let source_map = db.source_map(file_id);
     let expr_id = source_map.expr_for_node(AstPtr::new(ref_node.syntax()))?;
     let scopes = db.scopes(file_id);
@@ -191,71 +301,196 @@ fn complete_expr(
     let prefix = SmolStr::from(ref_node.token()?.text());
     let mut items = Vec::new();
     let mut feed = |compe: CompletionItem| {
-        if can_complete(&prefix, &compe.replace) {
+        if can_complete(&prefix, compe.filter_text()) {
             items.push(compe);
         }
     };
 
     // Keywords.
-    EXPR_POS_KEYWORDS
-        .iter()
+    expr_keywords(&ref_node)
+        .into_iter()
         .map(|kw| keyword_to_completion(kw, source_range))
         .for_each(&mut feed);
 
-    // Contextual keywords.
-    if ref_node
+    // Snippets.
+    let is_toplevel = ref_node
         .syntax()
-        .ancestors()
-        .find_map(ast::IfThenElse::cast)
-        .is_some()
-    {
-        feed(keyword_to_completion("then", source_range));
-        feed(keyword_to_completion("else", source_range));
-    }
-    if ref_node
-        .syntax()
-        .ancestors()
-        .find_map(ast::LetIn::cast)
-        .is_some()
-    {
-        feed(keyword_to_completion("in", source_range));
+        .parent()
+        .map_or(false, |parent| ast::SourceFile::can_cast(parent.kind()));
+    EXPR_POS_SNIPPETS
+        .iter()
+        .filter(|(_, _, toplevel_only)| is_toplevel || !toplevel_only)
+        .map(|&(label, body, _)| CompletionItem {
+            label: label.into(),
+            source_range,
+            replace: body.into(),
+            kind: CompletionItemKind::Snippet,
+            signature: None,
+            description: None,
+            documentation: None,
+            additional_edits: Vec::new(),
+            command: None,
+        })
+        .for_each(&mut feed);
+
+    names_in_scope(db, file_id, scope_id, source_range).for_each(&mut feed);
+
+    // TODO: Better sorting.
+    items.sort_by(|lhs, rhs| lhs.label.cmp(&rhs.label));
+    items.dedup_by(|lhs, rhs| lhs.label == rhs.label);
+
+    Some(items)
+}
+
+/// Complete the empty interpolation before `pos`, like `"${|}"`, where there is no `Ref` node.
+fn complete_empty_interpolation(
+    db: &dyn TyDatabase,
+    FilePos { file_id, pos }: FilePos,
+    root_node: &SyntaxNode,
+) -> Option<Vec<CompletionItem>> {
+    let tok = root_node.token_at_offset(pos).left_biased()?;
+    let next = std::iter::successors(tok.next_token(), |tok| tok.next_token())
+        .find(|tok| tok.kind() != SyntaxKind::SPACE)?;
+    if tok.kind() != T!["${"] || next.kind() != T!['}'] {
+        return None;
     }
+    let string_node = tok.parent_ancestors().find(|node| {
+        matches!(
+            node.kind(),
+            SyntaxKind::STRING | SyntaxKind::INDENT_STRING | SyntaxKind::PATH_INTERPOLATION
+        )
+    })?;
+    let source_map = db.source_map(file_id);
+    let expr_id = source_map.expr_for_node(AstPtr::new(&string_node))?;
+    let scope_id = db.scopes(file_id).scope_for_expr(expr_id)?;
+
+    let source_range = TextRange::empty(pos);
+    let mut items = EXPR_START_KEYWORDS
+        .iter()
+        .chain(&["rec"])
+        .map(|kw| keyword_to_completion(kw, source_range))
+        .chain(names_in_scope(db, file_id, scope_id, source_range))
+        .collect::<Vec<_>>();
+    items.sort_by(|lhs, rhs| lhs.label.cmp(&rhs.label));
+    items.dedup_by(|lhs, rhs| lhs.label == rhs.label);
+    Some(items)
+}
 
+/// Names defined in `scope_id` and its ancestors, and global builtins.
+fn names_in_scope(
+    db: &dyn TyDatabase,
+    file_id: FileId,
+    scope_id: ScopeId,
+    source_range: TextRange,
+) -> impl Iterator<Item = CompletionItem> {
+    let module = db.module(file_id);
+    let scopes = db.scopes(file_id);
     let infer = db.infer(file_id);
 
-    // Names in current scopes.
-    scopes
+    let names = scopes
         .ancestors(scope_id)
         .filter_map(|scope| scope.as_definitions())
         .flatten()
         .filter(|(text, _)| is_valid_ident(text))
-        .map(|(text, &name)| CompletionItem {
-            label: text.clone(),
-            source_range,
-            replace: text.clone(),
-            kind: module[name].kind.into(),
-            signature: {
-                let ty = infer.ty_for_name(name);
-                ty.is_known()
-                    .then(|| ty.display_with(TY_SIGNATURE_DISPLAY).to_string())
-            },
-            description: None,
-            documentation: None,
+        .map(|(text, &name)| {
+            let ty = infer.ty_for_name(name);
+            CompletionItem {
+                label: text.clone(),
+                source_range,
+                replace: text.clone(),
+                kind: module[name].kind.into(),
+                signature: ty
+                    .is_known()
+                    .then(|| ty.display_with(TY_SIGNATURE_DISPLAY).to_string()),
+                description: None,
+                documentation: None,
+                additional_edits: Vec::new(),
+                command: command_for_ty(&ty),
+            }
         })
-        .for_each(&mut feed);
+        .collect::<Vec<_>>();
 
-    // Global builtins.
-    ALL_BUILTINS
+    let builtins = ALL_BUILTINS
         .entries()
         .filter(|(_, b)| b.is_global)
-        .filter_map(|(name, _)| builtin_to_completion(source_range, name))
-        .for_each(&mut feed);
+        .filter_map(move |(name, _)| builtin_to_completion(source_range, name));
 
-    // TODO: Better sorting.
-    items.sort_by(|lhs, rhs| lhs.label.cmp(&rhs.label));
-    items.dedup_by(|lhs, rhs| lhs.label == rhs.label);
+    names.into_iter().chain(builtins)
+}
 
-    Some(items)
+/// Keywords which are syntactically valid at the expression `ref_node`.
+fn expr_keywords(ref_node: &ast::Ref) -> Vec<&'static str> {
+    let ref_range = ref_node.syntax().text_range();
+    let mut ret = vec!["rec"];
+
+    // Find the expression right before `ref_node` in the same application, if any.
+    // `assert`, `if`, `let` and `with` are only valid at the start of a full expression.
+    let mut node = ref_node.syntax().clone();
+    let mut is_full_expr = true;
+    let mut prev = None;
+    while let Some(parent) = node.parent() {
+        match_ast! {
+            match parent {
+                ast::Apply(app) => {
+                    if app.argument().map_or(false, |arg| *arg.syntax() == node) {
+                        is_full_expr = false;
+                        prev = app.function();
+                        break;
+                    }
+                    node = parent;
+                },
+                _ => {
+                    // Operands and list elements.
+                    is_full_expr = !matches!(
+                        parent.kind(),
+                        SyntaxKind::BINARY_OP
+                            | SyntaxKind::UNARY_OP
+                            | SyntaxKind::LIST
+                            | SyntaxKind::SELECT
+                    );
+                    break;
+                },
+            }
+        }
+    }
+    if is_full_expr {
+        ret.extend(EXPR_START_KEYWORDS);
+    }
+
+    // `a.b o|`
+    while let Some(ast::Expr::Apply(app)) = &prev {
+        prev = app.argument();
+    }
+    if let Some(ast::Expr::Select(sel)) = prev {
+        if sel.or_token().is_none() {
+            ret.push("or");
+        }
+    }
+
+    // `if a th|` or `if a then b el|`, where the keyword is missing after `ref_node`.
+    // The preceding expression must be non-empty.
+    let ends_with_ref = |e: Option<ast::Expr>| {
+        e.map_or(false, |e| {
+            let range = e.syntax().text_range();
+            range.start() < ref_range.start() && range.end() == ref_range.end()
+        })
+    };
+    if let Some(ite) = ref_node
+        .syntax()
+        .ancestors()
+        .find_map(ast::IfThenElse::cast)
+    {
+        if ite.then_token().is_none() && ends_with_ref(ite.condition()) {
+            ret.push("then");
+        } else if ite.then_token().is_some()
+            && ite.else_token().is_none()
+            && ends_with_ref(ite.then_body())
+        {
+            ret.push("else");
+        }
+    }
+
+    ret
 }
 
 fn complete_attrpath(
@@ -264,6 +499,7 @@ fn complete_attrpath(
     source_range: TextRange,
     name_node: ast::Name,
     path_node: ast::Attrpath,
+    lib_import: LibImportStrategy,
 ) -> Option<Vec<CompletionItem>> {
     let (set_node, container_node) = match_ast! {
         match (path_node.syntax().parent()?){
@@ -271,8 +507,9 @@ fn complete_attrpath(
                 let n = n.syntax().parent()?;
                 (n.clone(), n)
             },
-            ast::HasAttr(n) => (n.set()?.syntax().clone(), n.syntax().clone()),
-            ast::Select(n) => (n.set()?.syntax().clone(), n.syntax().clone()),
+            // Parentheses are not lowered, eg. `(import ./lib.nix).foo`.
+            ast::HasAttr(n) => (n.set()?.flatten_paren()?.syntax().clone(), n.syntax().clone()),
+            ast::Select(n) => (n.set()?.flatten_paren()?.syntax().clone(), n.syntax().clone()),
             _ => return None,
         }
     };
@@ -293,7 +530,9 @@ fn complete_attrpath(
     if attr_cnt == 1 && (is_let || is_attrset) {
         items.push(keyword_to_completion("inherit", source_range));
     }
-    if attr_cnt == 1 && is_let {
+    if attr_cnt == 1
+        && ast::LetIn::cast(container_node.clone()).map_or(false, |n| n.in_token().is_none())
+    {
         items.push(keyword_to_completion("in", source_range));
     }
 
@@ -332,6 +571,8 @@ fn complete_attrpath(
                                 signature: None,
                                 description: None,
                                 documentation: None,
+                                additional_edits: Vec::new(),
+                                command: None,
                             }
                         }),
                 );
@@ -343,6 +584,7 @@ fn complete_attrpath(
     // If we get here, we are either inside a selection path `a.b|`,
     // or non-first parts of a definition `{ a.b| }`.
     // Use type information
+    let mut lib_edit = None;
     (|| -> Option<()> {
         let infer = db.infer(file_id);
 
@@ -352,7 +594,12 @@ fn complete_attrpath(
             infer.ty_for_name(name)
         } else {
             let set_expr = source_map.expr_for_node(AstPtr::new(&set_node))?;
-            infer.ty_for_expr(set_expr)
+            lib_edit = lib_import_edit(db, file_id, set_expr, lib_import);
+            if lib_edit.is_some() {
+                known::LIB.clone()
+            } else {
+                infer.ty_for_expr(set_expr)
+            }
         };
 
         // Resolve prefix paths, except for the current NAME.
@@ -386,21 +633,493 @@ fn complete_attrpath(
                 kind: match src {
                     AttrSource::Unknown => CompletionItemKind::Field,
                     AttrSource::Name(name) => module[name].kind.into(),
+                    AttrSource::Imported(name) => db.module(name.file_id)[name.value].kind.into(),
                     // Handled above.
                     AttrSource::Builtin => unreachable!(),
                 },
                 signature: Some(ty.display_with(TY_SIGNATURE_DISPLAY).to_string()),
                 description: Some(ty.display_with(TY_DETAILED_DISPLAY).to_string()),
                 documentation: None,
+                additional_edits: Vec::new(),
+                command: command_for_ty(ty),
             })
         }));
 
         Some(())
     })();
 
+    // Option definitions are documented from the option set of the module, if loaded.
+    let option_prefix = is_attrset
+        .then(|| option_path_prefix(db, file_id, &set_node, &path_node, &name_node))
+        .flatten();
+    if let Some(prefix) = option_prefix {
+        let opts = db.option_set(db.module_option_set(file_id));
+        let fields = prefix
+            .iter()
+            .try_fold(&*opts, |opts, name| match &opts.get(&**name)?.ty {
+                nixos_options::Ty::Attrset { fields, .. } => Some(fields),
+                _ => None,
+            });
+        for item in &mut items {
+            let doc = fields
+                .and_then(|fields| fields.get(&*item.label)?.description.as_ref())
+                .and_then(|doc| match doc {
+                    nixos_options::Doc::Markdown { text } => Some(text),
+                    nixos_options::Doc::Other => None,
+                });
+            if let Some(doc) = doc {
+                item.documentation.get_or_insert_with(|| doc.clone());
+            }
+        }
+    }
+
+    // The first attribute of `pkgs.name` also completes packages from the nixpkgs index.
+    // Names already known from types take precedence.
+    let is_pkgs_select = ast::Select::can_cast(container_node.kind())
+        && path_node
+            .attrs()
+            .next()
+            .map_or(false, |attr| attr.syntax() == name_node.syntax())
+        && source_map
+            .expr_for_node(AstPtr::new(&set_node))
+            .map_or(false, |expr| is_package_set(db, file_id, expr));
+    if is_pkgs_select {
+        let packages = package_completions(db, source_range, &current_input, &items);
+        items.extend(packages);
+    }
+
+    // `lib.name` and `lib.module.name` are documented from nixpkgs `lib` sources, if loaded.
+    let lib_prefix = ast::Select::can_cast(container_node.kind())
+        .then(|| {
+            let set_expr = source_map.expr_for_node(AstPtr::new(&set_node))?;
+            if !matches!(&module[set_expr], Expr::Reference(name) if name == LIB_NAME) {
+                return None;
+            }
+            path_node
+                .attrs()
+                .take_while(|attr| attr.syntax() != name_node.syntax())
+                .map(|attr| match AttrKind::of(attr) {
+                    AttrKind::Static(Some(field)) => Some(field),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()
+                .filter(|prefix| prefix.len() <= 1)
+        })
+        .flatten();
+    if let Some(prefix) = lib_prefix {
+        let lib_docs = db.lib_docs();
+        let module = prefix.first().map(|module| &**module);
+        for item in &mut items {
+            if let Some(doc) = lib_docs.get(module, &item.label) {
+                item.documentation.get_or_insert_with(|| doc.doc.clone());
+            }
+        }
+        let known = items
+            .iter()
+            .map(|item| item.label.clone())
+            .collect::<HashSet<_>>();
+        let mut new_items = Vec::new();
+        match module {
+            None => {
+                new_items.extend(lib_docs.modules().map(|name| CompletionItem {
+                    label: name.into(),
+                    source_range,
+                    replace: name.into(),
+                    kind: CompletionItemKind::Field,
+                    signature: None,
+                    description: None,
+                    documentation: None,
+                    additional_edits: Vec::new(),
+                    command: None,
+                }));
+                new_items.extend(
+                    lib_docs
+                        .toplevel()
+                        .map(|(name, doc)| lib_doc_to_completion(source_range, name, doc)),
+                );
+            }
+            Some(module) => new_items.extend(
+                lib_docs
+                    .module(module)
+                    .map(|(name, doc)| lib_doc_to_completion(source_range, name, doc)),
+            ),
+        }
+        items.extend(
+            new_items
+                .into_iter()
+                .filter(|item| item.label != current_input && !known.contains(&item.label)),
+        );
+    }
+
+    if is_attrset && attr_cnt == 1 {
+        let placeholders = hash_placeholders(db, file_id, &container_node, source_range);
+        items.extend(
+            placeholders
+                .into_iter()
+                .flatten()
+                .filter(|item| can_complete(&current_input, &item.replace)),
+        );
+    }
+
+    if let Some(edit) = lib_edit {
+        for item in &mut items {
+            item.additional_edits.push(edit.clone());
+        }
+    }
+
+    Some(items)
+}
+
+/// The option path before the current NAME, if it is inside option definitions of a module or
+/// configuration, like `["programs", "git"]` of `{ programs.git.| }`.
+fn option_path_prefix(
+    db: &dyn TyDatabase,
+    file_id: FileId,
+    set_node: &SyntaxNode,
+    path_node: &ast::Attrpath,
+    name_node: &ast::Name,
+) -> Option<Vec<String>> {
+    let module = db.module(file_id);
+    let source_map = db.source_map(file_id);
+    let config_expr = ty::config_expr(db, &module, file_id)?;
+    let static_attrs = |attrs: &mut dyn Iterator<Item = Attr>| {
+        attrs
+            .map(|attr| match AttrKind::of(attr) {
+                AttrKind::Static(Some(name)) => Some(name),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+    };
+    let mut path = static_attrs(
+        &mut path_node
+            .attrs()
+            .take_while(|attr| attr.syntax() != name_node.syntax()),
+    )?;
+    let mut set_node = set_node.clone();
+    loop {
+        if source_map.expr_for_node(AstPtr::new(&set_node)) == Some(config_expr) {
+            return Some(path);
+        }
+        let Some(binding) = set_node.parent().and_then(ast::AttrpathValue::cast) else {
+            break;
+        };
+        let mut outer = static_attrs(&mut binding.attrpath()?.attrs())?;
+        outer.append(&mut path);
+        path = outer;
+        set_node = binding.syntax().parent()?;
+    }
+    // `{ config.foo = ...; }` of modules with `options`.
+    if matches!(*db.module_kind(file_id), ModuleKind::ConfigModule { .. })
+        && ast::Lambda::can_cast(set_node.parent()?.kind())
+        && path.first().map_or(false, |name| name == "config")
+    {
+        path.remove(0);
+        return Some(path);
+    }
+    None
+}
+
+/// Placeholders of `hash` in the argument of a fetcher, like `fetchurl { h| }`.
+/// Nix reports the correct hash when fetching with them.
+fn hash_placeholders(
+    db: &dyn TyDatabase,
+    file_id: FileId,
+    set_node: &SyntaxNode,
+    source_range: TextRange,
+) -> Option<Vec<CompletionItem>> {
+    let app = ast::Apply::cast(set_node.parent()?)?;
+    if app.argument()?.syntax() != set_node {
+        return None;
+    }
+    let module = db.module(file_id);
+    let source_map = db.source_map(file_id);
+    let func = source_map.expr_for_node(AstPtr::new(app.function()?.flatten_paren()?.syntax()))?;
+    ty::fetcher_arg_ty(&module, func)?;
+
+    let set_expr = source_map.expr_for_node(AstPtr::new(set_node))?;
+    let scopes = db.scopes(file_id);
+    let has_lib = scopes
+        .ancestors(scopes.scope_for_expr(set_expr)?)
+        .filter_map(|scope| scope.as_definitions())
+        .any(|defs| defs.contains_key(LIB_NAME));
+
+    let values = has_lib
+        .then_some("lib.fakeHash")
+        .into_iter()
+        .chain(Some("\"\""));
+    let items = values
+        .map(|value| CompletionItem {
+            label: format!("hash = {value}").into(),
+            source_range,
+            replace: format!("hash = {value};").into(),
+            kind: CompletionItemKind::Field,
+            signature: None,
+            description: Some("Placeholder to be replaced by the hash reported by Nix".into()),
+            documentation: None,
+            additional_edits: Vec::new(),
+            command: None,
+        })
+        .collect();
+    Some(items)
+}
+
+/// Packages from the nixpkgs index matching `current_input`, except ones already in `items`.
+fn package_completions(
+    db: &dyn TyDatabase,
+    source_range: TextRange,
+    current_input: &str,
+    items: &[CompletionItem],
+) -> Vec<CompletionItem> {
+    let index = db.package_index();
+    let known = items
+        .iter()
+        .map(|item| item.label.clone())
+        .collect::<HashSet<_>>();
+    index
+        .iter()
+        .filter(|(name, _)| {
+            **name != current_input
+                && can_complete(current_input, name)
+                && !known.contains(name.as_str())
+        })
+        .map(|(name, info)| {
+            let escaped_name = escape_literal_attr(name);
+            let detail = match (&info.version, &info.description) {
+                (Some(version), Some(desc)) => Some(format!("{version}: {desc}")),
+                (version, desc) => version.clone().or_else(|| desc.clone()),
+            };
+            CompletionItem {
+                label: escaped_name.as_ref().into(),
+                source_range,
+                replace: escaped_name.into(),
+                kind: CompletionItemKind::Package,
+                signature: None,
+                description: detail,
+                documentation: None,
+                additional_edits: Vec::new(),
+                command: None,
+            }
+        })
+        .collect()
+}
+
+/// Check if `expr` is a reference to the package set by its definition, that is, a parameter or
+/// an inherited binding named `pkgs`, a binding of `import <nixpkgs> { }`, `import nixpkgs { }`
+/// or `nixpkgs.legacyPackages.<system>`, or `final` and `prev` of an overlay.
+pub(crate) fn is_package_set(db: &dyn TyDatabase, file_id: FileId, expr: ExprId) -> bool {
+    let module = db.module(file_id);
+    if !matches!(&module[expr], Expr::Reference(_)) {
+        return false;
+    }
+    let nameres = db.name_resolution(file_id);
+    let Some(&ResolveResult::Definition(name)) = nameres.get(expr) else {
+        return false;
+    };
+    if db.module_kind(file_id).is_overlay_param(name) {
+        return true;
+    }
+    let is_pkgs_name = module[name].text == PACKAGE_SET_NAME;
+    if matches!(module[name].kind, NameKind::Param | NameKind::PatField) {
+        return is_pkgs_name;
+    }
+    let value = module.exprs().find_map(|(_, kind)| match kind {
+        Expr::LetIn(bindings, _) | Expr::RecAttrset(bindings) | Expr::LetAttrset(bindings) => {
+            bindings
+                .statics
+                .iter()
+                .find(|&&(def, _)| def == name)
+                .map(|&(_, value)| value)
+        }
+        _ => None,
+    });
+    let value = match value {
+        Some(BindingValue::Expr(value)) => value,
+        Some(BindingValue::Inherit(_) | BindingValue::InheritFrom(_)) => return is_pkgs_name,
+        None => return false,
+    };
+    // The `nth_back` attribute of `path` is literally `text`.
+    let is_attr_back = |path: &[ExprId], nth_back: usize, text: &str| {
+        path.len().checked_sub(nth_back + 1).map_or(
+            false,
+            |i| matches!(&module[path[i]], Expr::Literal(Literal::String(s)) if s == text),
+        )
+    };
+    // `<nixpkgs>`, `nixpkgs` or `inputs.nixpkgs`.
+    let is_nixpkgs_source = |src: ExprId| match &module[src] {
+        &Expr::Literal(Literal::Path(path)) => {
+            let data = db.lookup_intern_path(path);
+            *data.anchor() == PathAnchor::Search(NIXPKGS_NAME.into())
+                && data.relative_path().is_empty()
+        }
+        Expr::Reference(name) => name == NIXPKGS_NAME,
+        Expr::Select(_, path, None) => is_attr_back(path, 0, NIXPKGS_NAME),
+        _ => false,
+    };
+    match module[value] {
+        Expr::Apply(func, _) => matches!(
+            module[func],
+            Expr::Apply(import, src)
+                if nameres.check_builtin(import, &module) == Some("import") && is_nixpkgs_source(src)
+        ),
+        Expr::Select(_, ref path, None) => is_attr_back(path, 1, "legacyPackages"),
+        _ => false,
+    }
+}
+
+/// Complete names in `inherit name;` from the enclosing scope, or in `inherit (set) name;`
+/// from fields of `set`. Names already inherited by the same statement are skipped.
+fn complete_inherit(
+    db: &dyn TyDatabase,
+    file_id: FileId,
+    source_range: TextRange,
+    name_node: ast::Name,
+    inherit_node: ast::Inherit,
+) -> Option<Vec<CompletionItem>> {
+    let module = db.module(file_id);
+    let source_map = db.source_map(file_id);
+    let infer = db.infer(file_id);
+    let current_input = name_node
+        .token()
+        .map_or(SmolStr::default(), |tok| tok.text().into());
+    let inherited = inherit_node
+        .attrs()
+        .filter(|attr| attr.syntax() != name_node.syntax())
+        .filter_map(|attr| match AttrKind::of(attr) {
+            AttrKind::Static(name) => name,
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    let mut items = Vec::new();
+    match inherit_node.from_expr() {
+        Some(from_node) => {
+            let from_expr = source_map.expr_for_node(AstPtr::new(from_node.expr()?.syntax()))?;
+            let ty = infer.ty_for_expr(from_expr);
+            if let Some(set) = ty.as_attrset() {
+                items.extend(set.iter().map(|(name, ty, src)| {
+                    let escaped_name = escape_literal_attr(name);
+                    CompletionItem {
+                        label: escaped_name.as_ref().into(),
+                        source_range,
+                        replace: escaped_name.into(),
+                        kind: match src {
+                            AttrSource::Name(name) => module[name].kind.into(),
+                            AttrSource::Imported(name) => {
+                                db.module(name.file_id)[name.value].kind.into()
+                            }
+                            AttrSource::Unknown | AttrSource::Builtin => CompletionItemKind::Field,
+                        },
+                        signature: Some(ty.display_with(TY_SIGNATURE_DISPLAY).to_string()),
+                        description: Some(ty.display_with(TY_DETAILED_DISPLAY).to_string()),
+                        documentation: None,
+                        additional_edits: Vec::new(),
+                        command: command_for_ty(ty),
+                    }
+                }));
+            }
+            if is_package_set(db, file_id, from_expr) {
+                let packages = package_completions(db, source_range, &current_input, &items);
+                items.extend(packages);
+            }
+        }
+        None => {
+            // The name itself is lowered to a reference in the enclosing scope.
+            let ref_expr = source_map.expr_for_node(AstPtr::new(name_node.syntax()))?;
+            let scopes = db.scopes(file_id);
+            let scope_id = scopes.scope_for_expr(ref_expr)?;
+            items.extend(
+                scopes
+                    .ancestors(scope_id)
+                    .filter_map(|scope| scope.as_definitions())
+                    .flatten()
+                    .filter(|(text, _)| is_valid_ident(text))
+                    .map(|(text, &name)| {
+                        let ty = infer.ty_for_name(name);
+                        CompletionItem {
+                            label: text.clone(),
+                            source_range,
+                            replace: text.clone(),
+                            kind: module[name].kind.into(),
+                            signature: ty
+                                .is_known()
+                                .then(|| ty.display_with(TY_SIGNATURE_DISPLAY).to_string()),
+                            description: None,
+                            documentation: None,
+                            additional_edits: Vec::new(),
+                            command: command_for_ty(&ty),
+                        }
+                    }),
+            );
+            items.extend(
+                ALL_BUILTINS
+                    .entries()
+                    .filter(|(_, b)| b.is_global)
+                    .filter_map(|(name, _)| builtin_to_completion(source_range, name)),
+            );
+        }
+    }
+
+    items.retain(|item| {
+        item.label != current_input
+            && !inherited.contains(item.label.as_str())
+            && can_complete(&current_input, &item.replace)
+    });
+    items.sort_by(|lhs, rhs| lhs.label.cmp(&rhs.label));
+    items.dedup_by(|lhs, rhs| lhs.label == rhs.label);
     Some(items)
 }
 
+/// The edit to bring `lib` into scope, if `set_expr` is a reference to undefined `lib`.
+fn lib_import_edit(
+    db: &dyn TyDatabase,
+    file_id: FileId,
+    set_expr: ExprId,
+    strategy: LibImportStrategy,
+) -> Option<TextEdit> {
+    if strategy == LibImportStrategy::Off {
+        return None;
+    }
+    let module = db.module(file_id);
+    if !matches!(&module[set_expr], Expr::Reference(name) if name == LIB_NAME)
+        || db.name_resolution(file_id).get(set_expr).is_some()
+    {
+        return None;
+    }
+
+    // Walk through top-level lambdas. Patterns of inner lambdas are usually not
+    // the right place, eg. `map ({ name }: lib.f name) xs`.
+    let source_file = ast::SourceFile::cast(db.parse(file_id).syntax_node())?;
+    let mut body = source_file.expr()?;
+    let mut last_pat = None;
+    let mut has_pkgs = false;
+    while let ast::Expr::Lambda(lambda) = &body {
+        let param = lambda.param()?;
+        let is_pkgs = |name: Option<ast::Name>| {
+            name.and_then(|name| name.token())
+                .map_or(false, |tok| tok.text() == PACKAGE_SET_NAME)
+        };
+        has_pkgs |= is_pkgs(param.name());
+        if let Some(pat) = param.pat() {
+            has_pkgs |= pat.fields().any(|field| is_pkgs(field.name()));
+            last_pat = Some(pat);
+        }
+        body = lambda.body()?;
+    }
+
+    if let (LibImportStrategy::Pattern, Some(pat)) = (strategy, &last_pat) {
+        return Some(add_pat_field(pat, LIB_NAME));
+    }
+    let value = if has_pkgs {
+        "pkgs.lib"
+    } else {
+        "import <nixpkgs/lib>"
+    };
+    Some(TextEdit {
+        delete: TextRange::empty(body.syntax().text_range().start()),
+        insert: format!("let {LIB_NAME} = {value}; in ").into(),
+    })
+}
+
 fn complete_pat_param(
     db: &dyn TyDatabase,
     file_id: FileId,
@@ -433,19 +1152,245 @@ fn complete_pat_param(
                 .then(|| ty.display_with(TY_SIGNATURE_DISPLAY).to_string()),
             description: Some(ty.display_with(TY_DETAILED_DISPLAY).to_string()),
             documentation: None,
+            additional_edits: Vec::new(),
+            command: None,
         })
         .collect();
     Some(items)
 }
-fn keyword_to_completion(kw: &str, source_range: TextRange) -> CompletionItem {
-    CompletionItem {
-        label: kw.into(),
-        source_range,
-        replace: kw.into(),
-        kind: CompletionItemKind::Keyword,
-        signature: None,
-        description: None,
+
+/// Complete allowed values of `types.enum` options in string literals.
+fn complete_string(
+    db: &dyn TyDatabase,
+    FilePos { file_id, pos }: FilePos,
+    string_node: ast::String,
+) -> Option<Vec<CompletionItem>> {
+    let source_map = db.source_map(file_id);
+    let expr = source_map.expr_for_node(AstPtr::new(string_node.syntax()))?;
+    let module_kind = db.module_kind(file_id);
+    let enum_values = db.option_enum_values(file_id);
+    let (values, kind) = match &*module_kind {
+        // `inputs.foo.inputs.bar.follows = "|";`
+        ModuleKind::FlakeNix {
+            explicit_inputs,
+            follows,
+            ..
+        } if follows.iter().any(|&(e, _)| e == expr) => {
+            let mut inputs = explicit_inputs
+                .keys()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>();
+            inputs.sort();
+            (inputs, CompletionItemKind::Input)
+        }
+        _ => (
+            enum_values.get(expr)?.to_vec(),
+            CompletionItemKind::EnumMember,
+        ),
+    };
+
+    // The prefix includes the opening quote, so does the escaped replacement.
+    let source_range = string_node.syntax().text_range();
+    let prefix = &string_node.syntax().to_string()[..usize::from(pos - source_range.start())];
+    let items = values
+        .iter()
+        .map(|value| SmolStr::from(escape_string(value)))
+        .filter(|escaped| can_complete(prefix, escaped))
+        .map(|escaped| CompletionItem {
+            label: escaped.clone(),
+            source_range,
+            replace: escaped,
+            kind,
+            signature: None,
+            description: None,
+            documentation: None,
+            additional_edits: Vec::new(),
+            command: None,
+        })
+        .collect();
+    Some(items)
+}
+
+/// Complete the directory entries in a path literal like `./foo/b|` or `<nixpkgs/li|`.
+/// Since the filesystem is not tracked by the database, entries are listed by `read_dir`.
+///
+/// The literal is located textually, because incomplete ones like `./` or `<nix` do not parse.
+pub(crate) fn path_completions(
+    db: &dyn TyDatabase,
+    fpos: FilePos,
+    search_path: &SearchPath,
+    read_dir: impl Fn(&VfsPath) -> Vec<DirEntry>,
+) -> Option<Vec<CompletionItem>> {
+    let mut items = complete_path(db, fpos, search_path, read_dir)?;
+    rank(&db.file_content(fpos.file_id), fpos.pos, &mut items);
+    Some(items)
+}
+
+fn complete_path(
+    db: &dyn TyDatabase,
+    FilePos { file_id, pos }: FilePos,
+    search_path: &SearchPath,
+    read_dir: impl Fn(&VfsPath) -> Vec<DirEntry>,
+) -> Option<Vec<CompletionItem>> {
+    let is_path_char = |b: u8| b.is_ascii_alphanumeric() || b"._+-/".contains(&b);
+
+    // Not inside strings or comments.
+    let parse = db.parse(file_id);
+    let tok = parse.syntax_node().token_at_offset(pos).left_biased()?;
+    if tok.kind() == SyntaxKind::COMMENT
+        || tok.parent().map_or(false, |n| {
+            n.kind() == SyntaxKind::STRING || n.kind() == SyntaxKind::INDENT_STRING
+        })
+    {
+        return None;
+    }
+
+    let content = db.file_content(file_id);
+    let bytes = content.as_bytes();
+    let pos = usize::from(pos);
+    let start = pos
+        - bytes[..pos]
+            .iter()
+            .rev()
+            .take_while(|&&b| is_path_char(b))
+            .count();
+    let end = pos
+        + bytes[pos..]
+            .iter()
+            .take_while(|&&b| is_path_char(b) && b != b'/')
+            .count();
+    let word = &content[start..pos];
+    let (dir, prefix) = word.rsplit_once('/').unwrap_or(("", word));
+    let source_range = TextRange::new(((pos - prefix.len()) as u32).into(), (end as u32).into());
+
+    let is_search_path = start > 0 && bytes[start - 1] == b'<';
+    let dirs = if is_search_path {
+        search_path_dirs(search_path, word, dir)
+    } else if word.starts_with("./") || word.starts_with("../") {
+        let sid = db.file_source_root(file_id);
+        let mut vpath = db.source_root(sid).path_for_file(file_id).clone();
+        vpath.pop();
+        for seg in dir.split('/') {
+            match seg {
+                "." => {}
+                ".." => {
+                    vpath.pop();
+                }
+                _ => vpath.push(seg)?,
+            }
+        }
+        vec![vpath]
+    } else {
+        return None;
+    };
+
+    let mut items = Vec::new();
+    if is_search_path && !word.contains('/') {
+        items.extend(
+            search_path
+                .entries()
+                .iter()
+                .filter(|entry| !entry.prefix.is_empty())
+                .map(|entry| path_to_completion(&entry.prefix, true, source_range)),
+        );
+    }
+    for dir_path in &dirs {
+        for entry in read_dir(dir_path) {
+            // Hidden files are only completed on request.
+            if entry.name.starts_with('.') && !prefix.starts_with('.')
+                || !entry.name.bytes().all(is_path_char)
+            {
+                continue;
+            }
+            let is_candidate = if entry.is_dir {
+                dir_path.join(&entry.name).map_or(false, |subdir| {
+                    read_dir(&subdir)
+                        .iter()
+                        .any(|e| !e.is_dir && e.name == DEFAULT_IMPORT_FILE)
+                })
+            } else {
+                entry.name.ends_with(".nix")
+            };
+            if is_candidate {
+                items.push(path_to_completion(&entry.name, entry.is_dir, source_range));
+            }
+        }
+    }
+    items.retain(|item| can_complete(prefix, &item.replace));
+    items.sort_by(|lhs, rhs| lhs.label.cmp(&rhs.label));
+    items.dedup_by(|lhs, rhs| lhs.label == rhs.label);
+    Some(items)
+}
+
+/// The directories `<{dir}/...>` may refer to, in the order of precedence.
+fn search_path_dirs(search_path: &SearchPath, word: &str, dir: &str) -> Vec<VfsPath> {
+    search_path
+        .entries()
+        .iter()
+        .filter_map(|entry| {
+            let rest = if entry.prefix.is_empty() {
+                dir
+            } else if !word.contains('/') {
+                // Only entries without prefix can be listed in `<|`.
+                return None;
+            } else if dir == entry.prefix {
+                ""
+            } else {
+                dir.strip_prefix(&entry.prefix)?.strip_prefix('/')?
+            };
+            let mut vpath = VfsPath::new(&entry.path);
+            if !rest.is_empty() {
+                vpath.push(rest)?;
+            }
+            Some(vpath)
+        })
+        .collect()
+}
+
+fn path_to_completion(name: &str, is_dir: bool, source_range: TextRange) -> CompletionItem {
+    CompletionItem {
+        label: name.into(),
+        source_range,
+        replace: name.into(),
+        kind: if is_dir {
+            CompletionItemKind::Folder
+        } else {
+            CompletionItemKind::File
+        },
+        signature: None,
+        description: None,
+        documentation: None,
+        additional_edits: Vec::new(),
+        command: None,
+    }
+}
+
+fn keyword_to_completion(kw: &str, source_range: TextRange) -> CompletionItem {
+    CompletionItem {
+        label: kw.into(),
+        source_range,
+        replace: kw.into(),
+        kind: CompletionItemKind::Keyword,
+        signature: None,
+        description: None,
         documentation: None,
+        additional_edits: Vec::new(),
+        command: None,
+    }
+}
+
+fn lib_doc_to_completion(source_range: TextRange, name: &str, doc: &LibDoc) -> CompletionItem {
+    let escaped_name = escape_literal_attr(name);
+    CompletionItem {
+        label: escaped_name.as_ref().into(),
+        source_range,
+        replace: escaped_name.into(),
+        kind: CompletionItemKind::Field,
+        signature: doc.signature.clone(),
+        description: doc.doc.lines().next().map(Into::into),
+        documentation: Some(doc.doc.clone()),
+        additional_edits: Vec::new(),
+        command: None,
     }
 }
 
@@ -465,79 +1410,253 @@ fn builtin_to_completion(source_range: TextRange, name: &str) -> Option<Completi
         signature: ty
             .is_known()
             .then(|| ty.display_with(TY_SIGNATURE_DISPLAY).to_string()),
-        description: Some(format!(
-            "{}\n{}",
-            ty.display_with(TY_DETAILED_DISPLAY),
+        description: Some(builtin.detail()),
+        documentation: Some(format!(
+            "{}\n`{}`\n\n{}",
             builtin.summary,
+            ty.display_with(TY_DETAILED_DISPLAY),
+            builtin.doc().unwrap_or("(No documentation from Nix)"),
         )),
-        documentation: builtin.doc.map(|s| s.to_owned()),
+        additional_edits: Vec::new(),
+        command: command_for_ty(&ty),
     })
 }
 
-// Subsequence matching.
+fn command_for_ty(ty: &Ty) -> Option<CompletionCommand> {
+    matches!(ty, Ty::Lambda(..)).then_some(CompletionCommand::TriggerParameterHints)
+}
+
+/// Refine items completed at `prev_pos` in `prev_src`, after more characters of the same word
+/// are typed, without completing again. `None` if the input is otherwise changed.
+pub fn refine_completions(
+    prev_src: &str,
+    prev_pos: TextSize,
+    src: &str,
+    pos: TextSize,
+    mut items: Vec<CompletionItem>,
+) -> Option<Vec<CompletionItem>> {
+    let (prev_end, end) = (usize::from(prev_pos), usize::from(pos));
+    let typed = src.get(prev_end..end)?;
+    if prev_src.get(..prev_end)? != src.get(..prev_end)?
+        || !typed
+            .chars()
+            .all(|ch| ch.is_alphanumeric() || "_-'+".contains(ch))
+    {
+        return None;
+    }
+    let delta = pos - prev_pos;
+    items.retain_mut(|item| {
+        let range = item.source_range;
+        if !(range.start() <= prev_pos && prev_pos <= range.end()) {
+            return false;
+        }
+        item.source_range = TextRange::new(range.start(), range.end() + delta);
+        can_complete(&src[TextRange::new(range.start(), pos)], item.filter_text())
+    });
+    rank(src, pos, &mut items);
+    Some(items)
+}
+
+/// Merge `extra` items completed elsewhere, like by evaluation, into `items` at `pos` in `src`.
+/// Extra items whose labels are already completed, or not matching the input, are dropped.
+pub fn merge_completions(
+    src: &str,
+    pos: TextSize,
+    items: &mut Vec<CompletionItem>,
+    extra: impl IntoIterator<Item = CompletionItem>,
+) {
+    let known = items
+        .iter()
+        .map(|item| item.label.clone())
+        .collect::<HashSet<_>>();
+    items.extend(extra.into_iter().filter(|item| {
+        let range = item.source_range;
+        !known.contains(&item.label)
+            && range.start() <= pos
+            && can_complete(&src[TextRange::new(range.start(), pos)], item.filter_text())
+    }));
+    rank(src, pos, items);
+}
+
 fn can_complete(prefix: &str, replace: &str) -> bool {
-    let mut rest = prefix.as_bytes();
-    if rest.is_empty() {
-        return true;
+    match_quality(prefix, replace).is_some()
+}
+
+/// How well the input matches a candidate, from worst to best.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum MatchQuality {
+    /// A subsequence, scored by matches at word starts and consecutive matches.
+    Fuzzy(u32),
+    Prefix,
+    Exact,
+}
+
+/// Match `input` as a subsequence of `candidate`. Lowercase characters of `input` match
+/// case-insensitively, so `mkd` matches `mkDerivation`.
+fn match_quality(input: &str, candidate: &str) -> Option<MatchQuality> {
+    if input == candidate {
+        return Some(MatchQuality::Exact);
     }
-    for b in replace.bytes() {
-        if rest.first().unwrap() == &b {
-            rest = &rest[1..];
-            if rest.is_empty() {
-                return true;
+    if candidate.starts_with(input) {
+        return Some(MatchQuality::Prefix);
+    }
+
+    let mut rest = input.chars().peekable();
+    let mut score = 0;
+    let mut prev = None::<char>;
+    let mut prev_matched = false;
+    for ch in candidate.chars() {
+        let Some(&expect) = rest.peek() else {
+            break;
+        };
+        let matched = if expect.is_lowercase() {
+            ch.to_lowercase().eq(expect.to_lowercase())
+        } else {
+            ch == expect
+        };
+        if matched {
+            rest.next();
+            score += 1;
+            // The start of a word in camelCase, kebab-case or snake_case.
+            let is_word_start = prev.map_or(true, |prev| {
+                !prev.is_alphanumeric() || (prev.is_lowercase() && ch.is_uppercase())
+            });
+            if is_word_start {
+                score += 2;
+            }
+            if prev_matched {
+                score += 1;
             }
         }
+        prev = Some(ch);
+        prev_matched = matched;
     }
-    false
+    rest.peek().is_none().then_some(MatchQuality::Fuzzy(score))
+}
+
+/// Sort items by how well they match the input, then locals above globals, then by labels.
+fn rank(src: &str, pos: TextSize, items: &mut [CompletionItem]) {
+    let kind_rank = |kind: CompletionItemKind| match kind {
+        CompletionItemKind::Param | CompletionItemKind::LetBinding => 0,
+        CompletionItemKind::Field
+        | CompletionItemKind::EnumMember
+        | CompletionItemKind::Input
+        | CompletionItemKind::Package
+        | CompletionItemKind::File
+        | CompletionItemKind::Folder
+        | CompletionItemKind::Evaluated => 1,
+        CompletionItemKind::BuiltinConst
+        | CompletionItemKind::BuiltinFunction
+        | CompletionItemKind::BuiltinAttrset => 2,
+        CompletionItemKind::Keyword | CompletionItemKind::Snippet => 3,
+    };
+    items.sort_by_cached_key(|item| {
+        let range = item.source_range;
+        let input = if range.start() <= pos && pos <= range.end() {
+            &src[TextRange::new(range.start(), pos)]
+        } else {
+            ""
+        };
+        let quality = match_quality(input, item.filter_text());
+        (
+            std::cmp::Reverse(quality),
+            kind_rank(item.kind),
+            item.label.clone(),
+        )
+    });
 }
 
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
 
+    use super::{CompletionCommand, CompletionItemKind, DirEntry, LibImportStrategy};
     use crate::base::SourceDatabase;
     use crate::tests::TestDB;
+    use crate::{OptionSet, SourceRootId, TextEdit, VfsPath};
     use expect_test::{expect, Expect};
+    use nix_interop::lib_docs::LibDocs;
     use nix_interop::nixos_options::{self, NixosOption, NixosOptions};
+    use nix_interop::package_index::{PackageIndex, PackageInfo};
+    use nix_interop::search_path::SearchPath;
+    use syntax::TextSize;
 
     #[track_caller]
     fn check_no(fixture: &str, label: &str) {
         let (db, f) = TestDB::from_fixture(fixture).unwrap();
-        if let Some(compes) = super::completions(&db, f[0], None) {
+        if let Some(compes) = super::completions(&db, f[0], None, LibImportStrategy::default()) {
             assert_eq!(compes.iter().find(|item| item.label == label), None);
         }
     }
 
     #[track_caller]
     fn check_trigger(fixture: &str, trigger_char: Option<char>, label: &str, expect: Expect) {
+        check_lib_import(
+            fixture,
+            trigger_char,
+            LibImportStrategy::default(),
+            label,
+            expect,
+        );
+    }
+
+    #[track_caller]
+    fn check_lib_import(
+        fixture: &str,
+        trigger_char: Option<char>,
+        lib_import: LibImportStrategy,
+        label: &str,
+        expect: Expect,
+    ) {
         let (mut db, f) = TestDB::from_fixture(fixture).unwrap();
-        db.set_nixos_options(Arc::new(NixosOptions::from_iter([(
-            "nix".into(),
-            NixosOption {
-                ty: nixos_options::Ty::Attrset {
-                    fields: NixosOptions::from_iter([(
-                        "enable".into(),
-                        NixosOption {
-                            ty: nixos_options::Ty::Bool,
-                            ..NixosOption::default()
-                        },
-                    )]),
-                    rest: None,
+        db.set_option_set(
+            OptionSet::Nixos,
+            Arc::new(NixosOptions::from_iter([(
+                "nix".into(),
+                NixosOption {
+                    ty: nixos_options::Ty::Attrset {
+                        fields: NixosOptions::from_iter([
+                            (
+                                "enable".into(),
+                                NixosOption {
+                                    ty: nixos_options::Ty::Bool,
+                                    ..NixosOption::default()
+                                },
+                            ),
+                            (
+                                "mode".into(),
+                                NixosOption {
+                                    ty: nixos_options::Ty::Enum {
+                                        values: vec!["fast".into(), "slow".into()],
+                                    },
+                                    ..NixosOption::default()
+                                },
+                            ),
+                        ]),
+                        rest: None,
+                    },
+                    ..NixosOption::default()
                 },
-                ..NixosOption::default()
-            },
-        )])));
+            )])),
+        );
 
-        let compes = super::completions(&db, f[0], trigger_char).expect("No completion");
+        let compes =
+            super::completions(&db, f[0], trigger_char, lib_import).expect("No completion");
         let item = compes
             .iter()
             .find(|item| item.label == label)
             .expect("No expected completion");
 
-        let source_range =
-            usize::from(item.source_range.start())..usize::from(item.source_range.end());
+        let mut edits = item.additional_edits.clone();
+        edits.push(TextEdit {
+            delete: item.source_range,
+            insert: item.replace.clone(),
+        });
+        edits.sort_by_key(|edit| edit.delete.start());
         let mut completed = db.file_content(f[0].file_id).to_string();
-        completed.replace_range(source_range, &item.replace);
+        for edit in edits.iter().rev() {
+            edit.apply(&mut completed);
+        }
         let got = format!("({:?}) {}", item.kind, completed);
         expect.assert_eq(&got);
     }
@@ -562,6 +1681,177 @@ mod tests {
         check("if a th$0", "then", expect!["(Keyword) if a then"]);
     }
 
+    #[test]
+    fn keyword_context() {
+        check(
+            "if a == b th$0",
+            "then",
+            expect!["(Keyword) if a == b then"],
+        );
+        check(
+            "if a then b el$0",
+            "else",
+            expect!["(Keyword) if a then b else"],
+        );
+        check("a.b o$0", "or", expect!["(Keyword) a.b or"]);
+        check("f a.b o$0", "or", expect!["(Keyword) f a.b or"]);
+        check("let a = 1; i$0", "in", expect!["(Keyword) let a = 1; in"]);
+        check("f r$0", "rec", expect!["(Keyword) f rec"]);
+        check("[ r$0 ]", "rec", expect!["(Keyword) [ rec ]"]);
+        check("a: i$0", "if", expect!["(Keyword) a: if"]);
+
+        // Not after the condition.
+        check_no("if th$0", "then");
+        check_no("if a then th$0", "then");
+        check_no("if a then b else c el$0", "else");
+        check_no("if a el$0", "else");
+        check_no("if (a th$0) then b else c", "then");
+        // Not after a select with default, or without select.
+        check_no("a.b or c o$0", "or");
+        check_no("a o$0", "or");
+        check_no("o$0", "or");
+        // Not inside let bindings, or with `in` already.
+        check_no("let a = i$0", "in");
+        check_no("let a = 1; i$0 in a", "in");
+        // Only full expressions.
+        check_no("f i$0", "if");
+        check_no("1 + l$0", "let");
+        check_no("[ w$0 ]", "with");
+        check_no("!a$0", "assert");
+    }
+
+    #[test]
+    fn snippet() {
+        check(
+            "{ stdenv }: mkDe$0",
+            "mkDerivation",
+            expect![[r#"
+                (Snippet) { stdenv }: stdenv.mkDerivation {
+                  pname = "${1:name}";
+                  version = "${2:0.1.0}";
+
+                  src = ${3:./.};
+
+                  $0
+                }"#]],
+        );
+        check(
+            "1 + le$0",
+            "let-in",
+            expect![[r#"
+                (Snippet) 1 + let
+                  ${1:name} = ${2:value};
+                in
+                $0"#]],
+        );
+        check(
+            "modu$0",
+            "module",
+            expect![[r#"
+                (Snippet) { config, lib, pkgs, ... }:
+                {
+                  $0
+                }"#]],
+        );
+        // Module skeletons are only for the top-level.
+        check_no("[ modu$0 ]", "module");
+        check_no("mkDe$0", "let-in");
+    }
+
+    #[test]
+    fn inherit_name() {
+        check(
+            "let foo = 1; bar = 2; in { inherit ba$0; }",
+            "bar",
+            expect!["(LetBinding) let foo = 1; bar = 2; in { inherit bar; }"],
+        );
+        check(
+            "{ inherit ma$0; }",
+            "map",
+            expect!["(BuiltinFunction) { inherit map; }"],
+        );
+        check(
+            "let s = { foo = 1; bar = 2; }; in { inherit (s) fo$0; }",
+            "foo",
+            expect!["(Field) let s = { foo = 1; bar = 2; }; in { inherit (s) foo; }"],
+        );
+        // Names already inherited are skipped.
+        check_no("let foo = 1; in { inherit foo f$0; }", "foo");
+        check_no("let s = { foo = 1; }; in { inherit (s) foo f$0; }", "foo");
+        // Fields are not the enclosing scope.
+        check_no("let s = { foo = 1; }; in { inherit (s) fo$0; }", "s");
+        check_no("let s = { foo = 1; }; in { inherit fo$0; }", "foo");
+    }
+
+    #[test]
+    fn interpolation() {
+        check(
+            r#"let a.foo = 1; in "${a.f$0}""#,
+            "foo",
+            expect![[r#"(Field) let a.foo = 1; in "${a.foo}""#]],
+        );
+        check_trigger(
+            r#"let a.foo = 1; in "${a.$0}""#,
+            Some('.'),
+            "foo",
+            expect![[r#"(Field) let a.foo = 1; in "${a.foo}""#]],
+        );
+        check(
+            "let a.foo = 1; in ''${a.f$0}''",
+            "foo",
+            expect!["(Field) let a.foo = 1; in ''${a.foo}''"],
+        );
+        check(
+            r#"let a.foo = 1; in "${a.$0}""#,
+            "foo",
+            expect![[r#"(Field) let a.foo = 1; in "${a.foo}""#]],
+        );
+        check_trigger(
+            r#"let foo = 1; in "${$0}""#,
+            Some('{'),
+            "foo",
+            expect![[r#"(LetBinding) let foo = 1; in "${foo}""#]],
+        );
+        check(
+            "let foo = 1; in ''x${$0}''",
+            "foo",
+            expect!["(LetBinding) let foo = 1; in ''x${foo}''"],
+        );
+        check(
+            r#"let foo = 1; in ./${$0}"#,
+            "foo",
+            expect!["(LetBinding) let foo = 1; in ./${foo}"],
+        );
+        check(
+            r#"let foo = 1; in "${$0 }""#,
+            "foo",
+            expect![[r#"(LetBinding) let foo = 1; in "${foo }""#]],
+        );
+        // Unclosed interpolations while typing.
+        check_trigger(
+            "let a.foo = 1; in { x = \"${a.$0\";\n  y = a; }",
+            Some('.'),
+            "foo",
+            expect![[r#"
+                (Field) let a.foo = 1; in { x = "${a.foo";
+                  y = a; }"#]],
+        );
+        check(
+            r#"let foo = 1; in "x${fo$0}y""#,
+            "foo",
+            expect![[r#"(LetBinding) let foo = 1; in "x${foo}y""#]],
+        );
+        check_trigger(
+            "let a.foo = 1; in ''\n  ${a.$0}\n''",
+            Some('.'),
+            "foo",
+            expect![[r#"
+                (Field) let a.foo = 1; in ''
+                  ${a.foo}
+                ''"#]],
+        );
+    }
+
     #[test]
     fn local_binding() {
         check(
@@ -604,6 +1894,107 @@ mod tests {
         );
     }
 
+    #[track_caller]
+    fn check_order(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let compes = super::completions(&db, f[0], None, LibImportStrategy::default()).unwrap();
+        let got = compes
+            .iter()
+            .map(|item| format!("{:?} {}\n", item.kind, item.label))
+            .collect::<String>();
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn fuzzy_match() {
+        use super::{match_quality, MatchQuality};
+
+        assert_eq!(match_quality("foo", "foo"), Some(MatchQuality::Exact));
+        assert_eq!(match_quality("fo", "foo"), Some(MatchQuality::Prefix));
+        assert_eq!(
+            match_quality("mkd", "mkDerivation"),
+            Some(MatchQuality::Fuzzy(9))
+        );
+        assert_eq!(
+            match_quality("gs", "gnome-shell"),
+            Some(MatchQuality::Fuzzy(6))
+        );
+        assert_eq!(match_quality("gs", "gnomes"), Some(MatchQuality::Fuzzy(4)));
+        // Uppercase characters are case-sensitive.
+        assert_eq!(match_quality("mD", "mkdir"), None);
+        assert_eq!(match_quality("ba", "foo"), None);
+    }
+
+    #[test]
+    fn rank() {
+        check_order(
+            "let fooBar = 1; fb = 2; xfyb = 3; in fb$0",
+            expect![[r#"
+                LetBinding fb
+                LetBinding fooBar
+                BuiltinFunction fetchTarball
+                LetBinding xfyb
+            "#]],
+        );
+        check_order(
+            "let toStr = 1; in toS$0",
+            expect![[r#"
+            LetBinding toStr
+            BuiltinFunction toString
+            BuiltinFunction derivationStrict
+        "#]],
+        );
+    }
+
+    #[test]
+    fn refine() {
+        let (db, f) = TestDB::from_fixture("let foo = 1; fooBar = 2; in f$0 + 1").unwrap();
+        let prev_src = db.file_content(f[0].file_id);
+        let compes = super::completions(&db, f[0], None, LibImportStrategy::default()).unwrap();
+        let prev_pos = f[0].pos;
+
+        let refine = |typed: &str| {
+            let src = prev_src.replace("in f", &format!("in f{typed}"));
+            let pos = prev_pos + TextSize::of(typed);
+            let items = super::refine_completions(&prev_src, prev_pos, &src, pos, compes.clone())?;
+            Some(
+                items
+                    .iter()
+                    .map(|item| format!("{} {}\n", item.label, &src[item.source_range]))
+                    .collect::<String>(),
+            )
+        };
+        expect![[r#"
+            fooBar foB
+        "#]]
+        .assert_eq(&refine("oB").unwrap());
+        assert_eq!(refine(" "), None);
+        assert_eq!(refine("."), None);
+    }
+
+    #[test]
+    fn builtin_detail() {
+        let (db, f) = TestDB::from_fixture("builtins.$0").unwrap();
+        let compes =
+            super::completions(&db, f[0], Some('.'), LibImportStrategy::default()).unwrap();
+        let detail = |label: &str| {
+            compes
+                .iter()
+                .find(|item| item.label == label)
+                .and_then(|item| item.description.clone())
+                .unwrap()
+        };
+        expect![[r#"
+            1 argument: Return the names of the attributes in the set *set* in an alphabetically sorted list.
+        "#]]
+        .assert_eq(&(detail("attrNames") + "\n"));
+        assert!(detail("elemAt").starts_with("2 arguments: "));
+        assert!(detail("getEnv").starts_with("1 argument: "));
+        assert!(detail("getEnv").ends_with(" (impure)"));
+        assert!(!detail("currentTime").contains("argument"));
+        assert!(detail("currentTime").ends_with(" (impure)"));
+    }
+
     #[test]
     fn inherit() {
         check("{ i$0 }", "inherit", expect!["(Keyword) { inherit }"]);
@@ -844,6 +2235,247 @@ stdenv.mkDerivation {
         );
     }
 
+    #[test]
+    fn option_sets() {
+        let option = |name: &str, ty, doc: Option<&str>| {
+            let opt = NixosOption {
+                ty,
+                description: doc.map(|text| nixos_options::Doc::Markdown { text: text.into() }),
+                ..NixosOption::default()
+            };
+            (name.to_owned(), opt)
+        };
+        let attrset = |fields: Vec<(String, NixosOption)>| nixos_options::Ty::Attrset {
+            fields: NixosOptions::from_iter(fields),
+            rest: None,
+        };
+        let home_manager = NixosOptions::from_iter([
+            option("home", attrset(Vec::new()), None),
+            option(
+                "programs",
+                attrset(vec![option(
+                    "git",
+                    attrset(vec![option(
+                        "enable",
+                        nixos_options::Ty::Bool,
+                        Some("Whether to enable Git."),
+                    )]),
+                    None,
+                )]),
+                None,
+            ),
+        ]);
+        let darwin = NixosOptions::from_iter([option(
+            "launchd",
+            attrset(vec![option(
+                "agents",
+                nixos_options::Ty::Any,
+                Some("Agents."),
+            )]),
+            None,
+        )]);
+
+        let complete = |fixture: &str, label: &str| {
+            let (mut db, f) = TestDB::from_fixture(fixture).unwrap();
+            let paths = vec![VfsPath::new("/home")];
+            db.set_option_set_module_paths(OptionSet::HomeManager, Arc::new(paths));
+            db.set_option_set(OptionSet::HomeManager, Arc::new(home_manager.clone()));
+            db.set_option_set(OptionSet::NixDarwin, Arc::new(darwin.clone()));
+            let compes = super::completions(&db, f[0], None, LibImportStrategy::default())?;
+            let item = compes.into_iter().find(|item| item.label == label)?;
+            Some(item.documentation.unwrap_or_default())
+        };
+
+        assert_eq!(
+            complete(
+                "{ ... }: { home.stateVersion = \"24.05\"; programs.git.e$0 }",
+                "enable",
+            ),
+            Some("Whether to enable Git.".into()),
+        );
+        assert_eq!(
+            complete("{ osConfig, ... }: { programs.git = { e$0 }; }", "enable",),
+            Some("Whether to enable Git.".into()),
+        );
+        assert_eq!(
+            complete(
+                "{ ... }: { imports = [ inputs.foo.homeManagerModules.default ]; config.programs.git.e$0 }",
+                "enable",
+            ),
+            Some("Whether to enable Git.".into()),
+        );
+        assert_eq!(
+            complete("{ ... }: { homebrew = { }; launchd.a$0 }", "agents"),
+            Some("Agents.".into()),
+        );
+        // NixOS modules have no such options.
+        assert_eq!(complete("{ ... }: { programs.git.e$0 }", "enable"), None);
+        // Configured directories.
+        assert_eq!(
+            complete("#- /home/git.nix\n{ ... }: { programs.git.e$0 }", "enable"),
+            Some("Whether to enable Git.".into()),
+        );
+    }
+
+    #[test]
+    fn nixos_enum_value() {
+        check(
+            r#"{ ... }: { nix.mode = "f$0"; }"#,
+            r#""fast""#,
+            expect![[r#"(EnumMember) { ... }: { nix.mode = "fast"; }"#]],
+        );
+        check(
+            r#"{ ... }: { nix.mode = "$0"; }"#,
+            r#""slow""#,
+            expect![[r#"(EnumMember) { ... }: { nix.mode = "slow"; }"#]],
+        );
+        check_no(r#"{ ... }: { nix.mode = "f$0"; }"#, r#""slow""#);
+        check_no(r#"{ ... }: { nix.enable = "f$0"; }"#, r#""fast""#);
+    }
+
+    #[test]
+    fn flake_input_follows() {
+        let fixture = r#"
+#- /flake.nix
+{
+    inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+    inputs.home-manager.url = "github:nix-community/home-manager";
+    inputs.home-manager.inputs.nixpkgs.follows = "n$0";
+    outputs = { ... }: { };
+}
+        "#;
+        check(
+            fixture,
+            r#""nixpkgs""#,
+            expect![[r#"
+                (Input) {
+                    inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+                    inputs.home-manager.url = "github:nix-community/home-manager";
+                    inputs.home-manager.inputs.nixpkgs.follows = "nixpkgs";
+                    outputs = { ... }: { };
+                }"#]],
+        );
+        check_no(&fixture.replace("n$0", "ni$0"), r#""home-manager""#);
+        // Auto-closed by editors.
+        check_trigger(
+            &fixture.replace("n$0", "$0"),
+            Some('"'),
+            r#""nixpkgs""#,
+            expect![[r#"
+                (Input) {
+                    inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+                    inputs.home-manager.url = "github:nix-community/home-manager";
+                    inputs.home-manager.inputs.nixpkgs.follows = "nixpkgs";
+                    outputs = { ... }: { };
+                }"#]],
+        );
+        let (db, f) = TestDB::from_fixture(&fixture.replace("\"n$0\"", "\"n\"$0")).unwrap();
+        assert_eq!(
+            super::completions(&db, f[0], Some('"'), LibImportStrategy::default()),
+            None,
+        );
+        check_no(&fixture.replace("follows", "url"), r#""nixpkgs""#);
+    }
+
+    #[test]
+    fn fetcher_arg() {
+        check(
+            "{ fetchFromGitHub }: fetchFromGitHub { ow$0 }",
+            "owner",
+            expect!["(Field) { fetchFromGitHub }: fetchFromGitHub { owner }"],
+        );
+        check(
+            "{ pkgs }: pkgs.fetchurl { url = \"\"; h$0 }",
+            "hash",
+            expect![[r#"(Field) { pkgs }: pkgs.fetchurl { url = ""; hash }"#]],
+        );
+        check(
+            "{ lib, fetchzip }: fetchzip { h$0 }",
+            "hash = lib.fakeHash",
+            expect!["(Field) { lib, fetchzip }: fetchzip { hash = lib.fakeHash; }"],
+        );
+        check(
+            "{ fetchzip }: fetchzip { h$0 }",
+            "hash = \"\"",
+            expect![[r#"(Field) { fetchzip }: fetchzip { hash = ""; }"#]],
+        );
+        check_no("{ fetchzip }: fetchzip { h$0 }", "hash = lib.fakeHash");
+        check_no("{ foo }: foo { h$0 }", "hash = \"\"");
+        check_no("{ fetchzip }: fetchzip { a.h$0 }", "hash = \"\"");
+    }
+
+    #[test]
+    fn mk_shell_arg() {
+        check(
+            "{ pkgs }: pkgs.mkShell { pack$0 }",
+            "packages",
+            expect!["(Field) { pkgs }: pkgs.mkShell { packages }"],
+        );
+        check(
+            "{ mkShell }: mkShell { name = \"foo\"; shellH$0 }",
+            "shellHook",
+            expect![[r#"(Field) { mkShell }: mkShell { name = "foo"; shellHook }"#]],
+        );
+        check_no("{ pkgs }: pkgs.foo { pack$0 }", "packages");
+    }
+
+    #[test]
+    fn imported_attrset() {
+        check(
+            "
+#- /default.nix
+let lib = import ./lib.nix; in lib.f$0
+#- /lib.nix
+rec { foo = 1; }
+            ",
+            "foo",
+            expect!["(Field) let lib = import ./lib.nix; in lib.foo"],
+        );
+        check(
+            "
+#- /default.nix
+(import ./options.nix).f$0
+#- /options.nix
+{ foo = 1; }
+            ",
+            "foo",
+            expect!["(Field) (import ./options.nix).foo"],
+        );
+        check_trigger(
+            "
+#- /default.nix
+let mylib = import ./lib; in mylib.$0
+#- /lib/default.nix
+let foo = 1; in { inherit foo; }
+            ",
+            Some('.'),
+            "foo",
+            expect!["(Field) let mylib = import ./lib; in mylib.foo"],
+        );
+        check(
+            "
+#- /default.nix
+(import ./lib.nix).f$0
+#- /lib.nix
+{ bar = 1; } // { foo = 2; }
+            ",
+            "foo",
+            expect!["(Field) (import ./lib.nix).foo"],
+        );
+        check(
+            "
+#- /default.nix
+(import ./lib.nix).f$0
+#- /lib.nix
+{ inherit (import ./other.nix) foo; }
+#- /other.nix
+{ foo = 1; }
+            ",
+            "foo",
+            expect!["(Field) (import ./lib.nix).foo"],
+        );
+    }
+
     #[test]
     fn escape_attr() {
         check(
@@ -858,4 +2490,352 @@ stdenv.mkDerivation {
         check_no(r#"let "a b" = 1; in a$0"#, "a b");
         check_no(r#"let "a b" = 1; in a$0"#, r#""a b""#);
     }
+
+    #[test]
+    fn command() {
+        let (db, f) = TestDB::from_fixture("let fa = x: x; fb = 1; in f$0").unwrap();
+        let items = super::completions(&db, f[0], None, LibImportStrategy::default()).unwrap();
+        let command = |label: &str| {
+            items
+                .iter()
+                .find(|item| item.label == label)
+                .unwrap()
+                .command
+        };
+        assert_eq!(
+            command("fa"),
+            Some(CompletionCommand::TriggerParameterHints)
+        );
+        assert_eq!(command("fb"), None);
+        assert_eq!(
+            command("fetchTarball"),
+            Some(CompletionCommand::TriggerParameterHints)
+        );
+        assert_eq!(command("false"), None);
+    }
+
+    #[test]
+    fn lib_import() {
+        check(
+            "{ pkgs }: pkgs.mkShell { shellHook = lib.optional$0; }",
+            "optionalString",
+            expect!["(Field) { pkgs, lib }: pkgs.mkShell { shellHook = lib.optionalString; }"],
+        );
+        check_trigger(
+            "x: { a = lib.$0; }",
+            Some('.'),
+            "mkIf",
+            expect!["(Field) x: let lib = import <nixpkgs/lib>; in { a = lib.mkIf; }"],
+        );
+        check_lib_import(
+            "{ pkgs }: lib.mk$0",
+            None,
+            LibImportStrategy::Let,
+            "mkIf",
+            expect!["(Field) { pkgs }: let lib = pkgs.lib; in lib.mkIf"],
+        );
+        check(
+            "{ lib, ... }: { a = lib.mk$0; }",
+            "mkDefault",
+            expect!["(Field) { lib, ... }: { a = lib.mkDefault; }"],
+        );
+        check_no("{ lib }: lib.mk$0", "mkDefault");
+    }
+
+    #[track_caller]
+    fn check_lib_docs(fixture: &str, expect: Expect) {
+        let (mut db, f) = TestDB::from_fixture(fixture).unwrap();
+        db.set_lib_docs(Arc::new(LibDocs::from_sources([
+            (
+                "strings",
+                "{ lib }: rec {
+                    /** Determine whether a string has given prefix.
+
+                      # Type
+
+                      ```
+                      hasPrefix :: string -> string -> bool
+                      ```
+                    */
+                    hasPrefix = pref: str: true;
+                    /* Convert to lower case. */
+                    toLower = s: s;
+                }",
+            ),
+            (
+                "default",
+                "self: { inherit (self.strings) hasPrefix toLower; }",
+            ),
+        ])));
+        let compes =
+            super::completions(&db, f[0], Some('.'), LibImportStrategy::Off).unwrap_or_default();
+        let mut got = compes
+            .iter()
+            .filter(|item| item.documentation.is_some() || item.label == "strings")
+            .map(|item| {
+                format!(
+                    "{} {:?} {:?}\n",
+                    item.label, item.signature, item.description
+                )
+            })
+            .collect::<Vec<_>>();
+        got.sort();
+        expect.assert_eq(&got.concat());
+    }
+
+    #[test]
+    fn lib_docs() {
+        check_lib_docs(
+            "{ lib }: lib.strings.$0",
+            expect![[r#"
+                hasPrefix Some("string -> string -> bool") Some("Determine whether a string has given prefix.")
+                toLower None Some("Convert to lower case.")
+            "#]],
+        );
+        // Sub-libraries and functions re-exported at the top-level.
+        check_lib_docs(
+            "{ lib }: lib.$0",
+            expect![[r#"
+                hasPrefix Some("string -> string -> bool") Some("Determine whether a string has given prefix.")
+                strings None None
+                toLower None Some("Convert to lower case.")
+            "#]],
+        );
+        check_lib_docs("{ lib }: lib.strings.hasPrefix.$0", expect![""]);
+        check_lib_docs("{ foo }: foo.strings.$0", expect![""]);
+    }
+
+    #[test]
+    fn overlay_final() {
+        check(
+            "final: prev: { foo = 1; bar = final.f$0; }",
+            "foo",
+            expect!["(Field) final: prev: { foo = 1; bar = final.foo; }"],
+        );
+    }
+
+    #[track_caller]
+    fn check_package(fixture: &str, expect: Expect) {
+        let (mut db, f) = TestDB::from_fixture(fixture).unwrap();
+        db.set_package_index(Arc::new(PackageIndex::from_iter([
+            (
+                "ripgrep".into(),
+                PackageInfo {
+                    version: Some("14.1.0".into()),
+                    description: Some("A fast grep".into()),
+                },
+            ),
+            (
+                "ripgrep-all".into(),
+                PackageInfo {
+                    version: Some("0.10.6".into()),
+                    description: None,
+                },
+            ),
+            ("rPackages".into(), PackageInfo::default()),
+            ("hello".into(), PackageInfo::default()),
+        ])));
+        let compes =
+            super::completions(&db, f[0], None, LibImportStrategy::default()).unwrap_or_default();
+        let mut got = compes
+            .iter()
+            .filter(|item| item.kind == CompletionItemKind::Package)
+            .map(|item| format!("{} {:?}\n", item.label, item.description))
+            .collect::<Vec<_>>();
+        got.sort();
+        expect.assert_eq(&got.concat());
+    }
+
+    #[test]
+    fn package_index() {
+        check_package(
+            "{ pkgs }: pkgs.rg$0",
+            expect![[r#"
+                rPackages None
+                ripgrep Some("14.1.0: A fast grep")
+                ripgrep-all Some("0.10.6")
+            "#]],
+        );
+        check_package(
+            "let pkgs = import <nixpkgs> { }; in pkgs.rp$0",
+            expect![[r#"
+                rPackages None
+                ripgrep Some("14.1.0: A fast grep")
+                ripgrep-all Some("0.10.6")
+            "#]],
+        );
+        check_package(
+            "{ pkgs }: { inherit (pkgs) hello ripgrep rip$0; }",
+            expect![[r#"
+                ripgrep-all Some("0.10.6")
+            "#]],
+        );
+        check_package(
+            r#"{ pkgs }: "${pkgs.rip$0}/bin/rg""#,
+            expect![[r#"
+                ripgrep Some("14.1.0: A fast grep")
+                ripgrep-all Some("0.10.6")
+            "#]],
+        );
+        // `final` and `prev` of overlays.
+        check_package(
+            "final: prev: { ripgrep = prev.rip$0; }",
+            expect![[r#"
+                ripgrep Some("14.1.0: A fast grep")
+                ripgrep-all Some("0.10.6")
+            "#]],
+        );
+        check_package(
+            "self: super: { inherit (self) rip$0; }",
+            expect![[r#"
+                ripgrep Some("14.1.0: A fast grep")
+                ripgrep-all Some("0.10.6")
+            "#]],
+        );
+        // Not the package set, or not the top-level.
+        check_package("a: b: { c = b.rg$0; }", expect![""]);
+        check_package("{ foo }: foo.rg$0", expect![""]);
+        check_package("{ pkgs }: pkgs.hello.rg$0", expect![""]);
+        check_package("{ pkgs }: { pkgs.rg$0 = 1; }", expect![""]);
+
+        // Recognized by definitions instead of names.
+        check_package(
+            "let p = import <nixpkgs> { }; in p.rip$0",
+            expect![[r#"
+                ripgrep Some("14.1.0: A fast grep")
+                ripgrep-all Some("0.10.6")
+            "#]],
+        );
+        check_package(
+            "{ inputs, system }: let p = inputs.nixpkgs.legacyPackages.${system}; in p.rip$0",
+            expect![[r#"
+                ripgrep Some("14.1.0: A fast grep")
+                ripgrep-all Some("0.10.6")
+            "#]],
+        );
+        check_package("let pkgs = { }; in pkgs.rg$0", expect![""]);
+        check_package("with foo; pkgs.rg$0", expect![""]);
+    }
+
+    #[track_caller]
+    fn check_path(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let search_path = SearchPath::parse("nixpkgs=/nixpkgs:/channels");
+        // Directory entries are derived from paths of fixture files.
+        let paths = db
+            .source_root(SourceRootId(0))
+            .files()
+            .map(|(_, path)| path.as_path().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        let read_dir = |dir: &VfsPath| {
+            let dir = dir.as_path().unwrap();
+            let mut entries = paths
+                .iter()
+                .filter_map(|path| {
+                    let mut rest = path.strip_prefix(dir).ok()?.iter();
+                    let name = rest.next()?.to_str().unwrap().to_owned();
+                    let is_dir = rest.next().is_some();
+                    Some(DirEntry { name, is_dir })
+                })
+                .collect::<Vec<_>>();
+            entries.dedup();
+            entries
+        };
+        let compes =
+            super::path_completions(&db, f[0], &search_path, read_dir).expect("No completion");
+        let got = compes
+            .iter()
+            .map(|item| {
+                let mut completed = db.file_content(f[0].file_id).to_string();
+                TextEdit {
+                    delete: item.source_range,
+                    insert: item.replace.clone(),
+                }
+                .apply(&mut completed);
+                format!("({:?}) {}\n", item.kind, completed)
+            })
+            .collect::<String>();
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn relative_path() {
+        let fixture = "
+#- /default.nix
+import ./$0
+#- /foo.nix
+#- /.hidden.nix
+#- /README.md
+#- /lib/default.nix
+#- /pkgs/a.nix
+#- /pkgs/b/default.nix
+        ";
+        check_path(
+            fixture,
+            expect![[r#"
+                (File) import ./default.nix
+                (File) import ./foo.nix
+                (Folder) import ./lib
+            "#]],
+        );
+        check_path(
+            &fixture.replace("./$0", "./pkgs/$0"),
+            expect![[r#"
+                (File) import ./pkgs/a.nix
+                (Folder) import ./pkgs/b
+            "#]],
+        );
+        check_path(
+            &fixture.replace("./$0", "./pkgs/../f$0o"),
+            expect![[r#"
+                (File) import ./pkgs/../foo.nix
+                (File) import ./pkgs/../default.nix
+            "#]],
+        );
+        check_path(
+            &fixture.replace("./$0", "./.$0"),
+            expect![[r#"
+                (File) import ./.hidden.nix
+                (File) import ./default.nix
+                (File) import ./foo.nix
+            "#]],
+        );
+    }
+
+    #[test]
+    fn search_path() {
+        let fixture = "
+#- /default.nix
+<$0
+#- /nixpkgs/default.nix
+#- /nixpkgs/lib/default.nix
+#- /channels/home-manager/default.nix
+#- /channels/foo.nix
+        ";
+        check_path(
+            fixture,
+            expect![[r#"
+                (File) <foo.nix
+                (Folder) <home-manager
+                (Folder) <nixpkgs
+            "#]],
+        );
+        check_path(
+            &fixture.replace("<$0", "<nixpkgs/lib$0>"),
+            expect![[r#"
+                (Folder) <nixpkgs/lib>
+            "#]],
+        );
+    }
+
+    #[test]
+    fn no_path() {
+        let (db, f) = TestDB::from_fixture(r#"[ 1 /$0 2 "./$1" /* ./$2 */ foo/$3 ]"#).unwrap();
+        for &fpos in f.markers() {
+            assert_eq!(
+                super::path_completions(&db, fpos, &SearchPath::default(), |_| Vec::new()),
+                None
+            );
+        }
+    }
 }
@@ -1,16 +1,35 @@
-use crate::{DefDatabase, Diagnostic, FileId};
+use super::flake_schema::flake_schema;
+use super::suppression::Suppressions;
+use crate::def::{
+    AstPtr, BindingValue, Expr, ExprId, Literal, NameId, NameKind, NameResolution, PathAnchor,
+    ResolveResult,
+};
+use crate::ty::{is_mk_shell, known, TyConflict, TyKind};
+use crate::{
+    DefDatabase, Diagnostic, DiagnosticKind, FileId, FileRange, LanguageFeature, Module,
+    ModuleKind, TyDatabase, VfsPath,
+};
+use std::collections::{HashMap, HashSet};
+use syntax::ast::{self, AstNode, BinaryOpKind, LiteralKind, UnaryOpKind};
+use syntax::semantic::{escape_string, AttrKind};
+use syntax::{SyntaxKind, SyntaxNodePtr, T};
 
-pub(crate) fn diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic> {
+// Generic over the database, since trait upcasting to `dyn DefDatabase` is not available.
+pub(crate) fn diagnostics(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
     let mut diags = Vec::new();
 
     // Parsing.
     let parse = db.parse(file);
     diags.extend(parse.errors().iter().map(|&err| Diagnostic::from(err)));
+    diags.extend(disabled_language_features(db, file));
 
     // Lowering.
     let source_map = db.source_map(file);
     diags.extend(source_map.diagnostics().iter().cloned());
 
+    // Attrset updates.
+    diags.extend(overridden_keys(db, file));
+
     // Name resolution.
     diags.extend(db.name_resolution(file).to_diagnostics(db, file));
 
@@ -18,13 +37,1102 @@ pub(crate) fn diagnostics(db: &dyn DefDatabase, file: FileId) -> Vec<Diagnostic>
     let liveness = db.liveness_check(file);
     diags.extend(liveness.to_diagnostics(db, file));
 
+    // Shadowing.
+    diags.extend(shadowed_bindings(db, file));
+
+    // Evaluation.
+    diags.extend(infinite_recursions(db, file));
+    diags.extend(type_mismatches(db, file));
+    diags.extend(unreachable_code(db, file));
+
+    // Option types.
+    let module = db.module(file);
+    let mut enum_diags = db
+        .option_enum_values(file)
+        .iter()
+        .filter_map(|(expr, values)| {
+            let Expr::Literal(Literal::String(text)) = &module[expr] else {
+                return None;
+            };
+            if values.iter().any(|v| v == text) {
+                return None;
+            }
+            let range = source_map.node_for_expr(expr)?.text_range();
+            let expected = values
+                .iter()
+                .map(|v| escape_string(v))
+                .collect::<Vec<_>>()
+                .join(", ");
+            Some(
+                Diagnostic::new(range, DiagnosticKind::InvalidEnumValue).with_note(
+                    FileRange::new(file, range),
+                    format!("Expecting one of {expected}"),
+                ),
+            )
+        })
+        .collect::<Vec<_>>();
+    // Keep the order deterministic.
+    enum_diags.sort_by_key(|diag| diag.range.start());
+    diags.extend(enum_diags);
+
+    // Option definitions.
+    diags.extend(conflicting_definitions(db, file));
+
+    // Nixpkgs attributes.
+    diags.extend(db.deprecated_packages(file).to_diagnostics(db, file));
+    diags.extend(missing_package_args(db, file));
+
+    // Development shells.
+    diags.extend(shell_args(db, file));
+
+    // Static analysis gaps.
+    diags.extend(analysis_gaps(db, file));
+
+    // Builtins.
+    diags.extend(deprecated_builtins(db, file));
+
+    // Flakes.
+    diags.extend(flake_schema(db, file));
+
+    // Style.
+    diags.extend(redundant_parens(db, file));
+    diags.extend(redundant_booleans(db, file));
+    diags.extend(manual_inherits(db, file));
+    diags.extend(eta_reductions(db, file));
+
+    // Suppression comments.
+    let suppressions = Suppressions::collect(&parse.syntax_node(), &db.file_content(file));
+    diags.retain(|diag| !suppressions.is_suppressed(diag));
+
     diags
 }
 
+/// Find experimental syntax which is parsed but not enabled by `nix.languageFeatures`.
+fn disabled_language_features(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let enabled = db.language_features();
+    db.parse(file)
+        .syntax_node()
+        .descendants_with_tokens()
+        .filter_map(|elem| elem.into_token())
+        .filter_map(|tok| {
+            let feat = match tok.kind() {
+                T![|>] | T![<|] => LanguageFeature::PipeOperators,
+                _ => return None,
+            };
+            if enabled.contains(&feat) {
+                return None;
+            }
+            let range = tok.text_range();
+            Some(
+                Diagnostic::new(range, DiagnosticKind::DisabledLanguageFeature(feat)).with_note(
+                    FileRange::new(file, range),
+                    format!(
+                        "Add `{}` to `nix.languageFeatures` to enable it",
+                        feat.name()
+                    ),
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Find definitions shadowing ones of the same name in outer scopes, except for configured
+/// names, names starting with `_`, and `inherit name;` which are the same value.
+fn shadowed_bindings(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let ignored = db.shadowing_ignored_names();
+    let inherited = module
+        .exprs()
+        .filter_map(|(_, kind)| match kind {
+            Expr::LetIn(bindings, _) | Expr::RecAttrset(bindings) => Some(bindings),
+            _ => None,
+        })
+        .flat_map(|bindings| bindings.statics.iter())
+        .filter(|(_, value)| matches!(value, BindingValue::Inherit(_)))
+        .map(|&(name, _)| name)
+        .collect::<HashSet<_>>();
+
+    let mut ret = db
+        .scopes(file)
+        .shadowings()
+        .filter_map(|(name, shadowed)| {
+            let text = &*module[name].text;
+            if text.starts_with('_')
+                || ignored.iter().any(|s| s == text)
+                || inherited.contains(&name)
+            {
+                return None;
+            }
+            let range = source_map.nodes_for_name(name).next()?.text_range();
+            let shadowed_range = source_map.nodes_for_name(shadowed).next()?.text_range();
+            Some(
+                Diagnostic::new(range, DiagnosticKind::ShadowedBinding).with_note(
+                    FileRange::new(file, shadowed_range),
+                    format!("The shadowed definition of `{text}`"),
+                ),
+            )
+        })
+        .collect::<Vec<_>>();
+    // Keep the order deterministic.
+    ret.sort_by_key(|diag| diag.range.start());
+    ret
+}
+
+/// Find bindings of `let-in` and `rec` attrsets which always diverge when evaluated, since their
+/// values strictly depend on themselves, like `let x = x + 1; in x`.
+/// Only dependencies forced in every branch are considered, so lazy positions like attrset
+/// values, list elements and function arguments break cycles.
+fn infinite_recursions(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let nameres = db.name_resolution(file);
+
+    // Definitions forced by evaluating `expr` to the weak head normal form.
+    fn forced(module: &Module, nameres: &NameResolution, expr: ExprId) -> HashSet<NameId> {
+        let go = |e| forced(module, nameres, e);
+        match &module[expr] {
+            Expr::Reference(_) => match nameres.get(expr) {
+                Some(&ResolveResult::Definition(name)) => HashSet::from([name]),
+                _ => HashSet::new(),
+            },
+            &Expr::Unary(_, e)
+            | &Expr::Apply(e, _)
+            | &Expr::Binary(Some(BinaryOpKind::PipeFrom), e, _)
+            | &Expr::Binary(Some(BinaryOpKind::PipeInto), _, e)
+            | &Expr::Binary(
+                Some(BinaryOpKind::And | BinaryOpKind::Or | BinaryOpKind::Imply),
+                e,
+                _,
+            )
+            | &Expr::HasAttr(e, _)
+            | &Expr::Select(e, _, _)
+            | &Expr::With(_, e)
+            | &Expr::LetIn(_, e) => go(e),
+            &Expr::Binary(_, lhs, rhs) | &Expr::Assert(lhs, rhs) => {
+                let mut ret = go(lhs);
+                ret.extend(go(rhs));
+                ret
+            }
+            &Expr::IfThenElse(cond, then, else_) => {
+                let mut ret = go(cond);
+                let then = go(then);
+                ret.extend(go(else_).into_iter().filter(|name| then.contains(name)));
+                ret
+            }
+            Expr::StringInterpolation(parts) | Expr::PathInterpolation(parts) => {
+                parts.iter().flat_map(|&e| go(e)).collect()
+            }
+            Expr::Missing
+            | Expr::Literal(_)
+            | Expr::Lambda(..)
+            | Expr::List(_)
+            | Expr::Attrset(_)
+            | Expr::LetAttrset(_)
+            | Expr::RecAttrset(_) => HashSet::new(),
+        }
+    }
+
+    let mut deps = HashMap::new();
+    for (_, kind) in module.exprs() {
+        let (Expr::LetIn(bindings, _) | Expr::RecAttrset(bindings)) = kind else {
+            continue;
+        };
+        for &(name, value) in bindings.statics.iter() {
+            let value = match value {
+                BindingValue::Expr(e) | BindingValue::Inherit(e) => e,
+                BindingValue::InheritFrom(i) => bindings.inherit_froms[i],
+            };
+            deps.insert(name, forced(&module, &nameres, value));
+        }
+    }
+
+    // Drop definitions not reaching cycles, then ones not reached from cycles.
+    loop {
+        let len = deps.len();
+        let alive = deps.keys().copied().collect::<HashSet<_>>();
+        deps.retain(|_, names: &mut HashSet<NameId>| {
+            names.retain(|name| alive.contains(name));
+            !names.is_empty()
+        });
+        if deps.len() == len {
+            break;
+        }
+    }
+    loop {
+        let len = deps.len();
+        let reached = deps.values().flatten().copied().collect::<HashSet<_>>();
+        deps.retain(|name, _| reached.contains(name));
+        if deps.len() == len {
+            break;
+        }
+    }
+
+    let name_range = |name| Some(source_map.nodes_for_name(name).next()?.text_range());
+    let mut ret = deps
+        .iter()
+        .filter_map(|(&name, names)| {
+            let range = name_range(name)?;
+            let (next_range, next) = names
+                .iter()
+                .filter_map(|&next| Some((name_range(next)?, next)))
+                .min_by_key(|(range, _)| range.start())?;
+            Some(
+                Diagnostic::new(range, DiagnosticKind::InfiniteRecursion).with_note(
+                    FileRange::new(file, next_range),
+                    format!(
+                        "Evaluating it requires the value of `{}`",
+                        module[next].text
+                    ),
+                ),
+            )
+        })
+        .collect::<Vec<_>>();
+    // Keep the order deterministic.
+    ret.sort_by_key(|diag| diag.range.start());
+    ret
+}
+
+/// Find operations which always fail on values of obviously wrong types, like `"a" + 1` or
+/// calling a string, from conflicts found during type inference.
+fn type_mismatches(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let source_map = db.source_map(file);
+    let infer = db.infer(file);
+    let mut ret = Vec::new();
+    for &conflict in infer.conflicts() {
+        let (at, note) = match conflict {
+            TyConflict::Binary { expr, op, lhs, rhs } => {
+                let op = match op {
+                    BinaryOpKind::Add => "+",
+                    BinaryOpKind::Sub => "-",
+                    BinaryOpKind::Mul => "*",
+                    BinaryOpKind::Div => "/",
+                    BinaryOpKind::Less => "<",
+                    BinaryOpKind::Greater => ">",
+                    BinaryOpKind::LessEqual => "<=",
+                    BinaryOpKind::GreaterEqual => ">=",
+                    BinaryOpKind::And => "&&",
+                    BinaryOpKind::Or => "||",
+                    BinaryOpKind::Imply => "->",
+                    BinaryOpKind::Concat => "++",
+                    BinaryOpKind::Update => "//",
+                    BinaryOpKind::Equal => "==",
+                    BinaryOpKind::NotEqual => "!=",
+                    BinaryOpKind::PipeInto => "|>",
+                    BinaryOpKind::PipeFrom => "<|",
+                };
+                let (l, r) = (describe_ty_kind(lhs), describe_ty_kind(rhs));
+                (expr, format!("`{op}` cannot be applied to {l} and {r}"))
+            }
+            TyConflict::Unary { expr, op, arg } => {
+                let op = match op {
+                    UnaryOpKind::Not => "!",
+                    UnaryOpKind::Negate => "-",
+                };
+                let arg = describe_ty_kind(arg);
+                (expr, format!("`{op}` cannot be applied to {arg}"))
+            }
+            TyConflict::Condition { expr, ty } => (
+                expr,
+                format!("The condition is {}, not a bool", describe_ty_kind(ty)),
+            ),
+            TyConflict::NotCallable { expr, ty } => (
+                expr,
+                format!("Calling {}, which is not a function", describe_ty_kind(ty)),
+            ),
+        };
+        let Some(ptr) = source_map.node_for_expr(at) else {
+            continue;
+        };
+        let range = ptr.text_range();
+        ret.push(
+            Diagnostic::new(range, DiagnosticKind::TypeMismatch)
+                .with_note(FileRange::new(file, range), note),
+        );
+    }
+    // Keep the order deterministic.
+    ret.sort_by_key(|diag| diag.range.start());
+    ret
+}
+
+fn describe_ty_kind(kind: TyKind) -> &'static str {
+    match kind {
+        TyKind::Bool => "a bool",
+        TyKind::Int => "an int",
+        TyKind::Float => "a float",
+        TyKind::String => "a string",
+        TyKind::Path => "a path",
+        TyKind::List => "a list",
+        TyKind::Attrset => "an attrset",
+        TyKind::Lambda => "a function",
+    }
+}
+
+/// Find code which is never evaluated, since it is guarded by `assert false`, a constant `if`
+/// condition, or `builtins.seq` of a `throw` or `abort`. Those are usually leftover debugging
+/// guards. Only unshadowed `true` and `false` are considered.
+fn unreachable_code(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let nameres = db.name_resolution(file);
+    // `name` or `builtins.name`.
+    let builtin_name = |e: ExprId| match &module[e] {
+        Expr::Reference(_) => nameres.check_builtin(e, &module),
+        Expr::Select(set, path, None)
+            if path.len() == 1 && nameres.check_builtin(*set, &module) == Some("builtins") =>
+        {
+            match &module[path[0]] {
+                Expr::Literal(Literal::String(name)) => Some(&**name),
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    let bool_value = |e: ExprId| match nameres.check_builtin(e, &module)? {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    };
+    let always_fails = |e: ExprId| matches!(&module[e], &Expr::Apply(func, _) if matches!(builtin_name(func), Some("throw" | "abort")));
+
+    let mut ret = module
+        .exprs()
+        .filter_map(|(_, kind)| {
+            let (unreachable, cause, reason) = match *kind {
+                Expr::Assert(cond, body) if bool_value(cond) == Some(false) => {
+                    (body, cond, "The assertion always fails")
+                }
+                Expr::IfThenElse(cond, then_body, else_body) => match bool_value(cond)? {
+                    true => (else_body, cond, "The condition is always true"),
+                    false => (then_body, cond, "The condition is always false"),
+                },
+                // `builtins.seq (throw "...") x`
+                Expr::Apply(func, value) => match module[func] {
+                    Expr::Apply(seq, first)
+                        if matches!(builtin_name(seq), Some("seq" | "deepSeq"))
+                            && always_fails(first) =>
+                    {
+                        (value, first, "The first argument always fails")
+                    }
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            let range = source_map.node_for_expr(unreachable)?.text_range();
+            let cause_range = source_map.node_for_expr(cause)?.text_range();
+            Some(
+                Diagnostic::new(range, DiagnosticKind::UnreachableCode)
+                    .with_note(FileRange::new(file, cause_range), reason),
+            )
+        })
+        .collect::<Vec<_>>();
+    // Keep the order deterministic, and report nested unreachable code only once.
+    ret.sort_by_key(|diag| (diag.range.start(), std::cmp::Reverse(diag.range.end())));
+    let mut last_end = None;
+    ret.retain(|diag| {
+        if last_end.map_or(false, |end| diag.range.end() <= end) {
+            return false;
+        }
+        last_end = Some(diag.range.end());
+        true
+    });
+    ret
+}
+
+/// Find keys of literal attrsets overriding ones of previous literal operands of `//`.
+/// Nested attrsets are not merged by `//`, so `{ a.b = 1; } // { a.c = 2; }` loses `a.b`.
+fn overridden_keys(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+
+    // Only handle the outermost `//` of each chain.
+    let is_update =
+        |expr: ExprId| matches!(module[expr], Expr::Binary(Some(BinaryOpKind::Update), _, _));
+    let mut inner_updates = HashSet::new();
+    for (_, kind) in module.exprs() {
+        if let &Expr::Binary(Some(BinaryOpKind::Update), lhs, rhs) = kind {
+            inner_updates.extend([lhs, rhs].into_iter().filter(|&e| is_update(e)));
+        }
+    }
+
+    let mut ret = Vec::new();
+    for (expr, _) in module.exprs() {
+        if !is_update(expr) || inner_updates.contains(&expr) {
+            continue;
+        }
+        let mut operands = Vec::new();
+        let mut stack = vec![expr];
+        while let Some(e) = stack.pop() {
+            match module[e] {
+                Expr::Binary(Some(BinaryOpKind::Update), lhs, rhs) => stack.extend([rhs, lhs]),
+                _ => operands.push(e),
+            }
+        }
+
+        let mut defined = HashMap::new();
+        for e in operands {
+            let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) = &module[e] else {
+                continue;
+            };
+            for &(name, _) in bindings.statics.iter() {
+                let Some(ptr) = source_map.nodes_for_name(name).next() else {
+                    continue;
+                };
+                if let Some(prev) = defined.insert(&*module[name].text, ptr.text_range()) {
+                    ret.push(
+                        Diagnostic::new(ptr.text_range(), DiagnosticKind::OverriddenKey)
+                            .with_note(FileRange::new(file, prev), "Previously defined here"),
+                    );
+                }
+            }
+        }
+    }
+    // Keep the order deterministic.
+    ret.sort_by_key(|diag| diag.range.start());
+    ret
+}
+
+/// Find definitions of the same option with the same effective priority but different
+/// scalar values, which are likely to fail during evaluation.
+fn conflicting_definitions(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let defs = db.option_definitions(file);
+    if defs.iter().next().is_none() {
+        return Vec::new();
+    }
+    let index = db.option_definition_index(db.configuration_files(file));
+    let source_map = db.source_map(file);
+    let module = db.module(file);
+    let mut ret = Vec::new();
+    for def in defs.iter() {
+        let Some(value) = scalar_value(&module, def.value) else {
+            continue;
+        };
+        let winners = index.winners(&def.path);
+        if !winners
+            .iter()
+            .any(|w| w.file_id == file && w.value.name == def.name)
+        {
+            continue;
+        }
+        let mut diag = None;
+        for other in winners {
+            if other.file_id == file && other.value.name == def.name {
+                continue;
+            }
+            let other_module = db.module(other.file_id);
+            if scalar_value(&other_module, other.value.value).map_or(true, |v| v == value) {
+                continue;
+            }
+            let Some(other_ptr) = db
+                .source_map(other.file_id)
+                .nodes_for_name(other.value.name)
+                .next()
+            else {
+                continue;
+            };
+            let Some(ptr) = source_map.nodes_for_name(def.name).next() else {
+                break;
+            };
+            diag = Some(
+                diag.unwrap_or_else(|| {
+                    Diagnostic::new(ptr.text_range(), DiagnosticKind::ConflictingDefinition)
+                })
+                .with_note(
+                    FileRange::new(other.file_id, other_ptr.text_range()),
+                    format!("Also defined here with {}", other.value.priority),
+                ),
+            );
+        }
+        ret.extend(diag);
+    }
+    ret
+}
+
+/// Package sets whose `callPackage` auto-fills parameters from top-level attributes of nixpkgs.
+/// Scopes like `python3Packages.callPackage` provide more, and are not checked.
+const TOP_LEVEL_PACKAGE_SETS: &[&str] = &["pkgs", "final", "prev"];
+
+/// Find parameters of packages called by `callPackage ./pkg.nix { ... }`, which are neither
+/// passed explicitly nor top-level attributes of nixpkgs. It requires the package index.
+fn missing_package_args(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let index = db.package_index();
+    if index.is_empty() {
+        return Vec::new();
+    }
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let mut ret = Vec::new();
+    for site in db.call_package_sites(file).iter() {
+        let is_top_level = match &module[site.func_expr] {
+            Expr::Reference(_) => true,
+            Expr::Select(set, path, None) => {
+                path.len() == 1
+                    && matches!(&module[*set], Expr::Reference(name) if TOP_LEVEL_PACKAGE_SETS.contains(&&**name))
+            }
+            _ => false,
+        };
+        let Some(callee) = site.callee.filter(|_| is_top_level) else {
+            continue;
+        };
+        let (Expr::Attrset(args) | Expr::RecAttrset(args)) = &module[site.arg_expr] else {
+            continue;
+        };
+        if !args.dynamics.is_empty() {
+            continue;
+        }
+        let ModuleKind::Package { lambda_expr } = *db.module_kind(callee) else {
+            continue;
+        };
+        let callee_module = db.module(callee);
+        let Expr::Lambda(_, Some(pat), _) = &callee_module[lambda_expr] else {
+            continue;
+        };
+        let passed = args
+            .statics
+            .iter()
+            .map(|&(name, _)| &*module[name].text)
+            .collect::<HashSet<_>>();
+        let missing = pat
+            .fields
+            .iter()
+            .filter_map(|&(name, default)| default.is_none().then_some(name)?)
+            .map(|name| &*callee_module[name].text)
+            .filter(|name| !passed.contains(name) && !index.contains_key(*name))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            continue;
+        }
+        let Some(ptr) = source_map.node_for_expr(site.path_expr) else {
+            continue;
+        };
+        let range = ptr.text_range();
+        let arg_range = source_map
+            .node_for_expr(site.arg_expr)
+            .map_or(range, |ptr| ptr.text_range());
+        ret.push(missing.iter().fold(
+            Diagnostic::new(range, DiagnosticKind::MissingPackageArg),
+            |diag, name| {
+                diag.with_note(
+                    FileRange::new(file, arg_range),
+                    format!("`{name}` is neither passed here nor a top-level attribute of nixpkgs"),
+                )
+            },
+        ));
+    }
+    ret
+}
+
+/// Check arguments of `mkShell { ... }`. Unknown arguments are valid environment variables,
+/// thus only the ones similar to known arguments are reported.
+fn shell_args(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let known_args = known::MK_SHELL_ARG.as_attrset().unwrap();
+    let mut ret = Vec::new();
+    for (_, kind) in module.exprs() {
+        let &Expr::Apply(func, arg) = kind else {
+            continue;
+        };
+        if !is_mk_shell(&module, func) {
+            continue;
+        }
+        let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) = &module[arg] else {
+            continue;
+        };
+        for &(name, _) in bindings.statics.iter() {
+            let text = &*module[name].text;
+            let Some(ptr) = source_map.nodes_for_name(name).next() else {
+                continue;
+            };
+            let range = ptr.text_range();
+            if text == "nativeBuildInputs" {
+                ret.push(
+                    Diagnostic::new(range, DiagnosticKind::ShellNativeBuildInputs).with_note(
+                        FileRange::new(file, range),
+                        "`packages` are added to `nativeBuildInputs` by `mkShell`",
+                    ),
+                );
+                continue;
+            }
+            if known_args.get(text).is_some() {
+                continue;
+            }
+            let similar = known_args
+                .iter()
+                .map(|(known, ..)| (edit_distance(text, known), known))
+                .filter(|&(dist, known)| dist * 4 <= known.len())
+                .min_by_key(|&(dist, _)| dist);
+            if let Some((_, known)) = similar {
+                ret.push(
+                    Diagnostic::new(range, DiagnosticKind::MisspelledShellArg).with_note(
+                        FileRange::new(file, range),
+                        format!("Did you mean `{known}`?"),
+                    ),
+                );
+            }
+        }
+    }
+    // Keep the order deterministic.
+    ret.sort_by_key(|diag| diag.range.start());
+    ret
+}
+
+/// Find constructs blocking static analysis, which is the interest of strict checks:
+/// `import`s not resolved to a file in the workspace, and dynamic attributes of attrsets.
+fn analysis_gaps(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let nameres = db.name_resolution(file);
+    let mut ret = Vec::new();
+    for (_, kind) in module.exprs() {
+        match kind {
+            &Expr::Apply(func, arg) if nameres.check_builtin(func, &module) == Some("import") => {
+                let note = match &module[arg] {
+                    Expr::Literal(Literal::Path(path)) => {
+                        if path.resolve_file(db).is_some() {
+                            continue;
+                        }
+                        // Search paths outside the workspace are checked by `missing_paths`.
+                        if matches_search_path(db, *path) {
+                            continue;
+                        }
+                        "The path is not resolved to a file in the workspace"
+                    }
+                    _ => "The imported path is not a literal",
+                };
+                let Some(ptr) = source_map.node_for_expr(arg) else {
+                    continue;
+                };
+                let range = ptr.text_range();
+                ret.push(
+                    Diagnostic::new(range, DiagnosticKind::UnresolvedImport)
+                        .with_note(FileRange::new(file, range), note),
+                );
+            }
+            Expr::Attrset(bindings) | Expr::RecAttrset(bindings) | Expr::LetAttrset(bindings) => {
+                ret.extend(bindings.dynamics.iter().filter_map(|&(key, _)| {
+                    let range = source_map.node_for_expr(key)?.text_range();
+                    Some(Diagnostic::new(range, DiagnosticKind::DynamicAttr))
+                }));
+            }
+            _ => {}
+        }
+    }
+    // Keep the order deterministic.
+    ret.sort_by_key(|diag| diag.range.start());
+    ret
+}
+
+/// Find uses of deprecated or discouraged builtins, via global names, `builtins.name`,
+/// `with builtins;` or `inherit (builtins) name;`.
+fn deprecated_builtins(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let nameres = db.name_resolution(file);
+    let mut ret = Vec::new();
+    for (expr, kind) in module.exprs() {
+        let (name, expr) = match kind {
+            Expr::Reference(text) => match nameres.check_builtin(expr, &module) {
+                // Aliases `__name` resolve to `name`.
+                Some(_) if text.starts_with("__") => (&**text, expr),
+                Some(name) => (name, expr),
+                None => continue,
+            },
+            Expr::Select(set, path, _)
+                if nameres.check_builtin(*set, &module) == Some("builtins") =>
+            {
+                match path.first().map(|&attr| (&module[attr], attr)) {
+                    Some((Expr::Literal(Literal::String(name)), attr)) => (&**name, attr),
+                    _ => continue,
+                }
+            }
+            _ => continue,
+        };
+        let Some(suggestion) = builtin::deprecation(name) else {
+            continue;
+        };
+        let Some(ptr) = source_map.node_for_expr(expr) else {
+            continue;
+        };
+        let range = ptr.text_range();
+        ret.push(
+            Diagnostic::new(range, DiagnosticKind::DeprecatedBuiltin)
+                .with_note(FileRange::new(file, range), suggestion),
+        );
+    }
+    // Keep the order deterministic.
+    ret.sort_by_key(|diag| diag.range.start());
+    ret
+}
+
+/// Find parentheses around atoms, which never change the meaning.
+/// Parentheses around compound expressions are kept even if they are implied by precedence,
+/// since they usually aid readability. So are ones around configured kinds of atoms.
+fn redundant_parens(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let kept_kinds = db.redundant_paren_kept_kinds();
+    db.parse(file)
+        .syntax_node()
+        .descendants()
+        .filter_map(ast::Paren::cast)
+        .filter(|paren| is_redundant_paren(paren, &kept_kinds))
+        .map(|paren| Diagnostic::new(paren.syntax().text_range(), DiagnosticKind::RedundantParen))
+        .collect()
+}
+
+/// Kinds of atoms for `diagnostics.redundantParen.keptKinds`.
+fn atom_kind(expr: &ast::Expr) -> Option<&'static str> {
+    Some(match expr {
+        ast::Expr::Paren(_) => "paren",
+        ast::Expr::Ref(_) => "reference",
+        ast::Expr::String(_) | ast::Expr::IndentString(_) => "string",
+        ast::Expr::PathInterpolation(_) => "path",
+        ast::Expr::Literal(lit) if lit.kind() == Some(LiteralKind::Path) => "path",
+        ast::Expr::Literal(_) => "literal",
+        ast::Expr::List(_) => "list",
+        ast::Expr::AttrSet(_) => "attrset",
+        ast::Expr::Select(_) => "select",
+        _ => return None,
+    })
+}
+
+/// Whether the parentheses `paren` are redundant, unless they are around `kept_kinds` of atoms.
+/// Only the outermost ones of nested redundant parentheses are redundant.
+pub(crate) fn is_redundant_paren(paren: &ast::Paren, kept_kinds: &[String]) -> bool {
+    // `inherit (from) ...;` requires them.
+    let Some(parent) = paren.syntax().parent() else {
+        return false;
+    };
+    if parent.kind() == SyntaxKind::INHERIT || paren.r_brack_token().is_none() {
+        return false;
+    }
+    let Some(inner) = paren.expr() else {
+        return false;
+    };
+    let Some(kind) = atom_kind(&inner) else {
+        return false;
+    };
+    let is_atom = match &inner {
+        // `(./a).b` is not `./a.b`.
+        ast::Expr::Literal(_) => {
+            !matches!(parent.kind(), SyntaxKind::SELECT | SyntaxKind::HAS_ATTR)
+        }
+        // `or` is confusing in arguments, like `f a.b or c`.
+        ast::Expr::Select(sel) => sel.or_token().is_none(),
+        _ => true,
+    };
+    if !is_atom || kept_kinds.iter().any(|kept| kept == kind) {
+        return false;
+    }
+    // `((x))` is reported as a whole.
+    if ast::Paren::cast(parent).map_or(false, |outer| is_redundant_paren(&outer, kept_kinds)) {
+        return false;
+    }
+    // Comments would be misplaced.
+    if paren
+        .syntax()
+        .children_with_tokens()
+        .any(|elem| elem.kind() == SyntaxKind::COMMENT)
+    {
+        return false;
+    }
+    // Removing them must not join adjacent tokens, like `f(x)` into `fx`, or `x:(y)` into an URI.
+    let inner_text = inner.syntax().to_string();
+    let is_word = |c: char| c.is_alphanumeric() || "_'-./:".contains(c);
+    let joins_prev = paren
+        .l_brack_token()
+        .and_then(|tok| tok.prev_token())
+        .and_then(|tok| tok.text().chars().last())
+        .map_or(false, |c| is_word(c) && inner_text.starts_with(is_word));
+    let joins_next = paren
+        .r_brack_token()
+        .and_then(|tok| tok.next_token())
+        .and_then(|tok| tok.text().chars().next())
+        .map_or(false, |c| {
+            is_word(c) && inner_text.trim_end().ends_with(is_word)
+        });
+    !joins_prev && !joins_next
+}
+
+/// Find comparisons with boolean literals, double negations and `if c then true else false`.
+fn redundant_booleans(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    db.parse(file)
+        .syntax_node()
+        .descendants()
+        .filter_map(ast::Expr::cast)
+        .filter_map(|expr| {
+            let simplified = simplify_boolean(db, file, &expr)?;
+            let range = expr.syntax().text_range();
+            Some(
+                Diagnostic::new(range, DiagnosticKind::RedundantBoolean).with_note(
+                    FileRange::new(file, range),
+                    format!("Simplify to `{simplified}`"),
+                ),
+            )
+        })
+        .collect()
+}
+
+/// The simplified text of a redundant boolean expression, if it is.
+pub(crate) fn simplify_boolean<DB: DefDatabase + ?Sized>(
+    db: &DB,
+    file: FileId,
+    expr: &ast::Expr,
+) -> Option<String> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let nameres = db.name_resolution(file);
+    // Only if `true` and `false` are not shadowed.
+    let bool_value = |e: &ast::Expr| {
+        let e = source_map.expr_for_node(SyntaxNodePtr::new(e.syntax()))?;
+        match nameres.check_builtin(e, &module)? {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        }
+    };
+    let text = |e: &ast::Expr| e.syntax().to_string().trim_end().to_owned();
+    // Applications are also parenthesized for readability, though `!` binds looser.
+    let negate = |e: &ast::Expr| match e {
+        ast::Expr::Select(_)
+        | ast::Expr::HasAttr(_)
+        | ast::Expr::UnaryOp(_)
+        | ast::Expr::Paren(_)
+        | ast::Expr::Ref(_)
+        | ast::Expr::Literal(_)
+        | ast::Expr::List(_)
+        | ast::Expr::AttrSet(_) => format!("!{}", text(e)),
+        _ => format!("!({})", text(e)),
+    };
+
+    match expr {
+        // `x == true`, `x != false` and alike.
+        ast::Expr::BinaryOp(op) => {
+            let negated = match op.op_kind()? {
+                BinaryOpKind::Equal => false,
+                BinaryOpKind::NotEqual => true,
+                _ => return None,
+            };
+            let (lhs, rhs) = (op.lhs()?, op.rhs()?);
+            let (operand, value) = match (bool_value(&lhs), bool_value(&rhs)) {
+                (_, Some(value)) => (lhs, value),
+                (Some(value), None) => (rhs, value),
+                (None, None) => return None,
+            };
+            Some(if value != negated {
+                text(&operand)
+            } else {
+                negate(&operand)
+            })
+        }
+        // `!(!x)` and `!!x`.
+        ast::Expr::UnaryOp(op) if op.op_kind()? == UnaryOpKind::Not => {
+            let mut inner = op.arg()?;
+            while let ast::Expr::Paren(paren) = inner {
+                inner = paren.expr()?;
+            }
+            match inner {
+                ast::Expr::UnaryOp(inner) if inner.op_kind()? == UnaryOpKind::Not => {
+                    Some(text(&inner.arg()?))
+                }
+                _ => None,
+            }
+        }
+        // `if c then true else false`.
+        ast::Expr::IfThenElse(e) => {
+            let cond = e.condition()?;
+            match (bool_value(&e.then_body()?)?, bool_value(&e.else_body()?)?) {
+                (true, false) => Some(text(&cond)),
+                (false, true) => Some(negate(&cond)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Find `a = a;` in non-recursive attrsets and `a = b.a;`, which can be `inherit a;` and
+/// `inherit (b) a;`. `a = a;` in `let` or `rec` is an infinite recursion instead.
+fn manual_inherits(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    db.parse(file)
+        .syntax_node()
+        .descendants()
+        .filter_map(ast::AttrpathValue::cast)
+        .filter_map(|binding| {
+            let mut attrs = binding.attrpath()?.attrs();
+            let (Some(attr), None) = (attrs.next(), attrs.next()) else {
+                return None;
+            };
+            let AttrKind::Static(Some(lhs)) = AttrKind::of(attr.clone()) else {
+                return None;
+            };
+            let name = source_map.name_for_node(AstPtr::new(attr.syntax()))?;
+            let kind = match binding.value()?.flatten_paren()? {
+                ast::Expr::Ref(rhs) if rhs.token()?.text() == lhs => {
+                    if module[name].kind != NameKind::PlainAttrset {
+                        return None;
+                    }
+                    DiagnosticKind::ManualInherit
+                }
+                ast::Expr::Select(rhs) if rhs.or_token().is_none() => {
+                    let last = rhs.attrpath()?.attrs().last()?;
+                    match AttrKind::of(last) {
+                        AttrKind::Static(Some(rhs)) if rhs == lhs => {}
+                        _ => return None,
+                    }
+                    DiagnosticKind::ManualInheritFrom
+                }
+                _ => return None,
+            };
+            Some(Diagnostic::new(binding.syntax().text_range(), kind))
+        })
+        .collect()
+}
+
+/// Find lambdas `x: f x`, which are the same as `f` if `f` does not refer to `x`.
+fn eta_reductions(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    db.parse(file)
+        .syntax_node()
+        .descendants()
+        .filter_map(ast::Lambda::cast)
+        .filter_map(|lambda| {
+            let func = eta_reduce(&lambda)?;
+            let range = lambda.syntax().text_range();
+            Some(
+                Diagnostic::new(range, DiagnosticKind::EtaReduction).with_note(
+                    FileRange::new(file, range),
+                    format!("Reduce to `{}`", func.syntax().to_string().trim_end()),
+                ),
+            )
+        })
+        .collect()
+}
+
+/// The function `f` of the eta-reducible lambda `x: f x`, if it is.
+pub(crate) fn eta_reduce(lambda: &ast::Lambda) -> Option<ast::Expr> {
+    let param = lambda.param()?;
+    if param.pat().is_some() {
+        return None;
+    }
+    let name = param.name()?.token()?;
+    let ast::Expr::Apply(app) = lambda.body()?.flatten_paren()? else {
+        return None;
+    };
+    match app.argument()?.flatten_paren()? {
+        ast::Expr::Ref(arg) if arg.token()?.text() == name.text() => {}
+        _ => return None,
+    }
+    let func = app.function()?;
+    // Any mention of the name, including `inherit x;` and shadowing ones, keeps the lambda.
+    // Comments outside the function would be lost.
+    let func_range = func.syntax().text_range();
+    let keeps = lambda
+        .syntax()
+        .descendants_with_tokens()
+        .filter_map(|elem| elem.into_token())
+        .any(|tok| {
+            if func_range.contains_range(tok.text_range()) {
+                tok.kind() == SyntaxKind::IDENT && tok.text() == name.text()
+            } else {
+                tok.kind() == SyntaxKind::COMMENT
+            }
+        });
+    (!keeps).then_some(func)
+}
+
+/// Find relative path literals whose targets do not exist according to `exists`,
+/// which is only asked for paths not resolved to files in the workspace.
+pub(crate) fn missing_paths(
+    db: &impl TyDatabase,
+    file: FileId,
+    exists: impl Fn(&VfsPath) -> bool,
+) -> Vec<Diagnostic> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let mut ret = Vec::new();
+    for (expr, kind) in module.exprs() {
+        let Expr::Literal(Literal::Path(path)) = kind else {
+            continue;
+        };
+        if path.resolve_file(db).is_some() {
+            continue;
+        }
+        // Only relative paths in real files, and search paths, are resolved.
+        // Search paths are only resolved to existing candidates, like Nix.
+        let missing = match path.resolve(db) {
+            Some(vpath) => !exists(&vpath),
+            None => matches_search_path(db, *path),
+        };
+        if !missing {
+            continue;
+        }
+        let Some(ptr) = source_map.node_for_expr(expr) else {
+            continue;
+        };
+        ret.push(Diagnostic::new(
+            ptr.text_range(),
+            DiagnosticKind::MissingPath,
+        ));
+    }
+
+    let parse = db.parse(file);
+    let suppressions = Suppressions::collect(&parse.syntax_node(), &db.file_content(file));
+    ret.retain(|diag| !suppressions.is_suppressed(diag));
+    // Keep the order deterministic.
+    ret.sort_by_key(|diag| diag.range.start());
+    ret
+}
+
+/// Whether `path` is `<name/...>` with entries of the search path for `name`, regardless of
+/// whether it exists in any of them.
+fn matches_search_path(db: &dyn DefDatabase, path: crate::def::Path) -> bool {
+    match path.data(db).anchor() {
+        PathAnchor::Search(name) => db.search_path().resolve(name, |_| true).is_some(),
+        _ => false,
+    }
+}
+
+/// The Levenshtein distance. It is case-sensitive, since upper-case arguments like `NAME` are
+/// conventionally environment variables rather than misspellings of `name`.
+fn edit_distance(lhs: &str, rhs: &str) -> usize {
+    let lhs = lhs.chars().collect::<Vec<_>>();
+    let rhs = rhs.chars().collect::<Vec<_>>();
+    let mut row = (0..=rhs.len()).collect::<Vec<_>>();
+    for (i, &l) in lhs.iter().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, &r) in rhs.iter().enumerate() {
+            let cur = (prev + usize::from(l != r))
+                .min(row[j] + 1)
+                .min(row[j + 1] + 1);
+            prev = row[j + 1];
+            row[j + 1] = cur;
+        }
+    }
+    row[rhs.len()]
+}
+
+/// The value of literals and constant references, which cannot be merged.
+fn scalar_value(module: &Module, expr: ExprId) -> Option<&Expr> {
+    match &module[expr] {
+        e @ Expr::Literal(_) => Some(e),
+        e @ Expr::Reference(name) if matches!(&**name, "true" | "false" | "null") => Some(e),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::base::SourceDatabase;
     use crate::tests::TestDB;
+    use crate::OptionSet;
     use expect_test::{expect, Expect};
+    use nix_interop::nixos_options::{self, NixosOption, NixosOptions};
+    use nix_interop::package_index::{PackageIndex, PackageInfo};
+    use nix_interop::search_path::SearchPath;
+    use std::sync::Arc;
+    use syntax::TextRange;
 
     fn check(fixture: &str, expect: Expect) {
         let (db, file_id) = TestDB::single_file(fixture).unwrap();
@@ -46,6 +1154,24 @@ mod tests {
         check("1 == 2 == 3", expect!["7..9: SyntaxError(MultipleNoAssoc)"]);
     }
 
+    #[test]
+    fn pipe_operator() {
+        check(
+            "f: x: x |> f <| x",
+            expect![[r#"
+                13..15: SyntaxError(MultipleNoAssoc)
+                8..10: DisabledLanguageFeature(PipeOperators)
+                    8..10: Add `pipe-operators` to `nix.languageFeatures` to enable it
+                13..15: DisabledLanguageFeature(PipeOperators)
+                    13..15: Add `pipe-operators` to `nix.languageFeatures` to enable it
+            "#]],
+        );
+
+        let (mut db, file_id) = TestDB::single_file("x: x |> builtins.toString").unwrap();
+        db.set_language_features(Arc::new(vec![crate::LanguageFeature::PipeOperators]));
+        assert_eq!(super::diagnostics(&db, file_id), []);
+    }
+
     #[test]
     fn lower_error() {
         check(
@@ -57,6 +1183,278 @@ mod tests {
         );
     }
 
+    #[test]
+    fn overridden_key() {
+        check(
+            "{ a.b = 1; c = 2; } // { a.d = 3; } // { c = 4; e = 5; } // { e = 6; }",
+            expect![[r#"
+                25..26: OverriddenKey
+                    2..3: Previously defined here
+                41..42: OverriddenKey
+                    11..12: Previously defined here
+                62..63: OverriddenKey
+                    48..49: Previously defined here
+            "#]],
+        );
+        check(
+            "x: ({ a = 1; } // { b = 2; }) // x // { b = 3; }",
+            expect![[r#"
+                40..41: OverriddenKey
+                    20..21: Previously defined here
+            "#]],
+        );
+    }
+
+    #[test]
+    fn deprecated_builtin() {
+        check(
+            "[ (builtins.toPath ./.) derivationStrict (with builtins; toPath) builtins.map __mapAttrs __map ]",
+            expect![[r#"
+                89..94: UndefinedName
+                12..18: DeprecatedBuiltin
+                    12..18: Use `/. + "/path"` to convert a string into an absolute path instead
+                24..40: DeprecatedBuiltin
+                    24..40: Use `derivation` instead
+                57..63: DeprecatedBuiltin
+                    57..63: Use `/. + "/path"` to convert a string into an absolute path instead
+                78..88: DeprecatedBuiltin
+                    78..88: Use `builtins.mapAttrs` instead
+            "#]],
+        );
+    }
+
+    #[test]
+    fn redundant_paren() {
+        check(
+            "{ f, x, a, b, c, y }: [ f (x) (a * b) (a.b or c) ((y)) { inherit (x) z; } f(x) (./a).b ]",
+            expect![[r#"
+                26..29: RedundantParen
+                49..54: RedundantParen
+            "#]],
+        );
+
+        let (mut db, file_id) = TestDB::single_file("{ x, a }: [ (x) ([ ]) (a.b) ]").unwrap();
+        db.set_redundant_paren_kept_kinds(Arc::new(vec!["list".into(), "select".into()]));
+        let diags = super::diagnostics(&db, file_id);
+        let ranges = diags.iter().map(|d| d.range).collect::<Vec<_>>();
+        assert_eq!(ranges, [TextRange::new(12.into(), 15.into())]);
+        // The statix name.
+        check(
+            "{ f, g }: [\n  # nil:ignore useless_parens\n  (f)\n  (g)\n]",
+            expect!["50..53: RedundantParen"],
+        );
+    }
+
+    #[test]
+    fn redundant_boolean() {
+        check(
+            "
+{ x, f }: [
+  (x == true) (false != f x) (x == false) (x != true || x)
+  (!(!x)) (!!x)
+  (if x then true else false) (if x.y then false else true) (if x then true else true)
+]",
+            expect![[r#"
+                15..24: RedundantBoolean
+                    15..24: Simplify to `x`
+                27..39: RedundantBoolean
+                    27..39: Simplify to `f x`
+                42..52: RedundantBoolean
+                    42..52: Simplify to `!x`
+                55..65: RedundantBoolean
+                    55..65: Simplify to `!x`
+                74..79: RedundantBoolean
+                    74..79: Simplify to `x`
+                82..85: RedundantBoolean
+                    82..85: Simplify to `x`
+                90..115: RedundantBoolean
+                    90..115: Simplify to `x`
+                118..145: RedundantBoolean
+                    118..145: Simplify to `!x.y`
+            "#]],
+        );
+        // Shadowed.
+        check(
+            "let true = 1; in { x }: [ (x == true) y ]",
+            expect!["38..39: UndefinedName"],
+        );
+    }
+
+    #[test]
+    fn manual_inherit() {
+        check(
+            "{ a, b }: let c = b.c; in { a = a; b = (a); c.c = c; d = b.d; e = b.c or 1; f = c; }",
+            expect![[r#"
+                39..42: RedundantParen
+                14..22: ManualInheritFrom
+                28..34: ManualInherit
+                53..61: ManualInheritFrom
+            "#]],
+        );
+        // Infinite recursions.
+        check(
+            "let a = a; in rec { b = b; }",
+            expect![[r#"
+                4..5: UnusedBinding
+                4..5: InfiniteRecursion
+                    4..5: Evaluating it requires the value of `a`
+                20..21: InfiniteRecursion
+                    20..21: Evaluating it requires the value of `b`
+            "#]],
+        );
+    }
+
+    #[test]
+    fn eta_reduction() {
+        check(
+            "{ f, g }: [ (x: f x) (x: g f x) (x: x f x) (x: f x x) ({ x }: f x) ]",
+            expect![[r#"
+                13..19: EtaReduction
+                    13..19: Reduce to `f`
+                22..30: EtaReduction
+                    22..30: Reduce to `g f`
+            "#]],
+        );
+    }
+
+    #[test]
+    fn shadowed_binding() {
+        check(
+            "
+{ pkgs, lib, x, self, _a }:
+let
+  pkgs = lib.pkgs;
+  inherit lib;
+  f = x: { self, _a }: rec { x = 1; y = x; };
+in
+f pkgs lib
+",
+            expect![[r#"
+                2..6: UnusedBinding
+                13..14: UnusedBinding
+                16..20: UnusedBinding
+                22..24: UnusedBinding
+                77..81: UnusedParam
+                72..73: UnusedParam
+                34..38: ShadowedBinding
+                    2..6: The shadowed definition of `pkgs`
+                72..73: ShadowedBinding
+                    13..14: The shadowed definition of `x`
+                95..96: ShadowedBinding
+                    72..73: The shadowed definition of `x`
+                34..50: ManualInheritFrom
+            "#]],
+        );
+    }
+
+    #[test]
+    fn infinite_recursion() {
+        check(
+            "
+let
+  a = a;
+  b = c + 1;
+  c = if b > 0 then 1 else 2;
+  d = { x = d; y = [ d ]; z = f d; };
+  e = if true then e else 1;
+  f = x: x;
+  g = a;
+  h = rec { i = i.j or 1; k = h; };
+in
+[ a b c d e f g h ]
+",
+            expect![[r#"
+                6..7: InfiniteRecursion
+                    6..7: Evaluating it requires the value of `a`
+                15..16: InfiniteRecursion
+                    28..29: Evaluating it requires the value of `c`
+                28..29: InfiniteRecursion
+                    15..16: Evaluating it requires the value of `b`
+                156..157: InfiniteRecursion
+                    156..157: Evaluating it requires the value of `i`
+                120..121: UnreachableCode
+                    103..107: The condition is always true
+            "#]],
+        );
+    }
+
+    #[test]
+    fn type_mismatch() {
+        check(
+            r#"
+let
+  s = "a";
+  n = let in 1;
+in
+[
+  (s + 1) (n + 1.5) ("a" + ./b) (./b + "${s}") (builtins.length [ ] - "1") ([ ] ++ { }) ({ } // { })
+  (1 < "a") ("a" < "b") (!n) (-s) (if s then 1 else 2) (s 1) ({ __functor = _: _: 1; } 1)
+  (true && 1) (x: x) 1
+]
+"#,
+            expect![[r#"
+                21..27: EmptyLetIn
+                39..44: TypeMismatch
+                    39..44: `+` cannot be applied to a string and an int
+                84..109: TypeMismatch
+                    84..109: `-` cannot be applied to an int and a string
+                112..122: TypeMismatch
+                    112..122: `++` cannot be applied to a list and an attrset
+                140..147: TypeMismatch
+                    140..147: `<` cannot be applied to an int and a string
+                162..164: TypeMismatch
+                    162..164: `!` cannot be applied to an int
+                167..169: TypeMismatch
+                    167..169: `-` cannot be applied to a string
+                175..176: TypeMismatch
+                    175..176: The condition is a string, not a bool
+                193..194: TypeMismatch
+                    193..194: Calling a string, which is not a function
+                230..239: TypeMismatch
+                    230..239: `&&` cannot be applied to a bool and an int
+            "#]],
+        );
+    }
+
+    #[test]
+    fn unreachable_code() {
+        check(
+            r#"
+{ x, y }: [
+  (assert false; x)
+  (if true then x else y)
+  (if false then x else y)
+  (builtins.seq (throw "debug") x)
+  (builtins.deepSeq (abort "debug") y)
+  (assert false; if true then x else y)
+  (assert x; if x then x else y)
+]
+"#,
+            expect![[r#"
+                29..30: UnreachableCode
+                    22..27: The assertion always fails
+                55..56: UnreachableCode
+                    38..42: The condition is always true
+                75..76: UnreachableCode
+                    64..69: The condition is always false
+                117..118: UnreachableCode
+                    102..115: The first argument always fails
+                156..157: UnreachableCode
+                    141..154: The first argument always fails
+                176..197: UnreachableCode
+                    169..174: The assertion always fails
+            "#]],
+        );
+        // Shadowed.
+        check(
+            "let true = false; in { x, y }: [ (if true then x else y) (assert false; x) ]",
+            expect![[r#"
+                72..73: UnreachableCode
+                    65..70: The assertion always fails
+            "#]],
+        );
+    }
+
     #[test]
     fn name_resolution() {
         check("a", expect!["0..1: UndefinedName"]);
@@ -70,8 +1468,271 @@ mod tests {
                 4..5: UnusedBinding
                 21..28: UnusedWith
                 33..36: UnusedRec
+                4..5: InfiniteRecursion
+                    4..5: Evaluating it requires the value of `a`
+                29..40: TypeMismatch
+                    29..40: `+` cannot be applied to an int and an attrset
             "#]],
         );
+        // Merged attrsets have no source of their own.
+        check(
+            "{ a = rec { }; a = { b = 1; }; }",
+            expect!["15..16: MergeRecAttrset"],
+        );
+    }
+
+    #[test]
+    fn option_enum_value() {
+        let (mut db, file_id) =
+            TestDB::single_file(r#"{ ... }: { foo = "bad"; bar = "a"; }"#).unwrap();
+        db.set_option_set(
+            OptionSet::Nixos,
+            Arc::new(NixosOptions::from_iter(["foo", "bar"].map(|name| {
+                let ty = nixos_options::Ty::Enum {
+                    values: vec!["a".into(), "b".into()],
+                };
+                let opt = NixosOption {
+                    ty,
+                    ..NixosOption::default()
+                };
+                (name.into(), opt)
+            }))),
+        );
+        let diags = super::diagnostics(&db, file_id);
+        let got = diags
+            .iter()
+            .map(|d| d.debug_display().to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        expect![[r#"
+            17..22: InvalidEnumValue
+                17..22: Expecting one of "a", "b""#]]
+        .assert_eq(&got);
+    }
+
+    #[test]
+    fn conflicting_definition() {
+        let (db, f) = TestDB::from_fixture(
+            "
+#- /a.nix
+{ lib, ... }: { foo = 1; bar = lib.mkDefault true; baz = [ 1 ]; qux = 1; }
+#- /b.nix
+{ lib, ... }: { foo = 2; bar = lib.mkDefault false; baz = [ 2 ]; qux = lib.mkForce 2; }
+            ",
+        )
+        .unwrap();
+        let diags = super::diagnostics(&db, f["/a.nix"]);
+        let got = diags
+            .iter()
+            .map(|d| d.debug_display().to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        expect![[r#"
+            16..19: ConflictingDefinition
+                16..19: Also defined here with priority 100
+            25..28: ConflictingDefinition
+                25..28: Also defined here with priority 1000 (`mkDefault`)"#]]
+        .assert_eq(&got);
+    }
+
+    #[test]
+    fn conflicting_definition_imports() {
+        let (db, f) = TestDB::from_fixture(
+            "
+#- /host-a.nix
+{ config, ... }: { imports = [ ./common.nix ./a ]; foo = 1; }
+#- /host-b.nix
+{ config, ... }: { imports = [ ./common.nix ]; foo = 2; bar = 2; }
+#- /common.nix
+{ config, ... }: { bar = 1; baz = config.foo; }
+#- /a/default.nix
+{ config, ... }: { qux.enable = config.bar; }
+#- /loose.nix
+{ config, ... }: { foo = 3; }
+            ",
+        )
+        .unwrap();
+        let check = |file: &str, expect: Expect| {
+            let got = super::diagnostics(&db, f[file])
+                .iter()
+                .map(|d| d.debug_display().to_string())
+                .collect::<Vec<_>>()
+                .join("\n");
+            expect.assert_eq(&got);
+        };
+        // Separate configurations, and modules not composed by `imports`.
+        check("/host-a.nix", expect!["2..8: UnusedBinding"]);
+        check("/loose.nix", expect!["2..8: UnusedBinding"]);
+        check(
+            "/common.nix",
+            expect![[r#"
+                19..22: ConflictingDefinition
+                    56..59: Also defined here with priority 100"#]],
+        );
+    }
+
+    #[test]
+    fn shell_args() {
+        check(
+            "{ pkgs }: pkgs.mkShell { package = [ ]; shellhook = \"\"; FOO = 1; nativeBuildInputs = [ ]; NAME = 1; META = 1; ENV = 1; }",
+            expect![[r#"
+                25..32: MisspelledShellArg
+                    25..32: Did you mean `packages`?
+                40..49: MisspelledShellArg
+                    40..49: Did you mean `shellHook`?
+                65..82: ShellNativeBuildInputs
+                    65..82: `packages` are added to `nativeBuildInputs` by `mkShell`
+            "#]],
+        );
+        assert_eq!(super::edit_distance("buildInput", "buildInputs"), 1);
+        assert_eq!(super::edit_distance("kitten", "sitting"), 3);
+        assert_eq!(super::edit_distance("NAME", "name"), 4);
+    }
+
+    #[test]
+    fn missing_package_arg() {
+        let (mut db, f) = TestDB::from_fixture(
+            "
+#- /default.nix
+{ pkgs, callPackage }: [
+  (callPackage ./pkg.nix { })
+  (pkgs.callPackage ./pkg.nix { foo = 1; })
+  (pkgs.callPackage ./pkg.nix { foo = 1; bar = 2; })
+  (pkgs.python3Packages.callPackage ./pkg.nix { })
+]
+#- /pkg.nix
+{ stdenv, foo, bar, baz ? 1 }: stdenv.mkDerivation { }
+            ",
+        )
+        .unwrap();
+        let check = |db: &TestDB, expect: Expect| {
+            let got = super::diagnostics(db, f["/default.nix"])
+                .iter()
+                .map(|d| d.debug_display().to_string())
+                .collect::<Vec<_>>()
+                .join("\n");
+            expect.assert_eq(&got);
+        };
+        // Unknown without the package index.
+        check(&db, expect![""]);
+
+        db.set_package_index(Arc::new(PackageIndex::from_iter([(
+            "stdenv".into(),
+            PackageInfo::default(),
+        )])));
+        check(
+            &db,
+            expect![[r#"
+            40..49: MissingPackageArg
+                50..53: `foo` is neither passed here nor a top-level attribute of nixpkgs
+                50..53: `bar` is neither passed here nor a top-level attribute of nixpkgs
+            75..84: MissingPackageArg
+                85..97: `bar` is neither passed here nor a top-level attribute of nixpkgs"#]],
+        );
+    }
+
+    #[test]
+    fn analysis_gaps() {
+        let (mut db, f) = TestDB::from_fixture(
+            r#"
+#- /default.nix
+[
+  (import ./foo.nix)
+  (import ./bar.nix)
+  (import <nixpkgs>)
+  (import <unknown>)
+  (import (./. + "/foo.nix"))
+  { ${"a" + "b"} = 1; "c" = 2; }
+]
+#- /foo.nix
+1
+            "#,
+        )
+        .unwrap();
+        db.set_search_path(Arc::new(SearchPath::parse("nixpkgs=/nixpkgs")));
+        let got = super::diagnostics(&db, f["/default.nix"])
+            .iter()
+            .map(|d| d.debug_display().to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        expect![[r#"
+            33..42: UnresolvedImport
+                33..42: The path is not resolved to a file in the workspace
+            75..84: UnresolvedImport
+                75..84: The path is not resolved to a file in the workspace
+            97..113: UnresolvedImport
+                97..113: The imported path is not a literal
+            122..131: DynamicAttr"#]]
+        .assert_eq(&got);
+    }
+
+    #[test]
+    fn missing_path() {
+        let (mut db, f) = TestDB::from_fixture(
+            r#"
+#- /default.nix
+[
+  (import ./foo.nix)
+  ./sub
+  ./src
+  ./missing.nix # nil:ignore missing_path
+  ./does-not-exist
+  ./${"foo"}.nix
+  /etc
+  <nixpkgs/lib>
+  <nixpkgs/missing>
+]
+#- /foo.nix
+1
+#- /sub/default.nix
+1
+#- /nixpkgs/lib/default.nix
+{ }
+            "#,
+        )
+        .unwrap();
+        db.set_search_path(Arc::new(SearchPath::parse("nixpkgs=/nixpkgs")));
+        let got = super::missing_paths(&db, f["/default.nix"], |path| {
+            path.as_path() == Some("/src".as_ref())
+        })
+        .iter()
+        .map(|d| d.debug_display().to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+        expect![[r#"
+            83..99: MissingPath
+            142..159: MissingPath"#]]
+        .assert_eq(&got);
+    }
+
+    #[test]
+    fn suppression() {
+        check(
+            "
+let
+  a = 1; # nil:ignore unused-binding
+  # nil:ignore W010
+  b = 2;
+  # nil:ignore unused_with
+  c = 3;
+in with 1; 0
+",
+            expect![[r#"
+                99..100: UnusedBinding
+                109..116: UnusedWith
+            "#]],
+        );
+        check(
+            "# nil:ignore-file unused\nlet a = 1; in with 1; { a = 1; a = 2; }",
+            expect![[r#"
+                56..57: DuplicatedKey
+                    49..50: Previously defined here
+            "#]],
+        );
+        check(
+            "# nil:ignore-file syntax\n1 == 2 == 3 + a",
+            expect!["39..40: UndefinedName"],
+        );
     }
 
     #[test]
@@ -0,0 +1,299 @@
+//! Completion of attributes only known by evaluation, like `(import ./lib.nix { }).|`.
+//!
+//! The attrset expression is extracted with the `let` and `rec` bindings it depends on,
+//! so that it can be evaluated standalone, eg. by `nix eval`, relative to the file.
+//! Expressions depending on lambda parameters or `with` are not closed, and are skipped.
+use std::collections::HashMap;
+
+use syntax::ast::{self, AstNode};
+use syntax::semantic::{escape_literal_attr, AttrKind};
+use syntax::{match_ast, SyntaxKind, TextRange, T};
+
+use super::completion::{CompletionItem, CompletionItemKind};
+use crate::def::{AstPtr, NameKind, ResolveResult};
+use crate::ty::{AttrSource, Ty};
+use crate::{FilePos, TyDatabase, VfsPath};
+
+/// The maximum number of bindings to inline.
+const MAX_BINDINGS: usize = 64;
+/// The maximum length of the extracted expression.
+const MAX_EXPR_LEN: usize = 64 << 10;
+
+/// An attrset expression whose attribute names can only be known by evaluation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalCompletionQuery {
+    /// The standalone Nix expression of the attrset.
+    pub expr: String,
+    /// The file containing the expression, which relative paths are resolved against.
+    pub file: VfsPath,
+    /// Range of identifier that is being completed.
+    pub source_range: TextRange,
+}
+
+impl EvalCompletionQuery {
+    /// Completion items of evaluated attribute `names`.
+    pub fn to_completions<'a>(
+        &'a self,
+        names: impl IntoIterator<Item = &'a str> + 'a,
+    ) -> impl Iterator<Item = CompletionItem> + 'a {
+        names.into_iter().map(|name| {
+            let escaped_name = escape_literal_attr(name);
+            CompletionItem {
+                label: escaped_name.as_ref().into(),
+                source_range: self.source_range,
+                replace: escaped_name.into(),
+                kind: CompletionItemKind::Evaluated,
+                signature: None,
+                description: None,
+                documentation: None,
+                additional_edits: Vec::new(),
+                command: None,
+            }
+        })
+    }
+}
+
+pub(crate) fn eval_completion_query(
+    db: &dyn TyDatabase,
+    FilePos { file_id, pos }: FilePos,
+) -> Option<EvalCompletionQuery> {
+    let parse = db.parse(file_id);
+    let src = db.file_content(file_id);
+    let tok = parse.syntax_node().token_at_offset(pos).left_biased()?;
+    let source_range = match tok.kind() {
+        SyntaxKind::IDENT if tok.text_range().end() == pos => tok.text_range(),
+        _ => TextRange::empty(pos),
+    };
+    // `set.a.b.|` or `set ? a.b.|`.
+    let (set_node, path_node) = tok.parent_ancestors().find_map(|node| {
+        match_ast! {
+            match node {
+                ast::Select(n) => Some((n.set()?, n.attrpath())),
+                ast::HasAttr(n) => Some((n.set()?, n.attrpath())),
+                _ => None,
+            }
+        }
+    })?;
+    let set_range = set_node.syntax().text_range();
+    if pos <= set_range.end() || tok.kind() == T!['}'] {
+        return None;
+    }
+
+    // Prefix attributes before the current one, which must be static.
+    let mut prefix = Vec::new();
+    let mut set_text = format!("({})", &src[set_range]);
+    for attr in path_node.iter().flat_map(|path| path.attrs()) {
+        let range = attr.syntax().text_range();
+        if pos <= range.end() {
+            break;
+        }
+        let AttrKind::Static(Some(field)) = AttrKind::of(attr) else {
+            return None;
+        };
+        prefix.push(field);
+        set_text += ".";
+        set_text += &src[range];
+    }
+
+    let module = db.module(file_id);
+    let source_map = db.source_map(file_id);
+    // Parentheses are not lowered.
+    let set_expr = source_map.expr_for_node(AstPtr::new(set_node.flatten_paren()?.syntax()))?;
+
+    // Only if static analysis knows nothing about the attrset, except for fields inferred from
+    // uses like the current one.
+    let infer = db.infer(file_id);
+    let ty = prefix
+        .iter()
+        .try_fold(infer.ty_for_expr(set_expr), |ty, field| {
+            match ty.as_attrset() {
+                Some(set) => Some(set.get(field).cloned().unwrap_or(Ty::Unknown)),
+                None => matches!(ty, Ty::Unknown).then_some(Ty::Unknown),
+            }
+        })?;
+    let is_unknown = match &ty {
+        Ty::Unknown => true,
+        ty => ty.as_attrset().map_or(false, |set| {
+            set.iter().all(|(_, _, src)| src == AttrSource::Unknown)
+        }),
+    };
+    if !is_unknown {
+        return None;
+    }
+
+    // Collect `let` and `rec` bindings referenced transitively, outside of the range
+    // being extracted.
+    let nameres = db.name_resolution(file_id);
+    let mut bindings = Vec::new();
+    let mut seen = HashMap::new();
+    let mut stack = vec![(set_expr, set_range)];
+    let mut exprs = Vec::new();
+    while let Some((root, range)) = stack.pop() {
+        exprs.clear();
+        exprs.push(root);
+        while let Some(expr) = exprs.pop() {
+            module[expr].walk_child_exprs(|e| exprs.push(e));
+            let Some(resolved) = nameres.get(expr) else {
+                continue;
+            };
+            let name = match resolved {
+                ResolveResult::Builtin(_) => continue,
+                ResolveResult::WithExprs(_) => return None,
+                &ResolveResult::Definition(name) => name,
+            };
+            let mut ptrs = source_map.nodes_for_name(name);
+            let (Some(ptr), None) = (ptrs.next(), ptrs.next()) else {
+                return None;
+            };
+            if range.contains_range(ptr.text_range()) {
+                continue;
+            }
+            let text = &module[name].text;
+            match seen.get(text) {
+                Some(&seen) if seen == name => continue,
+                // Shadowed names cannot be inlined in a single `let`.
+                Some(_) => return None,
+                None => {}
+            }
+            if !matches!(module[name].kind, NameKind::LetIn | NameKind::RecAttrset) {
+                return None;
+            }
+            let value_node = value_of_name(&ptr.to_node(&parse.syntax_node()))?;
+            let value_range = value_node.syntax().text_range();
+            let value_expr =
+                source_map.expr_for_node(AstPtr::new(value_node.flatten_paren()?.syntax()))?;
+            seen.insert(text.clone(), name);
+            bindings.push((text.clone(), value_range));
+            if bindings.len() > MAX_BINDINGS {
+                return None;
+            }
+            stack.push((value_expr, value_range));
+        }
+    }
+
+    let mut expr = String::new();
+    if !bindings.is_empty() {
+        expr += "let ";
+        for (name, range) in &bindings {
+            expr += &format!("{name} = {}; ", &src[*range]);
+        }
+        expr += "in ";
+    }
+    expr += &set_text;
+    if expr.len() > MAX_EXPR_LEN {
+        return None;
+    }
+
+    let file = db
+        .source_root(db.file_source_root(file_id))
+        .path_for_file(file_id)
+        .clone();
+    Some(EvalCompletionQuery {
+        expr,
+        file,
+        source_range,
+    })
+}
+
+/// The value of a name defined by `name = value;`, but not `name.a = value;` or `inherit`.
+fn value_of_name(node: &syntax::SyntaxNode) -> Option<ast::Expr> {
+    let path = ast::Attrpath::cast(node.parent()?)?;
+    if path.attrs().nth(1).is_some() {
+        return None;
+    }
+    let value = ast::AttrpathValue::cast(path.syntax().parent()?)?.value()?;
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::completion::{completions, merge_completions, LibImportStrategy};
+    use crate::tests::TestDB;
+    use crate::SourceDatabase;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let got = super::eval_completion_query(&db, f[0]).map(|query| query.expr);
+        expect.assert_eq(got.as_deref().unwrap_or("<none>"));
+    }
+
+    #[test]
+    fn import() {
+        check(
+            "(import ./lib.nix { }).$0",
+            expect!["((import ./lib.nix { }))"],
+        );
+        check(
+            "(import ./lib.nix { }).foo.ba$0",
+            expect!["((import ./lib.nix { })).foo"],
+        );
+        check(
+            "(import ./lib.nix { }) ? foo.$0",
+            expect!["((import ./lib.nix { })).foo"],
+        );
+    }
+
+    #[test]
+    fn inline_bindings() {
+        check(
+            "
+let
+  pkgs = import <nixpkgs> { inherit overlays; };
+  overlays = [ (final: prev: { }) ];
+  unused = 1;
+in
+rec {
+  lib = pkgs.lib;
+  foo = lib.$0;
+}
+            ",
+            expect!["let lib = pkgs.lib; pkgs = import <nixpkgs> { inherit overlays; }; overlays = [ (final: prev: { }) ]; in (lib)"],
+        );
+
+        // Names bound inside are kept.
+        check(
+            "let f = x: import x; in (f ./a.nix).$0",
+            expect!["let f = x: import x; in ((f ./a.nix))"],
+        );
+    }
+
+    #[test]
+    fn not_closed() {
+        check("pkgs: pkgs.$0", expect!["<none>"]);
+        check("with import ./lib.nix; foo.$0", expect!["<none>"]);
+        // Shadowed names.
+        check(
+            "let a = import ./a.nix; in let b = a; in let a = b; in a.$0",
+            expect!["<none>"],
+        );
+    }
+
+    #[test]
+    fn statically_known() {
+        check("{ a = 1; }.$0", expect!["<none>"]);
+        check("let set = { a = 1; }; in set.$0", expect!["<none>"]);
+        check("builtins.$0", expect!["<none>"]);
+        check("1.$0", expect!["<none>"]);
+    }
+
+    #[test]
+    fn merge() {
+        let (db, f) = TestDB::from_fixture("let lib = import ./lib.nix; in lib.f$0").unwrap();
+        let query = super::eval_completion_query(&db, f[0]).unwrap();
+        let mut items = completions(&db, f[0], None, LibImportStrategy::default()).unwrap();
+        let names = ["bar", "fooBar", "foo"];
+        let src = db.file_content(f[0].file_id);
+        merge_completions(&src, f[0].pos, &mut items, query.to_completions(names));
+        let got = items
+            .iter()
+            .map(|item| format!("{} {:?}\n", item.label, item.kind))
+            .collect::<String>();
+        expect![[r#"
+            foo Evaluated
+            fooBar Evaluated
+        "#]]
+        .assert_eq(&got);
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{DefDatabase, FileId};
+use crate::def::{Expr, Literal};
+use crate::{DefDatabase, FileId, TextEdit, VfsPath};
+use nix_interop::DEFAULT_IMPORT_FILE;
+use std::path::{Component, Path, PathBuf};
 
 pub(crate) fn file_references(db: &dyn DefDatabase, file: FileId) -> Vec<FileId> {
     let mut refs = db
@@ -13,3 +16,284 @@ pub(crate) fn file_references(db: &dyn DefDatabase, file: FileId) -> Vec<FileId>
 pub(crate) fn file_referrers(db: &dyn DefDatabase, file: FileId) -> Vec<FileId> {
     db.module_referrers(file).into_vec()
 }
+
+/// Paths of relative path literals in `file` which are not resolved to any loaded file,
+/// in the order of appearance.
+pub(crate) fn unloaded_references(db: &dyn DefDatabase, file: FileId) -> Vec<VfsPath> {
+    let mut paths = Vec::new();
+    for (_, kind) in db.module(file).exprs() {
+        let Expr::Literal(Literal::Path(path)) = kind else {
+            continue;
+        };
+        if path.resolve_file(db).is_some() {
+            continue;
+        }
+        if let Some(vpath) = path.resolve(db) {
+            if !paths.contains(&vpath) {
+                paths.push(vpath);
+            }
+        }
+    }
+    paths
+}
+
+/// Edits of relative path literals in `file`, to keep their targets after moving files or
+/// directories by `renames` of `(old, new)` paths. `file` itself may be moved as well.
+/// Targets which cannot be written as a path literal are left unchanged.
+pub(crate) fn path_rename_edits(
+    db: &dyn DefDatabase,
+    file: FileId,
+    renames: &[(PathBuf, PathBuf)],
+) -> Vec<TextEdit> {
+    let moved = |path: &Path| {
+        renames.iter().find_map(|(old, new)| {
+            let rest = path.strip_prefix(old).ok()?;
+            Some(if rest.as_os_str().is_empty() {
+                new.clone()
+            } else {
+                new.join(rest)
+            })
+        })
+    };
+
+    let source_root = db.source_root(db.file_source_root(file));
+    let Some(file_path) = source_root.path_for_file(file).as_path() else {
+        return Vec::new();
+    };
+    let Some(file_dir) = file_path.parent() else {
+        return Vec::new();
+    };
+    let new_file_dir = moved(file_path).and_then(|path| Some(path.parent()?.to_owned()));
+
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let mut edits = Vec::new();
+    for (e, kind) in module.exprs() {
+        let Expr::Literal(Literal::Path(path)) = kind else {
+            continue;
+        };
+        let Some(VfsPath::Path(target)) = path.resolve(db) else {
+            continue;
+        };
+        // `./dir` refers to `./dir/default.nix`, which may be moved alone.
+        // But `./.` is usually the directory itself, not an import of this file.
+        let new_target = moved(&target).or_else(|| {
+            if target == file_dir {
+                return None;
+            }
+            let resolved_file = path.resolve_file(db)?;
+            let resolved_root = db.source_root(db.file_source_root(resolved_file));
+            let resolved = resolved_root.path_for_file(resolved_file).as_path()?;
+            let new_path = moved(resolved)?;
+            Some(match new_path.parent() {
+                Some(dir) if new_path.ends_with(DEFAULT_IMPORT_FILE) => dir.to_owned(),
+                _ => new_path,
+            })
+        });
+        if new_file_dir.is_none() && new_target.is_none() {
+            continue;
+        }
+
+        let new_text = relative_path_literal(
+            new_file_dir.as_deref().unwrap_or(file_dir),
+            new_target.as_deref().unwrap_or(&target),
+        );
+        let Some(new_text) = new_text else {
+            continue;
+        };
+        if relative_path_literal(file_dir, &target).as_ref() == Some(&new_text) {
+            continue;
+        }
+        if let Some(ptr) = source_map.node_for_expr(e) {
+            edits.push(TextEdit {
+                delete: ptr.text_range(),
+                insert: new_text.into(),
+            });
+        }
+    }
+    edits
+}
+
+/// The relative path literal from the directory `from` to `to`, like `../foo.nix`.
+fn relative_path_literal(from: &Path, to: &Path) -> Option<String> {
+    let common = from
+        .components()
+        .zip(to.components())
+        .take_while(|(lhs, rhs)| lhs == rhs)
+        .count();
+    let mut segments = vec![".."; from.components().count() - common];
+    for comp in to.components().skip(common) {
+        let Component::Normal(seg) = comp else {
+            return None;
+        };
+        let seg = seg.to_str()?;
+        let is_valid = seg
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"._-+".contains(&b));
+        if !is_valid {
+            return None;
+        }
+        segments.push(seg);
+    }
+    // Path literals must contain a `/`.
+    Some(match segments[..] {
+        [] => "./.".into(),
+        [".."] => "../.".into(),
+        ["..", ..] => segments.join("/"),
+        _ => format!("./{}", segments.join("/")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use crate::{SourceDatabase, VfsPath};
+    use expect_test::{expect, Expect};
+    use std::path::PathBuf;
+
+    #[track_caller]
+    fn check_rename(fixture: &str, renames: &[(&str, &str)], expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let renames = renames
+            .iter()
+            .map(|&(old, new)| (PathBuf::from(old), PathBuf::from(new)))
+            .collect::<Vec<_>>();
+        let mut got = String::new();
+        for &file in f.files() {
+            let mut edits = super::path_rename_edits(&db, file, &renames);
+            if edits.is_empty() {
+                continue;
+            }
+            edits.sort_by_key(|edit| edit.delete.start());
+            let mut src = db.file_content(file).to_string();
+            for edit in edits.iter().rev() {
+                edit.apply(&mut src);
+            }
+            got += &src;
+            got += "\n";
+        }
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn unloaded_references() {
+        let (db, f) = TestDB::from_fixture(
+            "
+#- /default.nix
+[ ./bar.nix ./foo.nix ./foo.nix ./. <nixpkgs> ./dir ]
+
+#- /bar.nix
+0
+",
+        )
+        .unwrap();
+        let paths = super::unloaded_references(&db, f["/default.nix"]);
+        assert_eq!(paths, [VfsPath::new("/foo.nix"), VfsPath::new("/dir")]);
+    }
+
+    #[test]
+    fn rename_referenced_file() {
+        check_rename(
+            "
+#- /default.nix
+[ ./foo.nix ./bar.nix ./foo.nix/.. ]
+
+#- /sub/a.nix
+import ../foo.nix
+
+#- /foo.nix
+0
+
+#- /bar.nix
+0
+",
+            &[("/foo.nix", "/dir/baz.nix")],
+            expect![[r#"
+                [ ./dir/baz.nix ./bar.nix ./foo.nix/.. ]
+                import ../dir/baz.nix
+            "#]],
+        );
+    }
+
+    #[test]
+    fn move_referrer() {
+        check_rename(
+            "
+#- /default.nix
+[ ./foo.nix ./. ./sub/a.nix <nixpkgs> /abs ]
+
+#- /sub/a.nix
+0
+
+#- /foo.nix
+0
+",
+            &[("/default.nix", "/sub/default.nix")],
+            expect![[r#"
+                [ ../foo.nix ../. ./a.nix <nixpkgs> /abs ]
+            "#]],
+        );
+    }
+
+    #[test]
+    fn move_directory() {
+        check_rename(
+            "
+#- /default.nix
+[ ./dir ./dir/a.nix ./other.nix ]
+
+#- /dir/default.nix
+[ ./a.nix ../other.nix ]
+
+#- /dir/a.nix
+0
+
+#- /other.nix
+0
+",
+            &[("/dir", "/lib/dir2")],
+            expect![[r#"
+                [ ./lib/dir2 ./lib/dir2/a.nix ./other.nix ]
+                [ ./a.nix ../../other.nix ]
+            "#]],
+        );
+    }
+
+    #[test]
+    fn move_default_nix() {
+        check_rename(
+            "
+#- /default.nix
+[ ./dir ./dir2 ]
+
+#- /dir/default.nix
+0
+
+#- /dir2/default.nix
+0
+",
+            &[
+                ("/dir/default.nix", "/dir/foo.nix"),
+                ("/dir2/default.nix", "/dir3/default.nix"),
+            ],
+            expect![[r#"
+                [ ./dir/foo.nix ./dir3 ]
+            "#]],
+        );
+    }
+
+    #[test]
+    fn rename_to_invalid_path_literal() {
+        check_rename(
+            "
+#- /default.nix
+import ./foo.nix
+
+#- /foo.nix
+0
+",
+            &[("/foo.nix", "/foo bar.nix")],
+            expect![""],
+        );
+    }
+}
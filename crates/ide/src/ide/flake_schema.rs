@@ -0,0 +1,271 @@
+//! Structural checks of `flake.nix` against the flake schema.
+//!
+//! See: https://nix.dev/manual/nix/stable/command-ref/new-cli/nix3-flake#flake-format
+use crate::def::{peel_expr, BindingValue, Bindings, Expr, ExprId, Literal};
+use crate::ty::known::FLAKE_OUTPUT_GENERIC_SYSTEM_FIELDS;
+use crate::{Diagnostic, DiagnosticKind, FileId, FileRange, Module, ModuleKind, TyDatabase};
+
+/// Top-level attributes allowed in `flake.nix`.
+const FLAKE_ATTRS: &[&str] = &["description", "inputs", "nixConfig", "outputs"];
+
+/// Deprecated outputs which are also keyed by systems.
+const LEGACY_SYSTEM_OUTPUTS: &[&str] = &["defaultApp", "defaultPackage", "devShell"];
+
+/// Kernels of system doubles like `x86_64-linux`.
+const SYSTEM_KERNELS: &[&str] = &[
+    "cygwin", "darwin", "freebsd", "genode", "linux", "netbsd", "none", "openbsd", "redox", "wasi",
+    "windows",
+];
+
+/// Check top-level attributes, `follows` of inputs, and system-keyed outputs of `flake.nix`.
+pub(crate) fn flake_schema(db: &impl TyDatabase, file: FileId) -> Vec<Diagnostic> {
+    let kind = db.module_kind(file);
+    let ModuleKind::FlakeNix {
+        explicit_inputs,
+        param_inputs,
+        follows,
+        outputs_expr,
+    } = &*kind
+    else {
+        return Vec::new();
+    };
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let mut ret = Vec::new();
+    let mut push = |range: Option<_>, kind, note: String| {
+        if let Some(range) = range {
+            ret.push(Diagnostic::new(range, kind).with_note(FileRange::new(file, range), note));
+        }
+    };
+    let name_range = |name| {
+        source_map
+            .nodes_for_name(name)
+            .next()
+            .map(|ptr| ptr.text_range())
+    };
+    let expr_range = |expr| source_map.node_for_expr(expr).map(|ptr| ptr.text_range());
+
+    let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) = &module[module.entry_expr()]
+    else {
+        return ret;
+    };
+    for &(name, value) in bindings.statics.iter() {
+        let text = &*module[name].text;
+        if !FLAKE_ATTRS.contains(&text) {
+            push(
+                name_range(name),
+                DiagnosticKind::UnknownFlakeAttr,
+                format!("Expecting one of {}", FLAKE_ATTRS.join(", ")),
+            );
+            continue;
+        }
+        let BindingValue::Expr(value) = value else {
+            continue;
+        };
+        let note = match (text, &module[value]) {
+            (
+                "description",
+                Expr::Literal(Literal::Int(_) | Literal::Float(_) | Literal::Path(_))
+                | Expr::PathInterpolation(_)
+                | Expr::List(_)
+                | Expr::Attrset(_)
+                | Expr::RecAttrset(_)
+                | Expr::Lambda(..),
+            ) => "Expecting a string",
+            (
+                "outputs",
+                Expr::Literal(_)
+                | Expr::StringInterpolation(_)
+                | Expr::PathInterpolation(_)
+                | Expr::List(_)
+                | Expr::Attrset(_)
+                | Expr::RecAttrset(_),
+            ) => "Expecting a function like `{ self, ... }: { }`",
+            _ => continue,
+        };
+        push(
+            expr_range(value),
+            DiagnosticKind::InvalidFlakeAttr,
+            note.into(),
+        );
+    }
+
+    for (expr, input) in follows {
+        // `follows = "";` follows the flake itself.
+        if input.is_empty()
+            || explicit_inputs.contains_key(input)
+            || param_inputs.contains_key(input)
+        {
+            continue;
+        }
+        push(
+            expr_range(*expr),
+            DiagnosticKind::UndefinedFollows,
+            format!("Input `{input}` is not declared"),
+        );
+    }
+
+    let body = outputs_expr
+        .and_then(|lambda| match &module[lambda] {
+            &Expr::Lambda(_, _, body) => Some(peel_expr(&module, body)),
+            _ => None,
+        })
+        .and_then(|body| literal_bindings(&module, body));
+    if let Some(body) = body {
+        let fields = FLAKE_OUTPUT_GENERIC_SYSTEM_FIELDS
+            .iter()
+            .copied()
+            .chain(LEGACY_SYSTEM_OUTPUTS.iter().map(|&field| (field, 0)));
+        for (field, depth) in fields {
+            let Some(BindingValue::Expr(value)) = body.get(field, &module) else {
+                continue;
+            };
+            // Descend to attrsets keyed by systems, through literal attrsets only.
+            let mut sets = Vec::from_iter(literal_bindings(&module, value));
+            for _ in 0..depth {
+                sets = sets
+                    .iter()
+                    .flat_map(|set| set.statics.iter())
+                    .filter_map(|&(_, value)| match value {
+                        BindingValue::Expr(value) => literal_bindings(&module, value),
+                        _ => None,
+                    })
+                    .collect();
+            }
+            for &(name, _) in sets.iter().flat_map(|set| set.statics.iter()) {
+                let system = &*module[name].text;
+                if is_system(system) {
+                    continue;
+                }
+                push(
+                    name_range(name),
+                    DiagnosticKind::NonSystemOutput,
+                    format!("`{field}` is keyed by systems like `x86_64-linux`"),
+                );
+            }
+        }
+    }
+
+    // Keep the order deterministic.
+    ret.sort_by_key(|diag| diag.range.start());
+    ret
+}
+
+fn literal_bindings(module: &Module, expr: ExprId) -> Option<&Bindings> {
+    match &module[expr] {
+        Expr::Attrset(bindings) | Expr::RecAttrset(bindings) => Some(bindings),
+        _ => None,
+    }
+}
+
+/// Check if `name` looks like a system double `<cpu>-<kernel>`.
+fn is_system(name: &str) -> bool {
+    name.split_once('-').map_or(false, |(cpu, kernel)| {
+        !cpu.is_empty() && SYSTEM_KERNELS.contains(&kernel)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let got = super::flake_schema(&db, f["/flake.nix"])
+            .iter()
+            .map(|d| d.debug_display().to_string() + "\n")
+            .collect::<String>();
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn top_level() {
+        check(
+            r#"
+#- /flake.nix
+{
+    description = 42;
+    inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+    output = { self }: { };
+    nixConfig.sandbox = true;
+    outputs = { };
+}
+            "#,
+            expect![[r#"
+                20..22: InvalidFlakeAttr
+                    20..22: Expecting a string
+                77..83: UnknownFlakeAttr
+                    77..83: Expecting one of description, inputs, nixConfig, outputs
+                145..148: InvalidFlakeAttr
+                    145..148: Expecting a function like `{ self, ... }: { }`
+            "#]],
+        );
+
+        // Not literals.
+        check(
+            r#"
+#- /flake.nix
+{
+    description = "foo ${"bar"}";
+    outputs = import ./outputs.nix;
+}
+            "#,
+            expect![""],
+        );
+    }
+
+    #[test]
+    fn follows() {
+        check(
+            r#"
+#- /flake.nix
+{
+    inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+    inputs.foo.inputs.nixpkgs.follows = "nixpkgs";
+    inputs.foo.inputs.utils.follows = "flake-utils/utils";
+    inputs.bar.inputs.nixpkgs.follows = "";
+    outputs = { self, nixpkgs, foo, bar, baz }: { };
+    inputs.baz.inputs.qux.follows = "baz";
+}
+            "#,
+            expect![[r#"
+                140..159: UndefinedFollows
+                    140..159: Input `flake-utils` is not declared
+            "#]],
+        );
+    }
+
+    #[test]
+    fn system_outputs() {
+        check(
+            r#"
+#- /flake.nix
+{
+    outputs = { self }: let system = "x86_64-linux"; in {
+        packages.x86_64-linux.default = 1;
+        packages.hello = 1;
+        packages.${system}.foo = 1;
+        devShells.aarch64-darwin.default = 1;
+        checks = { default = { }; };
+        hydraJobs.hello.riscv64-linux = 1;
+        hydraJobs.hello.default = 1;
+        defaultPackage.default = 1;
+        nixosModules.default = { };
+    };
+}
+            "#,
+            expect![[r#"
+                120..125: NonSystemOutput
+                    120..125: `packages` is keyed by systems like `x86_64-linux`
+                232..239: NonSystemOutput
+                    232..239: `checks` is keyed by systems like `x86_64-linux`
+                317..324: NonSystemOutput
+                    317..324: `hydraJobs` is keyed by systems like `x86_64-linux`
+                353..360: NonSystemOutput
+                    353..360: `defaultPackage` is keyed by systems like `x86_64-linux`
+            "#]],
+        );
+    }
+}
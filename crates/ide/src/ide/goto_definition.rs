@@ -1,7 +1,12 @@
+use super::completion::is_package_set;
+use super::rename::call_site_args;
 use super::NavigationTarget;
-use crate::def::{AstPtr, Expr, Literal, ResolveResult};
-use crate::{DefDatabase, FileId, FilePos, ModuleKind, VfsPath};
+use crate::def::{const_attr_name, AstPtr, Expr, Literal, NameId, NameKind, ResolveResult};
+use crate::ty::{config_param, AttrSource, Ty};
+use crate::{DefDatabase, FileId, FilePos, InFile, ModuleKind, OptionSet, TyDatabase, VfsPath};
+use nix_interop::nixos_options::{NixosOptions, Ty as OptionTy};
 use nix_interop::FLAKE_FILE;
+use smol_str::SmolStr;
 use syntax::ast::{self, AstNode};
 use syntax::{best_token_at_offset, match_ast, SyntaxKind, SyntaxToken};
 
@@ -9,11 +14,37 @@ use syntax::{best_token_at_offset, match_ast, SyntaxKind, SyntaxToken};
 pub enum GotoDefinitionResult {
     Path(VfsPath),
     Targets(Vec<NavigationTarget>),
+    /// An attribute `outputs.<attrpath>` of the locked flake input at `store_path`,
+    /// whose position can only be known by evaluation.
+    FlakeInputAttr {
+        store_path: VfsPath,
+        attrpath: Vec<String>,
+    },
+    /// A top-level attribute `name` of nixpkgs, passed to a parameter of a package by
+    /// `callPackage` or selected from the package set, whose position can only be known by
+    /// evaluation.
+    NixpkgsAttr {
+        name: String,
+    },
+    /// A NixOS option declared outside of the workspace, by files `declarations` which are
+    /// either absolute or relative to the source of the option set `set`, followed by its
+    /// definitions in the workspace.
+    NixosOption {
+        set: OptionSet,
+        declarations: Vec<String>,
+        targets: Vec<NavigationTarget>,
+    },
 }
 
+/// The maximum number of `inherit (set) name;` to go through.
+const MAX_INHERIT_HOPS: usize = 16;
+
+/// If `inherit_hops` is set, targets also include each `inherit (set) name;` passed through,
+/// before the original definition.
 pub(crate) fn goto_definition(
-    db: &dyn DefDatabase,
+    db: &impl TyDatabase,
     FilePos { file_id, pos }: FilePos,
+    inherit_hops: bool,
 ) -> Option<GotoDefinitionResult> {
     let parse = db.parse(file_id);
     let tok = best_token_at_offset(&parse.syntax_node(), pos)?;
@@ -23,6 +54,32 @@ pub(crate) fn goto_definition(
         return Some(ret);
     }
 
+    // Special case for attributes of flake inputs, like `nixpkgs.lib.foo`.
+    if let Some(ret) = goto_flake_input_attr(db, file_id, tok.clone()) {
+        return Some(ret);
+    }
+
+    // Special case for parameters of packages.
+    if let Some(ret) = goto_package_arg(db, file_id, tok.clone()) {
+        return Some(ret);
+    }
+
+    // Special case for option values like `config.foo.enable` in NixOS modules.
+    if let Some(ret) = goto_option_definition(db, file_id, tok.clone()) {
+        return Some(ret);
+    }
+
+    // Special case for attributes of selections, from type information.
+    if let Some(name) = select_attr_source(db, file_id, tok.clone()) {
+        let targets = inherit_targets(db, name, inherit_hops);
+        return (!targets.is_empty()).then_some(GotoDefinitionResult::Targets(targets));
+    }
+
+    // Special case for packages selected from the package set, like `prev.hello` in overlays.
+    if let Some(ret) = goto_package_set_attr(db, file_id, tok.clone()) {
+        return Some(ret);
+    }
+
     let ptr = tok.parent_ancestors().find_map(|node| {
         match_ast! {
             match node {
@@ -35,10 +92,28 @@ pub(crate) fn goto_definition(
     })?;
 
     let source_map = db.source_map(file_id);
+
+    // `inherit (set) name;` itself goes to where `set.name` is defined.
+    if let Some(name) = source_map.name_for_node(ptr.clone()) {
+        let chain = inherit_chain(db, InFile::new(file_id, name));
+        if chain.len() > 1 {
+            let hops = if inherit_hops {
+                &chain[1..]
+            } else {
+                &chain[chain.len() - 1..]
+            };
+            let targets = hops
+                .iter()
+                .flat_map(|&name| name_targets(db, name))
+                .collect::<Vec<_>>();
+            return (!targets.is_empty()).then_some(GotoDefinitionResult::Targets(targets));
+        }
+    }
+
     let expr_id = source_map.expr_for_node(ptr)?;
 
     // Special case for goto-path.
-    if tok.kind() == SyntaxKind::PATH {
+    if matches!(tok.kind(), SyntaxKind::PATH | SyntaxKind::SEARCH_PATH) {
         let module = db.module(file_id);
         let Expr::Literal(Literal::Path(path)) = &module[expr_id] else {
             return None;
@@ -49,23 +124,9 @@ pub(crate) fn goto_definition(
 
     let name_res = db.name_resolution(file_id);
     let targets = match name_res.get(expr_id)? {
-        &ResolveResult::Definition(name) => source_map
-            .nodes_for_name(name)
-            .filter_map(|ptr| {
-                let name_node = ptr.to_node(&parse.syntax_node());
-                let full_node = name_node.ancestors().find(|n| {
-                    matches!(
-                        n.kind(),
-                        SyntaxKind::LAMBDA | SyntaxKind::ATTR_PATH_VALUE | SyntaxKind::INHERIT
-                    )
-                })?;
-                Some(NavigationTarget {
-                    file_id,
-                    focus_range: name_node.text_range(),
-                    full_range: full_node.text_range(),
-                })
-            })
-            .collect(),
+        &ResolveResult::Definition(name) => {
+            inherit_targets(db, InFile::new(file_id, name), inherit_hops)
+        }
         ResolveResult::WithExprs(withs) => {
             withs
                 .iter()
@@ -98,6 +159,357 @@ pub(crate) fn goto_definition(
     Some(GotoDefinitionResult::Targets(targets))
 }
 
+pub(crate) fn name_targets(db: &dyn DefDatabase, name: InFile<NameId>) -> Vec<NavigationTarget> {
+    let parse = db.parse(name.file_id);
+    let source_map = db.source_map(name.file_id);
+    source_map
+        .nodes_for_name(name.value)
+        .filter_map(|ptr| {
+            let name_node = ptr.to_node(&parse.syntax_node());
+            let full_node = name_node.ancestors().find(|n| {
+                matches!(
+                    n.kind(),
+                    SyntaxKind::LAMBDA | SyntaxKind::ATTR_PATH_VALUE | SyntaxKind::INHERIT
+                )
+            })?;
+            Some(NavigationTarget {
+                file_id: name.file_id,
+                focus_range: name_node.text_range(),
+                full_range: full_node.text_range(),
+            })
+        })
+        .collect()
+}
+
+/// Targets of `name`, going through `inherit (set) name;` to the original definition.
+fn inherit_targets(
+    db: &impl TyDatabase,
+    name: InFile<NameId>,
+    inherit_hops: bool,
+) -> Vec<NavigationTarget> {
+    let chain = inherit_chain(db, name);
+    let hops = if inherit_hops {
+        &chain[..]
+    } else {
+        &chain[chain.len() - 1..]
+    };
+    hops.iter()
+        .flat_map(|&name| name_targets(db, name))
+        .collect()
+}
+
+/// The chain of names starting from `name`, where each one is defined by
+/// `inherit (set) name;` and the next one is the definition of `set.name`, see
+/// `inherit_source`. The last one is the original definition.
+fn inherit_chain(db: &impl TyDatabase, name: InFile<NameId>) -> Vec<InFile<NameId>> {
+    let mut chain = vec![name];
+    while chain.len() <= MAX_INHERIT_HOPS {
+        let Some(next) = inherit_source(db, *chain.last().unwrap()) else {
+            break;
+        };
+        if chain.contains(&next) {
+            break;
+        }
+        chain.push(next);
+    }
+    chain
+}
+
+/// The definition of `set.name` for `name` defined by `inherit (set) name;`, known by the type
+/// of `set`, or by types of arguments passed to the parameter `set` at call sites, like `lib`
+/// in `callPackage ./pkg.nix { lib = import ./lib.nix; }`.
+fn inherit_source(db: &impl TyDatabase, name: InFile<NameId>) -> Option<InFile<NameId>> {
+    let parse = db.parse(name.file_id);
+    let source_map = db.source_map(name.file_id);
+    let set_expr = source_map.nodes_for_name(name.value).find_map(|ptr| {
+        let node = ptr.to_node(&parse.syntax_node());
+        let set_node = ast::Inherit::cast(node.parent()?)?.from_expr()?.expr()?;
+        source_map.expr_for_node(AstPtr::new(set_node.syntax()))
+    })?;
+    let module = db.module(name.file_id);
+    let field = &module[name.value].text;
+    // Fields of unknown sets are inferred from the `inherit` itself.
+    let source_in = |file: FileId, set_ty: &Ty| {
+        let next = match set_ty.as_attrset()?.get_src(field)? {
+            AttrSource::Name(next) => InFile::new(file, next),
+            AttrSource::Imported(next) => next,
+            _ => return None,
+        };
+        (next != name).then_some(next)
+    };
+    if let Some(next) = source_in(name.file_id, &db.infer(name.file_id).ty_for_expr(set_expr)) {
+        return Some(next);
+    }
+
+    let Some(&ResolveResult::Definition(param)) = db.name_resolution(name.file_id).get(set_expr)
+    else {
+        return None;
+    };
+    if module[param].kind != NameKind::PatField {
+        return None;
+    }
+    let param_text = &module[param].text;
+    call_site_args(db, InFile::new(name.file_id, param))
+        .into_iter()
+        .find_map(|arg| {
+            let arg_ty = db.infer(arg.file_id).ty_for_expr(arg.value);
+            source_in(arg.file_id, arg_ty.as_attrset()?.get(param_text)?)
+        })
+}
+
+/// The attribute containing `tok`, unless `tok` is a reference inside `${}`, like `name` in
+/// `${name}`, which goes to its own definition instead.
+fn attr_at(tok: &SyntaxToken) -> Option<ast::Attr> {
+    if tok.parent()?.kind() == SyntaxKind::REF {
+        return None;
+    }
+    tok.parent_ancestors().find_map(ast::Attr::cast)
+}
+
+/// Find the definition of `b` in `a.b`, if the source of the field is known by type inference.
+/// This also works for attrsets from other files via `import`.
+pub(crate) fn select_attr_source(
+    db: &dyn TyDatabase,
+    file: FileId,
+    tok: SyntaxToken,
+) -> Option<InFile<NameId>> {
+    let attr_node = attr_at(&tok)?;
+    let path_node = attr_node.syntax().parent().and_then(ast::Attrpath::cast)?;
+    let select_node = path_node.syntax().parent().and_then(ast::Select::cast)?;
+
+    let source_map = db.source_map(file);
+    let infer = db.infer(file);
+    let set_node = select_node.set()?.flatten_paren()?;
+    let set_expr = source_map.expr_for_node(AstPtr::new(set_node.syntax()))?;
+    let mut set_ty = infer.ty_for_expr(set_expr);
+    for attr in path_node.attrs() {
+        let Some(field) = const_attr_name(db, file, attr.clone()) else {
+            return None;
+        };
+        let set = set_ty.as_attrset()?;
+        if attr.syntax() == attr_node.syntax() {
+            return match set.get_src(&field)? {
+                AttrSource::Name(name) => Some(InFile::new(file, name)),
+                AttrSource::Imported(name) => Some(name),
+                AttrSource::Unknown | AttrSource::Builtin => None,
+            };
+        }
+        set_ty = set.get(&field)?.clone();
+    }
+    None
+}
+
+/// `input.a.b` in `outputs` of `flake.nix`, where `input` is a parameter of a locked input.
+fn goto_flake_input_attr(
+    db: &dyn DefDatabase,
+    file: FileId,
+    tok: SyntaxToken,
+) -> Option<GotoDefinitionResult> {
+    let module_kind = db.module_kind(file);
+    let ModuleKind::FlakeNix { param_inputs, .. } = &*module_kind else {
+        return None;
+    };
+    let attr_node = attr_at(&tok)?;
+    let path_node = attr_node.syntax().parent().and_then(ast::Attrpath::cast)?;
+    let select_node = path_node.syntax().parent().and_then(ast::Select::cast)?;
+    let set_node = select_node.set()?.flatten_paren()?;
+    let set_expr = db
+        .source_map(file)
+        .expr_for_node(AstPtr::new(set_node.syntax()))?;
+    let &ResolveResult::Definition(name) = db.name_resolution(file).get(set_expr)? else {
+        return None;
+    };
+    let input = &db.module(file)[name].text;
+    if param_inputs.get(input) != Some(&name) {
+        return None;
+    }
+    let store_path = db
+        .source_root_flake_info(db.file_source_root(file))?
+        .input_store_paths
+        .get(&**input)?
+        .clone();
+
+    let mut attrpath = Vec::new();
+    for attr in path_node.attrs() {
+        let Some(key) = const_attr_name(db, file, attr.clone()) else {
+            return None;
+        };
+        attrpath.push(key);
+        if attr.syntax() == attr_node.syntax() {
+            break;
+        }
+    }
+    Some(GotoDefinitionResult::FlakeInputAttr {
+        store_path,
+        attrpath,
+    })
+}
+
+/// Parameters of packages go to explicit arguments of `callPackage` calls in the workspace,
+/// or otherwise the top-level attributes of nixpkgs which `callPackage` passes, if it is called
+/// by `callPackage` at all.
+fn goto_package_arg(
+    db: &dyn DefDatabase,
+    file: FileId,
+    tok: SyntaxToken,
+) -> Option<GotoDefinitionResult> {
+    let ModuleKind::Package { lambda_expr } = *db.module_kind(file) else {
+        return None;
+    };
+    let module = db.module(file);
+    let Expr::Lambda(_, Some(pat), _) = &module[lambda_expr] else {
+        return None;
+    };
+    let node = tok.parent().and_then(ast::Name::cast)?;
+    let name = db
+        .source_map(file)
+        .name_for_node(AstPtr::new(node.syntax()))?;
+    if !pat.fields.iter().any(|&(field, _)| field == Some(name)) {
+        return None;
+    }
+    let text = &module[name].text;
+
+    let mut targets = Vec::new();
+    for referrer in db.module_referrers(file) {
+        let referrer_module = db.module(referrer);
+        for site in db.call_package_sites(referrer).iter() {
+            if site.callee != Some(file) {
+                continue;
+            }
+            let (Expr::Attrset(args) | Expr::RecAttrset(args)) = &referrer_module[site.arg_expr]
+            else {
+                continue;
+            };
+            for &(arg, _) in args.statics.iter() {
+                if referrer_module[arg].text == *text {
+                    targets.extend(name_targets(db, InFile::new(referrer, arg)));
+                }
+            }
+        }
+    }
+    if !targets.is_empty() {
+        return Some(GotoDefinitionResult::Targets(targets));
+    }
+    // Packages guessed by their shape may be called in other ways, not with nixpkgs.
+    if !db.is_called_package(file) {
+        return None;
+    }
+    Some(GotoDefinitionResult::NixpkgsAttr {
+        name: text.to_string(),
+    })
+}
+
+/// `config.a.b` in NixOS modules goes to declarations of the option `a.b` by `mkOption` in
+/// modules of the same configurations, or in nixpkgs if it is not declared by the workspace.
+/// They are followed by definitions of the option, or definitions of options under it.
+fn goto_option_definition(
+    db: &impl TyDatabase,
+    file: FileId,
+    tok: SyntaxToken,
+) -> Option<GotoDefinitionResult> {
+    let attr_node = attr_at(&tok)?;
+    let path_node = attr_node.syntax().parent().and_then(ast::Attrpath::cast)?;
+    let select_node = path_node.syntax().parent().and_then(ast::Select::cast)?;
+    let module = db.module(file);
+    let config = config_param(db, &module, file)?;
+    let set_node = select_node.set()?.flatten_paren()?;
+    let set_expr = db
+        .source_map(file)
+        .expr_for_node(AstPtr::new(set_node.syntax()))?;
+    if db.name_resolution(file).get(set_expr) != Some(&ResolveResult::Definition(config)) {
+        return None;
+    }
+
+    let mut path = Vec::new();
+    for attr in path_node.attrs() {
+        let Some(key) = const_attr_name(db, file, attr.clone()) else {
+            return None;
+        };
+        path.push(SmolStr::from(key));
+        if attr.syntax() == attr_node.syntax() {
+            break;
+        }
+    }
+
+    let files = db.configuration_files(file);
+    let mut decls = Vec::new();
+    for &file in files.iter() {
+        for decl in db.option_declarations(file).iter() {
+            if *decl.path == *path {
+                decls.extend(name_targets(db, InFile::new(file, decl.name)));
+            }
+        }
+    }
+
+    let index = db.option_definition_index(files);
+    let mut defs = index.get(&path).iter().collect::<Vec<_>>();
+    if defs.is_empty() {
+        defs = index.under(&path).collect();
+    }
+    // `under` is unordered.
+    defs.sort_by_key(|def| (def.file_id, def.value.name.into_raw()));
+    let def_targets = defs
+        .into_iter()
+        .flat_map(|def| name_targets(db, InFile::new(def.file_id, def.value.name)));
+    if decls.is_empty() {
+        let set = db.module_option_set(file);
+        let declarations = nixos_option_declarations(&db.option_set(set), &path);
+        if !declarations.is_empty() {
+            return Some(GotoDefinitionResult::NixosOption {
+                set,
+                declarations,
+                targets: def_targets.collect(),
+            });
+        }
+    }
+    let mut targets = decls;
+    targets.extend(def_targets);
+    (!targets.is_empty()).then_some(GotoDefinitionResult::Targets(targets))
+}
+
+/// Declaring files of the option `path` in the option set `opts`.
+fn nixos_option_declarations(opts: &NixosOptions, path: &[SmolStr]) -> Vec<String> {
+    let Some((first, rest)) = path.split_first() else {
+        return Vec::new();
+    };
+    let Some(mut opt) = opts.get(&**first) else {
+        return Vec::new();
+    };
+    for field in rest {
+        let OptionTy::Attrset { fields, .. } = &opt.ty else {
+            return Vec::new();
+        };
+        let Some(child) = fields.get(&**field) else {
+            return Vec::new();
+        };
+        opt = child;
+    }
+    opt.declarations.clone()
+}
+
+/// The first attribute of `pkgs.name`, or `final.name` and `prev.name` in overlays, goes to the
+/// top-level attribute of nixpkgs. Ones added by the overlay are handled by types before.
+fn goto_package_set_attr(
+    db: &dyn TyDatabase,
+    file: FileId,
+    tok: SyntaxToken,
+) -> Option<GotoDefinitionResult> {
+    let attr_node = attr_at(&tok)?;
+    let path_node = attr_node.syntax().parent().and_then(ast::Attrpath::cast)?;
+    let select_node = path_node.syntax().parent().and_then(ast::Select::cast)?;
+    if path_node.attrs().next()?.syntax() != attr_node.syntax() {
+        return None;
+    }
+    let Some(name) = const_attr_name(db, file, attr_node) else {
+        return None;
+    };
+    let set_node = select_node.set()?.flatten_paren()?;
+    let set_expr = db
+        .source_map(file)
+        .expr_for_node(AstPtr::new(set_node.syntax()))?;
+    is_package_set(db, file, set_expr).then_some(GotoDefinitionResult::NixpkgsAttr { name })
+}
+
 fn goto_flake_input(
     db: &dyn DefDatabase,
     file: FileId,
@@ -107,12 +519,30 @@ fn goto_flake_input(
     let ModuleKind::FlakeNix {
         explicit_inputs,
         param_inputs,
+        follows,
         ..
     } = &*module_kind
     else {
         return None;
     };
     let flake_info = db.source_root_flake_info(db.file_source_root(file))?;
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let input_decl = |name: &str| {
+        let &name_id = explicit_inputs.get(name)?;
+        let targets = name_targets(db, InFile::new(file, name_id));
+        (!targets.is_empty()).then_some(GotoDefinitionResult::Targets(targets))
+    };
+
+    // `inputs.foo.follows = "bar";` goes to the declaration of `bar`.
+    let follows_input = tok
+        .parent_ancestors()
+        .find_map(ast::String::cast)
+        .and_then(|string| source_map.expr_for_node(AstPtr::new(string.syntax())))
+        .and_then(|expr| follows.iter().find(|(e, _)| *e == expr));
+    if let Some((_, input)) = follows_input {
+        return input_decl(input);
+    }
 
     let ptr = tok.parent_ancestors().find_map(|node| {
         match_ast! {
@@ -123,19 +553,22 @@ fn goto_flake_input(
         }
     })?;
 
-    let module = db.module(file);
-    let source_map = db.source_map(file);
     let name_id = source_map.name_for_node(ptr)?;
     let name_str = &*module[name_id].text;
 
-    if explicit_inputs.get(name_str) == Some(&name_id)
-        || param_inputs.get(name_str) == Some(&name_id)
-    {
+    let is_explicit = explicit_inputs.get(name_str) == Some(&name_id);
+    let is_param = param_inputs.get(name_str) == Some(&name_id);
+    if is_explicit || is_param {
         let target = flake_info
             .input_store_paths
-            .get(name_str)?
-            .join(FLAKE_FILE)?;
-        return Some(GotoDefinitionResult::Path(target));
+            .get(name_str)
+            .and_then(|path| path.join(FLAKE_FILE));
+        return match target {
+            Some(target) => Some(GotoDefinitionResult::Path(target)),
+            // The input is not locked yet. The parameter goes to the declaration instead.
+            None if is_param => input_decl(name_str),
+            None => None,
+        };
     }
 
     None
@@ -147,35 +580,60 @@ mod tests {
     use crate::base::SourceDatabase;
     use crate::tests::TestDB;
     use expect_test::{expect, Expect};
+    use nix_interop::nixos_options::NixosOption;
+    use nix_interop::search_path::SearchPath;
+    use std::sync::Arc;
 
     #[track_caller]
     fn check_no(fixture: &str) {
         let (db, f) = TestDB::from_fixture(fixture).unwrap();
         assert_eq!(f.markers().len(), 1, "Missing markers");
-        assert_eq!(goto_definition(&db, f[0]), None);
+        assert_eq!(goto_definition(&db, f[0], false), None);
     }
 
     #[track_caller]
     fn check(fixture: &str, expect: Expect) {
+        check_with(fixture, false, expect);
+    }
+
+    #[track_caller]
+    fn check_with(fixture: &str, inherit_hops: bool, expect: Expect) {
         let (db, f) = TestDB::from_fixture(fixture).unwrap();
         assert_eq!(f.markers().len(), 1, "Missing markers");
-        let mut got = match goto_definition(&db, f[0]).expect("No definition") {
+        let show_targets = |targets: Vec<NavigationTarget>| {
+            targets
+                .into_iter()
+                .map(|target| {
+                    assert!(target.full_range.contains_range(target.focus_range));
+                    let src = db.file_content(target.file_id);
+                    let mut full = src[target.full_range].to_owned();
+                    let relative_focus = target.focus_range - target.full_range.start();
+                    full.insert(relative_focus.end().into(), '>');
+                    full.insert(relative_focus.start().into(), '<');
+                    full
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut got = match goto_definition(&db, f[0], inherit_hops).expect("No definition") {
             GotoDefinitionResult::Path(path) => format!("file://{}", path.display()),
+            GotoDefinitionResult::FlakeInputAttr {
+                store_path,
+                attrpath,
+            } => format!("{}#{}", store_path.display(), attrpath.join(".")),
+            GotoDefinitionResult::NixpkgsAttr { name } => format!("<nixpkgs>#{name}"),
+            GotoDefinitionResult::NixosOption {
+                set: _,
+                declarations,
+                targets,
+            } => declarations
+                .iter()
+                .map(|decl| format!("<nixpkgs>/{decl}"))
+                .chain(show_targets(targets))
+                .collect::<Vec<_>>()
+                .join("\n"),
             GotoDefinitionResult::Targets(targets) => {
                 assert!(!targets.is_empty());
-                targets
-                    .into_iter()
-                    .map(|target| {
-                        assert!(target.full_range.contains_range(target.focus_range));
-                        let src = db.file_content(target.file_id);
-                        let mut full = src[target.full_range].to_owned();
-                        let relative_focus = target.focus_range - target.full_range.start();
-                        full.insert(relative_focus.end().into(), '>');
-                        full.insert(relative_focus.start().into(), '<');
-                        full
-                    })
-                    .collect::<Vec<_>>()
-                    .join("\n")
+                show_targets(targets).join("\n")
             }
         };
         // Prettify.
@@ -234,6 +692,58 @@ mod tests {
         );
     }
 
+    #[test]
+    fn inherit_from() {
+        let fixture = "
+#- /default.nix
+let
+    lib = import ./lib.nix;
+    inherit (lib) mkIf;
+in
+{ inherit mkIf; x = $0mkIf; }
+#- /lib.nix
+let
+    modules = { mkIf = cond: x: x; };
+in
+{ inherit (modules) mkIf; }
+        ";
+        check(fixture, expect!["<mkIf> = cond: x: x;"]);
+        check_with(
+            fixture,
+            true,
+            expect![[r#"
+                inherit (lib) <mkIf>;
+                inherit (modules) <mkIf>;
+                <mkIf> = cond: x: x;
+            "#]],
+        );
+        check(
+            &fixture
+                .replace("(lib) mkIf", "(lib) $0mkIf")
+                .replace("x = $0", "x = "),
+            expect!["<mkIf> = cond: x: x;"],
+        );
+
+        // Through arguments passed to the parameter.
+        check(
+            "
+#- /pkg.nix
+{ lib }: let inherit (lib) mkIf; in $0mkIf
+#- /default.nix
+{ callPackage }: callPackage ./pkg.nix { lib = import ./lib.nix; }
+#- /lib.nix
+{ mkIf = cond: x: x; }
+            ",
+            expect!["<mkIf> = cond: x: x;"],
+        );
+
+        // Sets without known types or arguments stop at the `inherit`.
+        check(
+            "{ lib, ... }: let inherit (lib) mkIf; in $0mkIf",
+            expect!["inherit (lib) <mkIf>;"],
+        );
+    }
+
     #[test]
     fn left_and_right() {
         check("let a = 1; in $0a ", expect!["<a> = 1;"]);
@@ -282,6 +792,205 @@ hello
         );
     }
 
+    #[test]
+    fn search_path() {
+        let (mut db, f) = TestDB::from_fixture(
+            "
+#- /default.nix
+import <nixpkgs/nix$0os>
+#- /nixpkgs/nixos/default.nix
+{ }
+#- /channels/nixpkgs/nixos/default.nix
+{ }
+            ",
+        )
+        .unwrap();
+        let resolve = |db: &TestDB| match goto_definition(db, f[0], false) {
+            Some(GotoDefinitionResult::Path(path)) => Some(path.display().to_string()),
+            _ => None,
+        };
+        assert_eq!(resolve(&db), None);
+        db.set_search_path(Arc::new(SearchPath::parse("/channels:nixpkgs=/nixpkgs")));
+        assert_eq!(resolve(&db).as_deref(), Some("/channels/nixpkgs/nixos"));
+        db.set_search_path(Arc::new(SearchPath::parse("nixpkgs=/nixpkgs:/channels")));
+        assert_eq!(resolve(&db).as_deref(), Some("/nixpkgs/nixos"));
+        // Entries where the path does not exist are skipped.
+        db.set_search_path(Arc::new(SearchPath::parse("/missing:nixpkgs=/nixpkgs")));
+        assert_eq!(resolve(&db).as_deref(), Some("/nixpkgs/nixos"));
+        db.set_search_path(Arc::new(SearchPath::parse("/missing")));
+        assert_eq!(resolve(&db), None);
+        // Or recorded as existing by the server.
+        let mut search_path = SearchPath::parse("nixpkgs=/store/nixpkgs");
+        search_path.insert_existing("/store/nixpkgs");
+        db.set_search_path(Arc::new(search_path));
+        assert_eq!(resolve(&db).as_deref(), Some("/store/nixpkgs/nixos"));
+    }
+
+    #[test]
+    fn package_arg() {
+        check(
+            "
+#- /default.nix
+{ callPackage }: callPackage ./pkg.nix { }
+#- /pkg.nix
+{ stdenv, fe$0tchurl }: stdenv.mkDerivation { }
+            ",
+            expect!["<nixpkgs>#fetchurl"],
+        );
+        // Not known to be called by `callPackage`.
+        check_no("{ stdenv, fe$0tchurl }: stdenv.mkDerivation { }");
+        check(
+            "
+#- /default.nix
+{ callPackage }: {
+  a = callPackage ./pkg.nix { };
+  b = callPackage ./pkg.nix { fetchurl = 1; };
+}
+#- /pkg.nix
+{ fe$0tchurl }: { }
+            ",
+            expect!["<fetchurl> = 1;"],
+        );
+        check_no("{ foo$0 }: { }");
+    }
+
+    #[test]
+    fn package_set_attr() {
+        check("{ pkgs }: pkgs.$0hello", expect!["<nixpkgs>#hello"]);
+        check(
+            "final: prev: { hello = prev.$0hello.override { }; }",
+            expect!["<nixpkgs>#hello"],
+        );
+        // Attributes added by the overlay itself.
+        check(
+            "final: prev: { foo = 1; bar = final.$0foo; }",
+            expect!["<foo> = 1;"],
+        );
+        check(
+            "final: prev: { foo = 1; bar = final.$0hello; }",
+            expect!["<nixpkgs>#hello"],
+        );
+        check_no("{ pkgs }: pkgs.hello.$0out");
+        check_no("a: b: { c = b.$0hello; }");
+    }
+
+    #[test]
+    fn option_definition() {
+        // Definitions in all configurations importing the module.
+        check(
+            "
+#- /host-a.nix
+{ config, ... }: { imports = [ ./common.nix ]; foo = 1; }
+#- /host-b.nix
+{ config, ... }: { imports = [ ./common.nix ]; foo = 2; }
+#- /common.nix
+{ config, ... }: { bar = config.$0foo; }
+#- /loose.nix
+{ config, ... }: { foo = 3; }
+            ",
+            expect![[r#"
+                <foo> = 1;
+                <foo> = 2;
+            "#]],
+        );
+        // Definitions under the option.
+        check(
+            "
+#- /default.nix
+{ config, ... }: { imports = [ ./foo.nix ]; bar = config.$0foo; }
+#- /foo.nix
+{ config, ... }: { foo.enable = true; }
+            ",
+            expect!["foo.<enable> = true;"],
+        );
+        check_no("{ config, ... }: { bar = config.$0foo; }");
+    }
+
+    #[test]
+    fn option_declaration() {
+        // Declarations in the same configurations go before definitions.
+        check(
+            "
+#- /default.nix
+{ config, ... }: { imports = [ ./foo.nix ]; foo.enable = config.foo.$0enable; }
+#- /foo.nix
+{ lib, ... }: { options.foo.enable = lib.mkEnableOption \"foo\"; }
+#- /other.nix
+{ lib, ... }: { options.foo.enable = lib.mkEnableOption \"foo\"; }
+            ",
+            expect![[r#"
+                options.foo.<enable> = lib.mkEnableOption "foo";
+                foo.<enable> = config.foo.enable;
+            "#]],
+        );
+
+        // Declarations of nixpkgs.
+        let (mut db, f) = TestDB::from_fixture(
+            "{ config, ... }: { foo.enable = true; bar = config.foo.$0enable; }",
+        )
+        .unwrap();
+        let opt = |ty, declarations| NixosOption {
+            ty,
+            declarations,
+            ..NixosOption::default()
+        };
+        let enable = opt(OptionTy::Bool, vec!["nixos/modules/foo.nix".into()]);
+        let foo = OptionTy::Attrset {
+            fields: NixosOptions::from_iter([("enable".into(), enable)]),
+            rest: None,
+        };
+        db.set_option_set(
+            OptionSet::Nixos,
+            Arc::new(NixosOptions::from_iter([(
+                "foo".into(),
+                opt(foo, Vec::new()),
+            )])),
+        );
+        let Some(GotoDefinitionResult::NixosOption {
+            set,
+            declarations,
+            targets,
+        }) = goto_definition(&db, f[0], false)
+        else {
+            panic!("Expecting declarations of nixpkgs");
+        };
+        assert_eq!(set, OptionSet::Nixos);
+        assert_eq!(declarations, ["nixos/modules/foo.nix"]);
+        assert_eq!(targets.len(), 1);
+    }
+
+    #[test]
+    fn select_attr() {
+        check("let a = { b.c = 1; }; in a.b.$0c", expect!["b.<c> = 1;"]);
+
+        // Constant dynamic attributes are folded.
+        check(
+            r#"let name = "b"; a = { b.c = 1; }; in a.${name}.$0c"#,
+            expect!["b.<c> = 1;"],
+        );
+        check(
+            r#"let name = "b"; a = { b.c = 1; }; in a.$0${name}.c"#,
+            expect!["<b>.c = 1;"],
+        );
+        // References inside go to their own definitions.
+        check(
+            r#"let name = "b"; a = { b.c = 1; }; in a.${$0name}.c"#,
+            expect![[r#"<name> = "b";"#]],
+        );
+        check_no(r#"let name = x; a = { b.c = 1; }; in a.${name}.$0c"#);
+        check(
+            "
+#- /default.nix
+let lib = import ./lib.nix; in lib.$0foo
+
+#- /lib.nix
+{ foo = 1; }
+            ",
+            expect!["<foo> = 1;"],
+        );
+        check_no("let a = { }; in a.$0b");
+    }
+
     #[test]
     fn flake_input() {
         check(
@@ -360,4 +1069,72 @@ hello
             "#,
         );
     }
+
+    #[test]
+    fn flake_input_attr() {
+        check(
+            r#"
+#- /flake.nix input:nixpkgs=/nix/store/eeee
+{
+    outputs = { nixpkgs, ... }: nixpkgs.lib.$0strings.hasPrefix;
+}
+            "#,
+            expect!["/nix/store/eeee#lib.strings"],
+        );
+        check(
+            r#"
+#- /flake.nix input:nixpkgs=/nix/store/eeee
+{
+    outputs = { nixpkgs, ... }: (nixpkgs).lib.strings.$0hasPrefix;
+}
+            "#,
+            expect!["/nix/store/eeee#lib.strings.hasPrefix"],
+        );
+
+        // Not locked.
+        check_no(
+            r#"
+#- /flake.nix
+{
+    outputs = { nixpkgs, ... }: nixpkgs.$0lib;
+}
+            "#,
+        );
+        // Dynamic.
+        check_no(
+            r#"
+#- /flake.nix input:nixpkgs=/nix/store/eeee
+{
+    outputs = { nixpkgs, ... }: nixpkgs.${nixpkgs}.$0foo;
+}
+            "#,
+        );
+    }
+
+    #[test]
+    fn flake_input_decl() {
+        // Not locked.
+        check(
+            r#"
+#- /flake.nix
+{
+    inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+    outputs = { $0nixpkgs, ... }: { };
+}
+            "#,
+            expect![[r#"inputs.<nixpkgs>.url = "github:NixOS/nixpkgs";"#]],
+        );
+
+        check(
+            r#"
+#- /flake.nix input:nixpkgs=/nix/store/eeee
+{
+    inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+    inputs.nix.inputs.nixpkgs.follows = "nix$0pkgs";
+    outputs = { ... }: { };
+}
+            "#,
+            expect![[r#"inputs.<nixpkgs>.url = "github:NixOS/nixpkgs";"#]],
+        );
+    }
 }
@@ -1,12 +1,21 @@
-use crate::def::{AstPtr, Expr, ResolveResult};
+use super::completion::{is_package_set, LIB_NAME};
+use super::goto_definition::{name_targets, select_attr_source};
+use super::rename::display_pos;
+use super::NavigationTarget;
+use crate::def::{
+    const_attr_name, const_eval_name, const_eval_string, AstPtr, Expr, NameId, ResolveResult,
+};
 use crate::ty::{DisplayConfig, Ty};
-use crate::{FilePos, NameKind, TyDatabase};
+use crate::{DefDatabase, FileId, FilePos, InFile, ModuleKind, NameKind, TyDatabase};
 use builtin::ALL_BUILTINS;
 use if_chain::if_chain;
+use nix_interop::flake_lock::InputSource;
+use nix_interop::store_path::store_path_root;
 use std::fmt::Write;
-use syntax::ast::{self, AstNode};
+use std::time::{SystemTime, UNIX_EPOCH};
+use syntax::ast::{self, AstNode, LiteralKind};
 use syntax::semantic::AttrKind;
-use syntax::{best_token_at_offset, match_ast, TextRange};
+use syntax::{best_token_at_offset, match_ast, SyntaxToken, TextRange};
 
 // Kinda detailed, but don't flood users with thousands of fields for `pkgs`.
 pub const TY_DETAILED_DISPLAY: DisplayConfig = DisplayConfig {
@@ -17,15 +26,43 @@ pub const TY_DETAILED_DISPLAY: DisplayConfig = DisplayConfig {
     lambda_need_parentheses: false,
 };
 
+/// Limits of the summarized attrset shape, which lists more field names than the type.
+const SHAPE_MAX_DEPTH: usize = 2;
+const SHAPE_MAX_FIELDS: usize = 16;
+
+/// Flake inputs not updated for this many days are marked as stale.
+const STALE_INPUT_DAYS: u64 = 90;
+
+/// Lines of the defining binding shown in the snippet.
+const SNIPPET_MAX_LINES: usize = 8;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HoverResult {
     pub range: TextRange,
     pub markup: String,
+    /// Where the hovered name is defined, if it is not the hovered name itself.
+    pub definition: Option<HoverDefinition>,
+    /// The store path referenced by the hovered literal, whose existence and metadata can only
+    /// be known by querying the local store.
+    pub store_path: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HoverDefinition {
+    pub target: NavigationTarget,
+    /// The leading lines of the defining binding, dedented.
+    pub snippet: String,
 }
 
-pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> Option<HoverResult> {
+pub(crate) fn hover(
+    db: &impl TyDatabase,
+    FilePos { file_id, pos }: FilePos,
+) -> Option<HoverResult> {
     let parse = db.parse(file_id);
     let tok = best_token_at_offset(&parse.syntax_node(), pos)?;
+    if let Some(ret) = hover_store_path(db, file_id, &tok) {
+        return Some(ret);
+    }
     let mut name_node = None;
     let ptr = tok.parent_ancestors().find_map(|node| {
         match_ast! {
@@ -49,7 +86,8 @@ pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> O
     let nameres = db.name_resolution(file_id);
     let infer = db.infer(file_id);
 
-    let mut name = None;
+    // The definition of the referenced name.
+    let mut resolved = None;
 
     if let Some(expr) = source_map.expr_for_node(ptr.clone()) {
         if let Some(builtin) = nameres.check_builtin(expr, &module) {
@@ -77,19 +115,21 @@ pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> O
                         .map_or("?", |env_node| &src[env_node.syntax().text_range()]);
                     write!(markup, "\n{i}. `with {env_text};`").unwrap();
                 }
-                return Some(HoverResult { range, markup });
+                return Some(HoverResult {
+                    range,
+                    markup,
+                    definition: None,
+                    store_path: None,
+                });
             }
             Some(ResolveResult::Definition(def)) => {
-                name = Some(*def);
+                resolved = Some(*def);
             }
         }
     }
 
-    if let Some(name) = name.or_else(|| source_map.name_for_node(ptr.clone())) {
-        let ty = infer
-            .ty_for_name(name)
-            .display_with(TY_DETAILED_DISPLAY)
-            .to_string();
+    if let Some(name) = resolved.or_else(|| source_map.name_for_node(ptr.clone())) {
+        let ty = infer.ty_for_name(name);
         let text = &module[name].text;
         let kind = match module[name].kind {
             NameKind::LetIn => "Let binding",
@@ -98,9 +138,27 @@ pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> O
             NameKind::Param => "Parameter",
             NameKind::PatField => "Field parameter",
         };
+        let mut markup = format!(
+            "{kind} `{text}`\n`{}`",
+            ty.display_with(TY_DETAILED_DISPLAY)
+        );
+        push_shape(&mut markup, &ty);
+        push_value(&mut markup, db, InFile::new(file_id, name));
+        if let Some(info) = flake_input_info(db, file_id, name) {
+            markup += "\n\n";
+            markup += &info;
+        }
+        if let Some(info) = option_definition_info(db, file_id, name) {
+            markup += "\n\n";
+            markup += &info;
+        }
+        // Only references, since the definition of a hovered definition is itself.
+        let definition = resolved.and_then(|name| hover_definition(db, InFile::new(file_id, name)));
         return Some(HoverResult {
             range,
-            markup: format!("{kind} `{text}`\n`{ty}`"),
+            markup,
+            definition,
+            store_path: None,
         });
     }
 
@@ -115,7 +173,8 @@ pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> O
                 ast::Select(n) => n.set(),
                 _ => None,
             }
-        }?;
+        }?
+        .flatten_paren()?;
         let expr = source_map.expr_for_node(AstPtr::new(set_node.syntax()))?;
 
         // Special case for `builtins.xxx`
@@ -137,9 +196,70 @@ pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> O
             }
         }
 
+        // Special case for documented `lib.xxx` and `lib.module.xxx`.
+        if matches!(&module[expr], Expr::Reference(name) if name == LIB_NAME) {
+            let path = path_node
+                .attrs()
+                .take_while(|attr| attr.syntax().text_range().start() <= pos)
+                .map(|attr| match AttrKind::of(attr) {
+                    AttrKind::Static(Some(field)) => Some(field),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>();
+            let lib_docs = db.lib_docs();
+            let doc = match path.as_deref() {
+                Some([name]) => lib_docs.get(None, name),
+                Some([module, name]) => lib_docs.get(Some(module), name),
+                _ => None,
+            };
+            if let Some(doc) = doc {
+                let range = set_node
+                    .syntax()
+                    .text_range()
+                    .cover(name_node.syntax().text_range());
+                let mut markup = format!("`{}`", &src[range]);
+                if let Some(sig) = &doc.signature {
+                    write!(markup, "\n`{sig}`").unwrap();
+                }
+                write!(markup, "\n\n{}", doc.doc).unwrap();
+                return Some(HoverResult {
+                    range,
+                    markup,
+                    definition: None,
+                    store_path: None,
+                });
+            }
+        }
+
+        // Special case for packages from the nixpkgs index via `pkgs.xxx`, or `final.xxx` and
+        // `prev.xxx` in overlays, unless the attribute is defined in the workspace.
+        if_chain! {
+            if path_node.attrs().next().map_or(false, |attr| attr.syntax() == name_node.syntax());
+            if is_package_set(db, file_id, expr);
+            if select_attr_source(db, file_id, tok.clone()).is_none();
+            if let Some(name) = name_node.token();
+            let index = db.package_index();
+            if let Some(info) = index.get(name.text());
+            then {
+                let mut markup = format!("Package `{}`", name.text());
+                if let Some(version) = &info.version {
+                    write!(markup, "\nVersion `{version}`").unwrap();
+                }
+                if let Some(desc) = &info.description {
+                    write!(markup, "\n\n{desc}").unwrap();
+                }
+                return Some(HoverResult {
+                    range: name_node.syntax().text_range(),
+                    markup,
+                    definition: None,
+                    store_path: None,
+                });
+            }
+        }
+
         let mut ty = infer.ty_for_expr(expr);
         for attr in path_node.attrs() {
-            let AttrKind::Static(Some(field)) = AttrKind::of(attr.clone()) else {
+            let Some(field) = const_attr_name(db, file_id, attr.clone()) else {
                 return None;
             };
             ty = ty.as_attrset()?.get(&field)?.clone();
@@ -148,14 +268,22 @@ pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> O
             }
         }
         let range = name_node.syntax().text_range();
-        let markup = format!(
+        let mut markup = format!(
             "Field `{}`\n`{}`",
             name_node
                 .token()
                 .map_or_else(String::new, |t| t.text().into()),
             ty.display_with(TY_DETAILED_DISPLAY),
         );
-        Some(HoverResult { range, markup })
+        push_shape(&mut markup, &ty);
+        let definition = select_attr_source(db, file_id, tok.clone())
+            .and_then(|name| hover_definition(db, name));
+        Some(HoverResult {
+            range,
+            markup,
+            definition,
+            store_path: None,
+        })
     }) {
         return Some(ret);
     }
@@ -163,6 +291,201 @@ pub(crate) fn hover(db: &dyn TyDatabase, FilePos { file_id, pos }: FilePos) -> O
     None
 }
 
+/// A string or path literal referencing a store path, like `"/nix/store/<hash>-foo/bin/foo"`.
+fn hover_store_path(
+    db: &impl TyDatabase,
+    file_id: FileId,
+    tok: &SyntaxToken,
+) -> Option<HoverResult> {
+    let node = tok.parent()?;
+    let (range, path) = match_ast! {
+        match node {
+            ast::String(n) => {
+                let source_map = db.source_map(file_id);
+                let expr = source_map.expr_for_node(AstPtr::new(n.syntax()))?;
+                (n.syntax().text_range(), const_eval_string(db, InFile::new(file_id, expr))?)
+            },
+            ast::Literal(n) => {
+                if n.kind() != Some(LiteralKind::Path) {
+                    return None;
+                }
+                (n.syntax().text_range(), n.token()?.text().to_owned())
+            },
+            _ => return None,
+        }
+    };
+    let store_path = store_path_root(&path)?;
+    Some(HoverResult {
+        range,
+        markup: format!("Store path `{store_path}`"),
+        definition: None,
+        store_path: Some(store_path.to_owned()),
+    })
+}
+
+/// Append the summarized shape of a non-empty attrset.
+fn push_shape(markup: &mut String, ty: &Ty) {
+    if ty.as_attrset().map_or(false, |set| !set.is_empty()) {
+        let shape = ty.display_shape(SHAPE_MAX_DEPTH, SHAPE_MAX_FIELDS);
+        write!(markup, "\n\nFields: `{shape}`").unwrap();
+    }
+}
+
+/// Append the computed value of `name`, unless it is defined by the same literal.
+fn push_value(markup: &mut String, db: &dyn DefDatabase, name: InFile<NameId>) {
+    let Some(value) = const_eval_name(db, name) else {
+        return;
+    };
+    let value = value.to_string();
+    let parse = db.parse(name.file_id);
+    let is_literal = db
+        .source_map(name.file_id)
+        .nodes_for_name(name.value)
+        .filter_map(|ptr| ptr.to_node(&parse.syntax_node()).ancestors().nth(2))
+        .filter_map(ast::AttrpathValue::cast)
+        .filter_map(|binding| binding.value())
+        .any(|expr| expr.syntax().to_string() == value);
+    if !is_literal {
+        if value.contains('`') {
+            write!(markup, "\n\nValue: `` {value} ``").unwrap();
+        } else {
+            write!(markup, "\n\nValue: `{value}`").unwrap();
+        }
+    }
+}
+
+/// The first definition site of `name` with the snippet of its binding.
+fn hover_definition(db: &dyn DefDatabase, name: InFile<NameId>) -> Option<HoverDefinition> {
+    let target = name_targets(db, name).into_iter().next()?;
+    let src = db.file_content(target.file_id);
+    let start = usize::from(target.full_range.start());
+    // Continuation lines are indented relatively to the line of the binding.
+    let line_start = src[..start].rfind('\n').map_or(0, |i| i + 1);
+    let indent = src[line_start..start].len() - src[line_start..start].trim_start().len();
+    let mut lines = src[target.full_range].lines();
+    let mut snippet = lines.next().unwrap_or_default().to_owned();
+    for (i, line) in lines.enumerate() {
+        if i + 1 == SNIPPET_MAX_LINES {
+            snippet += "\n…";
+            break;
+        }
+        let dedented = line
+            .get(..indent)
+            .filter(|prefix| prefix.trim().is_empty())
+            .map_or(line, |_| &line[indent..]);
+        snippet += "\n";
+        snippet += dedented;
+    }
+    Some(HoverDefinition { target, snippet })
+}
+
+/// Describe the locked source of the flake input declared or received by `name`.
+fn flake_input_info(db: &dyn TyDatabase, file: FileId, name: NameId) -> Option<String> {
+    let module_kind = db.module_kind(file);
+    let ModuleKind::FlakeNix {
+        explicit_inputs,
+        param_inputs,
+        ..
+    } = &*module_kind
+    else {
+        return None;
+    };
+    let text = &db.module(file)[name].text;
+    if explicit_inputs.get(text) != Some(&name) && param_inputs.get(text) != Some(&name) {
+        return None;
+    }
+    let flake_info = db.source_root_flake_info(db.file_source_root(file))?;
+    let source = flake_info.input_sources.get(&**text)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    Some(describe_input_source(source, now))
+}
+
+fn describe_input_source(source: &InputSource, now: u64) -> String {
+    let mut ret = match &source.url {
+        Some(url) => format!("Locked `{url}`"),
+        None => "Locked from an unknown source".into(),
+    };
+    if let Some(ref_) = &source.ref_ {
+        write!(ret, " at `{ref_}`").unwrap();
+    }
+    if let Some(rev) = &source.rev {
+        write!(ret, "\nRevision `{rev}`").unwrap();
+    }
+    if let Some(last_modified) = source.last_modified {
+        let days = now.saturating_sub(last_modified) / (24 * 60 * 60);
+        write!(
+            ret,
+            "\nLast modified {}, {days} day(s) ago",
+            format_date(last_modified),
+        )
+        .unwrap();
+        if days >= STALE_INPUT_DAYS {
+            ret += " (stale, consider `nix flake update`)";
+        }
+    }
+    ret
+}
+
+/// Format a Unix timestamp as `YYYY-MM-DD` in UTC.
+fn format_date(secs: u64) -> String {
+    // See: https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (secs / (24 * 60 * 60)) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Describe the priority of the option definition by `name`, and which definition wins.
+fn option_definition_info(db: &dyn TyDatabase, file: FileId, name: NameId) -> Option<String> {
+    let defs = db.option_definitions(file);
+    let def = defs.for_name(name)?;
+    let mut ret = format!("Option definition with {}", def.priority);
+    let index = db.option_definition_index(db.configuration_files(file));
+    let others = index.get(&def.path).len() - 1;
+    if others == 0 || def.priority.value.is_none() {
+        return Some(ret);
+    }
+    // Nothing is known if any priority is not statically known.
+    let winners = index.winners(&def.path);
+    let Some(first) = winners.first() else {
+        return Some(ret);
+    };
+    let is_winner = winners
+        .iter()
+        .any(|w| w.file_id == file && w.value.name == name);
+    if !is_winner {
+        let ptr = db
+            .source_map(first.file_id)
+            .nodes_for_name(first.value.name)
+            .next()?;
+        let pos = display_pos(db, first.file_id, ptr.text_range().start());
+        write!(
+            ret,
+            "\nOverridden by the definition at `{pos}` with {}",
+            first.value.priority,
+        )
+        .unwrap();
+    } else if winners.len() > 1 {
+        write!(
+            ret,
+            "\nMerged with {} other definition(s) of the same priority",
+            winners.len() - 1,
+        )
+        .unwrap();
+    } else {
+        write!(ret, "\nWins over {others} other definition(s)").unwrap();
+    }
+    Some(ret)
+}
+
 fn hover_builtin(name: &str, range: TextRange) -> Option<HoverResult> {
     let b = ALL_BUILTINS.get(name)?;
     let ty = crate::ty::known::BUILTINS
@@ -175,16 +498,26 @@ fn hover_builtin(name: &str, range: TextRange) -> Option<HoverResult> {
         "`builtins.{name}`\n`{}`\n\n{}\n{}",
         ty.display_with(TY_DETAILED_DISPLAY),
         b.summary,
-        b.doc.unwrap_or("(No documentation from Nix)"),
+        b.doc().unwrap_or("(No documentation from Nix)"),
     );
-    Some(HoverResult { range, markup })
+    Some(HoverResult {
+        range,
+        markup,
+        definition: None,
+        store_path: None,
+    })
 }
 
 #[cfg(test)]
 mod tests {
+    use super::display_pos;
     use crate::base::SourceDatabase;
     use crate::tests::TestDB;
     use expect_test::{expect, Expect};
+    use nix_interop::flake_lock::InputSource;
+    use nix_interop::lib_docs::LibDocs;
+    use nix_interop::package_index::{PackageIndex, PackageInfo};
+    use std::sync::Arc;
 
     #[track_caller]
     fn check(fixture: &str, full: &str, expect: Expect) {
@@ -200,6 +533,23 @@ mod tests {
         expect.assert_eq(&got);
     }
 
+    #[track_caller]
+    fn check_definition(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        assert_eq!(f.markers().len(), 1);
+        let got = super::hover(&db, f[0])
+            .expect("No hover")
+            .definition
+            .map_or_else(
+                || "None".into(),
+                |def| {
+                    let pos = display_pos(&db, def.target.file_id, def.target.focus_range.start());
+                    format!("{pos}\n{}\n", def.snippet)
+                },
+            );
+        expect.assert_eq(&got);
+    }
+
     #[track_caller]
     fn check_no(fixture: &str) {
         let (db, f) = TestDB::from_fixture(fixture).unwrap();
@@ -303,6 +653,212 @@ mod tests {
         );
     }
 
+    #[test]
+    fn definition_site() {
+        check_definition("let $0a = 1; in a", expect!["None"]);
+        check_definition(
+            "
+let
+  a = {
+    b = 1;
+  };
+in $0a
+            ",
+            expect![[r#"
+                /default.nix:2
+                a = {
+                  b = 1;
+                };
+            "#]],
+        );
+        check_definition(
+            "{ a = 1; b = a; }.$0b",
+            expect![[r#"
+            /default.nix:1
+            b = a;
+        "#]],
+        );
+        check_definition(
+            "
+#- /default.nix
+(import ./modules/foo.nix).$0enable
+#- /modules/foo.nix
+{
+  foo = 1;
+  enable = x:
+    let
+      a = 1;
+      b = 2;
+      c = 3;
+      d = 4;
+      e = 5;
+      f = 6;
+    in x;
+}
+            ",
+            expect![[r#"
+                /modules/foo.nix:3
+                enable = x:
+                  let
+                    a = 1;
+                    b = 2;
+                    c = 3;
+                    d = 4;
+                    e = 5;
+                    f = 6;
+                …
+            "#]],
+        );
+    }
+
+    #[test]
+    fn const_value() {
+        check(
+            r#"let version = "1.2.3"; name = "prefix-${version}"; in $0name"#,
+            "name",
+            expect![[r#"
+                Let binding `name`
+                `string`
+
+                Value: `"prefix-1.2.3"`
+            "#]],
+        );
+        check(
+            "let $0a = builtins.length [ 1 2 ]; in a",
+            "a",
+            expect![[r#"
+                Let binding `a`
+                `int`
+
+                Value: `2`
+            "#]],
+        );
+        check(
+            "let $0a = 1; in a",
+            "a",
+            expect![[r#"
+                Let binding `a`
+                `int`
+            "#]],
+        );
+    }
+
+    #[test]
+    fn flake_input() {
+        let fixture = r#"
+#- /flake.nix input:nixpkgs=/nix/store/eeee
+{
+  inputs.$0nixpkgs.url = "github:NixOS/nixpkgs/nixos-unstable";
+  outputs = { self, nixpkgs }: { };
+}
+#- /flake.lock
+{
+  "nodes": {
+    "nixpkgs": {
+      "locked": {
+        "lastModified": 1700000000,
+        "narHash": "sha256-AAAA",
+        "owner": "NixOS",
+        "repo": "nixpkgs",
+        "rev": "0123456789abcdef0123456789abcdef01234567",
+        "type": "github"
+      },
+      "original": {
+        "owner": "NixOS",
+        "ref": "nixos-unstable",
+        "repo": "nixpkgs",
+        "type": "github"
+      }
+    },
+    "root": { "inputs": { "nixpkgs": "nixpkgs" } }
+  },
+  "root": "root",
+  "version": 7
+}
+        "#;
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let markup = super::hover(&db, f[0]).expect("No hover").markup;
+        assert!(
+            markup.contains("Locked `github:NixOS/nixpkgs` at `nixos-unstable`"),
+            "{markup}",
+        );
+        assert!(markup.contains("Last modified 2023-11-14"), "{markup}");
+
+        // The parameter of `outputs`.
+        let fixture = fixture
+            .replace("$0", "")
+            .replace("{ self, nixpkgs }", "{ self, $0nixpkgs }");
+        let (db, f) = TestDB::from_fixture(&fixture).unwrap();
+        let markup = super::hover(&db, f[0]).expect("No hover").markup;
+        assert!(markup.contains("Revision `0123456789abcdef"), "{markup}");
+    }
+
+    #[test]
+    fn input_source() {
+        let day = 24 * 60 * 60;
+        let source = InputSource {
+            url: Some("github:NixOS/nixpkgs".into()),
+            rev: Some("0123456789abcdef0123456789abcdef01234567".into()),
+            ref_: Some("nixos-unstable".into()),
+            last_modified: Some(1_700_000_000),
+        };
+        let got = super::describe_input_source(&source, 1_700_000_000 + 3 * day);
+        expect![[r#"
+            Locked `github:NixOS/nixpkgs` at `nixos-unstable`
+            Revision `0123456789abcdef0123456789abcdef01234567`
+            Last modified 2023-11-14, 3 day(s) ago"#]]
+        .assert_eq(&got);
+        let source = InputSource {
+            url: None,
+            rev: None,
+            ref_: None,
+            last_modified: Some(0),
+        };
+        let got = super::describe_input_source(&source, 100 * day);
+        expect![[r#"
+            Locked from an unknown source
+            Last modified 1970-01-01, 100 day(s) ago (stale, consider `nix flake update`)"#]]
+        .assert_eq(&got);
+        assert_eq!(super::format_date(951_782_400), "2000-02-29");
+    }
+
+    #[test]
+    fn attrset_shape() {
+        check(
+            "let port = 1; a = { enable = true; inherit port; settings.x.y = 1; } // { extra = { }; }; in $0a",
+            "a",
+            expect![[r#"
+                Let binding `a`
+                `{ enable: bool, extra: { }, port: int, settings: { x: {…} } }`
+
+                Fields: `{ enable, extra = { }, port, settings = { x = { … } } }`
+            "#]],
+        );
+        check(
+            "
+#- /default.nix
+let lib = import ./lib.nix; in lib.$0strings
+#- /lib.nix
+{ strings = { a = 1; b = 2; c = 3; d = 4; e = 5; }; }
+            ",
+            "strings",
+            expect![[r#"
+                Field `strings`
+                `{ a: int, b: int, c: int, d: int, … }`
+
+                Fields: `{ a, b, c, d, e }`
+            "#]],
+        );
+        check(
+            "let a = { }; in $0a",
+            "a",
+            expect![[r#"
+                Let binding `a`
+                `{ }`
+            "#]],
+        );
+    }
+
     #[test]
     fn with() {
         check(
@@ -496,5 +1052,181 @@ mod tests {
                 `int`
             "#]],
         );
+        // Constant dynamic attributes are folded.
+        check(
+            r#"let name = "foo"; set.foo.bar = 1; in set.${name}.$0bar"#,
+            "bar",
+            expect![[r#"
+                Field `bar`
+                `int`
+            "#]],
+        );
+    }
+
+    #[test]
+    fn option_definition_priority() {
+        check(
+            "
+#- /a.nix
+{ lib, ... }: { foo.$0bar = lib.mkForce 1; }
+#- /b.nix
+{ ... }: { foo.bar = 2; }
+            ",
+            "bar",
+            expect![[r#"
+                Attrset attribute `bar`
+                `?`
+
+                Option definition with priority 50 (`mkForce`)
+                Wins over 1 other definition(s)
+            "#]],
+        );
+        check(
+            "
+#- /a.nix
+{ lib, ... }: { foo.bar = lib.mkForce 1; }
+#- /b.nix
+{ ... }: { foo.$0bar = 2; }
+            ",
+            "bar",
+            expect![[r#"
+                Attrset attribute `bar`
+                `int`
+
+                Option definition with priority 100
+                Overridden by the definition at `/a.nix:1` with priority 50 (`mkForce`)
+            "#]],
+        );
+        check(
+            "
+#- /a.nix
+{ ... }: { foo.$0bar = [ 1 ]; }
+#- /b.nix
+{ ... }: { foo.bar = [ 2 ]; }
+            ",
+            "bar",
+            expect![[r#"
+                Attrset attribute `bar`
+                `[int]`
+
+                Option definition with priority 100
+                Merged with 1 other definition(s) of the same priority
+            "#]],
+        );
+    }
+
+    #[test]
+    fn lib_docs() {
+        let (mut db, f) =
+            TestDB::from_fixture("{ lib }: [ lib.strings.$0hasPrefix lib.$1toLower ]").unwrap();
+        db.set_lib_docs(Arc::new(LibDocs::from_sources([
+            (
+                "strings",
+                "{ lib }: rec {
+                    /**
+                      Determine whether a string has given prefix.
+
+                      # Type
+
+                      ```
+                      hasPrefix :: string -> string -> bool
+                      ```
+                    */
+                    hasPrefix = pref: str: true;
+                    /* Convert to lower case. */
+                    toLower = s: s;
+                }",
+            ),
+            ("default", "self: { inherit (self.strings) toLower; }"),
+        ])));
+        let hover = |fpos| {
+            let ret = super::hover(&db, fpos).expect("No hover");
+            let src = db.file_content(fpos.file_id);
+            format!("{}\n{}\n", &src[ret.range], ret.markup)
+        };
+        expect![[r#"
+            lib.strings.hasPrefix
+            `lib.strings.hasPrefix`
+            `string -> string -> bool`
+
+            Determine whether a string has given prefix.
+
+            # Type
+
+            ```
+            hasPrefix :: string -> string -> bool
+            ```
+        "#]]
+        .assert_eq(&hover(f[0]));
+        expect![[r#"
+            lib.toLower
+            `lib.toLower`
+
+            Convert to lower case.
+        "#]]
+        .assert_eq(&hover(f[1]));
+    }
+
+    #[test]
+    fn package() {
+        let (mut db, f) = TestDB::from_fixture(
+            "final: prev: { foo = 1; bar = [ prev.$0hello final.$1foo final.$2hello ]; }",
+        )
+        .unwrap();
+        db.set_package_index(Arc::new(PackageIndex::from_iter([
+            (
+                "hello".into(),
+                PackageInfo {
+                    version: Some("2.12.1".into()),
+                    description: Some(
+                        "A program that produces a familiar, friendly greeting".into(),
+                    ),
+                },
+            ),
+            ("foo".into(), PackageInfo::default()),
+        ])));
+        let hover = |fpos| {
+            let ret = super::hover(&db, fpos).expect("No hover");
+            let src = db.file_content(fpos.file_id);
+            format!("{}\n{}\n", &src[ret.range], ret.markup)
+        };
+        expect![[r#"
+            hello
+            Package `hello`
+            Version `2.12.1`
+
+            A program that produces a familiar, friendly greeting
+        "#]]
+        .assert_eq(&hover(f[0]));
+        // Attributes added by the overlay itself.
+        expect![[r#"
+            foo
+            Field `foo`
+            `int`
+        "#]]
+        .assert_eq(&hover(f[1]));
+        assert_eq!(hover(f[2]), hover(f[0]));
+    }
+
+    #[test]
+    fn store_path() {
+        let hash = "0123456789abcdfghijklmnpqrsvwxyz";
+        check(
+            &format!(r#"{{ a = "/nix/store/{hash}-hello-2.12/bin/$0hello"; }}"#),
+            &format!(r#""/nix/store/{hash}-hello-2.12/bin/hello""#),
+            expect!["Store path `/nix/store/0123456789abcdfghijklmnpqrsvwxyz-hello-2.12`"],
+        );
+        check(
+            &format!(r#"let v = "2.12"; in "/nix/store/{hash}-$0hello-${{v}}""#),
+            &format!(r#""/nix/store/{hash}-hello-${{v}}""#),
+            expect!["Store path `/nix/store/0123456789abcdfghijklmnpqrsvwxyz-hello-2.12`"],
+        );
+        let (db, f) = TestDB::from_fixture(&format!("/nix/store/{hash}-$0hello")).unwrap();
+        assert_eq!(
+            super::hover(&db, f[0]).unwrap().store_path.as_deref(),
+            Some(&*format!("/nix/store/{hash}-hello")),
+        );
+        check_no(r#""/nix/store/$0short-hello""#);
+        check_no(r#""/etc/$0hello""#);
     }
 }
@@ -0,0 +1,163 @@
+use crate::def::{BindingValue, Expr, NameKind};
+use crate::ty::{DisplayConfig, TyDatabase};
+use crate::FileId;
+use std::collections::HashMap;
+use syntax::ast::{self, AstNode};
+use syntax::{SyntaxKind, TextRange, TextSize};
+
+/// Inferred types are truncated harder than in hovers, since hints are shown inline.
+const TY_HINT_DISPLAY: DisplayConfig = DisplayConfig {
+    max_lambda_lhs_depth: 2,
+    max_list_depth: 2,
+    max_attrset_depth: 1,
+    max_attrset_fields: 3,
+    lambda_need_parentheses: false,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InlayHintKind {
+    /// The type of a `let` binding.
+    BindingType,
+    /// The type of a lambda argument or pattern field.
+    ParamType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InlayHint {
+    /// The position right after the hinted name.
+    pub pos: TextSize,
+    pub label: String,
+    pub kind: InlayHintKind,
+}
+
+/// Types of `let` bindings and lambda parameters, whose names are in `range` if any.
+/// Names whose types are unknown in any part, and bindings of literals whose types are obvious,
+/// are skipped.
+pub(crate) fn inlay_hints(
+    db: &dyn TyDatabase,
+    file: FileId,
+    range: Option<TextRange>,
+) -> Vec<InlayHint> {
+    let parse = db.parse(file);
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let infer = db.infer(file);
+    let root = parse.syntax_node();
+
+    let literal_names = module
+        .exprs()
+        .filter_map(|(_, kind)| match kind {
+            Expr::LetIn(bindings, _) => Some(bindings),
+            _ => None,
+        })
+        .flat_map(|bindings| bindings.statics.iter())
+        .filter_map(|&(name, value)| match value {
+            BindingValue::Expr(e) => Some((name, matches!(module[e], Expr::Literal(_)))),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut hints = module
+        .names()
+        .filter_map(|(name, data)| {
+            let kind = match data.kind {
+                NameKind::LetIn if literal_names.get(&name) == Some(&false) => {
+                    InlayHintKind::BindingType
+                }
+                NameKind::Param | NameKind::PatField => InlayHintKind::ParamType,
+                _ => return None,
+            };
+            let ptr = source_map.nodes_for_name(name).next()?;
+            let node = ptr.to_node(&root);
+            // Only hint the single-attr path `a = ...;`, not `a.b = ...;` or `inherit a;`.
+            if data.kind == NameKind::LetIn {
+                let path = node.parent().and_then(ast::Attrpath::cast)?;
+                if path.syntax().parent()?.kind() != SyntaxKind::ATTR_PATH_VALUE
+                    || path.attrs().count() != 1
+                {
+                    return None;
+                }
+            }
+            let pos = node.text_range().end();
+            if range.map_or(false, |range| !range.contains_inclusive(pos)) {
+                return None;
+            }
+            let ty = infer.ty_for_name(name);
+            if !ty.is_fully_known() {
+                return None;
+            }
+            Some(InlayHint {
+                pos,
+                label: format!(": {}", ty.display_with(TY_HINT_DISPLAY)),
+                kind,
+            })
+        })
+        .collect::<Vec<_>>();
+    hints.sort_by_key(|hint| hint.pos);
+    hints
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let file = f.files()[0];
+        let range = match f.markers() {
+            [] => None,
+            _ => Some(f.unwrap_single_range_marker().range),
+        };
+        let mut src = db.file_content(file).to_string();
+        for hint in super::inlay_hints(&db, file, range).into_iter().rev() {
+            src.insert_str(hint.pos.into(), &format!("/*{}*/", hint.label));
+        }
+        expect.assert_eq(&src);
+    }
+
+    #[test]
+    fn let_bindings() {
+        check(
+            r#"let a = 1; b = "${toString a}"; c = [ a ]; d.e = a; inherit (d) e; in a"#,
+            expect![[
+                r#"let a = 1; b/*: string*/ = "${toString a}"; c/*: [int]*/ = [ a ]; d.e = a; inherit (d) e; in a"#
+            ]],
+        );
+    }
+
+    #[test]
+    fn params() {
+        check(
+            "{ enable ? false, port, ... }@args: x: if enable then port + 1 else x",
+            expect!["{ enable/*: bool*/ ? false, port/*: int*/, ... }@args/*: { enable: bool, port: int }*/: x/*: int*/: if enable then port + 1 else x"],
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        check(
+            "let s = { a = 1; b = 2; c = 3; d = 4; x = { y = 1; }; }; in s",
+            expect!["let s/*: { a: int, b: int, c: int, … }*/ = { a = 1; b = 2; c = 3; d = 4; x = { y = 1; }; }; in s"],
+        );
+    }
+
+    #[test]
+    fn unknown() {
+        check("x: y: x y", expect!["x: y: x y"]);
+        check(
+            "let f = { a, b }: a + 1; in f",
+            expect!["let f = { a/*: int*/, b }: a + 1; in f"],
+        );
+    }
+
+    #[test]
+    fn in_range() {
+        check(
+            "let a = [ 1 ]; $0b = [ a ];$1 in b",
+            expect!["let a = [ 1 ]; b/*: [[int]]*/ = [ a ]; in b"],
+        );
+    }
+}
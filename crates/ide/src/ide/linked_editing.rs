@@ -0,0 +1,108 @@
+use super::rename::find_name;
+use crate::{DefDatabase, FilePos};
+use syntax::ast::{self, AstNode};
+use syntax::TextRange;
+
+/// Find all occurrences of the name at `fpos` in the same file, which can be edited
+/// simultaneously without changing the semantics of the file.
+/// This is a lightweight, single-file alternative of `rename`.
+pub(crate) fn linked_editing_ranges(db: &dyn DefDatabase, fpos: FilePos) -> Option<Vec<TextRange>> {
+    let (_, name) = find_name(db, fpos)?;
+    let file_id = fpos.file_id;
+    let src = db.file_content(file_id);
+    let parse = db.parse(file_id);
+    let module = db.module(file_id);
+    let source_map = db.source_map(file_id);
+    let name_refs = db.name_reference(file_id);
+
+    let mut ranges = Vec::new();
+    for ptr in source_map.nodes_for_name(name) {
+        let node = ptr.to_node(&parse.syntax_node());
+        // `inherit (from) name;` also selects the attribute `name` of `from`,
+        // which must not be changed.
+        if node
+            .parent()
+            .and_then(ast::Inherit::cast)
+            .map_or(false, |i| i.from_expr().is_some())
+        {
+            return None;
+        }
+        ranges.push(ptr.text_range());
+    }
+    for &expr in name_refs.name_references(name).unwrap_or_default() {
+        let ptr = source_map.node_for_expr(expr)?;
+        // `inherit name;` also defines the attribute `name`, which must not be changed.
+        if ptr
+            .to_node(&parse.syntax_node())
+            .parent()
+            .map_or(false, |node| ast::Inherit::can_cast(node.kind()))
+        {
+            return None;
+        }
+        ranges.push(ptr.text_range());
+    }
+
+    // Linked ranges must have the same content, so names in string form are not supported.
+    let text = &*module[name].text;
+    if !ranges.iter().all(|&range| &src[range] == text) {
+        return None;
+    }
+    ranges.sort_by_key(|range| range.start());
+    ranges.dedup();
+    Some(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let Some(ranges) = super::linked_editing_ranges(&db, f[0]) else {
+            expect.assert_eq("None");
+            return;
+        };
+        let mut src = db.file_content(f[0].file_id).to_string();
+        for range in ranges.iter().rev() {
+            src.insert(usize::from(range.end()), '>');
+            src.insert(usize::from(range.start()), '<');
+        }
+        expect.assert_eq(&src);
+    }
+
+    #[test]
+    fn let_in() {
+        check(
+            "let $0a = 1; b = a; in { c = a; }",
+            expect!["let <a> = 1; b = <a>; in { c = <a>; }"],
+        );
+        check(
+            "let a = 1; in { c = $0a; }",
+            expect!["let <a> = 1; in { c = <a>; }"],
+        );
+    }
+
+    #[test]
+    fn rec_attrset() {
+        check(
+            "rec { $0a = 1; b = a; }",
+            expect!["rec { <a> = 1; b = <a>; }"],
+        );
+    }
+
+    #[test]
+    fn param() {
+        check("{ $0a, b ? a }: a", expect!["{ <a>, b ? <a> }: <a>"]);
+    }
+
+    #[test]
+    fn unsupported() {
+        check(r#"let "a" = 1; in $0a"#, expect!["None"]);
+        check("let a = 1; in { inherit ({ }) $0a; }", expect!["None"]);
+        check("let $0a = 1; in { inherit a; c = a; }", expect!["None"]);
+        check("$0a", expect!["None"]);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::def::{AstPtr, Expr, ExprId, Literal};
+use crate::def::{AstPtr, Expr, ExprId, Literal, PathAnchor};
 use crate::{DefDatabase, FileId, FileRange, VfsPath};
 use syntax::TextRange;
 use url::Url;
@@ -19,6 +19,10 @@ pub enum Link {
 pub enum LinkTarget {
     Uri(Url),
     VfsPath(VfsPath),
+    /// A path relative to the home directory, from `~/path`.
+    HomePath(String),
+    /// A path to look up in the search path, from `<path>`.
+    SearchPath(String),
 }
 
 pub(crate) fn links(db: &dyn DefDatabase, file_id: FileId) -> Vec<Link> {
@@ -58,16 +62,35 @@ pub(crate) fn link_resolve(db: &dyn DefDatabase, frange: FileRange) -> Option<Li
         .right_biased()?
         .parent()?;
     let expr = source_map.expr_for_node(AstPtr::new(&n))?;
-    let Expr::Literal(Literal::Path(path)) = &module[expr] else {
+    let &Expr::Literal(Literal::Path(path)) = &module[expr] else {
         return None;
     };
-    let vpath = path.resolve(db)?;
-    // Workaround: inlining this causes lifetime issues.
-    let tooltip = vpath.display().to_string();
+    let data = path.data(db);
+    let rel = data.relative_path();
+    let vpath_target = |vpath: VfsPath| {
+        // Workaround: inlining this causes lifetime issues.
+        let tooltip = vpath.display().to_string();
+        (tooltip, LinkTarget::VfsPath(vpath))
+    };
+    let (tooltip, target) = match data.anchor() {
+        PathAnchor::Relative(_) => vpath_target(path.resolve(db)?),
+        PathAnchor::Absolute => vpath_target(VfsPath::new(format!("/{rel}"))),
+        // Leading `..` cannot be resolved without knowing the target.
+        _ if data.supers() != 0 => return None,
+        PathAnchor::Home => (format!("~/{rel}"), LinkTarget::HomePath(rel.to_owned())),
+        PathAnchor::Search(name) => {
+            let path = if rel.is_empty() {
+                name.to_string()
+            } else {
+                format!("{name}/{rel}")
+            };
+            (format!("<{path}>"), LinkTarget::SearchPath(path))
+        }
+    };
     Some(Link::Resolved {
         range: frange.range,
         tooltip,
-        target: LinkTarget::VfsPath(vpath),
+        target,
     })
 }
 
@@ -88,6 +111,7 @@ fn try_resolve_link_uri(uri: &str) -> Option<Url> {
         "git+",
         "tarball+",
         "github:",
+        "gitlab:",
         "sourcehut:",
     ];
 
@@ -98,26 +122,19 @@ fn try_resolve_link_uri(uri: &str) -> Option<Url> {
     let mut uri = Url::parse(uri).ok()?;
     let scheme = uri.scheme();
 
-    // Shortcut `github:owner/repo(/ref_or_rev)?`.
-    if scheme == "github" {
-        let mut iter = uri.path().splitn(3, '/');
-        let owner = iter.next()?;
-        let repo = iter.next()?;
-        // let rev = iter.next()?; // TODO
-        return Some(
-            format!("https://github.com/{owner}/{repo}")
-                .parse()
-                .unwrap(),
-        );
-    }
-
-    // Shortcut `sourcehut:owner/repo(/ref_or_rev)?`.
-    if scheme == "sourcehut" {
+    // Shortcuts `(github|gitlab|sourcehut):owner/repo(/ref_or_rev)?`.
+    let host = match scheme {
+        "github" => Some("github.com"),
+        "gitlab" => Some("gitlab.com"),
+        "sourcehut" => Some("sr.ht"),
+        _ => None,
+    };
+    if let Some(host) = host {
         let mut iter = uri.path().splitn(3, '/');
         let owner = iter.next()?;
         let repo = iter.next()?;
         // let rev = iter.next()?; // TODO
-        return Some(format!("https://sr.ht/{owner}/{repo}").parse().unwrap());
+        return format!("https://{host}/{owner}/{repo}").parse().ok();
     }
 
     // For `anything+(file|path)://...`, chop it to `file://`.
@@ -174,6 +191,8 @@ mod tests {
                 let target = match &target {
                     LinkTarget::Uri(uri) => uri.to_string(),
                     LinkTarget::VfsPath(p) => p.display().to_string(),
+                    LinkTarget::HomePath(p) => format!("$HOME/{p}"),
+                    LinkTarget::SearchPath(p) => format!("$NIX_PATH/{p}"),
                 };
                 let src = &src[range];
                 Some(format!("{src} -> {target}: {tooltip}\n"))
@@ -189,6 +208,8 @@ mod tests {
                 github:NixOS/nixpkgs
                 "github:NixOS/nixpkgs/nixos-22.05"
                 "github:NixOS/nixpkgs/pull/190594/head"
+                "gitlab:veloren/veloren"
+                "sourcehut:~misterio/nix-colors"
                 "file:///root"
                 "https://example.com?foo=1#bar"
             ]"#,
@@ -196,6 +217,8 @@ mod tests {
                 github:NixOS/nixpkgs -> https://github.com/NixOS/nixpkgs: https://github.com/NixOS/nixpkgs
                 "github:NixOS/nixpkgs/nixos-22.05" -> https://github.com/NixOS/nixpkgs: https://github.com/NixOS/nixpkgs
                 "github:NixOS/nixpkgs/pull/190594/head" -> https://github.com/NixOS/nixpkgs: https://github.com/NixOS/nixpkgs
+                "gitlab:veloren/veloren" -> https://gitlab.com/veloren/veloren: https://gitlab.com/veloren/veloren
+                "sourcehut:~misterio/nix-colors" -> https://sr.ht/~misterio/nix-colors: https://sr.ht/~misterio/nix-colors
                 "https://example.com?foo=1#bar" -> https://example.com/: https://example.com/
             "#]],
         );
@@ -218,6 +241,20 @@ mod tests {
             expect![[r#"
                 ./. -> /: /
                 ./foo.nix -> /foo.nix: /foo.nix
+                /bar -> /bar: /bar
+            "#]],
+        );
+    }
+
+    #[test]
+    fn unanchored_path() {
+        check(
+            "[ /etc/../nix.conf ~/.config/nix <nixpkgs> <nixpkgs/lib> <foo/../bar> ]",
+            expect![[r#"
+                /etc/../nix.conf -> /nix.conf: /nix.conf
+                ~/.config/nix -> $HOME/.config/nix: ~/.config/nix
+                <nixpkgs> -> $NIX_PATH/nixpkgs: <nixpkgs>
+                <nixpkgs/lib> -> $NIX_PATH/nixpkgs/lib: <nixpkgs/lib>
             "#]],
         );
     }
@@ -1,14 +1,24 @@
 mod assists;
+mod code_lens;
 mod completion;
 mod diagnostics;
+mod eval_completion;
 mod expand_selection;
 mod file_references;
+mod flake_schema;
 mod goto_definition;
 mod highlight_related;
 mod hover;
+mod inlay_hints;
+mod linked_editing;
 mod links;
+mod prefetch_hash;
+mod query_stats;
 mod references;
 mod rename;
+mod repl_expr;
+mod signature_help;
+mod suppression;
 mod symbol_hierarchy;
 mod syntax_highlighting;
 
@@ -16,23 +26,38 @@ use crate::base::SourceDatabaseStorage;
 use crate::def::DefDatabaseStorage;
 use crate::ty::TyDatabaseStorage;
 use crate::{
-    Change, Diagnostic, FileId, FilePos, FileRange, FileSet, SourceRoot, VfsPath, WorkspaceEdit,
+    Change, Diagnostic, FileId, FilePos, FileRange, FileSet, OptionSet, SourceRoot, TextEdit,
+    VfsPath, WorkspaceEdit, DEFAULT_CALL_PACKAGE_NAMES, DEFAULT_SHADOWING_IGNORED_NAMES,
 };
+use nix_interop::package_aliases::PackageAliases;
+use nix_interop::search_path::SearchPath;
 use nix_interop::DEFAULT_IMPORT_FILE;
 use salsa::{Database, Durability, ParallelDatabase};
 use smol_str::SmolStr;
-use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{fmt, mem, panic};
 use syntax::TextRange;
 
 pub use assists::{Assist, AssistKind};
-pub use completion::{CompletionItem, CompletionItemKind};
+pub use code_lens::CodeLens;
+pub use completion::{
+    merge_completions, refine_completions, CompletionCommand, CompletionItem, CompletionItemKind,
+    DirEntry, LibImportStrategy,
+};
+pub use eval_completion::EvalCompletionQuery;
 pub use goto_definition::GotoDefinitionResult;
 pub use highlight_related::HlRelated;
-pub use hover::HoverResult;
+pub use hover::{HoverDefinition, HoverResult};
+pub use inlay_hints::{InlayHint, InlayHintKind};
 pub use links::{Link, LinkTarget};
-pub use rename::RenameResult;
-pub use symbol_hierarchy::SymbolTree;
+pub use prefetch_hash::HashPlaceholder;
+pub use query_stats::QueryStats;
+pub use rename::{RenameError, RenameResult};
+pub use signature_help::SignatureHelp;
+pub use symbol_hierarchy::{truncate_symbols, SymbolTree, SymbolValueKind};
 pub use syntax_highlighting::{HlAttrField, HlKeyword, HlOperator, HlPunct, HlRange, HlTag};
 
 pub const DEFAULT_LRU_CAP: usize = 128;
@@ -48,17 +73,49 @@ pub use salsa::Cancelled;
 
 pub type Cancellable<T> = Result<T, Cancelled>;
 
+/// Names and durations of analysis passes, in the order of computation.
+pub type PassTimings = Vec<(&'static str, Duration)>;
+
+/// A panic payload indicating that the request of an `Analysis` is abandoned, via the flag
+/// passed to [`AnalysisHost::snapshot_interruptible`].
+/// Unlike [`Cancelled`], it is not caught by `Analysis` and unwinds to the caller.
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
 #[salsa::database(SourceDatabaseStorage, DefDatabaseStorage, TyDatabaseStorage)]
 struct RootDatabase {
     storage: salsa::Storage<Self>,
+    interrupt: Option<Arc<AtomicBool>>,
 }
 
-impl salsa::Database for RootDatabase {}
+impl salsa::Database for RootDatabase {
+    fn salsa_event(&self, event: salsa::Event) {
+        if let salsa::EventKind::WillCheckCancellation = event.kind {
+            if self
+                .interrupt
+                .as_ref()
+                .map_or(false, |flag| flag.load(Ordering::Relaxed))
+            {
+                // Like `Cancelled`, skip the panic hook.
+                panic::resume_unwind(Box::new(Interrupted));
+            }
+        }
+    }
+}
 
 impl salsa::ParallelDatabase for RootDatabase {
     fn snapshot(&self) -> salsa::Snapshot<Self> {
         salsa::Snapshot::new(RootDatabase {
             storage: self.storage.snapshot(),
+            interrupt: self.interrupt.clone(),
         })
     }
 }
@@ -75,14 +132,46 @@ impl Default for RootDatabase {
 
         let mut db = Self {
             storage: salsa::Storage::default(),
+            interrupt: None,
         };
 
         crate::def::ParseQuery
             .in_db_mut(&mut db)
             .set_lru_capacity(DEFAULT_LRU_CAP);
 
+        db.set_source_root_count_with_durability(0, Durability::HIGH);
         db.set_flake_graph_with_durability(Arc::default(), Durability::MEDIUM);
-        db.set_nixos_options_with_durability(Arc::default(), Durability::MEDIUM);
+        for &set in OptionSet::ALL {
+            db.set_option_set_with_durability(set, Arc::default(), Durability::MEDIUM);
+            db.set_option_set_module_paths_with_durability(set, Arc::default(), Durability::MEDIUM);
+        }
+        db.set_package_aliases_with_durability(
+            Arc::new(PackageAliases::builtin()),
+            Durability::MEDIUM,
+        );
+        db.set_package_index_with_durability(Arc::default(), Durability::MEDIUM);
+        db.set_lib_docs_with_durability(Arc::default(), Durability::MEDIUM);
+        db.set_search_path_with_durability(Arc::default(), Durability::MEDIUM);
+        db.set_call_package_names_with_durability(
+            Arc::new(
+                DEFAULT_CALL_PACKAGE_NAMES
+                    .iter()
+                    .map(|&s| s.into())
+                    .collect(),
+            ),
+            Durability::MEDIUM,
+        );
+        db.set_shadowing_ignored_names_with_durability(
+            Arc::new(
+                DEFAULT_SHADOWING_IGNORED_NAMES
+                    .iter()
+                    .map(|&s| s.into())
+                    .collect(),
+            ),
+            Durability::MEDIUM,
+        );
+        db.set_redundant_paren_kept_kinds_with_durability(Arc::default(), Durability::MEDIUM);
+        db.set_language_features_with_durability(Arc::default(), Durability::MEDIUM);
         db
     }
 }
@@ -115,6 +204,17 @@ impl AnalysisHost {
         }
     }
 
+    /// Like [`AnalysisHost::snapshot`], but queries unwind with [`Interrupted`]
+    /// once `flag` is set.
+    pub fn snapshot_interruptible(&self, flag: Arc<AtomicBool>) -> Analysis {
+        Analysis {
+            db: salsa::Snapshot::new(RootDatabase {
+                storage: self.db.storage.snapshot(),
+                interrupt: Some(flag),
+            }),
+        }
+    }
+
     pub fn request_cancellation(&mut self) {
         self.db.salsa_runtime_mut().synthetic_write(Durability::LOW);
     }
@@ -123,6 +223,51 @@ impl AnalysisHost {
         self.request_cancellation();
         change.apply(&mut self.db);
     }
+
+    /// Drop all memoized query results by rebuilding the database from its inputs,
+    /// to release memory.
+    pub fn collect_garbage(&mut self) {
+        use crate::base::{FileContentQuery, SourceRootQuery};
+        use crate::SourceDatabase;
+        use salsa::debug::DebugQueryTable;
+
+        self.request_cancellation();
+        let old_db = mem::take(&mut self.db);
+        let mut roots = SourceRootQuery
+            .in_db(&old_db)
+            .entries::<Vec<_>>()
+            .into_iter()
+            .filter_map(|entry| Some((entry.key, entry.value?)))
+            .collect::<Vec<_>>();
+        roots.sort_by_key(|(sid, _)| sid.0);
+        let change = Change {
+            flake_graph: Some(old_db.flake_graph().as_ref().clone()),
+            roots: Some(roots.into_iter().map(|(_, root)| (*root).clone()).collect()),
+            file_changes: FileContentQuery
+                .in_db(&old_db)
+                .entries::<Vec<_>>()
+                .into_iter()
+                .filter_map(|entry| Some((entry.key, entry.value?)))
+                .collect(),
+            option_sets: OptionSet::ALL
+                .iter()
+                .map(|&set| (set, old_db.option_set(set).as_ref().clone()))
+                .collect(),
+            option_set_module_paths: OptionSet::ALL
+                .iter()
+                .map(|&set| (set, old_db.option_set_module_paths(set).as_ref().clone()))
+                .collect(),
+            package_aliases: Some(old_db.package_aliases().as_ref().clone()),
+            package_index: Some(old_db.package_index().as_ref().clone()),
+            lib_docs: Some(old_db.lib_docs().as_ref().clone()),
+            search_path: Some(old_db.search_path().as_ref().clone()),
+            call_package_names: Some(old_db.call_package_names().as_ref().clone()),
+            shadowing_ignored_names: Some(old_db.shadowing_ignored_names().as_ref().clone()),
+            redundant_paren_kept_kinds: Some(old_db.redundant_paren_kept_kinds().as_ref().clone()),
+            language_features: Some(old_db.language_features().as_ref().clone()),
+        };
+        change.apply(&mut self.db);
+    }
 }
 
 #[derive(Debug)]
@@ -156,16 +301,74 @@ impl Analysis {
         self.with_db(|db| diagnostics::diagnostics(db, file))
     }
 
-    pub fn goto_definition(&self, pos: FilePos) -> Cancellable<Option<GotoDefinitionResult>> {
-        self.with_db(|db| goto_definition::goto_definition(db, pos))
+    /// Same as `diagnostics`, also returning durations of the analysis passes computing them,
+    /// in order. Passes already computed by earlier queries take almost no time.
+    pub fn diagnostics_with_timings(
+        &self,
+        file: FileId,
+    ) -> Cancellable<(Vec<Diagnostic>, PassTimings)> {
+        self.with_db(|db| {
+            use crate::DefDatabase;
+            let mut timings = Vec::new();
+            let mut inst = Instant::now();
+            let mut lap = |name| {
+                let now = Instant::now();
+                timings.push((name, now - inst));
+                inst = now;
+            };
+            db.parse(file);
+            lap("parse");
+            db.module(file);
+            lap("lower");
+            db.name_resolution(file);
+            lap("resolve");
+            let diags = diagnostics::diagnostics(db, file);
+            lap("check");
+            (diags, timings)
+        })
+    }
+
+    /// Diagnostics of relative path literals whose targets do not exist according to `exists`.
+    /// They are separated from `diagnostics`, since the filesystem is not tracked by the database.
+    pub fn missing_paths(
+        &self,
+        file: FileId,
+        exists: impl Fn(&VfsPath) -> bool + std::panic::UnwindSafe,
+    ) -> Cancellable<Vec<Diagnostic>> {
+        self.with_db(|db| diagnostics::missing_paths(db, file, exists))
+    }
+
+    pub fn goto_definition(
+        &self,
+        pos: FilePos,
+        inherit_hops: bool,
+    ) -> Cancellable<Option<GotoDefinitionResult>> {
+        self.with_db(|db| goto_definition::goto_definition(db, pos, inherit_hops))
     }
 
     pub fn completions(
         &self,
         pos: FilePos,
         trigger_char: Option<char>,
+        lib_import: LibImportStrategy,
+    ) -> Cancellable<Option<Vec<CompletionItem>>> {
+        self.with_db(|db| completion::completions(db, pos, trigger_char, lib_import))
+    }
+
+    /// The attrset at the cursor to complete by evaluation, if static analysis knows nothing
+    /// about it. See `EvalCompletionQuery`.
+    pub fn eval_completion_query(&self, pos: FilePos) -> Cancellable<Option<EvalCompletionQuery>> {
+        self.with_db(|db| eval_completion::eval_completion_query(db, pos))
+    }
+
+    /// Completions of directory entries in a path literal. See `completions` for others.
+    pub fn path_completions(
+        &self,
+        pos: FilePos,
+        search_path: &SearchPath,
+        read_dir: impl Fn(&VfsPath) -> Vec<DirEntry> + panic::UnwindSafe,
     ) -> Cancellable<Option<Vec<CompletionItem>>> {
-        self.with_db(|db| completion::completions(db, pos, trigger_char))
+        self.with_db(|db| completion::path_completions(db, pos, search_path, read_dir))
     }
 
     pub fn references(&self, pos: FilePos) -> Cancellable<Option<Vec<FileRange>>> {
@@ -201,13 +404,46 @@ impl Analysis {
     }
 
     pub fn assists(&self, frange: FileRange) -> Cancellable<Vec<Assist>> {
-        self.with_db(|db| assists::assists(db, frange))
+        self.with_db(|db| {
+            let diagnostics = diagnostics::diagnostics(db, frange.file_id);
+            assists::assists(db, frange, &diagnostics)
+        })
+    }
+
+    pub fn code_lenses(&self, file: FileId) -> Cancellable<Vec<CodeLens>> {
+        self.with_db(|db| code_lens::code_lenses(db, file))
     }
 
     pub fn highlight_related(&self, fpos: FilePos) -> Cancellable<Vec<HlRelated>> {
         self.with_db(|db| highlight_related::highlight_related(db, fpos).unwrap_or_default())
     }
 
+    pub fn inlay_hints(
+        &self,
+        file: FileId,
+        range: Option<TextRange>,
+    ) -> Cancellable<Vec<InlayHint>> {
+        self.with_db(|db| inlay_hints::inlay_hints(db, file, range))
+    }
+
+    pub fn hash_placeholder(&self, fpos: FilePos) -> Cancellable<Option<HashPlaceholder>> {
+        self.with_db(|db| prefetch_hash::hash_placeholder(db, fpos))
+    }
+
+    /// The selected expression with bindings of surrounding scopes it depends on, to be
+    /// evaluated standalone in `nix repl`. Fails if it depends on unknown names.
+    pub fn repl_expr(&self, frange: FileRange) -> Cancellable<Result<String, String>> {
+        self.with_db(|db| repl_expr::repl_expr(db, frange))
+    }
+
+    pub fn linked_editing_ranges(&self, fpos: FilePos) -> Cancellable<Option<Vec<TextRange>>> {
+        self.with_db(|db| linked_editing::linked_editing_ranges(db, fpos))
+    }
+
+    pub fn signature_help(&self, fpos: FilePos) -> Cancellable<Option<SignatureHelp>> {
+        self.with_db(|db| signature_help::signature_help(db, fpos))
+    }
+
     //// Custom extensions ////
 
     pub fn file_references(&self, file: FileId) -> Cancellable<Vec<FileId>> {
@@ -217,4 +453,74 @@ impl Analysis {
     pub fn file_referrers(&self, file: FileId) -> Cancellable<Vec<FileId>> {
         self.with_db(|db| file_references::file_referrers(db, file))
     }
+
+    pub fn unloaded_references(&self, file: FileId) -> Cancellable<Vec<VfsPath>> {
+        self.with_db(|db| file_references::unloaded_references(db, file))
+    }
+
+    pub fn query_stats(&self) -> Cancellable<Vec<QueryStats>> {
+        self.with_db(query_stats::query_stats)
+    }
+
+    /// The syntax tree of `file` for debugging. See `syntax::Parse::debug_dump`.
+    pub fn syntax_tree(&self, file: FileId, range: Option<TextRange>) -> Cancellable<String> {
+        self.with_db(|db| {
+            use crate::DefDatabase;
+            db.parse(file).debug_dump(range)
+        })
+    }
+
+    pub fn path_rename_edits(
+        &self,
+        file: FileId,
+        renames: &[(PathBuf, PathBuf)],
+    ) -> Cancellable<Vec<TextEdit>> {
+        self.with_db(|db| file_references::path_rename_edits(db, file, renames))
+    }
+
+    /// Edits replacing all matches of the structural search and replace `rule` in `file`.
+    pub fn ssr_edits(&self, file: FileId, rule: &ssr::Rule) -> Cancellable<Vec<TextEdit>> {
+        // The rule is only read.
+        let rule = panic::AssertUnwindSafe(rule);
+        self.with_db(move |db| {
+            use crate::DefDatabase;
+            rule.replace_edits(&db.parse(file).syntax_node())
+                .into_iter()
+                .map(|(delete, insert)| TextEdit {
+                    delete,
+                    insert: insert.into(),
+                })
+                .collect()
+        })
+    }
+
+    /// The flake input whose store path contains `file`, and the path of `file` relative to it.
+    pub fn flake_input_for_file(&self, file: FileId) -> Cancellable<Option<(String, PathBuf)>> {
+        self.with_db(|db| {
+            use crate::SourceDatabase;
+            let sid = db.file_source_root(file);
+            let flake_info = db.source_root_flake_info(sid)?;
+            let path = db
+                .source_root(sid)
+                .path_for_file(file)
+                .as_path()?
+                .to_owned();
+            flake_info
+                .input_store_paths
+                .iter()
+                .find_map(|(name, store_path)| {
+                    let rel = path.strip_prefix(store_path.as_path()?).ok()?;
+                    Some((name.clone(), rel.to_owned()))
+                })
+        })
+    }
+
+    /// Compute and cache the syntax and name resolution of a file, for background indexing.
+    pub fn prime_file(&self, file: FileId) -> Cancellable<()> {
+        self.with_db(|db| {
+            use crate::DefDatabase;
+            db.module_kind(file);
+            db.name_resolution(file);
+        })
+    }
 }
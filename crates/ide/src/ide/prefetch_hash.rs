@@ -0,0 +1,321 @@
+//! Placeholder hashes in arguments of fetchers, like `fetchurl { url = "..."; hash = ""; }`,
+//! which can be filled by prefetching the source with Nix.
+//!
+//! Arguments must be constant strings, possibly interpolating constants like `"v${version}"`.
+//! Calls with arguments post-processing the content, like `postFetch`, are not supported.
+use std::collections::HashMap;
+
+use nix_interop::prefetch::PrefetchSource;
+use syntax::ast::{self, AstNode, HasBindings};
+use syntax::semantic::AttrKind;
+use syntax::{best_token_at_offset, TextRange};
+
+use crate::def::{const_eval_string, AstPtr};
+use crate::ty::is_function_named;
+use crate::{DefDatabase, FileId, FilePos, InFile, ModuleSourceMap};
+
+/// Fetchers whose sources can be prefetched.
+const FETCHERS: &[&str] = &[
+    "fetchurl",
+    "fetchzip",
+    "fetchgit",
+    "fetchFromGitHub",
+    "fetchFromGitLab",
+];
+
+/// Attributes of hashes, which accept SRI hashes.
+const HASH_ATTRS: &[&str] = &["hash", "sha256"];
+
+/// Arguments changing the fetched content in ways prefetching does not reproduce.
+const UNSUPPORTED_ARGS: &[&str] = &[
+    "postFetch",
+    "stripRoot",
+    "leaveDotGit",
+    "deepClone",
+    "sparseCheckout",
+    "forceFetchGit",
+];
+
+/// Placeholders from `lib`, which always mismatch.
+const FAKE_HASHES: &[&str] = &["fakeHash", "fakeSha256", "fakeSha512"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashPlaceholder {
+    /// The range of the placeholder value, to be replaced by the hash string.
+    pub range: TextRange,
+    pub source: PrefetchSource,
+}
+
+/// The placeholder hash in the innermost fetcher call containing `pos`, like `fetchurl { | }`.
+pub(crate) fn hash_placeholder(
+    db: &dyn DefDatabase,
+    FilePos { file_id, pos }: FilePos,
+) -> Option<HashPlaceholder> {
+    let parse = db.parse(file_id);
+    let module = db.module(file_id);
+    let source_map = db.source_map(file_id);
+    let tok = best_token_at_offset(&parse.syntax_node(), pos)?;
+    let (fetcher, set) = tok.parent_ancestors().find_map(|node| {
+        let app = ast::Apply::cast(node)?;
+        let ast::Expr::AttrSet(set) = app.argument()?.flatten_paren()? else {
+            return None;
+        };
+        let func = app.function()?.flatten_paren()?;
+        let func = source_map.expr_for_node(AstPtr::new(func.syntax()))?;
+        let &fetcher = FETCHERS
+            .iter()
+            .find(|&&name| is_function_named(&module, func, &[name]))?;
+        Some((fetcher, set))
+    })?;
+
+    let mut placeholder = None;
+    let mut args = HashMap::new();
+    for binding in set.bindings() {
+        let binding = match binding {
+            ast::Binding::Inherit(inherit) => {
+                let unsupported = inherit.attrs().any(|attr| match AttrKind::of(attr) {
+                    AttrKind::Static(Some(name)) => UNSUPPORTED_ARGS.contains(&&*name),
+                    _ => false,
+                });
+                if unsupported {
+                    return None;
+                }
+                continue;
+            }
+            ast::Binding::AttrpathValue(binding) => binding,
+        };
+        let mut attrs = binding.attrpath()?.attrs();
+        let (Some(attr), None) = (attrs.next(), attrs.next()) else {
+            continue;
+        };
+        let (AttrKind::Static(Some(name)), Some(value)) = (AttrKind::of(attr), binding.value())
+        else {
+            continue;
+        };
+        if UNSUPPORTED_ARGS.contains(&&*name) {
+            return None;
+        }
+        if HASH_ATTRS.contains(&&*name) {
+            let value = value.flatten_paren()?;
+            if !is_placeholder(db, &source_map, file_id, &value) {
+                return None;
+            }
+            placeholder = Some(value.syntax().text_range());
+        } else {
+            args.insert(name, value);
+        }
+    }
+    let range = placeholder?;
+
+    let string = |name: &str| {
+        let expr = source_map.expr_for_node(AstPtr::new(args.get(name)?.syntax()))?;
+        const_eval_string(db, InFile::new(file_id, expr))
+    };
+    let bool = |name: &str, default: bool| match args.get(name).cloned() {
+        None => Some(default),
+        Some(value) => match value.flatten_paren()? {
+            ast::Expr::Ref(r) => match r.token()?.text() {
+                "true" => Some(true),
+                "false" => Some(false),
+                _ => None,
+            },
+            _ => None,
+        },
+    };
+    let rev = || match string("tag") {
+        Some(tag) => Some(format!("refs/tags/{tag}")),
+        None => string("rev"),
+    };
+
+    let source = match fetcher {
+        "fetchurl" | "fetchzip" => PrefetchSource::Url {
+            url: string("url")?,
+            unpack: fetcher == "fetchzip",
+        },
+        "fetchgit" => PrefetchSource::Git {
+            url: string("url")?,
+            rev: rev()?,
+            fetch_submodules: bool("fetchSubmodules", true)?,
+        },
+        "fetchFromGitHub" => {
+            let base = string("githubBase").unwrap_or_else(|| "github.com".into());
+            let (owner, repo, rev) = (string("owner")?, string("repo")?, rev()?);
+            if bool("fetchSubmodules", false)? {
+                PrefetchSource::Git {
+                    url: format!("https://{base}/{owner}/{repo}.git"),
+                    rev,
+                    fetch_submodules: true,
+                }
+            } else {
+                PrefetchSource::Url {
+                    url: format!("https://{base}/{owner}/{repo}/archive/{rev}.tar.gz"),
+                    unpack: true,
+                }
+            }
+        }
+        "fetchFromGitLab" => {
+            let domain = string("domain").unwrap_or_else(|| "gitlab.com".into());
+            let slug = match string("group") {
+                Some(group) => format!("{group}/{}/{}", string("owner")?, string("repo")?),
+                None => format!("{}/{}", string("owner")?, string("repo")?),
+            };
+            let rev = rev()?;
+            if bool("fetchSubmodules", false)? {
+                PrefetchSource::Git {
+                    url: format!("https://{domain}/{slug}.git"),
+                    rev,
+                    fetch_submodules: true,
+                }
+            } else {
+                // The same escaping as `fetchFromGitLab` in nixpkgs.
+                let slug = slug.replace('.', "%2E").replace('/', "%2F");
+                let rev = rev
+                    .replace('%', "%25")
+                    .replace('+', "%2B")
+                    .replace('/', "%2F");
+                PrefetchSource::Url {
+                    url: format!(
+                        "https://{domain}/api/v4/projects/{slug}/repository/archive.tar.gz?sha={rev}"
+                    ),
+                    unpack: true,
+                }
+            }
+        }
+        _ => unreachable!(),
+    };
+    Some(HashPlaceholder { range, source })
+}
+
+/// `""`, or `fakeHash` and friends, possibly selected from `lib`.
+fn is_placeholder(
+    db: &dyn DefDatabase,
+    source_map: &ModuleSourceMap,
+    file_id: FileId,
+    value: &ast::Expr,
+) -> bool {
+    let name = match value {
+        ast::Expr::Ref(r) => r.token().map(|tok| tok.text().to_owned()),
+        ast::Expr::Select(sel) if sel.default_expr().is_none() => sel
+            .attrpath()
+            .and_then(|path| path.attrs().last())
+            .and_then(|attr| match AttrKind::of(attr) {
+                AttrKind::Static(name) => name,
+                AttrKind::Dynamic(_) => None,
+            }),
+        _ => {
+            let Some(expr) = source_map.expr_for_node(AstPtr::new(value.syntax())) else {
+                return false;
+            };
+            return const_eval_string(db, InFile::new(file_id, expr)).as_deref() == Some("");
+        }
+    };
+    name.map_or(false, |name| FAKE_HASHES.contains(&&*name))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let ret = match super::hash_placeholder(&db, f[0]) {
+            None => "None".into(),
+            Some(ret) => {
+                let src = db.file_content(f[0].file_id);
+                format!("{} {:?}", &src[ret.range], ret.source)
+            }
+        };
+        expect.assert_eq(&ret);
+    }
+
+    #[test]
+    fn url() {
+        check(
+            r#"{ fetchurl }: fetchurl { url = "https://example.com/a.tar.gz"; hash = $0""; }"#,
+            expect![[r#""" Url { url: "https://example.com/a.tar.gz", unpack: false }"#]],
+        );
+        check(
+            r#"{ lib, pkgs }: pkgs.fetchzip { $0url = "https://example.com/a.zip"; sha256 = lib.fakeSha256; }"#,
+            expect![[r#"lib.fakeSha256 Url { url: "https://example.com/a.zip", unpack: true }"#]],
+        );
+    }
+
+    #[test]
+    fn git() {
+        check(
+            r#"{ fetchgit }: fetchgit { url = "https://example.com/a.git"; rev = "v1"; hash = "";$0 }"#,
+            expect![[
+                r#""" Git { url: "https://example.com/a.git", rev: "v1", fetch_submodules: true }"#
+            ]],
+        );
+        check(
+            r#"
+{ stdenv, fetchFromGitHub, lib }:
+stdenv.mkDerivation rec {
+  version = "1.0";
+  src = fetchFromGitHub {
+    owner = "foo";
+    repo = "bar";
+    rev = "v${version}";
+    hash = lib.fakeHash;$0
+  };
+}
+"#,
+            expect![[
+                r#"lib.fakeHash Url { url: "https://github.com/foo/bar/archive/v1.0.tar.gz", unpack: true }"#
+            ]],
+        );
+        check(
+            r#"{ fetchFromGitHub, fakeHash }: fetchFromGitHub { owner = "foo"; repo = "bar"; tag = "v1"; fetchSubmodules = true; hash = fakeHash;$0 }"#,
+            expect![[
+                r#"fakeHash Git { url: "https://github.com/foo/bar.git", rev: "refs/tags/v1", fetch_submodules: true }"#
+            ]],
+        );
+        check(
+            r#"{ fetchFromGitLab }: fetchFromGitLab { group = "a.b"; owner = "foo"; repo = "bar"; rev = "1+2"; hash = "";$0 }"#,
+            expect![[
+                r#""" Url { url: "https://gitlab.com/api/v4/projects/a%2Eb%2Ffoo%2Fbar/repository/archive.tar.gz?sha=1%2B2", unpack: true }"#
+            ]],
+        );
+    }
+
+    #[test]
+    fn not_applicable() {
+        // Not a placeholder.
+        check(
+            r#"{ fetchurl }: fetchurl { url = "https://example.com/a"; hash = "sha256-AAAA";$0 }"#,
+            expect!["None"],
+        );
+        // Unknown arguments.
+        check(
+            r#"{ fetchurl, url }: fetchurl { inherit url; hash = "";$0 }"#,
+            expect!["None"],
+        );
+        // Arguments changing the content.
+        check(
+            r#"{ fetchzip }: fetchzip { url = "https://example.com/a.zip"; stripRoot = false; hash = "";$0 }"#,
+            expect!["None"],
+        );
+        check(
+            r#"{ fetchgit, postFetch }: fetchgit { url = "https://example.com/a.git"; rev = "v1"; inherit postFetch; hash = "";$0 }"#,
+            expect!["None"],
+        );
+        // No hash.
+        check(
+            r#"{ fetchurl }: fetchurl { url = "https://example.com/a";$0 }"#,
+            expect!["None"],
+        );
+        // Not a fetcher.
+        check(
+            r#"{ f }: f { url = "https://example.com/a"; hash = "";$0 }"#,
+            expect!["None"],
+        );
+        check(
+            r#"{ fetchurl }: fetchurl { url = "https://example.com/a"; hash = ""; } // $0{ }"#,
+            expect!["None"],
+        );
+    }
+}
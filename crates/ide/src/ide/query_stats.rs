@@ -0,0 +1,113 @@
+use super::RootDatabase;
+use salsa::debug::{DebugQueryTable, TableEntry};
+use salsa::Query;
+
+/// Entry counts of a query table, for diagnosing memory usage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryStats {
+    pub name: &'static str,
+    /// All keys ever queried, including ones whose values are evicted or not computed yet.
+    pub entries: usize,
+    /// Keys with cached values.
+    pub memoized: usize,
+}
+
+/// Counts entries without keeping keys and values.
+#[derive(Default)]
+struct EntryCount {
+    entries: usize,
+    memoized: usize,
+}
+
+impl<K, V> FromIterator<TableEntry<K, V>> for EntryCount {
+    fn from_iter<T: IntoIterator<Item = TableEntry<K, V>>>(iter: T) -> Self {
+        let mut this = Self::default();
+        for entry in iter {
+            this.entries += 1;
+            this.memoized += usize::from(entry.value.is_some());
+        }
+        this
+    }
+}
+
+/// Statistics of all query tables, most entries first.
+pub(super) fn query_stats(db: &RootDatabase) -> Vec<QueryStats> {
+    use crate::base::*;
+    use crate::def::*;
+    use crate::ty::*;
+
+    let mut stats = Vec::new();
+    macro_rules! collect {
+        ($($query:ident),* $(,)?) => {
+            $({
+                let count = $query.in_db(db).entries::<EntryCount>();
+                stats.push(QueryStats {
+                    name: <$query as Query>::QUERY_NAME,
+                    entries: count.entries,
+                    memoized: count.memoized,
+                });
+            })*
+        };
+    }
+    collect!(
+        // SourceDatabase.
+        FileContentQuery,
+        SourceRootQuery,
+        SourceRootFlakeInfoQuery,
+        FileSourceRootQuery,
+        SourceRootCountQuery,
+        FileForPathQuery,
+        FlakeGraphQuery,
+        OptionSetQuery,
+        PackageAliasesQuery,
+        // DefDatabase.
+        InternPathQuery,
+        ParseQuery,
+        ModuleWithSourceMapQuery,
+        ModuleQuery,
+        SourceMapQuery,
+        ModuleKindQuery,
+        ModuleOptionSetQuery,
+        ModuleReferencesQuery,
+        SourceRootReferrerGraphQuery,
+        SourceRootClosureQuery,
+        ModuleReferrersQuery,
+        CallPackageSitesQuery,
+        IsCalledPackageQuery,
+        ResolvePathQuery,
+        ResolvePathFileQuery,
+        ScopesQuery,
+        NameResolutionQuery,
+        NameReferenceQuery,
+        LivenessCheckQuery,
+        DeprecatedPackagesQuery,
+        // TyDatabase.
+        ModuleExpectedTyQuery,
+        InferQuery,
+        ImportTyQuery,
+        ConfigTyQuery,
+        FlakeInputTysQuery,
+        OptionEnumValuesQuery,
+    );
+    stats.sort_by(|lhs, rhs| rhs.entries.cmp(&lhs.entries).then(lhs.name.cmp(rhs.name)));
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::AnalysisHost;
+
+    #[test]
+    fn query_stats() {
+        let (host, file) = AnalysisHost::new_single_file("let a = 1; in a");
+        let analysis = host.snapshot();
+        analysis.diagnostics(file).unwrap();
+        let stats = analysis.query_stats().unwrap();
+        let get = |name| stats.iter().find(|stat| stat.name == name).unwrap();
+        assert_eq!(get("file_content").entries, 1);
+        assert_eq!(get("parse").memoized, 1);
+        assert_eq!(get("scopes").memoized, 1);
+        // Nothing is imported.
+        assert_eq!(get("import_ty").entries, 0);
+    }
+}
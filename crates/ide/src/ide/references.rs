@@ -1,5 +1,7 @@
-use crate::def::{AstPtr, ResolveResult};
-use crate::{DefDatabase, FilePos, FileRange};
+use crate::def::{AstPtr, NameId, ResolveResult};
+use crate::ty::OptionReference;
+use crate::{DefDatabase, FileId, FilePos, FileRange, ModuleKind, TyDatabase};
+use smol_str::SmolStr;
 use syntax::ast::{self, AstNode};
 use syntax::{best_token_at_offset, SyntaxKind, T};
 
@@ -9,7 +11,7 @@ enum DefKind {
 }
 
 pub(crate) fn references(
-    db: &dyn DefDatabase,
+    db: &impl TyDatabase,
     FilePos { file_id, pos }: FilePos,
 ) -> Option<Vec<FileRange>> {
     let parse = db.parse(file_id);
@@ -31,8 +33,16 @@ pub(crate) fn references(
     let source_map = db.source_map(file_id);
     let nameres = db.name_resolution(file_id);
     let nameref = db.name_reference(file_id);
+    let mut extra_refs = Vec::new();
     let refs = match kind {
         DefKind::Attr(ptr) => {
+            // `config.foo.bar` in NixOS modules references the option `foo.bar`.
+            if let Some(expr) = source_map.expr_for_node(ptr.clone()) {
+                if let Some(use_) = db.option_uses(file_id).iter().find(|u| u.attr == expr) {
+                    return Some(option_references(db, file_id, &use_.path, None));
+                }
+            }
+
             // If this is not a name definition, but a usage. We lookup its definition for the
             // query. This is covered by the test `on_usage`.
             let name = source_map.name_for_node(ptr.clone()).or_else(|| {
@@ -42,6 +52,18 @@ pub(crate) fn references(
                 };
                 Some(*name)
             })?;
+            extra_refs = flake_input_references(db, file_id, name);
+            let option_path = db
+                .option_definitions(file_id)
+                .for_name(name)
+                .map(|def| def.path.clone())
+                .or_else(|| {
+                    let decls = db.option_declarations(file_id);
+                    decls.for_name(name).map(|decl| decl.path.clone())
+                });
+            if let Some(path) = option_path {
+                extra_refs.extend(option_references(db, file_id, &path, Some(name)));
+            }
             nameref.name_references(name)
         }
         DefKind::With(ptr) => {
@@ -51,7 +73,7 @@ pub(crate) fn references(
     };
     // When {name,with}_references returns None, it means no references,
     // not a failure.
-    let refs = refs.map_or(Vec::new(), |refs| {
+    let mut refs = refs.map_or(Vec::new(), |refs| {
         refs.iter()
             .map(|&expr| {
                 let ptr = source_map.node_for_expr(expr).expect("Id must be valid");
@@ -59,9 +81,80 @@ pub(crate) fn references(
             })
             .collect()
     });
+    refs.extend(extra_refs);
     Some(refs)
 }
 
+/// Declarations, definitions and uses via `config.<path>` of the option `path`, in all NixOS
+/// modules of the workspace. The name being queried itself is excluded.
+fn option_references(
+    db: &dyn TyDatabase,
+    file: FileId,
+    path: &[SmolStr],
+    current: Option<NameId>,
+) -> Vec<FileRange> {
+    let index = db.option_reference_index(db.file_source_root(file));
+    index
+        .get(path)
+        .iter()
+        .filter_map(|r| {
+            let source_map = db.source_map(r.file_id);
+            let ptr = match r.value {
+                OptionReference::Declaration(name) | OptionReference::Definition(name) => {
+                    if r.file_id == file && Some(name) == current {
+                        return None;
+                    }
+                    source_map.nodes_for_name(name).next()?
+                }
+                OptionReference::Use(expr) => source_map.node_for_expr(expr)?,
+            };
+            Some(FileRange::new(r.file_id, ptr.text_range()))
+        })
+        .collect()
+}
+
+/// An explicit flake input is also referenced by the parameter of `outputs` and `follows`,
+/// together with references of the parameter.
+fn flake_input_references(db: &dyn DefDatabase, file: FileId, name: NameId) -> Vec<FileRange> {
+    let module_kind = db.module_kind(file);
+    let ModuleKind::FlakeNix {
+        explicit_inputs,
+        param_inputs,
+        follows,
+        ..
+    } = &*module_kind
+    else {
+        return Vec::new();
+    };
+    let module = db.module(file);
+    let input = &module[name].text;
+    if explicit_inputs.get(input) != Some(&name) {
+        return Vec::new();
+    }
+
+    let source_map = db.source_map(file);
+    let nameref = db.name_reference(file);
+    let mut exprs = follows
+        .iter()
+        .filter(|(_, target)| target == input)
+        .map(|&(expr, _)| expr)
+        .collect::<Vec<_>>();
+    let mut ret = Vec::new();
+    if let Some(&param) = param_inputs.get(input) {
+        ret.extend(
+            source_map
+                .nodes_for_name(param)
+                .map(|ptr| FileRange::new(file, ptr.text_range())),
+        );
+        exprs.extend(nameref.name_references(param).into_iter().flatten());
+    }
+    ret.extend(exprs.into_iter().map(|expr| {
+        let ptr = source_map.node_for_expr(expr).expect("Id must be valid");
+        FileRange::new(file, ptr.text_range())
+    }));
+    ret
+}
+
 #[cfg(test)]
 mod tests {
     use crate::tests::TestDB;
@@ -127,4 +220,91 @@ mod tests {
         // unrelated attributes as "references".
         check("with {}; $0a + b");
     }
+
+    #[test]
+    fn flake_input() {
+        check(
+            r#"
+#- /flake.nix
+{
+    inputs.$0nixpkgs.url = "github:NixOS/nixpkgs";
+    inputs.nix.inputs.nixpkgs.follows = $1"nixpkgs";
+    outputs = { self, $2nixpkgs, ... }: { lib = $3nixpkgs.lib; };
+}
+            "#,
+        );
+        // From usages of the parameter, like other names.
+        check(
+            r#"
+#- /flake.nix
+{
+    inputs.nixpkgs.url = "github:NixOS/nixpkgs";
+    outputs = { self, nixpkgs, ... }: { lib = $0$1nixpkgs.lib; };
+}
+            "#,
+        );
+    }
+
+    #[test]
+    fn option() {
+        let check_files = |fixture: &str| {
+            let (db, f) = TestDB::from_fixture(fixture).unwrap();
+            let expect = f.markers()[1..]
+                .iter()
+                .map(|p| (p.file_id, p.pos))
+                .collect::<Vec<_>>();
+            let mut got = super::references(&db, f[0])
+                .into_iter()
+                .flatten()
+                .map(|frange| (frange.file_id, frange.range.start()))
+                .collect::<Vec<_>>();
+            got.sort();
+            assert_eq!(got, expect);
+        };
+        // Declarations, definitions and uses in all modules of the workspace.
+        check_files(
+            r#"
+#- /default.nix
+{ config, ... }: { imports = [ ./foo.nix ]; foo.$0enable = true; bar = config.foo.$1enable; }
+#- /foo.nix
+{ config, lib, ... }: {
+    options.foo.$2enable = lib.mkEnableOption "foo";
+    options.foo.package = lib.mkOption { };
+    config = lib.mkIf config.foo.$3enable { foo.$4enable = lib.mkForce false; };
+}
+#- /other.nix
+{ config, ... }: let cfg = config.foo; in { bar = cfg.$5enable; baz = cfg.package; }
+            "#,
+        );
+        // Constant dynamic attributes are folded.
+        check_files(
+            r#"
+#- /default.nix
+{ config, ... }: let name = "foo"; in { $0foo.enable = true; bar = config.${name}.enable; }
+            "#,
+        );
+        check_files(
+            r#"
+#- /default.nix
+{ config, ... }: let name = "foo"; in { foo.$0enable = true; bar = config.${name}.$1enable; }
+            "#,
+        );
+        // From usages and declarations.
+        check_files(
+            r#"
+#- /default.nix
+{ config, ... }: { foo.$1enable = true; bar = config.foo.$0$2enable; }
+#- /foo.nix
+{ lib, ... }: { options.foo.$3enable = lib.mkEnableOption "foo"; }
+            "#,
+        );
+        check_files(
+            r#"
+#- /default.nix
+{ config, ... }: { foo.$1enable = true; bar = config.foo.$2enable; }
+#- /foo.nix
+{ lib, ... }: { options.foo.$0enable = lib.mkEnableOption "foo"; }
+            "#,
+        );
+    }
 }
@@ -1,36 +1,124 @@
-use crate::def::{AstPtr, NameId, ResolveResult};
-use crate::{DefDatabase, FilePos, TextEdit, WorkspaceEdit};
+use super::goto_definition::select_attr_source;
+use crate::def::{
+    is_call_package, AstPtr, BindingValue, Expr, ExprId, Literal, NameId, NameKind, ResolveResult,
+};
+use crate::ty::{AttrSource, MAX_IMPORT_DEPTH};
+use crate::{
+    DefDatabase, FileId, FilePos, FileRange, InFile, Module, TextEdit, TyDatabase, WorkspaceEdit,
+};
 use smol_str::SmolStr;
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use syntax::ast::{self, AstNode};
 use syntax::semantic::escape_literal_attr;
-use syntax::{best_token_at_offset, match_ast, SyntaxKind, TextRange};
+use syntax::{best_token_at_offset, match_ast, SyntaxKind, TextRange, TextSize};
 
-pub type RenameResult<T> = Result<T, String>;
+pub type RenameResult<T> = Result<T, RenameError>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenameError {
+    pub message: String,
+    /// Locations conflicting with the new name, with descriptions.
+    pub conflicts: Vec<(FileRange, String)>,
+}
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.message.fmt(f)
+    }
+}
+
+impl From<String> for RenameError {
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            conflicts: Vec::new(),
+        }
+    }
+}
+
+impl From<&str> for RenameError {
+    fn from(message: &str) -> Self {
+        message.to_owned().into()
+    }
+}
 
 pub(crate) fn prepare_rename(
-    db: &dyn DefDatabase,
+    db: &impl TyDatabase,
     fpos: FilePos,
 ) -> RenameResult<(TextRange, SmolStr)> {
-    let (range, name) = find_name(db, fpos).ok_or_else(|| "No references found".to_owned())?;
-    let module = db.module(fpos.file_id);
-    let text = module[name].text.clone();
+    let (range, name) = find_rename_target(db, fpos).ok_or("No references found")?;
+    let module = db.module(name.file_id);
+    let text = module[name.value].text.clone();
     Ok((range, text))
 }
 
 pub(crate) fn rename(
-    db: &dyn DefDatabase,
+    db: &impl TyDatabase,
     fpos: FilePos,
     new_name: &str,
 ) -> RenameResult<WorkspaceEdit> {
-    let (_, name) = find_name(db, fpos).ok_or_else(|| "No references found".to_owned())?;
+    let (_, name) = find_rename_target(db, fpos).ok_or("No references found")?;
+
+    let mut content_edits = HashMap::new();
+    content_edits.insert(name.file_id, rename_in_file(db, name, new_name)?);
+
+    // Rename fields of arguments at call sites if it is a lambda pattern field.
+    if db.module(name.file_id)[name.value].kind == NameKind::PatField {
+        for arg in call_site_args(db, name) {
+            let edits = rename_call_site_arg(db, arg, name, new_name)?;
+            content_edits
+                .entry(arg.file_id)
+                .or_insert_with(Vec::new)
+                .extend(edits);
+        }
+    }
+
+    // Rename field selections `a.name` in this file and files importing this file.
+    let new_attr = escape_literal_attr(new_name);
+    for file in importing_files(db, name.file_id) {
+        let edits = rename_selections(db, file, name, &new_attr);
+        if !edits.is_empty() {
+            content_edits
+                .entry(file)
+                .or_insert_with(Vec::new)
+                .extend(edits);
+        }
+    }
+
+    for edits in content_edits.values_mut() {
+        edits.sort_by_key(|edit| edit.delete.start());
+
+        // Sanity check.
+        if edits
+            .windows(2)
+            .any(|w| w[0].delete.end() > w[1].delete.start())
+        {
+            return Err("Change would overlap".into());
+        }
+    }
+
+    Ok(WorkspaceEdit {
+        content_edits,
+        file_system_edits: Vec::new(),
+    })
+}
 
+/// Rename definitions and references of a name in its own file.
+fn rename_in_file(
+    db: &dyn DefDatabase,
+    InFile {
+        file_id,
+        value: name,
+    }: InFile<NameId>,
+    new_name: &str,
+) -> RenameResult<Vec<TextEdit>> {
     let new_attr = escape_literal_attr(new_name);
 
-    let file_id = fpos.file_id;
     let src = db.file_content(file_id);
     let parse = db.parse(file_id);
-    let module = db.module(fpos.file_id);
+    let module = db.module(file_id);
     let source_map = db.source_map(file_id);
 
     let old_attr = escape_literal_attr(&module[name].text);
@@ -55,7 +143,7 @@ pub(crate) fn rename(
         //
         // Note that renaming `rec { inherit old; }` => `rec { new = old; }`
         // would never collide with another field `old`, since `inherit`ed names are unique.
-        // TODO: Check if `new` collides with other fields.
+        // Collisions with `new` are checked in `check_conflicts`.
 
         // First remove the old binding.
         edits.push(TextEdit {
@@ -104,6 +192,7 @@ pub(crate) fn rename(
     if matches!(new_attr, Cow::Owned(_)) && !refs.is_empty() {
         return Err("Cannot rename to a string literal while it is referenced".into());
     }
+    check_conflicts(db, InFile::new(file_id, name), refs, new_name)?;
     for &expr in refs {
         let ptr = source_map
             .node_for_expr(expr)
@@ -121,7 +210,7 @@ pub(crate) fn rename(
 
         // Here we are renaming the *reference* of an inherited name.
         // `inherit old;` => `old = new;`
-        // TODO: Check if `new` collides with another names.
+        // Captures of `new` are checked in `check_conflicts`.
         assert!(
             i.from_expr().is_none(),
             "Expr::Ref can only be from Inherit without from_expr"
@@ -146,22 +235,468 @@ pub(crate) fn rename(
         });
     }
 
-    edits.sort_by_key(|edit| edit.delete.start());
+    Ok(edits)
+}
+
+/// Check if renaming `name` to `new_name` would conflict with other names in its file, that is,
+/// - Another definition of `new_name` in the same bindings or lambda.
+/// - References of `name` shadowed by an inner definition of `new_name`,
+///   eg. renaming `a` to `b` in `let a = 1; in let b = 2; in a`.
+/// - References of `new_name` captured by the renamed definition,
+///   eg. renaming `a` to `b` in `with { b = 1; }; let a = 2; in b`.
+fn check_conflicts(
+    db: &dyn DefDatabase,
+    name: InFile<NameId>,
+    refs: &[ExprId],
+    new_name: &str,
+) -> RenameResult<()> {
+    let module = db.module(name.file_id);
+    let scopes = db.scopes(name.file_id);
+    let source_map = db.source_map(name.file_id);
+    let old_name = &module[name.value].text;
+    let range_for_name = |def: NameId| {
+        let range = source_map.nodes_for_name(def).next()?.text_range();
+        Some(FileRange::new(name.file_id, range))
+    };
+    let range_for_expr = |expr: ExprId| {
+        let range = source_map.node_for_expr(expr)?.text_range();
+        Some(FileRange::new(name.file_id, range))
+    };
+
+    let mut conflicts = Vec::new();
+    let mut message = None;
+    let in_message = |frange: Option<FileRange>| {
+        frange.map_or(String::new(), |frange| {
+            format!(
+                " at {}",
+                display_pos(db, frange.file_id, frange.range.start())
+            )
+        })
+    };
 
-    // Sanity check.
-    if edits
-        .windows(2)
-        .any(|w| w[0].delete.end() > w[1].delete.start())
+    // Duplicated definitions.
+    for dup in sibling_names(&module, name.value)
+        .into_iter()
+        .filter(|&sibling| sibling != name.value && module[sibling].text == new_name)
     {
-        return Err("Change would overlap".into());
+        let frange = range_for_name(dup);
+        message.get_or_insert_with(|| {
+            format!("`{new_name}` is already defined{}", in_message(frange))
+        });
+        conflicts
+            .extend(frange.map(|frange| (frange, format!("`{new_name}` is already defined here"))));
     }
 
-    Ok(WorkspaceEdit {
-        content_edits: [(file_id, edits)].into_iter().collect(),
-    })
+    // Shadowed references.
+    let mut shadows = Vec::new();
+    for &expr in refs {
+        let shadow = scopes.scope_for_expr(expr).and_then(|scope| {
+            scopes
+                .ancestors(scope)
+                .filter_map(|data| data.as_definitions())
+                .take_while(|defs| defs.get(old_name) != Some(&name.value))
+                .find_map(|defs| defs.get(new_name).copied())
+        });
+        // `inherit old;` is rewritten to `old = new;`, where `new` is resolved
+        // inside the bindings if they are recursive.
+        let shadow = shadow.or_else(|| {
+            let ptr = source_map.node_for_expr(expr)?;
+            let inherit_name = source_map.name_for_node(ptr)?;
+            if !module[inherit_name].kind.is_definition() {
+                return None;
+            }
+            sibling_names(&module, inherit_name)
+                .into_iter()
+                .find(|&sibling| module[sibling].text == new_name)
+        });
+        let Some(shadow) = shadow else {
+            continue;
+        };
+        conflicts.extend(range_for_expr(expr).map(|frange| {
+            (
+                frange,
+                format!("This reference would refer to another `{new_name}`"),
+            )
+        }));
+        if !shadows.contains(&shadow) {
+            shadows.push(shadow);
+        }
+    }
+    for shadow in shadows {
+        let frange = range_for_name(shadow);
+        message.get_or_insert_with(|| {
+            format!(
+                "Renaming to `{new_name}` would shadow references by another definition{}",
+                in_message(frange),
+            )
+        });
+        conflicts
+            .extend(frange.map(|frange| (frange, format!("`{new_name}` is already defined here"))));
+    }
+
+    // Captured references.
+    if module[name.value].kind.is_definition() {
+        for (expr, kind) in module.exprs() {
+            if !matches!(kind, Expr::Reference(text) if text == new_name) {
+                continue;
+            }
+            let Some(scope) = scopes.scope_for_expr(expr) else {
+                continue;
+            };
+            let is_captured = scopes
+                .ancestors(scope)
+                .filter_map(|data| data.as_definitions())
+                .find_map(|defs| {
+                    if defs.contains_key(new_name) {
+                        Some(false)
+                    } else if defs.get(old_name) == Some(&name.value) {
+                        Some(true)
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or(false);
+            if !is_captured {
+                continue;
+            }
+            let frange = range_for_expr(expr);
+            message.get_or_insert_with(|| {
+                format!(
+                    "Renaming to `{new_name}` would capture the reference{}",
+                    in_message(frange),
+                )
+            });
+            conflicts.extend(frange.map(|frange| {
+                (
+                    frange,
+                    "This reference would refer to the renamed definition".to_owned(),
+                )
+            }));
+        }
+    }
+
+    match message {
+        None => Ok(()),
+        Some(message) => Err(RenameError { message, conflicts }),
+    }
 }
 
-fn find_name(
+/// All names defined in the same bindings or lambda as `name`, including itself.
+fn sibling_names(module: &Module, name: NameId) -> Vec<NameId> {
+    module
+        .exprs()
+        .find_map(|(_, kind)| {
+            let names = match kind {
+                Expr::Lambda(param, pat, _) => param
+                    .iter()
+                    .copied()
+                    .chain(
+                        pat.iter()
+                            .flat_map(|pat| pat.fields.iter().filter_map(|f| f.0)),
+                    )
+                    .collect::<Vec<_>>(),
+                Expr::LetIn(bindings, _)
+                | Expr::Attrset(bindings)
+                | Expr::RecAttrset(bindings)
+                | Expr::LetAttrset(bindings) => {
+                    bindings.statics.iter().map(|&(name, _)| name).collect()
+                }
+                _ => return None,
+            };
+            names.contains(&name).then_some(names)
+        })
+        .unwrap_or_default()
+}
+
+/// Display the position as `path:line` for messages.
+pub(crate) fn display_pos<DB: DefDatabase + ?Sized>(
+    db: &DB,
+    file: FileId,
+    pos: TextSize,
+) -> String {
+    let line = db.file_content(file)[..usize::from(pos)]
+        .matches('\n')
+        .count()
+        + 1;
+    let path = db
+        .source_root(db.file_source_root(file))
+        .path_for_file(file)
+        .clone();
+    format!("{}:{line}", path.display())
+}
+
+/// Find argument expressions of call sites of the lambda with pattern field `name`.
+pub(crate) fn call_site_args(db: &dyn DefDatabase, name: InFile<NameId>) -> Vec<InFile<ExprId>> {
+    let module = db.module(name.file_id);
+    let lambda = module.exprs().find_map(|(expr, kind)| match kind {
+        Expr::Lambda(_, Some(pat), _)
+            if pat
+                .fields
+                .iter()
+                .any(|&(field, _)| field == Some(name.value)) =>
+        {
+            Some(expr)
+        }
+        _ => None,
+    });
+    match lambda {
+        Some(lambda) => lambda_call_site_args(db, InFile::new(name.file_id, lambda)),
+        None => Vec::new(),
+    }
+}
+
+/// Find argument expressions of call sites of `lambda`.
+/// Recognized calls are `f { }` with `f` defined by a local binding, and
+/// `import ./file.nix { }` or `callPackage ./file.nix { }` if the lambda is the whole file.
+pub(crate) fn lambda_call_site_args(
+    db: &dyn DefDatabase,
+    InFile {
+        file_id,
+        value: lambda,
+    }: InFile<ExprId>,
+) -> Vec<InFile<ExprId>> {
+    let module = db.module(file_id);
+    let mut args = Vec::new();
+
+    // Local calls via bindings, eg. `let f = { a }: a; in f { a = 1; }`.
+    let func_names = module
+        .exprs()
+        .filter_map(|(_, kind)| match kind {
+            Expr::LetIn(bindings, _)
+            | Expr::Attrset(bindings)
+            | Expr::RecAttrset(bindings)
+            | Expr::LetAttrset(bindings) => Some(bindings),
+            _ => None,
+        })
+        .flat_map(|bindings| bindings.statics.iter())
+        .filter(|&&(_, value)| value == BindingValue::Expr(lambda))
+        .map(|&(name, _)| name)
+        .collect::<HashSet<_>>();
+    if !func_names.is_empty() {
+        let nameres = db.name_resolution(file_id);
+        args.extend(module.exprs().filter_map(|(_, kind)| match *kind {
+            Expr::Apply(func, arg) => match nameres.get(func) {
+                Some(ResolveResult::Definition(func_name)) if func_names.contains(func_name) => {
+                    Some(InFile::new(file_id, arg))
+                }
+                _ => None,
+            },
+            _ => None,
+        }));
+    }
+
+    // Calls of the whole file from other files.
+    if module.entry_expr() == lambda {
+        for file in db.module_referrers(file_id).iter().copied() {
+            let module = db.module(file);
+            let nameres = db.name_resolution(file);
+            args.extend(module.exprs().filter_map(|(_, kind)| {
+                let &Expr::Apply(func, arg) = kind else {
+                    return None;
+                };
+                let &Expr::Apply(func, path_expr) = &module[func] else {
+                    return None;
+                };
+                if nameres.check_builtin(func, &module) != Some("import")
+                    && !is_call_package(db, &module, func)
+                {
+                    return None;
+                }
+                let &Expr::Literal(Literal::Path(path)) = &module[path_expr] else {
+                    return None;
+                };
+                (db.resolve_path_file(path) == Some(file_id)).then(|| InFile::new(file, arg))
+            }));
+        }
+    }
+
+    args
+}
+
+/// Rename the field for pattern field `name` in the argument `arg` of a call site.
+fn rename_call_site_arg(
+    db: &dyn DefDatabase,
+    arg: InFile<ExprId>,
+    name: InFile<NameId>,
+    new_name: &str,
+) -> RenameResult<Vec<TextEdit>> {
+    let module = db.module(arg.file_id);
+    let old_name = &db.module(name.file_id)[name.value].text;
+    let cannot_rewrite = |reason: &str| -> RenameError {
+        let pos = db
+            .source_map(arg.file_id)
+            .node_for_expr(arg.value)
+            .map_or(0.into(), |ptr| ptr.text_range().start());
+        format!(
+            "Cannot rename the argument of the call at {}, since {reason}",
+            display_pos(db, arg.file_id, pos),
+        )
+        .into()
+    };
+    let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) = &module[arg.value] else {
+        return Err(cannot_rewrite("it is not an attrset literal"));
+    };
+    match bindings
+        .statics
+        .iter()
+        .find(|&&(field, _)| module[field].text == *old_name)
+    {
+        Some(&(field, _)) => rename_in_file(db, InFile::new(arg.file_id, field), new_name),
+        // The field may be passed dynamically.
+        None if !bindings.dynamics.is_empty() => {
+            Err(cannot_rewrite("it contains dynamic attributes"))
+        }
+        // `callPackage` would no longer find the renamed field in the package set.
+        None if db
+            .call_package_sites(arg.file_id)
+            .iter()
+            .any(|site| site.arg_expr == arg.value) =>
+        {
+            Err(cannot_rewrite(&format!(
+                "`{old_name}` is provided automatically by callPackage"
+            )))
+        }
+        // The field is not passed, and is defaulted.
+        None => Ok(Vec::new()),
+    }
+}
+
+/// The file itself and files importing it, directly or indirectly via at most
+/// `MAX_IMPORT_DEPTH` levels of imports, whose types may contain fields from it.
+fn importing_files(db: &dyn DefDatabase, file: FileId) -> Vec<FileId> {
+    let mut files = vec![file];
+    let mut visited = HashSet::from([file]);
+    let mut level = vec![file];
+    for _ in 0..MAX_IMPORT_DEPTH {
+        level = level
+            .into_iter()
+            .flat_map(|file| db.module_referrers(file).into_vec())
+            .filter(|&file| visited.insert(file))
+            .collect();
+        files.extend(&level);
+    }
+    files
+}
+
+/// Rename attributes in `set.a.b`, `set ? a.b` and `inherit (set) a;` of `file`, whose source
+/// is `name` by inference.
+fn rename_selections(
+    db: &dyn TyDatabase,
+    file: FileId,
+    name: InFile<NameId>,
+    new_attr: &str,
+) -> Vec<TextEdit> {
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let infer = db.infer(file);
+    let expect_src = if name.file_id == file {
+        AttrSource::Name(name.value)
+    } else {
+        AttrSource::Imported(name)
+    };
+
+    let mut edits = Vec::new();
+    for (_, expr) in module.exprs() {
+        let (Expr::Select(set_expr, path, _) | Expr::HasAttr(set_expr, path)) = expr else {
+            continue;
+        };
+        let mut set_ty = infer.ty_for_expr(*set_expr);
+        for &attr in path.iter() {
+            let Expr::Literal(Literal::String(field)) = &module[attr] else {
+                break;
+            };
+            let Some(set) = set_ty.as_attrset() else {
+                break;
+            };
+            if set.get_src(field) == Some(expect_src) {
+                if let Some(ptr) = source_map.node_for_expr(attr) {
+                    edits.push(TextEdit {
+                        delete: ptr.text_range(),
+                        insert: new_attr.into(),
+                    });
+                }
+            }
+            let Some(field_ty) = set.get(field) else {
+                break;
+            };
+            set_ty = field_ty.clone();
+        }
+    }
+
+    // The local name is kept.
+    // `inherit (set) old;` => `old = (set).new;`
+    let parse = db.parse(file);
+    let src = db.file_content(file);
+    for (_, expr) in module.exprs() {
+        let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings) | Expr::LetIn(bindings, _)) =
+            expr
+        else {
+            continue;
+        };
+        for &(local_name, value) in bindings.statics.iter() {
+            let BindingValue::InheritFrom(i) = value else {
+                continue;
+            };
+            let set_ty = infer.ty_for_expr(bindings.inherit_froms[i]);
+            let text = &module[local_name].text;
+            if set_ty.as_attrset().and_then(|set| set.get_src(text)) != Some(expect_src) {
+                continue;
+            }
+            for ptr in source_map.nodes_for_name(local_name) {
+                let attr_node = ptr.to_node(&parse.syntax_node());
+                let Some(i) = attr_node.parent().and_then(ast::Inherit::cast) else {
+                    continue;
+                };
+                let Some(from_expr) = i.from_expr() else {
+                    continue;
+                };
+                let binding = format!(
+                    "{} = {}.{new_attr};",
+                    escape_literal_attr(text),
+                    // This is already parenthesized.
+                    &src[from_expr.syntax().text_range()],
+                );
+                // Replace the whole Inherit if it is the only Attr.
+                if i.attrs().count() == 1 {
+                    edits.push(TextEdit {
+                        delete: i.syntax().text_range(),
+                        insert: binding.into(),
+                    });
+                    continue;
+                }
+                // Also delete the space before the Attr, so no dangling space is left before `;`.
+                let start = match attr_node.prev_sibling_or_token() {
+                    Some(prev) if prev.kind().is_space() => prev.text_range().start(),
+                    _ => attr_node.text_range().start(),
+                };
+                edits.push(TextEdit {
+                    delete: TextRange::new(start, attr_node.text_range().end()),
+                    insert: "".into(),
+                });
+                edits.push(TextEdit {
+                    delete: TextRange::empty(i.syntax().text_range().end()),
+                    insert: format!(" {binding}").into(),
+                });
+            }
+        }
+    }
+    edits
+}
+
+/// Find the name to rename at `fpos`, either a local name, or the source of an attribute in
+/// `set.attr` which may be defined in another file.
+fn find_rename_target(db: &impl TyDatabase, fpos: FilePos) -> Option<(TextRange, InFile<NameId>)> {
+    if let Some((range, name)) = find_name(db, fpos) {
+        return Some((range, InFile::new(fpos.file_id, name)));
+    }
+    let parse = db.parse(fpos.file_id);
+    let tok = best_token_at_offset(&parse.syntax_node(), fpos.pos)?;
+    let attr_node = tok.parent_ancestors().find_map(ast::Attr::cast)?;
+    let name = select_attr_source(db, fpos.file_id, tok)?;
+    Some((attr_node.syntax().text_range(), name))
+}
+
+pub(crate) fn find_name(
     db: &dyn DefDatabase,
     FilePos { file_id, pos }: FilePos,
 ) -> Option<(TextRange, NameId)> {
@@ -230,7 +765,7 @@ mod tests {
                     format!("{src}\n{text}\n")
                 }
             }
-            Err(err) => err,
+            Err(err) => err.message,
         };
         expect.assert_eq(&ret);
     }
@@ -247,11 +782,36 @@ mod tests {
                 }
                 src
             }
-            Err(err) => err,
+            Err(err) => err.message,
         };
         expect.assert_eq(&ret);
     }
 
+    #[track_caller]
+    fn check_files(fixture: &str, new_name: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let ws_edit = match super::rename(&db, f[0], new_name) {
+            Ok(ws_edit) => ws_edit,
+            Err(err) => return expect.assert_eq(&err.message),
+        };
+        let mut got = String::new();
+        for &file in f.files() {
+            let Some(edits) = ws_edit.content_edits.get(&file) else {
+                continue;
+            };
+            let mut src = db.file_content(file).to_string();
+            for edit in edits.iter().rev() {
+                edit.apply(&mut src);
+            }
+            let path = db
+                .source_root(db.file_source_root(file))
+                .path_for_file(file)
+                .clone();
+            got += &format!("#- {}\n{}\n", path.display(), src.trim());
+        }
+        expect.assert_eq(&got);
+    }
+
     #[test]
     fn prepare_ident() {
         check_prepare("let $0a = a; in a", expect!["let <a> = a; in a"]);
@@ -503,4 +1063,250 @@ mod tests {
             expect![[r#"let b = 1; in { "1" = b; }"#]],
         );
     }
+
+    #[test]
+    fn rename_pat_field_call_sites() {
+        check(
+            "let f = { $0a, b ? 1 }: a; in f { a = 1; } + f { b = 2; a = 3; }",
+            "x",
+            expect!["let f = { x, b ? 1 }: x; in f { x = 1; } + f { b = 2; x = 3; }"],
+        );
+        check(
+            "let f = { $0a }: a; a = 1; in f { inherit a; }",
+            "x",
+            expect!["let f = { x }: x; a = 1; in f { x = a; }"],
+        );
+        check_files(
+            "
+#- /pkg.nix
+{ stdenv, $0fetchurl }: fetchurl
+#- /default.nix
+{ callPackage, fetchurl }: {
+  b = callPackage ./pkg.nix { fetchurl = 1; };
+  c = import ./pkg.nix { stdenv = 1; inherit fetchurl; };
+}
+            ",
+            "fetch",
+            expect![[r#"
+                #- /pkg.nix
+                { stdenv, fetch }: fetch
+                #- /default.nix
+                { callPackage, fetchurl }: {
+                  b = callPackage ./pkg.nix { fetch = 1; };
+                  c = import ./pkg.nix { stdenv = 1; fetch = fetchurl; };
+                }
+            "#]],
+        );
+    }
+
+    #[test]
+    fn rename_pat_field_unsafe_call_sites() {
+        check(
+            "let f = { $0a }: a; args = { a = 1; }; in f args",
+            "x",
+            expect!["Cannot rename the argument of the call at /default.nix:1, since it is not an attrset literal"],
+        );
+        check(
+            "let f = { $0a }: a; k = \"a\"; in f\n  { ${k} = 1; }",
+            "x",
+            expect!["Cannot rename the argument of the call at /default.nix:2, since it contains dynamic attributes"],
+        );
+        check_files(
+            "
+#- /pkg.nix
+{ stdenv, $0fetchurl }: fetchurl
+#- /default.nix
+{ callPackage }: callPackage ./pkg.nix { }
+            ",
+            "fetch",
+            expect!["Cannot rename the argument of the call at /default.nix:1, since `fetchurl` is provided automatically by callPackage"],
+        );
+    }
+
+    #[test]
+    fn rename_shadowed() {
+        check(
+            "let $0a = 1; in let b = 2; in a",
+            "b",
+            expect![
+                "Renaming to `b` would shadow references by another definition at /default.nix:1"
+            ],
+        );
+        check(
+            "{ $0a }: { b ? 1 }: { inherit a; c = b; }",
+            "b",
+            expect![
+                "Renaming to `b` would shadow references by another definition at /default.nix:1"
+            ],
+        );
+        // Unrelated scopes.
+        check(
+            "let $0a = 1; in [ a (let b = 2; in b) ]",
+            "b",
+            expect!["let b = 1; in [ b (let b = 2; in b) ]"],
+        );
+
+        let (db, f) = TestDB::from_fixture("let $0a = 1; in rec { b = 2; c = a; d = a; }").unwrap();
+        let src = db.file_content(f[0].file_id);
+        let err = super::rename(&db, f[0], "b").unwrap_err();
+        let conflicts = err
+            .conflicts
+            .iter()
+            .map(|(frange, msg)| format!("{}: {msg}\n", &src[frange.range]))
+            .collect::<String>();
+        expect![[r#"
+            a: This reference would refer to another `b`
+            a: This reference would refer to another `b`
+            b: `b` is already defined here
+        "#]]
+        .assert_eq(&conflicts);
+    }
+
+    #[test]
+    fn rename_duplicated() {
+        check(
+            "let $0a = 1; b = 2; in a",
+            "b",
+            expect!["`b` is already defined at /default.nix:1"],
+        );
+        check(
+            "{ $0a, b }: a",
+            "b",
+            expect!["`b` is already defined at /default.nix:1"],
+        );
+        check(
+            "{ a }@$0b: b",
+            "a",
+            expect!["`a` is already defined at /default.nix:1"],
+        );
+        check(
+            "{ $0a = 1; b = 2; }",
+            "b",
+            expect!["`b` is already defined at /default.nix:1"],
+        );
+        check(
+            "let a = 1; in { inherit $0a; b = 2; }",
+            "b",
+            expect!["`b` is already defined at /default.nix:1"],
+        );
+        check(
+            "{ x.$0a = 1; x.b = 2; }",
+            "b",
+            expect!["`b` is already defined at /default.nix:1"],
+        );
+    }
+
+    #[test]
+    fn rename_captured() {
+        check(
+            "let b = 1; in let $0a = 2; in b",
+            "b",
+            expect!["Renaming to `b` would capture the reference at /default.nix:1"],
+        );
+        check(
+            "with { b = 1; }; let $0a = 2; in b",
+            "b",
+            expect!["Renaming to `b` would capture the reference at /default.nix:1"],
+        );
+        check(
+            "let $0a = 1; in toString a",
+            "toString",
+            expect!["Renaming to `toString` would capture the reference at /default.nix:1"],
+        );
+        check(
+            "let $0a = 1; in rec { inherit a; b = 3; }",
+            "b",
+            expect![
+                "Renaming to `b` would shadow references by another definition at /default.nix:1"
+            ],
+        );
+        // Not captured.
+        check(
+            "let b = 1; in [ b (let $0a = 2; in a) ]",
+            "b",
+            expect!["let b = 1; in [ b (let b = 2; in b) ]"],
+        );
+        check(
+            "let $0a = 1; in { inherit a; b = 3; }",
+            "b",
+            expect!["let b = 1; in { a = b; b = 3; }"],
+        );
+        check(
+            "let $0a = 1; b = 2; in rec { inherit a; b = 3; }",
+            "c",
+            expect!["let c = 1; b = 2; in rec { a = c; b = 3; }"],
+        );
+    }
+
+    #[test]
+    fn rename_local_selection() {
+        check(
+            "let s = { $0a = 1; }; in s.a + (if s ? a then 1 else 0)",
+            "b",
+            expect!["let s = { b = 1; }; in s.b + (if s ? b then 1 else 0)"],
+        );
+    }
+
+    #[test]
+    fn rename_imported() {
+        check_files(
+            "
+#- /lib.nix
+{ $0foo = 1; bar = 2; }
+#- /default.nix
+let lib = import ./lib.nix; in lib.foo + lib.bar
+#- /other.nix
+{ callPackage }: (callPackage ./pkg.nix { }).lib.foo
+#- /pkg.nix
+{ }: { lib = import ./lib.nix; }
+            ",
+            "baz",
+            expect![[r#"
+                #- /lib.nix
+                { baz = 1; bar = 2; }
+                #- /default.nix
+                let lib = import ./lib.nix; in lib.baz + lib.bar
+                #- /other.nix
+                { callPackage }: (callPackage ./pkg.nix { }).lib.baz
+            "#]],
+        );
+    }
+
+    #[test]
+    fn rename_inherited_from_import() {
+        check_files(
+            "
+#- /lib.nix
+{ $0foo = 1; bar = 2; }
+#- /default.nix
+let lib = import ./lib.nix; inherit (lib) foo; in { inherit (lib) bar foo; x = foo; }
+            ",
+            "baz",
+            expect![[r#"
+                #- /lib.nix
+                { baz = 1; bar = 2; }
+                #- /default.nix
+                let lib = import ./lib.nix; foo = (lib).baz; in { inherit (lib) bar; foo = (lib).baz; x = foo; }
+            "#]],
+        );
+    }
+
+    #[test]
+    fn rename_from_selection() {
+        check_files(
+            "
+#- /default.nix
+(import ./lib.nix).$0foo
+#- /lib.nix
+{ foo = 1; }
+            ",
+            "bar",
+            expect![[r#"
+                #- /default.nix
+                (import ./lib.nix).bar
+                #- /lib.nix
+                { bar = 1; }
+            "#]],
+        );
+    }
 }
@@ -0,0 +1,266 @@
+//! The expression at a selection to be evaluated standalone, like typing into `nix repl`,
+//! wrapped with bindings of surrounding `let` and `rec` scopes it depends on.
+//!
+//! Names bound by lambdas or `with` are only known when evaluating the whole file, thus
+//! expressions referencing them are rejected.
+use std::collections::{BTreeMap, HashSet};
+
+use syntax::ast::{self, AstNode};
+use syntax::{best_token_at_offset, SyntaxNode, TextRange, TextSize};
+
+use crate::def::{AstPtr, Expr, NameKind, ResolveResult};
+use crate::{DefDatabase, FileRange};
+
+pub(crate) fn repl_expr(db: &dyn DefDatabase, frange: FileRange) -> Result<String, String> {
+    let file = frange.file_id;
+    let parse = db.parse(file);
+    let root = parse.syntax_node();
+    let module = db.module(file);
+    let source_map = db.source_map(file);
+    let name_res = db.name_resolution(file);
+
+    let node = selected_node(&root, db.file_content(file).as_ref(), frange.range)
+        .ok_or("No expression is selected")?;
+    let range = node.text_range();
+    if parse
+        .errors()
+        .iter()
+        .any(|err| range.contains_range(err.range))
+    {
+        return Err("The selected expression has syntax errors".into());
+    }
+
+    // Bindings to copy, keyed by their scopes and their positions.
+    let mut scopes = BTreeMap::<TextSize, (TextRange, BTreeMap<TextSize, String>)>::new();
+    let mut visited = HashSet::new();
+    // Nodes whose references are to be resolved, with ranges of the copied code containing them.
+    // Definitions inside copied code need no bindings.
+    let mut stack = vec![(node.clone(), node.text_range())];
+    while let Some((chunk, chunk_range)) = stack.pop() {
+        for n in chunk.descendants() {
+            let Some(expr) = source_map.expr_for_node(AstPtr::new(&n)) else {
+                continue;
+            };
+            let Expr::Reference(text) = &module[expr] else {
+                continue;
+            };
+            let name = match name_res.get(expr) {
+                Some(&ResolveResult::Definition(name)) => name,
+                Some(ResolveResult::WithExprs(_)) => {
+                    return Err(format!(
+                        "`{text}` comes from `with`, which is unknown without evaluating the file"
+                    ));
+                }
+                Some(ResolveResult::Builtin(_)) | None => continue,
+            };
+            let mut ptrs = source_map.nodes_for_name(name).peekable();
+            if ptrs
+                .peek()
+                .map_or(true, |ptr| chunk_range.contains_range(ptr.text_range()))
+                || !visited.insert(name)
+            {
+                continue;
+            }
+            if !matches!(module[name].kind, NameKind::LetIn | NameKind::RecAttrset) {
+                return Err(format!(
+                    "`{text}` is a lambda parameter, which is unknown without calling the lambda"
+                ));
+            }
+
+            for ptr in ptrs {
+                let attr = ptr.to_node(&root);
+                let Some(parent) = attr.parent() else {
+                    continue;
+                };
+                let (binding, code, deps) = if let Some(inherit) =
+                    ast::Inherit::cast(parent.clone())
+                {
+                    match inherit.from_expr() {
+                        // `inherit (from) attr;`
+                        Some(from) => (
+                            inherit.syntax().clone(),
+                            format!("inherit {} {};", from.syntax(), attr),
+                            from.syntax().clone(),
+                        ),
+                        // `inherit attr;`
+                        None => (
+                            inherit.syntax().clone(),
+                            format!("inherit {attr};"),
+                            attr.clone(),
+                        ),
+                    }
+                } else {
+                    // `attr.path = value;`
+                    let Some(binding) = parent.parent().and_then(ast::AttrpathValue::cast) else {
+                        continue;
+                    };
+                    let binding = binding.syntax().clone();
+                    (binding.clone(), binding.to_string(), binding)
+                };
+                let Some(scope) = binding.parent() else {
+                    continue;
+                };
+                let scope_range = scope.text_range();
+                scopes
+                    .entry(scope_range.start())
+                    .or_insert_with(|| (scope_range, BTreeMap::new()))
+                    .1
+                    .insert(attr.text_range().start(), code);
+                let deps_range = deps.text_range();
+                stack.push((deps, deps_range));
+            }
+        }
+    }
+
+    // Outer scopes come first, since all scopes contain the selection.
+    let mut ret = String::new();
+    for (_, (_, bindings)) in scopes {
+        ret += "let\n";
+        for code in bindings.values() {
+            ret += code;
+            ret += "\n";
+        }
+        ret += "in\n";
+    }
+    ret += &node.to_string();
+    Ok(ret)
+}
+
+/// The innermost expression covering the selection, ignoring surrounding spaces, or at the
+/// cursor, if the selection is empty.
+fn selected_node(root: &SyntaxNode, src: &str, range: TextRange) -> Option<SyntaxNode> {
+    let text = &src[range];
+    let start = range.start() + TextSize::of(&text[..text.len() - text.trim_start().len()]);
+    let end = range.end() - TextSize::of(&text[text.trim_end().len()..]);
+    let elem = if start >= end {
+        best_token_at_offset(root, range.start())?.into()
+    } else {
+        root.covering_element(TextRange::new(start, end))
+    };
+    elem.ancestors()
+        .find_map(ast::Expr::cast)
+        .map(|expr| expr.syntax().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use expect_test::{expect, Expect};
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let frange = f.unwrap_single_range_marker();
+        let got = super::repl_expr(&db, frange).unwrap_or_else(|err| format!("Error: {err}"));
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn standalone() {
+        check(
+            "1 + $0builtins.length [ ]$1",
+            expect!["builtins.length [ ]"],
+        );
+        check("{ a = $0\"foo\"; }", expect![[r#""foo""#]]);
+        check("x: $0let y = 1; in y$1", expect!["let y = 1; in y"]);
+    }
+
+    #[test]
+    fn let_scope() {
+        check(
+            "
+let
+  a = 1;
+  b = a + 1;
+  c = throw \"unused\";
+in
+$0b * 2$1
+            ",
+            expect![[r#"
+                let
+                a = 1;
+                b = a + 1;
+                in
+                b * 2"#]],
+        );
+        // Nested and shadowed.
+        check(
+            "let a = 1; b = a; in let a = 2; c = 0; in [ $0(a + b)$1 ]",
+            expect![[r#"
+                let
+                a = 1;
+                b = a;
+                in
+                let
+                a = 2;
+                in
+                (a + b)"#]],
+        );
+        // Only bindings in scope.
+        check(
+            "let a = let b = 1; in b; in $0a",
+            expect![[r#"
+                let
+                a = let b = 1; in b;
+                in
+                a"#]],
+        );
+    }
+
+    #[test]
+    fn inherit() {
+        check(
+            "let s = { x = 1; y = 2; }; inherit (s) x y; in $0x",
+            expect![[r#"
+                let
+                s = { x = 1; y = 2; };
+                inherit (s) x;
+                in
+                x"#]],
+        );
+        check(
+            "let a = 1; in let inherit a; in $0a",
+            expect![[r#"
+                let
+                a = 1;
+                in
+                let
+                inherit a;
+                in
+                a"#]],
+        );
+    }
+
+    #[test]
+    fn rec_attrset() {
+        check(
+            "rec { a.b = 1; a.c = 2; d = $0a$1; }",
+            expect![[r#"
+                let
+                a.b = 1;
+                a.c = 2;
+                in
+                a"#]],
+        );
+    }
+
+    #[test]
+    fn unknown() {
+        check(
+            "{ pkgs }: let a = pkgs.hello; in $0a",
+            expect![
+                "Error: `pkgs` is a lambda parameter, which is unknown without calling the lambda"
+            ],
+        );
+        check(
+            "with import <nixpkgs> { }; $0hello",
+            expect![
+                "Error: `hello` comes from `with`, which is unknown without evaluating the file"
+            ],
+        );
+        check(
+            "$0(1 +)",
+            expect!["Error: The selected expression has syntax errors"],
+        );
+    }
+}
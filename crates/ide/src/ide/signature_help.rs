@@ -0,0 +1,280 @@
+use super::completion::{LIB_NAME, TY_SIGNATURE_DISPLAY};
+use super::hover::hover;
+use crate::def::AstPtr;
+use crate::ty::Ty;
+use crate::{FilePos, TyDatabase};
+use syntax::ast::{self, AstNode};
+use syntax::semantic::AttrKind;
+use syntax::{SyntaxKind, TextRange, TextSize};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureHelp {
+    /// The called function followed by its type, like `map: (? → ?) → [?] → [?]`.
+    pub label: String,
+    /// Ranges of parameter types in `label`.
+    pub params: Vec<TextRange>,
+    /// The parameter which the argument at the cursor is passed to, if any.
+    pub active_param: Option<usize>,
+    /// The hover text of the function, in Markdown.
+    pub documentation: Option<String>,
+}
+
+/// The signature of the function applied at `fpos`, like `map f |`, or of the function
+/// just before the cursor, like `map|` after its completion is accepted.
+pub(crate) fn signature_help(
+    db: &impl TyDatabase,
+    FilePos { file_id, pos }: FilePos,
+) -> Option<SignatureHelp> {
+    let parse = db.parse(file_id);
+    let mut tok = parse.syntax_node().token_at_offset(pos).left_biased()?;
+    while matches!(tok.kind(), SyntaxKind::SPACE | SyntaxKind::COMMENT) {
+        tok = tok.prev_token()?;
+    }
+
+    // A function not applied yet, like `(f |)`. Otherwise, the outermost application of the
+    // innermost application chain containing the cursor.
+    let expr = tok.parent_ancestors().find_map(ast::Expr::cast)?;
+    let is_applied = expr
+        .syntax()
+        .parent()
+        .map_or(false, |node| ast::Apply::can_cast(node.kind()));
+    let (head, args) = match tok.parent_ancestors().find_map(ast::Apply::cast) {
+        _ if !is_applied && matches!(expr, ast::Expr::Ref(_) | ast::Expr::Select(_)) => {
+            (expr, Vec::new())
+        }
+        None => return None,
+        Some(mut apply) => {
+            while let Some(parent) = apply.syntax().parent().and_then(ast::Apply::cast) {
+                if parent.function()?.syntax() != apply.syntax() {
+                    break;
+                }
+                apply = parent;
+            }
+            let mut args = Vec::new();
+            let mut head = ast::Expr::Apply(apply);
+            while let ast::Expr::Apply(apply) = &head {
+                args.push(apply.argument()?.syntax().text_range());
+                head = apply.function()?;
+            }
+            (head, args)
+        }
+    };
+    // Nodes may contain trailing spaces before the next argument.
+    let mut last_tok = head.syntax().last_token()?;
+    while matches!(last_tok.kind(), SyntaxKind::SPACE | SyntaxKind::COMMENT) {
+        last_tok = last_tok.prev_token()?;
+    }
+    let head_range = TextRange::new(
+        head.syntax().text_range().start(),
+        last_tok.text_range().end(),
+    );
+    let active = args.iter().filter(|range| range.end() < pos).count();
+
+    let src = db.file_content(file_id);
+    let sig = lib_signature(db, &head).or_else(|| {
+        let expr = db
+            .source_map(file_id)
+            .expr_for_node(AstPtr::new(head.syntax()))?;
+        let ty = db.infer(file_id).ty_for_expr(expr);
+        matches!(ty, Ty::Lambda(..)).then(|| ty.display_with(TY_SIGNATURE_DISPLAY).to_string())
+    })?;
+
+    let mut label = format!("{}: ", &src[head_range]);
+    let offset = TextSize::of(&*label);
+    label.push_str(&sig);
+    let params = split_params(&sig)
+        .into_iter()
+        .map(|range| range + offset)
+        .collect::<Vec<_>>();
+    if params.is_empty() {
+        return None;
+    }
+    let active_param = (active < params.len()).then_some(active);
+
+    // On the last token of the function, which is the name for references and selections.
+    let hover_pos = last_tok.text_range().start();
+    let documentation = hover(db, FilePos::new(file_id, hover_pos)).map(|ret| ret.markup);
+
+    Some(SignatureHelp {
+        label,
+        params,
+        active_param,
+        documentation,
+    })
+}
+
+/// The documented signature of `lib.name` and `lib.module.name`.
+fn lib_signature(db: &impl TyDatabase, head: &ast::Expr) -> Option<String> {
+    let ast::Expr::Select(select) = head else {
+        return None;
+    };
+    if !matches!(select.set()?, ast::Expr::Ref(name) if name.token()?.text() == LIB_NAME) {
+        return None;
+    }
+    let path = select
+        .attrpath()?
+        .attrs()
+        .map(|attr| match AttrKind::of(attr) {
+            AttrKind::Static(Some(field)) => Some(field),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let lib_docs = db.lib_docs();
+    let doc = match &path[..] {
+        [name] => lib_docs.get(None, name),
+        [module, name] => lib_docs.get(Some(module), name),
+        _ => None,
+    }?;
+    doc.signature.clone()
+}
+
+/// Ranges of parameters in the signature `sig`, which are separated by top-level arrows `→`
+/// or `->`. The last part is the result.
+fn split_params(sig: &str) -> Vec<TextRange> {
+    let mut params = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut iter = sig.char_indices().peekable();
+    while let Some((i, c)) = iter.next() {
+        let arrow_len = match c {
+            '(' | '[' | '{' => {
+                depth += 1;
+                continue;
+            }
+            ')' | ']' | '}' => {
+                depth = depth.saturating_sub(1);
+                continue;
+            }
+            '→' => c.len_utf8(),
+            '-' if matches!(iter.peek(), Some((_, '>'))) => {
+                iter.next();
+                2
+            }
+            _ => continue,
+        };
+        if depth == 0 {
+            let param = &sig[start..i];
+            let trimmed = param.trim();
+            let param_start = start + (param.len() - param.trim_start().len());
+            params.push(TextRange::at(
+                TextSize::from(param_start as u32),
+                TextSize::of(trimmed),
+            ));
+            start = i + arrow_len;
+        }
+    }
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TestDB;
+    use crate::SourceDatabase;
+    use expect_test::{expect, Expect};
+    use nix_interop::lib_docs::LibDocs;
+    use std::sync::Arc;
+
+    #[track_caller]
+    fn check(fixture: &str, expect: Expect) {
+        let (mut db, f) = TestDB::from_fixture(fixture).unwrap();
+        db.set_lib_docs(Arc::new(LibDocs::from_sources([
+            (
+                "lists",
+                "{ lib }: {
+                /**
+                  Left fold.
+
+                  # Type
+
+                  ```
+                  foldl' :: (b -> a -> b) -> b -> [a] -> b
+                  ```
+                */
+                foldl' = op: nul: list: nul;
+            }",
+            ),
+            ("default", "self: { inherit (self.lists) foldl'; }"),
+        ])));
+        let Some(ret) = super::signature_help(&db, f[0]) else {
+            expect.assert_eq("None");
+            return;
+        };
+        let params = ret
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, &range)| {
+                let mark = if ret.active_param == Some(i) { "*" } else { "" };
+                format!("{mark}{}", &ret.label[range])
+            })
+            .collect::<Vec<_>>();
+        expect.assert_eq(&format!("{}\n{}", ret.label, params.join(", ")));
+    }
+
+    #[test]
+    fn local_lambda() {
+        check(
+            "let f = a: b: a + b + 1; in (f 1 $0)",
+            expect![[r#"
+                f: int → int → int
+                int, *int"#]],
+        );
+        check(
+            "let f = a: b: a + b + 1; in f$0",
+            expect![[r#"
+                f: int → int → int
+                *int, int"#]],
+        );
+        // Still the first argument.
+        check(
+            "let f = a: b: a + b + 1; in f 1$0",
+            expect![[r#"
+                f: int → int → int
+                *int, int"#]],
+        );
+        // All arguments are passed.
+        check(
+            "let f = a: b: a + b + 1; in (f 1 2 $0)",
+            expect![[r#"
+                f: int → int → int
+                int, int"#]],
+        );
+        check("let f = 1; in f$0", expect!["None"]);
+    }
+
+    #[test]
+    fn nested() {
+        check(
+            "let f = a: b: a + b + 1; g = s: s + \"\"; in f (g $0) 1",
+            expect![[r#"
+                g: string → string
+                *string"#]],
+        );
+        check(
+            "let f = a: b: a + b + 1; g = s: s + \"\"; in (f (g \"\") $0)",
+            expect![[r#"
+                f: int → int → int
+                int, *int"#]],
+        );
+    }
+
+    #[test]
+    fn builtin() {
+        check(
+            "(builtins.substring 0 $0)",
+            expect![[r#"
+                builtins.substring: int → int → string → string
+                int, *int, string"#]],
+        );
+    }
+
+    #[test]
+    fn lib_doc() {
+        check(
+            "{ lib }: (lib.foldl' (acc: x: acc) $0)",
+            expect![[r#"
+                lib.foldl': (b -> a -> b) -> b -> [a] -> b
+                (b -> a -> b), *b, [a]"#]],
+        );
+    }
+}
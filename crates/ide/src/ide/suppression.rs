@@ -0,0 +1,140 @@
+//! Inline suppression comments of diagnostics.
+//!
+//! - `# nil:ignore <codes>` suppresses diagnostics starting on the same line, or on the next
+//!   line if the comment is on its own line.
+//! - `# nil:ignore-file <codes>` suppresses diagnostics in the whole file.
+//!
+//! Codes are separated by spaces or commas. Each one is a diagnostic id like `W010`, a code
+//! like `unused_binding` (or `unused-binding`), a statix lint name like `useless_parens`, or a
+//! prefix of codes like `unused`.
+//! Without any code, all diagnostics are suppressed.
+use crate::Diagnostic;
+use syntax::{SyntaxKind, SyntaxNode, TextRange, TextSize};
+
+const LINE_DIRECTIVE: &str = "nil:ignore";
+const FILE_DIRECTIVE: &str = "nil:ignore-file";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Suppressions {
+    /// Suppressed codes and the affected range, or `None` for the whole file.
+    entries: Vec<(Option<TextRange>, Vec<String>)>,
+}
+
+/// The scope of a suppression comment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SuppressionScope {
+    Line,
+    File,
+}
+
+impl Suppressions {
+    pub(crate) fn collect(root: &SyntaxNode, src: &str) -> Self {
+        let entries = root
+            .descendants_with_tokens()
+            .filter_map(|elem| elem.into_token())
+            .filter(|tok| tok.kind() == SyntaxKind::COMMENT)
+            .filter_map(|tok| {
+                let (scope, codes) = parse_comment(tok.text())?;
+                let range = match scope {
+                    SuppressionScope::File => None,
+                    SuppressionScope::Line => {
+                        let start = tok.text_range().start();
+                        let line = line_range(src, start);
+                        let standalone = src[TextRange::new(line.start(), start)]
+                            .chars()
+                            .all(char::is_whitespace);
+                        Some(if standalone {
+                            line_range(src, line.end())
+                        } else {
+                            line
+                        })
+                    }
+                };
+                Some((range, codes))
+            })
+            .collect();
+        Self { entries }
+    }
+
+    pub(crate) fn is_suppressed(&self, diag: &Diagnostic) -> bool {
+        self.entries.iter().any(|(range, codes)| {
+            range.map_or(true, |range| range.contains(diag.range.start()))
+                && (codes.is_empty() || codes.iter().any(|code| suppresses(code, diag)))
+        })
+    }
+}
+
+/// Parse a suppression comment like `# nil:ignore unused_binding`.
+pub(crate) fn parse_comment(text: &str) -> Option<(SuppressionScope, Vec<String>)> {
+    let text = text.strip_prefix('#')?.trim_start();
+    let (scope, rest) = if let Some(rest) = text.strip_prefix(FILE_DIRECTIVE) {
+        (SuppressionScope::File, rest)
+    } else {
+        (SuppressionScope::Line, text.strip_prefix(LINE_DIRECTIVE)?)
+    };
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let codes = rest
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|code| !code.is_empty())
+        .map(|code| code.replace('-', "_"))
+        .collect();
+    Some((scope, codes))
+}
+
+/// The comment text to suppress `code` in `scope`.
+pub(crate) fn comment_text(scope: SuppressionScope, code: &str) -> String {
+    match scope {
+        SuppressionScope::Line => format!("# {LINE_DIRECTIVE} {code}"),
+        SuppressionScope::File => format!("# {FILE_DIRECTIVE} {code}"),
+    }
+}
+
+fn suppresses(code: &str, diag: &Diagnostic) -> bool {
+    let diag_code = diag.code();
+    code.eq_ignore_ascii_case(diag.id())
+        || diag_code == code
+        || diag.statix_name() == Some(code)
+        || diag_code
+            .strip_prefix(code)
+            .map_or(false, |rest| rest.starts_with('_'))
+}
+
+/// The range of the line containing `pos`, including the trailing newline.
+pub(crate) fn line_range(src: &str, pos: TextSize) -> TextRange {
+    let pos = usize::from(pos);
+    let start = src[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let end = src[pos..].find('\n').map_or(src.len(), |i| pos + i + 1);
+    TextRange::new(
+        TextSize::try_from(start).unwrap(),
+        TextSize::try_from(end).unwrap(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_comment, SuppressionScope};
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            parse_comment("# nil:ignore unused-binding, W011"),
+            Some((
+                SuppressionScope::Line,
+                vec!["unused_binding".into(), "W011".into()]
+            )),
+        );
+        assert_eq!(
+            parse_comment("#nil:ignore-file syntax"),
+            Some((SuppressionScope::File, vec!["syntax".into()])),
+        );
+        assert_eq!(
+            parse_comment("# nil:ignore"),
+            Some((SuppressionScope::Line, vec![])),
+        );
+        assert_eq!(parse_comment("# nil:ignored"), None);
+        assert_eq!(parse_comment("/* nil:ignore */"), None);
+        assert_eq!(parse_comment("# TODO"), None);
+    }
+}
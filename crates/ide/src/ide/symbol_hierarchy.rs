@@ -4,15 +4,29 @@ use smol_str::SmolStr;
 use syntax::ast::{self, AstNode};
 use syntax::{SyntaxNode, TextRange};
 
+/// The maximum length of default values of parameters in details.
+const MAX_DEFAULT_LEN: usize = 16;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SymbolTree {
     pub name: SmolStr,
+    /// Parameters of lambdas, like `{ name, port ? 80, ... }`.
+    pub detail: Option<String>,
     pub full_range: TextRange,
     pub focus_range: TextRange,
     pub kind: NameKind,
+    pub value_kind: SymbolValueKind,
     pub children: Vec<SymbolTree>,
 }
 
+/// The kind of the value bound to a symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolValueKind {
+    Lambda,
+    Attrset,
+    Other,
+}
+
 pub(crate) fn symbol_hierarchy(db: &dyn DefDatabase, file: FileId) -> Vec<SymbolTree> {
     let parse = db.parse(file);
     let module = db.module(file);
@@ -57,6 +71,19 @@ impl Collector<'_> {
                 }
             }
             (|| {
+                let value = match rhs {
+                    BindingValue::Expr(value) => Some(value),
+                    BindingValue::Inherit(_) | BindingValue::InheritFrom(_) => None,
+                };
+                let value_kind = match value.map(|value| &self.module[value]) {
+                    Some(Expr::Lambda(..)) => SymbolValueKind::Lambda,
+                    Some(Expr::Attrset(_) | Expr::RecAttrset(_)) => SymbolValueKind::Attrset,
+                    _ => SymbolValueKind::Other,
+                };
+                let detail = match value_kind {
+                    SymbolValueKind::Lambda => value.and_then(|value| self.lambda_detail(value)),
+                    _ => None,
+                };
                 let text = self.module[name].text.clone();
                 let kind = self.module[name].kind;
                 let name_node = self.source_map.nodes_for_name(name).next()?;
@@ -71,9 +98,11 @@ impl Collector<'_> {
                 sort_symbols(&mut children);
                 self.symbols.push(SymbolTree {
                     name: text,
+                    detail,
                     full_range,
                     focus_range,
                     kind,
+                    value_kind,
                     children,
                 });
                 Some(())
@@ -88,12 +117,94 @@ impl Collector<'_> {
             .chain(body)
             .for_each(|e| self.collect_expr(e));
     }
+
+    /// Parameters of curried lambdas, like `final: prev`.
+    fn lambda_detail(&self, lambda: ExprId) -> Option<String> {
+        let ptr = self.source_map.node_for_expr(lambda)?;
+        let mut lambda = ast::Lambda::cast(ptr.to_node(&self.root_node))?;
+        let mut params = vec![param_detail(&lambda.param()?)];
+        while let Some(ast::Expr::Lambda(inner)) = lambda.body().and_then(|e| e.flatten_paren()) {
+            params.push(param_detail(&inner.param()?));
+            lambda = inner;
+        }
+        Some(params.join(": "))
+    }
+}
+
+fn param_detail(param: &ast::Param) -> String {
+    let Some(pat) = param.pat() else {
+        return param.syntax().text().to_string();
+    };
+    let mut fields = pat
+        .fields()
+        .filter_map(|field| {
+            let name = field.name()?.syntax().text().to_string();
+            Some(match field.default_expr() {
+                Some(default) => {
+                    let text = default
+                        .syntax()
+                        .text()
+                        .to_string()
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    if text.len() <= MAX_DEFAULT_LEN {
+                        format!("{name} ? {text}")
+                    } else {
+                        format!("{name} ? …")
+                    }
+                }
+                None => name,
+            })
+        })
+        .collect::<Vec<_>>();
+    if pat.ellipsis_token().is_some() {
+        fields.push("...".into());
+    }
+    let pat_text = if fields.is_empty() {
+        "{ }".into()
+    } else {
+        format!("{{ {} }}", fields.join(", "))
+    };
+    let Some(name) = param.name() else {
+        return pat_text;
+    };
+    // Keep the order of `name @ { }` or `{ } @ name`.
+    if name.syntax().text_range().start() < pat.syntax().text_range().start() {
+        format!("{}@{pat_text}", name.syntax().text())
+    } else {
+        format!("{pat_text}@{}", name.syntax().text())
+    }
 }
 
 fn sort_symbols(syms: &mut [SymbolTree]) {
     syms.sort_by_key(|sym| sym.full_range.start());
 }
 
+/// Truncate symbol trees to at most `max_depth` levels and `max_count` symbols in total.
+/// Symbols are kept in breadth-first order, so shallower symbols are preferred.
+/// Returns whether any symbol is dropped.
+pub fn truncate_symbols(syms: &mut Vec<SymbolTree>, max_depth: usize, max_count: usize) -> bool {
+    let mut truncated = false;
+    let mut budget = max_count;
+    let mut depth = 0;
+    let mut level = vec![syms];
+    while !level.is_empty() {
+        depth += 1;
+        let mut next_level = Vec::new();
+        for list in level {
+            if depth > max_depth || list.len() > budget {
+                truncated |= !list.is_empty();
+                list.truncate(if depth > max_depth { 0 } else { budget });
+            }
+            budget -= list.len();
+            next_level.extend(list.iter_mut().map(|sym| &mut sym.children));
+        }
+        level = next_level;
+    }
+    truncated
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -112,28 +223,43 @@ mod tests {
 
     fn fmt_symbols(indent: usize, syms: &[SymbolTree], out: &mut String) {
         for sym in syms {
-            writeln!(
+            write!(
                 out,
-                "{:indent$}{}: {:?}",
+                "{:indent$}{}: {:?} {:?}",
                 "",
                 sym.name,
                 sym.kind,
+                sym.value_kind,
                 indent = indent
             )
             .unwrap();
+            match &sym.detail {
+                Some(detail) => writeln!(out, " ({detail})").unwrap(),
+                None => writeln!(out).unwrap(),
+            }
             fmt_symbols(indent + 4, &sym.children, out);
         }
     }
 
+    #[track_caller]
+    fn check_truncate(fixture: &str, max_depth: usize, max_count: usize, expect: Expect) {
+        let (db, file) = TestDB::single_file(fixture).unwrap();
+        let mut syms = symbol_hierarchy(&db, file);
+        let truncated = truncate_symbols(&mut syms, max_depth, max_count);
+        let mut got = format!("truncated: {truncated}\n");
+        fmt_symbols(0, &syms, &mut got);
+        expect.assert_eq(&got);
+    }
+
     #[test]
     fn let_in() {
         check(
             "let a.b = 1; c = let d = 1; in d; in a",
             expect![[r#"
-                a: LetIn
-                    b: PlainAttrset
-                c: LetIn
-                    d: LetIn
+                a: LetIn Attrset
+                    b: PlainAttrset Other
+                c: LetIn Other
+                    d: LetIn Other
             "#]],
         );
     }
@@ -143,12 +269,81 @@ mod tests {
         check(
             "{ a = 1; b = { c = 1; }; inherit d; inherit ({ e = 1; }) e; }",
             expect![[r#"
-                a: PlainAttrset
-                b: PlainAttrset
-                    c: PlainAttrset
-                d: PlainAttrset
-                e: PlainAttrset
-                e: PlainAttrset
+                a: PlainAttrset Other
+                b: PlainAttrset Attrset
+                    c: PlainAttrset Other
+                d: PlainAttrset Other
+                e: PlainAttrset Other
+                e: PlainAttrset Other
+            "#]],
+        );
+    }
+
+    #[test]
+    fn lambda() {
+        check(
+            r#"
+{
+    mkService = { name, port ? 80, extraConfig ? { enable = true; }, ... }: { };
+    overlay = final: prev: { };
+    wrapped = (args@{ pkgs }: x: 1);
+    mod = { config, ... }@args: { };
+    empty = { }: 1;
+    attrs = { a = 1; };
+}
+            "#,
+            expect![[r#"
+                mkService: PlainAttrset Lambda ({ name, port ? 80, extraConfig ? …, ... })
+                    enable: PlainAttrset Other
+                overlay: PlainAttrset Lambda (final: prev)
+                wrapped: PlainAttrset Lambda (args@{ pkgs }: x)
+                mod: PlainAttrset Lambda ({ config, ... }@args)
+                empty: PlainAttrset Lambda ({ })
+                attrs: PlainAttrset Attrset
+                    a: PlainAttrset Other
+            "#]],
+        );
+    }
+
+    #[test]
+    fn truncate() {
+        let src = "{ a = { b = { c = 1; }; d = 1; }; e = { f = 1; }; }";
+        check_truncate(
+            src,
+            usize::MAX,
+            usize::MAX,
+            expect![[r#"
+                truncated: false
+                a: PlainAttrset Attrset
+                    b: PlainAttrset Attrset
+                        c: PlainAttrset Other
+                    d: PlainAttrset Other
+                e: PlainAttrset Attrset
+                    f: PlainAttrset Other
+            "#]],
+        );
+        check_truncate(
+            src,
+            2,
+            usize::MAX,
+            expect![[r#"
+                truncated: true
+                a: PlainAttrset Attrset
+                    b: PlainAttrset Attrset
+                    d: PlainAttrset Other
+                e: PlainAttrset Attrset
+                    f: PlainAttrset Other
+            "#]],
+        );
+        check_truncate(
+            src,
+            usize::MAX,
+            3,
+            expect![[r#"
+                truncated: true
+                a: PlainAttrset Attrset
+                    b: PlainAttrset Attrset
+                e: PlainAttrset Attrset
             "#]],
         );
     }
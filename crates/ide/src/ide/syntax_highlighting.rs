@@ -4,7 +4,7 @@ use crate::def::{AstPtr, Expr, Literal, NameKind, ResolveResult};
 use crate::{DefDatabase, FileId};
 use builtin::{BuiltinKind, ALL_BUILTINS};
 use syntax::ast::AstNode;
-use syntax::{ast, match_ast, SyntaxKind, SyntaxToken, TextRange, T};
+use syntax::{ast, match_ast, SyntaxKind, SyntaxToken, TextRange, TextSize, T};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HlRange {
@@ -28,6 +28,8 @@ pub enum HlTag {
     Operator(HlOperator),
     Path,
     Punct(HlPunct),
+    /// A shell variable like `$out` or `''${out}` in strings of derivation phases.
+    ShellVariable,
     StringEscape,
     StringLiteral,
 }
@@ -51,6 +53,7 @@ pub enum HlOperator {
     Comparison,
     Arithmetic,
     Aggregation,
+    Pipe,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -161,6 +164,7 @@ pub(crate) fn highlight(
             }
             T![+] | T![-] | T![*] | T![/] => HlTag::Operator(HlOperator::Arithmetic),
             T![++] | T!["//"] => HlTag::Operator(HlOperator::Aggregation),
+            T![|>] | T![<|] => HlTag::Operator(HlOperator::Pipe),
             T!['{'] | T!['}'] | T!["${"] => HlTag::Punct(HlPunct::Brace),
             T!['['] | T![']'] => HlTag::Punct(HlPunct::Bracket),
             T!['('] | T![')'] => HlTag::Punct(HlPunct::Paren),
@@ -192,15 +196,104 @@ pub(crate) fn highlight(
         ),
     };
 
-    std::iter::successors(first_tok, |tok| tok.next_token())
+    let mut ret = Vec::new();
+    for tok in std::iter::successors(first_tok, |tok| tok.next_token())
         .take_while(|tok| tok.text_range().start() < end_pos)
-        .filter_map(|tok| {
-            Some(HlRange {
+    {
+        if tok.kind() == SyntaxKind::STRING_FRAGMENT {
+            if is_shell_string(&tok) {
+                ret.extend(shell_variables(&tok));
+            }
+            continue;
+        }
+        if let Some(tag) = token_tag(&tok) {
+            ret.push(HlRange {
                 range: tok.text_range(),
-                tag: token_tag(&tok)?,
-            })
-        })
-        .collect()
+                tag,
+            });
+        }
+    }
+    ret
+}
+
+/// Check if `name` is a conventional attribute of shell code for derivations,
+/// like `buildPhase`, `postPatch` or `shellHook`.
+fn is_shell_attr(name: &str) -> bool {
+    let is_hook = ["pre", "post"].iter().any(|prefix| {
+        name.strip_prefix(prefix)
+            .and_then(|rest| rest.chars().next())
+            .map_or(false, |ch| ch.is_ascii_uppercase())
+    });
+    is_hook || name.ends_with("Phase") || name == "shellHook"
+}
+
+/// Check if the string fragment is inside the value of a shell attribute.
+fn is_shell_string(tok: &SyntaxToken) -> bool {
+    let Some(apv) = tok.parent_ancestors().find_map(ast::AttrpathValue::cast) else {
+        return false;
+    };
+    let in_value = apv.value().map_or(false, |value| {
+        value.syntax().text_range().contains_range(tok.text_range())
+    });
+    let name = apv
+        .attrpath()
+        .and_then(|path| path.attrs().last())
+        .and_then(|attr| match attr {
+            ast::Attr::Name(name) => name.token(),
+            _ => None,
+        });
+    in_value && name.map_or(false, |name| is_shell_attr(name.text()))
+}
+
+/// Shell variables in a string fragment, like `$out`, `$1`, and `{out}` after an escaped `$`.
+fn shell_variables(tok: &SyntaxToken) -> Vec<HlRange> {
+    let text = tok.text();
+    let bytes = text.as_bytes();
+    let start = tok.text_range().start();
+    let mut ret = Vec::new();
+    let mut push = |from: usize, to: usize| {
+        ret.push(HlRange {
+            range: TextRange::new(
+                start + TextSize::from(from as u32),
+                start + TextSize::from(to as u32),
+            ),
+            tag: HlTag::ShellVariable,
+        });
+    };
+
+    // `''${out}` or `\${out}`, where the escape is a separated token.
+    let mut i = 0;
+    let after_escaped_dollar = tok.prev_token().map_or(false, |prev| {
+        prev.kind() == SyntaxKind::STRING_ESCAPE && prev.text().ends_with('$')
+    });
+    if after_escaped_dollar && bytes.first() == Some(&b'{') {
+        if let Some(end) = text.find('}') {
+            push(0, end + 1);
+            i = end + 1;
+        }
+    }
+
+    while i < bytes.len() {
+        if bytes[i] != b'$' {
+            i += 1;
+            continue;
+        }
+        let len = match bytes.get(i + 1) {
+            Some(b'a'..=b'z' | b'A'..=b'Z' | b'_') => {
+                1 + bytes[i + 1..]
+                    .iter()
+                    .take_while(|b| b.is_ascii_alphanumeric() || **b == b'_')
+                    .count()
+            }
+            Some(b'0'..=b'9' | b'@' | b'*' | b'#' | b'?' | b'$' | b'!' | b'-') => 2,
+            _ => 1,
+        };
+        if len > 1 {
+            push(i, i + len);
+        }
+        i += len;
+    }
+    ret
 }
 
 #[cfg(test)]
@@ -227,6 +320,14 @@ mod tests {
         expect.assert_eq(&got);
     }
 
+    #[track_caller]
+    fn check_no(fixture: &str) {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let FilePos { file_id, pos } = f[0];
+        let hls = super::highlight(&db, file_id, None);
+        assert_eq!(hls.iter().find(|hlrange| hlrange.range.contains(pos)), None);
+    }
+
     #[test]
     fn keyword() {
         check("$0if 1 then 2 else 3", expect!["Keyword(Conditional)"]);
@@ -244,6 +345,7 @@ mod tests {
         check("1 $0< 1", expect!["Operator(Comparison)"]);
         check("true $0-> false", expect!["Operator(Logical)"]);
         check("[] $0++ []", expect!["Operator(Aggregation)"]);
+        check("1 $0|> f", expect!["Operator(Pipe)"]);
     }
 
     #[test]
@@ -266,6 +368,33 @@ mod tests {
         check(r#""st$0\nring""#, expect!["StringEscape"]);
     }
 
+    #[test]
+    fn shell_variable() {
+        check(
+            "{ buildPhase = ''make $0$out''; }",
+            expect!["ShellVariable"],
+        );
+        check(
+            "{ postPatch = ''echo $out/$0$@''; }",
+            expect!["ShellVariable"],
+        );
+        check(
+            "{ installPhase = ''cp $src ''${$0out}/bin''; }",
+            expect!["ShellVariable"],
+        );
+        check(
+            r#"{ shellHook = "export FOO=$0$HOME"; }"#,
+            expect!["ShellVariable"],
+        );
+        check(
+            "{ buildPhase = ''make ${$0foo}''; }",
+            expect!["UnresolvedRef"],
+        );
+        check_no("{ buildPhase = ''make $0$ x''; }");
+        check_no("{ description = ''$0$out''; }");
+        check_no("{ preferLocalBuild = ''$0$out''; }");
+    }
+
     #[test]
     fn builtins_global() {
         check("$0true", expect!["BoolLiteral"]);
@@ -11,16 +11,20 @@ mod text_edit;
 mod tests;
 
 pub use self::ide::{
-    Analysis, AnalysisHost, Assist, AssistKind, Cancelled, CompletionItem, CompletionItemKind,
-    GotoDefinitionResult, HlAttrField, HlKeyword, HlOperator, HlPunct, HlRange, HlRelated, HlTag,
-    HoverResult, Link, LinkTarget, NavigationTarget, RenameResult, SymbolTree,
+    merge_completions, refine_completions, truncate_symbols, Analysis, AnalysisHost, Assist,
+    AssistKind, Cancelled, CodeLens, CompletionCommand, CompletionItem, CompletionItemKind,
+    DirEntry, EvalCompletionQuery, GotoDefinitionResult, HashPlaceholder, HlAttrField, HlKeyword,
+    HlOperator, HlPunct, HlRange, HlRelated, HlTag, HoverDefinition, HoverResult, InlayHint,
+    InlayHintKind, Interrupted, LibImportStrategy, Link, LinkTarget, NavigationTarget, PassTimings,
+    QueryStats, RenameError, RenameResult, SignatureHelp, SymbolTree, SymbolValueKind,
 };
 pub use base::{
-    Change, FileId, FilePos, FileRange, FileSet, FlakeGraph, FlakeInfo, InFile, SourceDatabase,
-    SourceRoot, SourceRootId, VfsPath,
+    Change, FileId, FilePos, FileRange, FileSet, FlakeGraph, FlakeInfo, InFile, LanguageFeature,
+    OptionSet, SourceDatabase, SourceRoot, SourceRootId, VfsPath, DEFAULT_CALL_PACKAGE_NAMES,
+    DEFAULT_SHADOWING_IGNORED_NAMES,
 };
 pub use builtin::BuiltinKind;
 pub use def::{DefDatabase, Module, ModuleKind, ModuleSourceMap, NameKind};
 pub use diagnostic::{Diagnostic, DiagnosticKind, Severity};
-pub use text_edit::{TextEdit, WorkspaceEdit};
+pub use text_edit::{FileSystemEdit, TextEdit, WorkspaceEdit};
 pub use ty::{InferenceResult, TyDatabase};
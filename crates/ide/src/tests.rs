@@ -2,12 +2,14 @@ use crate::base::SourceDatabaseStorage;
 use crate::def::DefDatabaseStorage;
 use crate::ty::TyDatabaseStorage;
 use crate::{
-    Change, DefDatabase, FileId, FilePos, FileRange, FileSet, FlakeGraph, FlakeInfo,
-    SourceDatabase, SourceRoot, SourceRootId, VfsPath,
+    Change, DefDatabase, FileId, FilePos, FileRange, FileSet, FlakeGraph, FlakeInfo, OptionSet,
+    SourceDatabase, SourceRoot, SourceRootId, VfsPath, DEFAULT_CALL_PACKAGE_NAMES,
+    DEFAULT_SHADOWING_IGNORED_NAMES,
 };
 use anyhow::{bail, ensure, Context, Result};
 use indexmap::IndexMap;
-use nix_interop::{DEFAULT_IMPORT_FILE, FLAKE_FILE};
+use nix_interop::package_aliases::PackageAliases;
+use nix_interop::{flake_lock, DEFAULT_IMPORT_FILE, FLAKE_FILE, FLAKE_LOCK_FILE};
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::{mem, ops};
@@ -48,7 +50,28 @@ impl TestDB {
             nodes: HashMap::from_iter(f.flake_info.clone().map(|info| (SourceRootId(0), info))),
         };
         change.set_flake_graph(flake_graph);
-        db.set_nixos_options(Arc::default());
+        for &set in OptionSet::ALL {
+            db.set_option_set(set, Arc::default());
+            db.set_option_set_module_paths(set, Arc::default());
+        }
+        db.set_package_aliases(Arc::new(PackageAliases::builtin()));
+        db.set_package_index(Arc::default());
+        db.set_lib_docs(Arc::default());
+        db.set_search_path(Arc::default());
+        db.set_call_package_names(Arc::new(
+            DEFAULT_CALL_PACKAGE_NAMES
+                .iter()
+                .map(|&s| s.into())
+                .collect(),
+        ));
+        db.set_shadowing_ignored_names(Arc::new(
+            DEFAULT_SHADOWING_IGNORED_NAMES
+                .iter()
+                .map(|&s| s.into())
+                .collect(),
+        ));
+        db.set_redundant_paren_kept_kinds(Arc::default());
+        db.set_language_features(Arc::default());
         change.apply(&mut db);
         Ok((db, f))
     }
@@ -118,6 +141,7 @@ impl Fixture {
                         flake_file: cur_file,
                         input_store_paths: HashMap::new(),
                         input_flake_outputs: HashMap::new(),
+                        input_sources: HashMap::new(),
                     });
                     for prop in iter {
                         if let Some((name, target)) = prop
@@ -177,6 +201,13 @@ impl Fixture {
             .map(|(i, p)| p.with_context(|| format!("Discontinuous marker: {i}")))
             .collect::<Result<Vec<_>>>()?;
 
+        if let Some(flake_info) = &mut this.flake_info {
+            if let Some(lock_src) = this.files.get(&VfsPath::new(format!("/{FLAKE_LOCK_FILE}"))) {
+                flake_info.input_sources =
+                    flake_lock::resolve_flake_input_sources(lock_src.as_bytes())?;
+            }
+        }
+
         Ok(this)
     }
 
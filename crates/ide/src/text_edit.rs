@@ -1,4 +1,4 @@
-use crate::FileId;
+use crate::{FileId, VfsPath};
 use smol_str::SmolStr;
 use std::collections::HashMap;
 use syntax::TextRange;
@@ -6,7 +6,16 @@ use syntax::TextRange;
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct WorkspaceEdit {
     pub content_edits: HashMap<FileId, Vec<TextEdit>>,
-    // Filesystem edit is not implemented yet.
+    /// Applied in order after all content edits, which refer to files by their old paths.
+    pub file_system_edits: Vec<FileSystemEdit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileSystemEdit {
+    /// Create a new file with the content. It fails if the file already exists.
+    CreateFile { path: VfsPath, content: String },
+    /// Move an existing file to a new path.
+    RenameFile { file: FileId, to: VfsPath },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -5,7 +5,7 @@ use std::sync::Arc;
 use nix_interop::flake_output::{FlakeOutput, Type as OutputTy};
 use nix_interop::nixos_options::Ty as OptionTy;
 
-use crate::{SourceRootId, TyDatabase};
+use crate::{OptionSet, SourceRootId, TyDatabase};
 
 use super::known::FLAKE_OUTPUT_GENERIC_SYSTEM_FIELDS;
 use super::{AttrSource, Attrset, Ty};
@@ -13,8 +13,8 @@ use super::{AttrSource, Attrset, Ty};
 // TODO: Get this at runtime.
 const NIX_SYSTEM: &str = "x86_64-linux";
 
-pub(crate) fn options_to_config_ty(db: &dyn TyDatabase) -> Ty {
-    let opts = db.nixos_options();
+pub(crate) fn options_to_config_ty(db: &dyn TyDatabase, set: OptionSet) -> Ty {
+    let opts = db.option_set(set);
     let fields = opts
         .iter()
         .map(|(name, opt)| (name.as_str(), from_raw_ty(&opt.ty), AttrSource::Unknown));
@@ -30,6 +30,7 @@ fn from_raw_ty(ty: &OptionTy) -> Ty {
         OptionTy::String => ty!(string),
         OptionTy::Path => ty!(path),
         OptionTy::Derivation => ty!(derivation),
+        OptionTy::Enum { .. } => ty!(string),
         OptionTy::List { elem } => ty!([(#from_raw_ty(elem))]),
         OptionTy::Lambda { from, to } => ty!((#from_raw_ty(from)) -> (#from_raw_ty(to))),
         OptionTy::Attrset { fields, rest } => {
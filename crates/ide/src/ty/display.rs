@@ -115,11 +115,69 @@ impl fmt::Display for TyDisplay<'_> {
     }
 }
 
+/// Names of fields of an attrset, where only nested attrsets are expanded,
+/// eg. `{ enable, port, settings = { … }, … }`.
+#[derive(Clone)]
+pub struct ShapeDisplay<'a> {
+    ty: &'a Ty,
+    max_depth: usize,
+    max_fields: usize,
+}
+
+impl<'a> ShapeDisplay<'a> {
+    pub fn new(ty: &'a Ty, max_depth: usize, max_fields: usize) -> Self {
+        Self {
+            ty,
+            max_depth,
+            max_fields,
+        }
+    }
+}
+
+impl fmt::Display for ShapeDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Ty::Attrset(set) = self.ty else {
+            return "…".fmt(f);
+        };
+        if set.is_empty() && set.rest.is_none() {
+            return "{ }".fmt(f);
+        }
+        if self.max_depth == 0 {
+            return "{ … }".fmt(f);
+        }
+        "{".fmt(f)?;
+        for (i, (name, ty, _src)) in set.iter().enumerate() {
+            if i != 0 {
+                ",".fmt(f)?;
+            }
+            if i == self.max_fields {
+                return " … }".fmt(f);
+            }
+            write!(f, " {}", escape_literal_attr(name))?;
+            if matches!(ty, Ty::Attrset(_)) {
+                let value = Self {
+                    ty,
+                    max_depth: self.max_depth - 1,
+                    ..*self
+                };
+                write!(f, " = {value}")?;
+            }
+        }
+        if set.rest.is_some() {
+            if !set.is_empty() {
+                ",".fmt(f)?;
+            }
+            " …".fmt(f)?;
+        }
+        " }".fmt(f)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use expect_test::{expect, Expect};
 
-    use super::{Config, TyDisplay};
+    use super::{Config, ShapeDisplay, TyDisplay};
     use crate::ty::Ty;
 
     #[track_caller]
@@ -153,4 +211,34 @@ mod tests {
         check_max_fields(3, ty, expect!["{ a: int, b: string, …: bool }"]);
         check_max_fields(4, ty, expect!["{ a: int, b: string, …: bool }"]);
     }
+
+    #[test]
+    fn shape() {
+        let check = |max_depth, max_fields, ty: &Ty, expect: Expect| {
+            expect.assert_eq(&ShapeDisplay::new(ty, max_depth, max_fields).to_string());
+        };
+        let ty = &ty!({
+            "enable": bool,
+            "port": int,
+            "settings": { "a": int, "b": { "c": int } },
+            "extra": { },
+        });
+        check(0, 4, ty, expect!["{ … }"]);
+        check(
+            1,
+            4,
+            ty,
+            expect!["{ enable, extra = { }, port, settings = { … } }"],
+        );
+        check(
+            3,
+            4,
+            ty,
+            expect!["{ enable, extra = { }, port, settings = { a, b = { c } } }"],
+        );
+        check(3, 2, ty, expect!["{ enable, extra = { }, … }"]);
+        check(3, 4, &ty!({ "a": int, _: int }), expect!["{ a, … }"]);
+        check(3, 4, &ty!({ _: int }), expect!["{ … }"]);
+        check(3, 4, &ty!(int), expect!["…"]);
+    }
 }
@@ -1,9 +1,11 @@
 use super::union_find::UnionFind;
 use super::{known, AttrSource, TyDatabase};
+use crate::base::InFile;
 use crate::def::{
-    BindingValue, Bindings, Expr, ExprId, Literal, NameId, NameResolution, ResolveResult,
+    is_call_package, BindingValue, Bindings, Expr, ExprId, Literal, NameId, NameResolution,
+    ResolveResult,
 };
-use crate::{FileId, Module};
+use crate::{FileId, Module, ModuleKind};
 use la_arena::ArenaMap;
 use smol_str::SmolStr;
 use std::collections::btree_map::{BTreeMap, Entry};
@@ -61,6 +63,65 @@ struct Attrset {
 pub struct InferenceResult {
     name_ty_map: ArenaMap<NameId, super::Ty>,
     expr_ty_map: ArenaMap<ExprId, super::Ty>,
+    conflicts: Vec<TyConflict>,
+}
+
+/// The outermost shape of a type, which is enough to tell an operation never succeeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TyKind {
+    Bool,
+    Int,
+    Float,
+    String,
+    Path,
+    List,
+    Lambda,
+    Attrset,
+}
+
+impl TyKind {
+    fn of(ty: &super::Ty) -> Option<Self> {
+        Some(match ty {
+            super::Ty::Unknown => return None,
+            super::Ty::Bool => Self::Bool,
+            super::Ty::Int => Self::Int,
+            super::Ty::Float => Self::Float,
+            super::Ty::String => Self::String,
+            super::Ty::Path => Self::Path,
+            super::Ty::List(_) => Self::List,
+            super::Ty::Lambda(..) => Self::Lambda,
+            super::Ty::Attrset(_) => Self::Attrset,
+        })
+    }
+
+    pub fn is_number(self) -> bool {
+        matches!(self, Self::Int | Self::Float)
+    }
+
+    /// Strings and paths can be concatenated with each other.
+    pub fn is_stringish(self) -> bool {
+        matches!(self, Self::String | Self::Path)
+    }
+}
+
+/// An operation on values of types it never accepts, found while unifying its operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TyConflict {
+    Binary {
+        expr: ExprId,
+        op: BinaryOpKind,
+        lhs: TyKind,
+        rhs: TyKind,
+    },
+    Unary {
+        expr: ExprId,
+        op: UnaryOpKind,
+        arg: TyKind,
+    },
+    /// The condition `expr` of an `if` is not a bool.
+    Condition { expr: ExprId, ty: TyKind },
+    /// The called `expr` is neither a function nor an attrset with `__functor`.
+    NotCallable { expr: ExprId, ty: TyKind },
 }
 
 impl InferenceResult {
@@ -71,41 +132,143 @@ impl InferenceResult {
     pub fn ty_for_expr(&self, expr: ExprId) -> super::Ty {
         self.expr_ty_map[expr].clone()
     }
+
+    /// Conflicts in the order of operations being inferred.
+    pub fn conflicts(&self) -> &[TyConflict] {
+        &self.conflicts
+    }
+}
+
+/// The maximum length of `import` chains to follow during inference.
+/// This also stops infinite recursion of cyclic imports.
+pub(crate) const MAX_IMPORT_DEPTH: u8 = 3;
+
+/// Check if `expr` is `mkShell`, `mkShellNoCC`, or the same under `<anything>.`.
+pub(crate) fn is_mk_shell(module: &Module, expr: ExprId) -> bool {
+    is_function_named(module, expr, &["mkShell", "mkShellNoCC"])
+}
+
+/// The argument type of `expr` if it is a known fetcher, or the same under `<anything>.`.
+pub(crate) fn fetcher_arg_ty(module: &Module, expr: ExprId) -> Option<&'static super::Ty> {
+    known::FETCHER_ARGS
+        .iter()
+        .find(|(name, _)| is_function_named(module, expr, &[name]))
+        .map(|(_, ty)| ty)
+}
+
+pub(crate) fn is_function_named(module: &Module, expr: ExprId, names: &[&str]) -> bool {
+    match &module[expr] {
+        Expr::Reference(text) => names.contains(&&**text),
+        Expr::Select(_, path, None) => path.last().map_or(false, |&attr| {
+            matches!(&module[attr], Expr::Literal(Literal::String(text)) if names.contains(&&**text))
+        }),
+        _ => false,
+    }
 }
 
 pub(crate) fn infer_query(db: &dyn TyDatabase, file: FileId) -> Arc<InferenceResult> {
+    let _span = tracing::debug_span!("infer", file = file.0).entered();
     let expect_ty = db.module_expected_ty(file);
-    infer_with(db, file, expect_ty)
+    infer_with(db, file, expect_ty, MAX_IMPORT_DEPTH)
+}
+
+/// The type of `import`ing `file`, following at most `depth` more levels of imports inside.
+pub(crate) fn import_ty_query(db: &dyn TyDatabase, file: FileId, depth: u8) -> super::Ty {
+    let _span = tracing::debug_span!("import_ty", file = file.0, depth).entered();
+    let module = db.module(file);
+    let infer = infer_with(db, file, db.module_expected_ty(file), depth);
+    into_imported(infer.ty_for_expr(module.entry_expr()), file)
+}
+
+/// Rewrite local name sources into sources of `file`, since `NameId`s are only meaningful
+/// inside their own file.
+fn into_imported(ty: super::Ty, file: FileId) -> super::Ty {
+    let src = |src| match src {
+        AttrSource::Name(name) => AttrSource::Imported(InFile::new(file, name)),
+        src => src,
+    };
+    match ty {
+        super::Ty::List(elem) => super::Ty::List(into_imported(elem.as_ref().clone(), file).into()),
+        super::Ty::Lambda(arg, ret) => super::Ty::Lambda(
+            into_imported(arg.as_ref().clone(), file).into(),
+            into_imported(ret.as_ref().clone(), file).into(),
+        ),
+        super::Ty::Attrset(set) => super::Ty::Attrset(super::Attrset {
+            fields: set
+                .fields
+                .iter()
+                .map(|(name, ty, s)| (name.clone(), into_imported(ty.clone(), file), src(*s)))
+                .collect(),
+            rest: set
+                .rest
+                .map(|rest| Arc::new((into_imported(rest.0.clone(), file), src(rest.1)))),
+        }),
+        ty => ty,
+    }
 }
 
 pub(crate) fn infer_with(
     db: &dyn TyDatabase,
     file: FileId,
     expect_ty: Option<super::Ty>,
+    import_depth: u8,
 ) -> Arc<InferenceResult> {
+    // `final` of an overlay contains the attributes added by the overlay itself. They are
+    // collected by a first pass, where nothing is known about `final`.
+    let final_ty = match *db.module_kind(file) {
+        ModuleKind::Overlay {
+            final_param,
+            body_expr,
+            ..
+        } => {
+            let infer = infer_once(db, file, expect_ty.clone(), None, import_depth);
+            Some((final_param, infer.ty_for_expr(body_expr)))
+        }
+        _ => None,
+    };
+    Arc::new(infer_once(db, file, expect_ty, final_ty, import_depth))
+}
+
+fn infer_once(
+    db: &dyn TyDatabase,
+    file: FileId,
+    expect_ty: Option<super::Ty>,
+    final_ty: Option<(NameId, super::Ty)>,
+    import_depth: u8,
+) -> InferenceResult {
     let module = db.module(file);
     let nameres = db.name_resolution(file);
     let table = UnionFind::new(module.names().len() + module.exprs().len(), |_| Ty::Unknown);
     let mut ctx = InferCtx {
+        db,
         module: &module,
         nameres: &nameres,
+        import_depth,
         table,
+        conflicts: Vec::new(),
     };
     let ty = ctx.infer_expr(module.entry_expr());
     if let Some(expect_ty) = expect_ty {
         ctx.unify_var_ty(ty, Ty::External(expect_ty));
     }
-    Arc::new(ctx.finish())
+    if let Some((name, final_ty @ super::Ty::Attrset(_))) = final_ty {
+        ctx.unify_var_ty(ctx.ty_for_name(name), Ty::External(final_ty));
+    }
+    ctx.finish()
 }
 
 struct InferCtx<'db> {
+    db: &'db dyn TyDatabase,
     module: &'db Module,
     nameres: &'db NameResolution,
+    /// Remaining levels of `import`s to follow.
+    import_depth: u8,
 
     /// The arena for both unification and interning.
     /// First `module.names().len() + module.exprs().len()` elements are types of each names and
     /// exprs, to allow recursive definition.
     table: UnionFind<Ty>,
+    conflicts: Vec<TyConflict>,
 }
 
 impl<'db> InferCtx<'db> {
@@ -135,6 +298,8 @@ impl<'db> InferCtx<'db> {
     }
 
     fn infer_expr(&mut self, e: ExprId) -> TyVar {
+        // Like lowering, large files are inferred in a single query.
+        self.db.unwind_if_cancelled();
         let ty = self.infer_expr_inner(e);
         let placeholder_ty = self.ty_for_expr(e);
         self.unify_var(placeholder_ty, ty);
@@ -208,6 +373,13 @@ impl<'db> InferCtx<'db> {
             &Expr::IfThenElse(cond, then, else_) => {
                 let cond_ty = self.infer_expr(cond);
                 self.unify_var_ty(cond_ty, Ty::Bool);
+                match self.kind_of(cond_ty) {
+                    Some(ty) if ty != TyKind::Bool => {
+                        self.conflicts
+                            .push(TyConflict::Condition { expr: cond, ty });
+                    }
+                    _ => {}
+                }
                 let then_ty = self.infer_expr(then);
                 let else_ty = self.infer_expr(else_);
                 self.unify_var(then_ty, else_ty);
@@ -221,7 +393,9 @@ impl<'db> InferCtx<'db> {
                     return self.new_ty_var();
                 };
 
-                match op {
+                let ret_ty = match op {
+                    // Lowered into `Expr::Apply`.
+                    BinaryOpKind::PipeInto | BinaryOpKind::PipeFrom => self.new_ty_var(),
                     BinaryOpKind::Equal | BinaryOpKind::NotEqual => Ty::Bool.intern(self),
                     BinaryOpKind::Imply | BinaryOpKind::Or | BinaryOpKind::And => {
                         self.unify_var_ty(lhs_ty, Ty::Bool);
@@ -256,27 +430,75 @@ impl<'db> InferCtx<'db> {
                         self.unify_var(rhs_ty, ret_ty);
                         ret_ty
                     }
+                };
+                let (lhs, rhs) = (self.kind_of(lhs_ty), self.kind_of(rhs_ty));
+                if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
+                    if !accepts_binary(op, lhs, rhs) {
+                        self.conflicts.push(TyConflict::Binary {
+                            expr: e,
+                            op,
+                            lhs,
+                            rhs,
+                        });
+                    }
                 }
+                ret_ty
             }
             &Expr::Unary(op, arg) => {
                 let arg_ty = self.infer_expr(arg);
-                match op {
-                    None => self.new_ty_var(),
-                    Some(UnaryOpKind::Not) => {
+                let Some(op) = op else {
+                    return self.new_ty_var();
+                };
+                let ret_ty = match op {
+                    UnaryOpKind::Not => {
                         self.unify_var_ty(arg_ty, Ty::Bool);
                         Ty::Bool.intern(self)
                     }
                     // TODO: The argument is int | bool.
-                    Some(UnaryOpKind::Negate) => arg_ty,
+                    UnaryOpKind::Negate => arg_ty,
+                };
+                match self.kind_of(arg_ty) {
+                    Some(ty)
+                        if ty != TyKind::Bool && op == UnaryOpKind::Not
+                            || !ty.is_number() && op == UnaryOpKind::Negate =>
+                    {
+                        self.conflicts.push(TyConflict::Unary {
+                            expr: e,
+                            op,
+                            arg: ty,
+                        });
+                    }
+                    _ => {}
                 }
+                ret_ty
             }
             &Expr::Apply(lam, arg) => {
                 let param_ty = self.new_ty_var();
                 let ret_ty = self.new_ty_var();
                 let lam_ty = self.infer_expr(lam);
+                if is_mk_shell(self.module, lam) {
+                    let mk_shell_ty = self.import_external(known::MK_SHELL.clone());
+                    self.unify_var(lam_ty, mk_shell_ty);
+                } else if let Some(arg_ty) = fetcher_arg_ty(self.module, lam) {
+                    let fetcher_ty =
+                        super::Ty::Lambda(arg_ty.clone().into(), known::DERIVATION.clone().into());
+                    let fetcher_ty = self.import_external(fetcher_ty);
+                    self.unify_var(lam_ty, fetcher_ty);
+                }
                 self.unify_var_ty(lam_ty, Ty::Lambda(param_ty, ret_ty));
+                // Attrsets with `__functor` are callable.
+                match self.kind_of(lam_ty) {
+                    Some(ty) if !matches!(ty, TyKind::Lambda | TyKind::Attrset) => {
+                        self.conflicts
+                            .push(TyConflict::NotCallable { expr: lam, ty });
+                    }
+                    _ => {}
+                }
                 let arg_ty = self.infer_expr(arg);
                 self.unify_var(arg_ty, param_ty);
+                if let Some(import_ty) = self.infer_import(lam, arg) {
+                    self.unify_var(ret_ty, import_ty);
+                }
                 ret_ty
             }
             Expr::HasAttr(set_expr, path) => {
@@ -346,6 +568,35 @@ impl<'db> InferCtx<'db> {
         }
     }
 
+    /// Infer `import ./path.nix` as the type of the imported file,
+    /// and `callPackage ./path.nix args` as the return type of the imported function.
+    fn infer_import(&mut self, lam: ExprId, arg: ExprId) -> Option<TyVar> {
+        if self.import_depth == 0 {
+            return None;
+        }
+        let (path_expr, is_call_package) =
+            if self.nameres.check_builtin(lam, self.module) == Some("import") {
+                (arg, false)
+            } else {
+                match self.module[lam] {
+                    Expr::Apply(func, path_expr) if is_call_package(self.db, self.module, func) => {
+                        (path_expr, true)
+                    }
+                    _ => return None,
+                }
+            };
+        let &Expr::Literal(Literal::Path(path)) = &self.module[path_expr] else {
+            return None;
+        };
+        let file = self.db.resolve_path_file(path)?;
+        let ty = match self.db.import_ty(file, self.import_depth - 1) {
+            super::Ty::Lambda(_, ret) if is_call_package => ret.as_ref().clone(),
+            _ if is_call_package => return None,
+            ty => ty,
+        };
+        Some(self.import_external(ty))
+    }
+
     fn infer_bindings(&mut self, bindings: &Bindings) -> Attrset {
         let inherit_from_tys = bindings
             .inherit_froms
@@ -434,6 +685,22 @@ impl<'db> InferCtx<'db> {
         self.new_ty_var()
     }
 
+    /// The shape `var` is unified to so far.
+    fn kind_of(&mut self, var: TyVar) -> Option<TyKind> {
+        Some(match self.table.get_mut(var.0) {
+            Ty::Unknown => return None,
+            Ty::Bool => TyKind::Bool,
+            Ty::Int => TyKind::Int,
+            Ty::Float => TyKind::Float,
+            Ty::String => TyKind::String,
+            Ty::Path => TyKind::Path,
+            Ty::List(_) => TyKind::List,
+            Ty::Lambda(..) => TyKind::Lambda,
+            Ty::Attrset(_) => TyKind::Attrset,
+            Ty::External(ty) => return TyKind::of(ty),
+        })
+    }
+
     fn unify_var_ty(&mut self, var: TyVar, rhs: Ty) {
         let lhs = mem::replace(self.table.get_mut(var.0), Ty::Unknown);
         let ret = self.unify(lhs, rhs);
@@ -441,6 +708,13 @@ impl<'db> InferCtx<'db> {
     }
 
     fn unify_var(&mut self, lhs: TyVar, rhs: TyVar) {
+        // Values of conflicting kinds are kept apart, so that neither overwrites the other. Nix
+        // allows them in the same list, and operations on them are reported as conflicts.
+        if let (Some(l), Some(r)) = (self.kind_of(lhs), self.kind_of(rhs)) {
+            if l != r {
+                return;
+            }
+        }
         let (var, rhs) = self.table.unify(lhs.0, rhs.0);
         let Some(rhs) = rhs else { return };
         self.unify_var_ty(TyVar(var), rhs);
@@ -525,7 +799,32 @@ impl<'db> InferCtx<'db> {
         InferenceResult {
             name_ty_map,
             expr_ty_map,
+            conflicts: self.conflicts,
+        }
+    }
+}
+
+/// Whether the binary operation `op` can succeed on operands of kind `lhs` and `rhs`.
+fn accepts_binary(op: BinaryOpKind, lhs: TyKind, rhs: TyKind) -> bool {
+    let numbers = lhs.is_number() && rhs.is_number();
+    match op {
+        BinaryOpKind::Add => numbers || lhs.is_stringish() && rhs.is_stringish(),
+        BinaryOpKind::Sub | BinaryOpKind::Mul | BinaryOpKind::Div => numbers,
+        BinaryOpKind::Less
+        | BinaryOpKind::Greater
+        | BinaryOpKind::LessEqual
+        | BinaryOpKind::GreaterEqual => {
+            numbers || lhs == rhs && matches!(lhs, TyKind::String | TyKind::Path | TyKind::List)
+        }
+        BinaryOpKind::And | BinaryOpKind::Or | BinaryOpKind::Imply => {
+            lhs == TyKind::Bool && rhs == TyKind::Bool
         }
+        BinaryOpKind::Concat => lhs == TyKind::List && rhs == TyKind::List,
+        BinaryOpKind::Update => lhs == TyKind::Attrset && rhs == TyKind::Attrset,
+        BinaryOpKind::Equal
+        | BinaryOpKind::NotEqual
+        | BinaryOpKind::PipeInto
+        | BinaryOpKind::PipeFrom => true,
     }
 }
 
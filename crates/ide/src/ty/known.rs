@@ -390,18 +390,222 @@ fn builtins() -> Ty {
     })
 }
 
+/// Commonly used functions of nixpkgs `lib`.
+// https://github.com/NixOS/nixpkgs/blob/23.11/lib/default.nix
+pub static LIB: Lazy<Ty> = Lazy::new(|| {
+    ty!({
+        "attrByPath": (forall a, [string] -> a -> { } -> a),
+        "concatLists": (forall a, [[a]] -> [a]),
+        "concatMap": (forall a b, (a -> [b]) -> [a] -> [b]),
+        "concatMapStrings": (forall a, (a -> string) -> [a] -> string),
+        "concatMapStringsSep": (forall a, string -> (a -> string) -> [a] -> string),
+        "concatStrings": ([string] -> string),
+        "concatStringsSep": (string -> [string] -> string),
+        "filterAttrs": (forall a, (string -> a -> bool) -> { _: a } -> { _: a }),
+        "genAttrs": (forall a, [string] -> (string -> a) -> { _: a }),
+        "getExe": (derivation -> string),
+        "hasPrefix": (string -> string -> bool),
+        "hasSuffix": (string -> string -> bool),
+        "licenses": { },
+        "literalExpression": (string -> { }),
+        "maintainers": { },
+        "makeBinPath": ([derivation] -> string),
+        "mapAttrs": (forall a b, (string -> a -> b) -> { _: a } -> { _: b }),
+        "mapAttrsToList": (forall a b, (string -> a -> b) -> { _: a } -> [b]),
+        "mkAfter": (forall a, a -> a),
+        "mkBefore": (forall a, a -> a),
+        "mkDefault": (forall a, a -> a),
+        "mkEnableOption": (string -> { }),
+        "mkForce": (forall a, a -> a),
+        "mkIf": (forall a, bool -> a -> a),
+        "mkMerge": (forall a, [a] -> a),
+        "mkOption": ({ } -> { }),
+        "mkOverride": (forall a, int -> a -> a),
+        "nameValuePair": (forall a, string -> a -> { "name": string, "value": a }),
+        "optional": (forall a, bool -> a -> [a]),
+        "optionalAttrs": (forall a, bool -> { _: a } -> { _: a }),
+        "optionalString": (bool -> string -> string),
+        "optionals": (forall a, bool -> [a] -> [a]),
+        "platforms": { },
+        "range": (int -> int -> [int]),
+        "recursiveUpdate": ({ } -> { } -> { }),
+        "removePrefix": (string -> string -> string),
+        "removeSuffix": (string -> string -> string),
+        "splitString": (string -> string -> [string]),
+        "toLower": (string -> string),
+        "toUpper": (string -> string),
+        "types": { },
+        "unique": (forall a, [a] -> [a]),
+        "versionAtLeast": (string -> string -> bool),
+        "versionOlder": (string -> string -> bool),
+    })
+});
+
 pub static PACKAGE: Lazy<Ty> = Lazy::new(|| {
     ty!({
-        // TODO: nixpkgs.lib
-        "lib": { },
+        "lib": (#LIB.clone()),
         // TODO: Packages.
         "pkgs": { },
     } -> derivation)
 });
 
+// https://github.com/NixOS/nixpkgs/blob/23.11/pkgs/build-support/mkshell/default.nix
+pub static MK_SHELL_ARG: Lazy<Ty> = Lazy::new(|| {
+    ty!({
+        "name": string,
+        "packages": [derivation],
+        "inputsFrom": [derivation],
+        "buildInputs": [derivation],
+        "nativeBuildInputs": [derivation],
+        "propagatedBuildInputs": [derivation],
+        "propagatedNativeBuildInputs": [derivation],
+        "shellHook": string,
+        "env": { _: stringish },
+        "passthru": { },
+        "meta": { },
+    })
+});
+
+/// `mkShell` and `mkShellNoCC` from nixpkgs.
+pub static MK_SHELL: Lazy<Ty> = Lazy::new(|| ty!((#MK_SHELL_ARG.clone()) -> derivation));
+
+// https://github.com/NixOS/nixpkgs/tree/24.05/pkgs/build-support
+/// Argument types of common fetchers from nixpkgs, by their names.
+pub static FETCHER_ARGS: Lazy<Vec<(&str, Ty)>> = Lazy::new(|| {
+    vec![
+        (
+            "fetchurl",
+            ty!({
+                "url": string,
+                "urls": [string],
+                "hash": string,
+                "sha256": string,
+                "name": string,
+                "curlOpts": string,
+                "postFetch": string,
+                "downloadToTemp": bool,
+                "executable": bool,
+                "recursiveHash": bool,
+            }),
+        ),
+        (
+            "fetchzip",
+            ty!({
+                "url": string,
+                "urls": [string],
+                "hash": string,
+                "sha256": string,
+                "name": string,
+                "stripRoot": bool,
+                "extension": string,
+                "postFetch": string,
+            }),
+        ),
+        (
+            "fetchgit",
+            ty!({
+                "url": string,
+                "rev": string,
+                "hash": string,
+                "sha256": string,
+                "name": string,
+                "branchName": string,
+                "fetchSubmodules": bool,
+                "fetchLFS": bool,
+                "leaveDotGit": bool,
+                "deepClone": bool,
+                "sparseCheckout": [string],
+                "postFetch": string,
+            }),
+        ),
+        (
+            "fetchFromGitHub",
+            ty!({
+                "owner": string,
+                "repo": string,
+                "rev": string,
+                "hash": string,
+                "sha256": string,
+                "name": string,
+                "fetchSubmodules": bool,
+                "leaveDotGit": bool,
+                "deepClone": bool,
+                "private": bool,
+                "githubBase": string,
+                "sparseCheckout": [string],
+                "postFetch": string,
+            }),
+        ),
+        (
+            "fetchFromGitLab",
+            ty!({
+                "owner": string,
+                "repo": string,
+                "rev": string,
+                "hash": string,
+                "sha256": string,
+                "name": string,
+                "domain": string,
+                "group": string,
+                "fetchSubmodules": bool,
+                "leaveDotGit": bool,
+                "deepClone": bool,
+            }),
+        ),
+        (
+            "fetchFromGitea",
+            ty!({
+                "domain": string,
+                "owner": string,
+                "repo": string,
+                "rev": string,
+                "hash": string,
+                "sha256": string,
+            }),
+        ),
+        (
+            "fetchFromSourcehut",
+            ty!({
+                "owner": string,
+                "repo": string,
+                "rev": string,
+                "hash": string,
+                "sha256": string,
+                "domain": string,
+                "vc": string,
+                "fetchSubmodules": bool,
+            }),
+        ),
+        (
+            "fetchpatch",
+            ty!({
+                "url": string,
+                "hash": string,
+                "sha256": string,
+                "name": string,
+                "stripLen": int,
+                "extraPrefix": string,
+                "excludes": [string],
+                "includes": [string],
+                "revert": bool,
+                "relative": string,
+            }),
+        ),
+        (
+            "fetchCrate",
+            ty!({
+                "pname": string,
+                "version": string,
+                "hash": string,
+                "sha256": string,
+            }),
+        ),
+    ]
+});
+
 pub fn config_module(config: Ty) -> Ty {
     ty!({
-        "lib": { },
+        "lib": (#LIB.clone()),
         "config": (#config.clone()),
         "pkgs": { },
     } -> {
@@ -419,7 +623,7 @@ pub fn config_module(config: Ty) -> Ty {
 
 pub fn config(config: Ty) -> Ty {
     ty!({
-        "lib": { },
+        "lib": (#LIB.clone()),
         "config": (#config.clone()),
         "pkgs": { },
     } -> (#config))
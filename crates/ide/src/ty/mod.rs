@@ -72,19 +72,27 @@ mod convert;
 mod display;
 mod infer;
 pub mod known;
+mod options;
 mod union_find;
 
 #[cfg(test)]
 mod tests;
 
-use crate::def::NameId;
-use crate::{DefDatabase, FileId, ModuleKind, SourceRootId};
+use crate::def::{Expr, NameId};
+use crate::{DefDatabase, FileId, InFile, ModuleKind, OptionSet, SourceRootId};
 use std::collections::HashMap;
 use std::fmt;
 use std::sync::Arc;
 
-pub use display::{Config as DisplayConfig, TyDisplay};
-pub use infer::InferenceResult;
+pub use display::{Config as DisplayConfig, ShapeDisplay, TyDisplay};
+pub(crate) use infer::{fetcher_arg_ty, is_function_named, is_mk_shell, MAX_IMPORT_DEPTH};
+pub use infer::{InferenceResult, TyConflict, TyKind};
+pub(crate) use options::{config_expr, config_param};
+pub use options::{
+    ModuleGraph, OptionDeclaration, OptionDeclarations, OptionDefinition, OptionDefinitionIndex,
+    OptionDefinitions, OptionEnumValues, OptionReference, OptionReferenceIndex, OptionUse,
+    OptionUses, Priority, DEFAULT_PRIORITY,
+};
 use smol_str::SmolStr;
 
 #[salsa::query_group(TyDatabaseStorage)]
@@ -95,11 +103,41 @@ pub trait TyDatabase: DefDatabase {
     #[salsa::invoke(infer::infer_query)]
     fn infer(&self, file: FileId) -> Arc<InferenceResult>;
 
+    #[salsa::invoke(infer::import_ty_query)]
+    fn import_ty(&self, file: FileId, depth: u8) -> Ty;
+
     #[salsa::invoke(convert::options_to_config_ty)]
-    fn nixos_config_ty(&self) -> Ty;
+    fn config_ty(&self, set: OptionSet) -> Ty;
 
     #[salsa::invoke(convert::flake_input_tys)]
     fn flake_input_tys(&self, sid: SourceRootId) -> Arc<HashMap<String, Ty>>;
+
+    #[salsa::invoke(options::option_enum_values_query)]
+    fn option_enum_values(&self, file: FileId) -> Arc<OptionEnumValues>;
+
+    #[salsa::invoke(options::option_definitions_query)]
+    fn option_definitions(&self, file: FileId) -> Arc<OptionDefinitions>;
+
+    #[salsa::invoke(options::option_declarations_query)]
+    fn option_declarations(&self, file: FileId) -> Arc<OptionDeclarations>;
+
+    #[salsa::invoke(options::option_uses_query)]
+    fn option_uses(&self, file: FileId) -> Arc<OptionUses>;
+
+    #[salsa::invoke(options::module_imports_query)]
+    fn module_imports(&self, file: FileId) -> Arc<[FileId]>;
+
+    #[salsa::invoke(options::source_root_module_graph_query)]
+    fn source_root_module_graph(&self, sid: SourceRootId) -> Arc<ModuleGraph>;
+
+    #[salsa::invoke(options::configuration_files_query)]
+    fn configuration_files(&self, file: FileId) -> Arc<[FileId]>;
+
+    #[salsa::invoke(options::option_definition_index_query)]
+    fn option_definition_index(&self, files: Arc<[FileId]>) -> Arc<OptionDefinitionIndex>;
+
+    #[salsa::invoke(options::option_reference_index_query)]
+    fn option_reference_index(&self, sid: SourceRootId) -> Arc<OptionReferenceIndex>;
 }
 
 #[derive(Clone, PartialEq, Eq)]
@@ -125,6 +163,23 @@ impl Ty {
         !matches!(self, Self::Unknown)
     }
 
+    /// Whether the type contains no unknown part, like `? → int` or `{ a: ? }`.
+    pub fn is_fully_known(&self) -> bool {
+        match self {
+            Self::Unknown => false,
+            Self::Bool | Self::Int | Self::Float | Self::String | Self::Path => true,
+            Self::List(elem) => elem.is_fully_known(),
+            Self::Lambda(arg, ret) => arg.is_fully_known() && ret.is_fully_known(),
+            Self::Attrset(set) => {
+                set.fields.iter().all(|(_, ty, _)| ty.is_fully_known())
+                    && set
+                        .rest
+                        .as_ref()
+                        .map_or(true, |rest| rest.0.is_fully_known())
+            }
+        }
+    }
+
     pub fn as_attrset(&self) -> Option<&Attrset> {
         match self {
             Self::Attrset(v) => Some(v),
@@ -136,6 +191,10 @@ impl Ty {
         TyDisplay::new(self, config)
     }
 
+    pub fn display_shape(&self, max_depth: usize, max_fields: usize) -> ShapeDisplay<'_> {
+        ShapeDisplay::new(self, max_depth, max_fields)
+    }
+
     pub fn debug(&self) -> TyDisplay<'_> {
         self.display_with(display::Config::FULL)
     }
@@ -228,6 +287,8 @@ pub enum AttrSource {
     Name(NameId),
     /// A builtin name.
     Builtin,
+    /// Defined by a name in another file, reached via `import`.
+    Imported(InFile<NameId>),
 }
 
 fn module_expected_ty(db: &dyn TyDatabase, file: FileId) -> Option<Ty> {
@@ -257,8 +318,23 @@ fn module_expected_ty(db: &dyn TyDatabase, file: FileId) -> Option<Ty> {
             inputs.dedup_by_key(|(name, _)| *name);
             Some(known::flake(&inputs))
         }
-        ModuleKind::Package { .. } => Some(known::PACKAGE.clone()),
-        ModuleKind::ConfigModule { .. } => Some(known::config_module(db.nixos_config_ty())),
-        ModuleKind::Config { .. } => Some(known::config(db.nixos_config_ty())),
+        &ModuleKind::Package { lambda_expr } => {
+            // Packages returning attrsets, eg. ones called by `callPackages`, are not derivations.
+            let module = db.module(file);
+            match module[lambda_expr] {
+                Expr::Lambda(_, _, body)
+                    if matches!(module[body], Expr::Attrset(_) | Expr::RecAttrset(_)) =>
+                {
+                    None
+                }
+                _ => Some(known::PACKAGE.clone()),
+            }
+        }
+        ModuleKind::ConfigModule { .. } => Some(known::config_module(
+            db.config_ty(db.module_option_set(file)),
+        )),
+        ModuleKind::Config { .. } => Some(known::config(db.config_ty(db.module_option_set(file)))),
+        // The `final` parameter is handled during inference, since it depends on the body.
+        ModuleKind::Overlay { .. } => None,
     }
 }
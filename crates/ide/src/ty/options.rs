@@ -0,0 +1,1070 @@
+//! Static checks of NixOS option definitions against declared option types,
+//! the index of option definitions with their priorities, the index of all references to
+//! option paths, and the graph of modules composed by `imports`.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+use nix_interop::nixos_options::{NixosOptions, Ty as OptionTy};
+use smol_str::SmolStr;
+
+use super::TyDatabase;
+use crate::def::{const_eval_string, BindingValue, Expr, ExprId, Literal, NameId, ResolveResult};
+use crate::{FileId, InFile, Module, ModuleKind, SourceRootId};
+
+/// The priority of option definitions without modifiers. Lower values take precedence.
+pub const DEFAULT_PRIORITY: u32 = 100;
+
+/// Modifier functions with fixed priorities, from `lib/modules.nix`.
+const PRIORITY_MODIFIERS: &[(&str, u32)] = &[
+    ("mkOptionDefault", 1500),
+    ("mkDefault", 1000),
+    ("mkImageMediaOverride", 60),
+    ("mkForce", 50),
+    ("mkVMOverride", 10),
+];
+
+/// Functions declaring an option, from `lib/options.nix`.
+const DECLARATION_FUNCTIONS: &[&str] = &["mkOption", "mkEnableOption", "mkPackageOption"];
+
+/// String definitions of options with `types.enum` type, mapping from
+/// the string literal expression to the allowed values.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OptionEnumValues {
+    values: HashMap<ExprId, Arc<[String]>>,
+}
+
+impl OptionEnumValues {
+    pub fn get(&self, expr: ExprId) -> Option<&[String]> {
+        self.values.get(&expr).map(|v| &**v)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ExprId, &'_ [String])> + '_ {
+        self.values.iter().map(|(&expr, values)| (expr, &**values))
+    }
+}
+
+/// The priority of an option definition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Priority {
+    /// `None` if it is not statically known, eg. `mkOverride n`.
+    pub value: Option<u32>,
+    /// The modifier function setting the priority, eg. `mkForce`.
+    pub modifier: Option<&'static str>,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Self {
+            value: Some(DEFAULT_PRIORITY),
+            modifier: None,
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.value {
+            Some(value) => write!(f, "priority {value}")?,
+            None => "unknown priority".fmt(f)?,
+        }
+        if let Some(modifier) = self.modifier {
+            write!(f, " (`{modifier}`)")?;
+        }
+        Ok(())
+    }
+}
+
+/// A leaf definition of an option in a NixOS module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionDefinition {
+    /// The option path, eg. `["services", "foo", "enable"]`.
+    pub path: Arc<[SmolStr]>,
+    /// The last name of the attrpath.
+    pub name: NameId,
+    /// The defined value, with all modifiers peeled.
+    pub value: ExprId,
+    pub priority: Priority,
+}
+
+/// Leaf option definitions of a file, in the order of occurrence.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OptionDefinitions {
+    defs: Vec<OptionDefinition>,
+}
+
+impl OptionDefinitions {
+    pub fn iter(&self) -> impl Iterator<Item = &'_ OptionDefinition> + '_ {
+        self.defs.iter()
+    }
+
+    pub fn for_name(&self, name: NameId) -> Option<&OptionDefinition> {
+        self.defs.iter().find(|def| def.name == name)
+    }
+}
+
+/// All option definitions of a set of NixOS modules, grouped by option paths.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OptionDefinitionIndex {
+    defs: HashMap<Arc<[SmolStr]>, Vec<InFile<OptionDefinition>>>,
+}
+
+impl OptionDefinitionIndex {
+    pub fn get(&self, path: &[SmolStr]) -> &[InFile<OptionDefinition>] {
+        self.defs.get(path).map_or(&[], |defs| &defs[..])
+    }
+
+    /// Definitions of `path` and all options under it, in no particular order.
+    pub fn under<'a>(
+        &'a self,
+        path: &'a [SmolStr],
+    ) -> impl Iterator<Item = &'a InFile<OptionDefinition>> + 'a {
+        self.defs
+            .iter()
+            .filter(move |(def_path, _)| def_path.starts_with(path))
+            .flat_map(|(_, defs)| defs)
+    }
+
+    /// Definitions which take effect for `path`, ie. ones with the lowest priority value.
+    /// Returns nothing if any priority is not statically known.
+    pub fn winners(&self, path: &[SmolStr]) -> Vec<&InFile<OptionDefinition>> {
+        let defs = self.get(path);
+        let Some(min) = defs.iter().map(|def| def.value.priority.value).min().flatten() else {
+            return Vec::new();
+        };
+        if defs.iter().any(|def| def.value.priority.value.is_none()) {
+            return Vec::new();
+        }
+        defs.iter()
+            .filter(|def| def.value.priority.value == Some(min))
+            .collect()
+    }
+}
+
+/// A use of an option value via `config.<path>` in a NixOS module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionUse {
+    /// The static prefix of the selected attrpath.
+    pub path: Arc<[SmolStr]>,
+    /// The last attribute of `path`.
+    pub attr: ExprId,
+}
+
+/// Uses of option values in a file, in the order of occurrence.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OptionUses {
+    uses: Vec<OptionUse>,
+}
+
+impl OptionUses {
+    pub fn iter(&self) -> impl Iterator<Item = &'_ OptionUse> + '_ {
+        self.uses.iter()
+    }
+}
+
+/// A declaration of an option by `mkOption` or alike under `options` of a NixOS module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionDeclaration {
+    /// The option path, eg. `["services", "foo", "enable"]`.
+    pub path: Arc<[SmolStr]>,
+    /// The last name of the attrpath.
+    pub name: NameId,
+    /// The declaring call, eg. `mkOption { }`.
+    pub value: ExprId,
+}
+
+/// Option declarations of a file, in the order of occurrence.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OptionDeclarations {
+    decls: Vec<OptionDeclaration>,
+}
+
+impl OptionDeclarations {
+    pub fn iter(&self) -> impl Iterator<Item = &'_ OptionDeclaration> + '_ {
+        self.decls.iter()
+    }
+
+    pub fn for_name(&self, name: NameId) -> Option<&OptionDeclaration> {
+        self.decls.iter().find(|decl| decl.name == name)
+    }
+}
+
+/// A reference to an option path in a NixOS module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionReference {
+    /// The last name of a declaration under `options`.
+    Declaration(NameId),
+    /// The last name of a definition.
+    Definition(NameId),
+    /// The last attribute of a use like `config.foo.enable`.
+    Use(ExprId),
+}
+
+/// All references to option paths in NixOS modules of a source root, grouped by option paths.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OptionReferenceIndex {
+    refs: HashMap<Arc<[SmolStr]>, Vec<InFile<OptionReference>>>,
+}
+
+impl OptionReferenceIndex {
+    /// References of `path`, in the order of files and occurrences.
+    pub fn get(&self, path: &[SmolStr]) -> &[InFile<OptionReference>] {
+        self.refs.get(path).map_or(&[], |refs| &refs[..])
+    }
+}
+
+/// The graph of NixOS modules in a source root, composed by `imports = [ ./a.nix ];`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ModuleGraph {
+    imports: HashMap<FileId, Vec<FileId>>,
+    importers: HashMap<FileId, Vec<FileId>>,
+}
+
+impl ModuleGraph {
+    pub fn imports(&self, file: FileId) -> &[FileId] {
+        self.imports.get(&file).map_or(&[], |files| &files[..])
+    }
+
+    pub fn importers(&self, file: FileId) -> &[FileId] {
+        self.importers.get(&file).map_or(&[], |files| &files[..])
+    }
+
+    /// Check if `file` imports or is imported by any module.
+    pub fn contains(&self, file: FileId) -> bool {
+        self.imports.contains_key(&file) || self.importers.contains_key(&file)
+    }
+
+    /// All modules of configurations containing `file`, sorted. That is, modules imported
+    /// transitively by root modules, which are not imported by others but import `file`
+    /// transitively.
+    pub fn configuration(&self, file: FileId) -> Vec<FileId> {
+        let ancestors = Self::closure(file, |f| self.importers(f));
+        let mut ret = ancestors
+            .iter()
+            .filter(|&&f| self.importers(f).is_empty())
+            .flat_map(|&root| Self::closure(root, |f| self.imports(f)))
+            .collect::<HashSet<_>>();
+        // Ancestors not reached from roots are in cycles of imports, which are roots themselves.
+        let cyclic_roots = ancestors
+            .into_iter()
+            .filter(|f| !ret.contains(f))
+            .collect::<Vec<_>>();
+        for root in cyclic_roots {
+            ret.extend(Self::closure(root, |f| self.imports(f)));
+        }
+        let mut ret = ret.into_iter().collect::<Vec<_>>();
+        ret.sort();
+        ret
+    }
+
+    fn closure<'a>(file: FileId, edges: impl Fn(FileId) -> &'a [FileId]) -> HashSet<FileId> {
+        let mut visited = HashSet::from([file]);
+        let mut stack = vec![file];
+        while let Some(file) = stack.pop() {
+            for &next in edges(file) {
+                if visited.insert(next) {
+                    stack.push(next);
+                }
+            }
+        }
+        visited
+    }
+}
+
+/// The attrset of option definitions of a module or configuration.
+pub(crate) fn config_expr(db: &dyn TyDatabase, module: &Module, file: FileId) -> Option<ExprId> {
+    match *db.module_kind(file) {
+        ModuleKind::Config { lambda_expr } => lambda_body(module, lambda_expr),
+        // Only definitions under `config` are options definitions.
+        ModuleKind::ConfigModule { lambda_expr } => {
+            lambda_body(module, lambda_expr).and_then(|body| match &module[body] {
+                Expr::Attrset(bindings) | Expr::RecAttrset(bindings) => {
+                    match bindings.get("config", module)? {
+                        BindingValue::Expr(e) => Some(e),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            })
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn option_enum_values_query(db: &dyn TyDatabase, file: FileId) -> Arc<OptionEnumValues> {
+    let module = db.module(file);
+    let Some(config_expr) = config_expr(db, &module, file) else {
+        return Arc::default();
+    };
+
+    let opts = db.option_set(db.module_option_set(file));
+    let mut ctx = Ctx {
+        module: &module,
+        values: HashMap::new(),
+    };
+    ctx.collect_attrset(config_expr, &opts, None);
+    let mut values = ctx.values;
+    values.shrink_to_fit();
+    Arc::new(OptionEnumValues { values })
+}
+
+pub(crate) fn option_definitions_query(
+    db: &dyn TyDatabase,
+    file: FileId,
+) -> Arc<OptionDefinitions> {
+    let module = db.module(file);
+    let Some(config_expr) = config_expr(db, &module, file) else {
+        return Arc::default();
+    };
+    let mut ctx = DefinitionCtx {
+        db,
+        file,
+        module: &module,
+        path: Vec::new(),
+        defs: Vec::new(),
+    };
+    ctx.collect(config_expr, None, Priority::default());
+    let mut defs = ctx.defs;
+    defs.shrink_to_fit();
+    Arc::new(OptionDefinitions { defs })
+}
+
+pub(crate) fn option_declarations_query(
+    db: &dyn TyDatabase,
+    file: FileId,
+) -> Arc<OptionDeclarations> {
+    let module = db.module(file);
+    let ModuleKind::ConfigModule { lambda_expr } = *db.module_kind(file) else {
+        return Arc::default();
+    };
+    let options_expr = lambda_body(&module, lambda_expr).and_then(|body| match &module[body] {
+        Expr::Attrset(bindings) | Expr::RecAttrset(bindings) => {
+            match bindings.get("options", &module)? {
+                BindingValue::Expr(e) => Some(e),
+                _ => None,
+            }
+        }
+        _ => None,
+    });
+    let Some(options_expr) = options_expr else {
+        return Arc::default();
+    };
+
+    fn collect(
+        module: &Module,
+        expr: ExprId,
+        path: &mut Vec<SmolStr>,
+        decls: &mut Vec<OptionDeclaration>,
+    ) {
+        let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) =
+            &module[peel_expr(module, expr)]
+        else {
+            return;
+        };
+        for &(name, value) in bindings.statics.iter() {
+            let BindingValue::Expr(value) = value else {
+                continue;
+            };
+            path.push(module[name].text.clone());
+            if is_declaration(module, value) {
+                decls.push(OptionDeclaration {
+                    path: path.clone().into(),
+                    name,
+                    value,
+                });
+            } else {
+                collect(module, value, path, decls);
+            }
+            path.pop();
+        }
+    }
+
+    let mut decls = Vec::new();
+    collect(&module, options_expr, &mut Vec::new(), &mut decls);
+    decls.shrink_to_fit();
+    Arc::new(OptionDeclarations { decls })
+}
+
+/// Check if `expr` is a call to an option declaring function, like `lib.mkOption { }`.
+fn is_declaration(module: &Module, expr: ExprId) -> bool {
+    let mut func = expr;
+    let mut applied = false;
+    while let &Expr::Apply(f, _) = &module[func] {
+        func = f;
+        applied = true;
+    }
+    applied
+        && DECLARATION_FUNCTIONS
+            .iter()
+            .any(|&name| is_lib_ref(module, func, name))
+}
+
+/// The `config` parameter of a NixOS module.
+pub(crate) fn config_param(db: &dyn TyDatabase, module: &Module, file: FileId) -> Option<NameId> {
+    let (ModuleKind::Config { lambda_expr } | ModuleKind::ConfigModule { lambda_expr }) =
+        *db.module_kind(file)
+    else {
+        return None;
+    };
+    let Expr::Lambda(_, Some(pat), _) = &module[lambda_expr] else {
+        return None;
+    };
+    pat.fields
+        .iter()
+        .filter_map(|&(name, _)| name)
+        .find(|&name| module[name].text == "config")
+}
+
+pub(crate) fn option_uses_query(db: &dyn TyDatabase, file: FileId) -> Arc<OptionUses> {
+    let module = db.module(file);
+    let Some(config) = config_param(db, &module, file) else {
+        return Arc::default();
+    };
+    let nameres = db.name_resolution(file);
+
+    // Names of option values with their option paths. `config` itself is the root.
+    // `let cfg = config.foo; in cfg.enable` is also a use of `foo.enable`.
+    let mut aliases = HashMap::from([(config, Vec::new())]);
+    for (_, kind) in module.exprs() {
+        let Expr::LetIn(bindings, _) = kind else {
+            continue;
+        };
+        for &(name, value) in bindings.statics.iter() {
+            let BindingValue::Expr(value) = value else {
+                continue;
+            };
+            let Expr::Select(set, attrpath, None) = &module[value] else {
+                continue;
+            };
+            if nameres.get(*set) != Some(&ResolveResult::Definition(config)) {
+                continue;
+            }
+            if let Some((path, _)) = static_attrpath(db, &module, file, &[], attrpath) {
+                if path.len() == attrpath.len() {
+                    aliases.insert(name, path);
+                }
+            }
+        }
+    }
+
+    let mut uses = Vec::new();
+    for (_, kind) in module.exprs() {
+        let Expr::Select(set, attrpath, _) = kind else {
+            continue;
+        };
+        let Some(ResolveResult::Definition(name)) = nameres.get(*set) else {
+            continue;
+        };
+        let Some(prefix) = aliases.get(name) else {
+            continue;
+        };
+        if let Some((path, attr)) = static_attrpath(db, &module, file, prefix, attrpath) {
+            uses.push(OptionUse {
+                path: path.into(),
+                attr,
+            });
+        }
+    }
+    uses.shrink_to_fit();
+    Arc::new(OptionUses { uses })
+}
+
+/// The static prefix of `attrpath` appended to `prefix`, with the expression of
+/// its last attribute. Constant dynamic attributes like `${name}` are folded.
+fn static_attrpath(
+    db: &dyn TyDatabase,
+    module: &Module,
+    file: FileId,
+    prefix: &[SmolStr],
+    attrpath: &[ExprId],
+) -> Option<(Vec<SmolStr>, ExprId)> {
+    let mut path = prefix.to_vec();
+    let mut last = None;
+    for &attr in attrpath {
+        let text = match &module[attr] {
+            Expr::Literal(Literal::String(text)) => text.clone(),
+            _ => match const_eval_string(db, InFile::new(file, attr)) {
+                Some(text) => text.into(),
+                None => break,
+            },
+        };
+        path.push(text);
+        last = Some(attr);
+    }
+    Some((path, last?))
+}
+
+pub(crate) fn module_imports_query(db: &dyn TyDatabase, file: FileId) -> Arc<[FileId]> {
+    let module = db.module(file);
+    let body = match *db.module_kind(file) {
+        ModuleKind::Config { lambda_expr } | ModuleKind::ConfigModule { lambda_expr } => {
+            lambda_body(&module, lambda_expr)
+        }
+        _ => None,
+    };
+    let Some(Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) = body.map(|e| &module[e])
+    else {
+        return Arc::new([]);
+    };
+    let Some(BindingValue::Expr(list)) = bindings.get("imports", &module) else {
+        return Arc::new([]);
+    };
+    let Expr::List(elems) = &module[list] else {
+        return Arc::new([]);
+    };
+    elems
+        .iter()
+        .filter_map(|&elem| match &module[elem] {
+            &Expr::Literal(Literal::Path(path)) => db.resolve_path_file(path),
+            _ => None,
+        })
+        .collect()
+}
+
+pub(crate) fn source_root_module_graph_query(
+    db: &dyn TyDatabase,
+    sid: SourceRootId,
+) -> Arc<ModuleGraph> {
+    let mut graph = ModuleGraph::default();
+    let source_root = db.source_root(sid);
+    let mut files = source_root
+        .files()
+        .map(|(file, _)| file)
+        .collect::<Vec<_>>();
+    files.sort();
+    for file in files {
+        let imports = db.module_imports(file);
+        if imports.is_empty() {
+            continue;
+        }
+        for &imported in imports.iter() {
+            graph.importers.entry(imported).or_default().push(file);
+        }
+        graph.imports.insert(file, imports.to_vec());
+    }
+    Arc::new(graph)
+}
+
+/// Modules of the NixOS configurations containing `file`, sorted. Modules not composed by
+/// `imports` at all are treated as a single configuration of all such modules.
+pub(crate) fn configuration_files_query(db: &dyn TyDatabase, file: FileId) -> Arc<[FileId]> {
+    let sid = db.file_source_root(file);
+    let graph = db.source_root_module_graph(sid);
+    if graph.contains(file) {
+        return graph.configuration(file).into();
+    }
+    let mut files = db
+        .source_root(sid)
+        .files()
+        .map(|(file, _)| file)
+        .filter(|&file| !graph.contains(file))
+        .collect::<Vec<_>>();
+    files.sort();
+    files.into()
+}
+
+pub(crate) fn option_definition_index_query(
+    db: &dyn TyDatabase,
+    files: Arc<[FileId]>,
+) -> Arc<OptionDefinitionIndex> {
+    let _span = tracing::debug_span!("option_definition_index", files = files.len()).entered();
+    let mut defs = HashMap::<_, Vec<_>>::new();
+    // Files are sorted, to keep the order deterministic.
+    for &file in files.iter() {
+        for def in db.option_definitions(file).iter() {
+            defs.entry(def.path.clone())
+                .or_default()
+                .push(InFile::new(file, def.clone()));
+        }
+    }
+    defs.shrink_to_fit();
+    Arc::new(OptionDefinitionIndex { defs })
+}
+
+pub(crate) fn option_reference_index_query(
+    db: &dyn TyDatabase,
+    sid: SourceRootId,
+) -> Arc<OptionReferenceIndex> {
+    let _span = tracing::debug_span!("option_reference_index", source_root = sid.0).entered();
+    let mut files = db
+        .source_root(sid)
+        .files()
+        .map(|(file, _)| file)
+        .collect::<Vec<_>>();
+    files.sort();
+    let mut refs = HashMap::<_, Vec<_>>::new();
+    for file in files {
+        let mut push = |path: &Arc<[SmolStr]>, r| {
+            refs.entry(path.clone())
+                .or_default()
+                .push(InFile::new(file, r));
+        };
+        for decl in db.option_declarations(file).iter() {
+            push(&decl.path, OptionReference::Declaration(decl.name));
+        }
+        for def in db.option_definitions(file).iter() {
+            push(&def.path, OptionReference::Definition(def.name));
+        }
+        for use_ in db.option_uses(file).iter() {
+            push(&use_.path, OptionReference::Use(use_.attr));
+        }
+    }
+    refs.shrink_to_fit();
+    Arc::new(OptionReferenceIndex { refs })
+}
+
+fn lambda_body(module: &Module, lambda_expr: ExprId) -> Option<ExprId> {
+    let Expr::Lambda(_, _, body) = module[lambda_expr] else {
+        return None;
+    };
+    Some(peel_expr(module, body))
+}
+
+/// Peel wrapper expressions which do not change the value being defined.
+fn peel_expr(module: &Module, expr: ExprId) -> ExprId {
+    std::iter::successors(Some(expr), |&e| match &module[e] {
+        Expr::With(_, inner) | Expr::Assert(_, inner) | Expr::LetIn(_, inner) => Some(*inner),
+        // `mkIf cond value`, `lib.mkIf cond value`.
+        Expr::Apply(func, value) => match &module[*func] {
+            Expr::Apply(func, _) if is_lib_ref(module, *func, "mkIf") => Some(*value),
+            Expr::Apply(func, _) if is_lib_ref(module, *func, "mkOverride") => Some(*value),
+            _ if PRIORITY_MODIFIERS
+                .iter()
+                .any(|&(name, _)| is_lib_ref(module, *func, name)) =>
+            {
+                Some(*value)
+            }
+            _ => None,
+        },
+        _ => None,
+    })
+    .last()
+    .unwrap()
+}
+
+/// Peel one priority modifier of `expr`, returning the priority and the inner value.
+fn peel_priority(module: &Module, expr: ExprId) -> Option<(Priority, ExprId)> {
+    let &Expr::Apply(func, value) = &module[expr] else {
+        return None;
+    };
+    if let &Expr::Apply(func, prio) = &module[func] {
+        if !is_lib_ref(module, func, "mkOverride") {
+            return None;
+        }
+        let value_prio = match &module[prio] {
+            &Expr::Literal(Literal::Int(n)) => u32::try_from(n).ok(),
+            _ => None,
+        };
+        let prio = Priority {
+            value: value_prio,
+            modifier: Some("mkOverride"),
+        };
+        return Some((prio, value));
+    }
+    PRIORITY_MODIFIERS.iter().find_map(|&(name, prio)| {
+        is_lib_ref(module, func, name).then_some((
+            Priority {
+                value: Some(prio),
+                modifier: Some(name),
+            },
+            value,
+        ))
+    })
+}
+
+/// Check if `expr` is `name` or `<anything>.name`.
+fn is_lib_ref(module: &Module, expr: ExprId, name: &str) -> bool {
+    match &module[expr] {
+        Expr::Reference(text) => text == name,
+        Expr::Select(_, path, None) => path.last().map_or(
+            false,
+            |&attr| matches!(&module[attr], Expr::Literal(Literal::String(text)) if text == name),
+        ),
+        _ => false,
+    }
+}
+
+struct DefinitionCtx<'a> {
+    db: &'a dyn TyDatabase,
+    file: FileId,
+    module: &'a Module,
+    path: Vec<SmolStr>,
+    defs: Vec<OptionDefinition>,
+}
+
+impl DefinitionCtx<'_> {
+    /// Special attributes of modules which are not option definitions.
+    const SPECIAL_NAMES: &'static [&'static str] = &["_file", "disabledModules", "imports", "key"];
+
+    fn collect(&mut self, expr: ExprId, name: Option<NameId>, mut priority: Priority) {
+        let module = self.module;
+        let mut expr = expr;
+        loop {
+            match &module[expr] {
+                Expr::With(_, inner) | Expr::Assert(_, inner) | Expr::LetIn(_, inner) => {
+                    expr = *inner;
+                }
+                // `mkIf cond value`.
+                &Expr::Apply(func, value) if matches!(module[func], Expr::Apply(func, _) if is_lib_ref(module, func, "mkIf")) =>
+                {
+                    expr = value;
+                }
+                // `mkMerge [ a b ]`. Each element is a definition.
+                &Expr::Apply(func, list) if is_lib_ref(module, func, "mkMerge") => {
+                    if let Expr::List(elems) = &module[list] {
+                        for &elem in elems.iter() {
+                            self.collect(elem, name, priority);
+                        }
+                    }
+                    return;
+                }
+                _ => match peel_priority(module, expr) {
+                    // The outermost modifier takes effect. Priorities are pushed down into
+                    // attrsets.
+                    Some((prio, value)) => {
+                        if priority.modifier.is_none() {
+                            priority = prio;
+                        }
+                        expr = value;
+                    }
+                    None => break,
+                },
+            }
+        }
+
+        match &module[expr] {
+            Expr::Attrset(bindings) | Expr::RecAttrset(bindings) => {
+                for &(key, value) in bindings.statics.iter() {
+                    let BindingValue::Expr(value) = value else {
+                        continue;
+                    };
+                    let text = &module[key].text;
+                    if self.path.is_empty() && Self::SPECIAL_NAMES.contains(&&**text) {
+                        continue;
+                    }
+                    self.path.push(text.clone());
+                    self.collect(value, Some(key), priority);
+                    self.path.pop();
+                }
+                // Constant dynamic attributes like `${name}`. Leaves without a static name are
+                // skipped.
+                for &(key, value) in bindings.dynamics.iter() {
+                    let Some(text) = const_eval_string(self.db, InFile::new(self.file, key)) else {
+                        continue;
+                    };
+                    if self.path.is_empty() && Self::SPECIAL_NAMES.contains(&&*text) {
+                        continue;
+                    }
+                    self.path.push(text.into());
+                    self.collect(value, None, priority);
+                    self.path.pop();
+                }
+            }
+            _ => {
+                if let Some(name) = name {
+                    self.defs.push(OptionDefinition {
+                        path: self.path.clone().into(),
+                        name,
+                        value: expr,
+                        priority,
+                    });
+                }
+            }
+        }
+    }
+}
+
+struct Ctx<'a> {
+    module: &'a Module,
+    values: HashMap<ExprId, Arc<[String]>>,
+}
+
+impl Ctx<'_> {
+    fn collect_attrset(&mut self, expr: ExprId, fields: &NixosOptions, rest: Option<&OptionTy>) {
+        let (Expr::Attrset(bindings) | Expr::RecAttrset(bindings)) =
+            &self.module[peel_expr(self.module, expr)]
+        else {
+            return;
+        };
+        for &(name, value) in bindings.statics.iter() {
+            let BindingValue::Expr(value) = value else {
+                continue;
+            };
+            let ty = fields
+                .get(&*self.module[name].text)
+                .map(|opt| &opt.ty)
+                .or(rest);
+            if let Some(ty) = ty {
+                self.collect_value(value, ty);
+            }
+        }
+    }
+
+    fn collect_value(&mut self, expr: ExprId, ty: &OptionTy) {
+        let expr = peel_expr(self.module, expr);
+        match ty {
+            // Enums without string values are not checked, since they are likely
+            // numeric or have non-static values.
+            OptionTy::Enum { values } if !values.is_empty() => {
+                if let Expr::Literal(Literal::String(_)) = &self.module[expr] {
+                    self.values.insert(expr, values.clone().into());
+                }
+            }
+            OptionTy::List { elem } => {
+                if let Expr::List(elems) = &self.module[expr] {
+                    for &elem_expr in elems.iter() {
+                        self.collect_value(elem_expr, elem);
+                    }
+                }
+            }
+            OptionTy::Attrset { fields, rest } => {
+                self.collect_attrset(expr, fields, rest.as_deref());
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use expect_test::{expect, Expect};
+    use nix_interop::nixos_options::{NixosOption, NixosOptions, Ty};
+
+    use crate::base::SourceDatabase;
+    use crate::tests::TestDB;
+    use crate::{DefDatabase, OptionSet, TyDatabase};
+
+    fn test_options() -> NixosOptions {
+        let opt = |ty| NixosOption {
+            ty,
+            ..NixosOption::default()
+        };
+        let mode = || {
+            opt(Ty::Enum {
+                values: vec!["fast".into(), "slow".into()],
+            })
+        };
+        NixosOptions::from_iter([(
+            "foo".into(),
+            opt(Ty::Attrset {
+                fields: NixosOptions::from_iter([
+                    ("mode".into(), mode()),
+                    (
+                        "modes".into(),
+                        opt(Ty::List {
+                            elem: Box::new(mode().ty),
+                        }),
+                    ),
+                ]),
+                rest: None,
+            }),
+        )])
+    }
+
+    #[track_caller]
+    fn check(src: &str, expect: Expect) {
+        let (mut db, file) = TestDB::single_file(src).unwrap();
+        db.set_option_set(OptionSet::Nixos, Arc::new(test_options()));
+        let src = db.file_content(file);
+        let source_map = db.source_map(file);
+        let mut got = db
+            .option_enum_values(file)
+            .iter()
+            .map(|(expr, values)| {
+                let range = source_map.node_for_expr(expr).unwrap().text_range();
+                format!("{}: {}\n", &src[range], values.join(","))
+            })
+            .collect::<Vec<_>>();
+        got.sort();
+        expect.assert_eq(&got.concat());
+    }
+
+    #[test]
+    fn config() {
+        check(
+            r#"{ ... }: { foo.mode = "fast"; foo.modes = [ "a" "b" ]; bar = "c"; }"#,
+            expect![[r#"
+                "a": fast,slow
+                "b": fast,slow
+                "fast": fast,slow
+            "#]],
+        );
+    }
+
+    #[test]
+    fn config_module() {
+        check(
+            r#"{ lib, ... }: { options = { }; config = lib.mkIf true { foo.mode = lib.mkDefault "bad"; }; }"#,
+            expect![[r#"
+                "bad": fast,slow
+            "#]],
+        );
+        check(
+            r#"{ lib, ... }: { options = { }; foo.mode = "bad"; }"#,
+            expect![""],
+        );
+    }
+
+    #[test]
+    fn not_module() {
+        check(r#"{ foo.mode = "bad"; }"#, expect![""]);
+    }
+
+    #[test]
+    fn module_graph() {
+        let (db, f) = TestDB::from_fixture(
+            "
+#- /host-a.nix
+{ ... }: { imports = [ ./common.nix ./a ./missing.nix ]; }
+#- /host-b.nix
+{ ... }: { imports = [ ./common.nix ]; }
+#- /common.nix
+{ ... }: { }
+#- /a/default.nix
+{ ... }: { imports = [ ../host-a.nix ]; }
+#- /loose.nix
+{ ... }: { }
+            ",
+        )
+        .unwrap();
+        let check = |file: &str, expect: Expect| {
+            let paths = db
+                .configuration_files(f[file])
+                .iter()
+                .map(|&file| {
+                    format!(
+                        "{}\n",
+                        db.source_root(db.file_source_root(file))
+                            .path_for_file(file)
+                            .display()
+                    )
+                })
+                .collect::<String>();
+            expect.assert_eq(&paths);
+        };
+        check(
+            "/host-a.nix",
+            expect![[r#"
+                /host-a.nix
+                /common.nix
+                /a/default.nix
+            "#]],
+        );
+        check(
+            "/common.nix",
+            expect![[r#"
+                /host-a.nix
+                /host-b.nix
+                /common.nix
+                /a/default.nix
+            "#]],
+        );
+        check(
+            "/loose.nix",
+            expect![[r#"
+                /loose.nix
+            "#]],
+        );
+    }
+
+    #[track_caller]
+    fn check_definitions(src: &str, expect: Expect) {
+        let (db, file) = TestDB::single_file(src).unwrap();
+        let src = db.file_content(file);
+        let source_map = db.source_map(file);
+        let got = db
+            .option_definitions(file)
+            .iter()
+            .map(|def| {
+                let range = source_map.node_for_expr(def.value).unwrap().text_range();
+                format!(
+                    "{} = {}: {}\n",
+                    def.path.join("."),
+                    &src[range],
+                    def.priority
+                )
+            })
+            .collect::<String>();
+        expect.assert_eq(&got);
+    }
+
+    #[test]
+    fn definitions() {
+        check_definitions(
+            r#"
+{ lib, ... }: {
+    imports = [ ];
+    foo.a = 1;
+    foo.b = lib.mkForce 2;
+    bar = lib.mkDefault { c = 3; d = lib.mkOverride 10 4; };
+    baz = lib.mkIf true (lib.mkMerge [ { e = 5; } (lib.mkOverride prio { f = 6; }) ]);
+}
+            "#,
+            expect![[r#"
+                foo.a = 1: priority 100
+                foo.b = 2: priority 50 (`mkForce`)
+                bar.c = 3: priority 1000 (`mkDefault`)
+                bar.d = 4: priority 1000 (`mkDefault`)
+                baz.e = 5: priority 100
+                baz.f = 6: unknown priority (`mkOverride`)
+            "#]],
+        );
+        check_definitions(
+            r#"
+{ lib, ... }: let name = "foo"; in {
+    services.${name}.enable = true;
+    services.${"b" + "ar"} = { port = 1; };
+    services.${lib.unknown}.enable = false;
+}
+            "#,
+            expect![[r#"
+                services.foo.enable = true: priority 100
+                services.bar.port = 1: priority 100
+            "#]],
+        );
+        check_definitions(
+            r#"{ lib, ... }: { options = { }; config.foo = lib.mkVMOverride true; }"#,
+            expect![[r#"
+                foo = true: priority 10 (`mkVMOverride`)
+            "#]],
+        );
+    }
+
+    #[test]
+    fn declarations() {
+        let (db, file) = TestDB::single_file(
+            r#"
+{ lib, pkgs, ... }: {
+    options.foo = {
+        enable = lib.mkEnableOption "foo";
+        package = lib.mkPackageOption pkgs "foo" { };
+        sub.bar = lib.mkOption { type = lib.types.int; };
+        baz = { };
+    };
+    options.qux = mkOption { };
+    config.foo.enable = lib.mkOption { };
+}
+            "#,
+        )
+        .unwrap();
+        let src = db.file_content(file);
+        let source_map = db.source_map(file);
+        let got = db
+            .option_declarations(file)
+            .iter()
+            .map(|decl| {
+                let range = source_map.node_for_expr(decl.value).unwrap().text_range();
+                format!("{} = {}\n", decl.path.join("."), &src[range])
+            })
+            .collect::<String>();
+        expect![[r#"
+            foo.enable = lib.mkEnableOption "foo"
+            foo.package = lib.mkPackageOption pkgs "foo" { }
+            foo.sub.bar = lib.mkOption { type = lib.types.int; }
+            qux = mkOption { }
+        "#]]
+        .assert_eq(&got);
+    }
+}
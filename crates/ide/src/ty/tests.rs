@@ -59,7 +59,7 @@ fn check_all(src: &str, expect: Expect) {
 fn check_all_expect(src: &str, expect_ty: Ty, expect: Expect) {
     let (db, file) = TestDB::single_file(src).unwrap();
     let module = db.module(file);
-    let infer = super::infer::infer_with(&db, file, Some(expect_ty));
+    let infer = super::infer::infer_with(&db, file, Some(expect_ty), 0);
     let got = all_types(&module, &infer);
     expect.assert_eq(&got);
 }
@@ -151,6 +151,8 @@ fn if_then_else() {
 fn lambda() {
     check("a: a", expect!["? → ?"]);
     check("(a: a) 1", expect!["int"]);
+    check("1 |> (a: a)", expect!["int"]);
+    check("(a: a) <| 1", expect!["int"]);
 
     check("{ }: 1", expect!["{ } → int"]);
     check_all(
@@ -348,6 +350,7 @@ fn input_flake_ty() {
                 flake_file: file,
                 input_store_paths: HashMap::new(),
                 input_flake_outputs: HashMap::from_iter([("nixpkgs".into(), nixpkgs_output)]),
+                input_sources: HashMap::new(),
             },
         )]),
     }));
@@ -363,3 +366,106 @@ fn input_flake_ty() {
     expect_output.assert_eq(&ty_for_name("export_output"));
     assert_eq!(ty_for_name("export_pkg_name"), "string");
 }
+
+#[test]
+fn import() {
+    let check_import = |fixture: &str, expect: Expect| {
+        let (db, f) = TestDB::from_fixture(fixture).unwrap();
+        let file = f.files()[0];
+        let ty = db.infer(file).ty_for_expr(db.module(file).entry_expr());
+        expect.assert_eq(&ty.debug().to_string());
+    };
+
+    check_import(
+        "
+#- /default.nix
+let a = import ./a.nix; in { inherit (a) foo; b = a.bar 1; }
+#- /a.nix
+{ foo = 1; bar = x: x + 1; }
+        ",
+        expect!["{ b: int, foo: int }"],
+    );
+
+    // Directories are resolved to `default.nix`.
+    check_import(
+        "
+#- /default.nix
+(import ./lib).foo
+#- /lib/default.nix
+{ foo = \"\"; }
+        ",
+        expect!["string"],
+    );
+
+    // `callPackage` returns the result of the imported function.
+    check_import(
+        "
+#- /default.nix
+{ callPackage }: { foo = callPackage ./foo.nix { }; bar = pkgs.callPackage ./foo.nix { }; }
+#- /foo.nix
+{ stdenv }: { name = \"foo\"; }
+        ",
+        expect!["{ callPackage: path → { } → { name: string } } → { bar: { name: string }, foo: { name: string } }"],
+    );
+
+    // Nested imports are limited by depth.
+    check_import(
+        "
+#- /default.nix
+import ./a.nix
+#- /a.nix
+{ a = import ./b.nix; }
+#- /b.nix
+{ b = import ./c.nix; }
+#- /c.nix
+{ c = import ./d.nix; }
+#- /d.nix
+{ d = 1; }
+        ",
+        expect!["{ a: { b: { c: ? } } }"],
+    );
+
+    // Cyclic imports terminate.
+    check_import(
+        "
+#- /default.nix
+{ self = import ./default.nix; }
+        ",
+        expect!["{ self: { self: { self: { self: ? } } } }"],
+    );
+}
+
+#[test]
+fn mk_shell() {
+    check_name(
+        "packages",
+        "{ pkgs }: pkgs.mkShell { packages = [ ]; shellHook = \"\"; }",
+        expect!["[{ args: [string], builder: string, name: string, system: string }]"],
+    );
+    check("(pkgs.mkShellNoCC { }).name", expect!["string"]);
+}
+
+#[test]
+fn overlay_final() {
+    check_name(
+        "final",
+        "
+final: prev: {
+    foo = { bar = 1; };
+    baz = final.foo.bar + 1;
+    qux = prev.qux;
+}
+        ",
+        expect!["{ baz: int, foo: { bar: int }, qux: ? }"],
+    );
+    check_all(
+        "final: prev: { foo = final.bar; bar = 1; }",
+        expect![[r#"
+            final: { bar: int, foo: ? }
+            prev: ?
+            foo: int
+            bar: int
+            : { bar: int, foo: ? } → ? → { bar: int, foo: int }
+        "#]],
+    );
+}
@@ -0,0 +1,218 @@
+//! Cancellation of in-flight requests via `$/cancelRequest`.
+//!
+//! The layer must be outside `ConcurrencyLayer`, which swallows the notification.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::ops::ControlFlow;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use async_lsp::{
+    AnyEvent, AnyNotification, AnyRequest, ErrorCode, LspService, RequestId, ResponseError,
+};
+use futures::task::AtomicWaker;
+use lsp_types::notification::{Cancel as CancelNotification, Notification};
+use lsp_types::CancelParams;
+use tower::{Layer, Service};
+
+thread_local! {
+    static CURRENT: RefCell<Option<CancelToken>> = RefCell::new(None);
+}
+
+/// The cancellation state of a request.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+    waker: Arc<AtomicWaker>,
+}
+
+impl CancelToken {
+    /// The token of the request being dispatched on this thread, if any.
+    /// Handlers capture it synchronously before spawning their work.
+    pub fn current() -> Option<Self> {
+        CURRENT.with(|cur| cur.borrow().clone())
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    /// The flag to interrupt queries, for `AnalysisHost::snapshot_interruptible`.
+    pub fn flag(&self) -> Arc<AtomicBool> {
+        self.flag.clone()
+    }
+
+    fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+        self.waker.wake();
+    }
+}
+
+pub struct Cancel<S> {
+    service: S,
+    ongoing: HashMap<RequestId, CancelToken>,
+}
+
+impl<S: LspService> Service<AnyRequest> for Cancel<S>
+where
+    S::Error: From<ResponseError>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: AnyRequest) -> Self::Future {
+        // Tokens of finished requests are only referenced here.
+        self.ongoing
+            .retain(|_, token| Arc::strong_count(&token.flag) > 1);
+        let token = CancelToken::default();
+        self.ongoing.insert(req.id.clone(), token.clone());
+
+        let prev = CURRENT.with(|cur| cur.replace(Some(token.clone())));
+        let fut = self.service.call(req);
+        CURRENT.with(|cur| *cur.borrow_mut() = prev);
+
+        ResponseFuture {
+            fut: Box::pin(fut),
+            token,
+        }
+    }
+}
+
+impl<S: LspService> LspService for Cancel<S>
+where
+    S::Error: From<ResponseError>,
+{
+    fn notify(&mut self, notif: AnyNotification) -> ControlFlow<async_lsp::Result<()>> {
+        if notif.method == CancelNotification::METHOD {
+            if let Ok(params) = serde_json::from_value::<CancelParams>(notif.params.clone()) {
+                if let Some(token) = self.ongoing.remove(&params.id) {
+                    token.cancel();
+                }
+            }
+        }
+        self.service.notify(notif)
+    }
+
+    fn emit(&mut self, event: AnyEvent) -> ControlFlow<async_lsp::Result<()>> {
+        self.service.emit(event)
+    }
+}
+
+/// Resolves to `RequestCancelled` once the request is cancelled, dropping the inner future.
+pub struct ResponseFuture<Fut> {
+    fut: Pin<Box<Fut>>,
+    token: CancelToken,
+}
+
+impl<Fut, Response, Error> Future for ResponseFuture<Fut>
+where
+    Fut: Future<Output = Result<Response, Error>>,
+    Error: From<ResponseError>,
+{
+    type Output = Fut::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.token.waker.register(cx.waker());
+        if self.token.is_cancelled() {
+            return Poll::Ready(Err(ResponseError::new(
+                ErrorCode::REQUEST_CANCELLED,
+                "Client cancelled the request",
+            )
+            .into()));
+        }
+        self.fut.as_mut().poll(cx)
+    }
+}
+
+#[derive(Default)]
+pub struct CancelLayer;
+
+impl<S> Layer<S> for CancelLayer {
+    type Service = Cancel<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Cancel {
+            service: inner,
+            ongoing: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CancelLayer, CancelToken};
+    use async_lsp::{AnyEvent, AnyNotification, AnyRequest, ErrorCode, LspService, ResponseError};
+    use futures::future::{self, BoxFuture};
+    use lsp_types::notification::{Cancel, Notification};
+    use lsp_types::{CancelParams, NumberOrString};
+    use std::ops::ControlFlow;
+    use std::task::{Context, Poll};
+    use tower::{Layer, Service};
+
+    /// Never responds, and records the token seen during dispatch.
+    #[derive(Default)]
+    struct Pending {
+        token: Option<CancelToken>,
+    }
+
+    impl Service<AnyRequest> for Pending {
+        type Response = serde_json::Value;
+        type Error = ResponseError;
+        type Future = BoxFuture<'static, Result<serde_json::Value, ResponseError>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _req: AnyRequest) -> Self::Future {
+            self.token = CancelToken::current();
+            Box::pin(future::pending())
+        }
+    }
+
+    impl LspService for Pending {
+        fn notify(&mut self, _notif: AnyNotification) -> ControlFlow<async_lsp::Result<()>> {
+            ControlFlow::Continue(())
+        }
+
+        fn emit(&mut self, _event: AnyEvent) -> ControlFlow<async_lsp::Result<()>> {
+            ControlFlow::Continue(())
+        }
+    }
+
+    #[tokio::test]
+    async fn cancel() {
+        let mut service = CancelLayer.layer(Pending::default());
+        let req = serde_json::from_value::<AnyRequest>(serde_json::json!({
+            "id": 42,
+            "method": "workspace/symbol",
+            "params": {},
+        }))
+        .unwrap();
+        let fut = service.call(req);
+        assert!(CancelToken::current().is_none());
+        let token = service.service.token.clone().unwrap();
+        assert!(!token.is_cancelled());
+
+        let notif = serde_json::from_value::<AnyNotification>(serde_json::json!({
+            "method": Cancel::METHOD,
+            "params": CancelParams {
+                id: NumberOrString::Number(42),
+            },
+        }))
+        .unwrap();
+        assert!(service.notify(notif).is_continue());
+        assert!(token.is_cancelled());
+        let err = fut.await.unwrap_err();
+        assert_eq!(err.code, ErrorCode::REQUEST_CANCELLED);
+        assert!(service.ongoing.is_empty());
+    }
+}
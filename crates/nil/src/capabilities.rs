@@ -1,12 +1,23 @@
+use crate::lsp_ext::{self, ClientCapabilitiesExt, DiagnosticOptions};
 use crate::semantic_tokens::{SEMANTIC_TOKEN_MODIFIERS, SEMANTIC_TOKEN_TYPES};
+use crate::PositionEncoding;
 use lsp_types::{
-    CodeActionProviderCapability, CompletionOptions, DocumentLinkOptions, HoverProviderCapability,
-    InitializeParams, OneOf, RenameOptions, SelectionRangeProviderCapability,
+    CodeActionProviderCapability, CodeLensOptions, CompletionOptions, DocumentLinkOptions,
+    ExecuteCommandOptions, FileOperationFilter, FileOperationPattern,
+    FileOperationRegistrationOptions, HoverProviderCapability, InitializeParams, InlayHintOptions,
+    InlayHintServerCapabilities, LinkedEditingRangeServerCapabilities, OneOf, PositionEncodingKind,
+    RenameOptions, ResourceOperationKind, SaveOptions, SelectionRangeProviderCapability,
     SemanticTokensFullOptions, SemanticTokensLegend, SemanticTokensOptions,
-    SemanticTokensServerCapabilities, ServerCapabilities, TextDocumentSyncCapability,
-    TextDocumentSyncKind, TextDocumentSyncOptions, WorkDoneProgressOptions,
+    SemanticTokensServerCapabilities, ServerCapabilities, SignatureHelpOptions,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions,
+    TextDocumentSyncSaveOptions, WorkDoneProgressOptions,
+    WorkspaceFileOperationsServerCapabilities, WorkspaceFoldersServerCapabilities,
+    WorkspaceServerCapabilities,
 };
 
+/// The identifier of pulled diagnostics.
+const DIAGNOSTIC_IDENTIFIER: &str = "nil";
+
 macro_rules! test {
     ($lhs:ident $(.$field:ident)*) => {
         Some($lhs)
@@ -17,7 +28,8 @@ macro_rules! test {
 
 pub(crate) fn negotiate_capabilities(
     init_params: &InitializeParams,
-) -> (ServerCapabilities, NegotiatedCapabilities) {
+    ext_caps: &ClientCapabilitiesExt,
+) -> (lsp_ext::ServerCapabilities, NegotiatedCapabilities) {
     let client_caps = &init_params.capabilities;
     let is_neovim = init_params
         .client_info
@@ -49,21 +61,94 @@ pub(crate) fn negotiate_capabilities(
                     .relative_pattern_support
             ),
         workspace_configuration: test!(client_caps.workspace.configuration),
+        code_lens_refresh: test!(client_caps.workspace.code_lens.refresh_support),
+        inlay_hint_refresh: test!(client_caps.workspace.inlay_hint.refresh_support),
+        // All deferred fields must be resolvable.
+        completion_resolve: client_caps
+            .text_document
+            .as_ref()
+            .and_then(|caps| caps.completion.as_ref()?.completion_item.as_ref())
+            .and_then(|caps| caps.resolve_support.as_ref())
+            .map_or(false, |support| {
+                ["detail", "documentation", "additionalTextEdits"]
+                    .iter()
+                    .all(|prop| support.properties.iter().any(|p| p == prop))
+            }),
+        completion_snippet: test!(
+            client_caps
+                .text_document
+                .completion
+                .completion_item
+                .snippet_support
+        ),
+        definition_link: test!(client_caps.text_document.definition.link_support),
+        document_changes: test!(client_caps.workspace.workspace_edit.document_changes),
+        resource_operations: client_caps
+            .workspace
+            .as_ref()
+            .and_then(|caps| caps.workspace_edit.as_ref()?.resource_operations.clone())
+            .unwrap_or_default(),
+        server_status_notification: client_caps
+            .experimental
+            .as_ref()
+            .and_then(|caps| caps.get("serverStatusNotification")?.as_bool())
+            .unwrap_or(false),
+        snippet_text_edit: client_caps
+            .experimental
+            .as_ref()
+            .and_then(|caps| caps.get("snippetTextEdit")?.as_bool())
+            .unwrap_or(false),
+        pull_diagnostics: ext_caps
+            .text_document
+            .as_ref()
+            .map_or(false, |caps| caps.diagnostic.is_some()),
+        diagnostic_refresh: test!(ext_caps.workspace.diagnostics.refresh_support),
+        // The first supported one in the client's preference, or the mandatory UTF-16.
+        position_encoding: client_caps
+            .general
+            .as_ref()
+            .and_then(|general| general.position_encodings.as_ref())
+            .and_then(|encodings| {
+                encodings.iter().find_map(|kind| match kind.as_str() {
+                    "utf-8" => Some(PositionEncoding::Utf8),
+                    "utf-16" => Some(PositionEncoding::Utf16),
+                    "utf-32" => Some(PositionEncoding::Utf32),
+                    _ => None,
+                })
+            })
+            .unwrap_or_default(),
     };
 
     let server_caps = ServerCapabilities {
+        position_encoding: Some(match final_caps.position_encoding {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => PositionEncodingKind::UTF32,
+        }),
         text_document_sync: Some(TextDocumentSyncCapability::Options(
             TextDocumentSyncOptions {
                 open_close: Some(true),
                 change: Some(TextDocumentSyncKind::INCREMENTAL),
                 will_save: None,
-                will_save_wait_until: None,
-                save: None,
+                // Gated by `formatting.willSave.enable`, which may change later.
+                will_save_wait_until: Some(true),
+                // The text resyncs the buffer. See `Server::on_did_save`.
+                save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                    include_text: Some(true),
+                })),
             },
         )),
         definition_provider: Some(OneOf::Left(true)),
         completion_provider: Some(CompletionOptions {
-            trigger_characters: Some(vec![".".into(), "?".into()]),
+            // `{` for interpolations after `$`.
+            trigger_characters: Some(vec![
+                ".".into(),
+                "?".into(),
+                "/".into(),
+                "\"".into(),
+                "{".into(),
+            ]),
+            resolve_provider: Some(final_caps.completion_resolve),
             ..Default::default()
         }),
         references_provider: Some(OneOf::Left(true)),
@@ -92,8 +177,60 @@ pub(crate) fn negotiate_capabilities(
         }),
         code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
         document_highlight_provider: Some(OneOf::Left(true)),
+        code_lens_provider: Some(CodeLensOptions {
+            resolve_provider: Some(true),
+        }),
+        inlay_hint_provider: Some(OneOf::Right(InlayHintServerCapabilities::Options(
+            InlayHintOptions {
+                resolve_provider: Some(false),
+                work_done_progress_options: WorkDoneProgressOptions::default(),
+            },
+        ))),
+        linked_editing_range_provider: Some(LinkedEditingRangeServerCapabilities::Simple(true)),
+        // Arguments are separated by spaces. It is triggered explicitly after completions of
+        // functions, see `nil.completion.triggerParameterHints`.
+        signature_help_provider: Some(SignatureHelpOptions {
+            trigger_characters: None,
+            retrigger_characters: Some(vec![" ".into()]),
+            work_done_progress_options: WorkDoneProgressOptions::default(),
+        }),
+        workspace: Some(WorkspaceServerCapabilities {
+            workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                supported: Some(true),
+                change_notifications: Some(OneOf::Left(true)),
+            }),
+            // Path literals referring to renamed files or directories are updated.
+            file_operations: Some(WorkspaceFileOperationsServerCapabilities {
+                will_rename: Some(FileOperationRegistrationOptions {
+                    filters: vec![FileOperationFilter {
+                        scheme: Some("file".into()),
+                        pattern: FileOperationPattern {
+                            glob: "**".into(),
+                            matches: None,
+                            options: None,
+                        },
+                    }],
+                }),
+                ..Default::default()
+            }),
+        }),
+        execute_command_provider: Some(ExecuteCommandOptions {
+            commands: lsp_ext::SERVER_COMMANDS
+                .iter()
+                .map(|&cmd| cmd.into())
+                .collect(),
+            work_done_progress_options: WorkDoneProgressOptions::default(),
+        }),
         ..Default::default()
     };
+    let server_caps = lsp_ext::ServerCapabilities {
+        base: server_caps,
+        diagnostic_provider: final_caps.pull_diagnostics.then(|| DiagnosticOptions {
+            identifier: Some(DIAGNOSTIC_IDENTIFIER.into()),
+            inter_file_dependencies: true,
+            workspace_diagnostics: true,
+        }),
+    };
 
     (server_caps, final_caps)
 }
@@ -105,4 +242,26 @@ pub(crate) struct NegotiatedCapabilities {
     pub watch_files: bool,
     pub watch_files_relative_pattern: bool,
     pub workspace_configuration: bool,
+    pub code_lens_refresh: bool,
+    pub inlay_hint_refresh: bool,
+    /// `detail`, `documentation` and `additionalTextEdits` of completion items can be resolved
+    /// lazily via `completionItem/resolve`.
+    pub completion_resolve: bool,
+    /// Snippets with tab stops are accepted in completion items.
+    pub completion_snippet: bool,
+    /// `LocationLink`s are accepted as results of `textDocument/definition`.
+    pub definition_link: bool,
+    /// Workspace edits can be ordered `documentChanges`, with versioned document edits.
+    pub document_changes: bool,
+    /// File operations accepted in `documentChanges`.
+    pub resource_operations: Vec<ResourceOperationKind>,
+    /// `experimental/serverStatus` is accepted.
+    pub server_status_notification: bool,
+    /// Snippets with tab stops are accepted in edits of code actions.
+    pub snippet_text_edit: bool,
+    /// Diagnostics are pulled by the client, instead of pushed by the server.
+    pub pull_diagnostics: bool,
+    pub diagnostic_refresh: bool,
+    /// The unit of columns in positions.
+    pub position_encoding: PositionEncoding,
 }
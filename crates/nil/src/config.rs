@@ -1,7 +1,16 @@
-use anyhow::ensure;
-use lsp_types::Url;
-use std::collections::HashSet;
-use std::path::PathBuf;
+use anyhow::{ensure, Context};
+use ide::{
+    Diagnostic, LanguageFeature, LibImportStrategy, OptionSet, Severity,
+    DEFAULT_CALL_PACKAGE_NAMES, DEFAULT_SHADOWING_IGNORED_NAMES,
+};
+use lsp_types::{DiagnosticSeverity, Url};
+use nix_interop::package_aliases::NixpkgsVersion;
+use nix_interop::search_path::{SearchPath, SearchPathEntry};
+use nix_interop::store_path::NIX_STORE_DIR;
+use nix_interop::FLAKE_FILE;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
 pub const CONFIG_KEY: &str = "nil";
 
@@ -23,6 +32,9 @@ macro_rules! define_config {
         }
 
         impl $config {
+            /// JSON pointers of all known settings.
+            const POINTERS: &'static [&'static str] = &[$($($pointer,)?)*];
+
             pub fn new(root_path: PathBuf) -> Self {
                 assert!(root_path.is_absolute());
                 Self {
@@ -66,14 +78,100 @@ macro_rules! define_config {
 pub struct Config {
     pub root_path: PathBuf,
 
+    #[parse("/analysis/root", parse = Config::parse_analysis_root)]
+    pub analysis_root: Option<AnalysisRoot>,
+    #[parse("/codeLens/flakeOutputs", default = true)]
+    pub code_lens_flake_outputs: bool,
+    #[parse("/codeLens/references", default = true)]
+    pub code_lens_references: bool,
+    #[parse("/completion/autoImportLib", parse = Config::parse_lib_import_strategy)]
+    pub completion_auto_import_lib: LibImportStrategy,
+    #[parse("/completion/triggerParameterHints")]
+    pub completion_trigger_parameter_hints: bool,
+    #[parse("/completion/eval/enable")]
+    pub completion_eval_enable: bool,
+    #[parse("/completion/eval/timeoutMs", default = 2000)]
+    pub completion_eval_timeout_ms: u64,
+    #[parse("/completion/eval/restrictEval", default = true)]
+    pub completion_eval_restrict_eval: bool,
+    #[parse("/diagnostics/enabled")]
+    pub diagnostics_enabled: HashSet<String>,
     #[parse("/diagnostics/excludedFiles", parse = Config::parse_rooted_file_paths)]
     pub diagnostics_excluded_files: Vec<Url>,
     #[parse("/diagnostics/ignored")]
     pub diagnostics_ignored: HashSet<String>,
+    #[parse("/diagnostics/severity", parse = Config::parse_diagnostics_severity)]
+    pub diagnostics_severity: HashMap<String, SeverityLevel>,
+    #[parse("/diagnostics/shadowedBinding/ignoredNames", default = DEFAULT_SHADOWING_IGNORED_NAMES.iter().map(|&s| s.into()).collect())]
+    pub diagnostics_shadowed_binding_ignored_names: Vec<String>,
+    #[parse("/diagnostics/redundantParen/keptKinds")]
+    pub diagnostics_redundant_paren_kept_kinds: Vec<String>,
+    #[parse("/diagnostics/debounceMs", default = 200)]
+    pub diagnostics_debounce_ms: u64,
+    #[parse("/diagnostics/eval/enable")]
+    pub diagnostics_eval_enable: bool,
+    #[parse("/diagnostics/eval/attribute")]
+    pub diagnostics_eval_attribute: Option<String>,
+    #[parse("/diagnostics/eval/timeoutMs", default = 30000)]
+    pub diagnostics_eval_timeout_ms: u64,
+    #[parse("/documentSymbol/maxDepth")]
+    pub document_symbol_max_depth: Option<usize>,
+    #[parse("/documentSymbol/maxCount", default = Some(10000))]
+    pub document_symbol_max_count: Option<usize>,
+    #[parse("/files/reloadOnSave")]
+    pub files_reload_on_save: bool,
     #[parse("/formatting/command", parse = Config::parse_optional_command)]
     pub formatting_command: Option<Vec<String>>,
+    #[parse("/formatting/willSave/enable")]
+    pub formatting_will_save_enable: bool,
+    #[parse("/formatting/willSave/timeoutMs", default = 1000)]
+    pub formatting_will_save_timeout_ms: u64,
+    #[parse("/gotoDefinition/inheritHops")]
+    pub goto_definition_inherit_hops: bool,
+    #[parse("/indexing/enable", default = true)]
+    pub indexing_enable: bool,
+    #[parse("/indexing/threads")]
+    pub indexing_threads: Option<usize>,
+    #[parse("/indexing/maxFiles", default = 10000)]
+    pub indexing_max_files: usize,
+    #[parse("/indexing/persist", default = true)]
+    pub indexing_persist: bool,
+    #[parse("/inlayHints/bindingTypes", default = true)]
+    pub inlay_hints_binding_types: bool,
+    #[parse("/inlayHints/parameterTypes", default = true)]
+    pub inlay_hints_parameter_types: bool,
     #[parse("/nix/binary", default = "nix".into())]
     pub nix_binary: PathBuf,
+    #[parse("/nix/nixpkgsPath", parse = Config::parse_optional_rooted_path)]
+    pub nix_nixpkgs_path: Option<PathBuf>,
+    #[parse("/nix/searchPath", parse = Config::parse_search_path)]
+    pub nix_search_path: Vec<SearchPathEntry>,
+    #[parse("/nix/callPackageNames", default = DEFAULT_CALL_PACKAGE_NAMES.iter().map(|&s| s.into()).collect(), parse = Config::parse_call_package_names)]
+    pub nix_call_package_names: Vec<String>,
+    #[parse("/nix/experimentalFeatures")]
+    pub nix_experimental_features: Vec<String>,
+    #[parse("/nix/languageFeatures", parse = Config::parse_language_features)]
+    pub nix_language_features: Vec<LanguageFeature>,
+    #[parse("/nix/nixosOptions/file", parse = Config::parse_optional_rooted_path)]
+    pub nix_nixos_options_file: Option<PathBuf>,
+    #[parse("/nix/nixosOptions/modulePaths", parse = Config::parse_rooted_paths)]
+    pub nix_nixos_options_module_paths: Vec<PathBuf>,
+    #[parse("/nix/homeManagerOptions/file", parse = Config::parse_optional_rooted_path)]
+    pub nix_home_manager_options_file: Option<PathBuf>,
+    #[parse("/nix/homeManagerOptions/modulePaths", parse = Config::parse_rooted_paths)]
+    pub nix_home_manager_options_module_paths: Vec<PathBuf>,
+    #[parse("/nix/darwinOptions/file", parse = Config::parse_optional_rooted_path)]
+    pub nix_darwin_options_file: Option<PathBuf>,
+    #[parse("/nix/darwinOptions/modulePaths", parse = Config::parse_rooted_paths)]
+    pub nix_darwin_options_module_paths: Vec<PathBuf>,
+    #[parse("/nix/packageAliases/file", parse = Config::parse_optional_rooted_path)]
+    pub nix_package_aliases_file: Option<PathBuf>,
+    #[parse("/nix/packageAliases/nixpkgsVersion")]
+    pub nix_package_aliases_nixpkgs_version: Option<NixpkgsVersion>,
+    #[parse("/nix/packageIndex/enable")]
+    pub nix_package_index_enable: bool,
+    #[parse("/nix/indexCache/enable", default = true)]
+    pub nix_index_cache_enable: bool,
     #[parse("/nix/maxMemoryMB", default = Some(2048))]
     pub nix_max_memory_mb: Option<u64>,
     #[parse("/nix/flake/autoArchive")]
@@ -82,9 +180,80 @@ pub struct Config {
     pub nix_flake_auto_eval_inputs: bool,
     #[parse("/nix/flake/nixpkgsInputName", default = Some("nixpkgs".into()))]
     pub nix_flake_nixpkgs_input_name: Option<String>,
+    #[parse("/nix/flake/homeManagerInputName", default = Some("home-manager".into()))]
+    pub nix_flake_home_manager_input_name: Option<String>,
+    #[parse("/nix/flake/darwinInputName", default = Some("nix-darwin".into()))]
+    pub nix_flake_darwin_input_name: Option<String>,
+    #[parse("/repl/timeoutMs", default = 30000)]
+    pub repl_timeout_ms: u64,
+    #[parse("/readOnly/nixStore", default = true)]
+    pub read_only_nix_store: bool,
+    #[parse("/readOnly/roots", parse = Config::parse_rooted_paths)]
+    pub read_only_roots: Vec<PathBuf>,
 }
 
 impl Config {
+    /// Whether the file is under a read-only root, which is only indexed for navigation,
+    /// without diagnostics or edits.
+    pub fn is_read_only(&self, uri: &Url) -> bool {
+        let Ok(path) = uri.to_file_path() else {
+            return false;
+        };
+        (self.read_only_nix_store && path.starts_with(NIX_STORE_DIR))
+            || self
+                .read_only_roots
+                .iter()
+                .any(|root| path.starts_with(root))
+    }
+
+    /// Update settings from a JSON file in the same format as the LSP settings under `nil`,
+    /// for CLI commands. Unknown keys and invalid values are reported in `errors`.
+    pub fn update_from_file(
+        &mut self,
+        path: &Path,
+        errors: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        let src =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
+        let value = serde_json::from_str::<serde_json::Value>(&src)
+            .with_context(|| format!("Invalid JSON in {path:?}"))?;
+        errors.extend(
+            Config::unknown_keys(&value)
+                .into_iter()
+                .map(|key| format!("unknown setting `{key}`")),
+        );
+        self.update(value, errors);
+        Ok(())
+    }
+
+    /// Collect all keys in `v` which are not known settings.
+    pub fn unknown_keys(v: &serde_json::Value) -> Vec<String> {
+        fn go(v: &serde_json::Value, pointer: &mut String, ret: &mut Vec<String>) {
+            let Some(obj) = v.as_object() else { return };
+            for (key, value) in obj {
+                let prev_len = pointer.len();
+                pointer.push('/');
+                pointer.push_str(key);
+                if !Config::POINTERS.contains(&&**pointer) {
+                    let is_prefix = Config::POINTERS.iter().any(|p| {
+                        p.strip_prefix(&**pointer)
+                            .map_or(false, |s| s.starts_with('/'))
+                    });
+                    if is_prefix && value.is_object() {
+                        go(value, pointer, ret);
+                    } else {
+                        ret.push(pointer[1..].replace('/', "."));
+                    }
+                }
+                pointer.truncate(prev_len);
+            }
+        }
+
+        let mut ret = Vec::new();
+        go(v, &mut String::new(), &mut ret);
+        ret
+    }
+
     fn parse_rooted_file_paths(&mut self, v: Vec<String>) -> anyhow::Result<Vec<Url>> {
         Ok(v.into_iter()
             .map(|path| {
@@ -93,6 +262,73 @@ impl Config {
             .collect())
     }
 
+    fn parse_rooted_paths(&mut self, v: Vec<String>) -> anyhow::Result<Vec<PathBuf>> {
+        ensure!(v.iter().all(|p| !p.is_empty()), "path must not be empty");
+        Ok(v.into_iter().map(|p| self.root_path.join(p)).collect())
+    }
+
+    fn parse_optional_rooted_path(&mut self, v: Option<String>) -> anyhow::Result<Option<PathBuf>> {
+        let Some(v) = v else { return Ok(None) };
+        ensure!(!v.is_empty(), "path must not be empty");
+        Ok(Some(self.root_path.join(v)))
+    }
+
+    /// `-I`-style entries `prefix=path` or `path`.
+    fn parse_search_path(&mut self, v: Vec<String>) -> anyhow::Result<Vec<SearchPathEntry>> {
+        v.into_iter()
+            .map(|entry| {
+                let (prefix, path) = entry.split_once('=').unwrap_or(("", &entry));
+                ensure!(!path.is_empty(), "path must not be empty");
+                Ok(SearchPathEntry {
+                    prefix: prefix.to_owned(),
+                    path: self.root_path.join(path),
+                })
+            })
+            .collect()
+    }
+
+    fn parse_call_package_names(&mut self, v: Vec<String>) -> anyhow::Result<Vec<String>> {
+        ensure!(v.iter().all(|s| !s.is_empty()), "name must not be empty");
+        Ok(v)
+    }
+
+    fn parse_language_features(&mut self, v: Vec<String>) -> anyhow::Result<Vec<LanguageFeature>> {
+        v.iter()
+            .map(|name| {
+                LanguageFeature::from_name(name).with_context(|| {
+                    let known = LanguageFeature::ALL
+                        .iter()
+                        .map(|feat| feat.name())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    format!("unknown language feature `{name}`, expecting one of {known}")
+                })
+            })
+            .collect()
+    }
+
+    fn parse_analysis_root(&mut self, v: Option<String>) -> anyhow::Result<Option<AnalysisRoot>> {
+        let Some(v) = v else { return Ok(None) };
+        let (file, attrpath) = v.split_once('#').unwrap_or((&v, ""));
+        ensure!(!file.is_empty(), "file path must not be empty");
+        let attrpath = if attrpath.is_empty() {
+            Vec::new()
+        } else {
+            attrpath
+                .split('.')
+                .map(|s| s.to_owned())
+                .collect::<Vec<_>>()
+        };
+        ensure!(
+            attrpath.iter().all(|attr| !attr.is_empty()),
+            "attribute names must not be empty",
+        );
+        Ok(Some(AnalysisRoot {
+            file: self.root_path.join(file),
+            attrpath,
+        }))
+    }
+
     fn parse_optional_command(
         &mut self,
         v: Option<Vec<String>>,
@@ -101,7 +337,418 @@ impl Config {
         Ok(v)
     }
 
+    fn parse_lib_import_strategy(&mut self, v: String) -> anyhow::Result<LibImportStrategy> {
+        Ok(match &*v {
+            "off" => LibImportStrategy::Off,
+            "pattern" => LibImportStrategy::Pattern,
+            "let" => LibImportStrategy::Let,
+            _ => anyhow::bail!("expecting one of \"off\", \"pattern\" and \"let\""),
+        })
+    }
+
+    fn parse_diagnostics_severity(
+        &mut self,
+        v: HashMap<String, SeverityLevel>,
+    ) -> anyhow::Result<HashMap<String, SeverityLevel>> {
+        Ok(v.into_iter()
+            .map(|(kind, level)| (kind.replace('-', "_"), level))
+            .collect())
+    }
+
+    /// Whether the diagnostic should be reported, according to its kind.
+    /// Opt-in kinds are also enabled by a configured severity other than `off`.
+    pub fn diagnostic_enabled(&self, diag: &Diagnostic) -> bool {
+        let is_listed = |set: &HashSet<String>| {
+            set.contains(diag.code())
+                || set.contains(diag.id())
+                || diag.statix_name().map_or(false, |name| set.contains(name))
+        };
+        let level = self.diagnostic_severity_level(diag);
+        !is_listed(&self.diagnostics_ignored)
+            && level != Some(SeverityLevel::Off)
+            && (!diag.is_opt_in() || is_listed(&self.diagnostics_enabled) || level.is_some())
+    }
+
+    /// The LSP severity of the diagnostic, or `None` if it is turned off.
+    pub fn diagnostic_severity(&self, diag: &Diagnostic) -> Option<DiagnosticSeverity> {
+        Some(match self.diagnostic_severity_level(diag) {
+            Some(SeverityLevel::Error) => DiagnosticSeverity::ERROR,
+            Some(SeverityLevel::Warning) => DiagnosticSeverity::WARNING,
+            Some(SeverityLevel::Information) => DiagnosticSeverity::INFORMATION,
+            Some(SeverityLevel::Hint) => DiagnosticSeverity::HINT,
+            Some(SeverityLevel::Off) => return None,
+            None => match diag.severity() {
+                Severity::Error | Severity::IncompleteSyntax => DiagnosticSeverity::ERROR,
+                Severity::Warning => DiagnosticSeverity::WARNING,
+            },
+        })
+    }
+
+    fn diagnostic_severity_level(&self, diag: &Diagnostic) -> Option<SeverityLevel> {
+        let map = &self.diagnostics_severity;
+        map.get(diag.code())
+            .or_else(|| map.get(diag.id()))
+            .or_else(|| map.get(diag.statix_name()?))
+            .copied()
+    }
+
+    /// The maximum depth and total count of symbols in a `documentSymbol` response.
+    pub fn document_symbol_limits(&self) -> (usize, usize) {
+        (
+            self.document_symbol_max_depth.unwrap_or(usize::MAX),
+            self.document_symbol_max_count.unwrap_or(usize::MAX),
+        )
+    }
+
+    /// The search path for `<path>` from `NIX_PATH`, preceded by entries of `nix.searchPath`,
+    /// where `nixpkgs` is overridden by `nix.nixpkgsPath` if set.
+    pub fn search_path(&self) -> SearchPath {
+        let mut search_path = SearchPath::from_env();
+        for entry in self.nix_search_path.iter().rev() {
+            search_path.prepend(entry.prefix.clone(), entry.path.clone());
+        }
+        if let Some(path) = &self.nix_nixpkgs_path {
+            search_path.prepend("nixpkgs", path);
+        }
+        search_path
+    }
+
     pub fn nix_max_memory(&self) -> Option<u64> {
         self.nix_max_memory_mb?.checked_mul(1 << 20)
     }
+
+    /// Experimental syntax enabled by `nix.languageFeatures`, or recognized ones in
+    /// `nix.experimentalFeatures`, which may also contain features unrelated to syntax.
+    pub fn language_features(&self) -> Vec<LanguageFeature> {
+        let mut features = self.nix_language_features.clone();
+        for feat in self
+            .nix_experimental_features
+            .iter()
+            .filter_map(|name| LanguageFeature::from_name(name))
+        {
+            if !features.contains(&feat) {
+                features.push(feat);
+            }
+        }
+        features
+    }
+
+    /// The key of settings of `set` under `nix`.
+    pub fn options_key(set: OptionSet) -> &'static str {
+        match set {
+            OptionSet::Nixos => "nixosOptions",
+            OptionSet::HomeManager => "homeManagerOptions",
+            OptionSet::NixDarwin => "darwinOptions",
+        }
+    }
+
+    /// The prebuilt `options.json` of `set`, loaded instead of evaluating options.
+    pub fn options_file(&self, set: OptionSet) -> Option<&Path> {
+        match set {
+            OptionSet::Nixos => self.nix_nixos_options_file.as_deref(),
+            OptionSet::HomeManager => self.nix_home_manager_options_file.as_deref(),
+            OptionSet::NixDarwin => self.nix_darwin_options_file.as_deref(),
+        }
+    }
+
+    /// Directories whose files are always modules of `set`.
+    pub fn options_module_paths(&self, set: OptionSet) -> &[PathBuf] {
+        match set {
+            OptionSet::Nixos => &self.nix_nixos_options_module_paths,
+            OptionSet::HomeManager => &self.nix_home_manager_options_module_paths,
+            OptionSet::NixDarwin => &self.nix_darwin_options_module_paths,
+        }
+    }
+
+    /// The flake input whose modules declare options of `set`. NixOS ones come from nixpkgs.
+    pub fn options_input_name(&self, set: OptionSet) -> Option<&str> {
+        match set {
+            OptionSet::Nixos => self.nix_flake_nixpkgs_input_name.as_deref(),
+            OptionSet::HomeManager => self.nix_flake_home_manager_input_name.as_deref(),
+            OptionSet::NixDarwin => self.nix_flake_darwin_input_name.as_deref(),
+        }
+    }
+
+    /// Whether the flake workspace should be reloaded after updating from `prev`.
+    pub fn need_reload_flake(&self, prev: &Self) -> bool {
+        self.analysis_root != prev.analysis_root
+            || self.nix_binary != prev.nix_binary
+            || self.nix_nixpkgs_path != prev.nix_nixpkgs_path
+            || OptionSet::ALL
+                .iter()
+                .any(|&set| self.options_file(set) != prev.options_file(set))
+            || self.nix_package_index_enable != prev.nix_package_index_enable
+            || self.nix_max_memory_mb != prev.nix_max_memory_mb
+            || self.nix_flake_auto_archive != prev.nix_flake_auto_archive
+            || self.nix_flake_auto_eval_inputs != prev.nix_flake_auto_eval_inputs
+            || OptionSet::ALL
+                .iter()
+                .any(|&set| self.options_input_name(set) != prev.options_input_name(set))
+    }
+}
+
+/// The configured severity of a diagnostic kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SeverityLevel {
+    Error,
+    Warning,
+    #[serde(alias = "info")]
+    Information,
+    Hint,
+    Off,
+}
+
+/// The expression from which the workspace is analyzed,
+/// written as `path/to/file.nix#attr.path` in the config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnalysisRoot {
+    /// The absolute path of the root file.
+    pub file: PathBuf,
+    /// The attribute path inside the root file. For flakes, it is relative to the outputs.
+    pub attrpath: Vec<String>,
+}
+
+impl AnalysisRoot {
+    /// The option set and the config name if this is `flake.nix#<output>.<name>`, where
+    /// `<output>` is one of `configurations_output`.
+    pub fn configuration(&self) -> Option<(OptionSet, &str)> {
+        let [outputs, name] = &self.attrpath[..] else {
+            return None;
+        };
+        if self.file.file_name()? != FLAKE_FILE {
+            return None;
+        }
+        let set = OptionSet::ALL
+            .iter()
+            .copied()
+            .find(|&set| Self::configurations_output(set) == outputs)?;
+        Some((set, name))
+    }
+
+    /// The flake output of configurations whose options are of `set`.
+    pub fn configurations_output(set: OptionSet) -> &'static str {
+        match set {
+            OptionSet::Nixos => "nixosConfigurations",
+            OptionSet::HomeManager => "homeConfigurations",
+            OptionSet::NixDarwin => "darwinConfigurations",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use ide::{Diagnostic, DiagnosticKind, LanguageFeature, OptionSet};
+    use lsp_types::{DiagnosticSeverity, Url};
+    use std::path::{Path, PathBuf};
+    use text_size::TextRange;
+
+    #[test]
+    fn analysis_root_configuration() {
+        let root = |v: &str| {
+            let mut config = Config::new(PathBuf::from("/root"));
+            let mut errors = Vec::new();
+            config.update(
+                serde_json::json!({ "analysis": { "root": v } }),
+                &mut errors,
+            );
+            assert_eq!(errors, Vec::<String>::new());
+            config.analysis_root.unwrap()
+        };
+        assert_eq!(
+            root("flake.nix#nixosConfigurations.host").configuration(),
+            Some((OptionSet::Nixos, "host")),
+        );
+        assert_eq!(
+            root("flake.nix#homeConfigurations.me").configuration(),
+            Some((OptionSet::HomeManager, "me")),
+        );
+        assert_eq!(
+            root("flake.nix#darwinConfigurations.mac").configuration(),
+            Some((OptionSet::NixDarwin, "mac")),
+        );
+        assert_eq!(
+            root("flake.nix#packages.x86_64-linux.foo").configuration(),
+            None
+        );
+        assert_eq!(
+            root("default.nix#nixosConfigurations.host").configuration(),
+            None
+        );
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let v = serde_json::json!({
+            "formatting": { "command": ["nixpkgs-fmt"], "foo": 1 },
+            "nix": { "flake": { "autoArchive": true, "bar": { "baz": 1 } } },
+            "diagnostics": 1,
+            "qux": null,
+        });
+        let mut got = Config::unknown_keys(&v);
+        got.sort();
+        assert_eq!(
+            got,
+            ["diagnostics", "formatting.foo", "nix.flake.bar", "qux"],
+        );
+    }
+
+    #[test]
+    fn option_sets() {
+        let mut config = Config::new(PathBuf::from("/root"));
+        let mut errors = Vec::new();
+        config.update(
+            serde_json::json!({ "nix": {
+                "homeManagerOptions": { "file": "hm.json", "modulePaths": ["home", "/other/home"] },
+                "flake": { "darwinInputName": "darwin" },
+            } }),
+            &mut errors,
+        );
+        assert_eq!(errors, Vec::<String>::new());
+        assert_eq!(
+            config.options_file(OptionSet::HomeManager),
+            Some(Path::new("/root/hm.json")),
+        );
+        assert_eq!(config.options_file(OptionSet::NixDarwin), None);
+        assert_eq!(
+            config.options_module_paths(OptionSet::HomeManager),
+            [PathBuf::from("/root/home"), PathBuf::from("/other/home")],
+        );
+        assert_eq!(
+            config.options_input_name(OptionSet::HomeManager),
+            Some("home-manager"),
+        );
+        assert_eq!(
+            config.options_input_name(OptionSet::NixDarwin),
+            Some("darwin")
+        );
+    }
+
+    #[test]
+    fn language_features() {
+        let mut config = Config::new(PathBuf::from("/"));
+        let mut errors = Vec::new();
+        config.update(
+            serde_json::json!({ "nix": { "experimentalFeatures": ["flakes", "pipe-operators"] } }),
+            &mut errors,
+        );
+        assert_eq!(errors, Vec::<String>::new());
+        assert_eq!(config.language_features(), [LanguageFeature::PipeOperators]);
+
+        let mut config = Config::new(PathBuf::from("/"));
+        config.update(
+            serde_json::json!({ "nix": { "languageFeatures": ["pipe-operators", "flakes"] } }),
+            &mut errors,
+        );
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        assert!(
+            errors[0].contains("unknown language feature `flakes`"),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn diagnostic_severity() {
+        let mut config = Config::new(PathBuf::from("/"));
+        let mut errors = Vec::new();
+        config.update(
+            serde_json::json!({
+                "diagnostics": {
+                    "severity": {
+                        "unused-binding": "hint",
+                        "W011": "error",
+                        "uri_literal": "off",
+                        "conflicting_definition": "info",
+                        "useless-parens": "hint",
+                    },
+                    "enabled": ["eta_reduction"],
+                },
+            }),
+            &mut errors,
+        );
+        assert_eq!(errors, Vec::<String>::new());
+
+        let diag = |kind| Diagnostic::new(TextRange::default(), kind);
+        let got = [
+            DiagnosticKind::UnusedBinding,
+            DiagnosticKind::UnusedWith,
+            DiagnosticKind::UriLiteral,
+            DiagnosticKind::ConflictingDefinition,
+            DiagnosticKind::UnusedRec,
+            DiagnosticKind::RedundantParen,
+            DiagnosticKind::EtaReduction,
+            DiagnosticKind::ManualInherit,
+        ]
+        .map(|kind| {
+            let diag = diag(kind);
+            (
+                config.diagnostic_enabled(&diag),
+                config.diagnostic_severity(&diag),
+            )
+        });
+        assert_eq!(
+            got,
+            [
+                (true, Some(DiagnosticSeverity::HINT)),
+                (true, Some(DiagnosticSeverity::ERROR)),
+                (false, None),
+                (true, Some(DiagnosticSeverity::INFORMATION)),
+                (true, Some(DiagnosticSeverity::WARNING)),
+                (true, Some(DiagnosticSeverity::HINT)),
+                (true, Some(DiagnosticSeverity::WARNING)),
+                (false, Some(DiagnosticSeverity::WARNING)),
+            ],
+        );
+    }
+
+    #[test]
+    fn read_only() {
+        let mut config = Config::new(PathBuf::from("/ws"));
+        let mut errors = Vec::new();
+        config.update(
+            serde_json::json!({ "readOnly": { "roots": ["vendor"] } }),
+            &mut errors,
+        );
+        assert_eq!(errors, Vec::<String>::new());
+
+        let is_read_only = |path: &str| config.is_read_only(&Url::from_file_path(path).unwrap());
+        assert!(is_read_only("/nix/store/eeee-source/default.nix"));
+        assert!(is_read_only("/ws/vendor/nixpkgs/default.nix"));
+        assert!(!is_read_only("/ws/vendored.nix"));
+        assert!(!is_read_only("/ws/default.nix"));
+
+        config.read_only_nix_store = false;
+        assert!(!config.is_read_only(&Url::from_file_path("/nix/store/eeee-source").unwrap()));
+    }
+
+    #[test]
+    fn search_path() {
+        let mut config = Config::new(PathBuf::from("/ws"));
+        let mut errors = Vec::new();
+        config.update(
+            serde_json::json!({
+                "nix": {
+                    "nixpkgsPath": "/nixpkgs",
+                    "searchPath": ["nixos-config=configuration.nix", "/channels"],
+                },
+            }),
+            &mut errors,
+        );
+        assert_eq!(errors, Vec::<String>::new());
+
+        let search_path = config.search_path();
+        let got = search_path.entries()[..3]
+            .iter()
+            .map(|entry| (&*entry.prefix, entry.path.to_str().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            got,
+            [
+                ("nixpkgs", "/nixpkgs"),
+                ("nixos-config", "/ws/configuration.nix"),
+                ("", "/channels"),
+            ],
+        );
+    }
 }
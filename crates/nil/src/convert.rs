@@ -1,19 +1,26 @@
-use crate::{semantic_tokens, LineMap, Result, Vfs};
+use crate::capabilities::NegotiatedCapabilities;
+use crate::config::Config;
+use crate::{lsp_ext, semantic_tokens, LineMap, Result, UrlExt, Vfs};
 use async_lsp::{ErrorCode, ResponseError};
 use ide::{
-    Assist, AssistKind, CompletionItem, CompletionItemKind, Diagnostic, FileId, FilePos, FileRange,
-    HlRange, HlRelated, HoverResult, Link, LinkTarget, NameKind, Severity, SymbolTree, TextEdit,
-    WorkspaceEdit,
+    Assist, AssistKind, CodeLens, CompletionCommand, CompletionItem, CompletionItemKind,
+    Diagnostic, FileId, FilePos, FileRange, FileSystemEdit, HlRange, HlRelated, HoverResult,
+    InlayHint, Link, LinkTarget, NameKind, NavigationTarget, RenameError, SignatureHelp,
+    SymbolTree, SymbolValueKind, TextEdit, WorkspaceEdit,
 };
 use lsp_types::{
-    self as lsp, CodeAction, CodeActionKind, CodeActionOrCommand, DiagnosticRelatedInformation,
+    self as lsp, CodeAction, CodeActionKind, CodeDescription, DiagnosticRelatedInformation,
     DiagnosticSeverity, DiagnosticTag, DocumentHighlight, DocumentHighlightKind, DocumentLink,
-    DocumentSymbol, Documentation, Hover, Location, MarkupContent, MarkupKind, NumberOrString,
-    Position, PrepareRenameResponse, Range, SemanticToken, SymbolKind, TextDocumentIdentifier,
-    TextDocumentPositionParams, Url,
+    DocumentSymbol, Documentation, Hover, Location, LocationLink, MarkupContent, MarkupKind,
+    NumberOrString, Position, PrepareRenameResponse, Range, SemanticToken, SymbolKind,
+    TextDocumentIdentifier, TextDocumentPositionParams, Url,
 };
+use nix_interop::eval_error::{ErrorLocation, EvalError};
 use nix_interop::DEFAULT_IMPORT_FILE;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use syntax::semantic::escape_literal_attr;
 use text_size::{TextRange, TextSize};
 
 pub(crate) fn from_file(vfs: &Vfs, doc: &TextDocumentIdentifier) -> Result<(FileId, Arc<LineMap>)> {
@@ -52,28 +59,42 @@ pub(crate) fn to_location(vfs: &Vfs, frange: FileRange) -> Location {
     Location::new(uri, to_range(&line_map, frange.range))
 }
 
+pub(crate) fn to_location_link(vfs: &Vfs, target: NavigationTarget) -> LocationLink {
+    let line_map = vfs.line_map_for_file(target.file_id);
+    LocationLink {
+        origin_selection_range: None,
+        target_uri: vfs.uri_for_file(target.file_id),
+        target_range: to_range(&line_map, target.full_range),
+        target_selection_range: to_range(&line_map, target.focus_range),
+    }
+}
+
 pub(crate) fn to_range(line_map: &LineMap, range: TextRange) -> Range {
     let (line1, col1) = line_map.line_col_for_pos(range.start());
     let (line2, col2) = line_map.line_col_for_pos(range.end());
     Range::new(Position::new(line1, col1), Position::new(line2, col2))
 }
 
+/// Convert diagnostics of `file`, keyed by the file they are located in.
+/// Notes in other files are hoisted to hints of those files.
 pub(crate) fn to_diagnostics(
+    vfs: &Vfs,
+    config: &Config,
     uri: &Url,
     file: FileId,
     line_map: &LineMap,
     diags: &[Diagnostic],
-) -> Vec<lsp::Diagnostic> {
+) -> HashMap<Url, Vec<lsp::Diagnostic>> {
     let mut ret = Vec::with_capacity(diags.len() * 2);
+    let mut foreign = HashMap::<Url, Vec<lsp::Diagnostic>>::new();
     for diag in diags {
         let primary_diag = lsp::Diagnostic {
-            severity: match diag.severity() {
-                Severity::Error | Severity::IncompleteSyntax => Some(DiagnosticSeverity::ERROR),
-                Severity::Warning => Some(DiagnosticSeverity::WARNING),
-            },
+            severity: config.diagnostic_severity(diag),
             range: to_range(line_map, diag.range),
             code: Some(NumberOrString::String(diag.code().into())),
-            code_description: None,
+            code_description: Url::parse(&diag.doc_url())
+                .ok()
+                .map(|href| CodeDescription { href }),
             source: None,
             message: diag.message(),
             related_information: {
@@ -81,7 +102,11 @@ pub(crate) fn to_diagnostics(
                     diag.notes
                         .iter()
                         .map(|(frange, msg)| DiagnosticRelatedInformation {
-                            location: Location::new(uri.clone(), to_range(line_map, frange.range)),
+                            location: if frange.file_id == file {
+                                Location::new(uri.clone(), to_range(line_map, frange.range))
+                            } else {
+                                to_location(vfs, *frange)
+                            },
                             message: msg.to_owned(),
                         })
                         .collect(),
@@ -102,14 +127,19 @@ pub(crate) fn to_diagnostics(
 
         // Hoist related information to top-level Hints.
         for (frange, msg) in &diag.notes {
-            // We cannot handle cross-file diagnostics here.
-            if frange.file_id != file {
-                continue;
-            }
-
-            ret.push(lsp::Diagnostic {
+            let (hints, range) = if frange.file_id == file {
+                (&mut ret, to_range(line_map, frange.range))
+            } else {
+                let note_uri = vfs.uri_for_file(frange.file_id);
+                let note_line_map = vfs.line_map_for_file(frange.file_id);
+                (
+                    foreign.entry(note_uri).or_default(),
+                    to_range(&note_line_map, frange.range),
+                )
+            };
+            hints.push(lsp::Diagnostic {
                 severity: Some(DiagnosticSeverity::HINT),
-                range: to_range(line_map, frange.range),
+                range,
                 code: primary_diag.code.clone(),
                 code_description: primary_diag.code_description.clone(),
                 source: primary_diag.source.clone(),
@@ -126,10 +156,76 @@ pub(crate) fn to_diagnostics(
         ret.push(primary_diag);
     }
 
-    ret
+    foreign.insert(uri.clone(), ret);
+    foreign
+}
+
+/// Convert the evaluation error from Nix, keyed by the file it is located in, or `fallback_uri`
+/// if it has no location. Files in the error are translated by `map_path` first, eg. from the
+/// store back to the workspace.
+pub(crate) fn to_eval_diagnostics(
+    vfs: &Vfs,
+    err: &EvalError,
+    fallback_uri: &Url,
+    map_path: impl Fn(&Path) -> PathBuf,
+) -> HashMap<Url, Vec<lsp::Diagnostic>> {
+    let to_location = |loc: &ErrorLocation| {
+        let uri = Url::from_file_path(map_path(&loc.file)).ok()?;
+        let (line, col) = (loc.line.saturating_sub(1), loc.column.saturating_sub(1));
+        let pos = Position::new(line, col);
+        let range = (|| {
+            // Columns from Nix count bytes. Highlight the word at the position, if any.
+            let file = vfs.file_for_uri(&uri).ok()?;
+            let src = vfs.content_for_file(file);
+            let line_map = vfs.line_map_for_file(file);
+            let start = usize::from(line_map.pos_for_line_col(line, 0)) + col as usize;
+            let rest = src.get(start..)?;
+            let is_word_char = |c: char| c.is_alphanumeric() || "_'-".contains(c);
+            let len = match rest.find(|c| !is_word_char(c)).unwrap_or(rest.len()) {
+                0 => rest.chars().next().map_or(0, char::len_utf8),
+                len => len,
+            };
+            let start = TextSize::try_from(start).ok()?;
+            let end = start + TextSize::try_from(len).ok()?;
+            Some(to_range(&line_map, TextRange::new(start, end)))
+        })()
+        .unwrap_or(Range::new(pos, pos));
+        Some(Location::new(uri, range))
+    };
+
+    let location = err.location().and_then(to_location).unwrap_or_else(|| {
+        let start = Position::new(0, 0);
+        Location::new(fallback_uri.clone(), Range::new(start, start))
+    });
+    let related_information = err
+        .trace
+        .iter()
+        .filter_map(|frame| {
+            Some(DiagnosticRelatedInformation {
+                location: to_location(frame.location.as_ref()?)?,
+                message: frame.message.clone(),
+            })
+        })
+        .collect();
+    let diag = lsp::Diagnostic {
+        severity: Some(DiagnosticSeverity::ERROR),
+        range: location.range,
+        code: Some(NumberOrString::String("eval_error".into())),
+        code_description: None,
+        source: Some("nix".into()),
+        message: err.error.message.clone(),
+        related_information: Some(related_information),
+        tags: None,
+        data: None,
+    };
+    HashMap::from([(location.uri, vec![diag])])
 }
 
-pub(crate) fn to_completion_item(line_map: &LineMap, item: CompletionItem) -> lsp::CompletionItem {
+pub(crate) fn to_completion_item(
+    config: &Config,
+    line_map: &LineMap,
+    item: CompletionItem,
+) -> lsp::CompletionItem {
     let kind = match item.kind {
         CompletionItemKind::Keyword => lsp::CompletionItemKind::KEYWORD,
         CompletionItemKind::Param => lsp::CompletionItemKind::VARIABLE,
@@ -138,12 +234,23 @@ pub(crate) fn to_completion_item(line_map: &LineMap, item: CompletionItem) -> ls
         CompletionItemKind::BuiltinConst => lsp::CompletionItemKind::CONSTANT,
         CompletionItemKind::BuiltinFunction => lsp::CompletionItemKind::FUNCTION,
         CompletionItemKind::BuiltinAttrset => lsp::CompletionItemKind::CLASS,
+        CompletionItemKind::EnumMember => lsp::CompletionItemKind::ENUM_MEMBER,
+        CompletionItemKind::Input => lsp::CompletionItemKind::MODULE,
+        CompletionItemKind::Package => lsp::CompletionItemKind::MODULE,
+        CompletionItemKind::Snippet => lsp::CompletionItemKind::SNIPPET,
+        CompletionItemKind::File => lsp::CompletionItemKind::FILE,
+        CompletionItemKind::Folder => lsp::CompletionItemKind::FOLDER,
+        CompletionItemKind::Evaluated => lsp::CompletionItemKind::FIELD,
     };
     lsp::CompletionItem {
+        filter_text: Some(item.filter_text().into()),
         label: item.label.into(),
         kind: Some(kind),
         insert_text: None,
-        insert_text_format: Some(lsp::InsertTextFormat::PLAIN_TEXT),
+        insert_text_format: Some(match item.kind {
+            CompletionItemKind::Snippet => lsp::InsertTextFormat::SNIPPET,
+            _ => lsp::InsertTextFormat::PLAIN_TEXT,
+        }),
         // We don't support indentation yet.
         insert_text_mode: Some(lsp::InsertTextMode::ADJUST_INDENTATION),
         text_edit: Some(lsp::CompletionTextEdit::Edit(lsp::TextEdit {
@@ -159,15 +266,89 @@ pub(crate) fn to_completion_item(line_map: &LineMap, item: CompletionItem) -> ls
         }),
         label_details: Some(lsp::CompletionItemLabelDetails {
             detail: item.signature.map(|sig| format!(": {sig}")),
-            description: None,
+            description: (item.kind == CompletionItemKind::Evaluated).then(|| "evaluated".into()),
+        }),
+        additional_text_edits: (!item.additional_edits.is_empty()).then(|| {
+            item.additional_edits
+                .into_iter()
+                .map(|edit| to_text_edit(line_map, edit))
+                .collect()
+        }),
+        command: item.command.and_then(|cmd| match cmd {
+            CompletionCommand::TriggerParameterHints => config
+                .completion_trigger_parameter_hints
+                .then(|| lsp::Command {
+                    title: "Trigger parameter hints".into(),
+                    command: "editor.action.triggerParameterHints".into(),
+                    arguments: None,
+                }),
         }),
-
         ..lsp::CompletionItem::default()
     }
 }
 
-pub(crate) fn to_rename_error(message: String) -> ResponseError {
-    ResponseError::new(ErrorCode::REQUEST_FAILED, message)
+/// Expensive fields of a completion item, sent on `completionItem/resolve`.
+#[derive(Debug, Clone)]
+pub(crate) struct DeferredCompletion {
+    detail: Option<String>,
+    documentation: Option<Documentation>,
+    additional_text_edits: Option<Vec<lsp::TextEdit>>,
+}
+
+/// Take the expensive fields of a completion item out, if any.
+/// The item is referred in `data` by the `generation` of the response and its `index` in it.
+pub(crate) fn defer_completion_item(
+    item: &mut lsp::CompletionItem,
+    generation: u64,
+    index: usize,
+) -> Option<DeferredCompletion> {
+    if item.detail.is_none() && item.documentation.is_none() && item.additional_text_edits.is_none()
+    {
+        return None;
+    }
+    item.data = Some(serde_json::json!({
+        "generation": generation,
+        "index": index,
+    }));
+    Some(DeferredCompletion {
+        detail: item.detail.take(),
+        documentation: item.documentation.take(),
+        additional_text_edits: item.additional_text_edits.take(),
+    })
+}
+
+pub(crate) fn resolve_completion_item(
+    item: &mut lsp::CompletionItem,
+    deferred: DeferredCompletion,
+) {
+    item.detail = deferred.detail;
+    item.documentation = deferred.documentation;
+    item.additional_text_edits = deferred.additional_text_edits;
+}
+
+pub(crate) fn from_completion_data(data: Option<serde_json::Value>) -> Option<(u64, usize)> {
+    let data = data?;
+    let generation = data.get("generation")?.as_u64()?;
+    let index = data.get("index")?.as_u64()?.try_into().ok()?;
+    Some((generation, index))
+}
+
+/// Conflicting locations are attached as `{ "conflicts": [DiagnosticRelatedInformation] }`
+/// in the error data.
+pub(crate) fn to_rename_error(vfs: &Vfs, err: RenameError) -> ResponseError {
+    let mut resp = ResponseError::new(ErrorCode::REQUEST_FAILED, err.message);
+    if !err.conflicts.is_empty() {
+        let conflicts = err
+            .conflicts
+            .into_iter()
+            .map(|(frange, message)| DiagnosticRelatedInformation {
+                location: to_location(vfs, frange),
+                message,
+            })
+            .collect::<Vec<_>>();
+        resp.data = Some(serde_json::json!({ "conflicts": conflicts }));
+    }
+    resp
 }
 
 pub(crate) fn to_prepare_rename_response(
@@ -182,29 +363,132 @@ pub(crate) fn to_prepare_rename_response(
     }
 }
 
-pub(crate) fn to_workspace_edit(vfs: &Vfs, ws_edit: WorkspaceEdit) -> lsp::WorkspaceEdit {
-    let content_edits = ws_edit
+/// Whether the client can apply all file system edits of `ws_edit`.
+pub(crate) fn is_workspace_edit_supported(
+    caps: &NegotiatedCapabilities,
+    ws_edit: &WorkspaceEdit,
+) -> bool {
+    ws_edit.file_system_edits.iter().all(|edit| {
+        let kind = match edit {
+            FileSystemEdit::CreateFile { .. } => lsp::ResourceOperationKind::Create,
+            FileSystemEdit::RenameFile { .. } => lsp::ResourceOperationKind::Rename,
+        };
+        caps.document_changes && caps.resource_operations.contains(&kind)
+    })
+}
+
+/// Convert to `documentChanges` if the client accepts them, so that edits are rejected if
+/// documents are changed in the meantime. Otherwise, file system edits are dropped.
+pub(crate) fn to_workspace_edit(
+    vfs: &Vfs,
+    caps: &NegotiatedCapabilities,
+    ws_edit: WorkspaceEdit,
+) -> lsp::WorkspaceEdit {
+    let mut content_edits = ws_edit
         .content_edits
         .into_iter()
         .map(|(file, edits)| {
-            let uri = vfs.uri_for_file(file);
+            let line_map = vfs.line_map_for_file(file);
             let edits = edits
                 .into_iter()
-                .map(|edit| {
-                    let line_map = vfs.line_map_for_file(file);
-                    to_text_edit(&line_map, edit)
-                })
-                .collect();
-            (uri, edits)
+                .map(|edit| to_text_edit(&line_map, edit))
+                .collect::<Vec<_>>();
+            (file, edits)
         })
-        .collect();
+        .collect::<Vec<_>>();
+
+    if !caps.document_changes {
+        if !ws_edit.file_system_edits.is_empty() {
+            tracing::warn!("File system edits are dropped, since `documentChanges` are rejected");
+        }
+        return lsp::WorkspaceEdit {
+            changes: Some(
+                content_edits
+                    .into_iter()
+                    .map(|(file, edits)| (vfs.uri_for_file(file), edits))
+                    .collect(),
+            ),
+            document_changes: None,
+            change_annotations: None,
+        };
+    }
+
+    content_edits.sort_by_key(|(file, _)| *file);
+    let mut ops = content_edits
+        .into_iter()
+        .map(|(file, edits)| {
+            text_document_edit(vfs.uri_for_file(file), vfs.file_version(file), edits)
+        })
+        .collect::<Vec<_>>();
+    for edit in ws_edit.file_system_edits {
+        match edit {
+            FileSystemEdit::CreateFile { path, content } => {
+                let uri = Url::from_vfs_path(&path);
+                ops.push(lsp::DocumentChangeOperation::Op(lsp::ResourceOp::Create(
+                    lsp::CreateFile {
+                        uri: uri.clone(),
+                        options: None,
+                        annotation_id: None,
+                    },
+                )));
+                if !content.is_empty() {
+                    let edit = lsp::TextEdit {
+                        range: Range::default(),
+                        new_text: content,
+                    };
+                    ops.push(text_document_edit(uri, None, vec![edit]));
+                }
+            }
+            FileSystemEdit::RenameFile { file, to } => {
+                ops.push(lsp::DocumentChangeOperation::Op(lsp::ResourceOp::Rename(
+                    lsp::RenameFile {
+                        old_uri: vfs.uri_for_file(file),
+                        new_uri: Url::from_vfs_path(&to),
+                        options: None,
+                        annotation_id: None,
+                    },
+                )));
+            }
+        }
+    }
+    lsp::WorkspaceEdit {
+        changes: None,
+        document_changes: Some(lsp::DocumentChanges::Operations(ops)),
+        change_annotations: None,
+    }
+}
+
+/// Like `to_workspace_edit`, but for `edits` of a single document already converted when it was
+/// at `version`, eg. before a long-running computation of the new text.
+pub(crate) fn to_file_workspace_edit(
+    caps: &NegotiatedCapabilities,
+    uri: Url,
+    version: Option<i32>,
+    edits: Vec<lsp::TextEdit>,
+) -> lsp::WorkspaceEdit {
+    if !caps.document_changes {
+        return lsp::WorkspaceEdit::new([(uri, edits)].into_iter().collect());
+    }
     lsp::WorkspaceEdit {
-        changes: Some(content_edits),
-        document_changes: None,
+        changes: None,
+        document_changes: Some(lsp::DocumentChanges::Operations(vec![text_document_edit(
+            uri, version, edits,
+        )])),
         change_annotations: None,
     }
 }
 
+fn text_document_edit(
+    uri: Url,
+    version: Option<i32>,
+    edits: Vec<lsp::TextEdit>,
+) -> lsp::DocumentChangeOperation {
+    lsp::DocumentChangeOperation::Edit(lsp::TextDocumentEdit {
+        text_document: lsp::OptionalVersionedTextDocumentIdentifier { uri, version },
+        edits: edits.into_iter().map(lsp::OneOf::Left).collect(),
+    })
+}
+
 pub(crate) fn to_text_edit(line_map: &LineMap, edit: TextEdit) -> lsp::TextEdit {
     lsp::TextEdit {
         range: to_range(line_map, edit.delete),
@@ -260,12 +544,37 @@ pub(crate) fn to_semantic_tokens(line_map: &LineMap, hls: &[HlRange]) -> Vec<Sem
     toks
 }
 
-pub(crate) fn to_hover(line_map: &LineMap, hover: HoverResult) -> Hover {
+pub(crate) fn to_hover(vfs: &Vfs, line_map: &LineMap, hover: HoverResult) -> Hover {
+    let mut value = hover.markup;
+    if let Some(def) = hover.definition {
+        let mut uri = vfs.uri_for_file(def.target.file_id);
+        let (line, _) = vfs
+            .line_map_for_file(def.target.file_id)
+            .line_col_for_pos(def.target.focus_range.start());
+        let line = line + 1;
+        // Relative to the workspace folder, like `./modules/foo.nix`.
+        let path = uri.to_file_path().ok().and_then(|path| {
+            let root = vfs.root_for_path(&path)?;
+            let rel = path.strip_prefix(root).ok()?;
+            Some(format!("./{}", rel.display()))
+        });
+        let path = path.unwrap_or_else(|| uri.to_string());
+        uri.set_fragment(Some(&format!("L{line}")));
+        let fence = if def.snippet.contains("```") {
+            "````"
+        } else {
+            "```"
+        };
+        value += &format!(
+            "\n\n{fence}nix\n{}\n{fence}\nDefined in [{path}:{line}]({uri})",
+            def.snippet,
+        );
+    }
     Hover {
         range: Some(to_range(line_map, hover.range)),
         contents: lsp::HoverContents::Markup(MarkupContent {
             kind: MarkupKind::Markdown,
-            value: hover.markup,
+            value,
         }),
     }
 }
@@ -283,10 +592,13 @@ fn to_document_symbol(line_map: &LineMap, sym: SymbolTree) -> DocumentSymbol {
     #[allow(deprecated)]
     DocumentSymbol {
         name: sym.name.into(),
-        detail: None,
-        kind: match sym.kind {
-            NameKind::PlainAttrset | NameKind::RecAttrset => SymbolKind::FIELD,
-            NameKind::LetIn | NameKind::Param | NameKind::PatField => SymbolKind::VARIABLE,
+        detail: sym.detail,
+        kind: match (sym.value_kind, sym.kind) {
+            (SymbolValueKind::Lambda, _) => SymbolKind::FUNCTION,
+            // Attrsets with children are namespaces, like `lib.strings`.
+            (SymbolValueKind::Attrset, _) if !sym.children.is_empty() => SymbolKind::MODULE,
+            (_, NameKind::PlainAttrset | NameKind::RecAttrset) => SymbolKind::FIELD,
+            (_, NameKind::LetIn | NameKind::Param | NameKind::PatField) => SymbolKind::VARIABLE,
         },
         tags: None,
         deprecated: None,
@@ -296,20 +608,74 @@ fn to_document_symbol(line_map: &LineMap, sym: SymbolTree) -> DocumentSymbol {
     }
 }
 
-pub(crate) fn to_code_action(vfs: &Vfs, assist: Assist) -> CodeActionOrCommand {
-    CodeActionOrCommand::CodeAction(CodeAction {
-        title: assist.label,
-        kind: Some(match assist.kind {
-            AssistKind::QuickFix => CodeActionKind::QUICKFIX,
-            AssistKind::RefactorRewrite => CodeActionKind::REFACTOR_REWRITE,
-        }),
-        diagnostics: None,
-        edit: Some(to_workspace_edit(vfs, assist.edits)),
-        command: None,
-        is_preferred: None,
-        disabled: None,
-        data: None,
-    })
+/// `client_diags` are diagnostics in the request context, which are linked to the quick fix
+/// resolving them.
+pub(crate) fn to_code_action(
+    vfs: &Vfs,
+    line_map: &LineMap,
+    client_diags: &[lsp::Diagnostic],
+    assist: Assist,
+    caps: &NegotiatedCapabilities,
+) -> lsp_ext::CodeAction {
+    let fixed_diags = assist.fixes.as_ref().map(|diag| {
+        let code = NumberOrString::String(diag.code().into());
+        let range = to_range(line_map, diag.range);
+        client_diags
+            .iter()
+            .filter(|client_diag| {
+                client_diag.code.as_ref() == Some(&code) && client_diag.range == range
+            })
+            .cloned()
+            .collect::<Vec<_>>()
+    });
+    let (edit, snippet_edit) = match assist.snippet_edits {
+        Some(snippet_edits) if caps.snippet_text_edit => {
+            (None, Some(to_snippet_workspace_edit(vfs, snippet_edits)))
+        }
+        _ => (Some(to_workspace_edit(vfs, caps, assist.edits)), None),
+    };
+    lsp_ext::CodeAction {
+        base: CodeAction {
+            title: assist.label,
+            kind: Some(match assist.kind {
+                AssistKind::QuickFix => CodeActionKind::QUICKFIX,
+                AssistKind::RefactorRewrite => CodeActionKind::REFACTOR_REWRITE,
+                AssistKind::Source => CodeActionKind::SOURCE,
+            }),
+            is_preferred: assist.fixes.is_some().then_some(true),
+            diagnostics: fixed_diags.filter(|diags| !diags.is_empty()),
+            edit,
+            command: None,
+            disabled: None,
+            data: None,
+        },
+        edit: snippet_edit,
+    }
+}
+
+fn to_snippet_workspace_edit(vfs: &Vfs, ws_edit: WorkspaceEdit) -> lsp_ext::SnippetWorkspaceEdit {
+    let document_changes = ws_edit
+        .content_edits
+        .into_iter()
+        .map(|(file, edits)| {
+            let line_map = vfs.line_map_for_file(file);
+            lsp_ext::SnippetTextDocumentEdit {
+                text_document: lsp::OptionalVersionedTextDocumentIdentifier {
+                    uri: vfs.uri_for_file(file),
+                    version: vfs.file_version(file),
+                },
+                edits: edits
+                    .into_iter()
+                    .map(|edit| lsp_ext::SnippetTextEdit {
+                        range: to_range(&line_map, edit.delete),
+                        new_text: edit.insert.into(),
+                        insert_text_format: Some(lsp::InsertTextFormat::SNIPPET),
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    lsp_ext::SnippetWorkspaceEdit { document_changes }
 }
 
 pub(crate) fn to_document_highlight(
@@ -355,6 +721,8 @@ pub(crate) fn to_document_link(
                     };
                     Url::from_file_path(target_path).ok()?
                 }
+                // Resolved by handlers according to the environment.
+                LinkTarget::HomePath(_) | LinkTarget::SearchPath(_) => return None,
             };
             (range, Some(target), Some(tooltip))
         }
@@ -372,18 +740,249 @@ pub(crate) fn from_document_link(
     vfs: &Vfs,
     link: &DocumentLink,
 ) -> Result<(Url, FileRange, Arc<LineMap>)> {
-    let uri = link
-        .data
-        .as_ref()
-        .and_then(|v| v.as_str())
+    let uri = from_data_uri(link.data.as_ref())?;
+    let file_id = vfs.file_for_uri(&uri)?;
+    let (line_map, range) = from_range(vfs, file_id, link.range)?;
+    Ok((uri, FileRange::new(file_id, range), line_map))
+}
+
+pub(crate) fn to_code_lenses(
+    line_map: &LineMap,
+    file_uri: &Url,
+    lens: CodeLens,
+) -> Vec<lsp::CodeLens> {
+    match lens {
+        CodeLens::References { range } => vec![lsp::CodeLens {
+            range: to_range(line_map, range),
+            command: None,
+            // Pass the URI to `CodeLensResolve`.
+            data: Some(file_uri.as_str().to_owned().into()),
+        }],
+        CodeLens::FlakeOutput { range, attrpath } => {
+            let range = to_range(line_map, range);
+            let attrpath = attrpath
+                .iter()
+                .map(|attr| escape_literal_attr(attr))
+                .collect::<Vec<_>>()
+                .join(".");
+            [
+                ("▶ nix eval", lsp_ext::EVAL_FLAKE_OUTPUT_COMMAND),
+                ("▶ nix build", lsp_ext::BUILD_FLAKE_OUTPUT_COMMAND),
+            ]
+            .into_iter()
+            .map(|(title, command)| lsp::CodeLens {
+                range,
+                command: Some(lsp::Command {
+                    title: title.into(),
+                    command: command.into(),
+                    arguments: Some(vec![attrpath.clone().into()]),
+                }),
+                data: None,
+            })
+            .collect()
+        }
+    }
+}
+
+pub(crate) fn from_code_lens(vfs: &Vfs, lens: &lsp::CodeLens) -> Result<(Url, FilePos)> {
+    let uri = from_data_uri(lens.data.as_ref())?;
+    let file_id = vfs.file_for_uri(&uri)?;
+    let line_map = vfs.line_map_for_file(file_id);
+    let pos = from_pos(&line_map, lens.range.start)?;
+    Ok((uri, FilePos::new(file_id, pos)))
+}
+
+pub(crate) fn to_inlay_hint(line_map: &LineMap, hint: InlayHint) -> lsp::InlayHint {
+    let (line, col) = line_map.line_col_for_pos(hint.pos);
+    lsp::InlayHint {
+        position: Position::new(line, col),
+        label: lsp::InlayHintLabel::String(hint.label),
+        kind: Some(lsp::InlayHintKind::TYPE),
+        text_edits: None,
+        tooltip: None,
+        padding_left: None,
+        padding_right: Some(true),
+        data: None,
+    }
+}
+
+pub(crate) fn to_signature_help(help: SignatureHelp) -> lsp::SignatureHelp {
+    // Offsets in labels are always in UTF-16.
+    let utf16_offset = |pos: TextSize| help.label[..usize::from(pos)].encode_utf16().count() as u32;
+    let parameters = help
+        .params
+        .iter()
+        .map(|&range| lsp::ParameterInformation {
+            label: lsp::ParameterLabel::LabelOffsets([
+                utf16_offset(range.start()),
+                utf16_offset(range.end()),
+            ]),
+            documentation: None,
+        })
+        .collect();
+    let signature = lsp::SignatureInformation {
+        label: help.label.clone(),
+        documentation: help.documentation.map(|doc| {
+            Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: doc,
+            })
+        }),
+        parameters: Some(parameters),
+        active_parameter: None,
+    };
+    lsp::SignatureHelp {
+        signatures: vec![signature],
+        active_signature: Some(0),
+        active_parameter: help.active_param.map(|i| i as u32),
+    }
+}
+
+pub(crate) fn to_show_references_command(
+    uri: Url,
+    pos: Position,
+    locs: Vec<Location>,
+) -> lsp::Command {
+    let title = match locs.len() {
+        1 => "1 reference".into(),
+        n => format!("{n} references"),
+    };
+    lsp::Command {
+        title,
+        command: lsp_ext::SHOW_REFERENCES_COMMAND.into(),
+        arguments: Some(vec![
+            serde_json::to_value(uri).unwrap(),
+            serde_json::to_value(pos).unwrap(),
+            serde_json::to_value(locs).unwrap(),
+        ]),
+    }
+}
+
+/// The code action to fill the placeholder hash of the fetcher call at `pos`.
+pub(crate) fn to_prefetch_hash_action(uri: Url, pos: Position) -> lsp_ext::CodeAction {
+    let title = "Prefetch and insert hash".to_owned();
+    lsp_ext::CodeAction {
+        base: CodeAction {
+            title: title.clone(),
+            kind: Some(CodeActionKind::REFACTOR_REWRITE),
+            command: Some(lsp::Command {
+                title,
+                command: lsp_ext::PREFETCH_HASH_COMMAND.into(),
+                arguments: Some(vec![
+                    serde_json::to_value(uri).unwrap(),
+                    serde_json::to_value(pos).unwrap(),
+                ]),
+            }),
+            ..CodeAction::default()
+        },
+        edit: None,
+    }
+}
+
+fn from_data_uri(data: Option<&serde_json::Value>) -> Result<Url> {
+    data.and_then(|v| v.as_str())
         .and_then(|s| Url::parse(s).ok())
         .ok_or_else(|| {
             anyhow::Error::from(ResponseError::new(
                 ErrorCode::INVALID_PARAMS,
                 "invalid `data` field",
             ))
-        })?;
-    let file_id = vfs.file_for_uri(&uri)?;
-    let (line_map, range) = from_range(vfs, file_id, link.range)?;
-    Ok((uri, FileRange::new(file_id, range), line_map))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_workspace_edit;
+    use crate::capabilities::NegotiatedCapabilities;
+    use crate::Vfs;
+    use ide::{FileSystemEdit, TextEdit, VfsPath, WorkspaceEdit};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use text_size::{TextRange, TextSize};
+
+    fn setup() -> (Vfs, WorkspaceEdit) {
+        let mut vfs = Vfs::new();
+        let file = vfs.set_path_content(VfsPath::new("/a.nix"), "foo".into());
+        let edit = TextEdit {
+            delete: TextRange::new(TextSize::from(0), TextSize::from(3)),
+            insert: "bar".into(),
+        };
+        let ws_edit = WorkspaceEdit {
+            content_edits: HashMap::from([(file, vec![edit])]),
+            file_system_edits: vec![
+                FileSystemEdit::CreateFile {
+                    path: VfsPath::new("/b.nix"),
+                    content: "baz".into(),
+                },
+                FileSystemEdit::CreateFile {
+                    path: VfsPath::new("/c.nix"),
+                    content: String::new(),
+                },
+                FileSystemEdit::RenameFile {
+                    file,
+                    to: VfsPath::new("/d.nix"),
+                },
+            ],
+        };
+        (vfs, ws_edit)
+    }
+
+    fn text_edit(uri: &str, new_text: &str, end: u32) -> serde_json::Value {
+        json!({
+            "textDocument": { "uri": uri, "version": null },
+            "edits": [{
+                "range": {
+                    "start": { "line": 0, "character": 0 },
+                    "end": { "line": 0, "character": end },
+                },
+                "newText": new_text,
+            }],
+        })
+    }
+
+    #[test]
+    fn document_changes() {
+        let (vfs, ws_edit) = setup();
+        let caps = NegotiatedCapabilities {
+            document_changes: true,
+            ..NegotiatedCapabilities::default()
+        };
+        let got = serde_json::to_value(to_workspace_edit(&vfs, &caps, ws_edit)).unwrap();
+        assert_eq!(
+            got,
+            json!({
+                "documentChanges": [
+                    // Content edits go first, since they refer to old paths.
+                    text_edit("file:///a.nix", "bar", 3),
+                    { "kind": "create", "uri": "file:///b.nix" },
+                    text_edit("file:///b.nix", "baz", 0),
+                    // No edit for the empty content.
+                    { "kind": "create", "uri": "file:///c.nix" },
+                    { "kind": "rename", "oldUri": "file:///a.nix", "newUri": "file:///d.nix" },
+                ],
+            }),
+        );
+    }
+
+    #[test]
+    fn changes_fallback() {
+        let (vfs, ws_edit) = setup();
+        let caps = NegotiatedCapabilities::default();
+        let got = serde_json::to_value(to_workspace_edit(&vfs, &caps, ws_edit)).unwrap();
+        // File system edits are dropped.
+        assert_eq!(
+            got,
+            json!({
+                "changes": {
+                    "file:///a.nix": [{
+                        "range": {
+                            "start": { "line": 0, "character": 0 },
+                            "end": { "line": 0, "character": 3 },
+                        },
+                        "newText": "bar",
+                    }],
+                },
+            }),
+        );
+    }
 }
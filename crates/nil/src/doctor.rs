@@ -0,0 +1,312 @@
+//! Self-check of the environment and configuration, for `nil doctor` and `nil.doctor`.
+use crate::capabilities::NegotiatedCapabilities;
+use crate::config::Config;
+use anyhow::{Context, Result};
+use ide::OptionSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, fmt, fs};
+
+/// The minimal Nix version with flakes and `nix eval --json`.
+const MIN_NIX_VERSION: (u32, u32) = (2, 4);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Check {
+    pub status: Status,
+    pub topic: &'static str,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    fn push(&mut self, status: Status, topic: &'static str, message: impl Into<String>) {
+        self.checks.push(Check {
+            status,
+            topic,
+            message: message.into(),
+            suggestion: None,
+        });
+    }
+
+    fn push_with(
+        &mut self,
+        status: Status,
+        topic: &'static str,
+        message: impl Into<String>,
+        suggestion: impl Into<String>,
+    ) {
+        self.checks.push(Check {
+            status,
+            topic,
+            message: message.into(),
+            suggestion: Some(suggestion.into()),
+        });
+    }
+
+    /// The most severe status of all checks.
+    pub fn status(&self) -> Status {
+        let has = |status| self.checks.iter().any(|check| check.status == status);
+        if has(Status::Error) {
+            Status::Error
+        } else if has(Status::Warning) {
+            Status::Warning
+        } else {
+            Status::Ok
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let status = match check.status {
+                Status::Ok => "ok",
+                Status::Warning => "warning",
+                Status::Error => "error",
+            };
+            writeln!(f, "[{status}] {}: {}", check.topic, check.message)?;
+            if let Some(suggestion) = &check.suggestion {
+                writeln!(f, "    {suggestion}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check the configuration and the environment it refers to.
+/// `config_errors` are errors collected when parsing the configuration.
+pub(crate) fn check_config(config: &Config, config_errors: &[String]) -> Report {
+    let mut report = Report::default();
+
+    if config_errors.is_empty() {
+        report.push(Status::Ok, "configuration", "No errors");
+    } else {
+        report.push_with(
+            Status::Error,
+            "configuration",
+            config_errors.join("; "),
+            "See docs/configuration.md for all settings",
+        );
+    }
+
+    let nix = config.nix_binary.display();
+    match Command::new(&config.nix_binary).arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            let version = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+            match parse_nix_version(&version) {
+                Some(ver) if ver < MIN_NIX_VERSION => report.push_with(
+                    Status::Warning,
+                    "nix",
+                    format!("{version} is too old, flake support is not available"),
+                    format!(
+                        "Upgrade Nix to {}.{} or later",
+                        MIN_NIX_VERSION.0, MIN_NIX_VERSION.1
+                    ),
+                ),
+                _ => report.push(Status::Ok, "nix", version),
+            }
+        }
+        Ok(output) => report.push_with(
+            Status::Error,
+            "nix",
+            format!("`{nix} --version` failed with {}", output.status),
+            "Set `nix.binary` to a working Nix binary",
+        ),
+        Err(err) => report.push_with(
+            Status::Error,
+            "nix",
+            format!("Failed to run `{nix}`: {err}"),
+            "Install Nix, or set `nix.binary` to its path",
+        ),
+    }
+
+    match config.formatting_command.as_deref() {
+        None => report.push_with(
+            Status::Warning,
+            "formatting",
+            "No formatter is configured, formatting is disabled",
+            "Set `formatting.command`, eg. `[\"nixpkgs-fmt\"]`",
+        ),
+        Some([program, ..]) => match find_program(Path::new(program)) {
+            Some(path) => report.push(Status::Ok, "formatting", path.display().to_string()),
+            None => report.push_with(
+                Status::Error,
+                "formatting",
+                format!("The formatter `{program}` is not found"),
+                "Install it, or set `formatting.command` to its path",
+            ),
+        },
+        // Rejected during parsing.
+        Some([]) => {}
+    }
+
+    if let Some(path) = &config.nix_nixpkgs_path {
+        if path.join("default.nix").is_file() {
+            report.push(Status::Ok, "nixpkgs", path.display().to_string());
+        } else {
+            report.push_with(
+                Status::Error,
+                "nixpkgs",
+                format!("{} is not a nixpkgs source tree", path.display()),
+                "Set `nix.nixpkgsPath` to a directory containing `default.nix`",
+            );
+        }
+    }
+
+    for &set in OptionSet::ALL {
+        let Some(path) = config.options_file(set) else {
+            continue;
+        };
+        if let Err(err) = fs::metadata(path) {
+            let key = Config::options_key(set);
+            report.push_with(
+                Status::Error,
+                key,
+                format!("Cannot read {}: {err}", path.display()),
+                format!("Fix or unset `nix.{key}.file`"),
+            );
+        }
+    }
+
+    if let Some(path) = &config.nix_package_aliases_file {
+        if let Err(err) = fs::metadata(path) {
+            report.push_with(
+                Status::Error,
+                "packageAliases",
+                format!("Cannot read {}: {err}", path.display()),
+                "Fix or unset `nix.packageAliases.file`",
+            );
+        }
+    }
+
+    report
+}
+
+/// Check for client capabilities affecting features.
+pub(crate) fn check_client(report: &mut Report, caps: &NegotiatedCapabilities) {
+    let gaps = [
+        (
+            caps.workspace_configuration,
+            "Settings are not pulled from the client",
+            "Pass settings via `initializationOptions`, if the client does not support `workspace/configuration`",
+        ),
+        (
+            caps.watch_files,
+            "Changes of closed files are not noticed",
+            "Restart the server after changing closed files outside the editor",
+        ),
+        (
+            caps.server_initiated_progress,
+            "Progress of indexing and flake loading is not shown",
+            "Check the server log for progress",
+        ),
+        (
+            caps.client_show_message_request,
+            "Prompts, like archiving flake inputs, are not shown",
+            "Set `nix.flake.autoArchive` to decide without prompts",
+        ),
+    ];
+    let mut any_gap = false;
+    for (supported, message, suggestion) in gaps {
+        if !supported {
+            any_gap = true;
+            report.push_with(Status::Warning, "client", message, suggestion);
+        }
+    }
+    if !any_gap {
+        report.push(Status::Ok, "client", "All used capabilities are supported");
+    }
+}
+
+/// Check the configuration in the JSON file `config_path`, or the default configuration,
+/// and print the report. Returns whether there is no error.
+pub fn doctor(config_path: Option<&Path>) -> Result<bool> {
+    let root_path = env::current_dir().context("Failed to get the current directory")?;
+    let mut config = Config::new(root_path);
+    let mut errors = Vec::new();
+    if let Some(path) = config_path {
+        config.update_from_file(path, &mut errors)?;
+    }
+    let report = check_config(&config, &errors);
+    print!("{report}");
+    Ok(report.status() != Status::Error)
+}
+
+/// Parse the major and minor version from the output of `nix --version`,
+/// like `nix (Nix) 2.18.1`.
+fn parse_nix_version(output: &str) -> Option<(u32, u32)> {
+    let version = output.split_whitespace().last()?;
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?;
+    let minor = minor[..minor
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(minor.len())]
+        .parse()
+        .ok()?;
+    Some((major, minor))
+}
+
+/// Find the executable `program` like a shell does.
+fn find_program(program: &Path) -> Option<PathBuf> {
+    if program.components().count() > 1 {
+        return program.is_file().then(|| program.to_owned());
+    }
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_config, parse_nix_version, Status};
+    use crate::config::Config;
+    use std::path::PathBuf;
+
+    #[test]
+    fn nix_version() {
+        assert_eq!(parse_nix_version("nix (Nix) 2.18.1"), Some((2, 18)));
+        assert_eq!(parse_nix_version("nix (Nix) 2.3pre1234"), Some((2, 3)));
+        assert_eq!(
+            parse_nix_version("nix (Lix, like Nix) 2.90.0"),
+            Some((2, 90))
+        );
+        assert_eq!(parse_nix_version("garbage"), None);
+    }
+
+    #[test]
+    fn missing_binaries() {
+        let mut config = Config::new("/".into());
+        config.nix_binary = "/non-existing/nix".into();
+        config.formatting_command = Some(vec!["/non-existing/fmt".into()]);
+        config.nix_nixpkgs_path = Some(PathBuf::from("/non-existing/nixpkgs"));
+        let report = check_config(&config, &["unknown setting `foo`".into()]);
+        let got = report
+            .checks
+            .iter()
+            .map(|check| (check.topic, check.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            got,
+            [
+                ("configuration", Status::Error),
+                ("nix", Status::Error),
+                ("formatting", Status::Error),
+                ("nixpkgs", Status::Error),
+            ],
+        );
+        assert_eq!(report.status(), Status::Error);
+    }
+}
@@ -0,0 +1,239 @@
+//! Batch formatting of files for `nil fmt`, with the same formatter as `textDocument/formatting`.
+use crate::collect_nix_files;
+use crate::config::Config;
+use crate::handler::run_formatter;
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+/// The number of unchanged lines shown around changes in diffs.
+const DIFF_CONTEXT_LINES: usize = 3;
+
+/// The maximum size of the table for diffing changed lines. Larger changes are shown as
+/// replacing all lines, instead of spending quadratic time and memory.
+const MAX_DIFF_CELLS: usize = 1 << 24;
+
+/// Format files in `paths`, or all Nix files under directories in `paths`, in place.
+/// With `check`, files are not modified, and diffs of unformatted files are printed instead.
+/// The formatter is `formatting.command` in the settings from `config_path`.
+/// Returns whether all files are formatted, or were already formatted with `check`.
+pub fn format_files(paths: &[PathBuf], check: bool, config_path: Option<&Path>) -> Result<bool> {
+    let root_path = env::current_dir().context("Failed to get the current directory")?;
+    let mut config = Config::new(root_path);
+    if let Some(path) = config_path {
+        let mut errors = Vec::new();
+        config.update_from_file(path, &mut errors)?;
+        for err in errors {
+            eprintln!("warning: {err}");
+        }
+    }
+    let Some(cmd) = &config.formatting_command else {
+        bail!("No formatter is configured. Set `formatting.command` in the settings of `--config`");
+    };
+    if paths.is_empty() {
+        bail!("No path is given");
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            files.extend(collect_nix_files(path, usize::MAX));
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(format_paths(cmd, &files, check))
+}
+
+fn format_paths(cmd: &[String], files: &[PathBuf], check: bool) -> bool {
+    let mut all_ok = true;
+    for path in files {
+        let ret = (|| -> Result<bool> {
+            let src =
+                fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
+            let formatted = run_formatter(cmd, src.clone().into_bytes(), None)
+                .with_context(|| format!("Failed to run formatter {cmd:?}"))?
+                .context("Formatter timed out")?;
+            if formatted == src {
+                return Ok(true);
+            }
+            if check {
+                print!("{}", unified_diff(path, &src, &formatted));
+                return Ok(false);
+            }
+            fs::write(path, formatted).with_context(|| format!("Failed to write {path:?}"))?;
+            Ok(true)
+        })();
+        match ret {
+            Ok(ok) => all_ok &= ok,
+            Err(err) => {
+                eprintln!("{}: {err:#}", path.display());
+                all_ok = false;
+            }
+        }
+    }
+    all_ok
+}
+
+/// A unified diff from `old` to `new`, with a single hunk spanning all changed lines.
+fn unified_diff(path: &Path, old: &str, new: &str) -> String {
+    let old = old.split_inclusive('\n').collect::<Vec<_>>();
+    let new = new.split_inclusive('\n').collect::<Vec<_>>();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+
+    let start = prefix.saturating_sub(DIFF_CONTEXT_LINES);
+    let context_after = suffix.min(DIFF_CONTEXT_LINES);
+    let old_len = old_end + context_after - start;
+    let new_len = new_end + context_after - start;
+    // Empty ranges start at the line before them.
+    let hunk_start = |len: usize| if len == 0 { start } else { start + 1 };
+
+    let path = path.display();
+    let mut out = format!(
+        "--- a/{path}\n+++ b/{path}\n@@ -{},{old_len} +{},{new_len} @@\n",
+        hunk_start(old_len),
+        hunk_start(new_len),
+    );
+    let mut push_line = |tag: char, line: &str| {
+        out.push(tag);
+        out.push_str(line);
+        if !line.ends_with('\n') {
+            out.push_str("\n\\ No newline at end of file\n");
+        }
+    };
+    for line in &old[start..prefix] {
+        push_line(' ', line);
+    }
+    for (tag, line) in diff_lines(&old[prefix..old_end], &new[prefix..new_end]) {
+        push_line(tag, line);
+    }
+    for line in &old[old_end..old_end + context_after] {
+        push_line(' ', line);
+    }
+    out
+}
+
+/// Lines tagged with ` `, `-` or `+` turning `old` into `new`, keeping the longest common
+/// subsequence unchanged.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(char, &'a str)> {
+    if old.len().saturating_mul(new.len()) > MAX_DIFF_CELLS {
+        return old
+            .iter()
+            .map(|&line| ('-', line))
+            .chain(new.iter().map(|&line| ('+', line)))
+            .collect();
+    }
+
+    // `lcs[i][j]` is the length of the longest common subsequence of `old[i..]` and `new[j..]`.
+    let mut lcs = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ret = Vec::with_capacity(old.len() + new.len());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ret.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if j == new.len() || i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1] {
+            ret.push(('-', old[i]));
+            i += 1;
+        } else {
+            ret.push(('+', new[j]));
+            j += 1;
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_paths, unified_diff};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn diff() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\n";
+        let new = "a\nb\nc\nd\nE\nf\nG\nh\n";
+        assert_eq!(
+            unified_diff(Path::new("x.nix"), old, new),
+            "\
+--- a/x.nix
++++ b/x.nix
+@@ -2,7 +2,7 @@
+ b
+ c
+ d
+-e
++E
+ f
+-g
++G
+ h
+",
+        );
+
+        assert_eq!(
+            unified_diff(Path::new("x.nix"), "a", "a\n"),
+            "\
+--- a/x.nix
++++ b/x.nix
+@@ -1,1 +1,1 @@
+-a
+\\ No newline at end of file
++a
+",
+        );
+
+        assert_eq!(
+            unified_diff(Path::new("x.nix"), "", "a\n"),
+            "\
+--- a/x.nix
++++ b/x.nix
+@@ -0,0 +1,1 @@
++a
+",
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn format_in_place() {
+        let dir = std::env::temp_dir().join(format!("nil-fmt-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let formatted = dir.join("formatted.nix");
+        let unformatted = dir.join("unformatted.nix");
+        fs::write(&formatted, "{ }\n").unwrap();
+        fs::write(&unformatted, "{ A }\n").unwrap();
+        let files = [formatted, unformatted.clone()];
+        let cmd = ["tr".to_owned(), "A".to_owned(), "a".to_owned()];
+
+        assert!(!format_paths(&cmd, &files, true));
+        assert_eq!(fs::read_to_string(&unformatted).unwrap(), "{ A }\n");
+
+        assert!(format_paths(&cmd, &files, false));
+        assert_eq!(fs::read_to_string(&unformatted).unwrap(), "{ a }\n");
+        assert!(format_paths(&cmd, &files, true));
+
+        let missing = [dir.join("missing.nix")];
+        assert!(!format_paths(&cmd, &missing, false));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,33 +1,242 @@
-use crate::{convert, StateSnapshot};
+use crate::config::Config;
+use crate::lsp_ext::{
+    ApplyFixParams, CodeAction, DocumentDiagnosticParams, DocumentDiagnosticReport,
+    FlakeInputSourceResult, MemoryUsageResult, ProfileEntry, ProfileParams, QueryStats,
+    ReplEvalParams, SymbolsPageParams, SymbolsPageResult, SyntaxTreeParams,
+    WorkspaceDiagnosticParams, WorkspaceDiagnosticReport, WorkspaceDocumentDiagnosticReport,
+};
+use crate::module_graph::{FileSummary, ModuleGraph, MAX_SUMMARIZED_FILES};
+use crate::{convert, LineMap, StateSnapshot, UrlExt, Vfs};
 use anyhow::{ensure, Context, Result};
 use async_lsp::{ErrorCode, ResponseError};
-use ide::{FileRange, GotoDefinitionResult};
+use ide::{
+    AssistKind, CompletionItemKind, EvalCompletionQuery, FileId, FilePos, FileRange,
+    GotoDefinitionResult, Link, LinkTarget, VfsPath,
+};
 use lsp_types::{
-    CodeActionParams, CodeActionResponse, CompletionParams, CompletionResponse,
-    DocumentFormattingParams, DocumentHighlight, DocumentHighlightParams, DocumentLink,
-    DocumentLinkParams, DocumentSymbolParams, DocumentSymbolResponse, GotoDefinitionParams,
-    GotoDefinitionResponse, Hover, HoverParams, Location, Position, PrepareRenameResponse, Range,
-    ReferenceParams, RenameParams, SelectionRange, SelectionRangeParams, SemanticTokens,
-    SemanticTokensParams, SemanticTokensRangeParams, SemanticTokensRangeResult,
-    SemanticTokensResult, TextDocumentPositionParams, TextEdit, Url, WorkspaceEdit,
+    CodeActionParams, CodeLens, CodeLensParams, CompletionList, CompletionParams,
+    CompletionResponse, CompletionTriggerKind, Diagnostic, DocumentFormattingParams,
+    DocumentHighlight, DocumentHighlightParams, DocumentLink, DocumentLinkParams,
+    DocumentSymbolParams, DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse,
+    Hover, HoverParams, InlayHint, InlayHintParams, LinkedEditingRangeParams, LinkedEditingRanges,
+    Location, Position, PrepareRenameResponse, Range, ReferenceParams, RenameFilesParams,
+    RenameParams, SelectionRange, SelectionRangeParams, SemanticTokens, SemanticTokensParams,
+    SemanticTokensRangeParams, SemanticTokensRangeResult, SemanticTokensResult, SignatureHelp,
+    SignatureHelpParams, TextDocumentIdentifier, TextDocumentPositionParams,
+    TextDocumentSaveReason, TextEdit, Url, WillSaveTextDocumentParams, WorkspaceEdit,
 };
-use nix_interop::DEFAULT_IMPORT_FILE;
+use nix_interop::prefetch::PrefetchSource;
+use nix_interop::store_path::StorePathInfo;
+use nix_interop::{flake_lock, DEFAULT_IMPORT_FILE, FLAKE_LOCK_FILE};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::process;
-use std::sync::Arc;
-use text_size::TextRange;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use text_size::{TextRange, TextSize};
+
+const MAX_DIAGNOSTICS_CNT: usize = 128;
+/// How often a formatter running with a time budget is checked for exit.
+const FORMATTER_POLL_PERIOD: Duration = Duration::from_millis(10);
+/// The number of files in a partial result of workspace diagnostics.
+const WORKSPACE_DIAGNOSTICS_BATCH_LEN: usize = 64;
+
+/// Filtered diagnostics of an opened file, shared by the pushing and pulling model.
+/// They are keyed by the file they are located in, which is `uri` except for hints of notes.
+pub(crate) fn file_diagnostics(
+    snap: &StateSnapshot,
+    uri: &Url,
+    file: FileId,
+    line_map: &LineMap,
+) -> Result<HashMap<Url, Vec<Diagnostic>>> {
+    let is_ignored = |uri: &Url| {
+        snap.config.diagnostics_excluded_files.contains(uri) || snap.config.is_read_only(uri)
+    };
+    if is_ignored(uri) {
+        return Ok(HashMap::new());
+    }
+    let inst = Instant::now();
+    let (mut diags, passes) = tracing::debug_span!("analyze")
+        .in_scope(|| snap.analysis.diagnostics_with_timings(file))?;
+    let path_cache = &*snap.path_cache;
+    diags.extend(tracing::debug_span!("missing_paths").in_scope(|| {
+        snap.analysis.missing_paths(file, |path| {
+            path.as_path().map_or(true, |path| path_cache.exists(path))
+        })
+    })?);
+    diags.retain(|diag| snap.config.diagnostic_enabled(diag));
+    diags.truncate(MAX_DIAGNOSTICS_CNT);
+    let _span = tracing::debug_span!("convert").entered();
+    let mut ret = convert::to_diagnostics(&snap.vfs(), &snap.config, uri, file, line_map, &diags);
+    ret.retain(|uri, _| !is_ignored(uri));
+    snap.profiler
+        .record("diagnostics", Some(uri.clone()), inst.elapsed(), passes);
+    Ok(ret)
+}
+
+pub(crate) fn document_diagnostic(
+    snap: StateSnapshot,
+    params: DocumentDiagnosticParams,
+) -> Result<DocumentDiagnosticReport> {
+    let uri = &params.text_document.uri;
+    let (file, line_map) = convert::from_file(&snap.vfs(), &params.text_document)?;
+    // Hints in other files are only pushed.
+    let mut items = file_diagnostics(&snap, uri, file, &line_map)?
+        .remove(uri)
+        .unwrap_or_default();
+    items.extend(snap.eval_diagnostics(uri));
+    diagnostic_report(items, params.previous_result_id.as_deref())
+}
+
+fn diagnostic_report(
+    items: Vec<Diagnostic>,
+    previous_result_id: Option<&str>,
+) -> Result<DocumentDiagnosticReport> {
+    // The result id is a fingerprint of the diagnostics, so it can be checked without
+    // remembering what was sent. Clients only keep the id of the last report.
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_string(&items)?.hash(&mut hasher);
+    let result_id = format!("{:016x}", hasher.finish());
+
+    if previous_result_id == Some(&*result_id) {
+        return Ok(DocumentDiagnosticReport::Unchanged { result_id });
+    }
+    Ok(DocumentDiagnosticReport::Full {
+        result_id: Some(result_id),
+        items,
+    })
+}
+
+/// Diagnostics of all loaded Nix files under the workspace root.
+/// If `partial` is given, reports are sent through it in batches and an empty report is
+/// returned, as required for partial results.
+pub(crate) fn workspace_diagnostic(
+    snap: StateSnapshot,
+    params: WorkspaceDiagnosticParams,
+    mut partial: Option<&mut dyn FnMut(WorkspaceDiagnosticReport)>,
+) -> Result<WorkspaceDiagnosticReport> {
+    let previous_result_ids = params
+        .previous_result_ids
+        .into_iter()
+        .map(|prev| (prev.uri, prev.value))
+        .collect::<HashMap<_, _>>();
+    let mut files = {
+        let vfs = snap.vfs();
+        vfs.iter()
+            .filter(|(_, uri)| {
+                uri.to_file_path().map_or(false, |path| {
+                    vfs.roots().iter().any(|root| path.starts_with(root))
+                        && path.extension().map_or(false, |ext| ext == "nix")
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    files.sort_by(|(_, lhs), (_, rhs)| lhs.cmp(rhs));
+
+    let mut ret = WorkspaceDiagnosticReport::default();
+    for batch in files.chunks(WORKSPACE_DIAGNOSTICS_BATCH_LEN) {
+        for (file, uri) in batch {
+            let line_map = snap.vfs().line_map_for_file(*file);
+            let mut items = file_diagnostics(&snap, uri, *file, &line_map)?
+                .remove(uri)
+                .unwrap_or_default();
+            items.extend(snap.eval_diagnostics(uri));
+            let prev = previous_result_ids.get(uri).map(|id| &**id);
+            // Files without problems are only reported to clear their previous reports.
+            if items.is_empty() && prev.is_none() {
+                continue;
+            }
+            ret.items.push(WorkspaceDocumentDiagnosticReport {
+                uri: uri.clone(),
+                version: None,
+                report: diagnostic_report(items, prev)?,
+            });
+        }
+        if let Some(partial) = &mut partial {
+            if !ret.items.is_empty() {
+                partial(std::mem::take(&mut ret));
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// The result of `goto_definition` on the snapshot.
+pub(crate) enum GotoDefinitionReply {
+    Response(Option<GotoDefinitionResponse>),
+    /// An attribute located by evaluation later.
+    AttrPos(AttrPosQuery),
+}
+
+/// An attribute whose position can only be known by evaluation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum AttrPosQuery {
+    /// `outputs.<attrpath>` of the flake at the path.
+    Flake(PathBuf, Vec<String>),
+    /// A top-level attribute of nixpkgs at the path.
+    Nixpkgs(PathBuf, String),
+}
 
 pub(crate) fn goto_definition(
     snap: StateSnapshot,
     params: GotoDefinitionParams,
-) -> Result<Option<GotoDefinitionResponse>> {
+) -> Result<GotoDefinitionReply> {
     let (fpos, _) = convert::from_file_pos(&snap.vfs(), &params.text_document_position_params)?;
-    let ret = snap.analysis.goto_definition(fpos)?;
+    let inherit_hops = snap.config.goto_definition_inherit_hops;
+    let ret = snap.analysis.goto_definition(fpos, inherit_hops)?;
     let vfs = snap.vfs();
     let targets = match ret {
-        None => return Ok(None),
+        None => return Ok(GotoDefinitionReply::Response(None)),
+        Some(GotoDefinitionResult::FlakeInputAttr {
+            store_path,
+            attrpath,
+        }) => {
+            let Some(path) = store_path.as_path() else {
+                return Ok(GotoDefinitionReply::Response(None));
+            };
+            return Ok(GotoDefinitionReply::AttrPos(AttrPosQuery::Flake(
+                path.to_owned(),
+                attrpath,
+            )));
+        }
+        Some(GotoDefinitionResult::NixpkgsAttr { name }) => {
+            let Some(nixpkgs) = snap.config.search_path().resolve("nixpkgs", Path::exists) else {
+                return Ok(GotoDefinitionReply::Response(None));
+            };
+            return Ok(GotoDefinitionReply::AttrPos(AttrPosQuery::Nixpkgs(
+                nixpkgs, name,
+            )));
+        }
+        Some(GotoDefinitionResult::NixosOption {
+            set,
+            declarations,
+            targets,
+        }) => {
+            // Declarations from prebuilt `options.json` are relative to the source of the set.
+            let root = vfs.option_set_root(set);
+            let decls = declarations.iter().filter_map(|decl| {
+                let path = Path::new(decl);
+                let path = if path.is_absolute() {
+                    path.to_owned()
+                } else {
+                    root?.join(path)
+                };
+                path.is_file().then(|| Location {
+                    uri: Url::from_file_path(path).unwrap(),
+                    range: Range::default(),
+                })
+            });
+            let defs = targets.into_iter().map(|target| {
+                convert::to_location(&vfs, FileRange::new(target.file_id, target.focus_range))
+            });
+            decls.chain(defs).collect()
+        }
         Some(GotoDefinitionResult::Path(vpath)) => {
             let Some(path) = vpath.as_path() else {
-                return Ok(None);
+                return Ok(GotoDefinitionReply::Response(None));
             };
             let default_child = path.join(DEFAULT_IMPORT_FILE);
             let target_path = if path.is_file() {
@@ -35,13 +244,25 @@ pub(crate) fn goto_definition(
             } else if default_child.is_file() {
                 &default_child
             } else {
-                return Ok(None);
+                return Ok(GotoDefinitionReply::Response(None));
             };
             vec![Location {
                 uri: Url::from_file_path(target_path).unwrap(),
                 range: Range::default(),
             }]
         }
+        // Hops are distinguishable by their full ranges of `inherit`s.
+        Some(GotoDefinitionResult::Targets(targets))
+            if inherit_hops && snap.capabilities.definition_link =>
+        {
+            let links = targets
+                .into_iter()
+                .map(|target| convert::to_location_link(&vfs, target))
+                .collect();
+            return Ok(GotoDefinitionReply::Response(Some(
+                GotoDefinitionResponse::Link(links),
+            )));
+        }
         Some(GotoDefinitionResult::Targets(targets)) => targets
             .into_iter()
             .map(|target| {
@@ -49,7 +270,9 @@ pub(crate) fn goto_definition(
             })
             .collect(),
     };
-    Ok(Some(GotoDefinitionResponse::Array(targets)))
+    Ok(GotoDefinitionReply::Response(Some(
+        GotoDefinitionResponse::Array(targets),
+    )))
 }
 
 pub(crate) fn references(
@@ -68,22 +291,228 @@ pub(crate) fn references(
     Ok(Some(locs))
 }
 
-pub(crate) fn completion(
+/// The edit of the quick fix for `nil.applyFix` command.
+pub(crate) fn apply_fix(
     snap: StateSnapshot,
-    params: CompletionParams,
-) -> Result<Option<CompletionResponse>> {
-    let (fpos, line_map) = convert::from_file_pos(&snap.vfs(), &params.text_document_position)?;
+    params: ApplyFixParams,
+) -> Result<Option<(String, WorkspaceEdit)>> {
+    let (file_id, _) = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let (_, range) = convert::from_range(&snap.vfs(), file_id, params.range)?;
+    let assists = snap.analysis.assists(FileRange { file_id, range })?;
+    let Some(assist) = assists.into_iter().find(|assist| {
+        is_writable(&snap.config, &snap.vfs(), &assist.edits)
+            && convert::is_workspace_edit_supported(&snap.capabilities, &assist.edits)
+            && match &params.id {
+                Some(id) => assist.id == *id,
+                None => assist.kind == AssistKind::QuickFix,
+            }
+    }) else {
+        return Ok(None);
+    };
+    let edit = convert::to_workspace_edit(&snap.vfs(), &snap.capabilities, assist.edits);
+    Ok(Some((assist.label, edit)))
+}
+
+/// The maximum number of items in a completion response. The list is marked incomplete if there
+/// are more, so that clients ask again as more characters are typed.
+const MAX_COMPLETION_CNT: usize = 512;
+
+/// All items of the last incomplete completion, to be refined by the next request of
+/// `TriggerForIncompleteCompletions` without completing again.
+#[derive(Debug)]
+pub(crate) struct CompletionCache {
+    file: FileId,
+    src: Arc<str>,
+    pos: TextSize,
+    items: Vec<ide::CompletionItem>,
+}
+
+/// Fields of items in the last completion response deferred to `completionItem/resolve`,
+/// by their indices. Items of earlier responses are not resolved.
+#[derive(Debug, Default)]
+pub(crate) struct CompletionResolveCache {
+    /// Increased on each response, and kept in `data` of its items.
+    generation: u64,
+    items: Vec<Option<convert::DeferredCompletion>>,
+}
+
+/// The result of `completion` on the snapshot.
+pub(crate) enum CompletionReply {
+    Response(Option<CompletionResponse>),
+    /// Items to be merged with attribute names known by evaluation later.
+    Eval(EvalCompletionQuery, Box<PendingCompletion>),
+}
+
+/// Completed items waiting for evaluation, with what is needed to respond without the snapshot.
+pub(crate) struct PendingCompletion {
+    ctx: CompletionResponseContext,
+    items: Vec<ide::CompletionItem>,
+}
+
+impl PendingCompletion {
+    /// Respond with evaluated attribute `names` merged.
+    pub(crate) fn finish(
+        mut self,
+        query: &EvalCompletionQuery,
+        names: &[String],
+    ) -> CompletionResponse {
+        let evaluated = query.to_completions(names.iter().map(|name| &**name));
+        ide::merge_completions(&self.ctx.src, self.ctx.fpos.pos, &mut self.items, evaluated);
+        self.ctx.respond(self.items)
+    }
+}
+
+struct CompletionResponseContext {
+    config: Arc<Config>,
+    completion_cache: Arc<Mutex<Option<CompletionCache>>>,
+    /// Present if the client resolves items.
+    resolve_cache: Option<Arc<Mutex<CompletionResolveCache>>>,
+    fpos: FilePos,
+    line_map: Arc<LineMap>,
+    src: Arc<str>,
+}
+
+impl CompletionResponseContext {
+    fn respond(self, mut items: Vec<ide::CompletionItem>) -> CompletionResponse {
+        let is_incomplete = items.len() > MAX_COMPLETION_CNT;
+        if is_incomplete {
+            *self.completion_cache.lock().unwrap() = Some(CompletionCache {
+                file: self.fpos.file_id,
+                src: self.src,
+                pos: self.fpos.pos,
+                items: items.clone(),
+            });
+            items.truncate(MAX_COMPLETION_CNT);
+        }
+
+        // Items are already ranked. Keep the order in clients.
+        let width = items.len().to_string().len();
+        let mut resolve_cache = self.resolve_cache.as_ref().map(|cache| {
+            let mut cache = cache.lock().unwrap();
+            cache.generation += 1;
+            cache.items.clear();
+            cache
+        });
+        let items = items
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let mut item = convert::to_completion_item(&self.config, &self.line_map, item);
+                item.sort_text = Some(format!("{i:0width$}"));
+                if let Some(cache) = &mut resolve_cache {
+                    let deferred = convert::defer_completion_item(&mut item, cache.generation, i);
+                    cache.items.push(deferred);
+                }
+                item
+            })
+            .collect::<Vec<_>>();
+        CompletionResponse::List(CompletionList {
+            is_incomplete,
+            items,
+        })
+    }
+}
+
+pub(crate) fn completion(snap: StateSnapshot, params: CompletionParams) -> Result<CompletionReply> {
+    let trigger_kind = params.context.as_ref().map(|ctx| ctx.trigger_kind);
     let trigger_char = params
         .context
         .and_then(|ctx| ctx.trigger_character?.chars().next());
-    let Some(items) = snap.analysis.completions(fpos, trigger_char)? else {
-        return Ok(None);
+    let pos = params.text_document_position;
+    let (fpos, line_map, src) = {
+        let vfs = snap.vfs();
+        let (fpos, line_map) = convert::from_file_pos(&vfs, &pos)?;
+        (fpos, line_map, vfs.content_for_file(fpos.file_id))
     };
-    let items = items
-        .into_iter()
-        .map(|item| convert::to_completion_item(&line_map, item))
-        .collect::<Vec<_>>();
-    Ok(Some(CompletionResponse::Array(items)))
+
+    let cache = snap.completion_cache.lock().unwrap().take();
+    let refined = cache
+        .filter(|cache| {
+            trigger_kind == Some(CompletionTriggerKind::TRIGGER_FOR_INCOMPLETE_COMPLETIONS)
+                && cache.file == fpos.file_id
+        })
+        .and_then(|cache| {
+            ide::refine_completions(&cache.src, cache.pos, &src, fpos.pos, cache.items)
+        });
+    let ctx = CompletionResponseContext {
+        config: snap.config.clone(),
+        completion_cache: snap.completion_cache.clone(),
+        resolve_cache: snap
+            .capabilities
+            .completion_resolve
+            .then(|| snap.completion_resolve_cache.clone()),
+        fpos,
+        line_map,
+        src,
+    };
+    if let Some(items) = refined {
+        return Ok(CompletionReply::Response(Some(ctx.respond(items))));
+    }
+
+    let items = completion_items(&snap, fpos, trigger_char)?;
+    let query = if snap.config.completion_eval_enable {
+        snap.analysis.eval_completion_query(fpos)?
+    } else {
+        None
+    };
+    Ok(match (items, query) {
+        (items, Some(query)) => CompletionReply::Eval(
+            query,
+            Box::new(PendingCompletion {
+                ctx,
+                items: items.unwrap_or_default(),
+            }),
+        ),
+        (Some(items), None) => CompletionReply::Response(Some(ctx.respond(items))),
+        (None, None) => CompletionReply::Response(None),
+    })
+}
+
+/// Fill in fields deferred by `completion`, kept for the item referred by `data`.
+pub(crate) fn completion_resolve(
+    snap: StateSnapshot,
+    mut item: lsp_types::CompletionItem,
+) -> Result<lsp_types::CompletionItem> {
+    let Some((generation, index)) = convert::from_completion_data(item.data.take()) else {
+        return Ok(item);
+    };
+    let cache = snap.completion_resolve_cache.lock().unwrap();
+    if cache.generation != generation {
+        return Ok(item);
+    }
+    if let Some(Some(deferred)) = cache.items.get(index) {
+        convert::resolve_completion_item(&mut item, deferred.clone());
+    }
+    Ok(item)
+}
+
+fn completion_items(
+    snap: &StateSnapshot,
+    fpos: FilePos,
+    trigger_char: Option<char>,
+) -> Result<Option<Vec<ide::CompletionItem>>> {
+    let path_cache = &*snap.path_cache;
+    let read_dir = |path: &VfsPath| {
+        path.as_path()
+            .map_or_else(Vec::new, |path| path_cache.read_dir(path).to_vec())
+    };
+    let path_items = snap
+        .analysis
+        .path_completions(fpos, &snap.config.search_path(), read_dir)?;
+    let mut items = match path_items {
+        Some(items) => items,
+        None => {
+            let lib_import = snap.config.completion_auto_import_lib;
+            match snap.analysis.completions(fpos, trigger_char, lib_import)? {
+                Some(items) => items,
+                None => return Ok(None),
+            }
+        }
+    };
+    items.retain(|item| {
+        item.kind != CompletionItemKind::Snippet || snap.capabilities.completion_snippet
+    });
+    Ok(Some(items))
 }
 
 pub(crate) fn selection_range(
@@ -125,10 +554,13 @@ pub(crate) fn prepare_rename(
     params: TextDocumentPositionParams,
 ) -> Result<Option<PrepareRenameResponse>> {
     let (fpos, line_map) = convert::from_file_pos(&snap.vfs(), &params)?;
+    if snap.config.is_read_only(&params.text_document.uri) {
+        return Err(read_only_error(&params.text_document.uri));
+    }
     let (range, text) = snap
         .analysis
         .prepare_rename(fpos)?
-        .map_err(convert::to_rename_error)?;
+        .map_err(|err| convert::to_rename_error(&snap.vfs(), err))?;
     let resp = convert::to_prepare_rename_response(&line_map, range, text.into());
     Ok(Some(resp))
 }
@@ -138,11 +570,94 @@ pub(crate) fn rename(snap: StateSnapshot, params: RenameParams) -> Result<Option
     let ws_edit = snap
         .analysis
         .rename(fpos, &params.new_name)?
-        .map_err(convert::to_rename_error)?;
-    let resp = convert::to_workspace_edit(&snap.vfs(), ws_edit);
+        .map_err(|err| convert::to_rename_error(&snap.vfs(), err))?;
+    {
+        let vfs = snap.vfs();
+        if let Some(uri) = ws_edit
+            .content_edits
+            .keys()
+            .map(|&file| vfs.uri_for_file(file))
+            .find(|uri| snap.config.is_read_only(uri))
+        {
+            return Err(read_only_error(&uri));
+        }
+    }
+    let resp = convert::to_workspace_edit(&snap.vfs(), &snap.capabilities, ws_edit);
     Ok(Some(resp))
 }
 
+pub(crate) fn will_rename_files(
+    snap: StateSnapshot,
+    params: RenameFilesParams,
+) -> Result<Option<WorkspaceEdit>> {
+    let to_path = |uri: &str| Url::parse(uri).ok()?.to_file_path().ok();
+    let renames = params
+        .files
+        .iter()
+        .filter_map(|rename| Some((to_path(&rename.old_uri)?, to_path(&rename.new_uri)?)))
+        .collect::<Vec<_>>();
+    if renames.is_empty() {
+        return Ok(None);
+    }
+
+    let files = snap
+        .vfs()
+        .iter()
+        .filter(|(_, uri)| !snap.config.is_read_only(uri))
+        .map(|(file, _)| file)
+        .collect::<Vec<_>>();
+    let mut content_edits = HashMap::new();
+    for file in files {
+        let edits = snap.analysis.path_rename_edits(file, &renames)?;
+        if !edits.is_empty() {
+            content_edits.insert(file, edits);
+        }
+    }
+    if content_edits.is_empty() {
+        return Ok(None);
+    }
+    let ws_edit = ide::WorkspaceEdit {
+        content_edits,
+        file_system_edits: Vec::new(),
+    };
+    Ok(Some(convert::to_workspace_edit(
+        &snap.vfs(),
+        &snap.capabilities,
+        ws_edit,
+    )))
+}
+
+pub(crate) fn ssr(snap: StateSnapshot, rule: String) -> Result<Option<(String, WorkspaceEdit)>> {
+    let rule = ssr::Rule::parse(&rule).map_err(|err| {
+        ResponseError::new(
+            ErrorCode::INVALID_PARAMS,
+            format!("Invalid SSR rule: {err:#}"),
+        )
+    })?;
+    let files = snap
+        .vfs()
+        .iter()
+        .filter(|(_, uri)| !snap.config.is_read_only(uri))
+        .map(|(file, _)| file)
+        .collect::<Vec<_>>();
+    let mut content_edits = HashMap::new();
+    for file in files {
+        let edits = snap.analysis.ssr_edits(file, &rule)?;
+        if !edits.is_empty() {
+            content_edits.insert(file, edits);
+        }
+    }
+    if content_edits.is_empty() {
+        return Ok(None);
+    }
+    let ws_edit = ide::WorkspaceEdit {
+        content_edits,
+        file_system_edits: Vec::new(),
+    };
+    let edit = convert::to_workspace_edit(&snap.vfs(), &snap.capabilities, ws_edit);
+    Ok(Some(("Structural replace".into(), edit)))
+}
+
 pub(crate) fn semantic_token_full(
     snap: StateSnapshot,
     params: SemanticTokensParams,
@@ -174,11 +689,56 @@ pub(crate) fn semantic_token_range(
     })))
 }
 
-pub(crate) fn hover(snap: StateSnapshot, params: HoverParams) -> Result<Option<Hover>> {
+pub(crate) enum HoverReply {
+    Response(Option<Hover>),
+    /// A store path whose metadata is queried later and appended to the hover.
+    StorePath(Hover, PathBuf),
+}
+
+pub(crate) fn hover(snap: StateSnapshot, params: HoverParams) -> Result<HoverReply> {
     let (fpos, line_map) =
         convert::from_file_pos(&snap.vfs(), &params.text_document_position_params)?;
-    let ret = snap.analysis.hover(fpos)?;
-    Ok(ret.map(|hover| convert::to_hover(&line_map, hover)))
+    let Some(ret) = snap.analysis.hover(fpos)? else {
+        return Ok(HoverReply::Response(None));
+    };
+    let store_path = ret.store_path.clone();
+    let hover = convert::to_hover(&snap.vfs(), &line_map, ret);
+    Ok(match store_path {
+        Some(path) => HoverReply::StorePath(hover, path.into()),
+        None => HoverReply::Response(Some(hover)),
+    })
+}
+
+/// The hover markup of the metadata of a store path, or of its absence.
+pub(crate) fn store_path_info_markup(info: Option<&StorePathInfo>) -> String {
+    let Some(info) = info else {
+        return "Not in the local store".into();
+    };
+    let mut markup = format!("In the local store, size {}", format_size(info.nar_size));
+    if let Some(closure_size) = info.closure_size {
+        markup += &format!(", closure size {}", format_size(closure_size));
+    }
+    if let Some(deriver) = &info.deriver {
+        markup += &format!("\nDeriver `{deriver}`");
+    }
+    markup
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for &next in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{size:.1} {unit}")
 }
 
 pub(crate) fn document_symbol(
@@ -186,53 +746,82 @@ pub(crate) fn document_symbol(
     params: DocumentSymbolParams,
 ) -> Result<Option<DocumentSymbolResponse>> {
     let (file, line_map) = convert::from_file(&snap.vfs(), &params.text_document)?;
-    let syms = snap.analysis.symbol_hierarchy(file)?;
+    let mut syms = snap.analysis.symbol_hierarchy(file)?;
+    let (max_depth, max_count) = snap.config.document_symbol_limits();
+    ide::truncate_symbols(&mut syms, max_depth, max_count);
     let syms = convert::to_document_symbols(&line_map, syms);
     Ok(Some(DocumentSymbolResponse::Nested(syms)))
 }
 
+pub(crate) fn symbols_page(
+    snap: StateSnapshot,
+    params: SymbolsPageParams,
+) -> Result<SymbolsPageResult> {
+    let (file, line_map) = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let mut syms = snap.analysis.symbol_hierarchy(file)?;
+    for &idx in &params.parent {
+        ensure!(
+            idx < syms.len(),
+            ResponseError::new(ErrorCode::INVALID_PARAMS, "parent symbol not found"),
+        );
+        syms = syms.swap_remove(idx).children;
+    }
+    let total = syms.len();
+    let (max_depth, max_count) = snap.config.document_symbol_limits();
+    let mut page = syms.split_off(params.start.min(total));
+    let truncated = ide::truncate_symbols(&mut page, max_depth, max_count);
+    Ok(SymbolsPageResult {
+        symbols: convert::to_document_symbols(&line_map, page),
+        total,
+        truncated,
+    })
+}
+
 // FIXME: This is sync now.
 pub(crate) fn formatting(
     snap: StateSnapshot,
     params: DocumentFormattingParams,
 ) -> Result<Option<Vec<TextEdit>>> {
-    fn run_with_stdin(
-        cmd: &[String],
-        stdin_data: impl AsRef<[u8]> + Send + 'static,
-    ) -> Result<String> {
-        let mut child = process::Command::new(&cmd[0])
-            .args(&cmd[1..])
-            .stdin(process::Stdio::piped())
-            .stdout(process::Stdio::piped())
-            .stderr(process::Stdio::piped())
-            .spawn()?;
-        let mut stdin = child.stdin.take().unwrap();
-        std::thread::spawn(move || {
-            let _ = std::io::copy(&mut stdin_data.as_ref(), &mut stdin);
-        });
-        let output = child.wait_with_output()?;
-        ensure!(
-            output.status.success(),
-            "Formatter exited with {}, stderr: {}",
-            output.status,
-            String::from_utf8_lossy(&output.stderr),
-        );
-        let stdout = String::from_utf8(output.stdout)?;
-        Ok(stdout)
+    format_file(&snap, &params.text_document, None)
+}
+
+/// Format-on-save for clients preferring `willSaveWaitUntil`. No edits are returned, and the
+/// save is not delayed further, if the formatter does not finish within the budget.
+pub(crate) fn will_save_wait_until(
+    snap: StateSnapshot,
+    params: WillSaveTextDocumentParams,
+) -> Result<Option<Vec<TextEdit>>> {
+    // Auto-saves after delay would reformat the file while typing.
+    if !snap.config.formatting_will_save_enable
+        || params.reason == TextDocumentSaveReason::AFTER_DELAY
+    {
+        return Ok(None);
     }
+    let timeout = Duration::from_millis(snap.config.formatting_will_save_timeout_ms);
+    format_file(&snap, &params.text_document, Some(timeout))
+}
 
+fn format_file(
+    snap: &StateSnapshot,
+    text_document: &TextDocumentIdentifier,
+    timeout: Option<Duration>,
+) -> Result<Option<Vec<TextEdit>>> {
     let Some(cmd) = &snap.config.formatting_command else {
         return Ok(None);
     };
 
     let (file_content, line_map) = {
         let vfs = snap.vfs();
-        let (file, line_map) = convert::from_file(&vfs, &params.text_document)?;
+        let (file, line_map) = convert::from_file(&vfs, text_document)?;
         (vfs.content_for_file(file), line_map)
     };
 
-    let new_content = run_with_stdin(cmd, <Arc<[u8]>>::from(file_content.clone()))
+    let new_content = run_formatter(cmd, <Arc<[u8]>>::from(file_content.clone()), timeout)
         .with_context(|| format!("Failed to run formatter {cmd:?}"))?;
+    let Some(new_content) = new_content else {
+        tracing::warn!("Formatter {cmd:?} timed out after {timeout:?}");
+        return Ok(None);
+    };
 
     if new_content == *file_content {
         return Ok(None);
@@ -255,6 +844,67 @@ pub(crate) fn formatting(
     }]))
 }
 
+/// Run the formatter command `cmd` with the source in stdin, and return its stdout.
+/// Returns `None` if the formatter is killed on timeout.
+pub(crate) fn run_formatter(
+    cmd: &[String],
+    stdin_data: impl AsRef<[u8]> + Send + 'static,
+    timeout: Option<Duration>,
+) -> Result<Option<String>> {
+    let mut child = process::Command::new(&cmd[0])
+        .args(&cmd[1..])
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    std::thread::spawn(move || {
+        let _ = std::io::copy(&mut stdin_data.as_ref(), &mut stdin);
+    });
+    let output = match timeout {
+        None => child.wait_with_output()?,
+        Some(timeout) => {
+            // Drain pipes in background, so that the formatter is not blocked on writing.
+            let read_pipe = |pipe: Option<Box<dyn Read + Send>>| {
+                std::thread::spawn(move || {
+                    let mut buf = Vec::new();
+                    if let Some(mut pipe) = pipe {
+                        let _ = pipe.read_to_end(&mut buf);
+                    }
+                    buf
+                })
+            };
+            let stdout = read_pipe(child.stdout.take().map(|p| Box::new(p) as _));
+            let stderr = read_pipe(child.stderr.take().map(|p| Box::new(p) as _));
+            let deadline = Instant::now() + timeout;
+            let status = loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if Instant::now() >= deadline {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Ok(None);
+                }
+                std::thread::sleep(FORMATTER_POLL_PERIOD);
+            };
+            process::Output {
+                status,
+                stdout: stdout.join().unwrap_or_default(),
+                stderr: stderr.join().unwrap_or_default(),
+            }
+        }
+    };
+    ensure!(
+        output.status.success(),
+        "Formatter exited with {}, stderr: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr),
+    );
+    let stdout = String::from_utf8(output.stdout)?;
+    Ok(Some(stdout))
+}
+
 pub(crate) fn document_links(
     snap: StateSnapshot,
     params: DocumentLinkParams,
@@ -263,7 +913,10 @@ pub(crate) fn document_links(
     let links = snap.analysis.links(file)?;
     let links = links
         .into_iter()
-        .filter_map(|link| convert::to_document_link(&line_map, &params.text_document.uri, link))
+        .filter_map(|link| {
+            let link = resolve_link_path(&snap.config, link)?;
+            convert::to_document_link(&line_map, &params.text_document.uri, link)
+        })
         .collect::<Vec<_>>();
     Ok(Some(links))
 }
@@ -275,6 +928,7 @@ pub(crate) fn document_link_resolve(
     let (uri, frange, line_map) = convert::from_document_link(&snap.vfs(), &params)?;
     snap.analysis
         .link_resolve(frange)?
+        .and_then(|link| resolve_link_path(&snap.config, link))
         .and_then(|link| convert::to_document_link(&line_map, &uri, link))
         .ok_or_else(|| {
             anyhow::Error::new(ResponseError::new(
@@ -284,21 +938,152 @@ pub(crate) fn document_link_resolve(
         })
 }
 
+/// Resolve home and search paths of `link` according to the environment. Returns `None` if
+/// not found.
+fn resolve_link_path(config: &Config, link: Link) -> Option<Link> {
+    let Link::Resolved {
+        range,
+        tooltip,
+        target,
+    } = link
+    else {
+        return Some(link);
+    };
+    let path = match &target {
+        LinkTarget::HomePath(path) => {
+            let home = std::env::var_os("HOME")?;
+            Path::new(&home).join(path)
+        }
+        LinkTarget::SearchPath(path) => config.search_path().resolve(path, Path::exists)?,
+        LinkTarget::Uri(_) | LinkTarget::VfsPath(_) => {
+            return Some(Link::Resolved {
+                range,
+                tooltip,
+                target,
+            })
+        }
+    };
+    Some(Link::Resolved {
+        range,
+        tooltip: path.display().to_string(),
+        target: LinkTarget::VfsPath(VfsPath::new(path)),
+    })
+}
+
 pub(crate) fn code_action(
     snap: StateSnapshot,
     params: CodeActionParams,
-) -> Result<Option<CodeActionResponse>> {
-    let (file_id, _) = convert::from_file(&snap.vfs(), &params.text_document)?;
+) -> Result<Option<Vec<CodeAction>>> {
+    let (file_id, line_map) = convert::from_file(&snap.vfs(), &params.text_document)?;
     let (_, range) = convert::from_range(&snap.vfs(), file_id, params.range)?;
     let assists = snap.analysis.assists(FileRange { file_id, range })?;
+    let placeholder = snap.analysis.hash_placeholder(FilePos {
+        file_id,
+        pos: range.start(),
+    })?;
     let vfs = snap.vfs();
-    let actions = assists
+    let mut actions = assists
         .into_iter()
-        .map(|assist| convert::to_code_action(&vfs, assist))
-        .collect();
+        .filter(|assist| {
+            is_writable(&snap.config, &vfs, &assist.edits)
+                && convert::is_workspace_edit_supported(&snap.capabilities, &assist.edits)
+        })
+        .map(|assist| {
+            convert::to_code_action(
+                &vfs,
+                &line_map,
+                &params.context.diagnostics,
+                assist,
+                &snap.capabilities,
+            )
+        })
+        .collect::<Vec<_>>();
+    if placeholder.is_some() && !snap.config.is_read_only(&params.text_document.uri) {
+        actions.push(convert::to_prefetch_hash_action(
+            params.text_document.uri,
+            params.range.start,
+        ));
+    }
     Ok(Some(actions))
 }
 
+/// The range of the placeholder hash of the fetcher call at the position, and the source to
+/// prefetch for it.
+/// The range of the placeholder to be replaced, with the version of the document it is in.
+pub(crate) fn hash_placeholder(
+    snap: StateSnapshot,
+    (uri, pos): (Url, Position),
+) -> Result<Option<(Option<i32>, Range, PrefetchSource)>> {
+    if snap.config.is_read_only(&uri) {
+        return Err(read_only_error(&uri));
+    }
+    let (fpos, line_map) = convert::from_file_pos(
+        &snap.vfs(),
+        &TextDocumentPositionParams::new(TextDocumentIdentifier::new(uri), pos),
+    )?;
+    let Some(placeholder) = snap.analysis.hash_placeholder(fpos)? else {
+        return Ok(None);
+    };
+    let range = convert::to_range(&line_map, placeholder.range);
+    let version = snap.vfs().file_version(fpos.file_id);
+    Ok(Some((version, range, placeholder.source)))
+}
+
+/// Whether the edit touches no read-only file.
+fn is_writable(config: &Config, vfs: &Vfs, edit: &ide::WorkspaceEdit) -> bool {
+    let fs_uris = edit.file_system_edits.iter().flat_map(|edit| match edit {
+        ide::FileSystemEdit::CreateFile { path, .. } => vec![Url::from_vfs_path(path)],
+        ide::FileSystemEdit::RenameFile { file, to } => {
+            vec![vfs.uri_for_file(*file), Url::from_vfs_path(to)]
+        }
+    });
+    edit.content_edits
+        .keys()
+        .map(|&file| vfs.uri_for_file(file))
+        .chain(fs_uris)
+        .all(|uri| !config.is_read_only(&uri))
+}
+
+fn read_only_error(uri: &Url) -> anyhow::Error {
+    anyhow::Error::new(ResponseError::new(
+        ErrorCode::REQUEST_FAILED,
+        format!("{uri} is read-only"),
+    ))
+}
+
+pub(crate) fn code_lens(
+    snap: StateSnapshot,
+    params: CodeLensParams,
+) -> Result<Option<Vec<CodeLens>>> {
+    let (file, line_map) = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let lenses = snap.analysis.code_lenses(file)?;
+    let lenses = lenses
+        .into_iter()
+        .filter(|lens| match lens {
+            ide::CodeLens::References { .. } => snap.config.code_lens_references,
+            ide::CodeLens::FlakeOutput { .. } => snap.config.code_lens_flake_outputs,
+        })
+        .flat_map(|lens| convert::to_code_lenses(&line_map, &params.text_document.uri, lens))
+        .collect();
+    Ok(Some(lenses))
+}
+
+pub(crate) fn code_lens_resolve(snap: StateSnapshot, mut params: CodeLens) -> Result<CodeLens> {
+    let (uri, fpos) = convert::from_code_lens(&snap.vfs(), &params)?;
+    let refs = snap.analysis.references(fpos)?.unwrap_or_default();
+    let vfs = snap.vfs();
+    let locs = refs
+        .into_iter()
+        .map(|frange| convert::to_location(&vfs, frange))
+        .collect();
+    params.command = Some(convert::to_show_references_command(
+        uri,
+        params.range.start,
+        locs,
+    ));
+    Ok(params)
+}
+
 pub(crate) fn document_highlight(
     snap: StateSnapshot,
     params: DocumentHighlightParams,
@@ -310,6 +1095,56 @@ pub(crate) fn document_highlight(
     Ok(Some(ret))
 }
 
+pub(crate) fn inlay_hint(
+    snap: StateSnapshot,
+    params: InlayHintParams,
+) -> Result<Option<Vec<InlayHint>>> {
+    let (file, range, line_map) = {
+        let vfs = snap.vfs();
+        let (file, line_map) = convert::from_file(&vfs, &params.text_document)?;
+        let (_, range) = convert::from_range(&vfs, file, params.range)?;
+        (file, range, line_map)
+    };
+    let hints = snap.analysis.inlay_hints(file, Some(range))?;
+    let hints = hints
+        .into_iter()
+        .filter(|hint| match hint.kind {
+            ide::InlayHintKind::BindingType => snap.config.inlay_hints_binding_types,
+            ide::InlayHintKind::ParamType => snap.config.inlay_hints_parameter_types,
+        })
+        .map(|hint| convert::to_inlay_hint(&line_map, hint))
+        .collect();
+    Ok(Some(hints))
+}
+
+pub(crate) fn linked_editing_range(
+    snap: StateSnapshot,
+    params: LinkedEditingRangeParams,
+) -> Result<Option<LinkedEditingRanges>> {
+    let (fpos, line_map) =
+        convert::from_file_pos(&snap.vfs(), &params.text_document_position_params)?;
+    let Some(ranges) = snap.analysis.linked_editing_ranges(fpos)? else {
+        return Ok(None);
+    };
+    Ok(Some(LinkedEditingRanges {
+        ranges: ranges
+            .into_iter()
+            .map(|range| convert::to_range(&line_map, range))
+            .collect(),
+        // Only identifiers are linked.
+        word_pattern: Some(r"[A-Za-z_][A-Za-z0-9_'-]*".into()),
+    }))
+}
+
+pub(crate) fn signature_help(
+    snap: StateSnapshot,
+    params: SignatureHelpParams,
+) -> Result<Option<SignatureHelp>> {
+    let (fpos, _) = convert::from_file_pos(&snap.vfs(), &params.text_document_position_params)?;
+    let ret = snap.analysis.signature_help(fpos)?;
+    Ok(ret.map(convert::to_signature_help))
+}
+
 pub(crate) fn parent_module(
     snap: StateSnapshot,
     params: TextDocumentPositionParams,
@@ -323,3 +1158,161 @@ pub(crate) fn parent_module(
         .collect();
     Ok(Some(GotoDefinitionResponse::Array(locs)))
 }
+
+/// The module graph of loaded files under `roots`, updating the one of the last session `prev`.
+/// Files in `opened` may have unsaved changes. Only files changed since summarized in `prev` are
+/// analyzed, opened ones first, and at most `MAX_SUMMARIZED_FILES` of them.
+pub(crate) fn module_graph(
+    snap: StateSnapshot,
+    (roots, opened, prev): (Vec<PathBuf>, HashSet<Url>, Option<Arc<ModuleGraph>>),
+) -> Result<ModuleGraph> {
+    let mut graph = prev.map_or_else(ModuleGraph::default, |graph| (*graph).clone());
+    let mut files = {
+        let vfs = snap.vfs();
+        vfs.iter()
+            .filter_map(|(file, uri)| {
+                let path = uri.to_file_path().ok()?;
+                if !roots.iter().any(|root| path.starts_with(root)) {
+                    return None;
+                }
+                let is_opened = opened.contains(&uri);
+                let text = vfs.content_for_file(file);
+                // The disk is only compared for opened files, see `FileSummary::new`.
+                if !is_opened && graph.is_up_to_date(&path, &text) {
+                    return None;
+                }
+                Some((file, path, is_opened, text))
+            })
+            .collect::<Vec<_>>()
+    };
+    files.sort_by(|lhs, rhs| rhs.2.cmp(&lhs.2).then_with(|| lhs.1.cmp(&rhs.1)));
+    if files.len() > MAX_SUMMARIZED_FILES {
+        tracing::info!(
+            "Summarizing {MAX_SUMMARIZED_FILES}/{} changed files of the module graph",
+            files.len(),
+        );
+        files.truncate(MAX_SUMMARIZED_FILES);
+    }
+    for (file, path, is_opened, text) in files {
+        let refs = snap.analysis.file_references(file)?;
+        let refs = {
+            let vfs = snap.vfs();
+            refs.into_iter()
+                .filter_map(|file| vfs.uri_for_file(file).to_file_path().ok())
+                .collect()
+        };
+        match FileSummary::new(&path, &text, is_opened, refs) {
+            Some(summary) => graph.insert(path, summary),
+            None => graph.remove(&path),
+        }
+    }
+    Ok(graph)
+}
+
+pub(crate) fn profile(snap: StateSnapshot, params: ProfileParams) -> Result<Vec<ProfileEntry>> {
+    let uri = params.text_document.map(|doc| doc.uri);
+    Ok(snap.profiler.entries(uri.as_ref()))
+}
+
+pub(crate) fn memory_usage(snap: StateSnapshot, (): ()) -> Result<MemoryUsageResult> {
+    let (vfs_files, vfs_bytes) = {
+        let vfs = snap.vfs();
+        let files = vfs.iter().map(|(file, _)| file).collect::<Vec<_>>();
+        let bytes = files
+            .iter()
+            .map(|&file| vfs.content_for_file(file).len())
+            .sum();
+        (files.len(), bytes)
+    };
+    let queries = snap
+        .analysis
+        .query_stats()?
+        .into_iter()
+        .map(|stats| QueryStats {
+            name: stats.name.into(),
+            entries: stats.entries,
+            memoized: stats.memoized,
+        })
+        .collect();
+    Ok(MemoryUsageResult {
+        resident_bytes: resident_bytes(),
+        vfs_files,
+        vfs_bytes,
+        queries,
+    })
+}
+
+/// The resident set size of this process, from `VmRSS` in `/proc/self/status`.
+fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kib = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+pub(crate) fn syntax_tree(snap: StateSnapshot, params: SyntaxTreeParams) -> Result<String> {
+    let (file, _) = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let range = match params.range {
+        Some(range) => Some(convert::from_range(&snap.vfs(), file, range)?.1),
+        None => None,
+    };
+    Ok(snap.analysis.syntax_tree(file, range)?)
+}
+
+/// The selected expression to evaluate standalone in `nix repl`, and the directory to resolve
+/// its relative paths against.
+pub(crate) fn repl_expr(snap: StateSnapshot, params: ReplEvalParams) -> Result<(String, PathBuf)> {
+    let (file_id, _) = convert::from_file(&snap.vfs(), &params.text_document)?;
+    let (_, range) = convert::from_range(&snap.vfs(), file_id, params.range)?;
+    let expr = snap
+        .analysis
+        .repl_expr(FileRange { file_id, range })?
+        .map_err(|msg| ResponseError::new(ErrorCode::REQUEST_FAILED, msg))?;
+    let base_dir = params
+        .text_document
+        .uri
+        .to_file_path()
+        .ok()
+        .and_then(|path| Some(path.parent()?.to_owned()))
+        .unwrap_or_else(|| snap.config.root_path.clone());
+    Ok((expr, base_dir))
+}
+
+pub(crate) fn flake_input_source(
+    snap: StateSnapshot,
+    params: TextDocumentIdentifier,
+) -> Result<Option<FlakeInputSourceResult>> {
+    let (file, _) = convert::from_file(&snap.vfs(), &params)?;
+    let Some((input, path)) = snap.analysis.flake_input_for_file(file)? else {
+        return Ok(None);
+    };
+    let lock_src = {
+        let vfs = snap.vfs();
+        let root = params
+            .uri
+            .to_file_path()
+            .ok()
+            .and_then(|path| vfs.root_for_path(&path).map(Path::to_owned))
+            .unwrap_or_else(|| snap.config.root_path.clone());
+        let lock_path = VfsPath::new(root.join(FLAKE_LOCK_FILE));
+        vfs.content_for_file(vfs.file_for_path(&lock_path)?)
+    };
+    let mut sources = flake_lock::resolve_flake_input_sources(lock_src.as_bytes())?;
+    let source = sources
+        .remove(&input)
+        .with_context(|| format!("Input {input:?} is not in the lock file"))?;
+    Ok(Some(FlakeInputSourceResult {
+        input,
+        path: path.display().to_string(),
+        url: source.url,
+        rev: source.rev,
+        ref_: source.ref_,
+        last_modified: source.last_modified,
+    }))
+}
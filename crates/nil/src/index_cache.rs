@@ -0,0 +1,235 @@
+//! Persistent cache of nixpkgs indexes on the disk, so that restarts skip the slow evaluation.
+//!
+//! Indexes are stored as JSON under `$XDG_CACHE_HOME/nil`, keyed by the revision of nixpkgs.
+//! The revision is only known for store paths and channels with `.git-revision`, otherwise
+//! nothing is cached, since a local checkout can change at any time.
+//! Module graphs of workspaces are also stored here, see `module_graph`.
+use anyhow::{Context, Result};
+use nix_interop::store_path::NIX_STORE_DIR;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// Bumped on incompatible changes of the format of any index.
+/// Entries written by other versions of nil are also ignored.
+const CACHE_VERSION: u32 = 1;
+
+/// The default number of entries kept for each kind of index, eg. revisions of nixpkgs.
+const MAX_ENTRIES: usize = 4;
+
+#[derive(Debug, Clone)]
+pub(crate) struct IndexCache {
+    dir: PathBuf,
+    max_entries: usize,
+}
+
+#[derive(Serialize)]
+struct EntryRef<'a, T> {
+    version: u32,
+    nil_version: &'a str,
+    data: &'a T,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+    nil_version: String,
+}
+
+#[derive(Deserialize)]
+struct Entry<T> {
+    data: T,
+}
+
+impl IndexCache {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            max_entries: MAX_ENTRIES,
+        }
+    }
+
+    /// Keep at most `max_entries` entries for each kind on `store`.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// `$XDG_CACHE_HOME/nil`, or `$HOME/.cache/nil`.
+    pub fn from_env() -> Option<Self> {
+        let cache_home = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            // Relative paths are invalid and should be ignored, per XDG Base Directory spec.
+            .filter(|path| path.is_absolute())
+            .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".cache")))?;
+        Some(Self::new(cache_home.join("nil")))
+    }
+
+    fn path(&self, kind: &str, rev: &str) -> PathBuf {
+        self.dir.join(format!("{kind}-{rev}.json"))
+    }
+
+    /// Load the index `kind` of `rev`, which is usually a nixpkgs revision.
+    /// Missing, outdated or corrupted entries are `None`.
+    pub fn load<T: DeserializeOwned>(&self, kind: &str, rev: &str) -> Option<T> {
+        let src = fs::read(self.path(kind, rev)).ok()?;
+        let header = serde_json::from_slice::<Header>(&src).ok()?;
+        if header.version != CACHE_VERSION || header.nil_version != env!("CARGO_PKG_VERSION") {
+            return None;
+        }
+        match serde_json::from_slice::<Entry<T>>(&src) {
+            Ok(entry) => Some(entry.data),
+            Err(err) => {
+                tracing::warn!("Ignoring corrupted cache of {kind}: {err}");
+                None
+            }
+        }
+    }
+
+    /// Store the index `kind` of `rev`, and remove old entries of the same kind.
+    pub fn store<T: Serialize>(&self, kind: &str, rev: &str, data: &T) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let path = self.path(kind, rev);
+        // Write to a temporary file first, so other instances never see partial files.
+        let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+        let ret = (|| {
+            let mut file = BufWriter::new(fs::File::create(&tmp_path)?);
+            let entry = EntryRef {
+                version: CACHE_VERSION,
+                nil_version: env!("CARGO_PKG_VERSION"),
+                data,
+            };
+            serde_json::to_writer(&mut file, &entry)?;
+            file.into_inner().map_err(|err| err.into_error())?;
+            fs::rename(&tmp_path, &path)?;
+            anyhow::Ok(())
+        })();
+        if ret.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        ret.with_context(|| format!("Failed to write {}", path.display()))?;
+        self.prune(kind);
+        Ok(())
+    }
+
+    /// Remove all but the most recently written `max_entries` entries of `kind`.
+    fn prune(&self, kind: &str) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let prefix = format!("{kind}-");
+        let mut entries = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let name = entry.file_name();
+                let name = name.to_str()?;
+                if !name.starts_with(&prefix) || !name.ends_with(".json") {
+                    return None;
+                }
+                let mtime = entry.metadata().ok()?.modified().ok()?;
+                Some((mtime, entry.path()))
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|lhs, rhs| rhs.0.cmp(&lhs.0));
+        for (_, path) in entries.iter().skip(self.max_entries) {
+            tracing::debug!("Removing outdated cache {}", path.display());
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// The immutable revision of nixpkgs at `path`, if known.
+///
+/// It is the hash of the store path for `/nix/store/<hash>-<name>`, or the content of
+/// `.git-revision` shipped by channels.
+pub(crate) fn nixpkgs_revision(path: &Path) -> Option<String> {
+    let is_rev_char = |c: char| c.is_ascii_alphanumeric();
+    if let Ok(rest) = path.strip_prefix(NIX_STORE_DIR) {
+        let mut components = rest.components();
+        let name = components.next()?.as_os_str().to_str()?;
+        let (hash, _) = name.split_once('-')?;
+        // Sub-directories of store paths are not expected.
+        if components.next().is_none() && !hash.is_empty() && hash.chars().all(is_rev_char) {
+            return Some(hash.to_owned());
+        }
+        return None;
+    }
+    let rev = fs::read_to_string(path.join(".git-revision")).ok()?;
+    let rev = rev.trim();
+    (!rev.is_empty() && rev.chars().all(is_rev_char)).then(|| format!("git-{rev}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{nixpkgs_revision, IndexCache, MAX_ENTRIES};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn revision() {
+        assert_eq!(
+            nixpkgs_revision(Path::new(
+                "/nix/store/hap5a6iw5rccl21adfxh5b3lk2c8qnmj-source"
+            ))
+            .as_deref(),
+            Some("hap5a6iw5rccl21adfxh5b3lk2c8qnmj"),
+        );
+        assert_eq!(
+            nixpkgs_revision(Path::new(
+                "/nix/store/hap5a6iw5rccl21adfxh5b3lk2c8qnmj-source/pkgs"
+            )),
+            None,
+        );
+
+        let dir = std::env::temp_dir().join(format!("nil-index-rev-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        assert_eq!(nixpkgs_revision(&dir), None);
+        fs::write(dir.join(".git-revision"), "0123abcd\n").unwrap();
+        assert_eq!(nixpkgs_revision(&dir).as_deref(), Some("git-0123abcd"));
+        fs::write(dir.join(".git-revision"), "../foo").unwrap();
+        assert_eq!(nixpkgs_revision(&dir), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_store() {
+        let dir = std::env::temp_dir().join(format!("nil-index-cache-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let cache = IndexCache::new(dir.clone());
+        let index = HashMap::from([("hello".to_owned(), 42)]);
+
+        assert_eq!(cache.load::<HashMap<String, i32>>("packages", "a"), None);
+        cache.store("packages", "a", &index).unwrap();
+        assert_eq!(cache.load("packages", "a"), Some(index.clone()));
+        // Different kinds and revisions.
+        assert_eq!(cache.load::<HashMap<String, i32>>("options", "a"), None);
+        assert_eq!(cache.load::<HashMap<String, i32>>("packages", "b"), None);
+        // Incompatible data.
+        assert_eq!(cache.load::<Vec<String>>("packages", "a"), None);
+
+        // Outdated versions.
+        let path = dir.join("packages-a.json");
+        let src = fs::read_to_string(&path).unwrap();
+        fs::write(&path, src.replace(r#""version":1"#, r#""version":0"#)).unwrap();
+        assert_eq!(cache.load::<HashMap<String, i32>>("packages", "a"), None);
+
+        for rev in 0..MAX_ENTRIES + 2 {
+            cache.store("packages", &rev.to_string(), &index).unwrap();
+        }
+        cache.store("options", "a", &index).unwrap();
+        let mut files = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files.len(), MAX_ENTRIES + 1);
+        assert!(files.contains(&"options-a.json".to_owned()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
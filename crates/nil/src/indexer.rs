@@ -0,0 +1,169 @@
+//! Background indexing of all Nix files in the workspace.
+//!
+//! Files are read in batches by a dedicated thread pool, so that the main loop can still handle
+//! interactive requests between batches. After all files are loaded, their syntax and name
+//! resolution are computed in parallel. This is cancelled by any following change, which
+//! also has the priority over the indexing.
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{Context, Result};
+use ide::{Analysis, FileId};
+use lsp_types::Url;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::session::FileSource;
+
+/// Files larger than this are not indexed. They are usually generated.
+pub(crate) const MAX_INDEXED_FILE_LEN: u64 = 4 << 20;
+/// The number of files read in a batch. Files of a batch are applied together.
+pub(crate) const INDEX_BATCH_LEN: usize = 256;
+
+pub(crate) fn build_thread_pool(num_threads: Option<usize>) -> Result<ThreadPool> {
+    ThreadPoolBuilder::new()
+        .num_threads(num_threads.unwrap_or(0))
+        .thread_name(|i| format!("nil-indexer-{i}"))
+        .build()
+        .context("Failed to build the indexer thread pool")
+}
+
+/// Collect at most `max_files` Nix files under `root`, in a deterministic order.
+/// Hidden directories and symlinks are skipped.
+pub fn collect_nix_files(root: &Path, max_files: usize) -> Vec<PathBuf> {
+    let files = walkdir::WalkDir::new(root)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+        })
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry.file_type().is_file()
+                && entry.path().extension().map_or(false, |ext| ext == "nix")
+                && entry
+                    .metadata()
+                    .map_or(false, |meta| meta.len() <= MAX_INDEXED_FILE_LEN)
+        })
+        .map(|entry| entry.into_path());
+    files.take(max_files).collect()
+}
+
+/// Read files in parallel. Unreadable files are skipped.
+pub(crate) fn read_files(
+    pool: &ThreadPool,
+    file_source: &FileSource,
+    paths: &[PathBuf],
+) -> Vec<(Url, String)> {
+    pool.install(|| {
+        paths
+            .par_iter()
+            .filter_map(|path| {
+                let uri = Url::from_file_path(path).ok()?;
+                match file_source.read(&uri, || read_regular_file(path)) {
+                    Ok(text) => Some((uri, text)),
+                    Err(err) => {
+                        tracing::debug!("Skip indexing {path:?}: {err}");
+                        None
+                    }
+                }
+            })
+            .collect()
+    })
+}
+
+/// Compute the syntax and name resolution of `files`, using one snapshot per worker.
+/// Workers take files from a shared queue until all files are done or any is cancelled.
+/// Returns the number of finished files.
+pub(crate) fn prime_caches(pool: &ThreadPool, snapshots: Vec<Analysis>, files: &[FileId]) -> usize {
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    pool.scope(|s| {
+        for snap in snapshots {
+            let (next, done) = (&next, &done);
+            s.spawn(move |_| loop {
+                let Some(&file) = files.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                    return;
+                };
+                match snap.prime_file(file) {
+                    Ok(()) => {
+                        done.fetch_add(1, Ordering::Relaxed);
+                    }
+                    // Stop all workers. Pending changes must be applied first.
+                    Err(_) => {
+                        next.store(files.len(), Ordering::Relaxed);
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    done.into_inner()
+}
+
+/// Read a file from the disk, refusing non-regular files.
+pub(crate) fn read_regular_file(path: &Path) -> io::Result<String> {
+    #[cfg(unix)]
+    use rustix::fs::{fcntl_getfl, fcntl_setfl, OFlags, OpenOptionsExt};
+
+    // Rule out non-regular files which may block `open()` infinitely
+    // (eg. FIFO). We open it with `O_NONBLOCK` and check it before reading.
+    let mut options = std::fs::File::options();
+    options.read(true);
+    #[cfg(unix)]
+    options.custom_flags(OFlags::NONBLOCK.bits() as _);
+
+    let mut file = options.open(path)?;
+    let ft = file.metadata()?.file_type();
+    if !ft.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("non-regular file type: {ft:?}"),
+        ));
+    }
+
+    // Remove the O_NONBLOCK flag for blocking read.
+    #[cfg(unix)]
+    {
+        let flags = fcntl_getfl(&file)? - OFlags::NONBLOCK;
+        fcntl_setfl(&file, flags)?;
+    }
+
+    let mut buf = String::new();
+    file.read_to_string(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn collect_files() {
+        let dir = std::env::temp_dir().join(format!("nil-indexer-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for path in ["a.nix", "b.txt", "c/d.nix", "c/e.nix", ".git/f.nix"] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "1").unwrap();
+        }
+
+        let got = collect_nix_files(&dir, 10)
+            .into_iter()
+            .map(|path| path.strip_prefix(&dir).unwrap().display().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(got, ["a.nix", "c/d.nix", "c/e.nix"]);
+        assert_eq!(collect_nix_files(&dir, 2).len(), 2);
+
+        let pool = build_thread_pool(Some(2)).unwrap();
+        let paths = collect_nix_files(&dir, 10);
+        let got = read_files(&pool, &FileSource::Disk, &paths);
+        assert_eq!(got.len(), 3);
+        assert!(got.iter().all(|(_, text)| text == "1"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,27 +1,53 @@
+mod cancel;
 mod capabilities;
 mod config;
 mod convert;
+mod doctor;
+mod format_files;
 mod handler;
+mod index_cache;
+mod indexer;
+mod log_trace;
 mod lsp_ext;
 mod meter;
+mod module_graph;
+mod path_cache;
+mod profile;
 mod semantic_tokens;
 mod server;
+mod session;
+mod trace;
+mod transport;
 mod vfs;
 
 use anyhow::{Context, Result};
-use async_lsp::client_monitor::ClientProcessMonitorLayer;
 use async_lsp::concurrency::ConcurrencyLayer;
 use async_lsp::server::LifecycleLayer;
 use async_lsp::stdio::{PipeStdin, PipeStdout};
 use async_lsp::tracing::TracingLayer;
+use futures::{AsyncRead, AsyncWrite};
 use ide::VfsPath;
 use lsp_types::Url;
+use std::path::Path;
+use std::sync::Arc;
 use tower::ServiceBuilder;
 
+pub use doctor::doctor;
+pub use format_files::format_files;
+pub use indexer::collect_nix_files;
+pub use log_trace::LogTraceLayer;
+pub use session::replay;
+pub use trace::ChromeTraceLayer;
+pub use transport::ListenAddr;
+
 pub(crate) use server::{Server, StateSnapshot};
-pub(crate) use vfs::{LineMap, Vfs};
+pub(crate) use vfs::{LineMap, PositionEncoding, Vfs};
 
+use crate::cancel::CancelLayer;
 use crate::meter::MeterLayer;
+use crate::profile::Profiler;
+use crate::session::{FileSource, Recorder, RecordingInput};
+use crate::transport::{ClientMonitorLayer, Compat};
 
 /// The file length limit. Files larger than this will be rejected from all interactions.
 /// The hard limit is `u32::MAX` due to following conditions.
@@ -59,7 +85,46 @@ impl UrlExt for Url {
     }
 }
 
-pub async fn run_server_stdio() -> Result<()> {
+/// Run the language server on stdin and stdout.
+/// If `record` is set, the session is recorded into this file for `replay`.
+pub async fn run_server_stdio(record: Option<&Path>) -> Result<()> {
+    let stdin = PipeStdin::lock_tokio().context("stdin is not pipe-like")?;
+    let stdout = PipeStdout::lock_tokio().context("stdout is not pipe-like")?;
+    run_server_recorded(stdin, stdout, record, true).await
+}
+
+/// Run the language server for the first client connecting to `addr`.
+/// The client is assumed to be remote, thus its process is not monitored.
+/// If `record` is set, the session is recorded into this file for `replay`.
+pub async fn run_server_listen(addr: &ListenAddr, record: Option<&Path>) -> Result<()> {
+    let stream = transport::accept(addr).await?;
+    let (input, output) = tokio::io::split(stream);
+    run_server_recorded(Compat(input), Compat(output), record, false).await
+}
+
+async fn run_server_recorded(
+    input: impl AsyncRead + Unpin,
+    output: impl AsyncWrite,
+    record: Option<&Path>,
+    is_local_client: bool,
+) -> Result<()> {
+    match record {
+        None => run_server(input, output, FileSource::Disk, is_local_client).await?,
+        Some(path) => {
+            let recorder = Arc::new(Recorder::create(path)?);
+            let input = RecordingInput::new(input, recorder.clone());
+            run_server(input, output, FileSource::Record(recorder), is_local_client).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn run_server(
+    input: impl AsyncRead,
+    output: impl AsyncWrite,
+    file_source: FileSource,
+    is_local_client: bool,
+) -> async_lsp::Result<()> {
     let concurrency = match std::thread::available_parallelism() {
         // Double the concurrency limit since many handlers are blocking anyway.
         Ok(n) => n.saturating_mul(2.try_into().expect("2 is not 0")),
@@ -72,10 +137,8 @@ pub async fn run_server_stdio() -> Result<()> {
 
     let init_messages = Vec::new();
 
-    let stdin = PipeStdin::lock_tokio().context("stdin is not pipe-like")?;
-    let stdout = PipeStdout::lock_tokio().context("stdout is not pipe-like")?;
-
     let (mainloop, _) = async_lsp::MainLoop::new_server(|client| {
+        let profiler = Arc::<Profiler>::default();
         ServiceBuilder::new()
             .layer(
                 TracingLayer::new()
@@ -83,13 +146,19 @@ pub async fn run_server_stdio() -> Result<()> {
                     .notification(|n| tracing::info_span!("notification", method = n.method))
                     .event(|e| tracing::info_span!("event", method = e.type_name())),
             )
-            .layer(MeterLayer)
+            .layer(MeterLayer::new(profiler.clone()))
             .layer(LifecycleLayer::default())
+            .layer(CancelLayer)
             // TODO: Use `CatchUnwindLayer`.
             .layer(ConcurrencyLayer::new(concurrency))
-            .layer(ClientProcessMonitorLayer::new(client.clone()))
-            .service(Server::new_router(client, init_messages))
+            .layer(ClientMonitorLayer::new(client.clone(), is_local_client))
+            .service(Server::new_router(
+                client,
+                init_messages,
+                file_source,
+                profiler,
+            ))
     });
 
-    Ok(mainloop.run_buffered(stdin, stdout).await?)
+    mainloop.run_buffered(input, output).await
 }
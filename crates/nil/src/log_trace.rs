@@ -0,0 +1,120 @@
+//! Forward logs to the client via `$/logTrace`, controlled by the `trace` value of `initialize`
+//! and `$/setTrace`, so that verbose logging can be switched at runtime.
+use async_lsp::ClientSocket;
+use lsp_types::notification::LogTrace;
+use lsp_types::{LogTraceParams, TraceValue};
+use std::cell::Cell;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+const TRACE_OFF: u8 = 0;
+const TRACE_MESSAGES: u8 = 1;
+const TRACE_VERBOSE: u8 = 2;
+
+static TRACE: AtomicU8 = AtomicU8::new(TRACE_OFF);
+static CLIENT: Mutex<Option<ClientSocket>> = Mutex::new(None);
+
+thread_local! {
+    /// Set while sending a log, so that logs emitted by the sending itself are not forwarded.
+    static IN_SEND: Cell<bool> = Cell::new(false);
+}
+
+/// Set the client to forward logs to. Logs are dropped before this is called.
+pub(crate) fn set_client(client: ClientSocket) {
+    *CLIENT.lock().unwrap() = Some(client);
+}
+
+pub(crate) fn set_trace(value: TraceValue) {
+    let value = match value {
+        TraceValue::Off => TRACE_OFF,
+        TraceValue::Messages => TRACE_MESSAGES,
+        TraceValue::Verbose => TRACE_VERBOSE,
+    };
+    TRACE.store(value, Ordering::Relaxed);
+}
+
+/// A tracing layer sending events as `$/logTrace` notifications.
+/// With `messages`, events at `INFO` level or above are sent. With `verbose`, `DEBUG` events are
+/// also sent, and the target and level are attached as the `verbose` field.
+/// This is independent of `NIL_LOG`, which only filters logs written to stderr or `--log-file`.
+#[derive(Debug, Default)]
+pub struct LogTraceLayer;
+
+impl<S: Subscriber> Layer<S> for LogTraceLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let trace = TRACE.load(Ordering::Relaxed);
+        let meta = event.metadata();
+        let max_level = match trace {
+            TRACE_OFF => return,
+            TRACE_MESSAGES => Level::INFO,
+            _ => Level::DEBUG,
+        };
+        if *meta.level() > max_level || IN_SEND.with(Cell::get) {
+            return;
+        }
+
+        let mut message = String::new();
+        event.record(&mut MessageVisitor(&mut message));
+        let verbose =
+            (trace == TRACE_VERBOSE).then(|| format!("{} {}", meta.level(), meta.target()));
+
+        IN_SEND.with(|flag| flag.set(true));
+        if let Some(client) = &*CLIENT.lock().unwrap() {
+            let _: Result<_, _> = client.notify::<LogTrace>(LogTraceParams { message, verbose });
+        }
+        IN_SEND.with(|flag| flag.set(false));
+    }
+}
+
+/// Format the `message` field and other fields as `name=value`, in the order of recording.
+struct MessageVisitor<'a>(&'a mut String);
+
+impl Visit for MessageVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        } else {
+            let _ = write!(self.0, "{}={value:?}", field.name());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MessageVisitor;
+    use std::sync::{Arc, Mutex};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    impl<S: Subscriber> Layer<S> for Capture {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            let mut message = String::new();
+            event.record(&mut MessageVisitor(&mut message));
+            self.0.lock().unwrap().push(message);
+        }
+    }
+
+    #[test]
+    fn message() {
+        let got = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(Capture(got.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("Loaded {} files", 42);
+            tracing::warn!(path = "a.nix", count = 1, "Failed");
+        });
+        assert_eq!(
+            *got.lock().unwrap(),
+            ["Loaded 42 files", r#"Failed path="a.nix" count=1"#],
+        );
+    }
+}
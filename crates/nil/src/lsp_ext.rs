@@ -1,5 +1,82 @@
 use lsp_types::notification::Notification;
 use lsp_types::request::Request;
+use lsp_types::{DocumentSymbol, Range, TextDocumentIdentifier, Url};
+use serde::{Deserialize, Serialize};
+
+/// The client command to show a list of references, used by reference count code lenses.
+/// Arguments are `[uri: Url, position: Position, locations: Location[]]`, the same as
+/// VSCode's `editor.action.showReferences`.
+pub const SHOW_REFERENCES_COMMAND: &str = "nil.showReferences";
+
+/// The server command to evaluate the derivation path of a flake output of the workspace.
+/// Arguments are `[attrpath: string]`, where `attrpath` is relative to the flake outputs.
+pub const EVAL_FLAKE_OUTPUT_COMMAND: &str = "nil.evalFlakeOutput";
+
+/// The server command to build a flake output of the workspace.
+/// Arguments are the same as `EVAL_FLAKE_OUTPUT_COMMAND`.
+pub const BUILD_FLAKE_OUTPUT_COMMAND: &str = "nil.buildFlakeOutput";
+
+/// The server command to apply a quick fix via `workspace/applyEdit`.
+/// Arguments are `[params: ApplyFixParams]`. Returns whether the edit is applied.
+pub const APPLY_FIX_COMMAND: &str = "nil.applyFix";
+
+/// The server command to find references of the name at a position.
+/// Arguments are `[uri: Url, position: Position]`. Returns `Location[] | null`.
+pub const SHOW_REFERENCES_AT_COMMAND: &str = "nil.showReferencesAt";
+
+/// The server command to drop cached analysis results to release memory. No arguments.
+pub const COLLECT_GARBAGE_COMMAND: &str = "nil.collectGarbage";
+
+/// The server command to reload the flake workspace, the same as `nil/reloadFlake`.
+/// No arguments.
+pub const RELOAD_FLAKE_COMMAND: &str = "nil.reloadFlake";
+
+/// The server command to reload flakes, NixOS options and the package index, and re-index all
+/// files of the workspace, the same as `nil/reloadWorkspace`. No arguments.
+pub const RELOAD_WORKSPACE_COMMAND: &str = "nil.reloadWorkspace";
+
+/// The server command to check the environment, configuration and client capabilities.
+/// No arguments. Returns the report as a string, which is also shown as a message.
+pub const DOCTOR_COMMAND: &str = "nil.doctor";
+
+/// The server command to replace all matches of a structural search and replace rule in the
+/// workspace via `workspace/applyEdit`. Arguments are `[rule: string]`, where `rule` is like
+/// `lib.optional $c [$e] ==>> lib.optionals $c [$e]`. Returns whether the edit is applied.
+pub const SSR_COMMAND: &str = "nil.ssr";
+
+/// The server command to prefetch the source of a fetcher call with a placeholder hash, like
+/// `hash = "";`, and fill in the hash via `workspace/applyEdit`. Arguments are
+/// `[uri: Url, position: Position]`, in the fetcher call. Returns whether the edit is applied.
+pub const PREFETCH_HASH_COMMAND: &str = "nil.prefetchHash";
+
+/// The server command to evaluate the selected expression in `nix repl`, the same as
+/// `nil/replEval`. Arguments are `[uri: Url, range: Range]`. Returns `ReplEvalResult`.
+pub const REPL_EVAL_COMMAND: &str = "nil.replEval";
+
+/// All server commands available in `workspace/executeCommand`.
+pub const SERVER_COMMANDS: &[&str] = &[
+    APPLY_FIX_COMMAND,
+    SHOW_REFERENCES_AT_COMMAND,
+    COLLECT_GARBAGE_COMMAND,
+    RELOAD_FLAKE_COMMAND,
+    RELOAD_WORKSPACE_COMMAND,
+    EVAL_FLAKE_OUTPUT_COMMAND,
+    BUILD_FLAKE_OUTPUT_COMMAND,
+    DOCTOR_COMMAND,
+    SSR_COMMAND,
+    PREFETCH_HASH_COMMAND,
+    REPL_EVAL_COMMAND,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplyFixParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+    /// The assist identifier to apply. If omitted, the first quick fix in `range` is applied.
+    #[serde(default)]
+    pub id: Option<String>,
+}
 
 /// <https://github.com/microsoft/language-server-protocol/issues/1002>
 pub enum ParentModule {}
@@ -16,3 +93,421 @@ impl Notification for ReloadFlake {
     type Params = ();
     const METHOD: &'static str = "nil/reloadFlake";
 }
+
+pub enum ReloadWorkspace {}
+
+impl Notification for ReloadWorkspace {
+    type Params = ();
+    const METHOD: &'static str = "nil/reloadWorkspace";
+}
+
+/// Fetch the children of a document symbol page by page, to browse outlines truncated by
+/// `documentSymbol.maxDepth` or `documentSymbol.maxCount`.
+pub enum SymbolsPage {}
+
+impl Request for SymbolsPage {
+    type Params = SymbolsPageParams;
+    type Result = SymbolsPageResult;
+    const METHOD: &'static str = "nil/symbolsPage";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolsPageParams {
+    pub text_document: TextDocumentIdentifier,
+    /// The indices of symbols from the top level down to the parent symbol.
+    /// Empty for top-level symbols.
+    #[serde(default)]
+    pub parent: Vec<usize>,
+    /// The index of the first child of the parent in this page.
+    #[serde(default)]
+    pub start: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolsPageResult {
+    pub symbols: Vec<DocumentSymbol>,
+    /// The total number of children of the parent.
+    pub total: usize,
+    /// Whether any symbol is dropped in this page.
+    pub truncated: bool,
+}
+
+/// Memory usage and cache statistics, for diagnosing memory growth.
+pub enum MemoryUsage {}
+
+impl Request for MemoryUsage {
+    type Params = ();
+    type Result = MemoryUsageResult;
+    const METHOD: &'static str = "nil/memoryUsage";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemoryUsageResult {
+    /// The resident set size of the process in bytes. Only available on Linux.
+    pub resident_bytes: Option<u64>,
+    /// The number of files in the VFS, including closed ones.
+    pub vfs_files: usize,
+    /// The total length of file contents in the VFS, in bytes.
+    pub vfs_bytes: usize,
+    /// Entry counts of analysis queries, most entries first.
+    pub queries: Vec<QueryStats>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryStats {
+    pub name: String,
+    pub entries: usize,
+    /// The number of entries with cached values.
+    pub memoized: usize,
+}
+
+/// Durations of recent requests and analysis passes, for diagnosing slowness.
+pub enum Profile {}
+
+impl Request for Profile {
+    type Params = ProfileParams;
+    type Result = Vec<ProfileEntry>;
+    const METHOD: &'static str = "nil/profile";
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileParams {
+    /// Only return entries of this document, if set.
+    #[serde(default)]
+    pub text_document: Option<TextDocumentIdentifier>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileEntry {
+    /// The request method, or the name of the analysis, eg. `diagnostics`.
+    pub name: String,
+    /// The document it is about, if any.
+    pub uri: Option<Url>,
+    /// Milliseconds since it finished.
+    pub ago_ms: u64,
+    pub duration_ms: f64,
+    /// Durations of passes inside, in the order of computation.
+    pub passes: Vec<ProfilePass>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfilePass {
+    pub name: String,
+    pub duration_ms: f64,
+}
+
+/// Evaluate the selected expression in a `nix repl` process managed by the server.
+pub enum ReplEval {}
+
+impl Request for ReplEval {
+    type Params = ReplEvalParams;
+    type Result = ReplEvalResult;
+    const METHOD: &'static str = "nil/replEval";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplEvalParams {
+    pub text_document: TextDocumentIdentifier,
+    /// The selection, or the cursor to evaluate the innermost expression at.
+    pub range: Range,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplEvalResult {
+    /// The evaluated expression, including bindings of surrounding scopes.
+    pub expression: String,
+    /// The printed value. Empty if the evaluation failed.
+    pub value: String,
+    /// Errors, warnings and traces printed during the evaluation.
+    pub messages: String,
+}
+
+/// The syntax tree of a document as indented text, for debugging.
+pub enum SyntaxTree {}
+
+impl Request for SyntaxTree {
+    type Params = SyntaxTreeParams;
+    type Result = String;
+    const METHOD: &'static str = "nil/syntaxTree";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyntaxTreeParams {
+    pub text_document: TextDocumentIdentifier,
+    /// Only dump the smallest syntax element covering the range, if set.
+    #[serde(default)]
+    pub range: Option<Range>,
+}
+
+/// Where a file inside a flake input comes from, according to `flake.lock`.
+pub enum FlakeInputSource {}
+
+impl Request for FlakeInputSource {
+    type Params = TextDocumentIdentifier;
+    type Result = Option<FlakeInputSourceResult>;
+    const METHOD: &'static str = "nil/flakeInputSource";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FlakeInputSourceResult {
+    /// The input name in `flake.nix`.
+    pub input: String,
+    /// The path of the document relative to the root of the input.
+    pub path: String,
+    /// The flake reference without the revision, eg. `github:NixOS/nixpkgs`.
+    pub url: Option<String>,
+    pub rev: Option<String>,
+    /// The followed branch or tag.
+    #[serde(rename = "ref")]
+    pub ref_: Option<String>,
+    /// The commit time in Unix timestamp.
+    pub last_modified: Option<u64>,
+}
+
+/// The health and background work of the server, sent if the client opts in by the experimental
+/// client capability `serverStatusNotification`. It is compatible with rust-analyzer's.
+pub enum ServerStatusNotification {}
+
+impl Notification for ServerStatusNotification {
+    type Params = ServerStatusParams;
+    const METHOD: &'static str = "experimental/serverStatus";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerStatusParams {
+    pub health: Health,
+    /// Whether there is no pending background work, like indexing or loading flakes.
+    pub quiescent: bool,
+    /// Errors of background work, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Health {
+    Ok,
+    Warning,
+    Error,
+}
+
+// The following are LSP 3.17 pull diagnostics, which are not supported by `lsp_types` yet.
+// Ref: https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#textDocument_pullDiagnostics
+
+/// `initialize` with additional capabilities. Params are parsed into
+/// `lsp_types::InitializeParams` and `ClientCapabilitiesExt` separately.
+pub enum Initialize {}
+
+impl Request for Initialize {
+    type Params = serde_json::Value;
+    type Result = InitializeResult;
+    const METHOD: &'static str = lsp_types::request::Initialize::METHOD;
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientCapabilitiesExt {
+    #[serde(default)]
+    pub text_document: Option<TextDocumentClientCapabilitiesExt>,
+    #[serde(default)]
+    pub workspace: Option<WorkspaceClientCapabilitiesExt>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentClientCapabilitiesExt {
+    /// Present if the client supports pulling diagnostics.
+    #[serde(default)]
+    pub diagnostic: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceClientCapabilitiesExt {
+    #[serde(default)]
+    pub diagnostics: Option<DiagnosticWorkspaceClientCapabilities>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticWorkspaceClientCapabilities {
+    #[serde(default)]
+    pub refresh_support: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializeResult {
+    pub capabilities: ServerCapabilities,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_info: Option<lsp_types::ServerInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerCapabilities {
+    #[serde(flatten)]
+    pub base: lsp_types::ServerCapabilities,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diagnostic_provider: Option<DiagnosticOptions>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+    /// Whether diagnostics of a document may change when other documents change.
+    pub inter_file_dependencies: bool,
+    pub workspace_diagnostics: bool,
+}
+
+/// `textDocument/codeAction` with edits in the snippet format, if the client sets the experimental
+/// capability `snippetTextEdit`, the same as rust-analyzer.
+/// See: https://github.com/rust-lang/rust-analyzer/blob/master/docs/dev/lsp-extensions.md#snippet-textedit
+pub enum CodeActionRequest {}
+
+impl Request for CodeActionRequest {
+    type Params = lsp_types::CodeActionParams;
+    type Result = Option<Vec<CodeAction>>;
+    const METHOD: &'static str = lsp_types::request::CodeActionRequest::METHOD;
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeAction {
+    #[serde(flatten)]
+    pub base: lsp_types::CodeAction,
+    /// Edits with snippets, set instead of `base.edit`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edit: Option<SnippetWorkspaceEdit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnippetWorkspaceEdit {
+    pub document_changes: Vec<SnippetTextDocumentEdit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnippetTextDocumentEdit {
+    pub text_document: lsp_types::OptionalVersionedTextDocumentIdentifier,
+    pub edits: Vec<SnippetTextEdit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnippetTextEdit {
+    pub range: Range,
+    pub new_text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub insert_text_format: Option<lsp_types::InsertTextFormat>,
+}
+
+pub enum DocumentDiagnosticRequest {}
+
+impl Request for DocumentDiagnosticRequest {
+    type Params = DocumentDiagnosticParams;
+    type Result = DocumentDiagnosticReport;
+    const METHOD: &'static str = "textDocument/diagnostic";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentDiagnosticParams {
+    pub text_document: TextDocumentIdentifier,
+    #[serde(default)]
+    pub identifier: Option<String>,
+    /// The result ID of the last report of the document, if any.
+    #[serde(default)]
+    pub previous_result_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum DocumentDiagnosticReport {
+    #[serde(rename_all = "camelCase")]
+    Full {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        result_id: Option<String>,
+        items: Vec<lsp_types::Diagnostic>,
+    },
+    /// Diagnostics are the same as the last report with `result_id`.
+    #[serde(rename_all = "camelCase")]
+    Unchanged { result_id: String },
+}
+
+/// Ask the client to pull diagnostics of all documents again.
+pub enum WorkspaceDiagnosticRefresh {}
+
+impl Request for WorkspaceDiagnosticRefresh {
+    type Params = ();
+    type Result = ();
+    const METHOD: &'static str = "workspace/diagnostic/refresh";
+}
+
+pub enum WorkspaceDiagnosticRequest {}
+
+impl Request for WorkspaceDiagnosticRequest {
+    type Params = WorkspaceDiagnosticParams;
+    type Result = WorkspaceDiagnosticReport;
+    const METHOD: &'static str = "workspace/diagnostic";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceDiagnosticParams {
+    #[serde(default)]
+    pub identifier: Option<String>,
+    /// The result IDs of the last reports of documents known by the client.
+    pub previous_result_ids: Vec<PreviousResultId>,
+    #[serde(flatten)]
+    pub partial_result_params: lsp_types::PartialResultParams,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PreviousResultId {
+    pub uri: lsp_types::Url,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WorkspaceDiagnosticReport {
+    pub items: Vec<WorkspaceDocumentDiagnosticReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WorkspaceDocumentDiagnosticReport {
+    pub uri: lsp_types::Url,
+    /// The version of the document, or `None` if it is not opened.
+    pub version: Option<i32>,
+    #[serde(flatten)]
+    pub report: DocumentDiagnosticReport,
+}
+
+/// `$/progress` with a partial result of `workspace/diagnostic`.
+pub enum WorkspaceDiagnosticProgress {}
+
+impl Notification for WorkspaceDiagnosticProgress {
+    type Params = WorkspaceDiagnosticProgressParams;
+    const METHOD: &'static str = lsp_types::notification::Progress::METHOD;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WorkspaceDiagnosticProgressParams {
+    pub token: lsp_types::ProgressToken,
+    pub value: WorkspaceDiagnosticReport,
+}
@@ -1,19 +1,32 @@
 use anyhow::{Context, Result};
 use argh::FromArgs;
 use codespan_reporting::term::termcolor::WriteColor;
-use ide::{AnalysisHost, Severity};
+use ide::{
+    AnalysisHost, Change, DiagnosticKind, FileId, FileRange, FileSet, Link, LinkTarget, Severity,
+    SourceRoot, VfsPath,
+};
+use nix_interop::search_path::SearchPath;
+use nix_interop::DEFAULT_IMPORT_FILE;
+use std::collections::HashMap;
 use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::{env, fs, io, process};
 use text_size::TextRange;
 use tracing_subscriber::fmt::writer::BoxMakeWriter;
-use tracing_subscriber::EnvFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
 
 const LOG_FILTER_ENV: &str = "NIL_LOG";
 const LOG_PATH_ENV: &str = "NIL_LOG_PATH";
 const BACKTRACE_ENV: &str = "RUST_BACKTRACE";
 
+/// The exit code of `nil diagnostics` when there are diagnostics reaching `--fail-on`.
+const EXIT_FINDINGS: i32 = 1;
+/// The exit code of invalid arguments, or failures of the tool itself like unreadable files.
+const EXIT_TOOL_ERROR: i32 = 2;
+
 #[derive(Debug, FromArgs)]
 /// LSP server for Nix Expression Language.
 /// Run without arguments to start the language server on stdin/stdout.
@@ -25,6 +38,23 @@ struct Args {
     /// warnings when either stdin or stdout is tty.
     #[argh(switch)]
     stdio: bool,
+    /// listen on an address and serve the first client connecting to it, instead of stdio.
+    /// It can be a TCP address `host:port`, a unix socket `unix:<path>`, or a named pipe
+    /// `\\.\pipe\<name>` on Windows.
+    #[argh(option)]
+    listen: Option<nil::ListenAddr>,
+    /// record all messages from the client and files read by the server into a session file,
+    /// which can be replayed by `nil replay` for bug reproduction.
+    #[argh(option)]
+    record: Option<PathBuf>,
+    /// write logs into a file instead of stderr, overriding `NIL_LOG_PATH`. Levels and
+    /// per-module filters are set by `NIL_LOG`, eg. `nil=debug,ide=info`.
+    #[argh(option)]
+    log_file: Option<PathBuf>,
+    /// write spans of requests and their phases into a file in the Chrome trace event format,
+    /// which can be viewed in `chrome://tracing` or Perfetto, for investigating latency.
+    #[argh(option)]
+    trace: Option<PathBuf>,
     #[argh(subcommand)]
     subcommand: Option<Subcommand>,
 }
@@ -33,35 +63,122 @@ struct Args {
 #[argh(subcommand)]
 enum Subcommand {
     Diagnostics(DiagnosticsArgs),
+    Doctor(DoctorArgs),
+    Fmt(FmtArgs),
     Parse(ParseArgs),
+    Replay(ReplayArgs),
     Ssr(SsrArgs),
 }
 
 #[derive(Debug, FromArgs)]
 #[argh(subcommand, name = "diagnostics")]
-/// Check and print diagnostics for a file.
-/// Exit with non-zero code if there are any diagnostics. (`1` for errors, `2` if only warnings)
-/// WARNING: The output format is for human and should not be relied on.
+/// Check and print diagnostics for files.
+/// Exit with code 1 if there are any diagnostics reaching `--fail-on`, or 2 on failures of the
+/// tool itself.
+/// WARNING: The text output format is for human and should not be relied on.
 struct DiagnosticsArgs {
-    /// nix file to check, or read from stdin for `-`.
+    /// nix files to check, or directories to check all nix files under them,
+    /// or read from stdin for `-`.
     /// NB. You need `--` before `-` for paths starting with `-`,
     /// to disambiguous it from flags.
     #[argh(positional)]
-    path: PathBuf,
+    paths: Vec<PathBuf>,
+    /// also report and fail on constructs blocking static analysis, like imports which cannot
+    /// be resolved and dynamic attributes.
+    #[argh(switch)]
+    strict: bool,
+    /// output format, one of `text` (default), `json`, `sarif` for code scanning, or `github`
+    /// for annotations of GitHub Actions.
+    #[argh(option, default = "DiagnosticsFormat::Text")]
+    format: DiagnosticsFormat,
+    /// the minimal level of diagnostics to fail on, one of `error` (default), `warning`, or
+    /// `hint` for any diagnostic.
+    #[argh(option, default = "DiagnosticLevel::Error")]
+    fail_on: DiagnosticLevel,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DiagnosticsFormat {
+    Text,
+    Json,
+    Sarif,
+    Github,
+}
+
+impl std::str::FromStr for DiagnosticsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "sarif" => Ok(Self::Sarif),
+            "github" => Ok(Self::Github),
+            _ => Err(format!(
+                "unknown format `{s}`, expecting `text`, `json`, `sarif` or `github`"
+            )),
+        }
+    }
+}
+
+/// Levels of diagnostics for `--fail-on`, from the least severe.
+/// There are currently no diagnostics at the `Hint` level, which only serves as a threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum DiagnosticLevel {
+    Hint,
+    Warning,
+    Error,
+}
+
+impl DiagnosticLevel {
+    fn of(diag: &ide::Diagnostic) -> Self {
+        match diag.severity() {
+            Severity::IncompleteSyntax | Severity::Error => Self::Error,
+            Severity::Warning => Self::Warning,
+        }
+    }
+}
+
+impl std::str::FromStr for DiagnosticLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "warning" => Ok(Self::Warning),
+            "hint" => Ok(Self::Hint),
+            _ => Err(format!(
+                "unknown level `{s}`, expecting `error`, `warning` or `hint`"
+            )),
+        }
+    }
 }
 
 #[derive(Debug, FromArgs)]
 #[argh(subcommand, name = "parse")]
 /// Parse a Nix file, print syntax tree in stdout and parse errors in stderr.
 /// Exit with non-zero code if there are any errors.
-/// WARNING: The output, including the syntax tree layout and error format, are for human and
-/// should not be relied on.
+/// WARNING: The text output, including the syntax tree layout and error format, are for human
+/// and should not be relied on.
 struct ParseArgs {
     /// nix file to check, or read from stdin for `-`.
     /// NB. You need `--` before `-` for paths starting with `-`,
     /// to disambiguous it from flags.
     #[argh(positional)]
     path: PathBuf,
+    /// print the syntax tree and parse errors in JSON to stdout instead.
+    #[argh(switch)]
+    json: bool,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "replay")]
+/// Replay a session recorded by `nil --record`, and print all messages from the server in stdout.
+/// WARNING: The session format and the output are for debugging and should not be relied on.
+struct ReplayArgs {
+    /// session file to replay.
+    #[argh(positional)]
+    path: PathBuf,
 }
 
 fn main() {
@@ -69,7 +186,27 @@ fn main() {
         env::set_var(BACKTRACE_ENV, "short");
     }
 
-    let args = argh::from_env::<Args>();
+    // Same as `argh::from_env`, but exit with `EXIT_TOOL_ERROR` on invalid arguments, so that
+    // they are distinguishable from findings of `nil diagnostics`.
+    let argv = env::args().collect::<Vec<_>>();
+    let cmd = argv.first().map_or("nil", |arg0| {
+        Path::new(arg0)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(arg0)
+    });
+    let rest = argv.iter().skip(1).map(|s| &**s).collect::<Vec<_>>();
+    let args = match Args::from_args(&[cmd], &rest) {
+        Ok(args) => args,
+        Err(argh::EarlyExit { output, status }) => {
+            if status.is_ok() {
+                println!("{output}");
+                process::exit(0);
+            }
+            eprintln!("{output}\nRun `{cmd} --help` for more information.");
+            process::exit(EXIT_TOOL_ERROR);
+        }
+    };
     if args.version {
         let release = option_env!("CFG_RELEASE").unwrap_or("unknown");
         println!("nil {release}");
@@ -79,14 +216,20 @@ fn main() {
     if let Some(subcommand) = args.subcommand {
         return match subcommand {
             Subcommand::Diagnostics(args) => main_diagnostics(args),
+            Subcommand::Doctor(args) => main_doctor(args),
+            Subcommand::Fmt(args) => main_fmt(args),
             Subcommand::Parse(args) => main_parse(args),
+            Subcommand::Replay(args) => main_replay(args),
             Subcommand::Ssr(args) => main_ssr(args),
         };
     }
 
-    setup_logger();
+    setup_logger(args.trace.as_deref(), args.log_file.as_deref(), true);
 
-    if !args.stdio && (io::stdin().is_terminal() || io::stdout().is_terminal()) {
+    if !args.stdio
+        && args.listen.is_none()
+        && (io::stdin().is_terminal() || io::stdout().is_terminal())
+    {
         // TODO: Make this a hard error.
         eprintln!(
             "\
@@ -100,7 +243,12 @@ fn main() {
         .enable_all()
         .build()
         .expect("Failed to spawn tokio runtime")
-        .block_on(nil::run_server_stdio());
+        .block_on(async {
+            match &args.listen {
+                Some(addr) => nil::run_server_listen(addr, args.record.as_deref()).await,
+                None => nil::run_server_stdio(args.record.as_deref()).await,
+            }
+        });
     match ret {
         Ok(()) => {}
         Err(err) => {
@@ -113,40 +261,189 @@ fn main() {
 fn main_diagnostics(args: DiagnosticsArgs) {
     use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
 
-    let ret = (|| -> Result<Option<Severity>> {
-        let path = &*args.path;
-
-        let src = if path.as_os_str() == "-" {
-            io::read_to_string(io::stdin().lock()).context("Failed to read from stdin")?
-        } else {
-            fs::read_to_string(path).context("Failed to read file")?
+    let ret = (|| -> Result<bool> {
+        let stdin_path = Path::new("-");
+        let (analysis, files, referenced) = match &*args.paths {
+            [] => anyhow::bail!("No path is given"),
+            [path] if path == stdin_path => {
+                let src =
+                    io::read_to_string(io::stdin().lock()).context("Failed to read from stdin")?;
+                let (analysis, file) = AnalysisHost::new_single_file(&src);
+                (analysis, vec![(file, path.clone(), src)], Vec::new())
+            }
+            paths => {
+                if paths.iter().any(|path| path == stdin_path) {
+                    anyhow::bail!("`-` cannot be used together with other paths");
+                }
+                let mut files = Vec::new();
+                for path in paths {
+                    if path.is_dir() {
+                        files.extend(nil::collect_nix_files(path, usize::MAX));
+                    } else {
+                        files.push(path.clone());
+                    }
+                }
+                load_files(&files, args.strict)?
+            }
         };
 
-        let (analysis, file) = AnalysisHost::new_single_file(&src);
-        let diags = analysis
-            .snapshot()
-            .diagnostics(file)
-            .expect("No cancellation");
-
+        let is_analysis_gap = |diag: &ide::Diagnostic| {
+            matches!(
+                diag.kind,
+                DiagnosticKind::UnresolvedImport | DiagnosticKind::DynamicAttr
+            )
+        };
+        // Analysis gaps are failures in strict mode.
+        let level_of = |diag: &ide::Diagnostic| {
+            if is_analysis_gap(diag) {
+                DiagnosticLevel::Error
+            } else {
+                DiagnosticLevel::of(diag)
+            }
+        };
+        let sources = files
+            .iter()
+            .chain(&referenced)
+            .map(|(file, path, src)| (*file, (&**path, &**src)))
+            .collect::<Sources>();
+        let snap = analysis.snapshot();
         let mut writer = StandardStream::stdout(ColorChoice::Auto);
-        emit_diagnostics(path, &src, &mut writer, &mut diags.iter().cloned())?;
+        let mut json_diags = Vec::new();
+        let mut failed = false;
+        for (file, path, src) in &files {
+            let mut diags = snap.diagnostics(*file).expect("No cancellation");
+            diags.extend(
+                snap.missing_paths(*file, |path| path.as_path().map_or(true, Path::exists))
+                    .expect("No cancellation"),
+            );
+            diags.retain(|diag| !diag.is_opt_in() || (args.strict && is_analysis_gap(diag)));
+
+            match args.format {
+                DiagnosticsFormat::Text => {
+                    emit_diagnostics(path, src, &sources, &mut writer, &mut diags.iter().cloned())?;
+                }
+                DiagnosticsFormat::Json => {
+                    json_diags.extend(
+                        diags
+                            .iter()
+                            .map(|diag| diagnostic_to_json(path, src, &sources, diag)),
+                    );
+                }
+                DiagnosticsFormat::Sarif => {
+                    json_diags.extend(diags.iter().map(|diag| {
+                        diagnostic_to_sarif(path, src, &sources, diag, level_of(diag))
+                    }));
+                }
+                DiagnosticsFormat::Github => {
+                    for diag in &diags {
+                        let line = diagnostic_to_github(path, src, &sources, diag, level_of(diag));
+                        println!("{line}");
+                    }
+                }
+            }
 
-        Ok(diags.iter().map(|diag| diag.severity()).max())
+            failed |= diags.iter().any(|diag| level_of(diag) >= args.fail_on);
+        }
+
+        match args.format {
+            DiagnosticsFormat::Json => println!("{}", serde_json::Value::Array(json_diags)),
+            DiagnosticsFormat::Sarif => println!("{}", sarif_log(json_diags)),
+            DiagnosticsFormat::Text | DiagnosticsFormat::Github => {}
+        }
+        Ok(failed)
     })();
     match ret {
-        Ok(None) => process::exit(0),
-        Ok(Some(max_severity)) => {
-            if max_severity > Severity::Warning {
-                process::exit(1)
-            } else {
-                process::exit(0)
-            }
-        }
+        Ok(false) => process::exit(0),
+        Ok(true) => process::exit(EXIT_FINDINGS),
         Err(err) => {
             eprintln!("{err:#}");
-            process::exit(1);
+            process::exit(EXIT_TOOL_ERROR);
+        }
+    }
+}
+
+/// The id, the path as given, and the content of a file to check.
+type CheckedFile = (FileId, PathBuf, String);
+
+/// Paths and contents of loaded files, which notes of diagnostics may refer to.
+type Sources<'a> = HashMap<FileId, (&'a Path, &'a str)>;
+
+/// Load files at their real paths in a single source root, so that they can refer to each other.
+/// With `with_references`, files they refer to by relative paths are also loaded, so that their
+/// imports can be resolved. Returns ids, paths and contents of files in `paths`, followed by
+/// ones of referenced files.
+fn load_files(
+    paths: &[PathBuf],
+    with_references: bool,
+) -> Result<(AnalysisHost, Vec<CheckedFile>, Vec<CheckedFile>)> {
+    let mut file_set = FileSet::default();
+    let mut change = Change::default();
+    let mut files = Vec::new();
+    for path in paths {
+        let src = fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
+        let vpath = VfsPath::new(
+            path.canonicalize()
+                .with_context(|| format!("Failed to resolve the path {path:?}"))?,
+        );
+        if file_set.file_for_path(&vpath).is_some() {
+            continue;
+        }
+        let file = FileId(file_set.iter().len() as u32);
+        file_set.insert(file, vpath);
+        change.change_file(file, src.clone().into());
+        files.push((file, path.clone(), src));
+    }
+    let entry = files.first().map(|(file, ..)| *file);
+    change.set_roots(vec![SourceRoot::new_local(file_set.clone(), entry)]);
+    let mut search_path = SearchPath::from_env();
+    search_path.check_existing();
+    change.set_search_path(search_path);
+    let mut host = AnalysisHost::new();
+    host.apply_change(change);
+    if !with_references {
+        return Ok((host, files, Vec::new()));
+    }
+
+    let analysis = host.snapshot();
+    let mut change = Change::default();
+    let mut referenced = Vec::new();
+    for &(file, ..) in &files {
+        for link in analysis.links(file).expect("No cancellation") {
+            let Link::Lazy { range } = link else {
+                continue;
+            };
+            let Some(Link::Resolved {
+                target: LinkTarget::VfsPath(mut vpath),
+                ..
+            }) = analysis
+                .link_resolve(FileRange::new(file, range))
+                .expect("No cancellation")
+            else {
+                continue;
+            };
+            let Some(mut target) = vpath.as_path().map(Path::to_path_buf) else {
+                continue;
+            };
+            // Directories are imported via their `default.nix`.
+            if target.is_dir() && vpath.push(DEFAULT_IMPORT_FILE).is_some() {
+                target.push(DEFAULT_IMPORT_FILE);
+            }
+            if file_set.file_for_path(&vpath).is_some() {
+                continue;
+            }
+            let Ok(content) = fs::read_to_string(&target) else {
+                continue;
+            };
+            let id = FileId(file_set.iter().len() as u32);
+            file_set.insert(id, vpath);
+            change.change_file(id, content.clone().into());
+            referenced.push((id, target, content));
         }
     }
+    drop(analysis);
+    change.set_roots(vec![SourceRoot::new_local(file_set, entry)]);
+    host.apply_change(change);
+    Ok((host, files, referenced))
 }
 
 fn main_parse(args: ParseArgs) {
@@ -163,10 +460,30 @@ fn main_parse(args: ParseArgs) {
 
         let parse = syntax::parse_file(&src);
 
+        if args.json {
+            let errors = parse
+                .errors()
+                .iter()
+                .map(|err| {
+                    serde_json::json!({
+                        "range": range_to_json(err.range),
+                        "message": err.kind.to_string(),
+                    })
+                })
+                .collect::<Vec<_>>();
+            let output = serde_json::json!({
+                "root": syntax_to_json(parse.syntax_node().into()),
+                "errors": errors,
+            });
+            println!("{output}");
+            return Ok(parse.errors().is_empty());
+        }
+
         let mut writer = StandardStream::stderr(ColorChoice::Auto);
         emit_diagnostics(
             path,
             &src,
+            &Sources::new(),
             &mut writer,
             &mut parse.errors().iter().map(|&err| err.into()),
         )?;
@@ -185,6 +502,97 @@ fn main_parse(args: ParseArgs) {
     }
 }
 
+/// Convert a syntax node or token into JSON with its kind and byte range.
+/// Nodes have their children, and tokens have their text.
+fn syntax_to_json(elem: syntax::SyntaxElement) -> serde_json::Value {
+    let kind = format!("{:?}", elem.kind());
+    let range = range_to_json(elem.text_range());
+    match elem {
+        syntax::NodeOrToken::Node(node) => serde_json::json!({
+            "kind": kind,
+            "range": range,
+            "children": node.children_with_tokens().map(syntax_to_json).collect::<Vec<_>>(),
+        }),
+        syntax::NodeOrToken::Token(tok) => serde_json::json!({
+            "kind": kind,
+            "range": range,
+            "text": tok.text(),
+        }),
+    }
+}
+
+/// A byte range as a `[start, end]` pair.
+fn range_to_json(range: TextRange) -> serde_json::Value {
+    serde_json::json!([u32::from(range.start()), u32::from(range.end())])
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "doctor")]
+/// Check the environment and configuration, and print suggestions for problems.
+/// Exit with non-zero code if there are any errors.
+struct DoctorArgs {
+    /// JSON file of settings to check, in the same format as the LSP settings under `nil`.
+    #[argh(option)]
+    config: Option<PathBuf>,
+}
+
+fn main_doctor(args: DoctorArgs) {
+    match nil::doctor(args.config.as_deref()) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("{err:#}");
+            process::exit(1);
+        }
+    }
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "fmt")]
+/// Format files in place with the formatter of the language server, `formatting.command`.
+/// Exit with non-zero code if any file fails to be formatted.
+struct FmtArgs {
+    /// nix files to format, or directories to format all nix files under them.
+    #[argh(positional)]
+    paths: Vec<PathBuf>,
+    /// do not modify files, but print diffs of unformatted files, and exit with non-zero code
+    /// if there are any.
+    #[argh(switch)]
+    check: bool,
+    /// JSON file of settings containing `formatting.command`, in the same format as the LSP
+    /// settings under `nil`.
+    #[argh(option)]
+    config: Option<PathBuf>,
+}
+
+fn main_fmt(args: FmtArgs) {
+    match nil::format_files(&args.paths, args.check, args.config.as_deref()) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("{err:#}");
+            process::exit(1);
+        }
+    }
+}
+
+fn main_replay(args: ReplayArgs) {
+    setup_logger(None, None, false);
+
+    let ret = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to spawn tokio runtime")
+        .block_on(nil::replay(&args.path));
+    match ret {
+        Ok(()) => {}
+        Err(err) => {
+            eprintln!("{err:#}");
+            process::exit(1);
+        }
+    }
+}
+
 #[derive(Debug, FromArgs)]
 #[argh(subcommand, name = "ssr")]
 /// Search structural patterns and optionally replace them.
@@ -196,6 +604,7 @@ struct SsrArgs {
     #[argh(positional)]
     path: PathBuf,
     /// expression pattern to search. Placeholders `$name` can be used to capture sub-expressions.
+    /// It can also be a rule `pattern ==>> template` to replace without the third argument.
     #[argh(positional)]
     pattern: String,
     /// expression template to replace. Placeholders `$name` can be used to substitute
@@ -215,9 +624,16 @@ fn main_ssr(args: SsrArgs) {
         };
 
         let parse = syntax::parse_file(&src);
-        let pat = ssr::Pattern::parse(&args.pattern).context("invalid SSR pattern")?;
+        let (pattern, template) = match &args.template {
+            Some(templ) => (&*args.pattern, Some(&**templ)),
+            None => match args.pattern.split_once(ssr::RULE_SEPARATOR) {
+                Some((pat, templ)) => (pat.trim(), Some(templ.trim())),
+                None => (&*args.pattern, None),
+            },
+        };
+        let pat = ssr::Pattern::parse(pattern).context("invalid SSR pattern")?;
 
-        match args.template {
+        match template {
             None => {
                 for n in pat.find_iter(&parse.syntax_node()) {
                     let range = n.text_range();
@@ -231,7 +647,7 @@ fn main_ssr(args: SsrArgs) {
                 }
             }
             Some(templ) => {
-                let templ = ssr::Template::parse(&templ, &pat).context("invalid SSR template")?;
+                let templ = ssr::Template::parse(templ, &pat).context("invalid SSR template")?;
                 let ret = pat.replace(&src, &templ, &parse.syntax_node());
                 print!("{ret}");
             }
@@ -252,6 +668,7 @@ fn main_ssr(args: SsrArgs) {
 fn emit_diagnostics(
     path: &Path,
     src: &str,
+    sources: &Sources,
     writer: &mut dyn WriteColor,
     diags: &mut dyn Iterator<Item = ide::Diagnostic>,
 ) -> Result<()> {
@@ -264,19 +681,31 @@ fn emit_diagnostics(
 
     let mut files = SimpleFiles::new();
     let cr_file = files.add(path.display().to_string(), src);
+    // Notes may be in other files, which are added on demand.
+    let mut note_files = HashMap::new();
 
     for diag in diags {
         let severity = match diag.severity() {
             ide::Severity::IncompleteSyntax | ide::Severity::Error => Severity::Error,
             ide::Severity::Warning => Severity::Warning,
         };
-        let labels = std::iter::once(Label::primary(cr_file, to_range(diag.range)))
-            .chain(diag.notes.iter().map(|(frange, note)| {
-                Label::secondary(cr_file, to_range(frange.range)).with_message(note)
-            }))
-            .collect();
+        let mut labels = vec![Label::primary(cr_file, to_range(diag.range))];
+        for (frange, note) in &diag.notes {
+            let Some(&(note_path, note_src)) = sources.get(&frange.file_id) else {
+                continue;
+            };
+            // Sources are borrowed from the same strings.
+            let cr_note_file = if std::ptr::eq(note_src, src) {
+                cr_file
+            } else {
+                *note_files
+                    .entry(frange.file_id)
+                    .or_insert_with(|| files.add(note_path.display().to_string(), note_src))
+            };
+            labels.push(Label::secondary(cr_note_file, to_range(frange.range)).with_message(note));
+        }
         let diag = Diagnostic::new(severity)
-            .with_code(diag.code())
+            .with_code(format!("{} {}", diag.id(), diag.code()))
             .with_message(diag.message())
             .with_labels(labels);
 
@@ -286,9 +715,190 @@ fn emit_diagnostics(
     Ok(())
 }
 
-fn setup_logger() {
-    let file = env::var_os(LOG_PATH_ENV).and_then(|path| {
-        let path = PathBuf::from(path);
+/// The 1-based line and column in characters of `offset` in `src`, or `None` if it is out of
+/// bounds or not on a character boundary.
+fn line_col(src: &str, offset: text_size::TextSize) -> Option<(usize, usize)> {
+    use codespan_reporting::files::{Files, SimpleFile};
+
+    let loc = SimpleFile::new("", src)
+        .location((), usize::from(offset))
+        .ok()?;
+    Some((loc.line_number, loc.column_number))
+}
+
+/// The 1-based start and end lines and columns of `range` in `src`.
+fn range_line_col(src: &str, range: TextRange) -> Option<((usize, usize), (usize, usize))> {
+    Some((line_col(src, range.start())?, line_col(src, range.end())?))
+}
+
+/// Convert a diagnostic into JSON, with 1-based lines and columns in characters.
+/// Notes in files not in `sources`, or with invalid ranges, are omitted.
+fn diagnostic_to_json(
+    path: &Path,
+    src: &str,
+    sources: &Sources,
+    diag: &ide::Diagnostic,
+) -> serde_json::Value {
+    let to_json = |src: &str, range: TextRange| {
+        let ((line, column), (end_line, end_column)) = range_line_col(src, range)?;
+        Some(serde_json::json!({
+            "start": { "line": line, "column": column },
+            "end": { "line": end_line, "column": end_column },
+        }))
+    };
+    let severity = match diag.severity() {
+        Severity::IncompleteSyntax | Severity::Error => "error",
+        Severity::Warning => "warning",
+    };
+    serde_json::json!({
+        "path": path.display().to_string(),
+        "range": to_json(src, diag.range),
+        "severity": severity,
+        "id": diag.id(),
+        "code": diag.code(),
+        "message": diag.message(),
+        "notes": diag.notes.iter().filter_map(|(frange, note)| {
+            let &(note_path, note_src) = sources.get(&frange.file_id)?;
+            Some(serde_json::json!({
+                "path": note_path.display().to_string(),
+                "range": to_json(note_src, frange.range)?,
+                "message": note,
+            }))
+        }).collect::<Vec<_>>(),
+    })
+}
+
+/// Convert a diagnostic into a SARIF result object.
+/// Notes in files not in `sources`, or with invalid ranges, are omitted.
+fn diagnostic_to_sarif(
+    path: &Path,
+    src: &str,
+    sources: &Sources,
+    diag: &ide::Diagnostic,
+    level: DiagnosticLevel,
+) -> serde_json::Value {
+    let location = |path: &Path, src: &str, range: TextRange, message: Option<&str>| {
+        let ((start_line, start_column), (end_line, end_column)) = range_line_col(src, range)?;
+        let mut loc = serde_json::json!({
+            "physicalLocation": {
+                "artifactLocation": { "uri": sarif_uri(path) },
+                "region": {
+                    "startLine": start_line,
+                    "startColumn": start_column,
+                    "endLine": end_line,
+                    "endColumn": end_column,
+                },
+            },
+        });
+        if let Some(message) = message {
+            loc["message"] = serde_json::json!({ "text": message });
+        }
+        Some(loc)
+    };
+    let level = match level {
+        DiagnosticLevel::Error => "error",
+        DiagnosticLevel::Warning => "warning",
+        DiagnosticLevel::Hint => "note",
+    };
+    serde_json::json!({
+        "ruleId": diag.id(),
+        "level": level,
+        "message": { "text": diag.message() },
+        "locations": location(path, src, diag.range, None).into_iter().collect::<Vec<_>>(),
+        "relatedLocations": diag.notes.iter().filter_map(|(frange, note)| {
+            let &(note_path, note_src) = sources.get(&frange.file_id)?;
+            location(note_path, note_src, frange.range, Some(note))
+        }).collect::<Vec<_>>(),
+        "helpUri": diag.doc_url(),
+        "properties": { "code": diag.code() },
+    })
+}
+
+/// Relative paths with `/` separators, as SARIF artifact URIs.
+fn sarif_uri(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+        .replace("//", "/")
+}
+
+/// Wrap SARIF results into a log of a single run.
+fn sarif_log(results: Vec<serde_json::Value>) -> serde_json::Value {
+    serde_json::json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "nil",
+                    "version": option_env!("CFG_RELEASE").unwrap_or("unknown"),
+                    "informationUri": "https://github.com/oxalica/nil",
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+/// Convert a diagnostic into a GitHub Actions workflow command, which creates an annotation.
+/// Annotations have a single location, thus notes are appended to the message, prefixed by
+/// their locations if they are in `sources`.
+/// See: <https://docs.github.com/en/actions/using-workflows/workflow-commands-for-github-actions>
+fn diagnostic_to_github(
+    path: &Path,
+    src: &str,
+    sources: &Sources,
+    diag: &ide::Diagnostic,
+    level: DiagnosticLevel,
+) -> String {
+    fn escape_data(s: &str) -> String {
+        s.replace('%', "%25")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A")
+    }
+    fn escape_property(s: &str) -> String {
+        escape_data(s).replace(':', "%3A").replace(',', "%2C")
+    }
+
+    let command = match level {
+        DiagnosticLevel::Error => "error",
+        DiagnosticLevel::Warning => "warning",
+        DiagnosticLevel::Hint => "notice",
+    };
+    let mut message = diag.message();
+    for (frange, note) in &diag.notes {
+        message.push('\n');
+        let note_pos = sources
+            .get(&frange.file_id)
+            .and_then(|&(note_path, note_src)| {
+                let (line, col) = line_col(note_src, frange.range.start())?;
+                Some(format!("{}:{line}:{col}", note_path.display()))
+            });
+        if let Some(note_pos) = note_pos {
+            message.push_str(&note_pos);
+            message.push_str(": ");
+        }
+        message.push_str(note);
+    }
+    let mut props = format!("file={}", escape_property(&path.display().to_string()));
+    if let Some(((line, col), (end_line, end_col))) = range_line_col(src, diag.range) {
+        props += &format!(",line={line},endLine={end_line},col={col},endColumn={end_col}");
+    }
+    format!(
+        "::{command} {props},title={}::{}",
+        escape_property(&format!("{} {}", diag.id(), diag.code())),
+        escape_data(&message),
+    )
+}
+
+/// Write logs filtered by `NIL_LOG` into `log_path`, `NIL_LOG_PATH` or stderr.
+/// With `log_trace`, logs are also sent to the client via `$/logTrace` when enabled by it.
+fn setup_logger(trace_path: Option<&Path>, log_path: Option<&Path>, log_trace: bool) {
+    let log_path = log_path
+        .map(PathBuf::from)
+        .or_else(|| env::var_os(LOG_PATH_ENV).map(PathBuf::from));
+    let file = log_path.and_then(|path| {
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent).ok()?;
         }
@@ -304,8 +914,21 @@ fn setup_logger() {
         None => BoxMakeWriter::new(io::stderr),
     };
 
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_env(LOG_FILTER_ENV))
-        .with_writer(writer)
+    // The filter only applies to logs, since all spans are wanted in traces.
+    let trace = trace_path.and_then(|path| match nil::ChromeTraceLayer::create(path) {
+        Ok(layer) => Some(layer),
+        Err(err) => {
+            eprintln!("Failed to create the trace file: {err}");
+            None
+        }
+    });
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(writer)
+                .with_filter(EnvFilter::from_env(LOG_FILTER_ENV)),
+        )
+        .with(trace)
+        .with(log_trace.then_some(nil::LogTraceLayer))
         .init();
 }
@@ -2,17 +2,24 @@ use std::future::Future;
 use std::io;
 use std::ops::ControlFlow;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::Instant;
 
 use async_lsp::{AnyEvent, AnyNotification, AnyRequest, LspService};
+use lsp_types::request::Request;
+use lsp_types::Url;
 use serde::Serialize;
 use tower::{Layer, Service};
 
+use crate::lsp_ext;
+use crate::profile::Profiler;
+
 const LEVEL: tracing::Level = tracing::Level::DEBUG;
 
 pub struct Meter<S> {
     service: S,
+    profiler: Arc<Profiler>,
 }
 
 impl<S: LspService> Service<AnyRequest> for Meter<S>
@@ -30,16 +37,26 @@ where
     }
 
     fn call(&mut self, req: AnyRequest) -> Self::Future {
-        // Fast path.
-        if !tracing::event_enabled!(LEVEL) {
-            return Box::pin(self.service.call(req));
-        }
-
+        // Requests are always recorded for `nil/profile`, except itself.
+        let profile = (req.method != lsp_ext::Profile::METHOD).then(|| {
+            let uri = req
+                .params
+                .pointer("/textDocument/uri")
+                .and_then(|uri| Url::parse(uri.as_str()?).ok());
+            (req.method.clone(), uri)
+        });
+        let profiler = self.profiler.clone();
         let inst = Instant::now();
         let fut = self.service.call(req);
         Box::pin(async move {
             let ret = fut.await;
             let elapsed = inst.elapsed();
+            if let Some((method, uri)) = profile {
+                profiler.record(method, uri, elapsed, Vec::new());
+            }
+            if !tracing::event_enabled!(LEVEL) {
+                return ret;
+            }
             let mut counter = CounterWriter::default();
             match &ret {
                 Ok(v) => serde_json::to_writer(&mut counter, v),
@@ -89,13 +106,23 @@ impl io::Write for CounterWriter {
     }
 }
 
-#[derive(Default)]
-pub struct MeterLayer;
+pub struct MeterLayer {
+    profiler: Arc<Profiler>,
+}
+
+impl MeterLayer {
+    pub fn new(profiler: Arc<Profiler>) -> Self {
+        Self { profiler }
+    }
+}
 
 impl<S> Layer<S> for MeterLayer {
     type Service = Meter<S>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        Meter { service: inner }
+        Meter {
+            service: inner,
+            profiler: self.profiler.clone(),
+        }
     }
 }
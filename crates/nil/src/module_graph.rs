@@ -0,0 +1,252 @@
+//! The module graph of the workspace, persisted between sessions.
+//!
+//! Analyses themselves live in the incremental database and cannot be persisted. Instead,
+//! path references between files are saved on shutdown with sizes, mtimes and hashes of their
+//! contents. On the next start, files related to an opened one, that is, its referrers and
+//! their references transitively, are loaded from the disk first, so that cross-file analysis
+//! of the configuration it belongs to is ready before the whole workspace is indexed.
+//!
+//! The graph is updated incrementally: on shutdown, only files changed since their summaries
+//! were made are analyzed again, and at most `MAX_SUMMARIZED_FILES` of them, so that shutting
+//! down never waits for the whole workspace.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+/// The maximum number of files related to an opened file, to load early.
+pub(crate) const MAX_RELATED_FILES: usize = 1024;
+
+/// The maximum number of files to analyze for references on shutdown. The rest keep their
+/// summaries of the last session, or are summarized in later sessions.
+pub(crate) const MAX_SUMMARIZED_FILES: usize = 512;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ModuleGraph {
+    files: HashMap<PathBuf, FileSummary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileSummary {
+    len: u64,
+    /// The modification time in nanoseconds since UNIX epoch.
+    mtime: u64,
+    /// The hash of the content, which validates files only touched, eg. by `git checkout`.
+    hash: u64,
+    /// Files referenced by path literals.
+    references: Vec<PathBuf>,
+}
+
+impl FileSummary {
+    /// The summary of the file at `path` on the disk, whose content is `text`.
+    /// Returns `None` if the file is unreadable, or `text` is not saved yet. The content on
+    /// the disk is only compared if `is_opened`, since others are reloaded on changes.
+    pub fn new(path: &Path, text: &str, is_opened: bool, references: Vec<PathBuf>) -> Option<Self> {
+        let meta = fs::metadata(path).ok()?;
+        let summary = Self {
+            len: meta.len(),
+            mtime: mtime_of(&meta)?,
+            hash: content_hash(text),
+            references,
+        };
+        if summary.len != text.len() as u64 {
+            return None;
+        }
+        if is_opened && fs::read_to_string(path).ok()? != text {
+            return None;
+        }
+        Some(summary)
+    }
+
+    /// Whether the file at `path` on the disk is unchanged since summarized.
+    fn is_fresh(&self, path: &Path) -> bool {
+        let Ok(meta) = fs::metadata(path) else {
+            return false;
+        };
+        if meta.len() != self.len {
+            return false;
+        }
+        if mtime_of(&meta) == Some(self.mtime) {
+            return true;
+        }
+        fs::read_to_string(path).map_or(false, |text| content_hash(&text) == self.hash)
+    }
+}
+
+impl ModuleGraph {
+    pub fn insert(&mut self, path: PathBuf, summary: FileSummary) {
+        self.files.insert(path, summary);
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    /// Whether the summary of `path` is still valid for its current content `text`, so that
+    /// its references need not be collected again.
+    pub fn is_up_to_date(&self, path: &Path, text: &str) -> bool {
+        self.files.get(path).map_or(false, |summary| {
+            summary.len == text.len() as u64
+                && summary.hash == content_hash(text)
+                // Targets may be deleted without touching the referrer.
+                && summary.references.iter().all(|target| target.exists())
+        })
+    }
+
+    /// Remove summaries of files changed or deleted on the disk since then.
+    pub fn retain_fresh(&mut self) {
+        self.files.retain(|path, summary| summary.is_fresh(path));
+    }
+
+    /// Files related to `path`, that is, files referring it transitively, and files they refer
+    /// to transitively, in the order of discovery. `path` itself is excluded.
+    pub fn related_files(&self, path: &Path) -> Vec<PathBuf> {
+        let mut referrers = HashMap::<&Path, Vec<&Path>>::new();
+        for (referrer, summary) in &self.files {
+            for target in &summary.references {
+                referrers.entry(target).or_default().push(referrer);
+            }
+        }
+        for list in referrers.values_mut() {
+            list.sort();
+        }
+
+        // Roots of configurations containing `path`, and `path` itself.
+        let mut seen = HashSet::from([path]);
+        let mut ret = vec![path];
+        let mut i = 0;
+        while let Some(&p) = ret.get(i) {
+            i += 1;
+            for &referrer in referrers.get(p).into_iter().flatten() {
+                if seen.insert(referrer) {
+                    ret.push(referrer);
+                }
+            }
+        }
+        // All modules of these configurations.
+        let mut i = 0;
+        while let Some(&p) = ret.get(i) {
+            i += 1;
+            let Some(summary) = self.files.get(p) else {
+                continue;
+            };
+            for target in &summary.references {
+                if seen.insert(target) {
+                    ret.push(target);
+                }
+            }
+        }
+        ret.into_iter()
+            .skip(1)
+            .take(MAX_RELATED_FILES)
+            .map(Path::to_owned)
+            .collect()
+    }
+}
+
+/// The cache key of the workspace of `roots`.
+pub(crate) fn cache_key(roots: &[PathBuf]) -> String {
+    let mut roots = roots.iter().collect::<Vec<_>>();
+    roots.sort();
+    let mut hasher = DefaultHasher::new();
+    roots.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+fn content_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn mtime_of(meta: &fs::Metadata) -> Option<u64> {
+    let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    u64::try_from(mtime.as_nanos()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileSummary, ModuleGraph};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn related_files() {
+        let dir =
+            std::env::temp_dir().join(format!("nil-module-graph-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name);
+        let mut graph = ModuleGraph::default();
+        // host1 -> common -> { a, b }; host2 -> common; host2 -> c; d.
+        for (name, refs) in [
+            ("host1.nix", &["common.nix"][..]),
+            ("host2.nix", &["common.nix", "c.nix"]),
+            ("common.nix", &["a.nix", "b.nix"]),
+            ("a.nix", &[]),
+            ("b.nix", &[]),
+            ("c.nix", &[]),
+            ("d.nix", &[]),
+        ] {
+            fs::write(path(name), name).unwrap();
+            let refs = refs.iter().map(|name| path(name)).collect();
+            let summary = FileSummary::new(&path(name), name, false, refs).unwrap();
+            graph.insert(path(name), summary);
+        }
+        let names = |paths: Vec<PathBuf>| {
+            paths
+                .iter()
+                .map(|p| p.strip_prefix(&dir).unwrap().to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(graph.related_files(&path("a.nix"))),
+            ["common.nix", "host1.nix", "host2.nix", "b.nix", "c.nix"],
+        );
+        assert_eq!(
+            names(graph.related_files(&path("host1.nix"))),
+            ["common.nix", "a.nix", "b.nix"],
+        );
+        assert!(graph.related_files(&path("d.nix")).is_empty());
+        assert!(graph
+            .related_files(Path::new("/non-existing.nix"))
+            .is_empty());
+
+        // Unsaved contents.
+        assert_eq!(
+            FileSummary::new(&path("a.nix"), "unsaved", true, Vec::new()),
+            None
+        );
+        assert_eq!(
+            FileSummary::new(&path("a.nix"), "b.nix", true, Vec::new()),
+            None
+        );
+        assert!(FileSummary::new(&path("a.nix"), "b.nix", false, Vec::new()).is_some());
+
+        // Summaries are reused for unchanged contents.
+        assert!(graph.is_up_to_date(&path("a.nix"), "a.nix"));
+        assert!(!graph.is_up_to_date(&path("a.nix"), "changed"));
+        assert!(!graph.is_up_to_date(&path("e.nix"), "e.nix"));
+        assert!(graph.is_up_to_date(&path("host2.nix"), "host2.nix"));
+        // References to deleted files are collected again.
+        fs::remove_file(path("c.nix")).unwrap();
+        assert!(!graph.is_up_to_date(&path("host2.nix"), "host2.nix"));
+
+        // Changed and deleted files are invalidated.
+        fs::write(path("common.nix"), "changed").unwrap();
+        fs::remove_file(path("host2.nix")).unwrap();
+        graph.retain_fresh();
+        assert!(graph.related_files(&path("a.nix")).is_empty());
+        assert_eq!(
+            names(graph.related_files(&path("host1.nix"))),
+            ["common.nix"]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
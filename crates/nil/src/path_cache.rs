@@ -0,0 +1,110 @@
+//! Existence of paths and entries of directories on the disk,
+//! cached until file watching reports changes.
+use ide::DirEntry;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Default)]
+pub(crate) struct PathCache {
+    exists: Mutex<HashMap<PathBuf, bool>>,
+    dirs: Mutex<HashMap<PathBuf, Arc<[DirEntry]>>>,
+}
+
+impl PathCache {
+    pub fn exists(&self, path: &Path) -> bool {
+        if let Some(&exists) = self.exists.lock().unwrap().get(path) {
+            return exists;
+        }
+        // Don't hold the lock during IO.
+        // Inaccessible paths are assumed to exist, to avoid false warnings.
+        let exists = path.try_exists().unwrap_or(true);
+        self.exists.lock().unwrap().insert(path.to_owned(), exists);
+        exists
+    }
+
+    /// Entries of the directory `path`, or nothing if it cannot be read.
+    pub fn read_dir(&self, path: &Path) -> Arc<[DirEntry]> {
+        if let Some(entries) = self.dirs.lock().unwrap().get(path) {
+            return entries.clone();
+        }
+        let entries = fs::read_dir(path)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                Some(DirEntry {
+                    name: entry.file_name().into_string().ok()?,
+                    // Follows symlinks.
+                    is_dir: entry.path().is_dir(),
+                })
+            })
+            .collect::<Arc<[_]>>();
+        self.dirs
+            .lock()
+            .unwrap()
+            .insert(path.to_owned(), entries.clone());
+        entries
+    }
+
+    /// Forget `path` which is created or deleted, together with its ancestors and descendants.
+    pub fn invalidate(&self, path: &Path) {
+        let is_unaffected =
+            |cached: &PathBuf| !cached.starts_with(path) && !path.starts_with(cached);
+        self.exists
+            .lock()
+            .unwrap()
+            .retain(|cached, _| is_unaffected(cached));
+        self.dirs
+            .lock()
+            .unwrap()
+            .retain(|cached, _| is_unaffected(cached));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PathCache;
+    use ide::DirEntry;
+    use std::fs;
+
+    #[test]
+    fn invalidate() {
+        let dir = std::env::temp_dir().join(format!("nil-path-cache-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a/b.nix");
+
+        let cache = PathCache::default();
+        assert!(cache.exists(&dir));
+        assert!(!cache.exists(&dir.join("a")));
+        assert!(!cache.exists(&file));
+
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, "1").unwrap();
+        // Still cached.
+        assert!(!cache.exists(&file));
+        cache.invalidate(&file);
+        assert!(cache.exists(&dir.join("a")));
+        assert!(cache.exists(&file));
+
+        assert_eq!(
+            &*cache.read_dir(&dir),
+            [DirEntry {
+                name: "a".into(),
+                is_dir: true,
+            }]
+        );
+        fs::write(dir.join("c.nix"), "1").unwrap();
+        // Still cached.
+        assert_eq!(cache.read_dir(&dir).len(), 1);
+        cache.invalidate(&dir.join("c.nix"));
+        assert_eq!(cache.read_dir(&dir).len(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+        cache.invalidate(&dir);
+        assert!(!cache.exists(&file));
+        assert!(cache.read_dir(&dir).is_empty());
+    }
+}
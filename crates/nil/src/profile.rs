@@ -0,0 +1,113 @@
+//! Durations of recent requests and analysis passes, returned by `nil/profile`.
+use crate::lsp_ext::{ProfileEntry, ProfilePass};
+use ide::PassTimings;
+use lsp_types::Url;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The maximum number of entries kept. Older ones are dropped.
+const MAX_ENTRIES: usize = 256;
+
+#[derive(Debug, Default)]
+pub struct Profiler {
+    entries: Mutex<VecDeque<Entry>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    uri: Option<Url>,
+    finished: Instant,
+    duration: Duration,
+    passes: PassTimings,
+}
+
+impl Profiler {
+    pub fn record(
+        &self,
+        name: impl Into<String>,
+        uri: Option<Url>,
+        duration: Duration,
+        passes: PassTimings,
+    ) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == MAX_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(Entry {
+            name: name.into(),
+            uri,
+            finished: Instant::now(),
+            duration,
+            passes,
+        });
+    }
+
+    /// Recorded entries about `uri` if set, or all entries, the most recent first.
+    pub fn entries(&self, uri: Option<&Url>) -> Vec<ProfileEntry> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .rev()
+            .filter(|entry| uri.map_or(true, |uri| entry.uri.as_ref() == Some(uri)))
+            .map(|entry| ProfileEntry {
+                name: entry.name.clone(),
+                uri: entry.uri.clone(),
+                ago_ms: (now - entry.finished).as_millis() as u64,
+                duration_ms: to_ms(entry.duration),
+                passes: entry
+                    .passes
+                    .iter()
+                    .map(|&(name, duration)| ProfilePass {
+                        name: name.into(),
+                        duration_ms: to_ms(duration),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+fn to_ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Profiler, MAX_ENTRIES};
+    use lsp_types::Url;
+    use std::time::Duration;
+
+    #[test]
+    fn entries() {
+        let profiler = Profiler::default();
+        let uri = Url::parse("file:///a.nix").unwrap();
+        profiler.record(
+            "diagnostics",
+            Some(uri.clone()),
+            Duration::from_millis(3),
+            vec![("parse", Duration::from_millis(1))],
+        );
+        for _ in 0..MAX_ENTRIES {
+            profiler.record("shutdown", None, Duration::ZERO, Vec::new());
+        }
+        assert_eq!(profiler.entries(None).len(), MAX_ENTRIES);
+        assert!(profiler.entries(Some(&uri)).is_empty());
+
+        profiler.record(
+            "diagnostics",
+            Some(uri.clone()),
+            Duration::from_millis(3),
+            vec![("parse", Duration::from_millis(1))],
+        );
+        let entries = profiler.entries(Some(&uri));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "diagnostics");
+        assert_eq!(entries[0].duration_ms, 3.0);
+        assert_eq!(entries[0].passes[0].name, "parse");
+        assert_eq!(entries[0].passes[0].duration_ms, 1.0);
+        assert_eq!(profiler.entries(None)[0], entries[0]);
+    }
+}
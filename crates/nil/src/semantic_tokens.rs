@@ -44,6 +44,7 @@ def_index! {
     Escape => SemanticTokenModifier::new("escape"),
     Parenthesis => SemanticTokenModifier::new("parenthesis"),
     Readonly => SemanticTokenModifier::READONLY,
+    Shell => SemanticTokenModifier::new("shell"),
     Unresolved => SemanticTokenModifier::new("unresolved"),
     WithAttribute => SemanticTokenModifier::new("withAttribute"),
 }
@@ -133,6 +134,10 @@ pub(crate) fn to_semantic_type_and_modifiers(tag: HlTag) -> (TokenTypeIdx, Token
             }
             TokenTypeIdx::Punctuation
         }
+        HlTag::ShellVariable => {
+            mods.insert(TokenModIdx::Shell);
+            TokenTypeIdx::Variable
+        }
         HlTag::StringLiteral => TokenTypeIdx::String,
     };
     (ty, mods)
@@ -1,59 +1,146 @@
+use crate::cancel::CancelToken;
 use crate::capabilities::{negotiate_capabilities, NegotiatedCapabilities};
-use crate::config::{Config, CONFIG_KEY};
-use crate::{convert, handler, lsp_ext, UrlExt, Vfs, MAX_FILE_LEN};
+use crate::config::{AnalysisRoot, Config, CONFIG_KEY};
+use crate::handler::{
+    AttrPosQuery, CompletionCache, CompletionReply, CompletionResolveCache, GotoDefinitionReply,
+    HoverReply,
+};
+use crate::index_cache::{self, IndexCache};
+use crate::lsp_ext::ClientCapabilitiesExt;
+use crate::module_graph::{self, ModuleGraph};
+use crate::path_cache::PathCache;
+use crate::profile::Profiler;
+use crate::session::FileSource;
+use crate::{convert, handler, indexer, lsp_ext, LineMap, UrlExt, Vfs, MAX_FILE_LEN};
+use crate::{doctor, log_trace};
 use anyhow::{bail, ensure, Context, Result};
 use async_lsp::router::Router;
 use async_lsp::{ClientSocket, ErrorCode, LanguageClient, ResponseError};
-use ide::{Analysis, AnalysisHost, Cancelled, FlakeInfo, VfsPath};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use ide::{Analysis, AnalysisHost, Cancelled, FileId, FlakeInfo, Interrupted, OptionSet, VfsPath};
 use lsp_types::notification::Notification;
 use lsp_types::request::{self as req, Request};
 use lsp_types::{
-    notification as notif, ConfigurationItem, ConfigurationParams, DidChangeConfigurationParams,
+    notification as notif, ApplyWorkspaceEditParams, CompletionParams, CompletionResponse,
+    ConfigurationItem, ConfigurationParams, DidChangeConfigurationParams,
     DidChangeTextDocumentParams, DidChangeWatchedFilesParams,
-    DidChangeWatchedFilesRegistrationOptions, DidCloseTextDocumentParams,
-    DidOpenTextDocumentParams, FileChangeType, FileEvent, FileSystemWatcher, GlobPattern,
-    InitializeParams, InitializeResult, InitializedParams, MessageActionItem,
-    MessageActionItemProperty, MessageType, NumberOrString, OneOf, ProgressParams,
-    ProgressParamsValue, PublishDiagnosticsParams, Registration, RegistrationParams,
-    RelativePattern, ServerInfo, ShowMessageParams, ShowMessageRequestParams, Url,
-    WorkDoneProgress, WorkDoneProgressBegin, WorkDoneProgressCreateParams, WorkDoneProgressEnd,
-    WorkDoneProgressReport,
+    DidChangeWatchedFilesRegistrationOptions, DidChangeWorkspaceFoldersParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DidSaveTextDocumentParams,
+    ExecuteCommandParams, FileChangeType, FileEvent, FileSystemWatcher, GlobPattern,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    InitializeParams, InitializedParams, Location, MessageActionItem, MessageActionItemProperty,
+    MessageType, NumberOrString, OneOf, Position, ProgressParams, ProgressParamsValue,
+    PublishDiagnosticsParams, Range, ReferenceContext, ReferenceParams, Registration,
+    RegistrationParams, RelativePattern, ServerInfo, SetTraceParams, ShowMessageParams,
+    ShowMessageRequestParams, TextDocumentIdentifier, TextDocumentPositionParams, Unregistration,
+    UnregistrationParams, Url, WatchKind, WorkDoneProgress, WorkDoneProgressBegin,
+    WorkDoneProgressCreateParams, WorkDoneProgressEnd, WorkDoneProgressReport, WorkspaceEdit,
+    WorkspaceFolder,
 };
+use nix_interop::eval::{self, EvalOptions};
+use nix_interop::eval_error::{self, EvalError, EvalTarget};
+use nix_interop::lib_docs::LibDocs;
 use nix_interop::nixos_options::{self, NixosOptions};
-use nix_interop::{flake_lock, flake_output, FlakeUrl, FLAKE_FILE, FLAKE_LOCK_FILE};
+use nix_interop::package_aliases::PackageAliases;
+use nix_interop::package_index::{self, PackageIndex};
+use nix_interop::prefetch;
+use nix_interop::repl::Repl;
+use nix_interop::store_path::{self, StorePathInfo, NIX_STORE_DIR};
+use nix_interop::{
+    flake_lock, flake_output, installable, FlakeUrl, DEFAULT_IMPORT_FILE, FLAKE_FILE,
+    FLAKE_LOCK_FILE,
+};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use std::backtrace::Backtrace;
 use std::borrow::BorrowMut;
 use std::cell::Cell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::future::{ready, Future};
-use std::io::{ErrorKind, Read};
+use std::io::ErrorKind;
 use std::ops::ControlFlow;
-use std::panic::UnwindSafe;
-use std::path::Path;
+use std::panic::{AssertUnwindSafe, UnwindSafe};
+use std::path::{Path, PathBuf};
 use std::pin::pin;
-use std::sync::{Arc, Once, RwLock};
+use std::sync::{Arc, Mutex, Once, RwLock};
 use std::time::Duration;
 use std::{fmt, panic};
-use tokio::sync::watch;
+use tokio::sync::{oneshot, watch};
 use tokio::task;
 use tokio::task::JoinHandle;
 
 const LSP_SERVER_NAME: &str = "nil";
+
+type AttrPosCache = HashMap<AttrPosQuery, Option<installable::AttrPos>>;
+/// Metadata of store paths existing in the local store.
+type StorePathCache = HashMap<PathBuf, StorePathInfo>;
+/// Evaluated attribute names of expressions, keyed by the base directory and the expression.
+type EvalCompletionCache = HashMap<(PathBuf, String), Arc<[String]>>;
+const LOAD_FLAKE_INFO_PROGRESS_TOKEN: &str = "nil/loadFlakeInfoProgress";
 const FLAKE_ARCHIVE_PROGRESS_TOKEN: &str = "nil/flakeArchiveProgress";
 const LOAD_INPUT_FLAKE_PROGRESS_TOKEN: &str = "nil/loadInputFlakeProgress";
 const LOAD_NIXOS_OPTIONS_PROGRESS_TOKEN: &str = "nil/loadNixosOptionsProgress";
+const LOAD_PACKAGE_INDEX_PROGRESS_TOKEN: &str = "nil/loadPackageIndexProgress";
+const INDEX_WORKSPACE_PROGRESS_TOKEN: &str = "nil/indexWorkspaceProgress";
+const PREFETCH_HASH_PROGRESS_TOKEN: &str = "nil/prefetchHashProgress";
+
+// Kinds of indexes persisted by `IndexCache`.
+const NIXOS_OPTIONS_CACHE: &str = "nixos-options";
+const HOME_MANAGER_OPTIONS_CACHE: &str = "home-manager-options";
+const DARWIN_OPTIONS_CACHE: &str = "darwin-options";
+const PACKAGE_INDEX_CACHE: &str = "package-index";
+const LIB_DOCS_CACHE: &str = "lib-docs";
+const MODULE_GRAPH_CACHE: &str = "module-graph";
 
-const MAX_DIAGNOSTICS_CNT: usize = 128;
+/// The number of workspaces whose module graphs are kept.
+const MAX_CACHED_MODULE_GRAPHS: usize = 16;
 
 const PROGRESS_REPORT_PERIOD: Duration = Duration::from_millis(100);
 const LOAD_FLAKE_WORKSPACE_DEBOUNCE_DURATION: Duration = Duration::from_millis(100);
+/// Large sources or stuck connections should not keep the command running forever.
+const PREFETCH_HASH_TIMEOUT: Duration = Duration::from_secs(300);
+/// Hovers should not wait for a busy Nix daemon.
+const STORE_PATH_INFO_TIMEOUT: Duration = Duration::from_secs(5);
 
 type NotifyResult = ControlFlow<async_lsp::Result<()>>;
 
 struct UpdateConfigEvent(serde_json::Value);
-struct UpdateDiagnostics(u64, Vec<(Url, Vec<lsp_types::Diagnostic>)>);
-struct SetFlakeInfoEvent(Option<FlakeInfo>);
-struct SetNixosOptionsEvent(NixosOptions);
+/// Diagnostics of opened files, each keyed by the file they are located in.
+struct UpdateDiagnostics(u64, Vec<(Url, HashMap<Url, Vec<lsp_types::Diagnostic>>)>);
+/// The debounce delay of diagnostics of the version has passed.
+struct DebouncedDiagnostics(u64);
+/// The result of evaluating `target` for the saved file `uri`, in the flake at `flake_root` if any.
+struct SetEvalDiagnosticsEvent {
+    target: EvalTarget,
+    uri: Url,
+    flake_root: Option<PathBuf>,
+    error: Option<EvalError>,
+}
+/// The flake info of a workspace folder.
+struct SetFlakeInfoEvent(PathBuf, Option<FlakeInfo>);
+/// Options of a set, with the source which relative declarations are relative to.
+struct SetOptionSetEvent(OptionSet, NixosOptions, Option<PathBuf>);
+struct SetPackageIndexEvent(PackageIndex);
+struct SetLibDocsEvent(LibDocs);
+/// A batch of indexed files, and whether it is the last one.
+struct IndexFilesEvent(Vec<(Url, String)>, bool);
+/// Closed files to be loaded from the disk on demand.
+struct LoadFilesEvent(Vec<Url>);
+/// The module graph saved by the last session.
+struct SetModuleGraphEvent(ModuleGraph);
+/// A background task is finished, with errors to report in `experimental/serverStatus`.
+struct TaskFinishedEvent(BackgroundTask, Vec<String>);
+
+/// Diagnostics of the evaluation error of each target, keyed by the file they are located in.
+type EvalDiagnostics = HashMap<EvalTarget, HashMap<Url, Vec<lsp_types::Diagnostic>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum BackgroundTask {
+    IndexWorkspace,
+    LoadFlakeWorkspace,
+}
 
 pub struct Server {
     // States.
@@ -62,126 +149,275 @@ pub struct Server {
     vfs: Arc<RwLock<Vfs>>,
     opened_files: HashMap<Url, FileData>,
     config: Arc<Config>,
+    /// Existence of paths referred by path literals.
+    path_cache: Arc<PathCache>,
+    completion_cache: Arc<Mutex<Option<CompletionCache>>>,
+    completion_resolve_cache: Arc<Mutex<CompletionResolveCache>>,
+    /// Durations of recent requests and analysis passes.
+    profiler: Arc<Profiler>,
+    /// Evaluated positions of attributes of flake inputs. Store paths are immutable,
+    /// thus they never expire.
+    attr_pos_cache: Arc<Mutex<AttrPosCache>>,
+    /// Metadata of store paths shown on hover. Registered paths are immutable until garbage
+    /// collected, thus only missing ones are queried again.
+    store_path_cache: Arc<Mutex<StorePathCache>>,
+    /// Attribute names evaluated for completion. Evaluation reads files from the disk,
+    /// thus they are cleared when watched files change.
+    eval_completion_cache: Arc<Mutex<EvalCompletionCache>>,
+    /// Found by `diagnostics.eval`, which are kept until the files containing them are edited.
+    eval_diagnostics: Arc<Mutex<EvalDiagnostics>>,
+    /// The `nix repl` process for `nil/replEval`, spawned on demand. Evaluations are serialized.
+    repl: Arc<tokio::sync::Mutex<Option<Repl>>>,
     /// Tried to load flake?
     /// This is used to reload flake only once after the configuration is first loaded.
     tried_flake_load: bool,
-    /// Is this workspace a flake?
-    workspace_is_flake: bool,
+    /// Workspace folders which are flakes.
+    flake_roots: HashSet<PathBuf>,
     diagnostic_version: u64,
+    /// The most recently opened or edited document, whose diagnostics are updated first.
+    focused_file: Option<Url>,
+    /// Should the workspace be indexed after the configuration is loaded?
+    index_pending: bool,
+    /// Files loaded by the ongoing indexing, to be analyzed after all batches are loaded.
+    indexed_files: Vec<FileId>,
+    /// The module graph saved by the last session, to load files related to opened ones early.
+    module_graph: Option<Arc<ModuleGraph>>,
+    /// Running background tasks, and errors of the last run of each.
+    running_tasks: HashSet<BackgroundTask>,
+    task_errors: HashMap<BackgroundTask, Vec<String>>,
+    /// The last sent `experimental/serverStatus`, to skip unchanged ones.
+    last_status: Option<lsp_ext::ServerStatusParams>,
 
     // Ongoing tasks.
     load_flake_workspace_fut: Option<JoinHandle<()>>,
+    index_workspace_fut: Option<JoinHandle<()>>,
+    /// The running evaluation of each target. A new one cancels the previous.
+    eval_diagnostics_futs: HashMap<EvalTarget, JoinHandle<()>>,
+    /// Created on the first indexing. The thread count is fixed since then.
+    indexer_pool: Option<Arc<ThreadPool>>,
+    /// Runs read-only handlers on snapshots, so that the main loop keeps applying edits.
+    request_pool: ThreadPool,
 
     // Immutable (mostly).
     client: ClientSocket,
     capabilities: NegotiatedCapabilities,
     /// Messages to show once initialized.
     init_messages: Vec<ShowMessageParams>,
+    /// Where to read files not opened by the client.
+    file_source: FileSource,
 }
 
 #[derive(Debug, Default)]
 struct FileData {
+    /// Diagnostics found by analyzing this file, keyed by the file they are located in.
     // XXX: `lsp_types::Diagnostic` has a very large memory footprint.
-    diagnostics: Vec<lsp_types::Diagnostic>,
+    diagnostics: HashMap<Url, Vec<lsp_types::Diagnostic>>,
 }
 
 impl Server {
-    pub fn new_router(client: ClientSocket, init_messages: Vec<ShowMessageParams>) -> Router<Self> {
-        let this = Self::new(client, init_messages);
+    pub fn new_router(
+        client: ClientSocket,
+        init_messages: Vec<ShowMessageParams>,
+        file_source: FileSource,
+        profiler: Arc<Profiler>,
+    ) -> Router<Self> {
+        let this = Self::new(client, init_messages, file_source, profiler);
         let mut router = Router::new(this);
         router
             //// Lifecycle ////
-            .request::<req::Initialize, _>(Self::on_initialize)
+            .request::<lsp_ext::Initialize, _>(Self::on_initialize)
             .notification::<notif::Initialized>(Self::on_initialized)
-            .request::<req::Shutdown, _>(|_, _| ready(Ok(())))
+            .request::<req::Shutdown, _>(Self::on_shutdown)
             .notification::<notif::Exit>(|_, _| ControlFlow::Break(Ok(())))
+            .notification::<notif::SetTrace>(Self::on_set_trace)
             //// Notifications ////
             .notification::<notif::DidOpenTextDocument>(Self::on_did_open)
             .notification::<notif::DidCloseTextDocument>(Self::on_did_close)
             .notification::<notif::DidChangeTextDocument>(Self::on_did_change)
+            .notification::<notif::DidSaveTextDocument>(Self::on_did_save)
             .notification::<notif::DidChangeConfiguration>(Self::on_did_change_configuration)
             // NB. This handler is mandatory.
             // > In former implementations clients pushed file events without the server actively asking for it.
             // Ref: https://microsoft.github.io/language-server-protocol/specifications/lsp/3.17/specification/#workspace_didChangeWatchedFiles
             .notification::<notif::DidChangeWatchedFiles>(Self::on_did_change_watched_files)
+            .notification::<notif::DidChangeWorkspaceFolders>(Self::on_did_change_workspace_folders)
             .notification::<lsp_ext::ReloadFlake>(Self::on_reload_flake)
+            .notification::<lsp_ext::ReloadWorkspace>(Self::on_reload_workspace)
             //// Requests ////
-            .request_snap::<req::GotoDefinition>(handler::goto_definition)
+            .request::<req::GotoDefinition, _>(Self::on_goto_definition)
             .request_snap::<req::References>(handler::references)
-            .request_snap::<req::Completion>(handler::completion)
+            .request::<req::Completion, _>(Self::on_completion)
+            .request_snap::<req::ResolveCompletionItem>(handler::completion_resolve)
             .request_snap::<req::SelectionRangeRequest>(handler::selection_range)
             .request_snap::<req::PrepareRenameRequest>(handler::prepare_rename)
             .request_snap::<req::Rename>(handler::rename)
+            .request_snap::<req::WillRenameFiles>(handler::will_rename_files)
             .request_snap::<req::SemanticTokensFullRequest>(handler::semantic_token_full)
             .request_snap::<req::SemanticTokensRangeRequest>(handler::semantic_token_range)
-            .request_snap::<req::HoverRequest>(handler::hover)
+            .request::<req::HoverRequest, _>(Self::on_hover)
             .request_snap::<req::DocumentSymbolRequest>(handler::document_symbol)
             .request_snap::<req::Formatting>(handler::formatting)
+            .request_snap::<req::WillSaveWaitUntil>(handler::will_save_wait_until)
             .request_snap::<req::DocumentLinkRequest>(handler::document_links)
             .request_snap::<req::DocumentLinkResolve>(handler::document_link_resolve)
-            .request_snap::<req::CodeActionRequest>(handler::code_action)
+            .request_snap::<lsp_ext::CodeActionRequest>(handler::code_action)
             .request_snap::<req::DocumentHighlightRequest>(handler::document_highlight)
+            .request_snap::<req::CodeLensRequest>(handler::code_lens)
+            .request_snap::<req::CodeLensResolve>(handler::code_lens_resolve)
+            .request_snap::<req::InlayHintRequest>(handler::inlay_hint)
+            .request_snap::<req::LinkedEditingRange>(handler::linked_editing_range)
+            .request_snap::<req::SignatureHelpRequest>(handler::signature_help)
             .request_snap::<lsp_ext::ParentModule>(handler::parent_module)
+            .request_snap::<lsp_ext::SymbolsPage>(handler::symbols_page)
+            .request_snap::<lsp_ext::FlakeInputSource>(handler::flake_input_source)
+            .request_snap::<lsp_ext::SyntaxTree>(handler::syntax_tree)
+            .request_snap::<lsp_ext::MemoryUsage>(handler::memory_usage)
+            .request_snap::<lsp_ext::Profile>(handler::profile)
+            .request_snap::<lsp_ext::DocumentDiagnosticRequest>(handler::document_diagnostic)
+            .request::<lsp_ext::ReplEval, _>(Self::on_repl_eval)
+            .request::<req::ExecuteCommand, _>(Self::on_execute_command)
+            .request::<lsp_ext::WorkspaceDiagnosticRequest, _>(Self::on_workspace_diagnostic)
             //// Events ////
             .event(Self::on_set_flake_info)
-            .event(Self::on_set_nixos_options)
+            .event(Self::on_set_option_set)
+            .event(Self::on_set_package_index)
+            .event(Self::on_set_lib_docs)
             .event(Self::on_update_config)
             .event(Self::on_update_diagnostics)
+            .event(Self::on_set_eval_diagnostics)
+            .event(Self::on_debounced_diagnostics)
+            .event(Self::on_index_files)
+            .event(Self::on_load_files)
+            .event(Self::on_set_module_graph)
+            .event(Self::on_task_finished)
             // Loopback event.
             .event(Self::on_did_change_watched_files);
         router
     }
 
-    pub fn new(client: ClientSocket, init_messages: Vec<ShowMessageParams>) -> Self {
+    pub fn new(
+        client: ClientSocket,
+        init_messages: Vec<ShowMessageParams>,
+        file_source: FileSource,
+        profiler: Arc<Profiler>,
+    ) -> Self {
         Self {
             host: AnalysisHost::default(),
             vfs: Arc::new(RwLock::new(Vfs::new())),
             opened_files: HashMap::default(),
             // Will be set during initialization.
             config: Arc::new(Config::new("/non-existing-path".into())),
+            path_cache: Arc::default(),
+            completion_cache: Arc::default(),
+            completion_resolve_cache: Arc::default(),
+            profiler,
+            attr_pos_cache: Arc::default(),
+            store_path_cache: Arc::default(),
+            eval_completion_cache: Arc::default(),
+            eval_diagnostics: Arc::default(),
+            repl: Arc::default(),
             tried_flake_load: false,
-            workspace_is_flake: false,
+            flake_roots: HashSet::new(),
             diagnostic_version: 0,
+            focused_file: None,
+            index_pending: false,
+            indexed_files: Vec::new(),
+            module_graph: None,
+            running_tasks: HashSet::new(),
+            task_errors: HashMap::new(),
+            last_status: None,
 
             load_flake_workspace_fut: None,
+            index_workspace_fut: None,
+            eval_diagnostics_futs: HashMap::new(),
+            indexer_pool: None,
+            request_pool: ThreadPoolBuilder::new()
+                .thread_name(|i| format!("nil-request-{i}"))
+                .build()
+                .expect("Failed to build the request thread pool"),
 
             client,
             // Will be set during initialization.
             capabilities: NegotiatedCapabilities::default(),
             init_messages,
+            file_source,
         }
     }
 
     fn on_initialize(
         &mut self,
-        params: InitializeParams,
-    ) -> impl Future<Output = Result<InitializeResult, ResponseError>> {
+        params: serde_json::Value,
+    ) -> impl Future<Output = Result<lsp_ext::InitializeResult, ResponseError>> {
+        // Capabilities unknown to `lsp_types` are extracted separately.
+        let ext_caps = params
+            .get("capabilities")
+            .and_then(|caps| ClientCapabilitiesExt::deserialize(caps).ok())
+            .unwrap_or_default();
+        let params = match InitializeParams::deserialize(params) {
+            Ok(params) => params,
+            Err(err) => {
+                return ready(Err(ResponseError::new(
+                    ErrorCode::INVALID_PARAMS,
+                    format!("Invalid initialize params: {err}"),
+                )))
+            }
+        };
         tracing::info!("Init params: {params:?}");
+        log_trace::set_client(self.client.clone());
+        if let Some(trace) = params.trace {
+            log_trace::set_trace(trace);
+        }
 
-        let (server_caps, final_caps) = negotiate_capabilities(&params);
+        let (server_caps, final_caps) = negotiate_capabilities(&params, &ext_caps);
+        self.vfs
+            .write()
+            .unwrap()
+            .set_position_encoding(final_caps.position_encoding);
         self.capabilities = final_caps;
 
-        // TODO: Use `workspaceFolders`.
-        let root_path = match params
+        let folders = params
+            .workspace_folders
+            .iter()
+            .flatten()
+            .filter_map(|folder| folder.uri.to_file_path().ok())
+            .collect::<Vec<_>>();
+        let root = params
             .root_uri
             .as_ref()
             .and_then(|uri| uri.to_file_path().ok())
-        {
-            Some(path) => path,
+            .or_else(|| folders.first().cloned());
+        // Without any workspace folder, opened files are analyzed on their own. Nothing is
+        // indexed, watched or loaded as a flake, but relative settings still need a base.
+        let root_path = match &root {
+            Some(path) => path.clone(),
             None => std::env::current_dir().expect("Failed to the current directory"),
         };
+        let mut roots = Vec::from_iter(root);
+        for folder in folders {
+            if !roots.contains(&folder) {
+                roots.push(folder);
+            }
+        }
+        self.vfs.write().unwrap().set_roots(roots);
 
         // Allow the client to pass initial settings through `initializationOptions`, especially
         // when they do not support `workspace/configuration`.
         *Arc::get_mut(&mut self.config).expect("No concurrent access yet") = Config::new(root_path);
-        if let Some(options) = params.initialization_options {
+        let mut search_path = self.config.search_path();
+        search_path.check_existing();
+        self.vfs.write().unwrap().set_search_path(search_path);
+        if let Some(mut options) = params.initialization_options {
+            // Some clients send the whole settings tree, which has our settings under `nil`.
+            if let Some(inner) = options.get_mut(CONFIG_KEY).filter(|v| v.is_object()) {
+                options = inner.take();
+            }
             if options.as_object().filter(|o| !o.is_empty()).is_some() {
                 tracing::debug!("Initialization options: {options}");
                 self.on_update_config(UpdateConfigEvent(options));
             }
         }
 
-        ready(Ok(InitializeResult {
+        ready(Ok(lsp_ext::InitializeResult {
             capabilities: server_caps,
             server_info: Some(ServerInfo {
                 name: LSP_SERVER_NAME.into(),
@@ -190,6 +426,11 @@ impl Server {
         }))
     }
 
+    fn on_set_trace(&mut self, params: SetTraceParams) -> NotifyResult {
+        log_trace::set_trace(params.value);
+        ControlFlow::Continue(())
+    }
+
     fn on_initialized(&mut self, _params: InitializedParams) -> NotifyResult {
         for msg in std::mem::take(&mut self.init_messages) {
             tracing::warn!("Init message ({:?}): {}", msg.typ, msg.message);
@@ -202,25 +443,22 @@ impl Server {
         // read uninitialized configs.
         self.spawn_reload_config();
 
+        // Indexing also depends on configurations. Wait for them if they are going to be loaded.
+        if self.capabilities.workspace_configuration {
+            self.index_pending = true;
+        } else {
+            self.spawn_index_workspace();
+        }
+
         // Make a virtual event to trigger loading of flake files for flake info.
-        let flake_files_changed_event = DidChangeWatchedFilesParams {
-            changes: [FLAKE_LOCK_FILE, FLAKE_FILE]
-                .into_iter()
-                .map(|name| {
-                    let uri = Url::from_file_path(self.config.root_path.join(name))
-                        .expect("Root must be absolute");
-                    let typ = FileChangeType::CREATED;
-                    FileEvent { uri, typ }
-                })
-                .collect(),
-        };
+        let roots = self.vfs.read().unwrap().roots().to_vec();
+        let flake_files_changed_event = flake_files_created_event(&roots);
         if self.capabilities.watch_files {
             tokio::spawn({
-                let config = self.config.clone();
                 let caps = self.capabilities.clone();
                 let mut client = self.client.clone();
                 async move {
-                    Self::register_watched_files(&config, &caps, &mut client).await;
+                    Self::register_watched_files(&roots, &caps, &mut client).await;
                     let _: Result<_, _> = client.emit(flake_files_changed_event);
                 }
             });
@@ -228,36 +466,93 @@ impl Server {
             self.on_did_change_watched_files(flake_files_changed_event)?;
         }
 
+        self.update_server_status();
         ControlFlow::Continue(())
     }
 
+    /// Save the module graph of the workspace for the next session. See `module_graph`.
+    fn on_shutdown(&mut self, (): ()) -> BoxFuture<'static, Result<(), ResponseError>> {
+        let roots = self.vfs.read().unwrap().roots().to_vec();
+        if !self.config.indexing_enable
+            || !self.config.indexing_persist
+            || !matches!(self.file_source, FileSource::Disk)
+            || roots.is_empty()
+        {
+            return ready(Ok(())).boxed();
+        }
+        let key = module_graph::cache_key(&roots);
+        let opened = self.opened_files.keys().cloned().collect();
+        let task = self.spawn_snap_handler(
+            "module_graph",
+            handler::module_graph,
+            (roots, opened, self.module_graph.clone()),
+        );
+        async move {
+            let graph = match task.await {
+                Ok(graph) => graph,
+                Err(err) => {
+                    tracing::warn!("Failed to collect the module graph: {}", err.message);
+                    return Ok(());
+                }
+            };
+            let ret = task::spawn_blocking(move || {
+                let cache = IndexCache::from_env()
+                    .context("No cache directory")?
+                    .with_max_entries(MAX_CACHED_MODULE_GRAPHS);
+                cache.store(MODULE_GRAPH_CACHE, &key, &graph)
+            })
+            .await;
+            match ret {
+                Ok(Ok(())) => tracing::info!("Saved the module graph"),
+                Ok(Err(err)) => tracing::warn!("Failed to save the module graph: {err:#}"),
+                Err(err) => tracing::warn!("Failed to save the module graph: {err}"),
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    /// Register file watchers for each of workspace folders `roots`.
     async fn register_watched_files(
-        config: &Config,
+        roots: &[PathBuf],
         caps: &NegotiatedCapabilities,
         client: &mut ClientSocket,
     ) {
-        let to_watcher = |pat: &str| FileSystemWatcher {
-            glob_pattern: if caps.watch_files_relative_pattern {
-                let root_uri = Url::from_file_path(&config.root_path).expect("Must be absolute");
-                GlobPattern::Relative(RelativePattern {
-                    base_uri: OneOf::Right(root_uri),
-                    pattern: pat.into(),
-                })
-            } else {
-                GlobPattern::String(format!("{}/{}", config.root_path.display(), pat))
-            },
-            // All events.
-            kind: None,
-        };
-        let register_options = DidChangeWatchedFilesRegistrationOptions {
-            watchers: [FLAKE_LOCK_FILE, FLAKE_FILE].map(to_watcher).into(),
-        };
-        let params = RegistrationParams {
-            registrations: vec![Registration {
-                id: notif::DidChangeWatchedFiles::METHOD.into(),
+        if roots.is_empty() {
+            return;
+        }
+        let to_registration = |root: &PathBuf| {
+            let to_watcher = |pat: &str, kind| FileSystemWatcher {
+                glob_pattern: if caps.watch_files_relative_pattern {
+                    let root_uri = Url::from_file_path(root).expect("Must be absolute");
+                    GlobPattern::Relative(RelativePattern {
+                        base_uri: OneOf::Right(root_uri),
+                        pattern: pat.into(),
+                    })
+                } else {
+                    GlobPattern::String(format!("{}/{}", root.display(), pat))
+                },
+                kind,
+            };
+            let register_options = DidChangeWatchedFilesRegistrationOptions {
+                watchers: vec![
+                    // All events.
+                    to_watcher(FLAKE_LOCK_FILE, None),
+                    to_watcher(FLAKE_FILE, None),
+                    // Contents of closed Nix files, eg. after switching git branches.
+                    to_watcher("**/*.nix", None),
+                    // Existence of paths referred by path literals.
+                    to_watcher("**/*", Some(WatchKind::Create | WatchKind::Delete)),
+                ],
+            };
+            Registration {
+                id: watcher_registration_id(root),
                 method: notif::DidChangeWatchedFiles::METHOD.into(),
                 register_options: Some(serde_json::to_value(register_options).unwrap()),
-            }],
+            }
+        };
+        let params = RegistrationParams {
+            registrations: roots.iter().map(to_registration).collect(),
         };
         if let Err(err) = client.register_capability(params).await {
             client.show_message_ext(
@@ -265,7 +560,7 @@ impl Server {
                 format!("Failed to watch flake files: {err:#}"),
             );
         }
-        tracing::info!("Registered file watching for flake files");
+        tracing::info!("Registered file watching for Nix files and paths");
     }
 
     fn on_did_open(&mut self, params: DidOpenTextDocumentParams) -> NotifyResult {
@@ -282,21 +577,30 @@ impl Server {
 
         let uri = params.text_document.uri;
         self.opened_files.insert(uri.clone(), FileData::default());
+        self.focused_file = Some(uri.clone());
         self.set_vfs_file_content(&uri, params.text_document.text);
-
-        // We created a new flake.nix
-        if !self.workspace_is_flake
-            && uri
-                .to_file_path()
-                .ok()
-                .as_ref()
-                .and_then(|path| path.file_name())
-                .map_or(false, |name| name == FLAKE_FILE)
         {
+            let mut vfs = self.vfs.write().unwrap();
+            if let Ok(file) = vfs.file_for_uri(&uri) {
+                vfs.set_file_version(file, Some(params.text_document.version));
+            }
+        }
+
+        // We created a new flake.nix in a workspace folder.
+        let new_flake_root = uri.to_file_path().ok().and_then(|path| {
+            let root = path.parent()?;
+            (path.file_name()? == FLAKE_FILE
+                && !self.flake_roots.contains(root)
+                && self.vfs.read().unwrap().roots().iter().any(|r| r == root))
+            .then_some(())
+        });
+        if new_flake_root.is_some() {
             self.spawn_load_flake_workspace();
         }
 
         self.spawn_update_diagnostics();
+        self.spawn_load_references(&uri);
+        self.load_related_files(&uri);
 
         ControlFlow::Continue(())
     }
@@ -305,16 +609,22 @@ impl Server {
         // N.B. Don't clear text here.
         // `DidCloseTextDocument` means the client ends its maintenance to a file but
         // not deletes it.
+        // Clear diagnostics found by closed files, including those in other files.
+        // Pulling clients manage their diagnostics by themselves.
+        let prev = self.collect_diagnostics();
         self.opened_files.remove(&params.text_document.uri);
-
-        // Clear diagnostics for closed files.
-        self.client
-            .publish_diagnostics(PublishDiagnosticsParams {
-                uri: params.text_document.uri,
-                diagnostics: Vec::new(),
-                version: None,
-            })
-            .expect("inside main loop");
+        {
+            let mut vfs = self.vfs.write().unwrap();
+            if let Ok(file) = vfs.file_for_uri(&params.text_document.uri) {
+                vfs.set_file_version(file, None);
+            }
+        }
+        if self.focused_file.as_ref() == Some(&params.text_document.uri) {
+            self.focused_file = None;
+        }
+        if !self.capabilities.pull_diagnostics {
+            self.publish_changed_diagnostics(prev);
+        }
 
         ControlFlow::Continue(())
     }
@@ -326,6 +636,7 @@ impl Server {
         let Ok(file) = vfs.file_for_uri(&uri) else {
             return ControlFlow::Continue(());
         };
+        vfs.set_file_version(file, Some(params.text_document.version));
         for change in params.content_changes {
             let ret = (|| {
                 let del_range = match change.range {
@@ -347,9 +658,78 @@ impl Server {
             }
         }
         drop(vfs);
+        self.focused_file = Some(uri.clone());
+        self.update_eval_diagnostics(|diags| {
+            diags.values_mut().fold(false, |changed, diags| {
+                diags.remove(&uri).is_some() || changed
+            })
+        });
 
         // FIXME: This blocks.
-        self.apply_vfs_change();
+        self.apply_vfs_change_without_diagnostics();
+        // Pulling clients request diagnostics of the changed file by themselves.
+        if !self.capabilities.pull_diagnostics {
+            self.spawn_update_diagnostics();
+        }
+        self.spawn_load_references(&uri);
+
+        ControlFlow::Continue(())
+    }
+
+    /// The saved content is the latest one on the disk, which is read by evaluation and loaded
+    /// by files referring it. With `files.reloadOnSave`, the content on the disk replaces the
+    /// buffer if they differ, eg. when a formatter rewrites the file behind the server.
+    /// Otherwise, the saved text sent by the client resyncs the buffer if it is out of sync.
+    fn on_did_save(&mut self, params: DidSaveTextDocumentParams) -> NotifyResult {
+        let uri = params.text_document.uri;
+        if !self.opened_files.contains_key(&uri) {
+            return ControlFlow::Continue(());
+        }
+        self.eval_completion_cache.lock().unwrap().clear();
+        if let Ok(path) = uri.to_file_path() {
+            // It may be created by this save.
+            self.path_cache.invalidate(&path);
+        }
+
+        let text = if self.config.files_reload_on_save {
+            let read = || {
+                let path = uri.to_file_path().map_err(|()| ErrorKind::InvalidInput)?;
+                indexer::read_regular_file(&path)
+            };
+            match self.file_source.read(&uri, read) {
+                Ok(text) => Some(text),
+                Err(err) => {
+                    tracing::warn!("Failed to reload saved file {uri}: {err}");
+                    params.text
+                }
+            }
+        } else {
+            params.text
+        };
+        let changed = text.map_or(false, |text| {
+            let mut vfs = self.vfs.write().unwrap();
+            let Ok(file) = vfs.file_for_uri(&uri) else {
+                return false;
+            };
+            if *vfs.content_for_file(file) == *text {
+                return false;
+            }
+            tracing::info!("Reconcile {uri} with the saved content");
+            vfs.set_path_content(uri.to_vfs_path(), text);
+            true
+        });
+
+        // Files referring it, and references added since opened.
+        self.load_related_files(&uri);
+        self.spawn_load_references(&uri);
+        if changed {
+            self.apply_vfs_change();
+        } else {
+            self.spawn_update_diagnostics();
+        }
+        if self.config.diagnostics_eval_enable {
+            self.spawn_eval_diagnostics(uri);
+        }
 
         ControlFlow::Continue(())
     }
@@ -366,50 +746,45 @@ impl Server {
 
     fn on_did_change_watched_files(&mut self, params: DidChangeWatchedFilesParams) -> NotifyResult {
         tracing::debug!("Watched files changed: {params:?}");
+        self.eval_completion_cache.lock().unwrap().clear();
 
         let mut flake_files_changed = false;
+        let mut paths_changed = false;
+        let mut vfs_changed = false;
         for &FileEvent { ref uri, mut typ } in &params.changes {
+            let Ok(path) = uri.to_file_path() else {
+                continue;
+            };
+            if typ != FileChangeType::CHANGED {
+                self.path_cache.invalidate(&path);
+                paths_changed = true;
+            }
             // Don't reload files maintained by the client.
             if self.opened_files.contains_key(uri) {
                 continue;
             }
-            let Ok(path) = uri.to_file_path() else {
-                continue;
+            let relative = {
+                let vfs = self.vfs.read().unwrap();
+                vfs.root_for_path(&path)
+                    .and_then(|root| path.strip_prefix(root).ok())
+                    .map(Path::to_owned)
             };
+            // Other files are watched only for their existence.
+            let is_flake_lock = relative.as_deref() == Some(Path::new(FLAKE_LOCK_FILE));
+            if !is_flake_lock && path.extension().map_or(true, |ext| ext != "nix") {
+                continue;
+            }
 
             if matches!(typ, FileChangeType::CREATED | FileChangeType::CHANGED) {
-                match (|| -> std::io::Result<_> {
-                    #[cfg(unix)]
-                    use rustix::fs::{fcntl_getfl, fcntl_setfl, OFlags, OpenOptionsExt};
-
-                    // Rule out non-regular files which may block `open()` infinitely
-                    // (eg. FIFO). We open it with `O_NONBLOCK` and check it before reading.
-                    let mut options = std::fs::File::options();
-                    options.read(true);
-                    #[cfg(unix)]
-                    options.custom_flags(OFlags::NONBLOCK.bits() as _);
-
-                    let mut file = options.open(&path)?;
-                    let ft = file.metadata()?.file_type();
-                    if !ft.is_file() {
-                        return Err(std::io::Error::new(
-                            std::io::ErrorKind::Other,
-                            format!("non-regular file type: {ft:?}"),
-                        ));
-                    }
-
-                    // Remove the O_NONBLOCK flag for blocking read.
-                    #[cfg(unix)]
-                    {
-                        let flags = fcntl_getfl(&file)? - OFlags::NONBLOCK;
-                        fcntl_setfl(&file, flags)?;
+                match self
+                    .file_source
+                    .read(uri, || indexer::read_regular_file(&path))
+                {
+                    Ok(text) => {
+                        let vpath = uri.to_vfs_path();
+                        self.vfs.write().unwrap().set_path_content(vpath, text);
+                        vfs_changed = true;
                     }
-
-                    let mut buf = String::new();
-                    file.read_to_string(&mut buf)?;
-                    Ok(buf)
-                })() {
-                    Ok(text) => self.set_vfs_file_content(uri, text),
                     Err(err) if matches!(err.kind(), ErrorKind::NotFound) => {
                         // File gets removed at the time calling `open()`.
                         typ = FileChangeType::DELETED;
@@ -417,17 +792,23 @@ impl Server {
                     Err(err) => tracing::error!("Ignore file {path:?}: {err}"),
                 }
             }
-            if typ == FileChangeType::DELETED {
-                let _: Result<_> = self.vfs.write().unwrap().remove_uri(uri);
+            if typ == FileChangeType::DELETED && self.vfs.write().unwrap().remove_uri(uri).is_ok() {
+                vfs_changed = true;
             }
 
-            if let Ok(relative) = path.strip_prefix(&self.config.root_path) {
+            if let Some(relative) = &relative {
                 if relative == Path::new(FLAKE_FILE) || relative == Path::new(FLAKE_LOCK_FILE) {
                     flake_files_changed = true;
                 }
             }
         }
 
+        // Apply all changes at once, since many files may change together.
+        if vfs_changed {
+            self.apply_vfs_change();
+        } else if paths_changed {
+            self.spawn_update_diagnostics();
+        }
         if flake_files_changed {
             self.spawn_load_flake_workspace();
         }
@@ -435,73 +816,705 @@ impl Server {
         ControlFlow::Continue(())
     }
 
-    fn on_reload_flake(&mut self, (): ()) -> NotifyResult {
+    fn on_did_change_workspace_folders(
+        &mut self,
+        params: DidChangeWorkspaceFoldersParams,
+    ) -> NotifyResult {
+        let to_paths = |folders: Vec<WorkspaceFolder>| {
+            folders
+                .into_iter()
+                .filter_map(|folder| folder.uri.to_file_path().ok())
+                .collect::<Vec<_>>()
+        };
+        let (added, removed) = (to_paths(params.event.added), to_paths(params.event.removed));
+        tracing::info!("Workspace folders changed, added: {added:?}, removed: {removed:?}");
+
+        let mut roots = self.vfs.read().unwrap().roots().to_vec();
+        let old_roots = roots.clone();
+        // The primary root is kept, since settings are relative to it.
+        roots.retain(|root| *root == self.config.root_path || !removed.contains(root));
+        for path in added {
+            if !roots.contains(&path) {
+                roots.push(path);
+            }
+        }
+        if roots == old_roots {
+            return ControlFlow::Continue(());
+        }
+        let added = roots
+            .iter()
+            .filter(|root| !old_roots.contains(root))
+            .cloned()
+            .collect::<Vec<_>>();
+        let removed = old_roots
+            .iter()
+            .filter(|root| !roots.contains(root))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for root in &removed {
+            self.flake_roots.remove(root);
+        }
+        self.vfs.write().unwrap().set_roots(roots);
+        self.apply_vfs_change();
         self.spawn_load_flake_workspace();
+        if !added.is_empty() {
+            self.spawn_index_workspace();
+        }
+
+        if self.capabilities.watch_files {
+            let caps = self.capabilities.clone();
+            let mut client = self.client.clone();
+            tokio::spawn(async move {
+                if !removed.is_empty() {
+                    let params = UnregistrationParams {
+                        unregisterations: removed
+                            .iter()
+                            .map(|root| Unregistration {
+                                id: watcher_registration_id(root),
+                                method: notif::DidChangeWatchedFiles::METHOD.into(),
+                            })
+                            .collect(),
+                    };
+                    if let Err(err) = client.unregister_capability(params).await {
+                        tracing::error!("Failed to unwatch removed folders: {err:#}");
+                    }
+                }
+                if !added.is_empty() {
+                    Self::register_watched_files(&added, &caps, &mut client).await;
+                    let _: Result<_, _> = client.emit(flake_files_created_event(&added));
+                }
+            });
+        } else if !added.is_empty() {
+            self.on_did_change_watched_files(flake_files_created_event(&added))?;
+        }
+
         ControlFlow::Continue(())
     }
 
-    /// Spawn a task to (re)load the flake workspace via `flake.{nix,lock}`, including flake info,
-    /// NixOS options and outputs (TODO).
-    fn spawn_load_flake_workspace(&mut self) {
-        let fut = task::spawn(Self::load_flake_workspace(
-            self.vfs.clone(),
-            self.config.clone(),
-            self.capabilities.clone(),
-            self.client.clone(),
-        ));
-        if let Some(prev_fut) = self.load_flake_workspace_fut.replace(fut) {
-            prev_fut.abort();
+    fn on_workspace_diagnostic(
+        &mut self,
+        params: lsp_ext::WorkspaceDiagnosticParams,
+    ) -> impl Future<Output = Result<lsp_ext::WorkspaceDiagnosticReport, ResponseError>> {
+        // The socket is only used to send notifications, which is not affected by panics.
+        let client = AssertUnwindSafe(self.client.clone());
+        let task = self.spawn_with_snapshot(move |snap| {
+            with_catch_unwind("workspace_diagnostic", move || {
+                // Stream reports through `$/progress` if the client asks for partial results.
+                let token = params.partial_result_params.partial_result_token.clone();
+                let mut send_partial = |value| {
+                    if let Some(token) = &token {
+                        let _: Result<_, _> = client
+                            .notify::<lsp_ext::WorkspaceDiagnosticProgress>(
+                                lsp_ext::WorkspaceDiagnosticProgressParams {
+                                    token: token.clone(),
+                                    value,
+                                },
+                            );
+                    }
+                };
+                let partial = token
+                    .is_some()
+                    .then_some(&mut send_partial as &mut dyn FnMut(_));
+                handler::workspace_diagnostic(snap, params, partial)
+            })
+        });
+        async move {
+            task.await
+                .expect("Already catch_unwind")
+                .map_err(error_to_response)
         }
     }
 
-    async fn load_flake_workspace(
-        vfs: Arc<RwLock<Vfs>>,
-        config: Arc<Config>,
-        caps: NegotiatedCapabilities,
-        mut client: ClientSocket,
-    ) {
-        // Delay the loading to debounce. Later triggers will cancel previous tasks at here.
-        tokio::time::sleep(LOAD_FLAKE_WORKSPACE_DEBOUNCE_DURATION).await;
+    fn on_goto_definition(
+        &mut self,
+        params: GotoDefinitionParams,
+    ) -> BoxFuture<'static, Result<Option<GotoDefinitionResponse>, ResponseError>> {
+        let task = self.spawn_snap_handler(
+            req::GotoDefinition::METHOD,
+            handler::goto_definition,
+            params,
+        );
+        let nix_binary = self.config.nix_binary.clone();
+        let cache = self.attr_pos_cache.clone();
+        async move {
+            let query = match task.await? {
+                GotoDefinitionReply::Response(resp) => return Ok(resp),
+                GotoDefinitionReply::AttrPos(query) => query,
+            };
+            let cached = cache.lock().unwrap().get(&query).cloned();
+            let pos = match cached {
+                Some(pos) => pos,
+                None => {
+                    let ret = match &query {
+                        AttrPosQuery::Flake(flake_path, attrpath) => {
+                            installable::attr_pos(&nix_binary, flake_path, attrpath).await
+                        }
+                        AttrPosQuery::Nixpkgs(nixpkgs_path, name) => {
+                            package_index::package_pos(&nix_binary, nixpkgs_path, name).await
+                        }
+                    };
+                    match ret {
+                        Ok(pos) => {
+                            cache.lock().unwrap().insert(query, pos.clone());
+                            pos
+                        }
+                        Err(err) => {
+                            tracing::warn!("Failed to locate {query:?}: {err:#}");
+                            None
+                        }
+                    }
+                }
+            };
+            Ok(pos.and_then(|pos| {
+                let uri = Url::from_file_path(&pos.file).ok()?;
+                let pos = Position::new(pos.line.saturating_sub(1), pos.column.saturating_sub(1));
+                Some(GotoDefinitionResponse::Scalar(Location::new(
+                    uri,
+                    Range::new(pos, pos),
+                )))
+            }))
+        }
+        .boxed()
+    }
 
-        tracing::info!("Loading flake workspace");
+    fn on_hover(
+        &mut self,
+        params: HoverParams,
+    ) -> BoxFuture<'static, Result<Option<Hover>, ResponseError>> {
+        let task = self.spawn_snap_handler(req::HoverRequest::METHOD, handler::hover, params);
+        let nix_binary = self.config.nix_binary.clone();
+        let cache = self.store_path_cache.clone();
+        async move {
+            let (mut hover, path) = match task.await? {
+                HoverReply::Response(resp) => return Ok(resp),
+                HoverReply::StorePath(hover, path) => (hover, path),
+            };
+            let cached = cache.lock().unwrap().get(&path).cloned();
+            let info = match cached {
+                Some(info) => Some(info),
+                None => {
+                    let query = store_path::path_info(&nix_binary, &path);
+                    match tokio::time::timeout(STORE_PATH_INFO_TIMEOUT, query).await {
+                        Ok(Ok(info)) => {
+                            if let Some(info) = &info {
+                                cache.lock().unwrap().insert(path, info.clone());
+                            }
+                            info
+                        }
+                        Ok(Err(err)) => {
+                            tracing::warn!("Failed to query {path:?}: {err:#}");
+                            return Ok(Some(hover));
+                        }
+                        Err(_) => {
+                            tracing::warn!(
+                                "Querying {path:?} timed out after {}s",
+                                STORE_PATH_INFO_TIMEOUT.as_secs(),
+                            );
+                            return Ok(Some(hover));
+                        }
+                    }
+                }
+            };
+            if let HoverContents::Markup(content) = &mut hover.contents {
+                content.value += "\n\n";
+                content.value += &handler::store_path_info_markup(info.as_ref());
+            }
+            Ok(Some(hover))
+        }
+        .boxed()
+    }
 
-        let flake_info = match Self::load_flake_info(&vfs, &config).await {
-            Ok(ret) => {
-                let _: Result<_, _> = client.emit(SetFlakeInfoEvent(ret.clone()));
-                ret
+    fn on_completion(
+        &mut self,
+        params: CompletionParams,
+    ) -> BoxFuture<'static, Result<Option<CompletionResponse>, ResponseError>> {
+        let task = self.spawn_snap_handler(req::Completion::METHOD, handler::completion, params);
+        let config = self.config.clone();
+        let cache = self.eval_completion_cache.clone();
+        async move {
+            let (query, pending) = match task.await? {
+                CompletionReply::Response(resp) => return Ok(resp),
+                CompletionReply::Eval(query, pending) => (query, pending),
+            };
+            let Some(base_dir) = query.file.as_path().and_then(Path::parent) else {
+                return Ok(Some(pending.finish(&query, &[])));
+            };
+            let key = (base_dir.to_owned(), query.expr.clone());
+            let cached = cache.lock().unwrap().get(&key).cloned();
+            let names = match cached {
+                Some(names) => names,
+                None => {
+                    let opts = EvalOptions {
+                        base_dir: base_dir.to_owned(),
+                        search_path: config.search_path().entries().to_vec(),
+                        restrict_eval: config.completion_eval_restrict_eval,
+                        allowed_paths: vec![config.root_path.clone(), base_dir.to_owned()],
+                        memory_limit: config.nix_max_memory(),
+                    };
+                    let timeout = Duration::from_millis(config.completion_eval_timeout_ms);
+                    let fut = eval::eval_attr_names(&config.nix_binary, &query.expr, &opts);
+                    let names = match tokio::time::timeout(timeout, fut).await {
+                        Ok(Ok(names)) => names,
+                        Ok(Err(err)) => {
+                            tracing::warn!("Failed to evaluate for completion: {err:#}");
+                            Vec::new()
+                        }
+                        Err(_) => {
+                            tracing::warn!("Evaluation timed out: {}", query.expr);
+                            Vec::new()
+                        }
+                    };
+                    // Failures are also cached, to not evaluate again on every keystroke.
+                    let names = Arc::<[String]>::from(names);
+                    cache.lock().unwrap().insert(key, names.clone());
+                    names
+                }
+            };
+            Ok(Some(pending.finish(&query, &names)))
+        }
+        .boxed()
+    }
+
+    fn on_execute_command(
+        &mut self,
+        params: ExecuteCommandParams,
+    ) -> BoxFuture<'static, Result<Option<serde_json::Value>, ResponseError>> {
+        let ExecuteCommandParams {
+            command, arguments, ..
+        } = params;
+        match &*command {
+            lsp_ext::APPLY_FIX_COMMAND => {
+                let (params,) = match parse_command_args(&command, arguments) {
+                    Ok(args) => args,
+                    Err(err) => return ready(Err(err)).boxed(),
+                };
+                let task =
+                    self.spawn_snap_handler(lsp_ext::APPLY_FIX_COMMAND, handler::apply_fix, params);
+                self.apply_edit_of(task)
             }
-            Err(err) => {
-                client.show_message_ext(
-                    MessageType::ERROR,
-                    format!("Failed to load flake workspace: {err:#}"),
+            lsp_ext::SSR_COMMAND => {
+                let (rule,) = match parse_command_args(&command, arguments) {
+                    Ok(args) => args,
+                    Err(err) => return ready(Err(err)).boxed(),
+                };
+                let task = self.spawn_snap_handler(lsp_ext::SSR_COMMAND, handler::ssr, rule);
+                self.apply_edit_of(task)
+            }
+            lsp_ext::PREFETCH_HASH_COMMAND => {
+                let (uri, position) = match parse_command_args(&command, arguments) {
+                    Ok(args) => args,
+                    Err(err) => return ready(Err(err)).boxed(),
+                };
+                self.prefetch_hash(uri, position)
+            }
+            lsp_ext::REPL_EVAL_COMMAND => {
+                let (uri, range) = match parse_command_args(&command, arguments) {
+                    Ok(args) => args,
+                    Err(err) => return ready(Err(err)).boxed(),
+                };
+                let params = lsp_ext::ReplEvalParams {
+                    text_document: TextDocumentIdentifier::new(uri),
+                    range,
+                };
+                let task = self.on_repl_eval(params);
+                async move { Ok(Some(serde_json::to_value(task.await?).unwrap())) }.boxed()
+            }
+            lsp_ext::SHOW_REFERENCES_AT_COMMAND => {
+                let (uri, position) = match parse_command_args(&command, arguments) {
+                    Ok(args) => args,
+                    Err(err) => return ready(Err(err)).boxed(),
+                };
+                let params = ReferenceParams {
+                    text_document_position: TextDocumentPositionParams::new(
+                        TextDocumentIdentifier::new(uri),
+                        position,
+                    ),
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                    context: ReferenceContext {
+                        include_declaration: true,
+                    },
+                };
+                let task = self.spawn_snap_handler(
+                    lsp_ext::SHOW_REFERENCES_AT_COMMAND,
+                    handler::references,
+                    params,
                 );
-                return;
+                async move { Ok(Some(serde_json::to_value(task.await?).unwrap())) }.boxed()
             }
-        };
-        let Some(flake_info) = flake_info else { return };
+            lsp_ext::COLLECT_GARBAGE_COMMAND => {
+                self.host.collect_garbage();
+                ready(Ok(None)).boxed()
+            }
+            lsp_ext::RELOAD_FLAKE_COMMAND => {
+                self.spawn_load_flake_workspace();
+                ready(Ok(None)).boxed()
+            }
+            lsp_ext::RELOAD_WORKSPACE_COMMAND => {
+                self.reload_workspace();
+                ready(Ok(None)).boxed()
+            }
+            lsp_ext::DOCTOR_COMMAND => {
+                let config = self.config.clone();
+                let caps = self.capabilities.clone();
+                let mut client = self.client.clone();
+                async move {
+                    let report = task::spawn_blocking(move || {
+                        let mut report = doctor::check_config(&config, &[]);
+                        doctor::check_client(&mut report, &caps);
+                        report
+                    })
+                    .await
+                    .map_err(|err| {
+                        ResponseError::new(ErrorCode::INTERNAL_ERROR, format!("{err}"))
+                    })?;
+                    let typ = match report.status() {
+                        doctor::Status::Ok => MessageType::INFO,
+                        doctor::Status::Warning => MessageType::WARNING,
+                        doctor::Status::Error => MessageType::ERROR,
+                    };
+                    let text = report.to_string();
+                    client.show_message_ext(typ, &text);
+                    Ok(Some(text.into()))
+                }
+                .boxed()
+            }
+            lsp_ext::EVAL_FLAKE_OUTPUT_COMMAND | lsp_ext::BUILD_FLAKE_OUTPUT_COMMAND => {
+                let is_build = command == lsp_ext::BUILD_FLAKE_OUTPUT_COMMAND;
+                let ret = parse_command_args(&command, arguments)
+                    .and_then(|(attrpath,)| self.execute_flake_output(attrpath, is_build));
+                ready(ret).boxed()
+            }
+            _ => ready(Err(ResponseError::new(
+                ErrorCode::INVALID_PARAMS,
+                format!("Unknown command: {command}"),
+            )))
+            .boxed(),
+        }
+    }
 
-        let missing_paths = || {
-            flake_info
-                .input_store_paths
-                .iter()
-                .filter(|(_, path)| !path.as_path().expect("Must be real paths").exists())
-        };
+    /// Apply the labeled edit computed by `task` via `workspace/applyEdit`, and respond whether
+    /// it is applied. Respond `false` if there is no edit.
+    fn apply_edit_of(
+        &self,
+        task: impl Future<Output = Result<Option<(String, WorkspaceEdit)>, ResponseError>>
+            + Send
+            + 'static,
+    ) -> BoxFuture<'static, Result<Option<serde_json::Value>, ResponseError>> {
+        let mut client = self.client.clone();
+        async move {
+            let Some((label, edit)) = task.await? else {
+                return Ok(Some(false.into()));
+            };
+            let resp = client
+                .apply_edit(ApplyWorkspaceEditParams {
+                    label: Some(label),
+                    edit,
+                })
+                .await
+                .map_err(|err| {
+                    ResponseError::new(
+                        ErrorCode::INTERNAL_ERROR,
+                        format!("Failed to apply edit: {err}"),
+                    )
+                })?;
+            Ok(Some(resp.applied.into()))
+        }
+        .boxed()
+    }
 
-        if missing_paths().next().is_some() {
-            tracing::debug!(
-                "Missing flake inputs: {:?}",
-                missing_paths().collect::<Vec<_>>()
-            );
+    /// Prefetch the source of the fetcher call at `pos` with a placeholder hash, and replace the
+    /// placeholder with the hash. Fetching may take long, thus the progress is reported, and the
+    /// edit is versioned to be rejected if the document is changed in the meantime.
+    fn prefetch_hash(
+        &self,
+        uri: Url,
+        pos: Position,
+    ) -> BoxFuture<'static, Result<Option<serde_json::Value>, ResponseError>> {
+        let task = self.spawn_snap_handler(
+            lsp_ext::PREFETCH_HASH_COMMAND,
+            handler::hash_placeholder,
+            (uri.clone(), pos),
+        );
+        let nix_binary = self.config.nix_binary.clone();
+        let client = self.client.clone();
+        let caps = self.capabilities.clone();
+        self.apply_edit_of(async move {
+            let Some((version, range, source)) = task.await? else {
+                return Ok(None);
+            };
+            let token = format!("{PREFETCH_HASH_PROGRESS_TOKEN}/{source}");
+            let progress = Progress::new(
+                &client,
+                &caps,
+                token,
+                "Prefetching",
+                Some(source.to_string()),
+            )
+            .await;
+            let failed = |msg: String| ResponseError::new(ErrorCode::REQUEST_FAILED, msg);
+            let fetch = prefetch::prefetch_hash(&nix_binary, &source);
+            let hash = match tokio::time::timeout(PREFETCH_HASH_TIMEOUT, fetch).await {
+                Ok(Ok(hash)) => hash,
+                Ok(Err(err)) => {
+                    return Err(failed(format!("Failed to prefetch {source}: {err:#}")))
+                }
+                Err(_) => {
+                    return Err(failed(format!(
+                        "Prefetching {source} timed out after {}s",
+                        PREFETCH_HASH_TIMEOUT.as_secs()
+                    )))
+                }
+            };
+            progress.done(Some(hash.clone()));
+            let edit = lsp_types::TextEdit::new(range, format!("\"{hash}\""));
+            let edit = convert::to_file_workspace_edit(&caps, uri, version, vec![edit]);
+            Ok(Some(("Insert the prefetched hash".into(), edit)))
+        })
+    }
 
-            let do_fetch = match config.nix_flake_auto_archive {
-                Some(do_fetch) => do_fetch,
-                None if caps.client_show_message_request => {
-                    let ret = client
-                        .show_message_request(ShowMessageRequestParams {
-                            typ: MessageType::INFO,
-                            message: "\
-                            Some flake inputs are not available. Fetch them now? \n\
-                            You can enable autoArchive in lsp configuration.\
+    /// Evaluate the selected expression in the managed `nix repl` process, which is spawned on
+    /// the first use, and respawned if the directory or settings change, or the last evaluation
+    /// failed or timed out.
+    fn on_repl_eval(
+        &mut self,
+        params: lsp_ext::ReplEvalParams,
+    ) -> BoxFuture<'static, Result<lsp_ext::ReplEvalResult, ResponseError>> {
+        let task = self.spawn_snap_handler(lsp_ext::ReplEval::METHOD, handler::repl_expr, params);
+        let config = self.config.clone();
+        let repl = self.repl.clone();
+        async move {
+            let (expression, base_dir) = task.await?;
+            let opts = EvalOptions {
+                base_dir,
+                search_path: config.search_path().entries().to_vec(),
+                restrict_eval: false,
+                allowed_paths: Vec::new(),
+                memory_limit: config.nix_max_memory(),
+            };
+            let failed = |msg: String| ResponseError::new(ErrorCode::REQUEST_FAILED, msg);
+            let mut guard = repl.lock().await;
+            // Taken out during the evaluation, so that it is dropped if the request is cancelled.
+            let mut process = match guard.take() {
+                Some(process) if process.is_spawned_with(&config.nix_binary, &opts) => process,
+                _ => Repl::spawn(&config.nix_binary, &opts)
+                    .map_err(|err| failed(format!("{err:#}")))?,
+            };
+            let timeout = Duration::from_millis(config.repl_timeout_ms);
+            let output = match tokio::time::timeout(timeout, process.eval(&expression)).await {
+                Ok(Ok(output)) => output,
+                Ok(Err(err)) => return Err(failed(format!("Failed to evaluate: {err:#}"))),
+                Err(_) => {
+                    return Err(failed(format!(
+                        "Evaluation timed out after {}ms",
+                        config.repl_timeout_ms
+                    )))
+                }
+            };
+            *guard = Some(process);
+            Ok(lsp_ext::ReplEvalResult {
+                expression,
+                value: output.value,
+                messages: output.messages,
+            })
+        }
+        .boxed()
+    }
+
+    /// Evaluate or build a flake output of the workspace, reporting the result asynchronously.
+    fn execute_flake_output(
+        &mut self,
+        attrpath: String,
+        is_build: bool,
+    ) -> Result<Option<serde_json::Value>, ResponseError> {
+        if !self.flake_roots.contains(&self.config.root_path) {
+            return Err(ResponseError::new(
+                ErrorCode::INVALID_REQUEST,
+                "The workspace is not a flake",
+            ));
+        }
+
+        // The command may take a long time. Report the result asynchronously.
+        let config = self.config.clone();
+        let mut client = self.client.clone();
+        tokio::spawn(async move {
+            let flake_url = FlakeUrl::new_path(&config.root_path);
+            let ret = if is_build {
+                installable::build(&config.nix_binary, &flake_url, &attrpath)
+                    .await
+                    .map(|paths| paths.join("\n"))
+            } else {
+                let drv_attrpath = format!("{attrpath}.drvPath");
+                installable::eval_raw(&config.nix_binary, &flake_url, &drv_attrpath).await
+            };
+            match ret {
+                Ok(out) => {
+                    client.show_message_ext(MessageType::INFO, format_args!("{attrpath}: {out}"))
+                }
+                Err(err) => client.show_message_ext(
+                    MessageType::ERROR,
+                    format_args!(
+                        "Failed to {} {attrpath}: {err:#}",
+                        if is_build { "build" } else { "evaluate" }
+                    ),
+                ),
+            }
+        });
+        Ok(None)
+    }
+
+    fn on_reload_flake(&mut self, (): ()) -> NotifyResult {
+        self.spawn_load_flake_workspace();
+        ControlFlow::Continue(())
+    }
+
+    fn on_reload_workspace(&mut self, (): ()) -> NotifyResult {
+        self.reload_workspace();
+        ControlFlow::Continue(())
+    }
+
+    /// Reload everything derived from the environment without restarting, eg. after
+    /// `nix flake update` or switching the pinned nixpkgs. NixOS options, lib docs and the
+    /// package index are dropped first, so that stale ones are not used if the reloading fails. Persistent
+    /// caches of them are still used if the nixpkgs revision is unchanged.
+    fn reload_workspace(&mut self) {
+        tracing::info!("Reloading the workspace");
+        {
+            let mut vfs = self.vfs.write().unwrap();
+            for &set in OptionSet::ALL {
+                vfs.set_option_set(set, NixosOptions::default(), None);
+            }
+            vfs.set_lib_docs(LibDocs::default());
+            vfs.set_package_index(PackageIndex::default());
+        }
+        self.apply_vfs_change();
+        self.host.collect_garbage();
+        self.spawn_load_flake_workspace();
+        self.spawn_index_workspace();
+    }
+
+    /// Spawn a task to (re)load flakes of all workspace folders via `flake.{nix,lock}`,
+    /// including flake info, NixOS options and outputs (TODO).
+    fn spawn_load_flake_workspace(&mut self) {
+        let roots = self.vfs.read().unwrap().roots().to_vec();
+        let (vfs, config, caps, client, file_source) = (
+            self.vfs.clone(),
+            self.config.clone(),
+            self.capabilities.clone(),
+            self.client.clone(),
+            self.file_source.clone(),
+        );
+        let fut = task::spawn(async move {
+            // Delay the loading to debounce. Later triggers will cancel previous tasks at here.
+            tokio::time::sleep(LOAD_FLAKE_WORKSPACE_DEBOUNCE_DURATION).await;
+            // One by one, since progress tokens are shared.
+            let mut errors = Vec::new();
+            let mut loaded_sets = HashSet::new();
+            for root in roots {
+                errors.extend(
+                    Self::load_flake_workspace(
+                        &vfs,
+                        &config,
+                        &caps,
+                        client.clone(),
+                        &file_source,
+                        root,
+                        &mut loaded_sets,
+                    )
+                    .await,
+                );
+            }
+            let _: Result<_, _> = client.emit(TaskFinishedEvent(
+                BackgroundTask::LoadFlakeWorkspace,
+                errors,
+            ));
+        });
+        if let Some(prev_fut) = self.load_flake_workspace_fut.replace(fut) {
+            prev_fut.abort();
+        }
+        self.running_tasks
+            .insert(BackgroundTask::LoadFlakeWorkspace);
+        self.update_server_status();
+    }
+
+    /// Load the flake of the workspace folder `root`, and return errors which are shown.
+    /// NixOS options are only loaded for the primary root. home-manager and nix-darwin options
+    /// are loaded from the first root providing them, tracked in `loaded_sets`.
+    async fn load_flake_workspace(
+        vfs: &RwLock<Vfs>,
+        config: &Config,
+        caps: &NegotiatedCapabilities,
+        mut client: ClientSocket,
+        file_source: &FileSource,
+        root: PathBuf,
+        loaded_sets: &mut HashSet<OptionSet>,
+    ) -> Vec<String> {
+        tracing::info!("Loading flake workspace {}", root.display());
+        let is_primary = root == config.root_path;
+        let mut errors = Vec::new();
+
+        // Prebuilt options indices take place of the evaluation below.
+        for &set in OptionSet::ALL.iter().filter(|_| is_primary) {
+            if let Some(path) = config.options_file(set) {
+                errors.extend(
+                    Self::load_options_file(config, &mut client, file_source, set, path).await,
+                );
+                loaded_sets.insert(set);
+            }
+        }
+        let options_file = config.options_file(OptionSet::Nixos).filter(|_| is_primary);
+
+        let flake_info = match Self::load_flake_info(vfs, config, caps, &client, &root).await {
+            Ok(ret) => {
+                let _: Result<_, _> = client.emit(SetFlakeInfoEvent(root.clone(), ret.clone()));
+                ret
+            }
+            Err(err) => {
+                let msg = format!("Failed to load flake workspace: {err:#}");
+                client.show_message_ext(MessageType::ERROR, &msg);
+                errors.push(msg);
+                return errors;
+            }
+        };
+        let Some(flake_info) = flake_info else {
+            // NixOS options and packages can still be loaded for non-flake workspaces.
+            if let Some(path) = config.nix_nixpkgs_path.as_ref().filter(|_| is_primary) {
+                errors.extend(Self::load_lib_docs(config, &mut client, path).await);
+                if options_file.is_none() {
+                    errors.extend(
+                        Self::load_nixos_options(config, caps, &mut client, path, None).await,
+                    );
+                }
+                if config.nix_package_index_enable {
+                    errors.extend(Self::load_package_index(config, caps, &mut client, path).await);
+                }
+            }
+            return errors;
+        };
+
+        let missing_paths = || {
+            flake_info
+                .input_store_paths
+                .iter()
+                .filter(|(_, path)| !path.as_path().expect("Must be real paths").exists())
+        };
+
+        if missing_paths().next().is_some() {
+            tracing::debug!(
+                "Missing flake inputs: {:?}",
+                missing_paths().collect::<Vec<_>>()
+            );
+
+            let do_fetch = match config.nix_flake_auto_archive {
+                Some(do_fetch) => do_fetch,
+                None if caps.client_show_message_request => {
+                    let ret = client
+                        .show_message_request(ShowMessageRequestParams {
+                            typ: MessageType::INFO,
+                            message: "\
+                            Some flake inputs are not available. Fetch them now? \n\
+                            You can enable autoArchive in lsp configuration.\
                         "
                             .into(),
                             actions: Some(vec![
@@ -540,13 +1553,13 @@ impl Server {
                 tracing::info!("Archiving flake");
                 let progress = Progress::new(
                     &client,
-                    &caps,
+                    caps,
                     FLAKE_ARCHIVE_PROGRESS_TOKEN,
                     "Fetching flake with inputs",
                     "nix flake archive".to_owned(),
                 )
                 .await;
-                let flake_url = FlakeUrl::new_path(&config.root_path);
+                let flake_url = FlakeUrl::new_path(&root);
                 let ret = flake_lock::archive(&config.nix_binary, &flake_url)
                     .await
                     .and_then(|()| {
@@ -560,62 +1573,475 @@ impl Server {
                 progress.done(None);
 
                 if let Err(err) = ret {
-                    client.show_message_ext(
-                        MessageType::ERROR,
-                        format_args!("Failed to archiving flake: {err:#}"),
-                    );
+                    let msg = format!("Failed to archiving flake: {err:#}");
+                    client.show_message_ext(MessageType::ERROR, &msg);
+                    errors.push(msg);
                     // Fallthrough and load the rest if possible.
                 }
             }
         }
 
-        if let Some((input_name, nixpkgs_path)) = (|| {
-            let input_name = config.nix_flake_nixpkgs_input_name.as_ref()?;
-            let path = flake_info
-                .input_store_paths
-                .get(input_name)?
-                .as_path()
-                .filter(|p| p.exists())?;
-            Some((input_name, path))
-        })() {
-            tracing::info!("Evaluating NixOS options from {}", nixpkgs_path.display());
-
-            let _progress = Progress::new(
-                &client,
-                &caps,
-                LOAD_NIXOS_OPTIONS_PROGRESS_TOKEN,
-                format!("Loading NixOS options from '{input_name}'"),
-                None,
-            )
-            .await;
+        // An explicit `nix.nixpkgsPath` takes precedence over the flake input.
+        let nixpkgs = match &config.nix_nixpkgs_path {
+            Some(path) => Some((None, path.as_path())),
+            None => (|| {
+                let input_name = config.nix_flake_nixpkgs_input_name.as_ref()?;
+                let path = flake_info
+                    .input_store_paths
+                    .get(input_name)?
+                    .as_path()
+                    .filter(|p| p.exists())?;
+                Some((Some(&**input_name), path))
+            })(),
+        };
+        if let Some((input_name, nixpkgs_path)) = nixpkgs {
+            // Options share a progress token, so they are loaded one by one, but concurrently
+            // with lib docs and the package index.
+            let (mut options_client, mut docs_client, mut index_client) =
+                (client.clone(), client.clone(), client.clone());
+            let options = async {
+                let mut errors = Self::load_input_options(
+                    config,
+                    caps,
+                    &mut options_client,
+                    is_primary,
+                    &flake_info,
+                    nixpkgs_path,
+                    loaded_sets,
+                )
+                .await;
+                if is_primary && options_file.is_none() {
+                    errors.extend(
+                        Self::load_nixos_options(
+                            config,
+                            caps,
+                            &mut options_client,
+                            nixpkgs_path,
+                            input_name,
+                        )
+                        .await,
+                    );
+                }
+                errors
+            };
+            let lib_docs = async {
+                if !is_primary {
+                    return None;
+                }
+                Self::load_lib_docs(config, &mut docs_client, nixpkgs_path).await
+            };
+            let package_index = async {
+                if !is_primary || !config.nix_package_index_enable {
+                    return None;
+                }
+                Self::load_package_index(config, caps, &mut index_client, nixpkgs_path).await
+            };
+            let (options_errors, docs_error, index_error) =
+                futures::join!(options, lib_docs, package_index);
+            errors.extend(options_errors);
+            errors.extend(docs_error);
+            errors.extend(index_error);
+        }
+
+        if config.nix_flake_auto_eval_inputs {
+            errors.extend(
+                Self::load_input_flakes(flake_info, &root, config, caps, &mut client).await,
+            );
+        }
+        errors
+    }
+
+    /// Evaluate NixOS options from `nixpkgs_path`, which is the flake input `input_name` if any.
+    /// Returns the error which is shown, if any.
+    async fn load_nixos_options(
+        config: &Config,
+        caps: &NegotiatedCapabilities,
+        client: &mut ClientSocket,
+        nixpkgs_path: &Path,
+        input_name: Option<&str>,
+    ) -> Option<String> {
+        let root_config = Self::root_configuration(config, OptionSet::Nixos);
+
+        // Options of a configuration depend on the workspace, not only nixpkgs.
+        let cache = Self::index_cache(config, nixpkgs_path).filter(|_| root_config.is_none());
+        if let Some(opts) =
+            Self::load_cached_index::<NixosOptions>(cache.as_ref(), NIXOS_OPTIONS_CACHE).await
+        {
+            tracing::info!("Loaded NixOS options ({} top-level options)", opts.len());
+            let root = Some(nixpkgs_path.to_owned());
+            let _: Result<_, _> = client.emit(SetOptionSetEvent(OptionSet::Nixos, opts, root));
+            return None;
+        }
+
+        tracing::info!("Evaluating NixOS options from {}", nixpkgs_path.display());
 
-            let ret = nixos_options::eval_all_options(&config.nix_binary, nixpkgs_path)
+        let title = match root_config {
+            Some(name) => format!("Loading NixOS options of '{name}'"),
+            None => format!(
+                "Loading NixOS options from '{}'",
+                input_name.map_or_else(|| nixpkgs_path.display().to_string(), Into::into),
+            ),
+        };
+        let _progress =
+            Progress::new(client, caps, LOAD_NIXOS_OPTIONS_PROGRESS_TOKEN, title, None).await;
+
+        let ret = match root_config {
+            Some(name) => {
+                let flake_url = FlakeUrl::new_path(&config.root_path);
+                nixos_options::eval_flake_config_options(
+                    &config.nix_binary,
+                    nixpkgs_path,
+                    &flake_url,
+                    AnalysisRoot::configurations_output(OptionSet::Nixos),
+                    name,
+                )
                 .await
-                .context("Failed to evaluate NixOS options");
+            }
+            None => nixos_options::eval_all_options(&config.nix_binary, nixpkgs_path).await,
+        }
+        .context("Failed to evaluate NixOS options");
+        match ret {
+            // Sanity check.
+            Ok(opts) if !opts.is_empty() => {
+                tracing::info!("Loaded NixOS options ({} top-level options)", opts.len());
+                let opts =
+                    Self::store_cached_index(cache.as_ref(), NIXOS_OPTIONS_CACHE, opts).await;
+                let root = Some(nixpkgs_path.to_owned());
+                let _: Result<_, _> = client.emit(SetOptionSetEvent(OptionSet::Nixos, opts, root));
+            }
+            Ok(_) => tracing::error!("Empty NixOS options?"),
+            Err(err) => {
+                let msg = format!("{err:#}");
+                client.show_message_ext(MessageType::ERROR, &msg);
+                return Some(msg);
+            }
+        }
+        None
+    }
+
+    /// The configuration name of `set` selected by `analysis.root` of the primary root, like
+    /// `flake.nix#nixosConfigurations.<name>`.
+    fn root_configuration(config: &Config, set: OptionSet) -> Option<&str> {
+        config
+            .analysis_root
+            .as_ref()
+            .filter(|root| root.file == config.root_path.join(FLAKE_FILE))
+            .and_then(|root| root.configuration())
+            .and_then(|(root_set, name)| (root_set == set).then_some(name))
+    }
+
+    /// Evaluate home-manager and nix-darwin options from inputs of the flake `flake_info`, with
+    /// `pkgs` from `nixpkgs_path`, unless they are already in `loaded_sets`.
+    /// For the primary root, configurations selected by `analysis.root` are evaluated instead.
+    /// Returns errors which are shown.
+    async fn load_input_options(
+        config: &Config,
+        caps: &NegotiatedCapabilities,
+        client: &mut ClientSocket,
+        is_primary: bool,
+        flake_info: &FlakeInfo,
+        nixpkgs_path: &Path,
+        loaded_sets: &mut HashSet<OptionSet>,
+    ) -> Vec<String> {
+        let mut errors = Vec::new();
+        for set in [OptionSet::HomeManager, OptionSet::NixDarwin] {
+            if loaded_sets.contains(&set) {
+                continue;
+            }
+            let root_config = Self::root_configuration(config, set).filter(|_| is_primary);
+            let input = config.options_input_name(set).and_then(|name| {
+                let path = flake_info
+                    .input_store_paths
+                    .get(name)?
+                    .as_path()
+                    .filter(|p| p.exists())?;
+                Some((name, path))
+            });
+            let title = match (root_config, input) {
+                (Some(name), _) => {
+                    tracing::info!("Evaluating {} options of {name}", set.title());
+                    format!("Loading {} options of '{name}'", set.title())
+                }
+                (None, Some((input_name, input_path))) => {
+                    tracing::info!(
+                        "Evaluating {} options from {}",
+                        set.title(),
+                        input_path.display(),
+                    );
+                    format!("Loading {} options from '{input_name}'", set.title())
+                }
+                (None, None) => continue,
+            };
+            loaded_sets.insert(set);
+
+            // Options of a configuration depend on the workspace, not only the inputs.
+            let cache = match (root_config, input) {
+                (None, Some((_, input_path))) => {
+                    Self::input_index_cache(config, nixpkgs_path, input_path)
+                }
+                _ => None,
+            };
+            let cache_kind = match set {
+                OptionSet::HomeManager => HOME_MANAGER_OPTIONS_CACHE,
+                OptionSet::NixDarwin => DARWIN_OPTIONS_CACHE,
+                OptionSet::Nixos => unreachable!(),
+            };
+            if let Some(opts) =
+                Self::load_cached_index::<NixosOptions>(cache.as_ref(), cache_kind).await
+            {
+                tracing::info!(
+                    "Loaded {} options ({} top-level options)",
+                    set.title(),
+                    opts.len(),
+                );
+                let root = input.map(|(_, path)| path.to_owned());
+                let _: Result<_, _> = client.emit(SetOptionSetEvent(set, opts, root));
+                continue;
+            }
+
+            let _progress =
+                Progress::new(client, caps, LOAD_NIXOS_OPTIONS_PROGRESS_TOKEN, title, None).await;
+            let (ret, root) = match (root_config, input) {
+                (Some(name), _) => {
+                    let ret = nixos_options::eval_flake_config_options(
+                        &config.nix_binary,
+                        nixpkgs_path,
+                        &FlakeUrl::new_path(&config.root_path),
+                        AnalysisRoot::configurations_output(set),
+                        name,
+                    )
+                    .await;
+                    (ret, config.root_path.clone())
+                }
+                (None, Some((_, input_path))) => {
+                    let ret = match set {
+                        OptionSet::HomeManager => {
+                            nixos_options::eval_home_manager_options(
+                                &config.nix_binary,
+                                nixpkgs_path,
+                                input_path,
+                            )
+                            .await
+                        }
+                        OptionSet::NixDarwin => {
+                            nixos_options::eval_darwin_options(
+                                &config.nix_binary,
+                                nixpkgs_path,
+                                input_path,
+                            )
+                            .await
+                        }
+                        OptionSet::Nixos => unreachable!(),
+                    };
+                    (ret, input_path.to_owned())
+                }
+                (None, None) => unreachable!(),
+            };
+            let ret = ret.with_context(|| format!("Failed to evaluate {} options", set.title()));
             match ret {
-                // Sanity check.
-                Ok(opts) if !opts.is_empty() => {
-                    tracing::info!("Loaded NixOS options ({} top-level options)", opts.len());
-                    let _: Result<_, _> = client.emit(SetNixosOptionsEvent(opts));
+                Ok(opts) => {
+                    tracing::info!(
+                        "Loaded {} options ({} top-level options)",
+                        set.title(),
+                        opts.len(),
+                    );
+                    let opts = Self::store_cached_index(cache.as_ref(), cache_kind, opts).await;
+                    let _: Result<_, _> = client.emit(SetOptionSetEvent(set, opts, Some(root)));
                 }
-                Ok(_) => tracing::error!("Empty NixOS options?"),
                 Err(err) => {
-                    client.show_message_ext(MessageType::ERROR, format_args!("{err:#}"));
+                    let msg = format!("{err:#}");
+                    client.show_message_ext(MessageType::ERROR, &msg);
+                    errors.push(msg);
                 }
             }
         }
+        errors
+    }
 
-        if config.nix_flake_auto_eval_inputs {
-            Self::load_input_flakes(flake_info, &config, &caps, &mut client).await;
+    /// Evaluate the index of top-level packages of nixpkgs at `nixpkgs_path`, for completion.
+    /// Returns the error which is shown, if any.
+    async fn load_package_index(
+        config: &Config,
+        caps: &NegotiatedCapabilities,
+        client: &mut ClientSocket,
+        nixpkgs_path: &Path,
+    ) -> Option<String> {
+        let cache = Self::index_cache(config, nixpkgs_path);
+        if let Some(index) =
+            Self::load_cached_index::<PackageIndex>(cache.as_ref(), PACKAGE_INDEX_CACHE).await
+        {
+            tracing::info!("Loaded package index ({} packages)", index.len());
+            let _: Result<_, _> = client.emit(SetPackageIndexEvent(index));
+            return None;
+        }
+
+        tracing::info!("Indexing packages from {}", nixpkgs_path.display());
+        let _progress = Progress::new(
+            client,
+            caps,
+            LOAD_PACKAGE_INDEX_PROGRESS_TOKEN,
+            "Indexing nixpkgs packages",
+            None,
+        )
+        .await;
+
+        let ret = package_index::eval_package_index(&config.nix_binary, nixpkgs_path)
+            .await
+            .context("Failed to index nixpkgs packages");
+        match ret {
+            Ok(index) => {
+                tracing::info!("Loaded package index ({} packages)", index.len());
+                let index =
+                    Self::store_cached_index(cache.as_ref(), PACKAGE_INDEX_CACHE, index).await;
+                let _: Result<_, _> = client.emit(SetPackageIndexEvent(index));
+                None
+            }
+            Err(err) => {
+                let msg = format!("{err:#}");
+                client.show_message_ext(MessageType::ERROR, &msg);
+                Some(msg)
+            }
         }
     }
 
+    /// Extract documentation of `lib` functions from sources of nixpkgs at `nixpkgs_path`.
+    /// Returns the error, if any.
+    async fn load_lib_docs(
+        config: &Config,
+        client: &mut ClientSocket,
+        nixpkgs_path: &Path,
+    ) -> Option<String> {
+        let cache = Self::index_cache(config, nixpkgs_path);
+        if let Some(docs) = Self::load_cached_index::<LibDocs>(cache.as_ref(), LIB_DOCS_CACHE).await
+        {
+            let _: Result<_, _> = client.emit(SetLibDocsEvent(docs));
+            return None;
+        }
+
+        let nixpkgs_path = nixpkgs_path.to_owned();
+        let ret = tokio::task::spawn_blocking(move || LibDocs::load(&nixpkgs_path))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|ret| ret)
+            .context("Failed to load documentation of nixpkgs lib");
+        match ret {
+            Ok(docs) => {
+                let docs = Self::store_cached_index(cache.as_ref(), LIB_DOCS_CACHE, docs).await;
+                let _: Result<_, _> = client.emit(SetLibDocsEvent(docs));
+                None
+            }
+            Err(err) => {
+                let msg = format!("{err:#}");
+                tracing::warn!("{msg}");
+                Some(msg)
+            }
+        }
+    }
+
+    /// The persistent cache of indexes of nixpkgs at `nixpkgs_path`, with its revision.
+    /// It is `None` if disabled, or the revision is unknown.
+    fn index_cache(config: &Config, nixpkgs_path: &Path) -> Option<(IndexCache, String)> {
+        if !config.nix_index_cache_enable {
+            return None;
+        }
+        let rev = index_cache::nixpkgs_revision(nixpkgs_path)?;
+        Some((IndexCache::from_env()?, rev))
+    }
+
+    /// The persistent cache of indexes of the flake input at `input_path` with `pkgs` from
+    /// `nixpkgs_path`, with both revisions. It is `None` if disabled, or any revision is unknown.
+    fn input_index_cache(
+        config: &Config,
+        nixpkgs_path: &Path,
+        input_path: &Path,
+    ) -> Option<(IndexCache, String)> {
+        let (cache, nixpkgs_rev) = Self::index_cache(config, nixpkgs_path)?;
+        let input_rev = index_cache::nixpkgs_revision(input_path)?;
+        Some((cache, format!("{nixpkgs_rev}-{input_rev}")))
+    }
+
+    async fn load_cached_index<T: DeserializeOwned + Send + 'static>(
+        cache: Option<&(IndexCache, String)>,
+        kind: &'static str,
+    ) -> Option<T> {
+        let (cache, rev) = cache?.clone();
+        tracing::info!("Loading {kind} of nixpkgs {rev} from cache");
+        tokio::task::spawn_blocking(move || cache.load(kind, &rev))
+            .await
+            .ok()?
+    }
+
+    /// Store the index `data` into the cache, if any, then give it back.
+    async fn store_cached_index<T: Serialize + Send + 'static>(
+        cache: Option<&(IndexCache, String)>,
+        kind: &'static str,
+        data: T,
+    ) -> T {
+        let Some((cache, rev)) = cache.cloned() else {
+            return data;
+        };
+        tokio::task::spawn_blocking(move || {
+            if let Err(err) = cache.store(kind, &rev, &data) {
+                tracing::warn!("Failed to cache {kind}: {err:#}");
+            }
+            data
+        })
+        .await
+        .expect("Serialization should not panic")
+    }
+
+    /// Load options of `set` from the prebuilt `options.json` at `path`. Its relative
+    /// declarations are relative to the source of the set in the search path, eg. `<nixpkgs>`.
+    /// Returns the error which is shown, if any.
+    async fn load_options_file(
+        config: &Config,
+        client: &mut ClientSocket,
+        file_source: &FileSource,
+        set: OptionSet,
+        path: &Path,
+    ) -> Option<String> {
+        tracing::info!("Loading {} options from {}", set.title(), path.display());
+        let (path, file_source) = (path.to_owned(), file_source.clone());
+        let ret = tokio::task::spawn_blocking(move || {
+            let src = file_source.read_path(&path)?;
+            nixos_options::from_options_json(&src)
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|ret| ret)
+        .with_context(|| format!("Failed to load `nix.{}.file`", Config::options_key(set)));
+        match ret {
+            Ok(opts) => {
+                tracing::info!(
+                    "Loaded {} options ({} top-level options)",
+                    set.title(),
+                    opts.len(),
+                );
+                let name = match set {
+                    OptionSet::Nixos => "nixpkgs",
+                    OptionSet::HomeManager => "home-manager",
+                    OptionSet::NixDarwin => "darwin",
+                };
+                let root = config.search_path().resolve(name, Path::exists);
+                let _: Result<_, _> = client.emit(SetOptionSetEvent(set, opts, root));
+                None
+            }
+            Err(err) => {
+                let msg = format!("{err:#}");
+                client.show_message_ext(MessageType::ERROR, &msg);
+                Some(msg)
+            }
+        }
+    }
+
+    /// Evaluate outputs of flake inputs. Returns the summary of errors, if any.
     async fn load_input_flakes(
         mut flake_info: FlakeInfo,
+        root: &Path,
         config: &Config,
         caps: &NegotiatedCapabilities,
         client: &mut ClientSocket,
-    ) {
+    ) -> Option<String> {
         // Filter out missing paths.
         let mut input_paths = flake_info
             .input_store_paths
@@ -629,7 +2055,7 @@ impl Server {
 
         // Fast path.
         if input_paths.is_empty() {
-            return;
+            return None;
         }
 
         // Sort by input names to keep evaluation order stable.
@@ -688,164 +2114,741 @@ impl Server {
                     Ok(ret) => break ret,
                     Err(_) => report(&watcher_rx.borrow()),
                 }
-            };
+            };
+
+            let output = match ret {
+                Ok(output) => output,
+                Err(err) => {
+                    // Don't spam on configuration errors (eg. bad Nix path).
+                    error_cnt += 1;
+                    if error_cnt <= 3 {
+                        client.show_message_ext(
+                            MessageType::ERROR,
+                            format!("Flake input {input_name:?} cannot be evaluated: {err:#}"),
+                        );
+                    }
+                    continue;
+                }
+            };
+            flake_info
+                .input_flake_outputs
+                .insert(input_name.clone(), output);
+            let _: Result<_, _> =
+                client.emit(SetFlakeInfoEvent(root.to_owned(), Some(flake_info.clone())));
+        }
+
+        tracing::info!("Finished loading flake inputs. {error_cnt}/{input_cnt} failed");
+        let msg =
+            (error_cnt != 0).then(|| format!("{error_cnt}/{input_cnt} input(s) failed to load"));
+        progress.done(msg.clone());
+        msg
+    }
+
+    async fn load_flake_info(
+        vfs: &RwLock<Vfs>,
+        config: &Config,
+        caps: &NegotiatedCapabilities,
+        client: &ClientSocket,
+        root: &Path,
+    ) -> Result<Option<FlakeInfo>> {
+        tracing::info!("Loading flake info");
+
+        let (flake_file, lock_src) = {
+            let vfs = vfs.read().unwrap();
+
+            let flake_vpath = VfsPath::new(root.join(FLAKE_FILE));
+            // We always load flake.nix when initialized. If there's none in Vfs, there's none.
+            let Ok(flake_file) = vfs.file_for_path(&flake_vpath) else {
+                return Ok(None);
+            };
+
+            let lock_vpath = VfsPath::new(root.join(FLAKE_LOCK_FILE));
+            let Ok(lock_file) = vfs.file_for_path(&lock_vpath) else {
+                return Ok(Some(FlakeInfo {
+                    flake_file,
+                    input_store_paths: HashMap::new(),
+                    input_flake_outputs: HashMap::new(),
+                    input_sources: HashMap::new(),
+                }));
+            };
+            let lock_src = vfs.content_for_file(lock_file);
+            (flake_file, lock_src)
+        };
+
+        // Sources are only for display, thus a malformed lock file is not fatal here.
+        let input_sources = flake_lock::resolve_flake_input_sources(lock_src.as_bytes())
+            .unwrap_or_else(|err| {
+                tracing::warn!("Failed to parse flake lock: {err:#}");
+                HashMap::new()
+            });
+
+        // Store paths of inputs are computed by Nix, which takes a while for large lock files.
+        let progress = Progress::new(
+            client,
+            caps,
+            LOAD_FLAKE_INFO_PROGRESS_TOKEN,
+            "Resolving flake inputs",
+            root.display().to_string(),
+        )
+        .await;
+        let inputs =
+            flake_lock::resolve_flake_locked_inputs(&config.nix_binary, lock_src.as_bytes())
+                .await
+                .context("Failed to resolve flake inputs from lock file")?;
+        progress.done(Some(format!("{} inputs", inputs.len())));
+
+        // We only need the map for input -> store path.
+        let input_store_paths = inputs
+            .into_iter()
+            .map(|(key, input)| (key, VfsPath::new(input.store_path)))
+            .collect();
+        Ok(Some(FlakeInfo {
+            flake_file,
+            input_store_paths,
+            input_flake_outputs: HashMap::new(),
+            input_sources,
+        }))
+    }
+
+    fn on_set_flake_info(
+        &mut self,
+        SetFlakeInfoEvent(root, info): SetFlakeInfoEvent,
+    ) -> NotifyResult {
+        tracing::debug!("Set flake info of {}: {info:?}", root.display());
+        if info.is_some() {
+            self.flake_roots.insert(root.clone());
+        } else {
+            self.flake_roots.remove(&root);
+        }
+        self.vfs.write().unwrap().set_flake_info(&root, info);
+        self.apply_vfs_change();
+        ControlFlow::Continue(())
+    }
+
+    fn on_set_option_set(
+        &mut self,
+        SetOptionSetEvent(set, opts, root): SetOptionSetEvent,
+    ) -> NotifyResult {
+        tracing::debug!("Set {} options ({:?} top-levels)", set.title(), opts.len());
+        self.vfs.write().unwrap().set_option_set(set, opts, root);
+        self.apply_vfs_change();
+        ControlFlow::Continue(())
+    }
+
+    fn on_set_package_index(&mut self, index: SetPackageIndexEvent) -> NotifyResult {
+        tracing::debug!("Set package index ({:?} packages)", index.0.len());
+        self.vfs.write().unwrap().set_package_index(index.0);
+        self.apply_vfs_change();
+        ControlFlow::Continue(())
+    }
+
+    fn on_set_lib_docs(&mut self, docs: SetLibDocsEvent) -> NotifyResult {
+        tracing::debug!("Set lib documentation");
+        self.vfs.write().unwrap().set_lib_docs(docs.0);
+        self.apply_vfs_change();
+        ControlFlow::Continue(())
+    }
+
+    fn spawn_reload_config(&self) {
+        if !self.capabilities.workspace_configuration {
+            return;
+        }
+        let mut client = self.client.clone();
+        tokio::spawn(async move {
+            let ret = client
+                .configuration(ConfigurationParams {
+                    items: vec![ConfigurationItem {
+                        scope_uri: None,
+                        section: Some(CONFIG_KEY.into()),
+                    }],
+                })
+                .await;
+            let mut v = match ret {
+                Ok(v) => v,
+                Err(err) => {
+                    client.show_message_ext(
+                        MessageType::ERROR,
+                        format_args!("Failed to update config: {err}"),
+                    );
+                    return;
+                }
+            };
+            tracing::debug!("Updating config: {:?}", v);
+            let v = v.pop().unwrap_or_default();
+            let _: Result<_, _> = client.emit(UpdateConfigEvent(v));
+        });
+    }
+
+    fn on_update_config(&mut self, value: UpdateConfigEvent) -> NotifyResult {
+        let mut config = Config::clone(&self.config);
+        let mut errors = Config::unknown_keys(&value.0)
+            .into_iter()
+            .map(|key| format!("unknown setting `{key}`"))
+            .collect::<Vec<_>>();
+        config.update(value.0, &mut errors);
+
+        let updated_diagnostics = (
+            &self.config.diagnostics_enabled,
+            &self.config.diagnostics_excluded_files,
+            &self.config.diagnostics_ignored,
+        ) != (
+            &config.diagnostics_enabled,
+            &config.diagnostics_excluded_files,
+            &config.diagnostics_ignored,
+        );
+
+        let updated_root = self.config.analysis_root != config.analysis_root;
+        let updated_package_aliases = (
+            &self.config.nix_package_aliases_file,
+            self.config.nix_package_aliases_nixpkgs_version,
+        ) != (
+            &config.nix_package_aliases_file,
+            config.nix_package_aliases_nixpkgs_version,
+        );
+        let updated_module_paths = OptionSet::ALL
+            .iter()
+            .copied()
+            .filter(|&set| {
+                self.config.options_module_paths(set) != config.options_module_paths(set)
+            })
+            .collect::<Vec<_>>();
+        let updated_call_package_names =
+            self.config.nix_call_package_names != config.nix_call_package_names;
+        let updated_shadowing_ignored_names =
+            self.config.diagnostics_shadowed_binding_ignored_names
+                != config.diagnostics_shadowed_binding_ignored_names;
+        let updated_redundant_paren_kept_kinds = self.config.diagnostics_redundant_paren_kept_kinds
+            != config.diagnostics_redundant_paren_kept_kinds;
+        let updated_language_features =
+            self.config.language_features() != config.language_features();
+        let updated_flake = config.need_reload_flake(&self.config);
+        let mut search_path = config.search_path();
+        let updated_search_path = self.config.search_path() != search_path;
+        let updated_code_lens = (
+            self.config.code_lens_flake_outputs,
+            self.config.code_lens_references,
+        ) != (config.code_lens_flake_outputs, config.code_lens_references);
+        let updated_inlay_hints = (
+            self.config.inlay_hints_binding_types,
+            self.config.inlay_hints_parameter_types,
+        ) != (
+            config.inlay_hints_binding_types,
+            config.inlay_hints_parameter_types,
+        );
+
+        let package_aliases = updated_package_aliases.then(|| {
+            let mut aliases = PackageAliases::builtin();
+            if let Some(path) = &config.nix_package_aliases_file {
+                match self
+                    .file_source
+                    .read_path(path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|src| PackageAliases::from_json(&src))
+                {
+                    Ok(user_aliases) => aliases.extend(user_aliases),
+                    Err(err) => errors.push(format!(
+                        "failed to load `nix.packageAliases.file` from {}: {err:#}",
+                        path.display(),
+                    )),
+                }
+            }
+            if let Some(version) = config.nix_package_aliases_nixpkgs_version {
+                aliases.retain_version(version);
+            }
+            aliases
+        });
+
+        tracing::info!("Updated config, errors: {errors:?}, config: {config:?}");
+        self.config = Arc::new(config);
+
+        if !errors.is_empty() {
+            let msg = ["Failed to apply some settings:"]
+                .into_iter()
+                .chain(errors.iter().flat_map(|s| ["\n- ", s]))
+                .collect::<String>();
+            self.client.show_message_ext(MessageType::ERROR, msg);
+        }
+
+        if let Some(aliases) = package_aliases {
+            tracing::debug!("Set package aliases ({} entries)", aliases.len());
+            self.vfs.write().unwrap().set_package_aliases(aliases);
+            self.apply_vfs_change();
+        }
+
+        if !updated_module_paths.is_empty() {
+            let mut vfs = self.vfs.write().unwrap();
+            for set in updated_module_paths {
+                let paths = self
+                    .config
+                    .options_module_paths(set)
+                    .iter()
+                    .map(VfsPath::new)
+                    .collect();
+                vfs.set_option_set_module_paths(set, paths);
+            }
+            drop(vfs);
+            self.apply_vfs_change();
+        }
+
+        if updated_call_package_names {
+            let names = self.config.nix_call_package_names.clone();
+            self.vfs.write().unwrap().set_call_package_names(names);
+            self.apply_vfs_change();
+        }
+
+        if updated_shadowing_ignored_names {
+            let names = self
+                .config
+                .diagnostics_shadowed_binding_ignored_names
+                .clone();
+            self.vfs.write().unwrap().set_shadowing_ignored_names(names);
+            self.apply_vfs_change();
+        }
+
+        if updated_redundant_paren_kept_kinds {
+            let kinds = self.config.diagnostics_redundant_paren_kept_kinds.clone();
+            self.vfs
+                .write()
+                .unwrap()
+                .set_redundant_paren_kept_kinds(kinds);
+            self.apply_vfs_change();
+        }
+
+        if updated_language_features {
+            let features = self.config.language_features();
+            self.vfs.write().unwrap().set_language_features(features);
+            self.apply_vfs_change();
+        }
+
+        if updated_search_path {
+            tracing::debug!("Set search path: {search_path:?}");
+            search_path.check_existing();
+            self.vfs.write().unwrap().set_search_path(search_path);
+            self.apply_vfs_change();
+        }
+
+        if updated_root {
+            self.load_analysis_root()?;
+        }
+
+        // If this is the first load, load the flake workspace, which depends on `nix.*` settings.
+        // NixOS options also depend on the analysis root.
+        if !self.tried_flake_load {
+            self.tried_flake_load = true;
+            self.spawn_load_flake_workspace();
+        } else if updated_flake {
+            self.spawn_load_flake_workspace();
+        }
+
+        if std::mem::take(&mut self.index_pending) {
+            self.spawn_index_workspace();
+            // Indexing may be disabled.
+            self.update_server_status();
+        }
+
+        if updated_code_lens && self.capabilities.code_lens_refresh {
+            let mut client = self.client.clone();
+            tokio::spawn(async move {
+                if let Err(err) = client.code_lens_refresh(()).await {
+                    tracing::warn!("Failed to refresh code lenses: {err}");
+                }
+            });
+        }
+
+        if updated_inlay_hints && self.capabilities.inlay_hint_refresh {
+            let mut client = self.client.clone();
+            tokio::spawn(async move {
+                if let Err(err) = client.inlay_hint_refresh(()).await {
+                    tracing::warn!("Failed to refresh inlay hints: {err}");
+                }
+            });
+        }
+
+        // Refresh all diagnostics since the filter may be changed.
+        if updated_diagnostics {
+            self.spawn_update_diagnostics();
+        }
+        if !self.config.diagnostics_eval_enable {
+            for (_, fut) in self.eval_diagnostics_futs.drain() {
+                fut.abort();
+            }
+            self.update_eval_diagnostics(|diags| {
+                let changed = !diags.is_empty();
+                diags.clear();
+                changed
+            });
+        }
+
+        ControlFlow::Continue(())
+    }
+
+    /// Spawn a task to load all Nix files in the workspace in batches. See `indexer`.
+    fn spawn_index_workspace(&mut self) {
+        if !self.config.indexing_enable {
+            return;
+        }
+        let pool = match &self.indexer_pool {
+            Some(pool) => pool.clone(),
+            None => match indexer::build_thread_pool(self.config.indexing_threads) {
+                Ok(pool) => self.indexer_pool.insert(Arc::new(pool)).clone(),
+                Err(err) => {
+                    let msg = format!("{err:#}");
+                    self.client.show_message_ext(MessageType::ERROR, &msg);
+                    self.task_errors
+                        .insert(BackgroundTask::IndexWorkspace, vec![msg]);
+                    self.update_server_status();
+                    return;
+                }
+            },
+        };
+        let roots = self.vfs.read().unwrap().roots().to_vec();
+        if roots.is_empty() {
+            return;
+        }
+        let fut = task::spawn(Self::index_workspace(
+            pool,
+            roots,
+            self.config.clone(),
+            self.capabilities.clone(),
+            self.client.clone(),
+            self.file_source.clone(),
+        ));
+        if let Some(prev_fut) = self.index_workspace_fut.replace(fut) {
+            prev_fut.abort();
+        }
+        self.running_tasks.insert(BackgroundTask::IndexWorkspace);
+        self.update_server_status();
+    }
+
+    fn on_task_finished(
+        &mut self,
+        TaskFinishedEvent(task, errors): TaskFinishedEvent,
+    ) -> NotifyResult {
+        self.running_tasks.remove(&task);
+        self.task_errors.insert(task, errors);
+        self.update_server_status();
+        ControlFlow::Continue(())
+    }
+
+    /// Send `experimental/serverStatus` if it is changed.
+    /// Indexing errors are fatal, while flake loading errors only degrade some features.
+    fn update_server_status(&mut self) {
+        if !self.capabilities.server_status_notification {
+            return;
+        }
+        let errors_of = |task| self.task_errors.get(&task).filter(|errs| !errs.is_empty());
+        let (health, errors) = if let Some(errs) = errors_of(BackgroundTask::IndexWorkspace) {
+            (lsp_ext::Health::Error, Some(errs))
+        } else if let Some(errs) = errors_of(BackgroundTask::LoadFlakeWorkspace) {
+            (lsp_ext::Health::Warning, Some(errs))
+        } else {
+            (lsp_ext::Health::Ok, None)
+        };
+        let status = lsp_ext::ServerStatusParams {
+            health,
+            quiescent: self.running_tasks.is_empty() && !self.index_pending,
+            message: errors.map(|errs| errs.join("\n")),
+        };
+        if self.last_status.as_ref() == Some(&status) {
+            return;
+        }
+        self.last_status = Some(status.clone());
+        let _: Result<_, _> = self
+            .client
+            .notify::<lsp_ext::ServerStatusNotification>(status);
+    }
+
+    async fn index_workspace(
+        pool: Arc<ThreadPool>,
+        roots: Vec<PathBuf>,
+        config: Arc<Config>,
+        caps: NegotiatedCapabilities,
+        client: ClientSocket,
+        file_source: FileSource,
+    ) {
+        tracing::info!("Indexing workspace");
+        let progress = Progress::new(
+            &client,
+            &caps,
+            INDEX_WORKSPACE_PROGRESS_TOKEN,
+            "Indexing workspace",
+            "Scanning files".to_owned(),
+        )
+        .await;
+
+        // Files unchanged since the last session are validated against the disk, which is not
+        // the source of replayed sessions.
+        if config.indexing_persist && matches!(file_source, FileSource::Disk) {
+            let key = module_graph::cache_key(&roots);
+            let graph = task::spawn_blocking(move || {
+                let mut graph =
+                    IndexCache::from_env()?.load::<ModuleGraph>(MODULE_GRAPH_CACHE, &key)?;
+                graph.retain_fresh();
+                Some(graph)
+            })
+            .await;
+            if let Ok(Some(graph)) = graph {
+                tracing::info!("Loaded the module graph of the last session");
+                let _: Result<_, _> = client.emit(SetModuleGraphEvent(graph));
+            }
+        }
+
+        let max_files = config.indexing_max_files;
+        let paths = match task::spawn_blocking(move || {
+            let mut paths = roots
+                .iter()
+                .flat_map(|root| indexer::collect_nix_files(root, max_files))
+                .collect::<Vec<_>>();
+            // Folders may be nested.
+            paths.sort();
+            paths.dedup();
+            paths.truncate(max_files);
+            paths
+        })
+        .await
+        {
+            Ok(paths) => Arc::new(paths),
+            Err(err) => {
+                let msg = format!("Failed to collect files to index: {err}");
+                tracing::error!("{msg}");
+                let _: Result<_, _> =
+                    client.emit(TaskFinishedEvent(BackgroundTask::IndexWorkspace, vec![msg]));
+                return;
+            }
+        };
+
+        let total = paths.len();
+        let file_source = Arc::new(file_source);
+        let mut pos = 0;
+        while pos < total {
+            progress.report((pos * 100 / total) as u32, format!("{pos}/{total} files"));
+            let end = (pos + indexer::INDEX_BATCH_LEN).min(total);
+            let files = task::spawn_blocking({
+                let (pool, file_source, paths) = (pool.clone(), file_source.clone(), paths.clone());
+                move || indexer::read_files(&pool, &file_source, &paths[pos..end])
+            })
+            .await
+            .unwrap_or_default();
+            if client.emit(IndexFilesEvent(files, end == total)).is_err() {
+                return;
+            }
+            pos = end;
+        }
+
+        tracing::info!("Loaded {total} files for indexing");
+        progress.done(Some(format!("{total} files")));
+        let _: Result<_, _> = client.emit(TaskFinishedEvent(
+            BackgroundTask::IndexWorkspace,
+            Vec::new(),
+        ));
+    }
+
+    /// Load closed Nix files referenced by path literals in `uri` from the disk, so that
+    /// navigation and diagnostics work across them before indexing, or without it.
+    fn spawn_load_references(&self, uri: &Url) {
+        let Ok(file) = self.vfs.read().unwrap().file_for_uri(uri) else {
+            return;
+        };
+        let client = self.client.clone();
+        self.spawn_with_snapshot(move |snap| {
+            let ret = with_catch_unwind("load_references", || {
+                let uris = snap
+                    .analysis
+                    .unloaded_references(file)?
+                    .into_iter()
+                    .filter_map(|vpath| {
+                        let path = vpath.as_path()?;
+                        let path = if path.is_dir() {
+                            path.join(DEFAULT_IMPORT_FILE)
+                        } else {
+                            path.to_owned()
+                        };
+                        if path.extension() != Some("nix".as_ref()) || !path.is_file() {
+                            return None;
+                        }
+                        Url::from_file_path(path).ok()
+                    })
+                    .collect::<Vec<_>>();
+                Ok(uris)
+            });
+            match ret {
+                Ok(uris) if uris.is_empty() => {}
+                Ok(uris) => {
+                    let _: Result<_, _> = client.emit(LoadFilesEvent(uris));
+                }
+                Err(err) if err.is::<Cancelled>() => {}
+                Err(err) => tracing::error!("Failed to collect referenced files: {err:#}"),
+            }
+        });
+    }
 
-            let output = match ret {
-                Ok(output) => output,
-                Err(err) => {
-                    // Don't spam on configuration errors (eg. bad Nix path).
-                    error_cnt += 1;
-                    if error_cnt <= 3 {
-                        client.show_message_ext(
-                            MessageType::ERROR,
-                            format!("Flake input {input_name:?} cannot be evaluated: {err:#}"),
-                        );
-                    }
+    fn on_load_files(&mut self, LoadFilesEvent(uris): LoadFilesEvent) -> NotifyResult {
+        let mut loaded = false;
+        {
+            let mut vfs = self.vfs.write().unwrap();
+            for uri in uris {
+                // Opened or indexed in the meantime.
+                if vfs.file_for_uri(&uri).is_ok() {
                     continue;
                 }
-            };
-            flake_info
-                .input_flake_outputs
-                .insert(input_name.clone(), output);
-            let _: Result<_, _> = client.emit(SetFlakeInfoEvent(Some(flake_info.clone())));
+                let read = || {
+                    let path = uri.to_file_path().map_err(|()| ErrorKind::InvalidInput)?;
+                    self.file_source
+                        .read(&uri, || indexer::read_regular_file(&path))
+                };
+                match vfs.get_file_for_uri(&uri, read) {
+                    Ok(_) => loaded = true,
+                    Err(err) => tracing::debug!("Skip loading: {err:#}"),
+                }
+            }
         }
-
-        tracing::info!("Finished loading flake inputs. {error_cnt}/{input_cnt} failed");
-        let msg =
-            (error_cnt != 0).then(|| format!("{error_cnt}/{input_cnt} input(s) failed to load"));
-        progress.done(msg);
+        if loaded {
+            self.apply_vfs_change();
+        }
+        ControlFlow::Continue(())
     }
 
-    async fn load_flake_info(vfs: &RwLock<Vfs>, config: &Config) -> Result<Option<FlakeInfo>> {
-        tracing::info!("Loading flake info");
-
-        let (flake_file, lock_src) = {
-            let vfs = vfs.read().unwrap();
-
-            let flake_vpath = VfsPath::new(config.root_path.join(FLAKE_FILE));
-            // We always load flake.nix when initialized. If there's none in Vfs, there's none.
-            let Ok(flake_file) = vfs.file_for_path(&flake_vpath) else {
-                return Ok(None);
-            };
+    fn on_set_module_graph(
+        &mut self,
+        SetModuleGraphEvent(graph): SetModuleGraphEvent,
+    ) -> NotifyResult {
+        self.module_graph = Some(Arc::new(graph));
+        let opened = self.opened_files.keys().cloned().collect::<Vec<_>>();
+        for uri in &opened {
+            self.load_related_files(uri);
+        }
+        ControlFlow::Continue(())
+    }
 
-            let lock_vpath = VfsPath::new(config.root_path.join(FLAKE_LOCK_FILE));
-            let Ok(lock_file) = vfs.file_for_path(&lock_vpath) else {
-                return Ok(Some(FlakeInfo {
-                    flake_file,
-                    input_store_paths: HashMap::new(),
-                    input_flake_outputs: HashMap::new(),
-                }));
-            };
-            let lock_src = vfs.content_for_file(lock_file);
-            (flake_file, lock_src)
+    /// Load closed files related to `uri` by the module graph of the last session, so that
+    /// cross-file analysis of `uri` works before indexing finishes.
+    fn load_related_files(&self, uri: &Url) {
+        let (Some(graph), Ok(path)) = (&self.module_graph, uri.to_file_path()) else {
+            return;
         };
-
-        let inputs =
-            flake_lock::resolve_flake_locked_inputs(&config.nix_binary, lock_src.as_bytes())
-                .await
-                .context("Failed to resolve flake inputs from lock file")?;
-
-        // We only need the map for input -> store path.
-        let input_store_paths = inputs
+        let uris = graph
+            .related_files(&path)
             .into_iter()
-            .map(|(key, input)| (key, VfsPath::new(input.store_path)))
-            .collect();
-        Ok(Some(FlakeInfo {
-            flake_file,
-            input_store_paths,
-            input_flake_outputs: HashMap::new(),
-        }))
+            .filter_map(|path| Url::from_file_path(path).ok())
+            .collect::<Vec<_>>();
+        if !uris.is_empty() {
+            tracing::debug!("Loading {} files related to {uri}", uris.len());
+            let _: Result<_, _> = self.client.emit(LoadFilesEvent(uris));
+        }
     }
 
-    fn on_set_flake_info(&mut self, info: SetFlakeInfoEvent) -> NotifyResult {
-        tracing::debug!("Set flake info: {:?}", info.0);
-        self.workspace_is_flake = info.0.is_some();
-        self.vfs.write().unwrap().set_flake_info(info.0);
+    fn on_index_files(&mut self, IndexFilesEvent(files, is_last): IndexFilesEvent) -> NotifyResult {
+        {
+            let mut vfs = self.vfs.write().unwrap();
+            for (uri, text) in files {
+                // Files opened or loaded by other means are more up-to-date.
+                if self.opened_files.contains_key(&uri) || vfs.file_for_uri(&uri).is_ok() {
+                    continue;
+                }
+                vfs.set_path_content(uri.to_vfs_path(), text);
+                if let Ok(file) = vfs.file_for_uri(&uri) {
+                    self.indexed_files.push(file);
+                }
+            }
+        }
+        // Apply all batches at once, since applying changes cancels ongoing requests.
+        if !is_last {
+            return ControlFlow::Continue(());
+        }
         self.apply_vfs_change();
+
+        let Some(pool) = self.indexer_pool.clone() else {
+            return ControlFlow::Continue(());
+        };
+        let files = std::mem::take(&mut self.indexed_files);
+        let snapshots = (0..pool.current_num_threads())
+            .map(|_| self.host.snapshot())
+            .collect::<Vec<_>>();
+        // NB. See `spawn_with_snapshot`.
+        task::spawn_blocking(move || {
+            let done = indexer::prime_caches(&pool, snapshots, &files);
+            tracing::info!("Analyzed {done}/{} indexed files", files.len());
+        });
         ControlFlow::Continue(())
     }
 
-    fn on_set_nixos_options(&mut self, opts: SetNixosOptionsEvent) -> NotifyResult {
-        tracing::debug!("Set NixOS options ({:?} top-levels)", opts.0.len());
-        self.vfs.write().unwrap().set_nixos_options(opts.0);
+    /// Set the entry file from `analysis.root`, and load it if it is not loaded yet.
+    fn load_analysis_root(&mut self) -> NotifyResult {
+        let path = self
+            .config
+            .analysis_root
+            .as_ref()
+            .map(|root| VfsPath::from(root.file.clone()));
+        let need_load = {
+            let mut vfs = self.vfs.write().unwrap();
+            vfs.set_entry_path(path.clone());
+            path.as_ref()
+                .filter(|path| vfs.file_for_path(path).is_err())
+                .and_then(|path| Url::from_file_path(path.as_path()?).ok())
+        };
+        if let Some(uri) = need_load {
+            // Make a virtual event to read the file.
+            self.on_did_change_watched_files(DidChangeWatchedFilesParams {
+                changes: vec![FileEvent {
+                    uri,
+                    typ: FileChangeType::CREATED,
+                }],
+            })?;
+        }
         self.apply_vfs_change();
         ControlFlow::Continue(())
     }
 
-    fn spawn_reload_config(&self) {
-        if !self.capabilities.workspace_configuration {
+    fn spawn_update_diagnostics(&mut self) {
+        self.diagnostic_version += 1;
+        let version = self.diagnostic_version;
+
+        // Let pulling clients know that diagnostics may be changed.
+        if self.capabilities.pull_diagnostics {
+            if self.capabilities.diagnostic_refresh {
+                let client = self.client.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = client
+                        .request::<lsp_ext::WorkspaceDiagnosticRefresh>(())
+                        .await
+                    {
+                        tracing::warn!("Failed to refresh diagnostics: {err}");
+                    }
+                });
+            }
             return;
         }
-        let mut client = self.client.clone();
+
+        // Coalesce bursts of changes, eg. typing, into a single update after the delay.
+        let delay = Duration::from_millis(self.config.diagnostics_debounce_ms);
+        if delay.is_zero() {
+            self.update_diagnostics(version);
+            return;
+        }
+        let client = self.client.clone();
         tokio::spawn(async move {
-            let ret = client
-                .configuration(ConfigurationParams {
-                    items: vec![ConfigurationItem {
-                        scope_uri: None,
-                        section: Some(CONFIG_KEY.into()),
-                    }],
-                })
-                .await;
-            let mut v = match ret {
-                Ok(v) => v,
-                Err(err) => {
-                    client.show_message_ext(
-                        MessageType::ERROR,
-                        format_args!("Failed to update config: {err}"),
-                    );
-                    return;
-                }
-            };
-            tracing::debug!("Updating config: {:?}", v);
-            let v = v.pop().unwrap_or_default();
-            let _: Result<_, _> = client.emit(UpdateConfigEvent(v));
+            tokio::time::sleep(delay).await;
+            let _: Result<_, _> = client.emit(DebouncedDiagnostics(version));
         });
     }
 
-    fn on_update_config(&mut self, value: UpdateConfigEvent) -> NotifyResult {
-        let mut config = Config::clone(&self.config);
-        let mut errors = Vec::new();
-        config.update(value.0, &mut errors);
-
-        let updated_diagnostics = (
-            &self.config.diagnostics_excluded_files,
-            &self.config.diagnostics_ignored,
-        ) != (
-            &config.diagnostics_excluded_files,
-            &config.diagnostics_ignored,
-        );
-
-        tracing::info!("Updated config, errors: {errors:?}, config: {config:?}");
-        self.config = Arc::new(config);
-
-        if !errors.is_empty() {
-            let msg = ["Failed to apply some settings:"]
-                .into_iter()
-                .chain(errors.iter().flat_map(|s| ["\n- ", s]))
-                .collect::<String>();
-            self.client.show_message_ext(MessageType::ERROR, msg);
-        }
-
-        // If this is the first load, load the flake workspace, which depends on `nix.binary`.
-        if !self.tried_flake_load {
-            self.tried_flake_load = true;
-            self.spawn_load_flake_workspace();
-        }
-
-        // Refresh all diagnostics since the filter may be changed.
-        if updated_diagnostics {
-            self.spawn_update_diagnostics();
+    fn on_debounced_diagnostics(
+        &mut self,
+        DebouncedDiagnostics(version): DebouncedDiagnostics,
+    ) -> NotifyResult {
+        // Superseded by later changes, which have their own delays.
+        if self.diagnostic_version == version {
+            self.update_diagnostics(version);
         }
-
         ControlFlow::Continue(())
     }
 
-    fn spawn_update_diagnostics(&mut self) {
-        self.diagnostic_version += 1;
-        let version = self.diagnostic_version;
-
-        let client = self.client.clone();
-        let opened_files = {
+    /// Compute diagnostics of all opened files. The focused one is computed and published
+    /// first, so that it is not delayed by others.
+    fn update_diagnostics(&self, version: u64) {
+        // The socket is only used to send notifications, which is not affected by panics.
+        let client = AssertUnwindSafe(self.client.clone());
+        let mut opened_files = {
             let vfs = self.vfs.read().unwrap();
             self.opened_files
                 .keys()
@@ -856,27 +2859,30 @@ impl Server {
                 })
                 .collect::<Vec<_>>()
         };
+        let focused = opened_files
+            .iter()
+            .position(|(uri, ..)| Some(uri) == self.focused_file.as_ref())
+            .map(|i| opened_files.swap_remove(i));
 
         self.spawn_with_snapshot(move |snap| {
             let ret = with_catch_unwind("diagnostics", || {
-                opened_files
-                    .into_iter()
-                    .map(|(uri, file, line_map)| {
-                        let diags = if !snap.config.diagnostics_excluded_files.contains(&uri) {
-                            let mut diags = snap.analysis.diagnostics(file)?;
-                            diags.retain(|diag| {
-                                !snap.config.diagnostics_ignored.contains(diag.code())
-                            });
-                            diags.truncate(MAX_DIAGNOSTICS_CNT);
-                            convert::to_diagnostics(&uri, file, &line_map, &diags)
-                        } else {
-                            Vec::new()
-                        };
-                        Ok((uri, diags))
-                    })
-                    .collect::<Result<Vec<_>>>()
+                let compute = |files: Vec<(Url, FileId, Arc<LineMap>)>| {
+                    files
+                        .into_iter()
+                        .map(|(uri, file, line_map)| {
+                            let diags = handler::file_diagnostics(&snap, &uri, file, &line_map)?;
+                            Ok((uri, diags))
+                        })
+                        .collect::<Result<Vec<_>>>()
+                };
+                if let Some(focused) = focused {
+                    let diags = compute(vec![focused])?;
+                    let _: Result<_, _> = client.emit(UpdateDiagnostics(version, diags));
+                }
+                compute(opened_files)
             });
             match ret {
+                Ok(diags) if diags.is_empty() => {}
                 Ok(diags) => {
                     let _: Result<_, _> = client.emit(UpdateDiagnostics(version, diags));
                 }
@@ -896,15 +2902,156 @@ impl Server {
             return ControlFlow::Continue(());
         }
 
+        let prev = self.collect_diagnostics();
         for (uri, diagnostics) in diags {
-            match self.opened_files.get_mut(&uri) {
-                // Update if changed.
-                Some(file_data) if file_data.diagnostics != diagnostics => {
-                    file_data.diagnostics = diagnostics.clone();
-                }
-                _ => continue,
+            if let Some(file_data) = self.opened_files.get_mut(&uri) {
+                file_data.diagnostics = diagnostics;
+            }
+        }
+        self.publish_changed_diagnostics(prev);
+
+        ControlFlow::Continue(())
+    }
+
+    /// Evaluate the saved file `uri`, or `diagnostics.eval.attribute` of its flake, in the
+    /// background, and report the error as diagnostics.
+    fn spawn_eval_diagnostics(&mut self, uri: Url) {
+        let Ok(path) = uri.to_file_path() else {
+            return;
+        };
+        let flake_root = self
+            .flake_roots
+            .iter()
+            .find(|root| path.starts_with(root))
+            .cloned();
+        let (target, flake_root) = match (&self.config.diagnostics_eval_attribute, flake_root) {
+            (Some(attrpath), Some(root)) => {
+                let target = EvalTarget::FlakeAttr {
+                    flake: FlakeUrl::new_path(&root),
+                    attrpath: attrpath.clone(),
+                };
+                (target, Some(root))
+            }
+            _ => (EvalTarget::File(path), None),
+        };
+
+        let config = self.config.clone();
+        let client = self.client.clone();
+        let fut = tokio::spawn({
+            let target = target.clone();
+            async move {
+                let timeout = Duration::from_millis(config.diagnostics_eval_timeout_ms);
+                let fut =
+                    eval_error::check_eval(&config.nix_binary, &target, config.nix_max_memory());
+                let error = match tokio::time::timeout(timeout, fut).await {
+                    Ok(Ok(error)) => error,
+                    Ok(Err(err)) => {
+                        tracing::warn!("Failed to evaluate {target}: {err:#}");
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::warn!("Evaluation of {target} timed out after {timeout:?}");
+                        return;
+                    }
+                };
+                let _: Result<_, _> = client.emit(SetEvalDiagnosticsEvent {
+                    target,
+                    uri,
+                    flake_root,
+                    error,
+                });
+            }
+        });
+        if let Some(prev_fut) = self.eval_diagnostics_futs.insert(target, fut) {
+            prev_fut.abort();
+        }
+    }
+
+    fn on_set_eval_diagnostics(
+        &mut self,
+        SetEvalDiagnosticsEvent {
+            target,
+            uri,
+            flake_root,
+            error,
+        }: SetEvalDiagnosticsEvent,
+    ) -> NotifyResult {
+        // Disabled after spawning.
+        if !self.config.diagnostics_eval_enable {
+            return ControlFlow::Continue(());
+        }
+        let diags = error.map(|err| {
+            // Flakes are evaluated in their copies in the store.
+            let map_path = |path: &Path| {
+                flake_root
+                    .as_ref()
+                    .and_then(|root| {
+                        let mut components = path.strip_prefix(NIX_STORE_DIR).ok()?.components();
+                        components.next()?;
+                        Some(root.join(components.as_path()))
+                    })
+                    .unwrap_or_else(|| path.to_owned())
+            };
+            convert::to_eval_diagnostics(&self.vfs.read().unwrap(), &err, &uri, map_path)
+        });
+        self.update_eval_diagnostics(|eval_diags| match diags {
+            Some(diags) => eval_diags.insert(target, diags.clone()) != Some(diags),
+            None => eval_diags.remove(&target).is_some(),
+        });
+        ControlFlow::Continue(())
+    }
+
+    /// Update evaluation diagnostics by `f`, and publish them if `f` returns they are changed.
+    fn update_eval_diagnostics(&mut self, f: impl FnOnce(&mut EvalDiagnostics) -> bool) {
+        let prev = self.collect_diagnostics();
+        if !f(&mut self.eval_diagnostics.lock().unwrap()) {
+            return;
+        }
+        if self.capabilities.pull_diagnostics {
+            // Only to refresh.
+            self.spawn_update_diagnostics();
+        } else {
+            self.publish_changed_diagnostics(prev);
+        }
+    }
+
+    /// Diagnostics found by all opened files and evaluation, merged by the file they are located in.
+    fn collect_diagnostics(&self) -> HashMap<Url, Vec<lsp_types::Diagnostic>> {
+        // Keep the order deterministic, so unchanged diagnostics are not published again.
+        let mut opened_files = self.opened_files.iter().collect::<Vec<_>>();
+        opened_files.sort_by_key(|(uri, _)| *uri);
+        let mut ret = HashMap::<Url, Vec<_>>::new();
+        for (_, file_data) in opened_files {
+            for (uri, diags) in &file_data.diagnostics {
+                ret.entry(uri.clone())
+                    .or_default()
+                    .extend(diags.iter().cloned());
             }
+        }
+        let eval_diags = self.eval_diagnostics.lock().unwrap();
+        let mut eval_diags = eval_diags.iter().collect::<Vec<_>>();
+        eval_diags.sort_by_key(|(target, _)| target.to_string());
+        for (uri, diags) in eval_diags.into_iter().flat_map(|(_, diags)| diags) {
+            ret.entry(uri.clone())
+                .or_default()
+                .extend(diags.iter().cloned());
+        }
+        ret
+    }
 
+    /// Publish diagnostics changed since `prev`, and clear ones no longer found.
+    fn publish_changed_diagnostics(&mut self, mut prev: HashMap<Url, Vec<lsp_types::Diagnostic>>) {
+        let mut changes = self
+            .collect_diagnostics()
+            .into_iter()
+            .filter(|(uri, diags)| prev.remove(uri).unwrap_or_default() != *diags)
+            .collect::<Vec<_>>();
+        changes.extend(
+            prev.into_iter()
+                .filter(|(_, diags)| !diags.is_empty())
+                .map(|(uri, _)| (uri, Vec::new())),
+        );
+        for (uri, diagnostics) in changes {
             tracing::debug!("Publish {} diagnostics for {}", diagnostics.len(), uri);
             self.client
                 .publish_diagnostics(PublishDiagnosticsParams {
@@ -914,25 +3061,59 @@ impl Server {
                 })
                 .expect("inside main loop");
         }
-
-        ControlFlow::Continue(())
     }
 
-    /// Create a blocking task with a database snapshot as the input.
-    // NB. `spawn_blocking` must be called immediately after snapshotting, so that the read guard
+    /// Run a task on the request pool with a database snapshot as the input.
+    /// The next edit cancels the snapshot, and its queries unwind with `Cancelled`.
+    // NB. The task must be spawned immediately after snapshotting, so that the read guard
     // held in `Analysis` is sent out of the async runtime worker. Otherwise, the read guard
     // is held by the async runtime, and the next `apply_change` acquiring the write guard would
     // deadlock.
     fn spawn_with_snapshot<T: Send + 'static>(
         &self,
         f: impl FnOnce(StateSnapshot) -> T + Send + 'static,
-    ) -> JoinHandle<T> {
+    ) -> oneshot::Receiver<T> {
+        // Queries of a request stop once the client cancels it.
+        let analysis = match CancelToken::current() {
+            Some(token) => self.host.snapshot_interruptible(token.flag()),
+            None => self.host.snapshot(),
+        };
         let snap = StateSnapshot {
-            analysis: self.host.snapshot(),
+            analysis,
             vfs: Arc::clone(&self.vfs),
             config: Arc::clone(&self.config),
+            path_cache: Arc::clone(&self.path_cache),
+            capabilities: self.capabilities.clone(),
+            completion_cache: Arc::clone(&self.completion_cache),
+            completion_resolve_cache: Arc::clone(&self.completion_resolve_cache),
+            profiler: Arc::clone(&self.profiler),
+            eval_diagnostics: Arc::clone(&self.eval_diagnostics),
         };
-        task::spawn_blocking(move || f(snap))
+        let (tx, rx) = oneshot::channel();
+        self.request_pool.spawn(move || {
+            let _: Result<_, _> = tx.send(f(snap));
+        });
+        rx
+    }
+
+    /// Run a snapshot handler in the background, for requests not routed by `request_snap`.
+    fn spawn_snap_handler<P, T>(
+        &self,
+        ctx: &'static str,
+        f: fn(StateSnapshot, P) -> Result<T>,
+        params: P,
+    ) -> impl Future<Output = Result<T, ResponseError>>
+    where
+        P: Send + UnwindSafe + 'static,
+        T: Send + 'static,
+    {
+        let task =
+            self.spawn_with_snapshot(move |snap| with_catch_unwind(ctx, move || f(snap, params)));
+        async move {
+            task.await
+                .expect("Already catch_unwind")
+                .map_err(error_to_response)
+        }
     }
 
     fn set_vfs_file_content(&mut self, uri: &Url, text: String) {
@@ -942,14 +3123,17 @@ impl Server {
     }
 
     fn apply_vfs_change(&mut self) {
+        self.apply_vfs_change_without_diagnostics();
+        self.spawn_update_diagnostics();
+    }
+
+    fn apply_vfs_change_without_diagnostics(&mut self) {
         let changes = self.vfs.write().unwrap().take_change();
         tracing::trace!("Apply VFS changes: {:?}", changes);
 
         // N.B. This acquires the internal write lock.
         // Must be called without holding the lock of `vfs`.
         self.host.apply_change(changes);
-
-        self.spawn_update_diagnostics();
     }
 }
 
@@ -1054,7 +3238,31 @@ impl Drop for Progress {
     }
 }
 
+/// The registration of file watchers for the workspace folder `root`.
+fn watcher_registration_id(root: &Path) -> String {
+    format!(
+        "{}:{}",
+        notif::DidChangeWatchedFiles::METHOD,
+        root.display()
+    )
+}
+
+/// A virtual event creating flake files of all `roots`, to trigger loading of flake info.
+fn flake_files_created_event(roots: &[PathBuf]) -> DidChangeWatchedFilesParams {
+    let changes = roots
+        .iter()
+        .flat_map(|root| [root.join(FLAKE_LOCK_FILE), root.join(FLAKE_FILE)])
+        .map(|path| {
+            let uri = Url::from_file_path(path).expect("Root must be absolute");
+            let typ = FileChangeType::CREATED;
+            FileEvent { uri, typ }
+        })
+        .collect();
+    DidChangeWatchedFilesParams { changes }
+}
+
 fn with_catch_unwind<T>(ctx: &str, f: impl FnOnce() -> Result<T> + UnwindSafe) -> Result<T> {
+    let _span = tracing::debug_span!("handle", method = ctx).entered();
     static INSTALL_PANIC_HOOK: Once = Once::new();
     thread_local! {
         static PANIC_LOCATION: Cell<String> = Cell::new(String::new());
@@ -1077,6 +3285,7 @@ fn with_catch_unwind<T>(ctx: &str, f: impl FnOnce() -> Result<T> + UnwindSafe) -
 
     match panic::catch_unwind(f) {
         Ok(ret) => ret,
+        Err(payload) if payload.is::<Interrupted>() => Err(Interrupted.into()),
         Err(payload) => {
             let reason = payload
                 .downcast_ref::<String>()
@@ -1093,10 +3302,27 @@ fn with_catch_unwind<T>(ctx: &str, f: impl FnOnce() -> Result<T> + UnwindSafe) -
     }
 }
 
+/// Deserialize arguments of `workspace/executeCommand` as a tuple.
+fn parse_command_args<T: DeserializeOwned>(
+    command: &str,
+    args: Vec<serde_json::Value>,
+) -> Result<T, ResponseError> {
+    serde_json::from_value(serde_json::Value::Array(args)).map_err(|err| {
+        ResponseError::new(
+            ErrorCode::INVALID_PARAMS,
+            format!("Invalid arguments for {command}: {err}"),
+        )
+    })
+}
+
 fn error_to_response(err: anyhow::Error) -> ResponseError {
-    if err.is::<Cancelled>() {
+    if err.is::<Interrupted>() {
         return ResponseError::new(ErrorCode::REQUEST_CANCELLED, "Client cancelled");
     }
+    // The snapshot is outdated by an edit. The client may retry the request.
+    if err.is::<Cancelled>() {
+        return ResponseError::new(ErrorCode::CONTENT_MODIFIED, "Content modified");
+    }
     match err.downcast::<ResponseError>() {
         Ok(resp) => resp,
         Err(err) => ResponseError::new(ErrorCode::INTERNAL_ERROR, err),
@@ -1108,10 +3334,29 @@ pub struct StateSnapshot {
     pub(crate) analysis: Analysis,
     vfs: Arc<RwLock<Vfs>>,
     pub(crate) config: Arc<Config>,
+    pub(crate) path_cache: Arc<PathCache>,
+    pub(crate) capabilities: NegotiatedCapabilities,
+    pub(crate) completion_cache: Arc<Mutex<Option<CompletionCache>>>,
+    pub(crate) completion_resolve_cache: Arc<Mutex<CompletionResolveCache>>,
+    pub(crate) profiler: Arc<Profiler>,
+    eval_diagnostics: Arc<Mutex<EvalDiagnostics>>,
 }
 
 impl StateSnapshot {
     pub(crate) fn vfs(&self) -> impl std::ops::Deref<Target = Vfs> + '_ {
         self.vfs.read().unwrap()
     }
+
+    /// Diagnostics of evaluation errors located in `uri`.
+    pub(crate) fn eval_diagnostics(&self, uri: &Url) -> Vec<lsp_types::Diagnostic> {
+        let eval_diags = self.eval_diagnostics.lock().unwrap();
+        let mut eval_diags = eval_diags.iter().collect::<Vec<_>>();
+        eval_diags.sort_by_key(|(target, _)| target.to_string());
+        eval_diags
+            .into_iter()
+            .filter_map(|(_, diags)| diags.get(uri))
+            .flatten()
+            .cloned()
+            .collect()
+    }
 }
@@ -0,0 +1,369 @@
+//! Recording and replaying of LSP sessions, for reproducing bugs.
+//!
+//! A session file contains one JSON entry per line, which is either a message from the client,
+//! or the content of a file read from the disk by the server. Results of `nix` invocations are
+//! not recorded, thus replaying of flake related features is not deterministic.
+//!
+//! During replay, messages are sent with the recorded timing, but each request is sent only
+//! after the previous one is responded.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context as _, Result};
+use futures::channel::mpsc;
+use futures::{ready, AsyncRead, AsyncWrite, StreamExt, TryStreamExt};
+use lsp_types::Url;
+use serde::{Deserialize, Serialize};
+
+/// The maximum time to wait for a message from the server during replay.
+const REPLAY_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+enum Entry {
+    /// A message from the client, received `time` milliseconds after the start of the session.
+    Message {
+        #[serde(default)]
+        time: u64,
+        message: serde_json::Value,
+    },
+    /// A file read from the disk by the server. `None` if it cannot be read.
+    File { uri: Url, text: Option<String> },
+}
+
+/// Where to read files which are not opened by the client.
+#[derive(Debug, Clone, Default)]
+pub enum FileSource {
+    #[default]
+    Disk,
+    /// Read from the disk and record contents.
+    Record(Arc<Recorder>),
+    /// Read recorded contents in order.
+    Replay(Arc<Mutex<HashMap<Url, VecDeque<Option<String>>>>>),
+}
+
+impl FileSource {
+    pub(crate) fn read(
+        &self,
+        uri: &Url,
+        read_disk: impl FnOnce() -> io::Result<String>,
+    ) -> io::Result<String> {
+        match self {
+            Self::Disk => read_disk(),
+            Self::Record(recorder) => {
+                let ret = read_disk();
+                recorder.record(&Entry::File {
+                    uri: uri.clone(),
+                    text: ret.as_ref().ok().cloned(),
+                });
+                ret
+            }
+            Self::Replay(files) => files
+                .lock()
+                .unwrap()
+                .get_mut(uri)
+                .and_then(|texts| texts.pop_front())
+                .flatten()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not recorded")),
+        }
+    }
+
+    /// Read the whole file at the absolute `path`, which is not limited to Nix sources,
+    /// eg. configured JSON files.
+    pub(crate) fn read_path(&self, path: &Path) -> io::Result<String> {
+        let uri = Url::from_file_path(path).map_err(|()| io::ErrorKind::InvalidInput)?;
+        self.read(&uri, || std::fs::read_to_string(path))
+    }
+}
+
+#[derive(Debug)]
+pub struct Recorder {
+    start: Instant,
+    out: Mutex<BufWriter<File>>,
+}
+
+impl Recorder {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create session file {path:?}"))?;
+        Ok(Self {
+            start: Instant::now(),
+            out: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    fn record(&self, entry: &Entry) {
+        let mut out = self.out.lock().unwrap();
+        // Flush every entry, so that the session is kept even if the server crashes.
+        let ret = serde_json::to_writer(&mut *out, entry)
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(out))
+            .and_then(|()| out.flush());
+        if let Err(err) = ret {
+            tracing::error!("Failed to record session: {err}");
+        }
+    }
+}
+
+/// Incremental parser of LSP message frames, ie. `Content-Length: <len>\r\n\r\n<body>`.
+#[derive(Debug, Default)]
+struct FrameParser {
+    buf: Vec<u8>,
+}
+
+impl FrameParser {
+    fn feed(&mut self, data: &[u8]) -> Vec<serde_json::Value> {
+        const SEP: &[u8] = b"\r\n\r\n";
+
+        self.buf.extend_from_slice(data);
+        let mut messages = Vec::new();
+        while let Some(header_len) = self.buf.windows(SEP.len()).position(|w| w == SEP) {
+            let content_len =
+                std::str::from_utf8(&self.buf[..header_len])
+                    .ok()
+                    .and_then(|header| {
+                        header.lines().find_map(|line| {
+                            let (key, value) = line.split_once(':')?;
+                            if !key.trim().eq_ignore_ascii_case("content-length") {
+                                return None;
+                            }
+                            value.trim().parse::<usize>().ok()
+                        })
+                    });
+            let body_start = header_len + SEP.len();
+            let Some(content_len) = content_len else {
+                tracing::error!("Ignore a message without content length");
+                self.buf.drain(..body_start);
+                continue;
+            };
+            let body_end = body_start + content_len;
+            if self.buf.len() < body_end {
+                break;
+            }
+            match serde_json::from_slice(&self.buf[body_start..body_end]) {
+                Ok(message) => messages.push(message),
+                Err(err) => tracing::error!("Ignore an invalid message: {err}"),
+            }
+            self.buf.drain(..body_end);
+        }
+        messages
+    }
+}
+
+fn to_frame(message: &serde_json::Value) -> Vec<u8> {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes()
+}
+
+/// The server input which records all messages from the client.
+pub(crate) struct RecordingInput<R> {
+    inner: R,
+    parser: FrameParser,
+    recorder: Arc<Recorder>,
+}
+
+impl<R> RecordingInput<R> {
+    pub(crate) fn new(inner: R, recorder: Arc<Recorder>) -> Self {
+        Self {
+            inner,
+            parser: FrameParser::default(),
+            recorder,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for RecordingInput<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let len = ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        for message in this.parser.feed(&buf[..len]) {
+            let time = this.recorder.start.elapsed().as_millis() as u64;
+            this.recorder.record(&Entry::Message { time, message });
+        }
+        Poll::Ready(Ok(len))
+    }
+}
+
+/// The server output during replay, which prints all messages to stdout.
+struct ReplayOutput {
+    parser: FrameParser,
+    tx: mpsc::UnboundedSender<serde_json::Value>,
+}
+
+impl AsyncWrite for ReplayOutput {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        for message in self.parser.feed(buf) {
+            println!("{message}");
+            let _: Result<_, _> = self.tx.unbounded_send(message);
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Ids of requests in flight during replay.
+#[derive(Debug)]
+struct ReplayState {
+    output_rx: mpsc::UnboundedReceiver<serde_json::Value>,
+    /// Requests from the server, which are not yet responded by the recorded client.
+    server_requests: HashSet<String>,
+    /// Requests from the client, which are not yet responded by the server.
+    client_requests: HashSet<String>,
+}
+
+impl ReplayState {
+    /// Wait for the next server message. Returns false if the server stopped or timed out.
+    async fn wait_server(&mut self) -> bool {
+        let message = match tokio::time::timeout(REPLAY_WAIT_TIMEOUT, self.output_rx.next()).await {
+            Ok(Some(message)) => message,
+            Ok(None) => return false,
+            Err(_) => {
+                tracing::warn!("Timeout waiting for the server");
+                return false;
+            }
+        };
+        if let Some(id) = message.get("id") {
+            if message.get("method").is_some() {
+                self.server_requests.insert(id.to_string());
+            } else {
+                self.client_requests.remove(&id.to_string());
+            }
+        }
+        true
+    }
+}
+
+/// Replay a recorded session against the current server, printing all server messages.
+pub async fn replay(path: &Path) -> Result<()> {
+    let file = File::open(path).with_context(|| format!("Failed to open session file {path:?}"))?;
+    let mut messages = Vec::new();
+    let mut files = HashMap::<_, VecDeque<_>>::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.context("Failed to read session file")?;
+        let entry = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid entry at line {}", i + 1))?;
+        match entry {
+            Entry::Message { time, message } => messages.push((time, message)),
+            Entry::File { uri, text } => files.entry(uri).or_default().push_back(text),
+        }
+    }
+
+    let (input_tx, input_rx) = mpsc::unbounded::<io::Result<Vec<u8>>>();
+    let (output_tx, output_rx) = mpsc::unbounded();
+    let server = crate::run_server(
+        input_rx.into_async_read(),
+        ReplayOutput {
+            parser: FrameParser::default(),
+            tx: output_tx,
+        },
+        FileSource::Replay(Arc::new(Mutex::new(files))),
+        // The recorded client process is gone.
+        false,
+    );
+
+    let driver = async move {
+        let mut state = ReplayState {
+            output_rx,
+            server_requests: HashSet::new(),
+            client_requests: HashSet::new(),
+        };
+
+        let start = tokio::time::Instant::now();
+        for (time, mut message) in messages {
+            // Keep the timing of messages, since background tasks may affect the result.
+            tokio::time::sleep_until(start + Duration::from_millis(time)).await;
+
+            let id = message.get("id").map(|id| id.to_string());
+            let is_request = message.get("method").is_some();
+            // Responses from the client are sent after the server requests them.
+            if let (Some(id), false) = (&id, is_request) {
+                while !state.server_requests.remove(id) {
+                    if !state.wait_server().await {
+                        break;
+                    }
+                }
+            }
+            // The recorded client process is gone.
+            if message["method"] == "initialize" {
+                message["params"]["processId"] = serde_json::Value::Null;
+            }
+            if input_tx.unbounded_send(Ok(to_frame(&message))).is_err() {
+                return;
+            }
+            // Requests are replayed one by one for determinism, each after the response of
+            // the previous one.
+            if let (Some(id), true) = (id, is_request) {
+                state.client_requests.insert(id.clone());
+                while state.client_requests.contains(&id) {
+                    if !state.wait_server().await {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Close the input to stop the server.
+        drop(input_tx);
+    };
+
+    let (ret, ()) = tokio::join!(server, driver);
+    match ret {
+        Ok(()) | Err(async_lsp::Error::Eof) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_parser() {
+        let mut parser = FrameParser::default();
+        let frame = to_frame(&serde_json::json!({ "id": 1 }));
+        let (lhs, rhs) = frame.split_at(10);
+        assert_eq!(parser.feed(lhs), Vec::<serde_json::Value>::new());
+        assert_eq!(parser.feed(rhs), [serde_json::json!({ "id": 1 })]);
+
+        let mut buf = b"Content-Type: foo\r\n\r\n".to_vec();
+        buf.extend(to_frame(&serde_json::json!(1)));
+        buf.extend(to_frame(&serde_json::json!(2)));
+        assert_eq!(
+            parser.feed(&buf),
+            [serde_json::json!(1), serde_json::json!(2)],
+        );
+        assert!(parser.buf.is_empty());
+    }
+
+    #[test]
+    fn replay_read_path() {
+        let path = Path::new("/nonexistent/options.json");
+        let uri = Url::from_file_path(path).unwrap();
+        let files = HashMap::from([(uri, VecDeque::from([Some("{}".to_owned()), None]))]);
+        let source = FileSource::Replay(Arc::new(Mutex::new(files)));
+        assert_eq!(source.read_path(path).unwrap(), "{}");
+        assert!(source.read_path(path).is_err());
+        assert!(source.read_path(path).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! Export spans in the [Chrome trace event format][format], which can be viewed in
+//! `chrome://tracing` or [Perfetto](https://ui.perfetto.dev).
+//!
+//! Besides spans of requests and their handling phases, the `ide` crate opens a span for each
+//! execution of expensive queries like `parse`, `lower`, `resolve` and `infer`. They nest into
+//! the spans of requests computing them, so the dominating query shows up in the trace.
+//!
+//! [format]: https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// A tracing layer writing each closed span as a complete event.
+pub struct ChromeTraceLayer<W = BufWriter<File>> {
+    start: Instant,
+    writer: Mutex<W>,
+}
+
+impl ChromeTraceLayer {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> ChromeTraceLayer<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        // The closing bracket is optional, so the trace is still valid if the server is killed.
+        writer.write_all(b"[\n")?;
+        Ok(Self {
+            start: Instant::now(),
+            writer: Mutex::new(writer),
+        })
+    }
+}
+
+struct SpanData {
+    start: Instant,
+    tid: u64,
+    args: Map<String, Value>,
+}
+
+struct ArgsVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for ArgsVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), format!("{value:?}").into());
+    }
+}
+
+impl<S, W> Layer<S> for ChromeTraceLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: Write + 'static,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut args = Map::new();
+        attrs.record(&mut ArgsVisitor(&mut args));
+        span.extensions_mut().insert(SpanData {
+            start: Instant::now(),
+            tid: thread_id(),
+            args,
+        });
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut extensions = span.extensions_mut();
+        if let Some(data) = extensions.get_mut::<SpanData>() {
+            values.record(&mut ArgsVisitor(&mut data.args));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else { return };
+        let Some(data) = span.extensions_mut().remove::<SpanData>() else {
+            return;
+        };
+        let name = match data.args.get("method") {
+            Some(Value::String(method)) => format!("{} {method}", span.name()),
+            _ => span.name().into(),
+        };
+        let event = json!({
+            "name": name,
+            "cat": span.metadata().target(),
+            "ph": "X",
+            "ts": micros(data.start - self.start),
+            "dur": micros(data.start.elapsed()),
+            "pid": std::process::id(),
+            "tid": data.tid,
+            "args": data.args,
+        });
+
+        let Ok(mut writer) = self.writer.lock() else {
+            return;
+        };
+        // Tracing failures are not worth disturbing the server.
+        let _: io::Result<()> = (|| {
+            serde_json::to_writer(&mut *writer, &event)?;
+            writer.write_all(b",\n")?;
+            writer.flush()
+        })();
+    }
+}
+
+fn micros(dur: Duration) -> u64 {
+    dur.as_micros().try_into().unwrap_or(u64::MAX)
+}
+
+/// A small sequential id of the current thread, since `ThreadId` has no stable integer form.
+fn thread_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static ID: u64 = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    }
+    ID.with(|id| *id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChromeTraceLayer;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuf {
+        fn events(&self) -> Vec<serde_json::Value> {
+            let text = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            let json = format!("{}]", text.trim_end().trim_end_matches(','));
+            serde_json::from_str(&json).unwrap()
+        }
+    }
+
+    impl io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn complete_events() {
+        let buf = SharedBuf::default();
+        let layer = ChromeTraceLayer::new(buf.clone()).unwrap();
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let _request = tracing::info_span!("request", method = "textDocument/hover").entered();
+            let _phase = tracing::debug_span!("diagnostics", file = 1).entered();
+        });
+
+        let events = buf.events();
+        let got = events
+            .iter()
+            .map(|event| {
+                assert_eq!(event["ph"], "X");
+                assert!(event["dur"].is_u64());
+                (event["name"].as_str().unwrap(), event["args"].to_string())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            got,
+            [
+                ("diagnostics", r#"{"file":1}"#.into()),
+                (
+                    "request textDocument/hover",
+                    r#"{"method":"textDocument/hover"}"#.into()
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn query_spans() {
+        let buf = SharedBuf::default();
+        let layer = ChromeTraceLayer::new(buf.clone()).unwrap();
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let (host, file) = ide::AnalysisHost::new_single_file("let a = 1; in a");
+            host.snapshot().diagnostics(file).unwrap();
+        });
+
+        let names = buf
+            .events()
+            .iter()
+            .map(|event| event["name"].as_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        for query in ["parse", "lower", "resolve", "liveness", "infer"] {
+            assert!(
+                names.iter().any(|name| name == query),
+                "{query} in {names:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Transports other than stdio, for clients connecting from another machine or container.
+use std::fmt;
+use std::io;
+use std::ops::ControlFlow;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+
+use anyhow::{Context as _, Result};
+use async_lsp::client_monitor::{ClientProcessMonitor, ClientProcessMonitorLayer};
+use async_lsp::{AnyEvent, AnyNotification, AnyRequest, ClientSocket, LspService};
+use futures::ready;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tower::{Layer, Service};
+
+/// An address to listen on for a single client connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    /// `host:port`.
+    Tcp(String),
+    /// `unix:<path>`.
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+    /// `\\.\pipe\<name>`.
+    #[cfg(windows)]
+    Pipe(String),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(Self::Unix(path.into()));
+            #[cfg(not(unix))]
+            return Err(format!(
+                "Unix sockets are not supported on this platform: {path}"
+            ));
+        }
+        #[cfg(windows)]
+        if s.starts_with(r"\\.\pipe\") {
+            return Ok(Self::Pipe(s.into()));
+        }
+        if !s.contains(':') {
+            return Err(format!(
+                "invalid address `{s}`, expecting `host:port` or `unix:<path>`"
+            ));
+        }
+        Ok(Self::Tcp(s.into()))
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => f.write_str(addr),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+            #[cfg(windows)]
+            Self::Pipe(name) => f.write_str(name),
+        }
+    }
+}
+
+pub(crate) trait Stream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Stream for T {}
+
+/// Wait for the first client connecting to `addr`. No more connections are accepted after it.
+pub(crate) async fn accept(addr: &ListenAddr) -> Result<Box<dyn Stream>> {
+    tracing::info!("Listening on {addr}");
+    match addr {
+        ListenAddr::Tcp(addr) => {
+            let listener = tokio::net::TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("Failed to listen on {addr}"))?;
+            let (stream, peer) = listener.accept().await.context("Failed to accept")?;
+            tracing::info!("Accepted connection from {peer}");
+            stream.set_nodelay(true)?;
+            Ok(Box::new(stream))
+        }
+        #[cfg(unix)]
+        ListenAddr::Unix(path) => {
+            let listener = tokio::net::UnixListener::bind(path)
+                .with_context(|| format!("Failed to listen on {path:?}"))?;
+            let (stream, _) = listener.accept().await.context("Failed to accept")?;
+            // Nobody else can connect to it.
+            if let Err(err) = std::fs::remove_file(path) {
+                tracing::warn!("Failed to remove the socket {path:?}: {err}");
+            }
+            tracing::info!("Accepted connection");
+            Ok(Box::new(stream))
+        }
+        #[cfg(windows)]
+        ListenAddr::Pipe(name) => {
+            let server = tokio::net::windows::named_pipe::ServerOptions::new()
+                .first_pipe_instance(true)
+                .create(name)
+                .with_context(|| format!("Failed to create the named pipe {name}"))?;
+            server.connect().await.context("Failed to accept")?;
+            tracing::info!("Accepted connection");
+            Ok(Box::new(server))
+        }
+    }
+}
+
+/// Adapts a `tokio` reader or writer to `futures` ones, which the main loop expects.
+pub(crate) struct Compat<T>(pub T);
+
+impl<T: AsyncRead + Unpin> futures::AsyncRead for Compat<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        ready!(Pin::new(&mut self.0).poll_read(cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> futures::AsyncWrite for Compat<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/// Monitors the client process only if it runs on the same machine.
+/// The `processId` of a remote client is meaningless, or even belongs to an unrelated process.
+pub(crate) enum ClientMonitor<S> {
+    Local(ClientProcessMonitor<S>),
+    Remote(S),
+}
+
+impl<S: LspService> Service<AnyRequest> for ClientMonitor<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self {
+            Self::Local(service) => service.poll_ready(cx),
+            Self::Remote(service) => service.poll_ready(cx),
+        }
+    }
+
+    fn call(&mut self, req: AnyRequest) -> Self::Future {
+        match self {
+            Self::Local(service) => service.call(req),
+            Self::Remote(service) => service.call(req),
+        }
+    }
+}
+
+impl<S: LspService> LspService for ClientMonitor<S> {
+    fn notify(&mut self, notif: AnyNotification) -> ControlFlow<async_lsp::Result<()>> {
+        match self {
+            Self::Local(service) => service.notify(notif),
+            Self::Remote(service) => service.notify(notif),
+        }
+    }
+
+    fn emit(&mut self, event: AnyEvent) -> ControlFlow<async_lsp::Result<()>> {
+        match self {
+            Self::Local(service) => service.emit(event),
+            Self::Remote(service) => service.emit(event),
+        }
+    }
+}
+
+pub(crate) struct ClientMonitorLayer {
+    /// The client to notify, or `None` for remote clients.
+    client: Option<ClientSocket>,
+}
+
+impl ClientMonitorLayer {
+    pub fn new(client: ClientSocket, is_local: bool) -> Self {
+        Self {
+            client: is_local.then_some(client),
+        }
+    }
+}
+
+impl<S> Layer<S> for ClientMonitorLayer {
+    type Service = ClientMonitor<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        match &self.client {
+            Some(client) => {
+                ClientMonitor::Local(ClientProcessMonitorLayer::new(client.clone()).layer(inner))
+            }
+            None => ClientMonitor::Remote(inner),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ListenAddr;
+
+    #[test]
+    fn parse_addr() {
+        assert_eq!(
+            "127.0.0.1:9257".parse::<ListenAddr>(),
+            Ok(ListenAddr::Tcp("127.0.0.1:9257".into()))
+        );
+        assert_eq!(
+            "[::1]:9257".parse::<ListenAddr>(),
+            Ok(ListenAddr::Tcp("[::1]:9257".into()))
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            "unix:/run/nil.sock".parse::<ListenAddr>(),
+            Ok(ListenAddr::Unix("/run/nil.sock".into()))
+        );
+        assert!("9257".parse::<ListenAddr>().is_err());
+    }
+}
@@ -1,28 +1,51 @@
-use crate::UrlExt;
+use crate::{UrlExt, MAX_FILE_LEN};
 use anyhow::{ensure, Context, Result};
-use ide::{Change, FileId, FileSet, FlakeGraph, FlakeInfo, SourceRoot, SourceRootId, VfsPath};
+use ide::{
+    Change, FileId, FileSet, FlakeGraph, FlakeInfo, LanguageFeature, OptionSet, SourceRoot,
+    SourceRootId, VfsPath,
+};
 use lsp_types::Url;
+use nix_interop::lib_docs::LibDocs;
 use nix_interop::nixos_options::NixosOptions;
+use nix_interop::package_aliases::PackageAliases;
+use nix_interop::package_index::PackageIndex;
+use nix_interop::search_path::SearchPath;
 use slab::Slab;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::{fmt, mem};
+use std::{fmt, io, mem};
 use text_size::{TextRange, TextSize};
 
 /// Vfs stores file contents with line mapping, and a mapping between
 /// filesystem paths and `FileId`s.
 /// The query system is built on `FileId`'s.
+///
+/// Files are grouped into one source root per workspace folder, by the innermost folder
+/// containing them. Files outside of all folders belong to the first one. Imports are resolved
+/// across source roots.
 pub struct Vfs {
     files: Slab<(Arc<str>, Arc<LineMap>)>,
     local_file_set: FileSet,
+    /// Paths of workspace folders. The first one is the primary root.
+    roots: Vec<PathBuf>,
+    entry_path: Option<VfsPath>,
+    flake_infos: HashMap<PathBuf, FlakeInfo>,
+    /// Sources which relative declarations of option sets are relative to.
+    option_set_roots: HashMap<OptionSet, PathBuf>,
+    /// Versions of documents opened by the client, for versioned workspace edits.
+    versions: HashMap<FileId, i32>,
     root_changed: bool,
+    flake_changed: bool,
     change: Change,
+    encoding: PositionEncoding,
 }
 
 impl fmt::Debug for Vfs {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Vfs")
             .field("file_cnt", &self.files.len())
+            .field("roots", &self.roots)
             .field("root_changed", &self.root_changed)
             .field("change", &self.change)
             .finish_non_exhaustive()
@@ -34,23 +57,125 @@ impl Vfs {
         Self {
             files: Slab::new(),
             local_file_set: FileSet::default(),
+            roots: Vec::new(),
+            entry_path: None,
+            flake_infos: HashMap::new(),
+            option_set_roots: HashMap::new(),
+            versions: HashMap::new(),
             root_changed: false,
+            flake_changed: false,
             change: Change::default(),
+            encoding: PositionEncoding::default(),
         }
     }
 
-    pub fn set_flake_info(&mut self, flake_info: Option<FlakeInfo>) {
-        self.change.set_flake_graph(FlakeGraph {
-            nodes: HashMap::from_iter(flake_info.map(|info| (SourceRootId(0), info))),
-        });
+    /// Set the encoding of columns of positions, and rebuild line maps of existing files.
+    pub fn set_position_encoding(&mut self, encoding: PositionEncoding) {
+        self.encoding = encoding;
+        for (_, (text, line_map)) in self.files.iter_mut() {
+            *line_map = Arc::new(LineMap::normalize(text.to_string(), encoding).1);
+        }
+    }
+
+    /// Set paths of workspace folders. The first one is the primary root.
+    pub fn set_roots(&mut self, roots: Vec<PathBuf>) {
+        if self.roots != roots {
+            self.flake_infos.retain(|path, _| roots.contains(path));
+            self.roots = roots;
+            self.root_changed = true;
+            self.flake_changed = true;
+        }
     }
 
-    pub fn set_nixos_options(&mut self, opts: NixosOptions) {
-        self.change.set_nixos_options(opts);
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// The workspace folder containing `path`, or the primary one if there is none.
+    pub fn root_for_path(&self, path: &Path) -> Option<&Path> {
+        self.roots
+            .iter()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())
+            .or(self.roots.first())
+            .map(|root| &**root)
+    }
+
+    /// Set the flake info of the workspace folder `root`.
+    pub fn set_flake_info(&mut self, root: &Path, flake_info: Option<FlakeInfo>) {
+        match flake_info {
+            Some(info) => self.flake_infos.insert(root.to_owned(), info),
+            None => self.flake_infos.remove(root),
+        };
+        self.flake_changed = true;
+    }
+
+    fn root_index_for_path(&self, path: &VfsPath) -> usize {
+        path.as_path()
+            .and_then(|path| self.root_for_path(path))
+            .and_then(|root| self.roots.iter().position(|p| p == root))
+            .unwrap_or(0)
+    }
+
+    /// Set options of `set`, whose relative declarations are relative to `root`.
+    pub fn set_option_set(&mut self, set: OptionSet, opts: NixosOptions, root: Option<PathBuf>) {
+        match root {
+            Some(root) => self.option_set_roots.insert(set, root),
+            None => self.option_set_roots.remove(&set),
+        };
+        self.change.set_option_set(set, opts);
+    }
+
+    pub fn option_set_root(&self, set: OptionSet) -> Option<&Path> {
+        self.option_set_roots.get(&set).map(|root| &**root)
+    }
+
+    pub fn set_option_set_module_paths(&mut self, set: OptionSet, paths: Vec<VfsPath>) {
+        self.change.set_option_set_module_paths(set, paths);
+    }
+
+    pub fn set_package_aliases(&mut self, aliases: PackageAliases) {
+        self.change.set_package_aliases(aliases);
+    }
+
+    pub fn set_package_index(&mut self, index: PackageIndex) {
+        self.change.set_package_index(index);
+    }
+
+    pub fn set_lib_docs(&mut self, docs: LibDocs) {
+        self.change.set_lib_docs(docs);
+    }
+
+    pub fn set_search_path(&mut self, search_path: SearchPath) {
+        self.change.set_search_path(search_path);
+    }
+
+    pub fn set_call_package_names(&mut self, names: Vec<String>) {
+        self.change.set_call_package_names(names);
+    }
+
+    pub fn set_shadowing_ignored_names(&mut self, names: Vec<String>) {
+        self.change.set_shadowing_ignored_names(names);
+    }
+
+    pub fn set_redundant_paren_kept_kinds(&mut self, kinds: Vec<String>) {
+        self.change.set_redundant_paren_kept_kinds(kinds);
+    }
+
+    pub fn set_language_features(&mut self, features: Vec<LanguageFeature>) {
+        self.change.set_language_features(features);
+    }
+
+    /// Set the entry file of the local source root, which may be not loaded yet.
+    pub fn set_entry_path(&mut self, path: Option<VfsPath>) {
+        if self.entry_path != path {
+            self.entry_path = path;
+            self.root_changed = true;
+        }
     }
 
     pub fn set_path_content(&mut self, path: VfsPath, text: String) -> FileId {
-        let (text, line_map) = LineMap::normalize(text);
+        let (text, line_map) = LineMap::normalize(text, self.encoding);
         let text = <Arc<str>>::from(text);
         let line_map = Arc::new(line_map);
         match self.local_file_set.file_for_path(&path) {
@@ -78,25 +203,26 @@ impl Vfs {
         del_range: Option<TextRange>,
         ins_text: &str,
     ) -> Result<()> {
-        let new_text = match del_range {
-            None => ins_text.to_owned(),
+        let (new_text, line_map) = match del_range {
+            None => LineMap::normalize(ins_text.to_owned(), self.encoding),
             Some(del_range) => {
-                let text = &*self.files[file.0 as usize].0;
+                let (text, line_map) = &self.files[file.0 as usize];
                 ensure!(
-                    del_range.end() <= TextSize::of(text),
+                    del_range.end() <= TextSize::of(&**text),
                     "Invalid delete range {del_range:?}",
                 );
+                let ins_text = ins_text.replace('\r', "");
                 let mut buf = String::with_capacity(
                     text.len() - usize::from(del_range.len()) + ins_text.len(),
                 );
                 buf += &text[..usize::from(del_range.start())];
-                buf += ins_text;
+                buf += &ins_text;
                 buf += &text[usize::from(del_range.end())..];
-                buf
+                let ins_len = u32::try_from(ins_text.len()).expect("Text too long");
+                let line_map = line_map.changed(&buf, del_range, ins_len);
+                (buf, line_map)
             }
         };
-        // This is not quite efficient, but we already do many O(n) traversals.
-        let (new_text, line_map) = LineMap::normalize(new_text);
         let new_text = <Arc<str>>::from(new_text);
         log::trace!("File {:?} content changed: {:?}", file, new_text);
         self.files[file.0 as usize] = (new_text.clone(), Arc::new(line_map));
@@ -104,9 +230,23 @@ impl Vfs {
         Ok(())
     }
 
+    /// Set or clear the version of an opened document.
+    pub fn set_file_version(&mut self, file: FileId, version: Option<i32>) {
+        match version {
+            Some(version) => self.versions.insert(file, version),
+            None => self.versions.remove(&file),
+        };
+    }
+
+    /// The version of the document, or `None` if it is not opened by the client.
+    pub fn file_version(&self, file: FileId) -> Option<i32> {
+        self.versions.get(&file).copied()
+    }
+
     /// Remove a file from Vfs, reflecting the deletion of a file in real FS.
     pub fn remove_uri(&mut self, uri: &Url) -> Result<()> {
         let file = self.file_for_uri(uri)?;
+        self.versions.remove(&file);
         self.local_file_set.remove_file(file);
         self.files.remove(file.0 as usize);
         // We cannot free a `FileId` from database. The best we can do is setting it to empty.
@@ -124,19 +264,66 @@ impl Vfs {
         self.file_for_path(&uri.to_vfs_path())
     }
 
+    /// Get the file of `uri`, or load it by `read` if it is not loaded yet.
+    /// Contents of files loaded this way are kept until the client opens them, or they are
+    /// changed on the disk.
+    pub fn get_file_for_uri(
+        &mut self,
+        uri: &Url,
+        read: impl FnOnce() -> io::Result<String>,
+    ) -> Result<FileId> {
+        if let Ok(file) = self.file_for_uri(uri) {
+            return Ok(file);
+        }
+        let text = read().with_context(|| format!("Failed to read {uri}"))?;
+        ensure!(text.len() <= MAX_FILE_LEN, "File too large: {uri}");
+        Ok(self.set_path_content(uri.to_vfs_path(), text))
+    }
+
     pub fn uri_for_file(&self, file: FileId) -> Url {
         let vpath = self.local_file_set.path_for_file(file);
         Url::from_vfs_path(vpath)
     }
 
+    /// All loaded files and their URIs.
+    pub fn iter(&self) -> impl Iterator<Item = (FileId, Url)> + '_ {
+        self.local_file_set
+            .iter()
+            .map(|(file, vpath)| (file, Url::from_vfs_path(vpath)))
+    }
+
     pub fn take_change(&mut self) -> Change {
         let mut change = mem::take(&mut self.change);
         if mem::take(&mut self.root_changed) {
-            change.set_roots(vec![SourceRoot::new_local(
-                self.local_file_set.clone(),
-                // TODO: Entry.
-                None,
-            )]);
+            let mut file_sets = vec![FileSet::default(); self.roots.len().max(1)];
+            for (file, path) in self.local_file_set.iter() {
+                file_sets[self.root_index_for_path(path)].insert(file, path.clone());
+            }
+            let entry_root = self
+                .entry_path
+                .as_ref()
+                .map(|path| self.root_index_for_path(path));
+            let roots = file_sets
+                .into_iter()
+                .enumerate()
+                .map(|(i, file_set)| {
+                    let entry = self
+                        .entry_path
+                        .as_ref()
+                        .filter(|_| entry_root == Some(i))
+                        .and_then(|path| file_set.file_for_path(path));
+                    SourceRoot::new_local(file_set, entry)
+                })
+                .collect();
+            change.set_roots(roots);
+        }
+        if mem::take(&mut self.flake_changed) {
+            let nodes = (0u32..)
+                .map(SourceRootId)
+                .zip(&self.roots)
+                .filter_map(|(sid, root)| Some((sid, self.flake_infos.get(root)?.clone())))
+                .collect();
+            change.set_flake_graph(FlakeGraph { nodes });
         }
         change
     }
@@ -150,6 +337,15 @@ impl Vfs {
     }
 }
 
+/// The unit of columns in LSP positions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    #[default]
+    Utf16,
+    Utf32,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct LineMap {
     /// Invariant:
@@ -158,62 +354,120 @@ pub struct LineMap {
     line_starts: Vec<u32>,
     char_diffs: HashMap<u32, Vec<(u32, CodeUnitsDiff)>>,
     len: u32,
+    encoding: PositionEncoding,
 }
 
+/// The difference between the UTF-8 length of a character and its length in columns.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum CodeUnitsDiff {
     One = 1,
     Two = 2,
+    Three = 3,
 }
 
 impl LineMap {
-    fn normalize(mut text: String) -> (String, Self) {
+    fn normalize(mut text: String, encoding: PositionEncoding) -> (String, Self) {
         text.retain(|c| c != '\r');
 
         // Must be valid for `TextSize`.
         let text_len = u32::try_from(text.len()).expect("Text too long");
-        let bytes = text.as_bytes();
-
-        let line_starts = Some(0)
-            .into_iter()
-            .chain(
-                bytes
-                    .iter()
-                    .zip(0u32..)
-                    .filter(|(b, _)| **b == b'\n')
-                    .map(|(_, i)| i + 1),
-            )
-            .collect::<Vec<_>>();
-
-        let mut char_diffs = HashMap::new();
-
-        let start_pos_iter = line_starts.iter().copied();
-        let end_pos_iter = line_starts[1..].iter().copied().chain(Some(text_len));
-        for ((start, end), i) in start_pos_iter.zip(end_pos_iter).zip(0u32..) {
-            let mut diffs = Vec::new();
-            for (&b, pos) in bytes[start as usize..end as usize].iter().zip(0u32..) {
-                let diff = match b {
-                    0b0000_0000..=0b0111_1111 |                      // utf8_len == 1, utf16_len == 1
-                    0b1000_0000..=0b1011_1111 => continue,           // Continuation bytes.
-                    0b1100_0000..=0b1101_1111 => CodeUnitsDiff::One, // utf8_len == 2, utf16_len == 1
-                    0b1110_0000..=0b1110_1111 => CodeUnitsDiff::Two, // utf8_len == 3, utf16_len == 1
-                    0b1111_0000.. => CodeUnitsDiff::Two,             // utf8_len == 4, utf16_len == 2
-                };
-                diffs.push((pos, diff));
-            }
-            if !diffs.is_empty() {
-                char_diffs.insert(i, diffs);
-            }
-        }
-
-        let this = Self {
-            line_starts,
-            char_diffs,
+        let mut this = Self {
+            line_starts: vec![0],
+            char_diffs: HashMap::new(),
             len: text_len,
+            encoding,
         };
+        this.scan(text.as_bytes(), 0, 0, text_len);
         (text, this)
     }
 
+    /// The map after replacing `del_range` with `ins_len` bytes, resulting in `text`.
+    /// Only lines touched by the change are scanned again.
+    fn changed(&self, text: &str, del_range: TextRange, ins_len: u32) -> Self {
+        let text_len = u32::try_from(text.len()).expect("Text too long");
+        let (del_start, del_end) = (u32::from(del_range.start()), u32::from(del_range.end()));
+        let shift = |pos: u32| pos - (del_end - del_start) + ins_len;
+        let line_of = |pos: u32| self.line_starts.partition_point(|&i| i <= pos) - 1;
+        let (start_line, end_line) = (line_of(del_start), line_of(del_end));
+        let old_line_cnt = self.line_starts.len();
+
+        let mut this = Self {
+            line_starts: self.line_starts[..=start_line].to_vec(),
+            char_diffs: HashMap::with_capacity(self.char_diffs.len()),
+            len: text_len,
+            encoding: self.encoding,
+        };
+        let scan_end = self
+            .line_starts
+            .get(end_line + 1)
+            .map_or(text_len, |&pos| shift(pos));
+        this.char_diffs.extend(
+            self.char_diffs
+                .iter()
+                .filter(|(&line, _)| (line as usize) < start_line)
+                .map(|(&line, diffs)| (line, diffs.clone())),
+        );
+        this.scan(
+            text.as_bytes(),
+            start_line as u32,
+            self.line_starts[start_line],
+            scan_end,
+        );
+
+        // The start of the line after `end_line` is already pushed by the scan.
+        let new_line_cnt = this.line_starts.len() + old_line_cnt.saturating_sub(end_line + 2);
+        this.line_starts.extend(
+            self.line_starts
+                .iter()
+                .skip(end_line + 2)
+                .map(|&pos| shift(pos)),
+        );
+        this.char_diffs.extend(
+            self.char_diffs
+                .iter()
+                .filter(|(&line, _)| line as usize > end_line)
+                .map(|(&line, diffs)| {
+                    (
+                        (line as usize + new_line_cnt - old_line_cnt) as u32,
+                        diffs.clone(),
+                    )
+                }),
+        );
+        this
+    }
+
+    /// Scan `bytes[start..end]`, where `start` is the start of `line`.
+    /// Starts of following lines are pushed, and non-ASCII characters are recorded.
+    fn scan(&mut self, bytes: &[u8], mut line: u32, start: u32, end: u32) {
+        let mut line_start = start;
+        let mut diffs = Vec::new();
+        for (&b, pos) in bytes[start as usize..end as usize].iter().zip(start..) {
+            let diff = match (b, self.encoding) {
+                (b'\n', _) => {
+                    if !diffs.is_empty() {
+                        self.char_diffs.insert(line, mem::take(&mut diffs));
+                    }
+                    line += 1;
+                    line_start = pos + 1;
+                    self.line_starts.push(line_start);
+                    continue;
+                }
+                // Columns are bytes in UTF-8.
+                (_, PositionEncoding::Utf8) => continue,
+                (0b0000_0000..=0b0111_1111, _) |                      // utf8_len == 1
+                (0b1000_0000..=0b1011_1111, _) => continue,           // Continuation bytes.
+                (0b1100_0000..=0b1101_1111, _) => CodeUnitsDiff::One, // utf8_len == 2, utf16_len == utf32_len == 1
+                (0b1110_0000..=0b1110_1111, _) => CodeUnitsDiff::Two, // utf8_len == 3, utf16_len == utf32_len == 1
+                (_, PositionEncoding::Utf16) => CodeUnitsDiff::Two,   // utf8_len == 4, utf16_len == 2
+                (_, PositionEncoding::Utf32) => CodeUnitsDiff::Three, // utf8_len == 4, utf32_len == 1
+            };
+            diffs.push((pos - line_start, diff));
+        }
+        if !diffs.is_empty() {
+            self.char_diffs.insert(line, diffs);
+        }
+    }
+
     pub fn last_line(&self) -> u32 {
         self.line_starts.len() as u32 - 1
     }
@@ -264,13 +518,110 @@ impl LineMap {
 
 #[cfg(test)]
 mod tests {
-    use super::{CodeUnitsDiff, LineMap};
+    use super::{CodeUnitsDiff, LineMap, PositionEncoding, Vfs};
+    use ide::VfsPath;
+    use lsp_types::Url;
     use std::collections::HashMap;
+    use std::io;
+    use text_size::TextRange;
+
+    #[test]
+    fn roots() {
+        let mut vfs = Vfs::new();
+        vfs.set_roots(vec!["/a".into(), "/a/b".into(), "/c".into()]);
+        let files = ["/a/1.nix", "/a/b/2.nix", "/c/3.nix", "/d/4.nix"]
+            .map(|path| vfs.set_path_content(VfsPath::new(path), String::new()));
+        assert_eq!(
+            vfs.root_for_path("/a/b/2.nix".as_ref()),
+            Some("/a/b".as_ref())
+        );
+        assert_eq!(vfs.root_for_path("/d/4.nix".as_ref()), Some("/a".as_ref()));
+
+        let change = vfs.take_change();
+        let roots = change.roots.unwrap();
+        let root_files = roots
+            .iter()
+            .map(|root| {
+                let mut files = root.files().map(|(file, _)| file).collect::<Vec<_>>();
+                files.sort();
+                files
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            root_files,
+            [vec![files[0], files[3]], vec![files[1]], vec![files[2]]],
+        );
+    }
+
+    #[test]
+    fn no_roots() {
+        let mut vfs = Vfs::new();
+        let files = [
+            VfsPath::new("/a/1.nix"),
+            VfsPath::Virtual("untitled:Untitled-1".into()),
+        ]
+        .map(|path| vfs.set_path_content(path, String::new()));
+        assert_eq!(vfs.root_for_path("/a/1.nix".as_ref()), None);
+
+        let roots = vfs.take_change().roots.unwrap();
+        assert_eq!(roots.len(), 1);
+        let mut root_files = roots[0].files().map(|(file, _)| file).collect::<Vec<_>>();
+        root_files.sort();
+        assert_eq!(root_files, files);
+    }
+
+    #[test]
+    fn cross_root_imports() {
+        let mut vfs = Vfs::new();
+        vfs.set_roots(vec!["/a".into(), "/a/sub".into(), "/b".into()]);
+        let [a, sub, b] = [
+            ("/a/default.nix", "[ (import ../b/lib.nix) ./sub ]"),
+            ("/a/sub/default.nix", "import ../../b/lib.nix"),
+            ("/b/lib.nix", "{ }"),
+        ]
+        .map(|(path, text)| vfs.set_path_content(VfsPath::new(path), text.into()));
+        let mut host = ide::AnalysisHost::new();
+        host.apply_change(vfs.take_change());
+        let analysis = host.snapshot();
+        assert_eq!(analysis.file_references(a).unwrap(), [sub, b]);
+        assert_eq!(analysis.file_references(sub).unwrap(), [b]);
+        assert_eq!(analysis.file_referrers(b).unwrap(), [a, sub]);
+    }
+
+    #[test]
+    fn get_file_for_uri() {
+        let mut vfs = Vfs::new();
+        let uri = Url::parse("file:///a.nix").unwrap();
+        let notfound = || Err(io::Error::from(io::ErrorKind::NotFound));
+        assert!(vfs.get_file_for_uri(&uri, notfound).is_err());
+        assert!(vfs.file_for_uri(&uri).is_err());
+
+        let file = vfs.get_file_for_uri(&uri, || Ok("1".into())).unwrap();
+        assert_eq!(&*vfs.content_for_file(file), "1");
+        // Loaded files are not read again.
+        let file2 = vfs
+            .get_file_for_uri(&uri, || panic!("Already loaded"))
+            .unwrap();
+        assert_eq!(file, file2);
+    }
+
+    #[test]
+    fn file_version() {
+        let mut vfs = Vfs::new();
+        let uri = Url::parse("file:///a.nix").unwrap();
+        let file = vfs.get_file_for_uri(&uri, || Ok("1".into())).unwrap();
+        assert_eq!(vfs.file_version(file), None);
+        vfs.set_file_version(file, Some(3));
+        assert_eq!(vfs.file_version(file), Some(3));
+        // Closed files are not versioned.
+        vfs.remove_uri(&uri).unwrap();
+        assert_eq!(vfs.file_version(file), None);
+    }
 
     #[test]
     fn line_map_ascii() {
         let s = "hello\nworld\nend";
-        let (norm, map) = LineMap::normalize(s.into());
+        let (norm, map) = LineMap::normalize(s.into(), PositionEncoding::Utf16);
         assert_eq!(norm, s);
         assert_eq!(&map.line_starts, &[0, 6, 12]);
 
@@ -297,7 +648,7 @@ mod tests {
         // ℝ  | U+0211D | E2 84 9D    | 211D
         // 💣 | U+1F4A3 | F0 9F 92 A3 | D83D DCA3
         let s = "_A_ß_ℝ_💣_";
-        let (norm, map) = LineMap::normalize(s.into());
+        let (norm, map) = LineMap::normalize(s.into(), PositionEncoding::Utf16);
         assert_eq!(norm, s);
         assert_eq!(&map.line_starts, &[0]);
         assert_eq!(
@@ -329,31 +680,74 @@ mod tests {
         }
     }
 
+    #[test]
+    fn line_map_encodings() {
+        // See comments in `line_map_unicode`.
+        let s = "_A_ß_ℝ_💣_";
+        let (_, map) = LineMap::normalize(s.into(), PositionEncoding::Utf8);
+        assert!(map.char_diffs.is_empty());
+        assert_eq!(map.line_col_for_pos(14.into()), (0, 14));
+        assert_eq!(map.pos_for_line_col(0, 14), 14.into());
+
+        let (_, map) = LineMap::normalize(s.into(), PositionEncoding::Utf32);
+        let mapping = [(3, 0, 3), (5, 0, 4), (9, 0, 6), (10, 0, 7), (14, 0, 8)];
+        for (pos, line, col) in mapping {
+            assert_eq!(map.line_col_for_pos(pos.into()), (line, col));
+            assert_eq!(map.pos_for_line_col(line, col), pos.into());
+        }
+        assert_eq!(map.end_col_for_line(0), 9);
+    }
+
     #[test]
     fn last_line() {
-        let (_, map) = LineMap::normalize("".into());
+        let (_, map) = LineMap::normalize("".into(), PositionEncoding::Utf16);
         assert_eq!(map.last_line(), 0);
-        let (_, map) = LineMap::normalize("\n".into());
+        let (_, map) = LineMap::normalize("\n".into(), PositionEncoding::Utf16);
         assert_eq!(map.last_line(), 1);
-        let (_, map) = LineMap::normalize("foo\nbar".into());
+        let (_, map) = LineMap::normalize("foo\nbar".into(), PositionEncoding::Utf16);
         assert_eq!(map.last_line(), 1);
-        let (_, map) = LineMap::normalize("foo\nbar\n".into());
+        let (_, map) = LineMap::normalize("foo\nbar\n".into(), PositionEncoding::Utf16);
         assert_eq!(map.last_line(), 2);
     }
 
     #[test]
     fn line_end_col() {
         // See comments in `line_map_unicode`.
-        let (_, map) = LineMap::normalize("hello\nAßℝ💣\n\nend".into());
+        let (_, map) = LineMap::normalize("hello\nAßℝ💣\n\nend".into(), PositionEncoding::Utf16);
         assert_eq!(map.end_col_for_line(0), 5);
         assert_eq!(map.end_col_for_line(1), 5);
         assert_eq!(map.end_col_for_line(2), 0);
         assert_eq!(map.end_col_for_line(3), 3);
     }
 
+    #[test]
+    fn line_map_changed() {
+        let text = "hello\nAßℝ💣\n\nworld\nß\nend";
+        let (_, map) = LineMap::normalize(text.into(), PositionEncoding::Utf16);
+        let cases = [
+            (0, 0, ""),
+            (0, 0, "\n"),
+            (2, 4, "ℝ"),
+            (5, 6, ""),
+            (6, 16, "x\ny"),
+            (3, 20, ""),
+            (0, text.len(), ""),
+            (16, 16, "\n\nß"),
+            (text.len(), text.len(), "\n"),
+            (text.len() - 1, text.len(), "💣\n"),
+        ];
+        for (start, end, ins) in cases {
+            let new_text = format!("{}{ins}{}", &text[..start], &text[end..]);
+            let del_range = TextRange::new((start as u32).into(), (end as u32).into());
+            let got = map.changed(&new_text, del_range, ins.len() as u32);
+            let (_, expect) = LineMap::normalize(new_text.clone(), PositionEncoding::Utf16);
+            assert_eq!(got, expect, "{start}..{end} {ins:?} => {new_text:?}");
+        }
+    }
+
     #[test]
     fn cr_lf() {
-        let (_, map) = LineMap::normalize("hello\r\nworld!".into());
+        let (_, map) = LineMap::normalize("hello\r\nworld!".into(), PositionEncoding::Utf16);
         assert_eq!(map.last_line(), 1);
         assert_eq!(map.end_col_for_line(0), 5);
         assert_eq!(map.end_col_for_line(1), 6);
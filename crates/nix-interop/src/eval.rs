@@ -1,11 +1,13 @@
 //! Wrapper for `nix eval`.
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 
 use anyhow::{ensure, Context, Result};
 use serde::de::DeserializeOwned;
 use tokio::process::Command;
 
+use crate::search_path::SearchPathEntry;
+
 pub async fn nix_eval_expr_json<T: DeserializeOwned>(nix_command: &Path, expr: &str) -> Result<T> {
     let output = Command::new(nix_command)
         .kill_on_drop(true)
@@ -36,6 +38,90 @@ pub async fn nix_eval_expr_json<T: DeserializeOwned>(nix_command: &Path, expr: &
     Ok(val)
 }
 
+/// How to evaluate expressions from the workspace, which are not trusted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalOptions {
+    /// The directory to resolve relative paths against.
+    pub base_dir: PathBuf,
+    /// Entries passed via `-I`, for `<name>` lookups.
+    pub search_path: Vec<SearchPathEntry>,
+    /// Enable `restrict-eval`, which denies fetching and accessing paths outside the store,
+    /// `search_path` and `allowed_paths`.
+    pub restrict_eval: bool,
+    /// Paths additionally accessible under `restrict_eval`, usually the workspace.
+    pub allowed_paths: Vec<PathBuf>,
+    /// The limit of memory usage in bytes.
+    pub memory_limit: Option<u64>,
+}
+
+impl EvalOptions {
+    /// Set the working directory and append arguments of the options to `command`.
+    pub(crate) fn apply(&self, command: &mut Command) {
+        command.current_dir(&self.base_dir).args(
+            self.restrict_eval
+                .then_some(["--option", "restrict-eval", "true"])
+                .into_iter()
+                .flatten(),
+        );
+        for entry in &self.search_path {
+            let mut arg = entry.prefix.clone();
+            if !arg.is_empty() {
+                arg.push('=');
+            }
+            arg.push_str(&entry.path.to_string_lossy());
+            command.arg("-I").arg(arg);
+        }
+        if self.restrict_eval {
+            // There is no other way to allow paths. The prefix is not a valid name to look up.
+            for path in &self.allowed_paths {
+                command
+                    .arg("-I")
+                    .arg(format!("-nil-allowed-={}", path.display()));
+            }
+        }
+        crate::limit_memory(command, self.memory_limit);
+    }
+}
+
+/// Evaluate the attribute names of the attrset `expr`, like `import ./lib.nix { }`.
+/// The evaluation is impure but has no side effects on the store.
+pub async fn eval_attr_names(
+    nix_command: &Path,
+    expr: &str,
+    opts: &EvalOptions,
+) -> Result<Vec<String>> {
+    let mut command = Command::new(nix_command);
+    command.kill_on_drop(true).args([
+        "eval",
+        "--experimental-features",
+        "nix-command",
+        "--impure",
+        "--read-only",
+        "--json",
+    ]);
+    opts.apply(&mut command);
+    command
+        .args(["--expr", &format!("builtins.attrNames ({expr})")])
+        .stdin(Stdio::null());
+
+    // Configures stdout/stderr automatically.
+    let output = command
+        .output()
+        .await
+        .with_context(|| format!("Failed to spawn {nix_command:?}"))?;
+
+    ensure!(
+        output.status.success(),
+        "Nix eval failed with {}.\nExpression: {}\nStderr: {}",
+        output.status,
+        expr,
+        String::from_utf8_lossy(&output.stderr),
+    );
+
+    let val = serde_json::from_slice(&output.stdout)?;
+    Ok(val)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -56,4 +142,34 @@ mod tests {
             .await
             .unwrap_err();
     }
+
+    #[tokio::test]
+    #[ignore = "requires calling 'nix'"]
+    async fn eval_attr_names_restricted() {
+        let dir = std::env::temp_dir();
+        let opts = EvalOptions {
+            base_dir: dir.clone(),
+            search_path: Vec::new(),
+            restrict_eval: true,
+            allowed_paths: Vec::new(),
+            memory_limit: None,
+        };
+        let names = eval_attr_names("nix".as_ref(), "{ b = 1; a = 2; }", &opts)
+            .await
+            .unwrap();
+        assert_eq!(names, ["a", "b"]);
+
+        std::fs::write(dir.join("nil-eval-test.nix"), "{ c = 1; }").unwrap();
+        eval_attr_names("nix".as_ref(), "import ./nil-eval-test.nix", &opts)
+            .await
+            .unwrap_err();
+        let opts = EvalOptions {
+            allowed_paths: vec![dir],
+            ..opts
+        };
+        let names = eval_attr_names("nix".as_ref(), "import ./nil-eval-test.nix", &opts)
+            .await
+            .unwrap();
+        assert_eq!(names, ["c"]);
+    }
 }
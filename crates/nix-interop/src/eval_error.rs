@@ -0,0 +1,270 @@
+//! Evaluate files or flake attributes with `nix eval` to find evaluation errors, like failed
+//! assertions, which are only reported with their locations in the message.
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use anyhow::{Context, Result};
+use tokio::process::Command;
+
+use crate::FlakeUrl;
+
+/// What to evaluate.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EvalTarget {
+    File(PathBuf),
+    FlakeAttr { flake: FlakeUrl, attrpath: String },
+}
+
+impl fmt::Display for EvalTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => path.display().fmt(f),
+            Self::FlakeAttr { flake, attrpath } => write!(f, "{flake}#{attrpath}"),
+        }
+    }
+}
+
+/// A location in an error message, 1-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorLocation {
+    pub file: PathBuf,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// A message with an optional location, either the error itself or a frame of its trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorFrame {
+    pub message: String,
+    pub location: Option<ErrorLocation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalError {
+    pub error: ErrorFrame,
+    /// Frames of `--show-trace`, or the ones shown by default, outermost first.
+    pub trace: Vec<ErrorFrame>,
+}
+
+impl EvalError {
+    /// The location of the error, or the innermost frame with a location, like the `throw` call.
+    pub fn location(&self) -> Option<&ErrorLocation> {
+        self.error.location.as_ref().or_else(|| {
+            self.trace
+                .iter()
+                .rev()
+                .find_map(|frame| frame.location.as_ref())
+        })
+    }
+}
+
+/// Strictly evaluate `target` without writing to the store, discarding the value.
+/// Returns the error if the evaluation fails.
+pub async fn check_eval(
+    nix_command: &Path,
+    target: &EvalTarget,
+    memory_limit: Option<u64>,
+) -> Result<Option<EvalError>> {
+    let mut command = Command::new(nix_command);
+    command.kill_on_drop(true).args([
+        "eval",
+        "--experimental-features",
+        "nix-command flakes",
+        "--read-only",
+    ]);
+    match target {
+        EvalTarget::File(path) => {
+            command
+                .arg("--file")
+                .arg(path)
+                .current_dir(path.parent().unwrap_or(path));
+        }
+        EvalTarget::FlakeAttr { .. } => {
+            command.arg(target.to_string());
+        }
+    }
+    command.stdin(Stdio::null()).stdout(Stdio::null());
+    crate::limit_memory(&mut command, memory_limit);
+
+    let output = command
+        .output()
+        .await
+        .with_context(|| format!("Failed to spawn {nix_command:?}"))?;
+    if output.status.success() {
+        return Ok(None);
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Ok(Some(parse_eval_error(&stderr).with_context(|| {
+        format!(
+            "Nix eval failed with {}, but no error is found.\nStderr: {stderr}",
+            output.status,
+        )
+    })?))
+}
+
+/// Parse the error printed by Nix to stderr, in the format of Nix 2.4+ like:
+/// ```text
+/// error:
+///        … while evaluating the attribute 'foo'
+///
+///          at /path/to/file.nix:1:3:
+///
+///        error: assertion 'false' failed
+///
+///        at /path/to/file.nix:1:9:
+/// ```
+/// or the one-line format of Nix 2.3, like `error: assertion 'false' failed at /a.nix:1:9`.
+pub fn parse_eval_error(stderr: &str) -> Option<EvalError> {
+    let mut frames = Vec::<ErrorFrame>::new();
+    for line in stderr.lines() {
+        let line = line.trim();
+        let message = if let Some(msg) = line.strip_prefix("error:") {
+            msg.trim()
+        } else if let Some(msg) = line.strip_prefix('…') {
+            msg.trim()
+        } else if let Some(loc) = line.strip_prefix("at ") {
+            if let Some(frame) = frames.last_mut().filter(|frame| frame.location.is_none()) {
+                frame.location = parse_location(loc.strip_suffix(':').unwrap_or(loc));
+            }
+            continue;
+        } else {
+            continue;
+        };
+        if message.is_empty() {
+            continue;
+        }
+        // The one-line format.
+        let frame = match message.rsplit_once(" at ") {
+            Some((msg, loc)) if parse_location(loc).is_some() => ErrorFrame {
+                message: msg.trim_end_matches(',').to_owned(),
+                location: parse_location(loc),
+            },
+            _ => ErrorFrame {
+                message: message.to_owned(),
+                location: None,
+            },
+        };
+        frames.push(frame);
+    }
+    let error = frames.pop()?;
+    Some(EvalError {
+        error,
+        trace: frames,
+    })
+}
+
+/// `/path/to/file.nix:LINE:COL`. Pseudo files like `«string»` have no locations.
+fn parse_location(s: &str) -> Option<ErrorLocation> {
+    let mut it = s.rsplitn(3, ':');
+    let column = it.next()?.parse().ok()?;
+    let line = it.next()?.parse().ok()?;
+    let file = PathBuf::from(it.next()?);
+    if !file.is_absolute() {
+        return None;
+    }
+    Some(ErrorLocation { file, line, column })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loc(file: &str, line: u32, column: u32) -> Option<ErrorLocation> {
+        Some(ErrorLocation {
+            file: file.into(),
+            line,
+            column,
+        })
+    }
+
+    #[test]
+    fn parse_trace() {
+        let stderr = "\
+error:
+       … while evaluating the attribute 'foo'
+
+         at /tmp/a.nix:1:3:
+
+            1| { foo = assert false; 1; }
+             |   ^
+
+       … while evaluating a string
+
+         at «string»:1:1:
+
+       error: assertion 'false' failed
+
+       at /tmp/a.nix:1:9:
+
+            1| { foo = assert false; 1; }
+             |         ^
+";
+        let err = parse_eval_error(stderr).unwrap();
+        assert_eq!(
+            err.error,
+            ErrorFrame {
+                message: "assertion 'false' failed".into(),
+                location: loc("/tmp/a.nix", 1, 9),
+            },
+        );
+        assert_eq!(
+            err.trace,
+            [
+                ErrorFrame {
+                    message: "while evaluating the attribute 'foo'".into(),
+                    location: loc("/tmp/a.nix", 1, 3),
+                },
+                ErrorFrame {
+                    message: "while evaluating a string".into(),
+                    location: None,
+                },
+            ],
+        );
+    }
+
+    #[test]
+    fn parse_without_location() {
+        let stderr = "\
+error:
+       … while calling the 'throw' builtin
+
+         at /tmp/a.nix:2:5:
+
+       error: unsupported
+";
+        let err = parse_eval_error(stderr).unwrap();
+        assert_eq!(err.error.message, "unsupported");
+        assert_eq!(err.location(), loc("/tmp/a.nix", 2, 5).as_ref());
+
+        assert_eq!(parse_eval_error("warning: something\n"), None);
+    }
+
+    #[test]
+    fn parse_one_line() {
+        let err = parse_eval_error("error: attribute 'x' missing, at /tmp/a.nix:3:7\n").unwrap();
+        assert_eq!(
+            err.error,
+            ErrorFrame {
+                message: "attribute 'x' missing".into(),
+                location: loc("/tmp/a.nix", 3, 7),
+            },
+        );
+        assert!(err.trace.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires calling 'nix'"]
+    async fn check_assertion() {
+        let dir = std::env::temp_dir().join(format!("nil-eval-error-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.nix");
+        std::fs::write(&path, "{ foo = assert false; 1; }").unwrap();
+        let err = check_eval("nix".as_ref(), &EvalTarget::File(path.clone()), None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(err.location().unwrap().file, path);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
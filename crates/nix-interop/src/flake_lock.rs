@@ -87,6 +87,49 @@ pub async fn resolve_flake_locked_inputs(
     Ok(resolved)
 }
 
+/// Where a locked input comes from, as recorded in the lock file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputSource {
+    /// The flake reference without the revision, eg. `github:NixOS/nixpkgs`.
+    /// `None` for unknown input types.
+    pub url: Option<String>,
+    /// The locked revision, if it is a version controlled source.
+    pub rev: Option<String>,
+    /// The branch or tag which is followed when updating, eg. `nixos-unstable`.
+    pub ref_: Option<String>,
+    /// The commit time in Unix timestamp.
+    pub last_modified: Option<u64>,
+}
+
+/// Get sources of all root inputs from a flake lock, without calling `nix`.
+pub fn resolve_flake_input_sources(lock_src: &[u8]) -> Result<HashMap<String, InputSource>> {
+    let lock =
+        serde_json::from_slice::<FlakeLock>(lock_src).context("Failed to parse flake lock")?;
+    let mut resolver = Resolver::new(&lock);
+    let inputs = resolver
+        .resolve_node_inputs(&lock.root)
+        .context("Failed to resolve inputs from flake lock")?;
+    let sources = inputs
+        .into_iter()
+        // The root node is unlocked. See `resolve_flake_locked_inputs`.
+        .filter_map(|(input_name, node)| {
+            let locked = node.locked.as_ref()?;
+            let source = InputSource {
+                url: locked.to_url(),
+                rev: locked.rev.clone(),
+                ref_: node
+                    .original
+                    .as_ref()
+                    .and_then(|original| original.ref_.clone())
+                    .or_else(|| locked.ref_.clone()),
+                last_modified: locked.last_modified,
+            };
+            Some((input_name.to_owned(), source))
+        })
+        .collect();
+    Ok(sources)
+}
+
 #[derive(Debug)]
 struct Resolver<'a> {
     lock: &'a FlakeLock,
@@ -165,6 +208,8 @@ struct FlakeNode {
     inputs: HashMap<String, FlakeInput>,
     /// For the root node (the current flake), this is `None`.
     locked: Option<LockedFlakeRef>,
+    #[serde(default)]
+    original: Option<OriginalFlakeRef>,
     #[serde(default = "const_true")]
     flake: bool,
 }
@@ -184,6 +229,56 @@ enum FlakeInput {
 #[serde(rename_all = "camelCase")]
 struct LockedFlakeRef {
     nar_hash: String,
+    #[serde(default, rename = "type")]
+    type_: Option<String>,
+    #[serde(default)]
+    owner: Option<String>,
+    #[serde(default)]
+    repo: Option<String>,
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    rev: Option<String>,
+    #[serde(default, rename = "ref")]
+    ref_: Option<String>,
+    #[serde(default)]
+    last_modified: Option<u64>,
+    // ...
+}
+
+impl LockedFlakeRef {
+    /// <https://github.com/NixOS/nix/blob/2.13.1/src/nix/flake.md#types>
+    fn to_url(&self) -> Option<String> {
+        let url = match self.type_.as_deref()? {
+            ty @ ("github" | "gitlab" | "sourcehut") => {
+                let mut url = format!("{ty}:{}/{}", self.owner.as_ref()?, self.repo.as_ref()?);
+                if let Some(host) = &self.host {
+                    url += "?host=";
+                    url += host;
+                }
+                url
+            }
+            "git" => format!("git+{}", self.url.as_ref()?),
+            "mercurial" => format!("hg+{}", self.url.as_ref()?),
+            "tarball" | "file" => self.url.clone()?,
+            "path" => format!("path:{}", self.path.as_ref()?),
+            "indirect" => format!("flake:{}", self.id.as_ref()?),
+            _ => return None,
+        };
+        Some(url)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+struct OriginalFlakeRef {
+    #[serde(default, rename = "ref")]
+    ref_: Option<String>,
     // ...
 }
 
@@ -246,6 +341,33 @@ mod tests {
         assert_eq!(got, expect);
     }
 
+    #[test]
+    fn resolve_flake_input_sources() {
+        let lock_src = std::fs::read("./tests/test_flake/flake.lock").unwrap();
+        let got = super::resolve_flake_input_sources(&lock_src).unwrap();
+        let expect = HashMap::from_iter([
+            (
+                "nixpkgs".to_owned(),
+                InputSource {
+                    url: Some("github:NixOS/nixpkgs".into()),
+                    rev: Some("5ed481943351e9fd354aeb557679624224de38d5".into()),
+                    ref_: None,
+                    last_modified: Some(1674211260),
+                },
+            ),
+            (
+                "nix".to_owned(),
+                InputSource {
+                    url: Some("github:NixOS/nix".into()),
+                    rev: Some("4acc684ef7b3117c6d6ac12837398a0008a53d85".into()),
+                    ref_: Some("2.13.3".into()),
+                    last_modified: Some(1677045134),
+                },
+            ),
+        ]);
+        assert_eq!(got, expect);
+    }
+
     #[tokio::test]
     #[ignore = "requires calling 'nix' and network access"]
     async fn archive() {
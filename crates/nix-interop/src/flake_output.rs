@@ -34,28 +34,7 @@ pub async fn eval_flake_output(
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
 
-    // MacOS does not respect `RLIMIT_DATA`.
-    // See: https://bugs.chromium.org/p/chromium/issues/detail?id=853873#c2
-    #[cfg(target_os = "linux")]
-    unsafe {
-        if let Some(limit) = memory_limit {
-            use rustix::process::{setrlimit, Resource, Rlimit};
-            command.pre_exec(move || {
-                // NB. RSS limit has no effect on modern Linux. We set DATA limit instead.
-                setrlimit(
-                    Resource::Data,
-                    Rlimit {
-                        current: Some(limit),
-                        maximum: Some(limit),
-                    },
-                )?;
-                Ok(())
-            });
-        }
-    }
-
-    #[cfg(not(target_os = "linux"))]
-    let _unused = memory_limit;
+    crate::limit_memory(&mut command, memory_limit);
 
     let mut child = command.spawn().context("Failed to spawn `nix`")?;
 
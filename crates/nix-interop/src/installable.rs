@@ -0,0 +1,98 @@
+//! Wrappers for `nix eval` and `nix build` of flake output attributes.
+use std::path::Path;
+use std::process::Stdio;
+
+use anyhow::{ensure, Context, Result};
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::FlakeUrl;
+
+/// Evaluate the string value of `flake#attrpath`.
+pub async fn eval_raw(nix_command: &Path, flake: &FlakeUrl, attrpath: &str) -> Result<String> {
+    let installable = format!("{flake}#{attrpath}");
+    let stdout = run(nix_command, &["eval", "--raw", &installable]).await?;
+    Ok(stdout)
+}
+
+/// Build `flake#attrpath` without creating the result link, returning the output paths.
+pub async fn build(nix_command: &Path, flake: &FlakeUrl, attrpath: &str) -> Result<Vec<String>> {
+    let installable = format!("{flake}#{attrpath}");
+    let stdout = run(
+        nix_command,
+        &["build", "--no-link", "--print-out-paths", &installable],
+    )
+    .await?;
+    Ok(stdout.lines().map(Into::into).collect())
+}
+
+/// The definition position of an attribute, from `builtins.unsafeGetAttrPos`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AttrPos {
+    pub file: String,
+    /// 1-based.
+    pub line: u32,
+    /// 1-based.
+    pub column: u32,
+}
+
+/// Locate the definition of `outputs.<attrpath>` of the flake at `flake_path`, which is usually
+/// a locked input in the store. Returns `None` if the position is unknown, eg. for attributes
+/// created by `builtins.listToAttrs`.
+pub async fn attr_pos(
+    nix_command: &Path,
+    flake_path: &Path,
+    attrpath: &[String],
+) -> Result<Option<AttrPos>> {
+    let (last, parents) = attrpath.split_last().context("Empty attrpath")?;
+    let flake_url = FlakeUrl::new_path(flake_path);
+    // Validate since we'll wrap these in Nix strings below.
+    for s in std::iter::once(flake_url.as_str()).chain(attrpath.iter().map(|s| &**s)) {
+        ensure!(
+            s.bytes().all(|b| b != b'\\' && b != b'"' && b != b'$'),
+            "Invalid string for Nix: {s:?}",
+        );
+    }
+    let parents = parents
+        .iter()
+        .map(|key| format!("\"{key}\" "))
+        .collect::<String>();
+    let expr = format!(
+        r#"
+        let
+          flake = builtins.getFlake "{flake_url}";
+          set = builtins.foldl' (set: key: set.${{key}}) flake.outputs [ {parents}];
+        in
+          builtins.unsafeGetAttrPos "{last}" set
+        "#
+    );
+    // Store paths are not locked flake references, thus `--impure` is required.
+    let stdout = run(
+        nix_command,
+        &["eval", "--impure", "--read-only", "--json", "--expr", &expr],
+    )
+    .await?;
+    Ok(serde_json::from_str(&stdout)?)
+}
+
+async fn run(nix_command: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new(nix_command)
+        .kill_on_drop(true)
+        .args(["--experimental-features", "nix-command flakes"])
+        .args(args)
+        .stdin(Stdio::null())
+        // Configures stdout/stderr automatically.
+        .output()
+        .await
+        .with_context(|| format!("Failed to spawn {nix_command:?}"))?;
+
+    ensure!(
+        output.status.success(),
+        "`nix {}` failed with {}.\nStderr: {}",
+        args.join(" "),
+        output.status,
+        String::from_utf8_lossy(&output.stderr),
+    );
+
+    Ok(String::from_utf8(output.stdout)?)
+}
@@ -4,10 +4,19 @@ use std::fmt;
 use std::path::{Path, PathBuf};
 
 pub mod eval;
+pub mod eval_error;
 pub mod flake_lock;
 pub mod flake_output;
 pub mod info;
+pub mod installable;
+pub mod lib_docs;
 pub mod nixos_options;
+pub mod package_aliases;
+pub mod package_index;
+pub mod prefetch;
+pub mod repl;
+pub mod search_path;
+pub mod store_path;
 
 pub const DEFAULT_IMPORT_FILE: &str = "default.nix";
 pub const FLAKE_FILE: &str = "flake.nix";
@@ -17,7 +26,33 @@ pub const FLAKE_LOCK_FILE: &str = "flake.lock";
 #[error("Nix exceeds memory limit")]
 pub struct NixOutOfMemory;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Limit the memory usage of the spawned `command` to `limit` bytes, if set.
+pub(crate) fn limit_memory(command: &mut tokio::process::Command, limit: Option<u64>) {
+    // MacOS does not respect `RLIMIT_DATA`.
+    // See: https://bugs.chromium.org/p/chromium/issues/detail?id=853873#c2
+    #[cfg(target_os = "linux")]
+    unsafe {
+        if let Some(limit) = limit {
+            use rustix::process::{setrlimit, Resource, Rlimit};
+            command.pre_exec(move || {
+                // NB. RSS limit has no effect on modern Linux. We set DATA limit instead.
+                setrlimit(
+                    Resource::Data,
+                    Rlimit {
+                        current: Some(limit),
+                        maximum: Some(limit),
+                    },
+                )?;
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _unused = (command, limit);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FlakeUrl(String);
 
 impl FlakeUrl {
@@ -0,0 +1,365 @@
+//! Documentation of nixpkgs `lib` functions, extracted from doc comments in `lib/` sources.
+//!
+//! Both the structured format of RFC 145 and the legacy one are recognized, eg.
+//! ```nix
+//! {
+//!   /**
+//!     Determine whether a string has given prefix.
+//!
+//!     # Type
+//!
+//!     ```
+//!     hasPrefix :: string -> string -> bool
+//!     ```
+//!   */
+//!   hasPrefix = pref: str: /* ... */;
+//!
+//!   /* Determine whether a string has given suffix.
+//!
+//!      Type: hasSuffix :: string -> string -> bool
+//!   */
+//!   hasSuffix = suffix: content: /* ... */;
+//! }
+//! ```
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use syntax::ast::{self, AstNode, HasBindings};
+use syntax::semantic::AttrKind;
+use syntax::SyntaxKind;
+
+use crate::DEFAULT_IMPORT_FILE;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LibDocs {
+    /// Sub-libraries like `strings` to their documented functions.
+    modules: HashMap<String, HashMap<String, LibDoc>>,
+    /// Names re-exported at the top-level `lib`, to their sub-library.
+    reexports: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LibDoc {
+    /// The type signature from the `Type` section, without the leading `name ::`.
+    pub signature: Option<String>,
+    /// The dedented doc comment in Markdown.
+    pub doc: String,
+}
+
+impl LibDocs {
+    /// Load from the `lib` directory of nixpkgs at `nixpkgs_path`.
+    /// Each `lib/<name>.nix` and `lib/<name>/default.nix` is the sub-library `lib.<name>`.
+    pub fn load(nixpkgs_path: &Path) -> Result<Self> {
+        let lib_dir = nixpkgs_path.join("lib");
+        let mut sources = Vec::new();
+        for entry in std::fs::read_dir(&lib_dir)
+            .with_context(|| format!("Failed to read {}", lib_dir.display()))?
+        {
+            let path = entry?.path();
+            let (name, file) = if path.is_dir() {
+                (path.file_name(), path.join(DEFAULT_IMPORT_FILE))
+            } else if path.extension().map_or(false, |ext| ext == "nix") {
+                (path.file_stem(), path.clone())
+            } else {
+                continue;
+            };
+            let Some(name) = name.and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if !file.is_file() {
+                continue;
+            }
+            let src = std::fs::read_to_string(&file)
+                .with_context(|| format!("Failed to read {}", file.display()))?;
+            sources.push((name.to_owned(), src));
+        }
+        Ok(Self::from_sources(
+            sources.iter().map(|(name, src)| (&**name, &**src)),
+        ))
+    }
+
+    /// Extract from `(name, source)` of sub-libraries. The one named `default` is the
+    /// top-level `lib`, where `inherit (self.<name>) ...;` re-exports are collected.
+    pub fn from_sources<'a>(sources: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        let mut ret = Self::default();
+        let mut toplevel = None;
+        for (name, src) in sources {
+            let parse = syntax::parse_file(src);
+            if name == "default" {
+                toplevel = Some(parse.root());
+                continue;
+            }
+            let docs = collect_docs(parse.root());
+            if !docs.is_empty() {
+                ret.modules.insert(name.to_owned(), docs);
+            }
+        }
+
+        if let Some(root) = toplevel {
+            for inherit in root.syntax().descendants().filter_map(ast::Inherit::cast) {
+                let Some(module) = inherit
+                    .from_expr()
+                    .and_then(|paren| paren.expr())
+                    .and_then(|from| inherit_from_module(&from))
+                    .filter(|module| ret.modules.contains_key(module))
+                else {
+                    continue;
+                };
+                for attr in inherit.attrs() {
+                    if let AttrKind::Static(Some(name)) = AttrKind::of(attr) {
+                        ret.reexports.insert(name, module.clone());
+                    }
+                }
+            }
+        }
+        ret
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+
+    /// Names of sub-libraries.
+    pub fn modules(&self) -> impl Iterator<Item = &str> + '_ {
+        self.modules.keys().map(|s| &**s)
+    }
+
+    /// Documented functions of the sub-library `lib.<module>`.
+    pub fn module(&self, module: &str) -> impl Iterator<Item = (&str, &LibDoc)> + '_ {
+        self.modules
+            .get(module)
+            .into_iter()
+            .flatten()
+            .map(|(name, doc)| (&**name, doc))
+    }
+
+    /// Documented functions re-exported at the top-level `lib`.
+    pub fn toplevel(&self) -> impl Iterator<Item = (&str, &LibDoc)> + '_ {
+        self.reexports
+            .iter()
+            .filter_map(|(name, module)| Some((&**name, self.modules.get(module)?.get(name)?)))
+    }
+
+    /// The documentation of `lib.<name>`, or `lib.<module>.<name>` if `module` is given.
+    pub fn get(&self, module: Option<&str>, name: &str) -> Option<&LibDoc> {
+        let module = match module {
+            Some(module) => module,
+            None => self.reexports.get(name)?,
+        };
+        self.modules.get(module)?.get(name)
+    }
+}
+
+/// `inherit (self.strings)`, `inherit (lib.strings)` or `inherit (strings)`.
+fn inherit_from_module(expr: &ast::Expr) -> Option<String> {
+    match expr {
+        ast::Expr::Ref(r) => Some(r.token()?.text().into()),
+        ast::Expr::Select(sel) => {
+            let mut attrs = sel.attrpath()?.attrs();
+            let (Some(attr), None) = (attrs.next(), attrs.next()) else {
+                return None;
+            };
+            match AttrKind::of(attr) {
+                AttrKind::Static(Some(name)) => Some(name),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Collect doc comments of bindings in the exported attrset of a sub-library file, which is
+/// usually `{ lib }: let ... in rec { ... }`.
+fn collect_docs(root: ast::SourceFile) -> HashMap<String, LibDoc> {
+    let mut ret = HashMap::new();
+    let mut body = root.expr();
+    let set = loop {
+        body = match body {
+            Some(ast::Expr::Lambda(e)) => e.body(),
+            Some(ast::Expr::LetIn(e)) => e.body(),
+            Some(ast::Expr::Paren(e)) => e.expr(),
+            Some(ast::Expr::AttrSet(set)) => break set,
+            _ => return ret,
+        };
+    };
+    for binding in set.bindings() {
+        let ast::Binding::AttrpathValue(binding) = binding else {
+            continue;
+        };
+        let mut attrs = binding.attrpath().into_iter().flat_map(|path| path.attrs());
+        let (Some(attr), None) = (attrs.next(), attrs.next()) else {
+            continue;
+        };
+        let AttrKind::Static(Some(name)) = AttrKind::of(attr) else {
+            continue;
+        };
+        // The closest comment before the binding.
+        let comment = std::iter::successors(
+            binding
+                .syntax()
+                .first_token()
+                .and_then(|tok| tok.prev_token()),
+            |tok| tok.prev_token(),
+        )
+        .find(|tok| tok.kind() != SyntaxKind::SPACE)
+        .filter(|tok| tok.kind() == SyntaxKind::COMMENT);
+        if let Some(doc) = comment.and_then(|tok| parse_doc_comment(tok.text())) {
+            ret.insert(name, doc);
+        }
+    }
+    ret
+}
+
+fn parse_doc_comment(text: &str) -> Option<LibDoc> {
+    let body = text.strip_prefix("/*")?.strip_suffix("*/")?;
+    let body = body.strip_prefix('*').unwrap_or(body);
+    let mut lines = body.lines();
+    let first = lines.next().unwrap_or_default().trim();
+    let rest = lines.collect::<Vec<_>>();
+    let indent = rest
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    let lines = std::iter::once(first)
+        .chain(
+            rest.iter()
+                .map(|line| line.get(indent..).unwrap_or_default().trim_end()),
+        )
+        .collect::<Vec<_>>();
+
+    let start = lines.iter().position(|line| !line.is_empty())?;
+    let end = lines.iter().rposition(|line| !line.is_empty())? + 1;
+    let lines = &lines[start..end];
+
+    Some(LibDoc {
+        signature: parse_signature(lines),
+        doc: lines.join("\n"),
+    })
+}
+
+/// The first line in the code block of `# Type` section, or after the legacy `Type:`.
+fn parse_signature(lines: &[&str]) -> Option<String> {
+    let line = if let Some(pos) = lines.iter().position(|line| line.trim() == "# Type") {
+        lines[pos + 1..]
+            .iter()
+            .map(|line| line.trim())
+            .skip_while(|line| !line.starts_with("```"))
+            .skip(1)
+            .find(|line| !line.is_empty())
+            .filter(|line| !line.starts_with("```"))?
+    } else {
+        let pos = lines
+            .iter()
+            .position(|line| line.trim_start().starts_with("Type:"))?;
+        let inline = lines[pos].trim_start()["Type:".len()..].trim();
+        if inline.is_empty() {
+            lines.get(pos + 1)?.trim()
+        } else {
+            inline
+        }
+    };
+    let sig = line.split_once("::").map_or(line, |(_, sig)| sig).trim();
+    (!sig.is_empty()).then(|| sig.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LibDoc, LibDocs};
+
+    #[test]
+    fn extract() {
+        let strings = r#"
+{ lib }:
+let
+  inherit (builtins) length;
+in
+rec {
+  /**
+    Determine whether a string has given prefix.
+
+    # Type
+
+    ```
+    hasPrefix :: string -> string -> bool
+    ```
+  */
+  hasPrefix = pref: str: true;
+
+  /* Determine whether a string has given suffix.
+
+     Type: hasSuffix :: string -> string -> bool
+
+     Example:
+       hasSuffix "foo" "barfoo"
+       => true
+  */
+  hasSuffix = suffix: content: true;
+
+  # Not a doc comment.
+  undocumented = 1;
+
+  /** Nested paths are skipped. */
+  a.b = 1;
+}
+        "#;
+        let toplevel = r#"
+let
+  lib = self: {
+    strings = callLibs ./strings.nix;
+    inherit (self.strings) hasPrefix undocumented;
+    inherit (self.unknown) foo;
+  };
+in lib
+        "#;
+        let docs = LibDocs::from_sources([("strings", strings), ("default", toplevel)]);
+
+        let mut module = docs.module("strings").collect::<Vec<_>>();
+        module.sort_by_key(|(name, _)| *name);
+        assert_eq!(
+            module,
+            [
+                (
+                    "hasPrefix",
+                    &LibDoc {
+                        signature: Some("string -> string -> bool".into()),
+                        doc: "\
+Determine whether a string has given prefix.
+
+# Type
+
+```
+hasPrefix :: string -> string -> bool
+```"
+                        .into(),
+                    }
+                ),
+                (
+                    "hasSuffix",
+                    &LibDoc {
+                        signature: Some("string -> string -> bool".into()),
+                        doc: "\
+Determine whether a string has given suffix.
+
+Type: hasSuffix :: string -> string -> bool
+
+Example:
+  hasSuffix \"foo\" \"barfoo\"
+  => true"
+                            .into(),
+                    }
+                ),
+            ]
+        );
+
+        assert_eq!(docs.modules().collect::<Vec<_>>(), ["strings"]);
+        let toplevel = docs.toplevel().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(toplevel, ["hasPrefix"]);
+        assert!(docs.get(None, "hasPrefix").is_some());
+        assert!(docs.get(None, "hasSuffix").is_none());
+        assert!(docs.get(Some("strings"), "hasSuffix").is_some());
+    }
+}
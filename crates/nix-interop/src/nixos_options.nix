@@ -1,14 +1,17 @@
 # References:
 # - nixos/lib/eval-cacheable-options.nix
 # - nixos/lib/make-options-doc/default.nix
-nixpkgs:
+# The argument is either the path to nixpkgs, or `{ nixpkgs; options; }` for evaluated options of
+# a NixOS configuration, home-manager or nix-darwin.
+arg:
 let
+  nixpkgs = arg.nixpkgs or arg;
   libPath = nixpkgs + "/lib";
   lib = import libPath;
   modulePath = nixpkgs + "/nixos/modules";
   moduleListPath = modulePath + "/module-list.nix";
 
-  inherit (builtins) isString filter mapAttrs isPath isFunction functionArgs pathExists;
+  inherit (builtins) isString filter mapAttrs isPath isFunction functionArgs pathExists isAttrs;
   inherit (lib) evalModules trivial optionals filterAttrs;
   inherit (lib.options) unknownModule literalExpression;
 
@@ -125,7 +128,13 @@ let
 
       optionType = { name = "attrset"; rest = anything; };
 
-      # enum
+      # Only string values are reported. Since 24.11, the payload is wrapped in `{ values; }`.
+      enum = {
+        name = "enum";
+        values = filter isString
+          (if isAttrs ty.functor.payload then ty.functor.payload.values else ty.functor.payload);
+      };
+
       # either
       # oneOf
       # coerceTo
@@ -179,5 +188,5 @@ let
 in
   if pathExists libPath && pathExists moduleListPath
     && builtins.compareVersions trivial.release "22.11" >= 0
-  then normalizeOptionSet eval.options
+  then normalizeOptionSet (arg.options or eval.options)
   else { }
@@ -3,16 +3,85 @@ use std::path::Path;
 use std::process::Stdio;
 
 use anyhow::{ensure, Context, Result};
-use serde::{de, Deserialize};
-use syntax::semantic::escape_string;
+use serde::{de, Deserialize, Serialize};
+use syntax::semantic::{escape_literal_attr, escape_string};
 use tokio::process::Command;
 
+use crate::FlakeUrl;
+
 pub async fn eval_all_options(nix_command: &Path, nixpkgs_path: &Path) -> Result<NixosOptions> {
+    let nixpkgs_path = nixpkgs_path_expr(nixpkgs_path)?;
+    eval_options(nix_command, &nixpkgs_path).await
+}
+
+/// Evaluate options of the configuration `<output>.<config_name>` of a flake, like
+/// `nixosConfigurations.<name>` of NixOS or `homeConfigurations.<name>` of home-manager,
+/// including options declared by its own modules.
+/// `nixpkgs_path` is only used for the `lib`.
+pub async fn eval_flake_config_options(
+    nix_command: &Path,
+    nixpkgs_path: &Path,
+    flake_url: &FlakeUrl,
+    output: &str,
+    config_name: &str,
+) -> Result<NixosOptions> {
+    let arg = format!(
+        "{{ nixpkgs = {}; options = (builtins.getFlake {}).{}.{}.options; }}",
+        nixpkgs_path_expr(nixpkgs_path)?,
+        escape_string(flake_url.as_str()),
+        escape_literal_attr(output),
+        escape_literal_attr(config_name),
+    );
+    eval_options(nix_command, &arg).await
+}
+
+/// Evaluate all options of home-manager at `home_manager_path`, like the `home-manager` flake
+/// input, with `pkgs` from `nixpkgs_path`.
+pub async fn eval_home_manager_options(
+    nix_command: &Path,
+    nixpkgs_path: &Path,
+    home_manager_path: &Path,
+) -> Result<NixosOptions> {
+    let nixpkgs_path = nixpkgs_path_expr(nixpkgs_path)?;
+    let arg = format!(
+        "{{ nixpkgs = {nixpkgs_path}; options = (import ({} + \"/modules\") {{ configuration = {{ }}; pkgs = import {nixpkgs_path} {{ }}; check = false; }}).options; }}",
+        source_path_expr(home_manager_path)?,
+    );
+    eval_options(nix_command, &arg).await
+}
+
+/// Evaluate all options of nix-darwin at `darwin_path`, like the `nix-darwin` flake input,
+/// with `pkgs` from `nixpkgs_path`.
+pub async fn eval_darwin_options(
+    nix_command: &Path,
+    nixpkgs_path: &Path,
+    darwin_path: &Path,
+) -> Result<NixosOptions> {
+    let nixpkgs_path = nixpkgs_path_expr(nixpkgs_path)?;
+    let arg = format!(
+        "{{ nixpkgs = {nixpkgs_path}; options = (import {} {{ nixpkgs = {nixpkgs_path}; configuration = {{ }}; }}).options; }}",
+        source_path_expr(darwin_path)?,
+    );
+    eval_options(nix_command, &arg).await
+}
+
+fn source_path_expr(path: &Path) -> Result<String> {
+    let path = path
+        .to_str()
+        .filter(|path| path.starts_with('/'))
+        .with_context(|| format!("Invalid source path: {}", path.display()))?;
+    Ok(escape_string(path))
+}
+
+pub(crate) fn nixpkgs_path_expr(nixpkgs_path: &Path) -> Result<String> {
     let nixpkgs_path = nixpkgs_path
         .to_str()
         .filter(|path| path.starts_with('/'))
         .with_context(|| format!("Invalid path to nixpkgs: {}", nixpkgs_path.display()))?;
+    Ok(escape_string(nixpkgs_path))
+}
 
+async fn eval_options(nix_command: &Path, arg_expr: &str) -> Result<NixosOptions> {
     let output = Command::new(nix_command)
         .kill_on_drop(true)
         .args([
@@ -24,7 +93,7 @@ pub async fn eval_all_options(nix_command: &Path, nixpkgs_path: &Path) -> Result
             "--json",
             "--show-trace",
             "--expr",
-            &escape_string(nixpkgs_path),
+            arg_expr,
             // Workaround: `--argstr` is broken currently.
             // https://github.com/NixOS/nix/issues/2678
             "--apply",
@@ -49,7 +118,7 @@ pub async fn eval_all_options(nix_command: &Path, nixpkgs_path: &Path) -> Result
 
 pub type NixosOptions = HashMap<String, NixosOption>;
 
-#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct NixosOption {
     pub description: Option<Doc>,
@@ -65,7 +134,7 @@ pub struct NixosOption {
     pub related_packages: Vec<RelatedPackage>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "_type")]
 pub enum Doc {
     #[serde(rename = "mdDoc")]
@@ -74,7 +143,7 @@ pub enum Doc {
     Other,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "_type")]
 pub enum Value {
     #[serde(rename = "literalExpression")]
@@ -86,7 +155,7 @@ pub enum Value {
 }
 
 // https://github.com/NixOS/nixpkgs/blob/28c1aac72e3aef70b8c898ea9c16d5907f9eae22/nixos/lib/make-options-doc/default.nix#L61
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct RelatedPackage {
     pub path: Vec<String>,
     pub comment: Option<String>,
@@ -130,7 +199,7 @@ impl<'de> de::Deserialize<'de> for RelatedPackage {
     }
 }
 
-#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase", tag = "name")]
 pub enum Ty {
     #[default]
@@ -141,6 +210,11 @@ pub enum Ty {
     String,
     Path,
     Derivation,
+    /// `types.enum` with allowed string values.
+    Enum {
+        #[serde(default)]
+        values: Vec<String>,
+    },
     List {
         elem: Box<Ty>,
     },
@@ -155,12 +229,176 @@ pub enum Ty {
     },
 }
 
+/// Load options from a prebuilt `options.json`, as generated by
+/// `nixos/lib/make-options-doc` for the NixOS manual.
+/// It is a flat object from option names to declarations, which is converted into the hierarchy.
+pub fn from_options_json(src: &str) -> Result<NixosOptions> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct JsonOption {
+        /// Since NixOS 23.05. Only names are split on `.` before.
+        loc: Option<Vec<String>>,
+        #[serde(rename = "type")]
+        ty: String,
+        description: Option<serde_json::Value>,
+        #[serde(default)]
+        declarations: Vec<serde_json::Value>,
+        #[serde(default)]
+        read_only: bool,
+        default: Option<serde_json::Value>,
+        example: Option<serde_json::Value>,
+    }
+
+    let json =
+        serde_json::from_str::<HashMap<String, JsonOption>>(src).context("Invalid options.json")?;
+    let mut root = Ty::Attrset {
+        fields: NixosOptions::new(),
+        rest: None,
+    };
+    // Parents before children.
+    let mut json = json.into_iter().collect::<Vec<_>>();
+    json.sort_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs));
+    for (name, opt) in json {
+        let loc = opt
+            .loc
+            .unwrap_or_else(|| name.split('.').map(Into::into).collect());
+        let Some((last, parents)) = loc.split_last() else {
+            continue;
+        };
+        let mut ty = &mut root;
+        for seg in parents {
+            ty = child_ty(ty, seg);
+        }
+        let Ty::Attrset { fields, .. } = attrset_ty(ty) else {
+            unreachable!()
+        };
+        let entry = fields.entry(last.clone()).or_default();
+        entry.ty = parse_type_description(&opt.ty);
+        entry.description = opt.description.and_then(|v| match v {
+            serde_json::Value::String(text) => Some(Doc::Markdown { text }),
+            v => serde_json::from_value(v).ok(),
+        });
+        entry.declarations = opt
+            .declarations
+            .into_iter()
+            .filter_map(|decl| match decl {
+                serde_json::Value::String(path) => Some(path),
+                decl => Some(decl.get("name")?.as_str()?.to_owned()),
+            })
+            .collect();
+        entry.read_only = opt.read_only;
+        entry.default = opt.default.and_then(|v| serde_json::from_value(v).ok());
+        entry.example = opt.example.and_then(|v| serde_json::from_value(v).ok());
+    }
+    let Ty::Attrset { fields, .. } = root else {
+        unreachable!()
+    };
+    Ok(fields)
+}
+
+/// Coerce `ty` into an attrset, keeping the existing fields.
+fn attrset_ty(ty: &mut Ty) -> &mut Ty {
+    if !matches!(ty, Ty::Attrset { .. }) {
+        *ty = Ty::Attrset {
+            fields: NixosOptions::new(),
+            rest: None,
+        };
+    }
+    ty
+}
+
+/// The type of the child `seg` of `ty`, where `<name>` is any attribute and `*` is any element.
+fn child_ty<'a>(ty: &'a mut Ty, seg: &str) -> &'a mut Ty {
+    if seg == "*" {
+        if !matches!(ty, Ty::List { .. }) {
+            *ty = Ty::List {
+                elem: Box::new(Ty::Any),
+            };
+        }
+        let Ty::List { elem } = ty else { unreachable!() };
+        return elem;
+    }
+    let Ty::Attrset { fields, rest } = attrset_ty(ty) else {
+        unreachable!()
+    };
+    if seg.starts_with('<') && seg.ends_with('>') {
+        rest.get_or_insert_with(|| Box::new(Ty::Any))
+    } else {
+        &mut fields.entry(seg.to_owned()).or_default().ty
+    }
+}
+
+/// Parse a type description of `options.json`, like `list of (attribute set of string)`.
+/// Unions and unknown types are `Any`.
+fn parse_type_description(desc: &str) -> Ty {
+    let desc = desc.trim();
+    if let Some(rest) = desc.strip_prefix("null or ") {
+        return parse_type_description(rest);
+    }
+    if let Some(inner) = desc
+        .strip_prefix('(')
+        .and_then(|s| s.strip_suffix(')'))
+        .filter(|inner| !inner.contains(['(', ')']))
+    {
+        return parse_type_description(inner);
+    }
+    if let Some(elem) = desc.strip_prefix("list of ") {
+        return Ty::List {
+            elem: Box::new(parse_type_description(elem)),
+        };
+    }
+    if let Some(elem) = desc
+        .strip_prefix("attribute set of ")
+        .or_else(|| desc.strip_prefix("lazy attribute set of "))
+    {
+        return Ty::Attrset {
+            fields: NixosOptions::new(),
+            rest: Some(Box::new(parse_type_description(elem))),
+        };
+    }
+    if let Some(to) = desc
+        .strip_prefix("function that evaluates to a(n) ")
+        .or_else(|| desc.strip_prefix("function that evaluates to a "))
+    {
+        return Ty::Lambda {
+            from: Box::new(Ty::Any),
+            to: Box::new(parse_type_description(to)),
+        };
+    }
+    // Only string values are kept, like `types.enum` in evaluated options.
+    if let Some(values) = desc.strip_prefix("one of ") {
+        let values = values
+            .split(", ")
+            .filter_map(|v| serde_json::from_str::<String>(v).ok())
+            .collect();
+        return Ty::Enum { values };
+    }
+    if desc.contains(" or ") {
+        return Ty::Any;
+    }
+    match desc {
+        "boolean" => Ty::Bool,
+        "floating point number" | "signed integer or floating point number" => Ty::Float,
+        "path" | "absolute path" => Ty::Path,
+        "package" => Ty::Derivation,
+        "submodule" => Ty::Attrset {
+            fields: NixosOptions::new(),
+            rest: None,
+        },
+        "attribute set" => Ty::Attrset {
+            fields: NixosOptions::new(),
+            rest: Some(Box::new(Ty::Any)),
+        },
+        _ if desc.contains("integer") => Ty::Int,
+        _ if desc.to_lowercase().contains("string") => Ty::String,
+        _ => Ty::Any,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use tokio::sync::OnceCell;
 
-    use crate::FlakeUrl;
-
     use super::*;
 
     async fn check_nixpkgs(name: &str) {
@@ -225,4 +463,158 @@ mod tests {
     async fn nixos_23_05() {
         check_nixpkgs("nixos-23-05").await;
     }
+
+    #[test]
+    fn type_description() {
+        let list = |elem| Ty::List {
+            elem: Box::new(elem),
+        };
+        let attrs = |rest| Ty::Attrset {
+            fields: NixosOptions::new(),
+            rest: Some(Box::new(rest)),
+        };
+        assert_eq!(parse_type_description("boolean"), Ty::Bool);
+        assert_eq!(parse_type_description("null or boolean"), Ty::Bool);
+        assert_eq!(
+            parse_type_description("16 bit unsigned integer; between 0 and 65535 (both inclusive)"),
+            Ty::Int
+        );
+        assert_eq!(
+            parse_type_description("strings concatenated with \"\\n\""),
+            Ty::String
+        );
+        assert_eq!(
+            parse_type_description("list of (list of string)"),
+            list(list(Ty::String))
+        );
+        assert_eq!(
+            parse_type_description("attribute set of (submodule)"),
+            attrs(Ty::Attrset {
+                fields: NixosOptions::new(),
+                rest: None
+            })
+        );
+        assert_eq!(
+            parse_type_description(r#"one of "fast", "slow", 42"#),
+            Ty::Enum {
+                values: vec!["fast".into(), "slow".into()]
+            },
+        );
+        assert_eq!(parse_type_description("string or list of string"), Ty::Any);
+        assert_eq!(parse_type_description("package or path"), Ty::Any);
+    }
+
+    #[test]
+    fn options_json() {
+        let opts = from_options_json(
+            r#"{
+                "nix.enable": {
+                    "loc": ["nix", "enable"],
+                    "type": "boolean",
+                    "description": "Whether to enable Nix.",
+                    "declarations": ["nixos/modules/config/nix.nix"],
+                    "default": { "_type": "literalExpression", "text": "true" },
+                    "readOnly": false
+                },
+                "users.users": {
+                    "loc": ["users", "users"],
+                    "type": "attribute set of (submodule)",
+                    "description": "Additional user accounts."
+                },
+                "users.users.<name>.home": {
+                    "loc": ["users", "users", "<name>", "home"],
+                    "type": "string with the path",
+                    "description": "The user's home directory."
+                },
+                "boot.kernelPatches.*.name": {
+                    "type": "string",
+                    "description": { "_type": "mdDoc", "text": "Name of the patch." }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let Ty::Attrset { fields, .. } = &opts["nix"].ty else {
+            panic!("Invalid options: {opts:?}");
+        };
+        let opt = &fields["enable"];
+        assert_eq!(opt.ty, Ty::Bool);
+        assert_eq!(
+            opt.description,
+            Some(Doc::Markdown {
+                text: "Whether to enable Nix.".into()
+            })
+        );
+        assert_eq!(opt.declarations, ["nixos/modules/config/nix.nix"]);
+        assert_eq!(
+            opt.default,
+            Some(Value::Expression {
+                text: "true".into()
+            })
+        );
+
+        let Ty::Attrset { fields, .. } = &opts["users"].ty else {
+            panic!("Invalid options: {opts:?}");
+        };
+        let users = &fields["users"];
+        assert!(users.description.is_some());
+        let Ty::Attrset {
+            rest: Some(user), ..
+        } = &users.ty
+        else {
+            panic!("Invalid options: {users:?}");
+        };
+        let Ty::Attrset { fields, .. } = &**user else {
+            panic!("Invalid options: {user:?}");
+        };
+        assert_eq!(fields["home"].ty, Ty::String);
+
+        let Ty::Attrset { fields, .. } = &opts["boot"].ty else {
+            panic!("Invalid options: {opts:?}");
+        };
+        let Ty::List { elem } = &fields["kernelPatches"].ty else {
+            panic!("Invalid options: {opts:?}");
+        };
+        let Ty::Attrset { fields, .. } = &**elem else {
+            panic!("Invalid options: {opts:?}");
+        };
+        assert_eq!(
+            fields["name"].description,
+            Some(Doc::Markdown {
+                text: "Name of the patch.".into()
+            })
+        );
+    }
+
+    #[test]
+    fn serialize_roundtrip() {
+        let opt = NixosOption {
+            description: Some(Doc::Other),
+            declarations: vec!["nixos/modules/foo.nix".into()],
+            read_only: true,
+            ty: Ty::Enum {
+                values: vec!["a".into()],
+            },
+            default: Some(Value::Markdown { text: "b".into() }),
+            example: Some(Value::Other),
+            related_packages: vec![RelatedPackage {
+                path: vec!["pkgs".into(), "hello".into()],
+                comment: Some("c".into()),
+            }],
+        };
+        let opts = NixosOptions::from([(
+            "foo".into(),
+            NixosOption {
+                ty: Ty::Attrset {
+                    fields: NixosOptions::from([("bar".into(), opt)]),
+                    rest: Some(Box::new(Ty::List {
+                        elem: Box::new(Ty::Int),
+                    })),
+                },
+                ..NixosOption::default()
+            },
+        )]);
+        let json = serde_json::to_string(&opts).unwrap();
+        assert_eq!(serde_json::from_str::<NixosOptions>(&json).unwrap(), opts);
+    }
 }
@@ -0,0 +1,141 @@
+//! The database of renamed and removed top-level attributes of nixpkgs.
+//!
+//! It is a JSON object from the old attribute name to its entry, eg.
+//! ```json
+//! {
+//!   "gnome3": { "to": "gnome", "since": "21.11" },
+//!   "exa": { "to": "eza", "since": "23.11", "message": "exa is unmaintained" }
+//! }
+//! ```
+//! A small database is shipped, and users can supply their own entries.
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use serde::{de, Deserialize};
+
+const BUILTIN_PACKAGE_ALIASES: &str = include_str!("./package_aliases.json");
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct PackageAliases(HashMap<String, PackageAlias>);
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageAlias {
+    /// The new attribute path, or `None` if it is removed without a replacement.
+    #[serde(default)]
+    pub to: Option<String>,
+    /// The first nixpkgs release where the old name is deprecated.
+    /// `None` means all releases.
+    #[serde(default)]
+    pub since: Option<NixpkgsVersion>,
+    /// An additional explanation.
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl PackageAliases {
+    /// The database shipped with nil.
+    pub fn builtin() -> Self {
+        Self::from_json(BUILTIN_PACKAGE_ALIASES).expect("Builtin package aliases are valid")
+    }
+
+    pub fn from_json(src: &str) -> Result<Self> {
+        serde_json::from_str(src).context("Invalid package aliases")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PackageAlias> {
+        self.0.get(name)
+    }
+
+    /// Add entries from `other`, which take precedence over existing ones with the same name.
+    pub fn extend(&mut self, other: Self) {
+        self.0.extend(other.0);
+    }
+
+    /// Only keep entries which are already deprecated in the nixpkgs release `version`.
+    pub fn retain_version(&mut self, version: NixpkgsVersion) {
+        self.0
+            .retain(|_, alias| alias.since.map_or(true, |since| since <= version));
+    }
+}
+
+impl FromIterator<(String, PackageAlias)> for PackageAliases {
+    fn from_iter<T: IntoIterator<Item = (String, PackageAlias)>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+/// A nixpkgs release like `23.11`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NixpkgsVersion {
+    pub year: u16,
+    pub month: u8,
+}
+
+impl FromStr for NixpkgsVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        (|| {
+            let (year, month) = s.split_once('.')?;
+            let year = year.parse().ok()?;
+            let month = month.parse().ok().filter(|m| (1..=12).contains(m))?;
+            Some(Self { year, month })
+        })()
+        .with_context(|| format!("invalid nixpkgs version {s:?}, expecting YY.MM like \"23.11\""))
+    }
+}
+
+impl fmt::Display for NixpkgsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:02}", self.year, self.month)
+    }
+}
+
+impl<'de> Deserialize<'de> for NixpkgsVersion {
+    fn deserialize<D: de::Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(de)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NixpkgsVersion, PackageAliases};
+
+    #[test]
+    fn builtin() {
+        let aliases = PackageAliases::builtin();
+        assert!(!aliases.is_empty());
+        assert_eq!(aliases.get("gnome3").unwrap().to.as_deref(), Some("gnome"));
+    }
+
+    #[test]
+    fn version() {
+        let v = |s: &str| s.parse::<NixpkgsVersion>().unwrap();
+        assert_eq!(v("23.05").to_string(), "23.05");
+        assert!(v("21.11") < v("22.05"));
+        assert!("23".parse::<NixpkgsVersion>().is_err());
+        assert!("23.13".parse::<NixpkgsVersion>().is_err());
+
+        let mut aliases = PackageAliases::from_json(
+            r#"{ "a": { "to": "b", "since": "22.05" }, "c": { "since": "23.11" }, "d": { } }"#,
+        )
+        .unwrap();
+        aliases.retain_version(v("23.05"));
+        let mut names = aliases.0.keys().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a", "d"]);
+    }
+}
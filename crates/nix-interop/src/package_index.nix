@@ -0,0 +1,24 @@
+# Shallowly collect top-level attributes of nixpkgs with their versions and descriptions.
+# Attributes failing to evaluate, like removed aliases, are skipped. Non-derivations, like
+# package sets, are kept with empty information.
+nixpkgs:
+let
+  inherit (builtins) tryEval deepSeq isAttrs isString mapAttrs filter attrNames listToAttrs;
+
+  pkgs = import nixpkgs {
+    config = { allowAliases = false; };
+    overlays = [ ];
+  };
+
+  strOrNull = v: if isString v then v else null;
+
+  info = v:
+    if isAttrs v && v.type or null == "derivation" then {
+      version = strOrNull (v.version or null);
+      description = strOrNull (v.meta.description or null);
+    } else { };
+
+  eval = mapAttrs (_: v: tryEval (let i = info v; in deepSeq i i)) pkgs;
+in
+  listToAttrs (map (name: { inherit name; value = eval.${name}.value; })
+    (filter (name: eval.${name}.success) (attrNames eval)))
@@ -0,0 +1,129 @@
+//! The index of top-level packages of nixpkgs, for completion of `pkgs.name`.
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Stdio;
+
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use syntax::semantic::escape_string;
+use tokio::process::Command;
+
+use crate::installable::AttrPos;
+use crate::nixos_options::nixpkgs_path_expr;
+
+/// Top-level attribute names of nixpkgs to their information.
+pub type PackageIndex = HashMap<String, PackageInfo>;
+
+/// The information of a top-level attribute. Both are `None` for non-derivations.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageInfo {
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// Evaluate the index of nixpkgs at `nixpkgs_path`, for the current system.
+/// It evaluates every top-level attribute, thus is slow and should be done in background.
+pub async fn eval_package_index(nix_command: &Path, nixpkgs_path: &Path) -> Result<PackageIndex> {
+    let nixpkgs_path = nixpkgs_path_expr(nixpkgs_path)?;
+    let output = Command::new(nix_command)
+        .kill_on_drop(true)
+        .args([
+            "eval",
+            "--experimental-features",
+            "nix-command",
+            "--read-only",
+            "--impure",
+            "--json",
+            "--expr",
+            &nixpkgs_path,
+            "--apply",
+            include_str!("./package_index.nix"),
+        ])
+        .stdin(Stdio::null())
+        // Configures stdout/stderr automatically.
+        .output()
+        .await
+        .context("Failed to spawn `nix`")?;
+
+    ensure!(
+        output.status.success(),
+        "Nix eval failed with {}. Stderr:\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr),
+    );
+
+    from_json(&output.stdout)
+}
+
+/// Locate the definition of the top-level attribute `name` of nixpkgs at `nixpkgs_path`,
+/// which is usually in `all-packages.nix`.
+pub async fn package_pos(
+    nix_command: &Path,
+    nixpkgs_path: &Path,
+    name: &str,
+) -> Result<Option<AttrPos>> {
+    let expr = format!(
+        "builtins.unsafeGetAttrPos {} (import {} {{ }})",
+        escape_string(name),
+        nixpkgs_path_expr(nixpkgs_path)?,
+    );
+    let output = Command::new(nix_command)
+        .kill_on_drop(true)
+        .args([
+            "eval",
+            "--experimental-features",
+            "nix-command",
+            "--read-only",
+            "--impure",
+            "--json",
+            "--expr",
+            &expr,
+        ])
+        .stdin(Stdio::null())
+        // Configures stdout/stderr automatically.
+        .output()
+        .await
+        .context("Failed to spawn `nix`")?;
+
+    ensure!(
+        output.status.success(),
+        "Nix eval failed with {}. Stderr:\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr),
+    );
+
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+pub fn from_json(src: &[u8]) -> Result<PackageIndex> {
+    serde_json::from_slice(src).context("Invalid package index")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_json, PackageInfo};
+
+    #[test]
+    fn parse() {
+        let index = from_json(
+            br#"{
+                "ripgrep": { "version": "14.1.0", "description": "A fast grep" },
+                "hello": { "version": "2.12.1", "description": null },
+                "python3Packages": {}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(index.len(), 3);
+        assert_eq!(
+            index["ripgrep"],
+            PackageInfo {
+                version: Some("14.1.0".into()),
+                description: Some("A fast grep".into()),
+            }
+        );
+        assert_eq!(index["hello"].description, None);
+        assert_eq!(index["python3Packages"], PackageInfo::default());
+    }
+}
@@ -0,0 +1,121 @@
+//! Prefetch sources of fetchers to get their hashes, via `nix store prefetch-file` and
+//! `nix-prefetch-git`.
+use std::fmt;
+use std::path::Path;
+use std::process::Stdio;
+
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+/// The source fetched by a fetcher, whose hash is to be computed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum PrefetchSource {
+    /// A file at `url`, like `fetchurl`, or its unpacked content, like `fetchzip`.
+    #[serde(rename_all = "camelCase")]
+    Url { url: String, unpack: bool },
+    /// A checkout of a git repository at `rev`, like `fetchgit`.
+    #[serde(rename_all = "camelCase")]
+    Git {
+        url: String,
+        rev: String,
+        fetch_submodules: bool,
+    },
+}
+
+impl fmt::Display for PrefetchSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Url { url, .. } => f.write_str(url),
+            Self::Git { url, rev, .. } => write!(f, "{url} at {rev}"),
+        }
+    }
+}
+
+/// Fetch `source` and return its SHA-256 hash in the SRI format, like `sha256-...=`.
+pub async fn prefetch_hash(nix_command: &Path, source: &PrefetchSource) -> Result<String> {
+    match source {
+        PrefetchSource::Url { url, unpack } => {
+            #[derive(Deserialize)]
+            struct Output {
+                hash: String,
+            }
+
+            let mut args = vec!["store", "prefetch-file", "--json", "--hash-type", "sha256"];
+            if *unpack {
+                args.push("--unpack");
+            }
+            args.push(url);
+            let mut cmd = Command::new(nix_command);
+            cmd.args(["--experimental-features", "nix-command"])
+                .args(&args);
+            let out = run(cmd, &format!("nix {}", args.join(" "))).await?;
+            Ok(serde_json::from_str::<Output>(&out)?.hash)
+        }
+        PrefetchSource::Git {
+            url,
+            rev,
+            fetch_submodules,
+        } => {
+            #[derive(Deserialize)]
+            struct Output {
+                /// Only available in newer versions.
+                hash: Option<String>,
+                /// In the Nix base-32 format.
+                sha256: String,
+            }
+
+            let mut args = vec!["--quiet", "--url", url, "--rev", rev];
+            if *fetch_submodules {
+                args.push("--fetch-submodules");
+            }
+            let mut cmd = Command::new("nix-prefetch-git");
+            cmd.args(&args);
+            let out = run(cmd, &format!("nix-prefetch-git {}", args.join(" "))).await?;
+            let out = serde_json::from_str::<Output>(&out)?;
+            if let Some(hash) = out.hash {
+                return Ok(hash);
+            }
+            let args = ["hash", "to-sri", "--type", "sha256", &out.sha256];
+            let mut cmd = Command::new(nix_command);
+            cmd.args(["--experimental-features", "nix-command"])
+                .args(args);
+            let out = run(cmd, &format!("nix {}", args.join(" "))).await?;
+            Ok(out.trim().to_owned())
+        }
+    }
+}
+
+async fn run(mut cmd: Command, display: &str) -> Result<String> {
+    let output = cmd
+        .kill_on_drop(true)
+        .stdin(Stdio::null())
+        // Configures stdout/stderr automatically.
+        .output()
+        .await
+        .with_context(|| format!("Failed to spawn `{display}`"))?;
+    ensure!(
+        output.status.success(),
+        "`{display}` failed with {}.\nStderr: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr),
+    );
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires calling 'nix' and network access"]
+    async fn prefetch_url() {
+        let source = PrefetchSource::Url {
+            url: "https://github.com/NixOS/nixpkgs/raw/23.05/.version".into(),
+            unpack: false,
+        };
+        let hash = prefetch_hash("nix".as_ref(), &source).await.unwrap();
+        assert!(hash.starts_with("sha256-"), "{hash}");
+    }
+}
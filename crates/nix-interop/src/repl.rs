@@ -0,0 +1,159 @@
+//! A long-running `nix repl` process, evaluating expressions and printing their values one by
+//! one, so that the startup cost is paid only once.
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use anyhow::{bail, Context, Result};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command};
+
+use crate::eval::EvalOptions;
+
+/// The prompt, which may be printed even if stdin is not a terminal.
+const PROMPT: &str = "nix-repl> ";
+
+/// Printed after each evaluation, to know where the output ends.
+const END_MARKER: &str = "nil-repl-end";
+
+#[derive(Debug)]
+pub struct Repl {
+    nix_command: PathBuf,
+    opts: EvalOptions,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    stderr: Lines<BufReader<ChildStderr>>,
+    /// The number of evaluations, to make end markers unique.
+    eval_cnt: u64,
+    /// Killed on drop.
+    _child: Child,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplOutput {
+    /// The printed value. Empty if the evaluation failed.
+    pub value: String,
+    /// Errors, warnings and traces printed during the evaluation.
+    pub messages: String,
+}
+
+impl Repl {
+    /// Spawn `nix repl` in `opts.base_dir`, where relative paths in expressions are resolved.
+    pub fn spawn(nix_command: &Path, opts: &EvalOptions) -> Result<Self> {
+        let mut command = Command::new(nix_command);
+        command
+            .kill_on_drop(true)
+            .args(["repl", "--experimental-features", "nix-command flakes"])
+            .env("NO_COLOR", "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        opts.apply(&mut command);
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Failed to spawn {nix_command:?}"))?;
+        Ok(Self {
+            nix_command: nix_command.to_owned(),
+            opts: opts.clone(),
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()).lines(),
+            stderr: BufReader::new(child.stderr.take().unwrap()).lines(),
+            eval_cnt: 0,
+            _child: child,
+        })
+    }
+
+    /// Whether this process is spawned with the same command and options, thus reusable.
+    pub fn is_spawned_with(&self, nix_command: &Path, opts: &EvalOptions) -> bool {
+        self.nix_command == nix_command && self.opts == *opts
+    }
+
+    /// Evaluate `expr`, which may span multiple lines, and return what is printed.
+    /// Outputs are out of sync if this fails or is cancelled, thus the process should be dropped.
+    pub async fn eval(&mut self, expr: &str) -> Result<ReplOutput> {
+        self.eval_cnt += 1;
+        let marker = format!("{END_MARKER}-{}", self.eval_cnt);
+        // Parenthesized, so that the REPL keeps reading until the whole expression is complete.
+        // The marker is printed to both stdout and stderr.
+        let input = format!("(\n{expr}\n)\nbuiltins.trace \"{marker}\" \"{marker}\"\n");
+        self.stdin
+            .write_all(input.as_bytes())
+            .await
+            .context("Failed to write to nix repl")?;
+        self.stdin.flush().await?;
+
+        let (stdout_end, stderr_end) = (format!("\"{marker}\""), format!("trace: {marker}"));
+        let (value, messages) = tokio::try_join!(
+            read_until(&mut self.stdout, &stdout_end),
+            read_until(&mut self.stderr, &stderr_end),
+        )?;
+        // The greeting is printed before the first evaluation.
+        let messages = messages
+            .lines()
+            .filter(|line| !line.starts_with("Welcome to Nix"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(ReplOutput {
+            value,
+            messages: messages.trim().to_owned(),
+        })
+    }
+}
+
+/// Read lines until the `end` line, and return lines before it without prompts.
+async fn read_until(
+    lines: &mut Lines<BufReader<impl AsyncRead + Unpin>>,
+    end: &str,
+) -> Result<String> {
+    let mut out = String::new();
+    while let Some(line) = lines.next_line().await? {
+        let line = strip_prompts(&line);
+        if line.trim_end() == end {
+            return Ok(out.trim().to_owned());
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    bail!("nix repl exited unexpectedly");
+}
+
+fn strip_prompts(mut line: &str) -> &str {
+    while let Some(rest) = line.strip_prefix(PROMPT) {
+        line = rest;
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompts() {
+        assert_eq!(strip_prompts("nix-repl> nix-repl> 2"), "2");
+        assert_eq!(strip_prompts("{ a = 1; }"), "{ a = 1; }");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires calling 'nix'"]
+    async fn eval() {
+        let opts = EvalOptions {
+            base_dir: std::env::temp_dir(),
+            search_path: Vec::new(),
+            restrict_eval: false,
+            allowed_paths: Vec::new(),
+            memory_limit: None,
+        };
+        let mut repl = Repl::spawn("nix".as_ref(), &opts).unwrap();
+        let out = repl.eval("let\n  a = 1;\nin\na + 1").await.unwrap();
+        assert_eq!(out.value, "2");
+        assert_eq!(out.messages, "");
+
+        let out = repl.eval("throw \"foo\"").await.unwrap();
+        assert_eq!(out.value, "");
+        assert!(out.messages.contains("foo"), "{}", out.messages);
+
+        let out = repl.eval("builtins.trace 1 [ 2 ]").await.unwrap();
+        assert_eq!(out.value, "[ 2 ]");
+        assert!(out.messages.contains("trace: 1"), "{}", out.messages);
+    }
+}
@@ -0,0 +1,202 @@
+//! The search path for `<name/path>` lookups, usually configured by `NIX_PATH`.
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+pub const NIX_PATH_ENV: &str = "NIX_PATH";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SearchPath {
+    entries: Vec<SearchPathEntry>,
+    /// Paths known to exist on the disk, filled by `SearchPath::check_existing`.
+    existing: BTreeSet<PathBuf>,
+}
+
+/// An entry `prefix=path`, or a plain `path` with an empty prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchPathEntry {
+    pub prefix: String,
+    pub path: PathBuf,
+}
+
+impl SearchPath {
+    /// The search path from the `NIX_PATH` environment variable.
+    pub fn from_env() -> Self {
+        env::var(NIX_PATH_ENV)
+            .map(|s| Self::parse(&s))
+            .unwrap_or_default()
+    }
+
+    /// Parse a `:`-separated search path like `NIX_PATH`.
+    /// Entries which are not local directories, like URLs and `flake:` references, are skipped.
+    pub fn parse(s: &str) -> Self {
+        let mut entries = Vec::new();
+        let mut segments = s.split(':').peekable();
+        while let Some(seg) = segments.next() {
+            let mut entry = seg.to_owned();
+            // `:` of URL schemes, like `nixpkgs=https://...`.
+            while segments.peek().map_or(false, |next| next.starts_with("//")) {
+                entry.push(':');
+                entry.push_str(segments.next().unwrap());
+            }
+            let (prefix, path) = entry.split_once('=').unwrap_or(("", &entry));
+            if Path::new(path).is_absolute() {
+                entries.push(SearchPathEntry {
+                    prefix: prefix.to_owned(),
+                    path: path.into(),
+                });
+            }
+        }
+        Self {
+            entries,
+            existing: BTreeSet::new(),
+        }
+    }
+
+    /// Prepend an entry, which takes precedence over all existing ones.
+    pub fn prepend(&mut self, prefix: impl Into<String>, path: impl Into<PathBuf>) {
+        self.entries.insert(
+            0,
+            SearchPathEntry {
+                prefix: prefix.into(),
+                path: path.into(),
+            },
+        );
+    }
+
+    pub fn entries(&self) -> &[SearchPathEntry] {
+        &self.entries
+    }
+
+    /// Record which entries exist on the disk, for `SearchPath::resolve_existing` without I/O.
+    /// Prefixed entries are recorded as a whole, and unprefixed entries by their direct
+    /// children, since their candidates are `entry/name`.
+    pub fn check_existing(&mut self) {
+        self.existing.clear();
+        for entry in &self.entries {
+            if !entry.prefix.is_empty() {
+                if entry.path.exists() {
+                    self.existing.insert(entry.path.clone());
+                }
+            } else if let Ok(dir) = fs::read_dir(&entry.path) {
+                self.existing
+                    .extend(dir.filter_map(|ent| Some(ent.ok()?.path())));
+            }
+        }
+    }
+
+    /// Mark a path as existing, like `SearchPath::check_existing` does.
+    pub fn insert_existing(&mut self, path: impl Into<PathBuf>) {
+        self.existing.insert(path.into());
+    }
+
+    /// Resolve `<path>` like Nix, to the first candidate satisfying `exists`.
+    pub fn resolve(&self, path: &str, exists: impl Fn(&Path) -> bool) -> Option<PathBuf> {
+        self.entries.iter().find_map(|entry| {
+            let candidate = entry.candidate(path)?;
+            exists(&candidate).then_some(candidate)
+        })
+    }
+
+    /// Resolve `<path>` without I/O, to the first candidate satisfying `exists`, or whose entry
+    /// is recorded by `SearchPath::check_existing`.
+    pub fn resolve_existing(&self, path: &str, exists: impl Fn(&Path) -> bool) -> Option<PathBuf> {
+        self.entries.iter().find_map(|entry| {
+            let candidate = entry.candidate(path)?;
+            let recorded = if entry.prefix.is_empty() {
+                entry.path.join(path.split('/').next()?)
+            } else {
+                entry.path.clone()
+            };
+            (exists(&candidate) || self.existing.contains(&recorded)).then_some(candidate)
+        })
+    }
+}
+
+impl SearchPathEntry {
+    fn candidate(&self, path: &str) -> Option<PathBuf> {
+        let rest = if self.prefix.is_empty() {
+            path
+        } else if path == self.prefix {
+            ""
+        } else {
+            path.strip_prefix(&self.prefix)?.strip_prefix('/')?
+        };
+        Some(if rest.is_empty() {
+            self.path.clone()
+        } else {
+            self.path.join(rest)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SearchPath;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn parse() {
+        let search_path = SearchPath::parse(
+            "nixpkgs=/nix/var/nix/profiles/per-user/root/channels/nixpkgs:\
+            home-manager=https://github.com/nix-community/home-manager/archive/master.tar.gz:\
+            flake:nixpkgs:/etc/nix/path:relative",
+        );
+        let got = search_path
+            .entries()
+            .iter()
+            .map(|entry| (&*entry.prefix, entry.path.to_str().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            got,
+            [
+                (
+                    "nixpkgs",
+                    "/nix/var/nix/profiles/per-user/root/channels/nixpkgs"
+                ),
+                ("", "/etc/nix/path"),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve() {
+        let mut search_path = SearchPath::parse("nixpkgs=/a:/b");
+        search_path.prepend("nixpkgs/lib", "/lib");
+        let exists = |path: &Path| path != Path::new("/b/foo");
+        let resolve = |path| search_path.resolve(path, exists);
+        assert_eq!(resolve("nixpkgs"), Some(PathBuf::from("/a")));
+        assert_eq!(resolve("nixpkgs/lib"), Some(PathBuf::from("/lib")));
+        assert_eq!(
+            resolve("nixpkgs/lib/default.nix"),
+            Some(PathBuf::from("/lib/default.nix"))
+        );
+        assert_eq!(
+            resolve("nixpkgs-foo"),
+            Some(PathBuf::from("/b/nixpkgs-foo"))
+        );
+        assert_eq!(resolve("foo"), None);
+    }
+
+    #[test]
+    fn check_existing() {
+        let dir = std::env::temp_dir().join(format!("nil-search-path-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("channels/nixpkgs")).unwrap();
+        let mut search_path = SearchPath::parse(&format!(
+            "foo={}:bar={}:{}",
+            dir.display(),
+            dir.join("missing").display(),
+            dir.join("channels").display(),
+        ));
+        search_path.check_existing();
+        let resolve = |path| search_path.resolve_existing(path, |_| false);
+        assert_eq!(resolve("foo/a"), Some(dir.join("a")));
+        assert_eq!(resolve("bar"), None);
+        assert_eq!(
+            resolve("nixpkgs/lib"),
+            Some(dir.join("channels/nixpkgs/lib"))
+        );
+        assert_eq!(resolve("home-manager"), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
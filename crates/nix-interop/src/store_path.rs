@@ -0,0 +1,132 @@
+//! Store paths referenced by strings and path literals, and their metadata registered in the
+//! local store, queried via `nix path-info`.
+use std::path::Path;
+use std::process::Stdio;
+
+use anyhow::{ensure, Context, Result};
+use serde::Deserialize;
+use tokio::process::Command;
+
+/// The default Nix store.
+pub const NIX_STORE_DIR: &str = "/nix/store";
+
+/// The length of hashes in store path names, in the Nix base-32 format.
+const HASH_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorePathInfo {
+    /// The size of the serialized path itself, in bytes.
+    pub nar_size: u64,
+    /// The size of the path and all its dependencies, in bytes.
+    pub closure_size: Option<u64>,
+    /// The derivation building the path, if known.
+    pub deriver: Option<String>,
+}
+
+/// The top-level store path of a path inside the store,
+/// like `/nix/store/<hash>-foo` of `/nix/store/<hash>-foo/bin/foo`.
+pub fn store_path_root(path: &str) -> Option<&str> {
+    let rest = path.strip_prefix(NIX_STORE_DIR)?.strip_prefix('/')?;
+    let base_name = rest.split('/').next()?;
+    let hash = base_name.get(..HASH_LEN)?;
+    let name = base_name[HASH_LEN..].strip_prefix('-')?;
+    if !hash.bytes().all(|b| b.is_ascii_alphanumeric()) || name.is_empty() {
+        return None;
+    }
+    Some(&path[..path.len() - rest.len() + base_name.len()])
+}
+
+/// Query the metadata of `store_path`, or `None` if it does not exist locally.
+pub async fn path_info(nix_command: &Path, store_path: &Path) -> Result<Option<StorePathInfo>> {
+    if !store_path.exists() {
+        return Ok(None);
+    }
+    let output = Command::new(nix_command)
+        .kill_on_drop(true)
+        .args([
+            "path-info",
+            "--experimental-features",
+            "nix-command",
+            "--json",
+            "--closure-size",
+        ])
+        .arg(store_path)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .with_context(|| format!("Failed to spawn {nix_command:?}"))?;
+    ensure!(
+        output.status.success(),
+        "`nix path-info {}` failed with {}.\nStderr: {}",
+        store_path.display(),
+        output.status,
+        String::from_utf8_lossy(&output.stderr),
+    );
+    parse_path_info(&output.stdout)
+}
+
+/// Parse the output of `nix path-info --json` for a single path. It is an array of objects with
+/// `path` fields before Nix 2.19, and an object keyed by paths since then.
+/// Unregistered paths have no info.
+fn parse_path_info(json: &[u8]) -> Result<Option<StorePathInfo>> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Output {
+        Legacy(Vec<Option<StorePathInfo>>),
+        Keyed(std::collections::HashMap<String, Option<StorePathInfo>>),
+    }
+
+    let infos = match serde_json::from_slice::<Output>(json)? {
+        Output::Legacy(infos) => infos,
+        Output::Keyed(infos) => infos.into_values().collect(),
+    };
+    Ok(infos.into_iter().next().flatten())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root() {
+        let hash = "0123456789abcdfghijklmnpqrsvwxyz";
+        let path = format!("/nix/store/{hash}-hello-2.12");
+        assert_eq!(store_path_root(&path), Some(&*path));
+        assert_eq!(store_path_root(&format!("{path}/bin/hello")), Some(&*path));
+        assert_eq!(store_path_root(&format!("/nix/store/{hash}")), None);
+        assert_eq!(store_path_root("/nix/store/short-hello"), None);
+        assert_eq!(store_path_root("/nix/store"), None);
+        assert_eq!(store_path_root("/etc/hello"), None);
+    }
+
+    #[test]
+    fn parse() {
+        let legacy = br#"[{"path":"/nix/store/a-b","narSize":10,"closureSize":30,"deriver":"/nix/store/c-b.drv","valid":true}]"#;
+        let info = StorePathInfo {
+            nar_size: 10,
+            closure_size: Some(30),
+            deriver: Some("/nix/store/c-b.drv".into()),
+        };
+        assert_eq!(parse_path_info(legacy).unwrap(), Some(info.clone()));
+
+        let keyed =
+            br#"{"/nix/store/a-b":{"narSize":10,"closureSize":30,"deriver":"/nix/store/c-b.drv"}}"#;
+        assert_eq!(parse_path_info(keyed).unwrap(), Some(info));
+
+        let unknown_deriver = br#"{"/nix/store/a-b":{"narSize":10,"deriver":null}}"#;
+        assert_eq!(
+            parse_path_info(unknown_deriver).unwrap(),
+            Some(StorePathInfo {
+                nar_size: 10,
+                closure_size: None,
+                deriver: None,
+            }),
+        );
+
+        assert_eq!(
+            parse_path_info(br#"{"/nix/store/a-b":null}"#).unwrap(),
+            None
+        );
+    }
+}
@@ -184,6 +184,35 @@ impl Template {
     }
 }
 
+/// The separator between the pattern and the template of a rule.
+pub const RULE_SEPARATOR: &str = "==>>";
+
+/// A replacement rule like `lib.optional $c [$e] ==>> lib.optionals $c [$e]`.
+#[derive(Debug)]
+pub struct Rule {
+    pattern: Pattern,
+    template: Template,
+}
+
+impl Rule {
+    pub fn parse(rule: &str) -> Result<Self> {
+        let (pat, templ) = rule
+            .split_once(RULE_SEPARATOR)
+            .with_context(|| format!("missing `{RULE_SEPARATOR}` in the rule"))?;
+        let pattern = Pattern::parse(pat.trim()).context("invalid pattern")?;
+        let template = Template::parse(templ.trim(), &pattern).context("invalid template")?;
+        Ok(Self { pattern, template })
+    }
+
+    pub fn replace_edits(&self, input: &SyntaxNode) -> Vec<(TextRange, String)> {
+        self.pattern.replace_edits(&self.template, input)
+    }
+
+    pub fn replace(&self, src: &str, input: &SyntaxNode) -> String {
+        self.pattern.replace(src, &self.template, input)
+    }
+}
+
 #[derive(Debug)]
 struct RawPattern {
     placeholders: IndexMap<TextRange, SmolStr>,
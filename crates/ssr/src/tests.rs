@@ -1,7 +1,7 @@
 use expect_test::{expect, Expect};
 use syntax::parse_file;
 
-use crate::Template;
+use crate::{Rule, Template};
 
 use super::Pattern;
 
@@ -85,3 +85,19 @@ fn replace_paren() {
         expect!["let a = assert 42; 42; in assert a; a"],
     );
 }
+
+#[test]
+fn rule() {
+    let src = "[ (lib.optional cond [ a ]) (lib.optional (x > 1) [ b c ]) ]";
+    let parse = parse_file(src);
+    let rule = Rule::parse("lib.optional $c [$e] ==>> lib.optionals $c [$e]").unwrap();
+    expect![[r#"[ (lib.optionals cond [a]) (lib.optional (x > 1) [ b c ]) ]"#]]
+        .assert_eq(&rule.replace(src, &parse.syntax_node()));
+
+    expect!["missing `==>>` in the rule"]
+        .assert_eq(&Rule::parse("$a + $b").unwrap_err().to_string());
+    expect!["invalid template: missing placeholder $c from source pattern"].assert_eq(&format!(
+        "{:#}",
+        Rule::parse("$a + $b ==>> $c").unwrap_err()
+    ));
+}
@@ -7,6 +7,11 @@ pub use rowan::ast::{AstChildren, AstNode};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum BinaryOpKind {
+    /// `x |> f`, the experimental pipe operator.
+    PipeInto,
+    /// `f <| x`, the experimental pipe operator.
+    PipeFrom,
+
     Imply,
     Or,
     And,
@@ -273,30 +278,31 @@ impl Expr {
 
                 // Binary and unary ops. They follow `infix_bp` in parser.
                 Expr::BinaryOp(e) => match e.op_kind()? {
-                    BinaryOpKind::Imply => 1,
-                    BinaryOpKind::Or => 3,
-                    BinaryOpKind::And => 5,
-                    BinaryOpKind::Equal | BinaryOpKind::NotEqual => 7,
+                    BinaryOpKind::PipeInto | BinaryOpKind::PipeFrom => 1,
+                    BinaryOpKind::Imply => 3,
+                    BinaryOpKind::Or => 5,
+                    BinaryOpKind::And => 7,
+                    BinaryOpKind::Equal | BinaryOpKind::NotEqual => 9,
                     BinaryOpKind::Less
                     | BinaryOpKind::Greater
                     | BinaryOpKind::LessEqual
-                    | BinaryOpKind::GreaterEqual => 9,
-                    BinaryOpKind::Update => 11,
-                    BinaryOpKind::Add | BinaryOpKind::Sub => 15,
-                    BinaryOpKind::Mul | BinaryOpKind::Div => 17,
-                    BinaryOpKind::Concat => 19,
+                    | BinaryOpKind::GreaterEqual => 11,
+                    BinaryOpKind::Update => 13,
+                    BinaryOpKind::Add | BinaryOpKind::Sub => 17,
+                    BinaryOpKind::Mul | BinaryOpKind::Div => 19,
+                    BinaryOpKind::Concat => 21,
                 },
                 Expr::UnaryOp(e) => match e.op_kind()? {
-                    UnaryOpKind::Not => 13,
-                    UnaryOpKind::Negate => 23,
+                    UnaryOpKind::Not => 15,
+                    UnaryOpKind::Negate => 25,
                 },
-                Expr::HasAttr(_) => 21,
-                Expr::Apply(_) => 25,
+                Expr::HasAttr(_) => 23,
+                Expr::Apply(_) => 27,
 
                 // Lists can contain Select.
-                Expr::List(_) => 27,
+                Expr::List(_) => 29,
 
-                Expr::Select(_) => 29,
+                Expr::Select(_) => 31,
 
                 // Atoms.
                 Expr::AttrSet(_)
@@ -304,7 +310,7 @@ impl Expr {
                 | Expr::IndentString(_)
                 | Expr::Literal(_)
                 | Expr::PathInterpolation(_)
-                | Expr::Ref(_) => 29,
+                | Expr::Ref(_) => 31,
 
                 // Special. See below.
                 Expr::Paren(_) => PAREN,
@@ -312,7 +318,7 @@ impl Expr {
         }
 
         const TOPLEVEL: u8 = 0;
-        const PAREN: u8 = 31;
+        const PAREN: u8 = 33;
 
         match (bp(self), bp(inner)) {
             // Special case 1: `Paren`s can safely contain or be contained by anything.
@@ -380,6 +386,8 @@ asts! {
             self.syntax().children_with_tokens().find_map(|n| {
                 let tok = n.into_token()?;
                 let op = match tok.kind() {
+                    T![|>] => BinaryOpKind::PipeInto,
+                    T![<|] => BinaryOpKind::PipeFrom,
                     T![->] => BinaryOpKind::Imply,
                     T![&&] => BinaryOpKind::And,
                     T![||] => BinaryOpKind::Or,
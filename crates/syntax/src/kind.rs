@@ -111,9 +111,11 @@ def! {
     EQ2 = [==],
     GT_EQ = [>=],
     LT_EQ = [<=],
+    LT_PIPE = [<|],
     MINUS_GT = [->],
     NOT_EQ = [!=],
     OR2 = [||],
+    PIPE_GT = [|>],
     PLUS2 = [++],
     QUOTE2 = ["''"],
     SLASH2 = ["//"],
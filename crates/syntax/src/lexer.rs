@@ -111,6 +111,8 @@ regex_dfa! {
         DOT3 = r"\.\.\.",
         MINUS_GT = r"->",
         OR2 = r"\|\|",
+        PIPE_GT = r"\|>",
+        LT_PIPE = r"<\|",
         AND2 = r"&&",
         EQ2 = r"==",
         NOT_EQ = r"!=",
@@ -180,6 +182,39 @@ regex_dfa! {
 
 pub type LexTokens = Vec<(SyntaxKind, TextRange)>;
 
+#[derive(Clone, Copy)]
+struct NestedString {
+    /// The number of tokens before the opening quote.
+    out_len: usize,
+    /// The offset of the opening quote.
+    offset: TextSize,
+    /// The depth of contexts before the opening quote.
+    depth: usize,
+    /// Whether it is known to span lines and close the interpolation, like `"${"a\nb"}"`.
+    is_multiline: bool,
+}
+
+/// Check if the rest of a nested string, starting at `src`, ends right before the `}` closing
+/// its interpolation. Nested interpolations are not followed.
+fn closes_interpolation(src: &[u8]) -> bool {
+    let mut iter = src.iter();
+    while let Some(&b) = iter.next() {
+        match b {
+            b'\\' => {
+                iter.next();
+            }
+            b'$' if iter.as_slice().first() == Some(&b'{') => return false,
+            b'"' => {
+                return iter
+                    .find(|b| !b.is_ascii_whitespace())
+                    .map_or(false, |&b| b == b'}');
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
 /// Tokenize the source of a Nix file.
 ///
 /// # Panics
@@ -196,11 +231,50 @@ pub fn lex(src: &[u8]) -> LexTokens {
 
     let mut out = Vec::new();
     let mut ctxs = Vec::new();
+    // Strings opened directly inside an interpolation of a string with the same quote,
+    // like `"${ "`, and not terminated yet.
+    let mut nested_strings = Vec::<NestedString>::new();
 
     let mut offset = TextSize::from(0);
-    while offset != total_len {
+    loop {
         let dfa = ctxs.last().copied().unwrap_or(default_ctx);
 
+        // Recover for an unclosed interpolation, like `"${foo.";`, where the following quote is
+        // lexed as a nested string. Go back and treat the quote as the end of the outer string,
+        // if the nested one consumes everything till the end, or it spans lines, which is unusual
+        // for a nested `"`, unless it then closes the interpolation.
+        let unterminated = if offset == total_len {
+            if nested_strings.is_empty() {
+                break;
+            }
+            Some(0)
+        } else {
+            let i = nested_strings.len().checked_sub(1);
+            match i.map(|i| &mut nested_strings[i]) {
+                Some(nested)
+                    if ptr::eq(dfa, string_ctx)
+                        && nested.depth + 1 == ctxs.len()
+                        && !nested.is_multiline
+                        && src[usize::from(offset)] == b'\n' =>
+                {
+                    nested.is_multiline = closes_interpolation(&src[usize::from(offset)..]);
+                    i.filter(|_| !nested.is_multiline)
+                }
+                _ => None,
+            }
+        };
+        if let Some(i) = unterminated {
+            let nested = nested_strings[i];
+            nested_strings.truncate(i);
+            out.truncate(nested.out_len);
+            // Pop the interpolation, so that the quote closes the outer string.
+            ctxs.truncate(nested.depth - 1);
+            offset = nested.offset;
+            // The synthetic and empty `}` is reported by the parser.
+            out.push((T!['}'], TextRange::empty(offset)));
+            continue;
+        }
+
         let rest = &src[usize::from(offset)..];
         let (mut tok, mut len) = match dfa.match_first(rest) {
             // Offset <= u32, already checked.
@@ -227,8 +301,24 @@ pub fn lex(src: &[u8]) -> LexTokens {
             T!['"'] | T!["''"] if !ptr::eq(dfa, default_ctx) => {
                 ctxs.pop();
             }
-            T!['"'] => ctxs.push(string_ctx),
-            T!["''"] => ctxs.push(indent_string_ctx),
+            T!['"'] | T!["''"] => {
+                let ctx = if tok == T!['"'] {
+                    string_ctx
+                } else {
+                    indent_string_ctx
+                };
+                // The default context directly above a string is always an interpolation.
+                let outer = ctxs.len().checked_sub(2).map(|i| ctxs[i]);
+                if outer.map_or(false, |outer| ptr::eq(outer, ctx)) {
+                    nested_strings.push(NestedString {
+                        out_len: out.len(),
+                        offset,
+                        depth: ctxs.len(),
+                        is_multiline: false,
+                    });
+                }
+                ctxs.push(ctx);
+            }
             T!['{'] | T!["${"] => ctxs.push(default_ctx),
             T!['}'] => {
                 ctxs.pop();
@@ -259,6 +349,13 @@ pub fn lex(src: &[u8]) -> LexTokens {
 
         out.push((tok, TextRange::at(offset, len)));
         offset += len;
+        // Terminated ones.
+        while nested_strings
+            .last()
+            .map_or(false, |nested| nested.depth >= ctxs.len())
+        {
+            nested_strings.pop();
+        }
     }
 
     if matches!(ctxs.last(), Some(&dfa) if ptr::eq(dfa, path_ctx)) {
@@ -510,6 +607,45 @@ mod tests {
         );
     }
 
+    #[test]
+    fn unclosed_interpolation() {
+        check_lex(
+            "[ \"${a.\"\n\"b\" ]",
+            expect![[r#"
+                L_BRACK "["
+                SPACE " "
+                DQUOTE "\""
+                DOLLAR_L_CURLY "${"
+                IDENT "a"
+                DOT "."
+                R_CURLY ""
+                DQUOTE "\""
+                SPACE "\n"
+                DQUOTE "\""
+                STRING_FRAGMENT "b"
+                DQUOTE "\""
+                SPACE " "
+                R_BRACK "]"
+            "#]],
+        );
+    }
+
+    #[test]
+    fn multiline_nested_string() {
+        check_lex(
+            "\"${\"a\nb\"}\"",
+            expect![[r#"
+                DQUOTE "\""
+                DOLLAR_L_CURLY "${"
+                DQUOTE "\""
+                STRING_FRAGMENT "a\nb"
+                DQUOTE "\""
+                R_CURLY "}"
+                DQUOTE "\""
+            "#]],
+        );
+    }
+
     #[test]
     fn indent_string() {
         check_lex(
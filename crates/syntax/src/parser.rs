@@ -1,8 +1,9 @@
 use crate::ast::{AstNode, SourceFile};
 use crate::lexer::LexTokens;
 use crate::SyntaxKind::{self, *};
-use crate::{lexer, Error, ErrorKind, SyntaxNode};
+use crate::{lexer, Error, ErrorKind, NodeOrToken, SyntaxNode};
 use rowan::{Checkpoint, GreenNode, GreenNodeBuilder, TextRange, TextSize};
+use std::fmt::Write;
 
 const MAX_STEPS: usize = 100_000_000;
 const MAX_DEPTHS: usize = 500;
@@ -29,6 +30,26 @@ impl Parse {
     pub fn errors(&self) -> &[Error] {
         &self.errors
     }
+
+    /// Dump errors and the indented syntax tree for debugging, like `NODE@0..1` per line.
+    /// If `range` is set, only the element covering it and errors inside it are dumped.
+    pub fn debug_dump(&self, range: Option<TextRange>) -> String {
+        let mut out = String::new();
+        for err in &self.errors {
+            if range.map_or(true, |range| range.contains_range(err.range)) {
+                writeln!(out, "{:?}: {:?}", err.range, err.kind).unwrap();
+            }
+        }
+        let node = self.syntax_node();
+        match range.filter(|&range| node.text_range().contains_range(range)) {
+            None => write!(out, "{node:#?}").unwrap(),
+            Some(range) => match node.covering_element(range) {
+                NodeOrToken::Node(node) => write!(out, "{node:#?}").unwrap(),
+                NodeOrToken::Token(tok) => writeln!(out, "{tok:#?}").unwrap(),
+            },
+        }
+        out
+    }
 }
 
 /// Parse the source of a Nix file.
@@ -54,6 +75,7 @@ pub fn parse_file_tokens(src: &str, mut tokens: LexTokens) -> Parse {
         src,
         steps: 0,
         depth: 0,
+        guards: Vec::new(),
     }
     .parse()
 }
@@ -65,6 +87,9 @@ struct Parser<'i> {
     src: &'i str,
     steps: usize,
     depth: usize,
+    /// Closing tokens expected by enclosing nodes, as the recovery set.
+    /// An unexpected one of them terminates the current node instead of being consumed by it.
+    guards: Vec<SyntaxKind>,
 }
 
 impl<'i> Parser<'i> {
@@ -97,6 +122,10 @@ impl<'i> Parser<'i> {
             .last()
             .map(|&(_, range)| range)
             .unwrap_or_else(|| TextRange::empty(TextSize::from(self.src.len() as u32)));
+        // Following errors at the same token are cascaded from the first one.
+        if self.errors.last().map_or(false, |prev| prev.range == range) {
+            return;
+        }
         self.errors.push(Error { range, kind });
     }
 
@@ -164,6 +193,32 @@ impl<'i> Parser<'i> {
             .filter(|k| !k.is_trivia())
     }
 
+    /// Consume all following whitespaces if any, and check if the following tokens look like
+    /// the start of a binding `a.b =`. It is never valid after an expression, thus the previous
+    /// binding misses its `;`.
+    fn at_binding_start(&mut self) -> bool {
+        self.ws();
+        let mut tok_iter = self.peek_iter_non_ws();
+        loop {
+            if tok_iter.next() != Some(IDENT) {
+                return false;
+            }
+            match tok_iter.next() {
+                Some(T![.]) => {}
+                Some(T![=]) => return true,
+                _ => return false,
+            }
+        }
+    }
+
+    /// Check if `kind` is expected by an enclosing node, but not the innermost one.
+    fn is_outer_guard(&self, kind: SyntaxKind) -> bool {
+        match self.guards.split_last() {
+            Some((_, outer)) => outer.contains(&kind),
+            None => false,
+        }
+    }
+
     /// Consumes all following whitespaces if any.
     fn ws(&mut self) {
         while matches!(self.peek(), Some(k) if k.is_trivia()) {
@@ -268,10 +323,14 @@ impl<'i> Parser<'i> {
             Some(T![if]) => {
                 self.start_node(IF_THEN_ELSE);
                 self.bump(); // if
+                self.guards.push(T![then]);
                 self.expr_function_opt();
+                self.guards.pop();
                 // If the separator is not found, stop early.
                 if self.require_expr_end(T![then]) {
+                    self.guards.push(T![else]);
                     self.expr_function_opt();
+                    self.guards.pop();
                     if self.require_expr_end(T![else]) {
                         self.expr_function_opt();
                     }
@@ -420,6 +479,16 @@ impl<'i> Parser<'i> {
             }
 
             if rbp == APPLY_RBP {
+                // Recover for a missing `;` before the next binding.
+                // ```
+                // {
+                //   a = f x  # => `a = f x <missing semicolon>`
+                //   b = 1;   # <- Not an argument of `f`.
+                // }
+                // ```
+                if self.at_binding_start() {
+                    break;
+                }
                 self.start_node_at(cp, APPLY);
             } else {
                 self.start_node_at(cp, BINARY_OP);
@@ -483,7 +552,9 @@ impl<'i> Parser<'i> {
             Some(T!['(']) => {
                 self.start_node(PAREN);
                 self.bump(); // '('
+                self.guards.push(T![')']);
                 self.expr_function_opt();
+                self.guards.pop();
                 self.require_expr_end(T![')']);
                 self.finish_node();
             }
@@ -511,6 +582,7 @@ impl<'i> Parser<'i> {
             Some(T!['[']) => {
                 self.start_node(LIST);
                 self.bump(); // '['
+                self.guards.push(T![']']);
                 loop {
                     match self.peek_non_ws() {
                         Some(T![']']) => {
@@ -532,6 +604,7 @@ impl<'i> Parser<'i> {
                         }
                     }
                 }
+                self.guards.pop();
                 self.finish_node();
             }
             _ => {
@@ -648,6 +721,7 @@ impl<'i> Parser<'i> {
     /// Maybe consume tokens and maybe make many `INHERIT` or `ATTR_PATH_VALUE` nodes,
     /// and must consume the guard token or reaching EOF.
     fn bindings_until(&mut self, guard: SyntaxKind) {
+        self.guards.push(guard);
         loop {
             match self.peek_non_ws() {
                 None => {
@@ -658,6 +732,17 @@ impl<'i> Parser<'i> {
                     self.bump(); // guard
                     break;
                 }
+                // Recover for an unclosed node, by leaving the token to the enclosing one.
+                // ```
+                // let
+                //   a = {    # => `{ b = 1; <missing curly>`
+                //     b = 1;
+                // in a       # <- Terminates `let` instead.
+                // ```
+                Some(k) if self.is_outer_guard(k) => {
+                    self.error(ErrorKind::ExpectToken(guard));
+                    break;
+                }
                 Some(T![inherit]) => {
                     self.start_node(INHERIT);
                     self.bump(); // inherit
@@ -669,7 +754,9 @@ impl<'i> Parser<'i> {
                         self.finish_node();
                     }
                     // Use lookahead for ending condition, since `;` might not be typed yet.
-                    while self.peek_non_ws().map_or(false, SyntaxKind::can_start_attr) {
+                    while self.peek_non_ws().map_or(false, SyntaxKind::can_start_attr)
+                        && !self.at_binding_start()
+                    {
                         self.attr_opt(false);
                     }
                     self.want(T![;]);
@@ -688,7 +775,12 @@ impl<'i> Parser<'i> {
                     // }
                     // ```
                     if self.want(T![=]) {
-                        self.expr_function_opt();
+                        // The value is missing before the next binding.
+                        if self.at_binding_start() {
+                            self.error(ErrorKind::ExpectExpr);
+                        } else {
+                            self.expr_function_opt();
+                        }
                     }
                     self.want(T![;]);
                     self.finish_node();
@@ -724,6 +816,7 @@ impl<'i> Parser<'i> {
                 }
             }
         }
+        self.guards.pop();
     }
 
     /// Maybe consume tokens and always make a `ATTR_PATH` node.
@@ -764,7 +857,14 @@ impl<'i> Parser<'i> {
         assert_eq!(self.peek(), Some(T!["${"]));
         self.start_node(DYNAMIC);
         self.bump(); // "${"
+        self.guards.push(T!['}']);
         self.expr_function_opt();
+        self.guards.pop();
+        // The lexer inserts an empty `}` for an unclosed interpolation inside strings.
+        self.ws();
+        if matches!(self.peek_full(), Some((T!['}'], range)) if range.is_empty()) {
+            self.error(ErrorKind::ExpectToken(T!['}']));
+        }
         self.require_expr_end(T!['}']);
         self.finish_node();
     }
@@ -850,8 +950,8 @@ impl SyntaxKind {
     fn prefix_bp(self) -> Option<u8> {
         // See `infix_bp`.
         Some(match self {
-            T![!] => 13,
-            T![-] => 23,
+            T![!] => 15,
+            T![-] => 25,
             _ => return None,
         })
     }
@@ -860,7 +960,7 @@ impl SyntaxKind {
     fn postfix_bp(self) -> Option<u8> {
         // See `infix_bp`.
         Some(match self {
-            T![?] => 21,
+            T![?] => 23,
             _ => return None,
         })
     }
@@ -868,28 +968,31 @@ impl SyntaxKind {
     #[rustfmt::skip]
     fn infix_bp(self) -> Option<(u8, u8)> {
         Some(match self {
-            T![->] => (2, 1),
-            T![||] => (3, 4),
-            T![&&] => (5, 6),
+            // Experimental pipe operators. They cannot be mixed without parentheses.
+            T![|>] => (1, 2),
+            T![<|] => (2, 1),
+            T![->] => (4, 3),
+            T![||] => (5, 6),
+            T![&&] => (7, 8),
             T![==] |
-            T![!=] => (7, 7),
+            T![!=] => (9, 9),
             T![<] |
             T![<=] |
             T![>] |
-            T![>=] => (9, 9),
-            T!["//"] => (12, 11),
-            // Prefix `!` => 13
+            T![>=] => (11, 11),
+            T!["//"] => (14, 13),
+            // Prefix `!` => 15
             T![+] |
-            T![-] => (15, 16),
+            T![-] => (17, 18),
             T![*] |
-            T![/] => (17, 18),
-            T![++] => (20, 19),
-            // Postfix `?` => 21
-            // Prefix `-` => 23
-            _ if self.can_start_atom_expr() => (25, 26), // APPLY
+            T![/] => (19, 20),
+            T![++] => (22, 21),
+            // Postfix `?` => 23
+            // Prefix `-` => 25
+            _ if self.can_start_atom_expr() => (27, 28), // APPLY
             _ => return None,
         })
     }
 }
 
-const APPLY_RBP: u8 = 26;
+const APPLY_RBP: u8 = 28;
@@ -42,7 +42,7 @@ impl fmt::Display for EscapeStringFragment<'_> {
                 '\\' => "\\\\",
                 '\n' => "\\n",
                 '\r' => "\\r",
-                '\t' => "\\r",
+                '\t' => "\\t",
                 '$' if self.0[i..].starts_with("${") => "\\$",
                 _ => {
                     ch.fmt(f)?;
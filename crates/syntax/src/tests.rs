@@ -1,7 +1,6 @@
-use crate::{parse_file, NixLanguage};
-use expect_test::expect_file;
+use crate::{parse_file, NixLanguage, TextRange};
+use expect_test::{expect, expect_file};
 use rowan::ast::AstNode;
-use std::fmt::Write;
 use std::fs;
 use std::path::Path;
 
@@ -34,11 +33,7 @@ fn run_test(dir: &Path, ok: bool) {
         println!("Parsing {}", path.display());
 
         let ast = parse_file(&src);
-        let mut got = String::new();
-        for err in ast.errors() {
-            writeln!(got, "{:?}: {:?}", err.range, err.kind).unwrap();
-        }
-        write!(got, "{:#?}", ast.syntax_node()).unwrap();
+        let got = ast.debug_dump(None);
 
         if ok != ast.errors().is_empty() {
             println!("--------\n{got}\n--------");
@@ -57,3 +52,31 @@ fn parser() {
     run_test(&dir.join("err"), false);
     run_test(&dir.join("fuzz"), false);
 }
+
+#[test]
+fn debug_dump_range() {
+    let parse = parse_file("{ a = 1 + ; b = 2; }");
+    let range = |start: u32, end: u32| Some(TextRange::new(start.into(), end.into()));
+    expect![[r#"
+        10..11: ExpectExpr
+        ATTR_PATH_VALUE@2..11
+          ATTR_PATH@2..4
+            NAME@2..3
+              IDENT@2..3 "a"
+            SPACE@3..4 " "
+          EQ@4..5 "="
+          SPACE@5..6 " "
+          BINARY_OP@6..10
+            LITERAL@6..7
+              INT@6..7 "1"
+            SPACE@7..8 " "
+            PLUS@8..9 "+"
+            SPACE@9..10 " "
+          SEMICOLON@10..11 ";"
+    "#]]
+    .assert_eq(&parse.debug_dump(range(6, 11)));
+    expect![[r#"
+        IDENT@12..13 "b"
+    "#]]
+    .assert_eq(&parse.debug_dump(range(12, 13)));
+}
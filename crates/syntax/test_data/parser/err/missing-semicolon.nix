@@ -0,0 +1,9 @@
+{
+  a = f x
+  b.c = 1;
+  d =
+  e = 1;
+  inherit g h
+  i = 1;
+  j = 1;
+}
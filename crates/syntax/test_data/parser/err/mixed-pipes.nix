@@ -0,0 +1,4 @@
+[
+  (x |> f <| y)
+  (f <| x |> g)
+]
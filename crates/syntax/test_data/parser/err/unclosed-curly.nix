@@ -0,0 +1,7 @@
+let
+  a = {
+    b = 1;
+in {
+  c = let d = 1;
+  e = if a then { f = 1; else 2;
+}
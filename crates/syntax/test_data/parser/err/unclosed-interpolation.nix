@@ -0,0 +1,7 @@
+{
+  a = "foo ${bar.";
+  b = ''
+    ${baz
+  '';
+  c = "${"nested"}";
+}
@@ -0,0 +1,6 @@
+[
+  (x |> f |> g)
+  (f <| g <| x)
+  (a -> b |> f)
+  (x |> f a b)
+]